@@ -0,0 +1,174 @@
+use super::observation::{self, Corrections};
+use super::Body;
+
+/// Alignment of two bodies as seen from a third one: an eclipse, transit or
+/// occultation when the bodies are close enough in the sky.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alignment {
+    /// First and last analyzed times with the bodies within the tolerance.
+    pub start: f64,
+    pub end: f64,
+    /// Time of the smallest separation, interpolated between the analyzed times.
+    pub closest: f64,
+    /// Smallest separation, in degrees.
+    pub separation: f64,
+    /// Whether the first body is the nearer one at the closest analyzed time,
+    /// i.e., passes in front of the second.
+    pub first_in_front: bool,
+}
+
+/// Angle between the directions to `a` and `b` seen from `observer`, in
+/// degrees, and whether `a` is the nearer one.
+pub fn separation(observer: &Body, a: &Body, b: &Body, corrections: Corrections) -> (f64, bool) {
+    let a = observation::apparent_position(observer, a, corrections);
+    let b = observation::apparent_position(observer, b, corrections);
+    let angle = a.cross(&b).norm().atan2(a.dot(&b)).to_degrees();
+    (angle, a.norm() < b.norm())
+}
+
+/// Finds the alignments in separations given in time order.
+#[derive(Debug, Clone)]
+pub struct Detector {
+    /// Largest separation counting as aligned, in degrees.
+    tolerance: f64,
+    /// Last sample, kept to interpolate the minimum of an alignment starting
+    /// at the next one.
+    previous: Option<Sample>,
+    /// Samples of the current alignment, from the one before it started.
+    current: Vec<Sample>,
+    alignments: Vec<Alignment>,
+}
+
+/// Time, separation and whether the first body is in front.
+type Sample = (f64, f64, bool);
+
+impl Detector {
+    pub fn new(tolerance: f64) -> Self {
+        Detector {
+            tolerance,
+            previous: None,
+            current: Vec::new(),
+            alignments: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, time: f64, separation: f64, first_in_front: bool) {
+        let sample = (time, separation, first_in_front);
+        if separation <= self.tolerance {
+            if self.current.is_empty() {
+                self.current.extend(self.previous);
+            }
+            self.current.push(sample);
+        } else if !self.current.is_empty() {
+            self.current.push(sample);
+            self.close();
+        }
+        self.previous = Some(sample);
+    }
+
+    /// Alignments found, including one still going on at the last sample.
+    pub fn finish(mut self) -> Vec<Alignment> {
+        if !self.current.is_empty() {
+            self.close();
+        }
+        self.alignments
+    }
+
+    fn close(&mut self) {
+        let samples = std::mem::take(&mut self.current);
+        let tolerance = self.tolerance;
+        let mut within = samples.iter().filter(|(_, separation, _)| *separation <= tolerance);
+        let start = within.next().expect("an alignment has a sample within the tolerance").0;
+        let end = within.next_back().map_or(start, |sample| sample.0);
+        let k = (0..samples.len())
+            .min_by(|&i, &j| samples[i].1.total_cmp(&samples[j].1))
+            .expect("an alignment has samples");
+        let (mut closest, mut separation, first_in_front) = samples[k];
+        if let [before, at, after] = samples[k.saturating_sub(1)..(k + 2).min(samples.len())]
+            && let Some((time, squared)) = vertex(before, at, after)
+        {
+            // The squared separation is quadratic in time for bodies moving
+            // in straight lines across the sky.
+            closest = time;
+            separation = squared.max(0.0).sqrt();
+        }
+        self.alignments.push(Alignment {
+            start,
+            end,
+            closest,
+            separation,
+            first_in_front,
+        });
+    }
+}
+
+/// Minimum of the parabola through three samples of the squared separation,
+/// if it lies between the outer ones.
+fn vertex((t0, s0, _): Sample, (t1, s1, _): Sample, (t2, s2, _): Sample) -> Option<(f64, f64)> {
+    let (y0, y1, y2) = (s0 * s0, s1 * s1, s2 * s2);
+    let (a, b) = ((y1 - y0) / (t1 - t0), (y2 - y1) / (t2 - t1));
+    let curvature = (b - a) / (t2 - t0);
+    if curvature <= 0.0 || !curvature.is_finite() {
+        return None;
+    }
+    // y = y1 + slope·(t - t1) + curvature·(t - t1)², with the slope at t1.
+    let slope = a + curvature * (t1 - t0);
+    let time = t1 - slope / (2.0 * curvature);
+    (t0..=t2)
+        .contains(&time)
+        .then(|| (time, y1 - slope * slope / (4.0 * curvature)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::{Tags, Vector};
+
+    fn body(name: &str, position: Vector) -> Body {
+        Body {
+            name: name.to_string(),
+            mass: 1.0,
+            position,
+            velocity: Vector::null(),
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    #[test]
+    fn test_finds_the_closest_approach_between_samples() {
+        // A moon crossing in front of a distant star along y, passing 0.1 degrees
+        // below it at t = 4.3.
+        let (observer, star) = (body("Observer", Vector::null()), body("Star", Vector::new(1000.0, 0.0, 0.0)));
+        let offset = (0.1f64).to_radians().tan();
+        let mut detector = Detector::new(1.0);
+        for step in 0..=20 {
+            let time = step as f64;
+            let moon = body("Moon", Vector::new(1.0, 0.01 * (time - 4.3), -offset));
+            let (angle, in_front) = separation(&observer, &moon, &star, Corrections::default());
+            detector.add(time, angle, in_front);
+        }
+        let alignments = detector.finish();
+
+        assert_eq!(alignments.len(), 1);
+        let alignment = &alignments[0];
+        assert_eq!((alignment.start, alignment.end), (3.0, 6.0));
+        assert!((alignment.closest - 4.3).abs() < 1e-3, "{:?}", alignment);
+        assert!((alignment.separation - 0.1).abs() < 1e-4, "{:?}", alignment);
+        assert!(alignment.first_in_front);
+    }
+
+    #[test]
+    fn test_reports_separate_alignments() {
+        let mut detector = Detector::new(0.5);
+        for (time, angle) in [(0.0, 2.0), (1.0, 0.4), (2.0, 3.0), (3.0, 0.2)] {
+            detector.add(time, angle, false);
+        }
+        let alignments = detector.finish();
+
+        assert_eq!(alignments.len(), 2);
+        assert_eq!((alignments[0].start, alignments[0].end), (1.0, 1.0));
+        assert_eq!((alignments[1].closest, alignments[1].separation), (3.0, 0.2));
+    }
+}
@@ -0,0 +1,187 @@
+use super::dynamics::SequentialWriter;
+use super::Body;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Occupancy of the queue between the simulation and the writer thread.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueMetrics {
+    pub capacity: usize,
+    /// Frames handed to the writer thread and not written yet.
+    pub depth: usize,
+    pub peak_depth: usize,
+    /// Frames the simulation had to wait to enqueue because the queue was full,
+    /// i.e., how often writing held back the integration.
+    pub stalls: usize,
+}
+
+#[derive(Default)]
+struct Counters {
+    /// Frames handed to the writer thread.
+    sent: AtomicUsize,
+    /// Frames the writer thread has written.
+    written: AtomicUsize,
+    peak_depth: AtomicUsize,
+    stalls: AtomicUsize,
+}
+
+/// Runs a writer on a dedicated thread behind a bounded queue of frames, so
+/// encoding and compressing the output overlap the integration instead of
+/// stalling it. When the queue is full, `add` waits for room (back-pressure).
+pub struct Background<W> {
+    sender: Option<SyncSender<(f64, Vec<Body>)>>,
+    worker: Option<JoinHandle<Result<W, String>>>,
+    capacity: usize,
+    counters: Arc<Counters>,
+}
+
+impl Counters {
+    /// Frames sent and not written yet. The writer thread may count a frame
+    /// before the simulation does, so this never goes below zero.
+    fn depth(&self) -> usize {
+        let written = self.written.load(Ordering::Relaxed);
+        self.sent.load(Ordering::Relaxed).saturating_sub(written)
+    }
+}
+
+impl<W: SequentialWriter + Send + 'static> Background<W> {
+    /// Starts the writer thread with room for `capacity` frames (at least one),
+    /// the one being written included.
+    pub fn new(mut writer: W, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let (sender, receiver) = mpsc::sync_channel::<(f64, Vec<Body>)>(capacity - 1);
+        let counters = Arc::new(Counters::default());
+        let shared = Arc::clone(&counters);
+        let worker = thread::spawn(move || {
+            for (time, bodies) in receiver {
+                writer.add(time, &bodies).map_err(|e| e.to_string())?;
+                shared.written.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(writer)
+        });
+        Background {
+            sender: Some(sender),
+            worker: Some(worker),
+            capacity,
+            counters,
+        }
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        QueueMetrics {
+            capacity: self.capacity,
+            depth: self.counters.depth(),
+            peak_depth: self.counters.peak_depth.load(Ordering::Relaxed),
+            stalls: self.counters.stalls.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Waits for the queued frames to be written and hands the writer back
+    /// (e.g., to close it).
+    pub fn finish(mut self) -> Result<W, Box<dyn Error>> {
+        self.sender = None;
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<W, Box<dyn Error>> {
+        let worker = self.worker.take().ok_or("the writer thread has already finished")?;
+        match worker.join() {
+            Ok(result) => result.map_err(Into::into),
+            Err(_) => Err("the writer thread panicked".into()),
+        }
+    }
+}
+
+impl<W: SequentialWriter + Send + 'static> SequentialWriter for Background<W> {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        let sender = self.sender.as_ref().ok_or("the writer thread has already finished")?;
+        let sent = match sender.try_send((time, bodies.to_vec())) {
+            Err(TrySendError::Full(frame)) => {
+                self.counters.stalls.fetch_add(1, Ordering::Relaxed);
+                sender.send(frame).is_ok()
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+            Ok(()) => true,
+        };
+        if !sent {
+            // The writer thread stopped on an error; report it.
+            self.sender = None;
+            self.join()?;
+            return Err("the writer thread stopped early".into());
+        }
+        self.counters.sent.fetch_add(1, Ordering::Relaxed);
+        self.counters.peak_depth.fetch_max(self.counters.depth(), Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl<W> Drop for Background<W> {
+    fn drop(&mut self) {
+        // Closing the queue ends the thread once it has written what is queued.
+        self.sender = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::{Tags, Vector};
+    use std::time::Duration;
+
+    struct Slow {
+        times: Vec<f64>,
+        fail_at: Option<f64>,
+    }
+
+    impl SequentialWriter for Slow {
+        fn add(&mut self, time: f64, _bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+            thread::sleep(Duration::from_millis(2));
+            if self.fail_at == Some(time) {
+                return Err("disk full".into());
+            }
+            self.times.push(time);
+            Ok(())
+        }
+    }
+
+    fn bodies() -> Vec<Body> {
+        vec![Body {
+            name: "Rock".to_string(),
+            mass: 1.0,
+            position: Vector::null(),
+            velocity: Vector::null(),
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }]
+    }
+
+    #[test]
+    fn test_writes_every_frame_in_order_with_back_pressure() {
+        let mut background = Background::new(Slow { times: Vec::new(), fail_at: None }, 2);
+        for i in 0..20 {
+            background.add(i as f64, &bodies()).unwrap();
+        }
+        let metrics = background.metrics();
+        let slow = background.finish().unwrap();
+
+        assert_eq!(slow.times, (0..20).map(|i| i as f64).collect::<Vec<_>>());
+        assert_eq!(metrics.peak_depth, 2, "{:?}", metrics);
+        assert!(metrics.depth <= 2, "{:?}", metrics);
+        assert!(metrics.stalls > 0, "{:?}", metrics);
+    }
+
+    #[test]
+    fn test_writer_errors_reach_the_simulation() {
+        let mut background = Background::new(Slow { times: Vec::new(), fail_at: Some(3.0) }, 1);
+        let error = (0..20).find_map(|i| background.add(i as f64, &bodies()).err());
+
+        assert_eq!(error.unwrap().to_string(), "disk full");
+    }
+}
@@ -0,0 +1,138 @@
+use super::dynamics::SequentialWriter;
+use super::Body;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes trajectories for Blender: a CSV of keyframes (`frame,time,name,x,y,z`,
+/// one row per body per recorded frame, in meters) and, next to it, a Python
+/// script that builds one animated sphere per body from it.
+///
+/// Recorded frames become consecutive Blender frames starting at 1; the script
+/// has `SCALE` and `RADIUS` settings at the top to fit the scene.
+pub struct Writer {
+    csv: BufWriter<File>,
+    path: PathBuf,
+    frames: usize,
+}
+
+impl Writer {
+    /// Starts the keyframe CSV at `path`; the script is written on close as `path` with a `.py` extension.
+    pub fn new(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut csv = BufWriter::new(File::create(path)?);
+        writeln!(csv, "frame,time,name,x,y,z")?;
+        Ok(Writer {
+            csv,
+            path: path.to_path_buf(),
+            frames: 0,
+        })
+    }
+
+    pub fn close(mut self) -> Result<(), Box<dyn Error>> {
+        self.csv.flush()?;
+        let csv_path = fs::canonicalize(&self.path)?;
+        fs::write(self.path.with_extension("py"), script(&csv_path))?;
+        Ok(())
+    }
+}
+
+impl SequentialWriter for Writer {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        for body in bodies {
+            writeln!(
+                self.csv,
+                "{},{},{},{},{},{}",
+                self.frames,
+                time,
+                quote(&body.name),
+                body.position.x,
+                body.position.y,
+                body.position.z
+            )?;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+/// Quotes a CSV field when it contains a separator, quote or line break.
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn script(csv_path: &Path) -> String {
+    format!(
+        r#"# Builds animated spheres from trajectories exported by newtonian-solar-system.
+# Run it from Blender's Scripting workspace, or with `blender --python <this file>`.
+import csv
+
+import bpy
+
+CSV_PATH = r"{}"
+SCALE = 1e-9  # Blender units per meter
+RADIUS = 0.05  # sphere radius, in Blender units
+
+objects = {{}}
+last_frame = 1
+with open(CSV_PATH, newline="") as f:
+    for row in csv.DictReader(f):
+        name = row["name"]
+        obj = objects.get(name)
+        if obj is None:
+            bpy.ops.mesh.primitive_uv_sphere_add(radius=RADIUS)
+            obj = bpy.context.active_object
+            obj.name = name
+            objects[name] = obj
+        frame = int(row["frame"]) + 1
+        obj.location = tuple(float(row[axis]) * SCALE for axis in "xyz")
+        obj.keyframe_insert(data_path="location", frame=frame)
+        last_frame = max(last_frame, frame)
+
+scene = bpy.context.scene
+scene.frame_start = 1
+scene.frame_end = last_frame
+"#,
+        csv_path.display()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::{Tags, Vector};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_keyframes_and_script() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("orbits.csv");
+        let body = |x: f64| Body {
+            name: "Moon, Earth's".to_string(),
+            mass: 7.342e22,
+            position: Vector { x, y: 2.0, z: 3.0 },
+            velocity: Vector::null(),
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        };
+
+        let mut writer = Writer::new(&path).unwrap();
+        writer.add(0.0, &[body(1.0)]).unwrap();
+        writer.add(60.0, &[body(1.5)]).unwrap();
+        writer.close().unwrap();
+
+        let csv = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            csv,
+            "frame,time,name,x,y,z\n0,0,\"Moon, Earth's\",1,2,3\n1,60,\"Moon, Earth's\",1.5,2,3\n"
+        );
+        let script = fs::read_to_string(temp_dir.path().join("orbits.py")).unwrap();
+        assert!(script.contains("orbits.csv"));
+        assert!(script.contains("keyframe_insert"));
+    }
+}
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub use super::vector::Vector;
+
+/// Free-form labels of a body (e.g., `category = asteroid`), carried into the output.
+pub type Tags = BTreeMap<String, String>;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Body {
+    pub name: String,
+    pub mass: f64,
+    pub position: Vector,
+    pub velocity: Vector,
+
+    #[serde(default = "Vector::null")]
+    pub acceleration: Vector,
+
+    #[serde(default, skip_serializing_if = "Tags::is_empty")]
+    pub tags: Tags,
+
+    /// Temperature in kelvin, evolved during runs with `thermal` settings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+}
+
+impl Clone for Body {
+    fn clone(&self) -> Self {
+        Body {
+            name: self.name.clone(),
+            mass: self.mass,
+            position: self.position,
+            velocity: self.velocity,
+            acceleration: self.acceleration,
+            tags: self.tags.clone(),
+            temperature: self.temperature,
+        }
+    }
+
+    /// Reuses the memory of the name, and keeps the tags when they are the same,
+    /// so copying the state of a run into a buffer doesn't allocate.
+    fn clone_from(&mut self, source: &Self) {
+        self.name.clone_from(&source.name);
+        self.mass = source.mass;
+        self.position = source.position;
+        self.velocity = source.velocity;
+        self.acceleration = source.acceleration;
+        if self.tags != source.tags {
+            self.tags.clone_from(&source.tags);
+        }
+        self.temperature = source.temperature;
+    }
+}
+
+impl Body {
+    /// A body without tags or a temperature, whose acceleration is computed
+    /// at the start of a run.
+    pub fn new(name: impl Into<String>, mass: f64, position: Vector, velocity: Vector) -> Self {
+        Body {
+            name: name.into(),
+            mass,
+            position,
+            velocity,
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    /// Whether the body carries every one of `tags` with the same value.
+    pub fn has_tags(&self, tags: &Tags) -> bool {
+        tags.iter().all(|(key, value)| self.tags.get(key) == Some(value))
+    }
+}
+
+/// Kinetic and Newtonian potential energy of the bodies, in joules, summed
+/// over all pairs.
+pub fn energy(bodies: &[Body], gravity: f64) -> (f64, f64) {
+    let kinetic = bodies.iter().map(|b| 0.5 * b.mass * b.velocity.norm().powi(2)).sum();
+    let mut potential = 0.0;
+    for (i, a) in bodies.iter().enumerate() {
+        for b in &bodies[i + 1..] {
+            potential -= gravity * a.mass * b.mass / (b.position - a.position).norm();
+        }
+    }
+    (kinetic, potential)
+}
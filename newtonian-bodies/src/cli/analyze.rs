@@ -0,0 +1,1303 @@
+use super::{parse_assignment, parse_expression, ScenarioArgs, SettingsArgs};
+use clap::{Args, Subcommand};
+use newtonian_solar_system::alignment::{self, Detector};
+use newtonian_solar_system::body::Vector;
+use newtonian_solar_system::cluster::{Statistics, LAGRANGIAN_FRACTIONS};
+use newtonian_solar_system::frequency;
+use newtonian_solar_system::galaxy::{Census, Halo};
+use newtonian_solar_system::ground_track::Rotation;
+use newtonian_solar_system::impact::{self, Campaign};
+use newtonian_solar_system::kepler::Elements;
+use newtonian_solar_system::observation::{self, Corrections, Kind};
+use newtonian_solar_system::reader::{Frame, SimulationReader};
+use newtonian_solar_system::uncertainty::Uncertain;
+use newtonian_solar_system::{kepler, scenario, Body};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct AnalyzeArgs {
+    #[command(subcommand)]
+    pub analysis: Analysis,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Analysis {
+    /// Delta-v of transfers between two bodies over a grid of departure and arrival times
+    Porkchop(PorkchopArgs),
+    /// Fraction of perturbed realizations of a body that hit a target, with a confidence interval
+    ImpactProbability(Box<ImpactArgs>),
+    /// Secular and proper frequencies of orbital elements recorded in a simulation output
+    Frequencies(FrequencyArgs),
+    /// Minimum orbit intersection distance between recorded bodies over time
+    Moid(MoidArgs),
+    /// Delta-v and time of a Hohmann transfer between two circular orbits about a body
+    Hohmann(HohmannArgs),
+    /// Outgoing velocity of a gravity assist, optionally setting the encounter up in a scenario
+    Flyby(FlybyArgs),
+    /// Synthetic ranges, range rates and angles of recorded bodies, seen from another one
+    Observe(ObserveArgs),
+    /// Light-delayed, aberrated positions of recorded bodies as another one sees them
+    Apparent(ApparentArgs),
+    /// Times two recorded bodies line up as seen from a third: eclipses, transits and occultations
+    Eclipses(EclipseArgs),
+    /// Latitudes and longitudes under recorded bodies orbiting a rotating body
+    GroundTrack(GroundTrackArgs),
+    /// Stars of a recorded cluster still bound to it and length of the stream of those stripped by a halo
+    Stream(StreamArgs),
+    /// Lagrangian radii, core radius, velocity dispersion and bound mass of a recorded cluster over time
+    Cluster(ClusterArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct PorkchopArgs {
+    /// Scenario with the bodies; times are counted from its initial state
+    pub input: PathBuf,
+
+    /// Body the transfer leaves from
+    #[arg(long)]
+    pub from: String,
+
+    /// Body the transfer arrives at
+    #[arg(long)]
+    pub to: String,
+
+    /// Body the transfer orbits; defaults to the most massive body of the scenario
+    #[arg(long)]
+    pub central: Option<String>,
+
+    /// Earliest departure, in seconds (e.g., "0")
+    #[arg(long, default_value = "0", value_parser = parse_expression)]
+    pub departure_start: f64,
+
+    /// Latest departure, in seconds (e.g., "365*86400")
+    #[arg(long, value_parser = parse_expression)]
+    pub departure_end: f64,
+
+    /// Earliest arrival, in seconds
+    #[arg(long, value_parser = parse_expression)]
+    pub arrival_start: f64,
+
+    /// Latest arrival, in seconds
+    #[arg(long, value_parser = parse_expression)]
+    pub arrival_end: f64,
+
+    /// Complete revolutions of the transfer arcs; of the two arcs with
+    /// revolutions, the cheaper one is reported
+    #[arg(long, default_value_t = 0)]
+    pub revolutions: u32,
+
+    /// Number of departure and of arrival times in the grid
+    #[arg(long, default_value_t = 50)]
+    pub steps: usize,
+
+    /// CSV file receiving one row per departure and arrival time
+    #[arg(short, long, default_value = "porkchop.csv")]
+    pub output: PathBuf,
+
+    /// Gravitational constant (e.g., "6.67430e-11")
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+
+    #[command(flatten)]
+    pub scenario: ScenarioArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ImpactArgs {
+    /// Scenario with the bodies
+    pub input: PathBuf,
+
+    /// Body whose uncertain initial state is sampled (e.g., an asteroid)
+    #[arg(long)]
+    pub body: String,
+
+    /// Body it may hit
+    #[arg(long)]
+    pub target: String,
+
+    /// Distance between the centers counting as an impact, in meters (e.g., the target's radius)
+    #[arg(long, value_parser = parse_expression)]
+    pub radius: f64,
+
+    /// Standard deviation of each position component, in meters
+    #[arg(long, value_parser = parse_expression)]
+    pub sigma_position: f64,
+
+    /// Standard deviation of each velocity component, in m/s
+    #[arg(long, value_parser = parse_expression)]
+    pub sigma_velocity: f64,
+
+    /// Number of realizations to simulate
+    #[arg(long, default_value_t = 1000)]
+    pub realizations: usize,
+
+    /// Seed of the random draws
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// CSV file receiving the outcome of every realization; {shard} is replaced
+    /// by the shard index
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Which shard of the realizations to run (e.g., "$SLURM_ARRAY_TASK_ID")
+    #[arg(long, default_value_t = 0, requires = "shard_count")]
+    pub shard_index: usize,
+
+    /// Number of shards the realizations are split into; realization i belongs to shard i % count
+    #[arg(long, default_value_t = 1)]
+    pub shard_count: usize,
+
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+
+    #[command(flatten)]
+    pub scenario: ScenarioArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct FrequencyArgs {
+    /// Simulation output (Parquet or CSV) with velocities, recorded at a fixed interval
+    pub input: PathBuf,
+
+    /// Body the elements are computed about; defaults to the most massive body
+    #[arg(long)]
+    pub central: Option<String>,
+
+    /// Bodies to analyze, separated by commas; defaults to every other body
+    #[arg(long, value_delimiter = ',')]
+    pub bodies: Vec<String>,
+
+    /// Strongest lines reported per body and signal
+    #[arg(long, default_value_t = 3)]
+    pub lines: usize,
+
+    /// CSV file receiving the table of frequencies
+    #[arg(short, long, default_value = "frequencies.csv")]
+    pub output: PathBuf,
+
+    /// Gravitational constant (e.g., "6.67430e-11")
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+}
+
+#[derive(Args, Debug)]
+pub struct MoidArgs {
+    /// Simulation output (Parquet or CSV) with velocities
+    pub input: PathBuf,
+
+    /// Body the orbits are computed about; defaults to the most massive body
+    #[arg(long)]
+    pub central: Option<String>,
+
+    /// Bodies to analyze, separated by commas; defaults to every other body
+    #[arg(long, value_delimiter = ',')]
+    pub bodies: Vec<String>,
+
+    /// Only report pairs including this body (e.g., Earth for asteroid screening)
+    #[arg(long)]
+    pub against: Option<String>,
+
+    /// Analyze every n-th recorded frame
+    #[arg(long, default_value_t = 1)]
+    pub every: usize,
+
+    /// CSV file receiving one row per pair and analyzed frame
+    #[arg(short, long, default_value = "moid.csv")]
+    pub output: PathBuf,
+
+    /// Gravitational constant (e.g., "6.67430e-11")
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+}
+
+#[derive(Args, Debug)]
+#[command(group = clap::ArgGroup::new("target").required(true).args(["to", "to_radius"]))]
+pub struct HohmannArgs {
+    /// Scenario with the bodies
+    pub input: PathBuf,
+
+    /// Body leaving its orbit (e.g., a spacecraft), taken as circular at its current distance
+    #[arg(long)]
+    pub from: String,
+
+    /// Body whose orbit, taken as circular at its current distance, is the destination
+    #[arg(long)]
+    pub to: Option<String>,
+
+    /// Radius of the destination orbit, in meters
+    #[arg(long, value_parser = parse_expression)]
+    pub to_radius: Option<f64>,
+
+    /// Body both orbits are about; defaults to the most massive body of the scenario
+    #[arg(long)]
+    pub central: Option<String>,
+
+    /// CSV file receiving the two burns of the `--from` body as time, body and
+    /// delta-v vector, starting now
+    #[arg(long, value_name = "FILE")]
+    pub schedule: Option<PathBuf>,
+
+    /// Gravitational constant (e.g., "6.67430e-11")
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+
+    #[command(flatten)]
+    pub scenario: ScenarioArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct FlybyArgs {
+    /// Scenario with the bodies
+    pub input: PathBuf,
+
+    /// Body flying by (e.g., a spacecraft); its velocity relative to the planet is the incoming v∞
+    #[arg(long)]
+    pub body: String,
+
+    /// Planet giving the assist
+    #[arg(long)]
+    pub planet: String,
+
+    /// Closest approach to the planet's center, in meters
+    #[arg(long, value_parser = parse_expression)]
+    pub periapsis: f64,
+
+    /// Angle of the B vector in the B-plane, in degrees from T (= v∞ × z) towards R
+    #[arg(long, default_value = "0", value_parser = parse_expression)]
+    pub b_angle: f64,
+
+    /// Scenario file receiving the body on the incoming hyperbola, at --start-distance from the planet
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Distance from the planet where the written encounter starts, in meters; defaults to the
+    /// planet's sphere of influence about the most massive body
+    #[arg(long, value_parser = parse_expression)]
+    pub start_distance: Option<f64>,
+
+    /// Gravitational constant (e.g., "6.67430e-11")
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+
+    #[command(flatten)]
+    pub scenario: ScenarioArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ObserveArgs {
+    /// Simulation output (Parquet or CSV) with velocities
+    pub input: PathBuf,
+
+    /// Body making the observations (e.g., Earth for a ground station at its center)
+    #[arg(long)]
+    pub observer: String,
+
+    /// Body observed; repeatable
+    #[arg(long = "target", required = true)]
+    pub targets: Vec<String>,
+
+    /// Quantities measured, separated by commas: range (m), range_rate (m/s), ra and dec (degrees)
+    #[arg(long, value_delimiter = ',', default_value = "range,range_rate,ra,dec")]
+    pub kinds: Vec<Kind>,
+
+    /// Standard deviation of a kind of measurement (e.g., "range=100"); defaults to 10 m,
+    /// 1 mm/s and 1e-4 degrees
+    #[arg(long = "sigma", value_name = "KIND=SIGMA", value_parser = parse_assignment)]
+    pub sigmas: Vec<(String, String)>,
+
+    /// See the targets where they were when the light reaching the observer left them
+    #[arg(long)]
+    pub light_time: bool,
+
+    /// Tilt the measured angles towards the motion of the observer (aberration of light)
+    #[arg(long)]
+    pub aberration: bool,
+
+    /// Add normally distributed errors of the standard deviations to the measurements
+    #[arg(long)]
+    pub noise: bool,
+
+    /// Seed of the measurement errors
+    #[arg(long, default_value_t = 0, requires = "noise")]
+    pub seed: u64,
+
+    /// Observe every n-th recorded frame
+    #[arg(long, default_value_t = 1)]
+    pub every: usize,
+
+    /// CSV file receiving the observations, as read by `od --observations`
+    #[arg(short, long, default_value = "observations.csv")]
+    pub output: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct ApparentArgs {
+    /// Simulation output (Parquet or CSV) with velocities
+    pub input: PathBuf,
+
+    /// Body looking at the others
+    #[arg(long)]
+    pub observer: String,
+
+    /// Body seen; repeatable (all others by default)
+    #[arg(long = "body")]
+    pub bodies: Vec<String>,
+
+    /// Leave out the light time: bodies are seen where they are
+    #[arg(long)]
+    pub no_light_time: bool,
+
+    /// Leave out the aberration of light by the motion of the observer
+    #[arg(long)]
+    pub no_aberration: bool,
+
+    /// Analyze every n-th recorded frame
+    #[arg(long, default_value_t = 1)]
+    pub every: usize,
+
+    /// CSV file receiving the apparent positions
+    #[arg(short, long, default_value = "apparent.csv")]
+    pub output: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct EclipseArgs {
+    /// Simulation output (Parquet or CSV); velocities are needed with --apparent
+    pub input: PathBuf,
+
+    /// Body the alignments are seen from
+    #[arg(long)]
+    pub observer: String,
+
+    /// The two bodies lining up, separated by a comma (e.g., "Moon,Sun")
+    #[arg(long, value_delimiter = ',', num_args = 1, required = true)]
+    pub bodies: Vec<String>,
+
+    /// Largest angle between the bodies counting as aligned, in degrees
+    #[arg(long, default_value = "0.5", value_parser = parse_expression)]
+    pub tolerance: f64,
+
+    /// Use the apparent positions, after the light time and aberration
+    #[arg(long)]
+    pub apparent: bool,
+
+    /// Analyze every n-th recorded frame
+    #[arg(long, default_value_t = 1)]
+    pub every: usize,
+
+    /// CSV file receiving one row per alignment
+    #[arg(short, long, default_value = "eclipses.csv")]
+    pub output: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct GroundTrackArgs {
+    /// Simulation output (Parquet or CSV)
+    pub input: PathBuf,
+
+    /// Rotating body the tracks are on; defaults to the most massive body
+    #[arg(long)]
+    pub central: Option<String>,
+
+    /// Bodies to analyze, separated by commas; defaults to every other body
+    #[arg(long, value_delimiter = ',')]
+    pub bodies: Vec<String>,
+
+    /// Sidereal rotation period of the central body in seconds (e.g., "86164.0905"
+    /// for the Earth), negative for a retrograde spin
+    #[arg(long, allow_negative_numbers = true, value_parser = parse_expression)]
+    pub rotation_period: f64,
+
+    /// Spin axis of the central body in the simulation axes, as "x,y,z"
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 1,
+        default_value = "0,0,1",
+        allow_negative_numbers = true,
+        value_parser = parse_expression
+    )]
+    pub axis: Vec<f64>,
+
+    /// Angle of the prime meridian east of the simulation's x axis at time 0, in
+    /// degrees (the Greenwich sidereal angle, for the Earth in equatorial axes)
+    #[arg(long, default_value = "0", allow_negative_numbers = true, value_parser = parse_expression)]
+    pub prime_meridian: f64,
+
+    /// Radius of the central body, to report altitudes above it rather than
+    /// distances from its center
+    #[arg(long, default_value = "0", value_parser = parse_expression)]
+    pub radius: f64,
+
+    /// Analyze every n-th recorded frame
+    #[arg(long, default_value_t = 1)]
+    pub every: usize,
+
+    /// CSV file receiving one row per body and analyzed frame
+    #[arg(short, long, default_value = "ground_track.csv")]
+    pub output: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct StreamArgs {
+    /// Simulation output (Parquet or CSV) with velocities
+    pub input: PathBuf,
+
+    /// Only bodies tagged KEY=VALUE are stars of the cluster (e.g., "group=cluster");
+    /// repeat to require several tags, or leave out to take every body
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_assignment)]
+    pub tags: Vec<(String, String)>,
+
+    /// Circular speed of the halo the cluster was simulated in, in m/s
+    #[arg(long, value_parser = parse_expression)]
+    pub halo_speed: f64,
+
+    /// Core radius of the halo, in meters
+    #[arg(long, default_value = "0", value_parser = parse_expression)]
+    pub halo_core: f64,
+
+    /// Analyze every n-th recorded frame
+    #[arg(long, default_value_t = 1)]
+    pub every: usize,
+
+    /// CSV file receiving one row per analyzed frame
+    #[arg(short, long, default_value = "stream.csv")]
+    pub output: PathBuf,
+
+    /// Gravitational constant (e.g., "6.67430e-11")
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+}
+
+#[derive(Args, Debug)]
+pub struct ClusterArgs {
+    /// Simulation output (Parquet or CSV) with masses and velocities
+    pub input: PathBuf,
+
+    /// Only bodies tagged KEY=VALUE are stars of the cluster (e.g., "group=cluster");
+    /// repeat to require several tags, or leave out to take every body
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_assignment)]
+    pub tags: Vec<(String, String)>,
+
+    /// Analyze every n-th recorded frame
+    #[arg(long, default_value_t = 1)]
+    pub every: usize,
+
+    /// CSV file receiving one row per analyzed frame
+    #[arg(short, long, default_value = "cluster.csv")]
+    pub output: PathBuf,
+
+    /// Gravitational constant (e.g., "6.67430e-11")
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+}
+
+pub fn run(args: &AnalyzeArgs) -> Result<(), Box<dyn Error>> {
+    match &args.analysis {
+        Analysis::Porkchop(porkchop) => run_porkchop(porkchop),
+        Analysis::ImpactProbability(impact) => run_impact_probability(impact),
+        Analysis::Frequencies(frequencies) => run_frequencies(frequencies),
+        Analysis::Moid(moid) => run_moid(moid),
+        Analysis::Hohmann(hohmann) => run_hohmann(hohmann),
+        Analysis::Flyby(flyby) => run_flyby(flyby),
+        Analysis::Observe(observe) => run_observe(observe),
+        Analysis::Apparent(apparent) => run_apparent(apparent),
+        Analysis::Eclipses(eclipses) => run_eclipses(eclipses),
+        Analysis::GroundTrack(ground_track) => run_ground_track(ground_track),
+        Analysis::Stream(stream) => run_stream(stream),
+        Analysis::Cluster(cluster) => run_cluster(cluster),
+    }
+}
+
+/// Seconds in a Julian year, for frequencies in arcseconds per year.
+const YEAR: f64 = 365.25 * 86400.0;
+
+/// Complex samples of the eccentricity, inclination and mean longitude signals.
+type Signals = [Vec<(f64, f64)>; 3];
+
+/// Spectra of the osculating elements of each body about the central one.
+///
+/// The signals follow the usual secular theory variables: `e·exp(iϖ)` gives the
+/// perihelion frequencies g, `sin(i/2)·exp(iΩ)` the nodal frequencies s, and
+/// `exp(iλ)` the mean motion.
+fn run_frequencies(args: &FrequencyArgs) -> Result<(), Box<dyn Error>> {
+    let reader = SimulationReader::open(&args.input)?;
+    if !reader.has_velocities() {
+        return Err(format!("{} has no velocities; record it again with this version", args.input.display()).into());
+    }
+    let mut times = Vec::new();
+    let mut names = Vec::new();
+    let mut signals: Vec<Signals> = Vec::new();
+    for frame in reader {
+        let frame = frame?;
+        let central = central_body(&frame, args.central.as_deref())?;
+        if names.is_empty() {
+            names = orbiting(&frame, central, &args.bodies)?;
+            signals = vec![Default::default(); names.len()];
+        }
+        let elements = osculating(&frame, central, &names, args.gravity)?;
+        for ([eccentricity, inclination, longitude], elements) in signals.iter_mut().zip(elements) {
+            eccentricity.push(polar(elements.eccentricity, elements.longitude_of_periapsis()));
+            inclination.push(polar((elements.inclination / 2.0).sin(), elements.ascending_node));
+            longitude.push(polar(1.0, elements.mean_longitude()));
+        }
+        times.push(frame.time);
+    }
+    let (dt, count) = sampling_interval(&times)?;
+
+    let mut writer = BufWriter::new(File::create(&args.output)?);
+    writeln!(writer, "body,signal,frequency,period,arcsec_per_year,amplitude")?;
+    for (name, series) in names.iter().zip(&signals) {
+        for (signal, samples) in ["e", "i", "lambda"].iter().zip(series) {
+            for line in frequency::lines(&samples[..count], dt, args.lines) {
+                writeln!(
+                    writer,
+                    "\"{}\",{},{},{},{},{}",
+                    name.replace('"', "\"\""),
+                    signal,
+                    line.frequency,
+                    1.0 / line.frequency,
+                    line.frequency * 1_296_000.0 * YEAR,
+                    line.amplitude
+                )?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// MOID of every pair of bodies (or of every body with one of them) at each
+/// analyzed frame, from their osculating orbits about the central body.
+fn run_moid(args: &MoidArgs) -> Result<(), Box<dyn Error>> {
+    if args.every == 0 {
+        return Err("--every must be at least 1".into());
+    }
+    let reader = SimulationReader::open(&args.input)?;
+    if !reader.has_velocities() {
+        return Err(format!("{} has no velocities; record it again with this version", args.input.display()).into());
+    }
+    let mut writer = BufWriter::new(File::create(&args.output)?);
+    writeln!(writer, "time,body_a,body_b,moid")?;
+    let mut names = Vec::new();
+    for frame in reader.step_by(args.every) {
+        let frame = frame?;
+        let central = central_body(&frame, args.central.as_deref())?;
+        if names.is_empty() {
+            names = orbiting(&frame, central, &args.bodies)?;
+            if let Some(against) = args.against.as_ref().filter(|against| !names.contains(against)) {
+                return Err(format!("'{}' is not among the analyzed bodies", against).into());
+            }
+        }
+        let elements = osculating(&frame, central, &names, args.gravity)?;
+        for i in 0..names.len() {
+            for j in i + 1..names.len() {
+                if args.against.as_ref().is_some_and(|against| *against != names[i] && *against != names[j]) {
+                    continue;
+                }
+                writeln!(
+                    writer,
+                    "{},\"{}\",\"{}\",{}",
+                    frame.time,
+                    names[i].replace('"', "\"\""),
+                    names[j].replace('"', "\"\""),
+                    kepler::moid(&elements[i], &elements[j])
+                )?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Central body of a recorded frame: the named one, or else the most massive.
+fn central_body<'a>(frame: &'a Frame, name: Option<&str>) -> Result<&'a Body, Box<dyn Error>> {
+    match name {
+        Some(name) => frame.bodies.iter().find(|body| body.name == name),
+        None => frame.bodies.iter().max_by(|a, b| a.mass.total_cmp(&b.mass)),
+    }
+    .ok_or_else(|| format!("no central body at time {}", frame.time).into())
+}
+
+/// Names of the bodies to analyze: the selected ones, or every body but the
+/// central one.
+fn orbiting(frame: &Frame, central: &Body, selected: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    let names: Vec<String> = frame
+        .bodies
+        .iter()
+        .filter(|body| body.name != central.name)
+        .filter(|body| selected.is_empty() || selected.contains(&body.name))
+        .map(|body| body.name.clone())
+        .collect();
+    if names.is_empty() {
+        return Err("no body to analyze".into());
+    }
+    Ok(names)
+}
+
+/// Osculating elements of the named bodies about the central one.
+fn osculating(frame: &Frame, central: &Body, names: &[String], gravity: f64) -> Result<Vec<Elements>, Box<dyn Error>> {
+    names
+        .iter()
+        .map(|name| {
+            let body = frame
+                .bodies
+                .iter()
+                .find(|body| &body.name == name)
+                .ok_or_else(|| format!("'{}' is missing at time {}", name, frame.time))?;
+            Ok(Elements::from_state(
+                &(body.position - central.position),
+                &(body.velocity - central.velocity),
+                gravity * (central.mass + body.mass),
+            ))
+        })
+        .collect()
+}
+
+/// Interval between the recorded frames and the number of frames recorded at
+/// it; a trailing frame off the regular interval (e.g., at the end of the run)
+/// is left out.
+fn sampling_interval(times: &[f64]) -> Result<(f64, usize), Box<dyn Error>> {
+    if times.len() < 4 {
+        return Err("at least four recorded frames are needed".into());
+    }
+    let dt = times[1] - times[0];
+    let regular = times
+        .iter()
+        .enumerate()
+        .take_while(|&(k, time)| (time - times[0] - k as f64 * dt).abs() <= 1e-6 * dt)
+        .count();
+    if regular + 1 < times.len() {
+        return Err("frames must be recorded at a fixed interval; use --record-interval or --record-count".into());
+    }
+    Ok((dt, regular))
+}
+
+fn polar(radius: f64, angle: f64) -> (f64, f64) {
+    (radius * angle.cos(), radius * angle.sin())
+}
+
+fn run_impact_probability(args: &ImpactArgs) -> Result<(), Box<dyn Error>> {
+    let bodies = scenario::load_with(&args.input, &args.scenario.variables())?;
+    let index = |name: &str| {
+        bodies
+            .iter()
+            .position(|body| body.name == name)
+            .ok_or_else(|| format!("the scenario has no body named '{}'", name))
+    };
+    let campaign = Campaign {
+        impactor: Uncertain::diagonal(index(&args.body)?, args.sigma_position, args.sigma_velocity),
+        target: index(&args.target)?,
+        radius: args.radius,
+        realizations: args.realizations,
+        seed: args.seed,
+        shard_index: args.shard_index,
+        shard_count: args.shard_count,
+    };
+    // Shards must not overwrite each other's outcomes.
+    let output = args.output.as_ref().map(|output| output.to_string_lossy().into_owned());
+    if args.shard_count > 1 && output.as_ref().is_some_and(|output| !output.contains("{shard}")) {
+        return Err("with --shard-count, the output name must contain {shard}".into());
+    }
+
+    let estimate = impact::estimate(&bodies, &args.settings.settings(), &campaign)?;
+    let shard = if args.shard_count > 1 {
+        format!(" (shard {} of {})", args.shard_index, args.shard_count)
+    } else {
+        String::new()
+    };
+    println!(
+        "{} of {} realizations{} hit {}: probability {:.6} (95% confidence {:.6} to {:.6})",
+        estimate.impacts,
+        estimate.realizations.len(),
+        shard,
+        args.target,
+        estimate.probability,
+        estimate.interval.0,
+        estimate.interval.1
+    );
+    if let Some(output) = output {
+        let output = output.replace("{shard}", &args.shard_index.to_string());
+        let mut writer = BufWriter::new(File::create(output)?);
+        writeln!(writer, "realization,impact_time,closest_approach")?;
+        for realization in &estimate.realizations {
+            let time = realization.impact_time.map(|t| t.to_string()).unwrap_or_default();
+            writeln!(writer, "{},{},{}", realization.index, time, realization.closest_approach)?;
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Two-body state of a body relative to the central one.
+struct Orbit {
+    position: Vector,
+    velocity: Vector,
+    mu: f64,
+}
+
+impl Orbit {
+    fn new(body: &Body, central: &Body, gravity: f64) -> Self {
+        Orbit {
+            position: body.position - central.position,
+            velocity: body.velocity - central.velocity,
+            mu: gravity * (central.mass + body.mass),
+        }
+    }
+
+    fn at(&self, time: f64) -> Result<(Vector, Vector), Box<dyn Error>> {
+        kepler::propagate(&self.position, &self.velocity, self.mu, time)
+    }
+}
+
+/// Patched-conic porkchop data: both bodies follow Kepler orbits around the
+/// central body and each transfer is the prograde Lambert arc between them.
+fn run_porkchop(args: &PorkchopArgs) -> Result<(), Box<dyn Error>> {
+    if args.steps < 2 {
+        return Err("--steps must be at least 2".into());
+    }
+    let bodies = scenario::load_with(&args.input, &args.scenario.variables())?;
+    let find = |name: &str| {
+        bodies
+            .iter()
+            .find(|body| body.name == name)
+            .ok_or_else(|| format!("the scenario has no body named '{}'", name))
+    };
+    let central = match &args.central {
+        Some(name) => find(name)?,
+        None => bodies
+            .iter()
+            .max_by(|a, b| a.mass.total_cmp(&b.mass))
+            .ok_or("the scenario has no bodies")?,
+    };
+    let (from, to) = (find(&args.from)?, find(&args.to)?);
+    if from.name == central.name || to.name == central.name {
+        return Err(format!("the transfer bodies must differ from the central body '{}'", central.name).into());
+    }
+    let departure = Orbit::new(from, central, args.gravity);
+    let arrival = Orbit::new(to, central, args.gravity);
+    let mu = args.gravity * central.mass;
+
+    let mut writer = BufWriter::new(File::create(&args.output)?);
+    writeln!(
+        writer,
+        "departure,arrival,time_of_flight,c3,v_inf_departure,v_inf_arrival,delta_v"
+    )?;
+    let departures = grid(args.departure_start, args.departure_end, args.steps);
+    let arrivals = grid(args.arrival_start, args.arrival_end, args.steps);
+    for &departure_time in &departures {
+        let (start, start_velocity) = departure.at(departure_time)?;
+        for &arrival_time in &arrivals {
+            let time_of_flight = arrival_time - departure_time;
+            if time_of_flight <= 0.0 {
+                continue;
+            }
+            let (end, end_velocity) = arrival.at(arrival_time)?;
+            // Arcs without a solution (e.g., exactly opposite positions) leave gaps in the contours.
+            let transfers = kepler::lambert_revolutions(&start, &end, time_of_flight, mu, args.revolutions)
+                .unwrap_or_default();
+            let (v_departure, v_arrival) = transfers
+                .iter()
+                .map(|transfer| {
+                    (
+                        (transfer.departure - start_velocity).norm(),
+                        (end_velocity - transfer.arrival).norm(),
+                    )
+                })
+                .min_by(|a, b| (a.0 + a.1).total_cmp(&(b.0 + b.1)))
+                .unwrap_or((f64::NAN, f64::NAN));
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{}",
+                departure_time,
+                arrival_time,
+                time_of_flight,
+                v_departure * v_departure,
+                v_departure,
+                v_arrival,
+                v_departure + v_arrival
+            )?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// `steps` evenly spaced times from `start` to `end`, both included.
+fn grid(start: f64, end: f64, steps: usize) -> Vec<f64> {
+    (0..steps)
+        .map(|i| start + (end - start) * i as f64 / (steps - 1) as f64)
+        .collect()
+}
+
+/// Hohmann transfer from the current distance of a body to a destination
+/// orbit, with its burns along the body's motion if a schedule is asked for.
+fn run_hohmann(args: &HohmannArgs) -> Result<(), Box<dyn Error>> {
+    let bodies = scenario::load_with(&args.input, &args.scenario.variables())?;
+    let find = |name: &str| {
+        bodies
+            .iter()
+            .find(|body| body.name == name)
+            .ok_or_else(|| format!("the scenario has no body named '{}'", name))
+    };
+    let central = match &args.central {
+        Some(name) => find(name)?,
+        None => bodies
+            .iter()
+            .max_by(|a, b| a.mass.total_cmp(&b.mass))
+            .ok_or("the scenario has no bodies")?,
+    };
+    let from = find(&args.from)?;
+    let target = args.to.as_deref().map(find).transpose()?;
+    if from.name == central.name || target.is_some_and(|target| target.name == central.name) {
+        return Err(format!("the transfer bodies must differ from the central body '{}'", central.name).into());
+    }
+    let departure = Orbit::new(from, central, args.gravity);
+    let radius = match (target, args.to_radius) {
+        (Some(target), _) => (target.position - central.position).norm(),
+        (None, Some(radius)) => radius,
+        (None, None) => unreachable!("clap requires --to or --to-radius"),
+    };
+    let transfer = kepler::hohmann(departure.position.norm(), radius, departure.mu)?;
+
+    println!(
+        "Hohmann transfer about {} from {:.6e} m to {:.6e} m: delta-v {:.6e} m/s ({:+.6e} then {:+.6e} m/s) over {:.6e} s",
+        central.name,
+        departure.position.norm(),
+        radius,
+        transfer.delta_v(),
+        transfer.departure,
+        transfer.arrival,
+        transfer.time
+    );
+    if let Some(target) = target {
+        let destination = Orbit::new(target, central, args.gravity);
+        println!(
+            "{} must lead {} by {:.3} degrees at departure; it leads by {:.3} now",
+            target.name,
+            from.name,
+            transfer.phase_angle.to_degrees(),
+            lead(&departure, &destination).to_degrees()
+        );
+    }
+
+    if let Some(schedule) = &args.schedule {
+        // Both burns along the motion, the second where the transfer arc ends.
+        let first = along_motion(&departure.position, &departure.velocity)? * transfer.departure;
+        let (position, velocity) = kepler::propagate(&departure.position, &(departure.velocity + first), departure.mu, transfer.time)?;
+        let second = along_motion(&position, &velocity)? * transfer.arrival;
+        let mut writer = BufWriter::new(File::create(schedule)?);
+        writeln!(writer, "time,body,delta_v,delta_vx,delta_vy,delta_vz")?;
+        for (time, burn) in [(0.0, first), (transfer.time, second)] {
+            writeln!(
+                writer,
+                "{},\"{}\",{},{},{},{}",
+                time,
+                from.name.replace('"', "\"\""),
+                burn.norm(),
+                burn.x,
+                burn.y,
+                burn.z
+            )?;
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Unit vector of the circular motion through a position, in the plane of
+/// the given velocity.
+fn along_motion(position: &Vector, velocity: &Vector) -> Result<Vector, Box<dyn Error>> {
+    let direction = position.cross(velocity).cross(position);
+    let norm = direction.norm();
+    if norm == 0.0 {
+        return Err("the departing body has no motion about the central body to burn along".into());
+    }
+    Ok(direction / norm)
+}
+
+/// Angle from the departing body to the target, in the direction of motion of
+/// the departing body, between -π and π.
+fn lead(departure: &Orbit, destination: &Orbit) -> f64 {
+    let normal = departure.position.cross(&departure.velocity);
+    let sine = departure.position.cross(&destination.position).dot(&normal) / normal.norm();
+    sine.atan2(departure.position.dot(&destination.position))
+}
+
+/// Measurements of the targets from the observer at every analyzed frame.
+fn run_observe(args: &ObserveArgs) -> Result<(), Box<dyn Error>> {
+    if args.every == 0 {
+        return Err("--every must be at least 1".into());
+    }
+    let mut kinds: Vec<(Kind, f64)> = args
+        .kinds
+        .iter()
+        .map(|&kind| {
+            let sigma = match kind {
+                Kind::Range => 10.0,
+                Kind::RangeRate => 1e-3,
+                Kind::RightAscension | Kind::Declination => 1e-4,
+            };
+            (kind, sigma)
+        })
+        .collect();
+    for (kind, sigma) in &args.sigmas {
+        let kind: Kind = kind.parse()?;
+        let sigma = parse_expression(sigma)?;
+        if !(sigma > 0.0 && sigma.is_finite()) {
+            return Err(format!("--sigma {}: must be positive, got {}", kind, sigma).into());
+        }
+        let entry = kinds
+            .iter_mut()
+            .find(|(measured, _)| *measured == kind)
+            .ok_or_else(|| format!("--sigma {}: not among the --kinds", kind))?;
+        entry.1 = sigma;
+    }
+
+    let reader = SimulationReader::open(&args.input)?;
+    if !reader.has_velocities() {
+        return Err(format!("{} has no velocities; record it again with this version", args.input.display()).into());
+    }
+    let corrections = Corrections {
+        light_time: args.light_time,
+        aberration: args.aberration,
+    };
+    let mut observations = Vec::new();
+    for frame in reader.step_by(args.every) {
+        let frame = frame?;
+        let find = |name: &str| {
+            frame
+                .bodies
+                .iter()
+                .find(|body| body.name == name)
+                .ok_or_else(|| format!("no body named '{}' at time {}", name, frame.time))
+        };
+        let observer = find(&args.observer)?;
+        for target in &args.targets {
+            observations.extend(observation::observe(frame.time, observer, find(target)?, &kinds, corrections));
+        }
+    }
+    if args.noise {
+        observation::add_noise(&mut observations, &mut StdRng::seed_from_u64(args.seed));
+    }
+    observation::write_csv(&args.output, &observations)?;
+    eprintln!("{} observations written to {}", observations.len(), args.output.display());
+    Ok(())
+}
+
+/// Positions of the bodies relative to the observer as it sees them, with how
+/// far the corrections moved them across the sky.
+fn run_apparent(args: &ApparentArgs) -> Result<(), Box<dyn Error>> {
+    if args.every == 0 {
+        return Err("--every must be at least 1".into());
+    }
+    let corrections = Corrections {
+        light_time: !args.no_light_time,
+        aberration: !args.no_aberration,
+    };
+    let reader = SimulationReader::open(&args.input)?;
+    if !reader.has_velocities() {
+        return Err(format!("{} has no velocities; record it again with this version", args.input.display()).into());
+    }
+    let mut writer = BufWriter::new(File::create(&args.output)?);
+    writeln!(writer, "time,name,x,y,z,distance,light_time,shift")?;
+    let mut rows = 0;
+    for frame in reader.step_by(args.every) {
+        let frame = frame?;
+        let observer = frame
+            .bodies
+            .iter()
+            .find(|body| body.name == args.observer)
+            .ok_or_else(|| format!("no body named '{}' at time {}", args.observer, frame.time))?;
+        for name in &args.bodies {
+            if !frame.bodies.iter().any(|body| &body.name == name) {
+                return Err(format!("no body named '{}' at time {}", name, frame.time).into());
+            }
+        }
+        let seen = frame
+            .bodies
+            .iter()
+            .filter(|body| body.name != observer.name && (args.bodies.is_empty() || args.bodies.contains(&body.name)));
+        for body in seen {
+            let apparent = observation::apparent_position(observer, body, corrections);
+            let geometric = body.position - observer.position;
+            // Angle between the directions, in arcseconds.
+            let shift = apparent.cross(&geometric).norm().atan2(apparent.dot(&geometric)).to_degrees() * 3600.0;
+            writeln!(
+                writer,
+                "{},\"{}\",{},{},{},{},{},{}",
+                frame.time,
+                body.name.replace('"', "\"\""),
+                apparent.x,
+                apparent.y,
+                apparent.z,
+                apparent.norm(),
+                apparent.norm() / observation::SPEED_OF_LIGHT,
+                shift
+            )?;
+            rows += 1;
+        }
+    }
+    writer.flush()?;
+    eprintln!("{} apparent positions written to {}", rows, args.output.display());
+    Ok(())
+}
+
+/// Alignments of the two bodies seen from the observer over the recording.
+fn run_eclipses(args: &EclipseArgs) -> Result<(), Box<dyn Error>> {
+    if args.every == 0 {
+        return Err("--every must be at least 1".into());
+    }
+    let [first, second] = args.bodies.as_slice() else {
+        return Err(format!("--bodies takes two bodies, got {}", args.bodies.len()).into());
+    };
+    if !(args.tolerance > 0.0 && args.tolerance < 180.0) {
+        return Err(format!("--tolerance must be between 0 and 180 degrees, got {}", args.tolerance).into());
+    }
+    let corrections = Corrections {
+        light_time: args.apparent,
+        aberration: args.apparent,
+    };
+    let reader = SimulationReader::open(&args.input)?;
+    if args.apparent && !reader.has_velocities() {
+        return Err(format!("{} has no velocities; record it again with this version", args.input.display()).into());
+    }
+    let mut detector = Detector::new(args.tolerance);
+    for frame in reader.step_by(args.every) {
+        let frame = frame?;
+        let find = |name: &str| {
+            frame
+                .bodies
+                .iter()
+                .find(|body| body.name == name)
+                .ok_or_else(|| format!("no body named '{}' at time {}", name, frame.time))
+        };
+        let (separation, first_in_front) =
+            alignment::separation(find(&args.observer)?, find(first)?, find(second)?, corrections);
+        detector.add(frame.time, separation, first_in_front);
+    }
+
+    let alignments = detector.finish();
+    let mut writer = BufWriter::new(File::create(&args.output)?);
+    writeln!(writer, "start,end,closest,separation,front,behind")?;
+    for alignment in &alignments {
+        let (front, behind) = if alignment.first_in_front { (first, second) } else { (second, first) };
+        eprintln!(
+            "{} in front of {} from {} s to {} s, closest at {} s ({:.6} degrees)",
+            front, behind, alignment.start, alignment.end, alignment.closest, alignment.separation
+        );
+        writeln!(
+            writer,
+            "{},{},{},{},\"{}\",\"{}\"",
+            alignment.start,
+            alignment.end,
+            alignment.closest,
+            alignment.separation,
+            front.replace('"', "\"\""),
+            behind.replace('"', "\"\"")
+        )?;
+    }
+    writer.flush()?;
+    eprintln!("{} alignments written to {}", alignments.len(), args.output.display());
+    Ok(())
+}
+
+/// Points of the central body's surface under the bodies at every analyzed frame.
+fn run_ground_track(args: &GroundTrackArgs) -> Result<(), Box<dyn Error>> {
+    if args.every == 0 {
+        return Err("--every must be at least 1".into());
+    }
+    let &[x, y, z] = args.axis.as_slice() else {
+        return Err(format!("--axis takes three components, got {}", args.axis.len()).into());
+    };
+    let rotation = Rotation::new(Vector::new(x, y, z), args.rotation_period, args.prime_meridian)?;
+    let reader = SimulationReader::open(&args.input)?;
+    let mut writer = BufWriter::new(File::create(&args.output)?);
+    writeln!(writer, "time,name,latitude,longitude,altitude")?;
+    let mut names = Vec::new();
+    for frame in reader.step_by(args.every) {
+        let frame = frame?;
+        let central = central_body(&frame, args.central.as_deref())?;
+        if names.is_empty() {
+            names = orbiting(&frame, central, &args.bodies)?;
+        }
+        for name in &names {
+            let body = frame
+                .bodies
+                .iter()
+                .find(|body| body.name == *name)
+                .ok_or_else(|| format!("no body named '{}' at time {}", name, frame.time))?;
+            let position = body.position - central.position;
+            let (latitude, longitude) = rotation.geographic(&position, frame.time);
+            writeln!(
+                writer,
+                "{},\"{}\",{},{},{}",
+                frame.time,
+                name.replace('"', "\"\""),
+                latitude,
+                longitude,
+                position.norm() - args.radius
+            )?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn run_stream(args: &StreamArgs) -> Result<(), Box<dyn Error>> {
+    if args.every == 0 {
+        return Err("--every must be at least 1".into());
+    }
+    let halo = Halo {
+        circular_speed: args.halo_speed,
+        core_radius: args.halo_core,
+    };
+    halo.check()?;
+    let tags = args.tags.iter().cloned().collect();
+    let reader = SimulationReader::open(&args.input)?;
+    let mut writer = BufWriter::new(File::create(&args.output)?);
+    writeln!(writer, "time,bound,stripped,bound_mass,tidal_radius,stream_length")?;
+    let mut last = None;
+    for frame in reader.step_by(args.every) {
+        let frame = frame?;
+        let stars: Vec<Body> = frame.bodies.into_iter().filter(|body| body.has_tags(&tags)).collect();
+        if stars.is_empty() {
+            return Err(format!("no recorded body is a star of the cluster at time {}", frame.time).into());
+        }
+        let census = Census::new(&stars, &halo, args.gravity);
+        writeln!(
+            writer,
+            "{},{},{},{},{},{}",
+            frame.time,
+            census.bound_count(),
+            census.stripped_count(),
+            census.bound_mass,
+            census.tidal_radius,
+            census.stream_length
+        )?;
+        last = Some(census);
+    }
+    writer.flush()?;
+    if let Some(census) = last {
+        println!(
+            "{} of {} stars stripped, along a stream {:.6e} m long",
+            census.stripped_count(),
+            census.bound.len(),
+            census.stream_length
+        );
+    }
+    Ok(())
+}
+
+fn run_cluster(args: &ClusterArgs) -> Result<(), Box<dyn Error>> {
+    if args.every == 0 {
+        return Err("--every must be at least 1".into());
+    }
+    let tags = args.tags.iter().cloned().collect();
+    let reader = SimulationReader::open(&args.input)?;
+    let mut writer = BufWriter::new(File::create(&args.output)?);
+    let radii: Vec<String> =
+        LAGRANGIAN_FRACTIONS.iter().map(|f| format!("lagrangian_{}", (f * 100.0).round())).collect();
+    writeln!(writer, "time,bound,bound_fraction,{},core_radius,velocity_dispersion", radii.join(","))?;
+    let mut last = None;
+    for frame in reader.step_by(args.every) {
+        let frame = frame?;
+        let stars: Vec<Body> = frame.bodies.into_iter().filter(|body| body.has_tags(&tags)).collect();
+        if stars.is_empty() {
+            return Err(format!("no recorded body is a star of the cluster at time {}", frame.time).into());
+        }
+        let statistics = Statistics::new(&stars, args.gravity);
+        let [r10, r50, r90] = statistics.lagrangian_radii;
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{}",
+            frame.time,
+            statistics.bound_count(),
+            statistics.bound_fraction,
+            r10,
+            r50,
+            r90,
+            statistics.core_radius,
+            statistics.velocity_dispersion
+        )?;
+        last = Some(statistics);
+    }
+    writer.flush()?;
+    if let Some(statistics) = last {
+        println!(
+            "{:.1}% of the mass bound, half of it within {:.6e} m, core radius {:.6e} m",
+            100.0 * statistics.bound_fraction,
+            statistics.lagrangian_radii[1],
+            statistics.core_radius
+        );
+    }
+    Ok(())
+}
+
+/// Patched-conic gravity assist: the body's velocity relative to the planet is
+/// turned by the hyperbolic flyby at the chosen periapsis and B-plane angle.
+fn run_flyby(args: &FlybyArgs) -> Result<(), Box<dyn Error>> {
+    let mut bodies = scenario::load_with(&args.input, &args.scenario.variables())?;
+    let index = |name: &str| {
+        bodies
+            .iter()
+            .position(|body| body.name == name)
+            .ok_or_else(|| format!("the scenario has no body named '{}'", name))
+    };
+    let (body, planet) = (index(&args.body)?, index(&args.planet)?);
+    if body == planet {
+        return Err("the body cannot fly by itself".into());
+    }
+    let (spacecraft, world) = (&bodies[body], &bodies[planet]);
+    let mu = args.gravity * (world.mass + spacecraft.mass);
+    let incoming = spacecraft.velocity - world.velocity;
+    let flyby = kepler::flyby(&incoming, mu, args.periapsis, args.b_angle.to_radians())?;
+    let outgoing = world.velocity + flyby.outgoing;
+    println!(
+        "v-infinity {:.6e} m/s turned by {:.3} degrees, B = {:.6e} m; outgoing velocity ({:.6e}, {:.6e}, {:.6e}) m/s, {:+.6e} m/s",
+        incoming.norm(),
+        flyby.turn_angle.to_degrees(),
+        flyby.b_vector.norm(),
+        outgoing.x,
+        outgoing.y,
+        outgoing.z,
+        outgoing.norm() - spacecraft.velocity.norm()
+    );
+
+    if let Some(output) = &args.output {
+        let start_distance = match args.start_distance {
+            Some(distance) => distance,
+            None => {
+                let central = bodies
+                    .iter()
+                    .filter(|other| other.name != world.name && other.name != spacecraft.name)
+                    .max_by(|a, b| a.mass.total_cmp(&b.mass))
+                    .ok_or("no body for the planet to orbit; give --start-distance")?;
+                // Laplace's sphere of influence.
+                (world.position - central.position).norm() * (world.mass / central.mass).powf(0.4)
+            }
+        };
+        let (position, velocity, time) =
+            kepler::before_periapsis(&flyby.periapsis_position, &flyby.periapsis_velocity, mu, start_distance)?;
+        let (position, velocity) = (world.position + position, world.velocity + velocity);
+        let spacecraft = &mut bodies[body];
+        spacecraft.position = position;
+        spacecraft.velocity = velocity;
+        eprintln!(
+            "{} starts {:.6e} m from {}, {:.6e} s before the closest approach; written to {}",
+            args.body,
+            start_distance,
+            args.planet,
+            time,
+            output.display()
+        );
+        scenario::save(output, &bodies)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,436 @@
+use super::notify::{NotifyArgs, Notifier, Notifying};
+use super::output::OutputSpec;
+use super::{close_outputs, open_writer, EventArgs, OutputArgs, PairArgs, ScenarioArgs, SettingsArgs};
+use clap::Args;
+use indicatif::{ProgressBar, ProgressStyle};
+use newtonian_solar_system::dynamics::{simulate_with, Settings};
+use newtonian_solar_system::memory::{self, MemoryUsage};
+use newtonian_solar_system::registry::{self, Registry, RunRecord};
+use newtonian_solar_system::scenario::{self, Variables};
+use newtonian_solar_system::script::Script;
+use serde_json::json;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+#[derive(Args, Debug)]
+pub struct BatchArgs {
+    /// Scenario files, glob patterns such as 'runs/*.json', or directories whose scenario files
+    /// (.json, .csv, .tsv, .bin) are all run
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Directory receiving one output file per scenario
+    #[arg(long, default_value = ".")]
+    pub output_dir: PathBuf,
+
+    /// Output file name; {scenario}, {index} (position in the full batch) and
+    /// {shard} are replaced for every scenario
+    #[arg(long, default_value = "{scenario}.parquet")]
+    pub output_template: String,
+
+    /// Which shard of the batch to run (e.g., "$SLURM_ARRAY_TASK_ID")
+    #[arg(long, default_value_t = 0, requires = "shard_count")]
+    pub shard_index: usize,
+
+    /// Number of shards the batch is split into; scenario i belongs to shard i % count
+    #[arg(long, default_value_t = 1)]
+    pub shard_count: usize,
+
+    /// Number of scenarios simulated in parallel
+    #[arg(short, long, default_value_t = 1)]
+    pub jobs: usize,
+
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+
+    #[command(flatten)]
+    pub scenario: ScenarioArgs,
+
+    #[command(flatten)]
+    pub output_options: OutputArgs,
+
+    /// Record every scenario's run in this registry directory (list them with `runs list`)
+    #[arg(long, value_name = "DIR", num_args = 0..=1, default_missing_value = super::runs::DEFAULT_REGISTRY)]
+    pub registry: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub notify: NotifyArgs,
+}
+
+/// Result of running one scenario of the batch.
+struct Outcome {
+    scenario: PathBuf,
+    output: PathBuf,
+    /// Unix time at which the scenario started.
+    started: u64,
+    elapsed: f64,
+    result: Result<Summary, String>,
+}
+
+struct Summary {
+    run: registry::Summary,
+    memory: MemoryUsage,
+}
+
+pub fn run(args: &BatchArgs) -> Result<(), Box<dyn Error>> {
+    if args.shard_count == 0 || args.shard_index >= args.shard_count {
+        return Err(format!(
+            "shard index {} is out of range for {} shards",
+            args.shard_index, args.shard_count
+        )
+        .into());
+    }
+
+    let scenarios = collect_scenarios(&args.inputs)?;
+    if scenarios.is_empty() {
+        return Err("no scenario files found".into());
+    }
+    // Outputs are checked over the whole batch so shards can never overwrite each other.
+    let outputs = output_paths(&scenarios, &args.output_dir, &args.output_template, args.shard_count)?;
+    let (scenarios, outputs): (Vec<PathBuf>, Vec<PathBuf>) = scenarios
+        .into_iter()
+        .zip(outputs)
+        .enumerate()
+        .filter(|(i, _)| i % args.shard_count == args.shard_index)
+        .map(|(_, pair)| pair)
+        .unzip();
+    if scenarios.is_empty() {
+        println!("shard {} of {} has no scenarios", args.shard_index, args.shard_count);
+        return Ok(());
+    }
+
+    let jobs = args.jobs.max(1);
+    // Scenarios running side by side share the memory budget equally.
+    let settings = Settings {
+        progress: false,
+        max_memory: args.settings.max_memory.map(|max| max / jobs.min(scenarios.len())),
+        ..args.settings.settings()
+    };
+    let registry = args.registry.as_deref().map(Registry::open).transpose()?;
+    let notifier = Notifier::new(&args.notify);
+    let outcomes = run_all(
+        &scenarios,
+        &args.scenario.variables(),
+        &outputs,
+        &settings,
+        &args.output_options,
+        jobs,
+        &notifier,
+    );
+
+    print_summary(&outcomes);
+    if let (Some(registry), Some(directory)) = (&registry, &args.registry) {
+        let ids = record(registry, &outcomes, &settings)?;
+        if let (Some(first), Some(last)) = (ids.first(), ids.last()) {
+            eprintln!("recorded as runs {} to {} in {}", first, last, directory.display());
+        }
+    }
+    let failed = outcomes.iter().filter(|o| o.result.is_err()).count();
+    notifier.completed(json!({
+        "scenarios": outcomes.len(),
+        "succeeded": outcomes.len() - failed,
+        "failed": failed,
+        "failures": outcomes
+            .iter()
+            .filter_map(|o| o.result.as_ref().err().map(|e| json!({
+                "scenario": o.scenario.display().to_string(),
+                "error": e,
+            })))
+            .collect::<Vec<_>>(),
+    }));
+    if failed > 0 {
+        return Err(format!("{} of {} scenarios failed", failed, outcomes.len()).into());
+    }
+    Ok(())
+}
+
+/// Expands the inputs into scenario files, taking every scenario file of directories and every
+/// match of glob patterns.
+fn collect_scenarios(inputs: &[PathBuf]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut scenarios = Vec::new();
+    for input in inputs {
+        let pattern = input.to_string_lossy();
+        if !input.exists() && pattern.contains(['*', '?', '[']) {
+            let mut found: Vec<PathBuf> = glob::glob(&pattern)?.collect::<Result<_, _>>()?;
+            if found.is_empty() {
+                return Err(format!("no scenario matches '{}'", pattern).into());
+            }
+            found.sort();
+            scenarios.extend(found);
+        } else if input.is_dir() {
+            let mut found: Vec<PathBuf> = fs::read_dir(input)?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<Result<_, _>>()?;
+            found.retain(|path| {
+                path.is_file()
+                    && path.extension().is_some_and(|ext| {
+                        scenario::EXTENSIONS.iter().any(|known| ext.eq_ignore_ascii_case(known))
+                    })
+            });
+            found.sort();
+            scenarios.extend(found);
+        } else {
+            scenarios.push(input.clone());
+        }
+    }
+    Ok(scenarios)
+}
+
+/// Names each output from the template, refusing scenarios that would overwrite each other.
+fn output_paths(
+    scenarios: &[PathBuf],
+    output_dir: &Path,
+    template: &str,
+    shard_count: usize,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut seen = HashSet::new();
+    scenarios
+        .iter()
+        .enumerate()
+        .map(|(index, scenario)| {
+            let stem = scenario
+                .file_stem()
+                .ok_or_else(|| format!("invalid scenario path {}", scenario.display()))?;
+            let name = template
+                .replace("{scenario}", &stem.to_string_lossy())
+                .replace("{index}", &index.to_string())
+                .replace("{shard}", &(index % shard_count).to_string());
+            let output = output_dir.join(name);
+            if !seen.insert(output.clone()) {
+                return Err(format!(
+                    "several scenarios would write to {}; rename them or add {{index}} to the output template",
+                    output.display()
+                )
+                .into());
+            }
+            Ok(output)
+        })
+        .collect()
+}
+
+fn run_all(
+    scenarios: &[PathBuf],
+    variables: &Variables,
+    outputs: &[PathBuf],
+    settings: &Settings,
+    options: &OutputArgs,
+    jobs: usize,
+    notifier: &Notifier,
+) -> Vec<Outcome> {
+    let pb = ProgressBar::new(scenarios.len() as u64);
+    pb.set_style(ProgressStyle::default_bar()
+        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} scenarios ({eta})")
+        .unwrap()
+        .progress_chars("=>-"));
+
+    let next = AtomicUsize::new(0);
+    let outcomes = Mutex::new(Vec::with_capacity(scenarios.len()));
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(scenarios.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= scenarios.len() {
+                    break;
+                }
+                let outcome = run_one(&scenarios[i], variables, &outputs[i], settings, options, notifier);
+                let progress = {
+                    let mut outcomes = outcomes.lock().unwrap();
+                    outcomes.push((i, outcome));
+                    pb.inc(1);
+                    json!({
+                        "scenarios": scenarios.len(),
+                        "finished": outcomes.len(),
+                        "failed": outcomes.iter().filter(|(_, o)| o.result.is_err()).count(),
+                    })
+                };
+                // Other jobs shouldn't wait on the webhook.
+                notifier.progress(progress);
+            });
+        }
+    });
+    pb.finish_and_clear();
+
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by_key(|(i, _)| *i);
+    outcomes.into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+fn run_one(
+    scenario: &Path,
+    variables: &Variables,
+    output: &Path,
+    settings: &Settings,
+    options: &OutputArgs,
+    notifier: &Notifier,
+) -> Outcome {
+    let started = registry::now();
+    let start = Instant::now();
+    let result = simulate_scenario(scenario, variables, output, settings, options, notifier).map_err(|e| e.to_string());
+    Outcome {
+        scenario: scenario.to_path_buf(),
+        output: output.to_path_buf(),
+        started,
+        elapsed: start.elapsed().as_secs_f64(),
+        result,
+    }
+}
+
+fn simulate_scenario(
+    scenario: &Path,
+    variables: &Variables,
+    output: &Path,
+    settings: &Settings,
+    options: &OutputArgs,
+    notifier: &Notifier,
+) -> Result<Summary, Box<dyn Error>> {
+    let mut bodies = scenario::load_with(scenario, variables)?;
+    let mut settings = settings.clone();
+    if let Some(script) = scenario::script(scenario, variables)? {
+        settings.script = Some(Script::load(&script)?);
+    }
+    let settings = &settings;
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let outputs = [OutputSpec::new(output.to_path_buf())];
+    let (writer, state) = open_writer(
+        &outputs,
+        &PairArgs::default(),
+        &EventArgs::default(),
+        settings,
+        options,
+        &bodies,
+        Vec::new(),
+    )?;
+    let mut writer = Notifying::new(writer, notifier, scenario.display().to_string(), settings.total_time);
+    let initial_energy = registry::total_energy(&bodies, settings.gravity);
+    simulate_with(&mut bodies, settings, &mut writer)?;
+    let frames = writer.frames();
+    let (peak_buffer, _) = close_outputs(writer.inner)?;
+    let memory = MemoryUsage {
+        state,
+        writer: peak_buffer,
+    };
+    Ok(Summary {
+        run: registry::Summary::new(initial_energy, &bodies, frames, settings.gravity),
+        memory,
+    })
+}
+
+/// Records the run of every scenario, returning their numbers.
+fn record(registry: &Registry, outcomes: &[Outcome], settings: &Settings) -> Result<Vec<u64>, Box<dyn Error>> {
+    outcomes
+        .iter()
+        .map(|o| {
+            let (summary, error) = match &o.result {
+                Ok(summary) => (Some(summary.run.clone()), None),
+                Err(e) => (None, Some(e.clone())),
+            };
+            registry.record(RunRecord {
+                started: o.started,
+                elapsed: o.elapsed,
+                summary,
+                error,
+                ..RunRecord::start(&o.scenario, settings, vec![o.output.clone()])
+            })
+        })
+        .collect()
+}
+
+fn print_summary(outcomes: &[Outcome]) {
+    let rows: Vec<[String; 6]> = outcomes
+        .iter()
+        .map(|o| {
+            let (bodies, frames, memory, status) = match &o.result {
+                Ok(summary) => (
+                    summary.run.bodies.to_string(),
+                    summary.run.frames.to_string(),
+                    memory::format_size(summary.memory.total()),
+                    format!("ok -> {}", o.output.display()),
+                ),
+                Err(e) => ("-".to_string(), "-".to_string(), "-".to_string(), format!("failed: {}", e)),
+            };
+            [o.scenario.display().to_string(), bodies, frames, format!("{:.2}", o.elapsed), memory, status]
+        })
+        .collect();
+
+    let header = ["scenario", "bodies", "frames", "seconds", "est. memory", "status"].map(String::from);
+    let widths: Vec<usize> = (0..5)
+        .map(|c| rows.iter().chain([&header]).map(|r| r[c].len()).max().unwrap_or(0))
+        .collect();
+    for row in [&header].into_iter().chain(&rows) {
+        println!(
+            "{:<w0$}  {:>w1$}  {:>w2$}  {:>w3$}  {:>w4$}  {}",
+            row[0], row[1], row[2], row[3], row[4], row[5],
+            w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3], w4 = widths[4]
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_directories_expand_to_their_scenario_files() {
+        let temp_dir = TempDir::new().unwrap();
+        for name in ["b.json", "a.json", "c.csv", "notes.txt"] {
+            fs::write(temp_dir.path().join(name), "[]").unwrap();
+        }
+        let extra = PathBuf::from("extra.json");
+
+        let scenarios = collect_scenarios(&[temp_dir.path().to_path_buf(), extra.clone()]).unwrap();
+        assert_eq!(
+            scenarios,
+            vec![
+                temp_dir.path().join("a.json"),
+                temp_dir.path().join("b.json"),
+                temp_dir.path().join("c.csv"),
+                extra
+            ]
+        );
+    }
+
+    #[test]
+    fn test_glob_patterns_expand_to_their_matches() {
+        let temp_dir = TempDir::new().unwrap();
+        for name in ["b.json", "a.json", "c.csv"] {
+            fs::write(temp_dir.path().join(name), "[]").unwrap();
+        }
+
+        let pattern = temp_dir.path().join("*.json");
+        let scenarios = collect_scenarios(std::slice::from_ref(&pattern)).unwrap();
+        assert_eq!(scenarios, vec![temp_dir.path().join("a.json"), temp_dir.path().join("b.json")]);
+        assert!(collect_scenarios(&[temp_dir.path().join("*.tsv")]).is_err());
+    }
+
+    #[test]
+    fn test_colliding_outputs_are_rejected() {
+        let scenarios = [PathBuf::from("one/earth.json"), PathBuf::from("two/earth.json")];
+        assert!(output_paths(&scenarios, Path::new("out"), "{scenario}.parquet", 1).is_err());
+        assert_eq!(
+            output_paths(&scenarios[..1], Path::new("out"), "{scenario}.parquet", 1).unwrap(),
+            vec![PathBuf::from("out/earth.parquet")]
+        );
+    }
+
+    #[test]
+    fn test_output_template_placeholders() {
+        let scenarios = [PathBuf::from("one/earth.json"), PathBuf::from("two/earth.json")];
+        assert_eq!(
+            output_paths(&scenarios, Path::new("out"), "shard{shard}/{index}-{scenario}.parquet", 2).unwrap(),
+            vec![
+                PathBuf::from("out/shard0/0-earth.parquet"),
+                PathBuf::from("out/shard1/1-earth.parquet"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use super::{parse_expression, ScenarioArgs};
+use clap::Args;
+use newtonian_solar_system::forces::Forces;
+use newtonian_solar_system::scenario;
+use newtonian_solar_system::tree::{BarnesHut, MultipoleOrder, TreeTimings};
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(Args, Debug)]
+pub struct BenchmarkArgs {
+    /// Scenario with the bodies whose accelerations are evaluated
+    pub input: PathBuf,
+
+    /// Opening angle of the tree code
+    #[arg(long, default_value = "0.5", value_parser = parse_expression)]
+    pub opening_angle: f64,
+
+    /// Expansion of the tree cells: "quadrupole" (2) or "monopole" (0)
+    #[arg(long, default_value = "quadrupole")]
+    pub multipole_order: MultipoleOrder,
+
+    /// Number of evaluations timed
+    #[arg(long, default_value_t = 5)]
+    pub repeat: usize,
+
+    /// Also time the sum over all pairs, for comparison
+    #[arg(long)]
+    pub direct: bool,
+
+    /// Gravitational constant
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+
+    #[command(flatten)]
+    pub scenario: ScenarioArgs,
+}
+
+/// Times the phases of the tree force evaluation on a scenario and prints the
+/// fastest and mean time of each, so the phase that stops scaling with more
+/// threads (or bodies) stands out.
+pub fn run(args: &BenchmarkArgs) -> Result<(), Box<dyn Error>> {
+    if args.repeat == 0 {
+        return Err("--repeat must be at least 1".into());
+    }
+    let tree = BarnesHut {
+        opening_angle: args.opening_angle,
+        order: args.multipole_order,
+    };
+    tree.check()?;
+    let mut bodies = scenario::load_with(&args.input, &args.scenario.variables())?;
+
+    let timings: Vec<TreeTimings> = (0..args.repeat).map(|_| tree.accelerate_timed(&mut bodies, args.gravity)).collect();
+    println!(
+        "{} bodies, {} threads, opening angle {}, {} expansion",
+        bodies.len(),
+        rayon::current_num_threads(),
+        tree.opening_angle,
+        tree.order
+    );
+    println!("{:<10} {:>12} {:>12}", "phase", "fastest", "mean");
+    let phase = |time: fn(&TreeTimings) -> Duration| timings.iter().map(time).collect::<Vec<_>>();
+    report("sort", &phase(|t| t.sort));
+    report("build", &phase(|t| t.build));
+    report("traverse", &phase(|t| t.traverse));
+    report("total", &phase(TreeTimings::total));
+
+    if args.direct {
+        let forces = Forces::newtonian(args.gravity);
+        let direct: Vec<Duration> = (0..args.repeat)
+            .map(|_| {
+                let start = Instant::now();
+                forces.accelerate(&mut bodies);
+                start.elapsed()
+            })
+            .collect();
+        report("direct", &direct);
+    }
+    Ok(())
+}
+
+fn report(phase: &str, times: &[Duration]) {
+    let fastest = times.iter().min().copied().unwrap_or_default();
+    let mean = times.iter().sum::<Duration>() / times.len() as u32;
+    println!(
+        "{:<10} {:>9.3} ms {:>9.3} ms",
+        phase,
+        fastest.as_secs_f64() * 1e3,
+        mean.as_secs_f64() * 1e3
+    );
+}
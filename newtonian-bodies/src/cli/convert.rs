@@ -0,0 +1,208 @@
+use super::parse_expression;
+use clap::{Args, ValueEnum};
+use newtonian_solar_system::interpolate::Sampler;
+use newtonian_solar_system::reader::SimulationReader;
+use newtonian_solar_system::rebound::{self, Snapshot};
+use newtonian_solar_system::dynamics::SequentialWriter;
+use newtonian_solar_system::{blender, gadget, scenario, snapshot, tipsy, vtk, Body};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+#[derive(Args, Debug)]
+pub struct ConvertArgs {
+    /// Recording (Parquet or CSV), REBOUND binary (.bin), snapshot (.msgpack) or JSON scenario to read
+    pub input: PathBuf,
+
+    /// File to write; unless --format is given, the format follows the extension:
+    /// .bin (REBOUND), .msgpack (snapshot), .json (scenario), .gadget (Gadget-2),
+    /// .tipsy/.std (TIPSY) or .pvd (VTK time series of every frame); Blender exports
+    /// need --format blender
+    pub output: PathBuf,
+
+    /// Format of the output file, overriding its extension
+    #[arg(short, long, value_enum)]
+    pub format: Option<Format>,
+
+    /// Time of a recording to export, interpolated between frames; defaults to the last frame
+    #[arg(long, value_parser = parse_expression)]
+    pub time: Option<f64>,
+
+    /// Gravitational constant written to REBOUND files when the input doesn't store one
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// REBOUND binary snapshot
+    Rebound,
+    /// Compact MessagePack snapshot of every field of the bodies, for checkpoints
+    Snapshot,
+    /// Scenario file of initial conditions
+    Json,
+    /// Gadget-2 snapshot (format 1), for SPLASH, yt and similar tools
+    Gadget,
+    /// Standard TIPSY binary
+    Tipsy,
+    /// ParaView time series: a .pvd collection of .vtp files, one per frame
+    Vtk,
+    /// Keyframe CSV of every frame plus a Blender Python script (same name, .py) importing it
+    Blender,
+}
+
+
+impl Format {
+    /// Whether the format holds every frame of a recording rather than a single state.
+    fn is_series(&self) -> bool {
+        matches!(self, Format::Vtk | Format::Blender)
+    }
+
+    fn from_extension(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match extension(path).as_str() {
+            "bin" => Ok(Format::Rebound),
+            "msgpack" => Ok(Format::Snapshot),
+            "json" => Ok(Format::Json),
+            "gadget" => Ok(Format::Gadget),
+            "tipsy" | "std" => Ok(Format::Tipsy),
+            "pvd" => Ok(Format::Vtk),
+            other => Err(format!(
+                "cannot tell the output format from extension '{}'; use --format",
+                other
+            )
+            .into()),
+        }
+    }
+}
+
+/// One state of the bodies, as read from any supported input.
+struct State {
+    time: f64,
+    gravity: Option<f64>,
+    bodies: Vec<Body>,
+}
+
+pub fn run(args: &ConvertArgs) -> Result<(), Box<dyn Error>> {
+    let format = match args.format {
+        Some(format) => format,
+        None => Format::from_extension(&args.output)?,
+    };
+    // Time series take every frame of a recording unless a single time is asked for.
+    if format.is_series() && args.time.is_none() && is_recording(&args.input) {
+        let frames = SimulationReader::open(&args.input)?;
+        return match format {
+            Format::Blender => write_series(blender::Writer::new(&args.output)?, frames, blender::Writer::close),
+            _ => write_series(vtk::Writer::new(&args.output)?, frames, vtk::Writer::close),
+        };
+    }
+
+    let state = load(&args.input, args.time)?;
+    save(&args.output, format, state, args.gravity)
+}
+
+fn write_series<W: SequentialWriter>(
+    mut writer: W,
+    frames: SimulationReader,
+    close: impl FnOnce(W) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    for frame in frames {
+        let frame = frame?;
+        writer.add(frame.time, &frame.bodies)?;
+    }
+    close(writer)
+}
+
+fn is_recording(input: &Path) -> bool {
+    !matches!(extension(input).as_str(), "bin" | "msgpack" | "json")
+}
+
+fn load(input: &Path, time: Option<f64>) -> Result<State, Box<dyn Error>> {
+    match extension(input).as_str() {
+        "bin" => {
+            let snapshot = rebound::read(input)?;
+            Ok(State {
+                time: snapshot.time,
+                gravity: Some(snapshot.gravity),
+                bodies: snapshot.bodies,
+            })
+        }
+        "msgpack" => {
+            let snapshot = snapshot::read(input)?;
+            Ok(State {
+                time: snapshot.time,
+                gravity: snapshot.gravity,
+                bodies: snapshot.bodies,
+            })
+        }
+        "json" => Ok(State {
+            time: 0.0,
+            gravity: None,
+            bodies: scenario::load(input)?,
+        }),
+        _ => load_recording(input, time),
+    }
+}
+
+fn load_recording(input: &Path, time: Option<f64>) -> Result<State, Box<dyn Error>> {
+    let mut reader = SimulationReader::open(input)?;
+    let (time, bodies) = match time {
+        Some(time) => {
+            let bodies = Sampler::new(reader)?
+                .sample(time)?
+                .ok_or_else(|| format!("time {} is outside of the recording", time))?;
+            (time, bodies)
+        }
+        None => {
+            let mut last = None;
+            for frame in reader.by_ref() {
+                last = Some(frame?);
+            }
+            let last = last.ok_or("the recording has no frames")?;
+            (last.time, last.bodies)
+        }
+    };
+    Ok(State {
+        time,
+        gravity: None,
+        bodies,
+    })
+}
+
+fn save(output: &Path, format: Format, state: State, gravity: f64) -> Result<(), Box<dyn Error>> {
+    match format {
+        Format::Rebound => rebound::write(
+            output,
+            &Snapshot {
+                time: state.time,
+                gravity: state.gravity.unwrap_or(gravity),
+                bodies: state.bodies,
+            },
+        ),
+        Format::Snapshot => snapshot::write(
+            output,
+            &snapshot::Snapshot {
+                time: state.time,
+                gravity: state.gravity,
+                bodies: state.bodies,
+            },
+        ),
+        Format::Json => scenario::save(output, &state.bodies),
+        Format::Gadget => gadget::write(output, state.time, &state.bodies),
+        Format::Tipsy => tipsy::write(output, state.time, &state.bodies),
+        Format::Vtk => {
+            let mut writer = vtk::Writer::new(output)?;
+            writer.add(state.time, &state.bodies)?;
+            writer.close()
+        }
+        Format::Blender => {
+            let mut writer = blender::Writer::new(output)?;
+            writer.add(state.time, &state.bodies)?;
+            writer.close()
+        }
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
@@ -0,0 +1,328 @@
+use super::parse_expression;
+use clap::{Args, Subcommand, ValueEnum};
+use newtonian_solar_system::galaxy::Halo;
+use newtonian_solar_system::generate::{circularize, roche_limit, Population, TidalDisruption, TidalStream, AU};
+use newtonian_solar_system::scenario;
+use std::error::Error;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct GenerateArgs {
+    #[command(subcommand)]
+    pub generator: Generator,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Generator {
+    /// A rubble pile on a close approach to a planet, optionally with a ring of test particles
+    TidalDisruption(TidalDisruptionArgs),
+    /// A belt of minor bodies (e.g., the asteroid belt) added to a scenario as test particles
+    Belt(BeltArgs),
+    /// Give bodies of a scenario the velocities of circular orbits about their primaries
+    Circular(CircularArgs),
+    /// A star cluster on an eccentric orbit in a galactic halo, to be stripped into tidal streams
+    Stream(StreamArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct TidalDisruptionArgs {
+    /// Mass of the planet, in kg
+    #[arg(long, default_value = "5.972e24", value_parser = parse_expression)]
+    pub planet_mass: f64,
+
+    /// Total mass of the rubble pile, in kg
+    #[arg(long, default_value = "1e18", value_parser = parse_expression)]
+    pub clump_mass: f64,
+
+    /// Radius of the rubble pile, in meters
+    #[arg(long, default_value = "5e4", value_parser = parse_expression)]
+    pub clump_radius: f64,
+
+    /// Number of particles in the rubble pile
+    #[arg(long, default_value_t = 200)]
+    pub particles: usize,
+
+    /// Closest approach of the pile's center, in meters; defaults to half the Roche limit
+    #[arg(long, value_parser = parse_expression)]
+    pub periapsis: Option<f64>,
+
+    /// Initial distance of the pile's center, in meters; defaults to five Roche limits
+    #[arg(long, value_parser = parse_expression)]
+    pub start_distance: Option<f64>,
+
+    /// Speed of the pile far from the planet, in m/s; zero for a parabolic approach
+    #[arg(long, default_value = "0", value_parser = parse_expression)]
+    pub excess_speed: f64,
+
+    /// Number of test particles on circular orbits in the planet's equatorial plane
+    #[arg(long, default_value_t = 0)]
+    pub ring_particles: usize,
+
+    /// Inner radius of the ring, in meters
+    #[arg(long, default_value = "1e7", value_parser = parse_expression)]
+    pub ring_inner: f64,
+
+    /// Outer radius of the ring, in meters
+    #[arg(long, default_value = "2e7", value_parser = parse_expression)]
+    pub ring_outer: f64,
+
+    /// Seed of the random particle positions
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Scenario file to write
+    #[arg(short, long, default_value = "tidal-disruption.json")]
+    pub output: PathBuf,
+
+    /// Gravitational constant the scenario will be simulated with (e.g., "6.67430e-11")
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+}
+
+#[derive(Args, Debug)]
+pub struct BeltArgs {
+    /// Scenario the belt is added to, with its primary (and the body opening the Kirkwood gaps)
+    pub scenario: PathBuf,
+
+    /// Population whose distributions are drawn from; the options below override them
+    #[arg(long, value_enum, default_value_t = Belt::Main)]
+    pub population: Belt,
+
+    /// Number of particles
+    #[arg(long, default_value_t = 1000)]
+    pub count: usize,
+
+    /// Body the particles orbit (the Sun by default)
+    #[arg(long)]
+    pub primary: Option<String>,
+
+    /// Smallest semi-major axis, in AU
+    #[arg(long, value_parser = parse_expression)]
+    pub inner: Option<f64>,
+
+    /// Largest semi-major axis, in AU
+    #[arg(long, value_parser = parse_expression)]
+    pub outer: Option<f64>,
+
+    /// Scale of the Rayleigh distribution of the eccentricities
+    #[arg(long, value_parser = parse_expression)]
+    pub eccentricity: Option<f64>,
+
+    /// Scale of the Rayleigh distribution of the inclinations, in degrees
+    #[arg(long, value_parser = parse_expression)]
+    pub inclination: Option<f64>,
+
+    /// Body whose 3:1, 5:2, 7:3 and 2:1 resonances are left empty (Jupiter for the main belt)
+    #[arg(long, value_name = "NAME", conflicts_with = "no_kirkwood")]
+    pub kirkwood: Option<String>,
+
+    /// Leave no Kirkwood gaps
+    #[arg(long)]
+    pub no_kirkwood: bool,
+
+    /// Tag `group` of the particles and prefix of their names ("asteroid" or "kbo" by default)
+    #[arg(long)]
+    pub group: Option<String>,
+
+    /// Seed of the random orbits
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Scenario file to write
+    #[arg(short, long, default_value = "belt.json")]
+    pub output: PathBuf,
+
+    /// Gravitational constant the scenario will be simulated with (e.g., "6.67430e-11")
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+}
+
+#[derive(Args, Debug)]
+pub struct CircularArgs {
+    /// Scenario whose bodies are set on circular orbits
+    pub scenario: PathBuf,
+
+    /// Body and the primary it orbits (e.g., "Moon=Earth"), set in order, so
+    /// planets go before their moons; repeat for several
+    #[arg(long = "orbit", value_name = "BODY=PRIMARY", required = true, value_parser = parse_orbit)]
+    pub orbits: Vec<(String, String)>,
+
+    /// Scenario file to write
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// Gravitational constant the scenario will be simulated with (e.g., "6.67430e-11")
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+}
+
+#[derive(Args, Debug)]
+pub struct StreamArgs {
+    /// Mass of the cluster, in kg (1e5 solar masses by default)
+    #[arg(long, default_value = "1e5 * 1.989e30", value_parser = parse_expression)]
+    pub cluster_mass: f64,
+
+    /// Plummer scale radius of the cluster, in meters (5 pc by default)
+    #[arg(long, default_value = "5 * 3.0857e16", value_parser = parse_expression)]
+    pub scale_radius: f64,
+
+    /// Number of stars in the cluster
+    #[arg(long, default_value_t = 500)]
+    pub stars: usize,
+
+    /// Initial distance of the cluster from the center of the halo, in meters (10 kpc by default)
+    #[arg(long, default_value = "1e4 * 3.0857e16", value_parser = parse_expression)]
+    pub distance: f64,
+
+    /// Initial speed of the cluster, perpendicular to its distance, as a fraction of
+    /// the circular speed there; below 1 the cluster starts at its apocenter
+    #[arg(long, default_value = "0.6", value_parser = parse_expression)]
+    pub speed_fraction: f64,
+
+    /// Circular speed of the halo far outside its core, in m/s; simulate with the same
+    /// --halo-speed
+    #[arg(long, default_value = "220e3", value_parser = parse_expression)]
+    pub halo_speed: f64,
+
+    /// Core radius of the halo, in meters (1 kpc by default); simulate with the same --halo-core
+    #[arg(long, default_value = "3.0857e19", value_parser = parse_expression)]
+    pub halo_core: f64,
+
+    /// Seed of the random star positions and velocities
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Scenario file to write
+    #[arg(short, long, default_value = "stream.json")]
+    pub output: PathBuf,
+
+    /// Gravitational constant the scenario will be simulated with (e.g., "6.67430e-11")
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+}
+
+fn parse_orbit(orbit: &str) -> Result<(String, String), String> {
+    match orbit.split_once('=') {
+        Some((body, primary)) if !body.trim().is_empty() && !primary.trim().is_empty() => {
+            Ok((body.trim().to_string(), primary.trim().to_string()))
+        }
+        _ => Err(format!("expected BODY=PRIMARY, got '{}'", orbit)),
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Belt {
+    /// Main asteroid belt, 2.1 to 3.3 AU
+    Main,
+    /// Classical Kuiper belt, 39 to 48 AU
+    Kuiper,
+}
+
+pub fn run(args: &GenerateArgs) -> Result<(), Box<dyn Error>> {
+    match &args.generator {
+        Generator::TidalDisruption(disruption) => run_tidal_disruption(disruption),
+        Generator::Belt(belt) => run_belt(belt),
+        Generator::Circular(circular) => run_circular(circular),
+        Generator::Stream(stream) => run_stream(stream),
+    }
+}
+
+fn run_tidal_disruption(args: &TidalDisruptionArgs) -> Result<(), Box<dyn Error>> {
+    let setup = TidalDisruption {
+        planet_mass: args.planet_mass,
+        clump_mass: args.clump_mass,
+        clump_radius: args.clump_radius,
+        particles: args.particles,
+        periapsis: args.periapsis,
+        start_distance: args.start_distance,
+        excess_speed: args.excess_speed,
+        ring_particles: args.ring_particles,
+        ring: (args.ring_inner, args.ring_outer),
+        seed: args.seed,
+    };
+    let bodies = setup.bodies(args.gravity)?;
+    eprintln!(
+        "Roche limit {:.6e} m; {} bodies written to {}",
+        roche_limit(args.planet_mass, args.clump_mass, args.clump_radius),
+        bodies.len(),
+        args.output.display()
+    );
+    scenario::save(&args.output, &bodies)
+}
+
+fn run_belt(args: &BeltArgs) -> Result<(), Box<dyn Error>> {
+    let mut population = match args.population {
+        Belt::Main => Population::main_belt(args.count),
+        Belt::Kuiper => Population::kuiper_belt(args.count),
+    };
+    if let Some(primary) = &args.primary {
+        population.primary = primary.clone();
+    }
+    let (inner, outer) = population.semi_major_axis;
+    population.semi_major_axis = (args.inner.map_or(inner, |au| au * AU), args.outer.map_or(outer, |au| au * AU));
+    if let Some(eccentricity) = args.eccentricity {
+        population.eccentricity = eccentricity;
+    }
+    if let Some(inclination) = args.inclination {
+        population.inclination = inclination.to_radians();
+    }
+    if args.no_kirkwood {
+        population.kirkwood = None;
+    } else if let Some(kirkwood) = &args.kirkwood {
+        population.kirkwood = Some(kirkwood.clone());
+    }
+    if let Some(group) = &args.group {
+        population.group = group.clone();
+    }
+    population.seed = args.seed;
+
+    let mut bodies = scenario::load(&args.scenario)?;
+    let particles = population.bodies(&bodies, args.gravity)?;
+    eprintln!(
+        "{} {} particles added to the {} bodies of {}, written to {}",
+        particles.len(),
+        population.group,
+        bodies.len(),
+        args.scenario.display(),
+        args.output.display()
+    );
+    bodies.extend(particles);
+    scenario::save(&args.output, &bodies)
+}
+
+fn run_circular(args: &CircularArgs) -> Result<(), Box<dyn Error>> {
+    let mut bodies = scenario::load(&args.scenario)?;
+    for (body, primary) in &args.orbits {
+        circularize(&mut bodies, body, primary, args.gravity)?;
+        let velocity = |name: &str| bodies.iter().find(|b| b.name == name).map(|b| b.velocity);
+        if let (Some(moving), Some(center)) = (velocity(body), velocity(primary)) {
+            eprintln!("{} circles {} at {:.6e} m/s", body, primary, (moving - center).norm());
+        }
+    }
+    scenario::save(&args.output, &bodies)
+}
+
+fn run_stream(args: &StreamArgs) -> Result<(), Box<dyn Error>> {
+    let setup = TidalStream {
+        halo: Halo {
+            circular_speed: args.halo_speed,
+            core_radius: args.halo_core,
+        },
+        cluster_mass: args.cluster_mass,
+        scale_radius: args.scale_radius,
+        stars: args.stars,
+        distance: args.distance,
+        speed_fraction: args.speed_fraction,
+        seed: args.seed,
+    };
+    let bodies = setup.bodies(args.gravity)?;
+    eprintln!(
+        "tidal radius {:.6e} m at the start; {} stars written to {}, to simulate with --halo-speed {} --halo-core {}",
+        setup.halo.tidal_radius(args.cluster_mass, args.distance, args.gravity),
+        bodies.len(),
+        args.output.display(),
+        args.halo_speed,
+        args.halo_core
+    );
+    scenario::save(&args.output, &bodies)
+}
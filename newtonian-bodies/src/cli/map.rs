@@ -0,0 +1,120 @@
+use super::{parse_assignment, parse_expression, ScenarioArgs, SettingsArgs};
+use clap::Args;
+use newtonian_solar_system::kepler::Elements;
+use newtonian_solar_system::scenario;
+use newtonian_solar_system::stability::{self, Element};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// Name of the test particles added to the scenario.
+const PARTICLE: &str = "Test particle";
+
+#[derive(Args, Debug)]
+pub struct MapArgs {
+    /// Scenario the test particles are added to
+    pub input: PathBuf,
+
+    /// Body the test particles orbit; defaults to the most massive body
+    #[arg(long)]
+    pub central: Option<String>,
+
+    /// Orbital element varied along the first axis of the grid, with its range and
+    /// number of values (e.g., "a=7e11:8.5e11:60"): a (m), e, or i, node, peri and
+    /// mean_anomaly (degrees)
+    #[arg(long, value_name = "ELEMENT=START:END:COUNT", value_parser = parse_axis)]
+    pub x: Axis,
+
+    /// Orbital element varied along the second axis of the grid (e.g., "e=0:0.3:30")
+    #[arg(long, value_name = "ELEMENT=START:END:COUNT", value_parser = parse_axis)]
+    pub y: Axis,
+
+    /// Orbital element shared by every test particle (e.g., "i=5"); repeatable, the
+    /// elements set nowhere are zero
+    #[arg(long = "fix", value_name = "ELEMENT=VALUE", value_parser = parse_assignment)]
+    pub fixed: Vec<(String, String)>,
+
+    /// CSV file receiving the MEGNO and FLI of every cell of the grid
+    #[arg(short, long, default_value = "map.csv")]
+    pub output: PathBuf,
+
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+
+    #[command(flatten)]
+    pub scenario: ScenarioArgs,
+}
+
+/// Values an orbital element takes along one axis of the grid.
+#[derive(Debug, Clone)]
+pub struct Axis {
+    pub element: Element,
+    pub values: Vec<f64>,
+}
+
+fn parse_axis(text: &str) -> Result<Axis, String> {
+    let (element, range) = parse_assignment(text)?;
+    let [start, end, count] = range.split(':').collect::<Vec<_>>()[..] else {
+        return Err(format!("expected ELEMENT=START:END:COUNT, got '{}'", text));
+    };
+    let (start, end) = (parse_expression(start)?, parse_expression(end)?);
+    let count: usize = count.trim().parse().map_err(|_| format!("invalid number of values '{}'", count))?;
+    let values = match count {
+        0 => return Err("an axis needs at least one value".to_string()),
+        1 => vec![start],
+        _ => (0..count).map(|i| start + (end - start) * i as f64 / (count - 1) as f64).collect(),
+    };
+    Ok(Axis {
+        element: element.parse()?,
+        values,
+    })
+}
+
+pub fn run(args: &MapArgs) -> Result<(), Box<dyn Error>> {
+    let bodies = scenario::load_with(&args.input, &args.scenario.variables())?;
+    let settings = args.settings.settings();
+    let central = match &args.central {
+        Some(name) => bodies.iter().find(|body| body.name == *name),
+        None => bodies.iter().max_by(|a, b| a.mass.total_cmp(&b.mass)),
+    }
+    .ok_or_else(|| format!("the scenario has no body named '{}'", args.central.as_deref().unwrap_or_default()))?;
+    if args.x.element == args.y.element {
+        return Err(format!("--x and --y both vary {}", args.x.element).into());
+    }
+    let mut base = Elements {
+        semi_major_axis: 0.0,
+        eccentricity: 0.0,
+        inclination: 0.0,
+        ascending_node: 0.0,
+        argument_of_periapsis: 0.0,
+        mean_anomaly: 0.0,
+    };
+    for (element, value) in &args.fixed {
+        let element: Element = element.parse()?;
+        element.set(&mut base, parse_expression(value)?);
+    }
+
+    let mut cells = Vec::new();
+    let mut particles = Vec::new();
+    for &x in &args.x.values {
+        for &y in &args.y.values {
+            let mut elements = base;
+            args.x.element.set(&mut elements, x);
+            args.y.element.set(&mut elements, y);
+            particles.push(stability::particle(PARTICLE, central, &elements, settings.gravity)?);
+            cells.push((x, y));
+        }
+    }
+    eprintln!("integrating {} test particles for {} s", particles.len(), settings.total_time);
+    let indicators = stability::scan(&bodies, &settings, &particles)?;
+
+    let mut writer = BufWriter::new(File::create(&args.output)?);
+    writeln!(writer, "{},{},megno,fli", args.x.element, args.y.element)?;
+    for ((x, y), indicators) in cells.iter().zip(&indicators) {
+        writeln!(writer, "{},{},{},{}", x, y, indicators.megno, indicators.fli)?;
+    }
+    writer.flush()?;
+    eprintln!("stability map written to {}", args.output.display());
+    Ok(())
+}
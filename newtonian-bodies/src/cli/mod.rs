@@ -0,0 +1,658 @@
+pub mod analyze;
+pub mod batch;
+pub mod benchmark;
+pub mod convert;
+pub mod generate;
+pub mod map;
+pub mod notify;
+pub mod od;
+pub mod output;
+pub mod plugin;
+pub mod query;
+pub mod runs;
+pub mod spice;
+pub mod target;
+pub mod threads;
+pub mod worker;
+
+use clap::{Args, ValueEnum};
+use newtonian_solar_system::background::{Background, QueueMetrics};
+use newtonian_solar_system::body::Tags;
+use newtonian_solar_system::contact::Contacts;
+use newtonian_solar_system::dynamics::{Recording, Settings};
+use newtonian_solar_system::fanout::{Downsample, FanOut};
+use newtonian_solar_system::far_field::FarField;
+use newtonian_solar_system::forces::PeriodicBox;
+use newtonian_solar_system::galaxy::Halo;
+use newtonian_solar_system::mass_transfer::{self, MassTransfer};
+use newtonian_solar_system::integrator::Integrator;
+use newtonian_solar_system::memory::{self, MemoryUsage};
+use newtonian_solar_system::plugin::PluginObserver;
+use newtonian_solar_system::partition::PartitionedWriter;
+use newtonian_solar_system::precision::Divergence;
+use newtonian_solar_system::reader;
+use newtonian_solar_system::regularization::Regularization;
+use newtonian_solar_system::scenario::Variables;
+use newtonian_solar_system::sph::Sph;
+use newtonian_solar_system::schema::{Layout, Precision};
+use newtonian_solar_system::thermal::Thermal;
+use newtonian_solar_system::tree::{BarnesHut, MultipoleOrder};
+use newtonian_solar_system::uncertainty::Uncertain;
+use newtonian_solar_system::{pairs, spheres, Body};
+use std::error::Error;
+use output::{Output, OutputFormat, OutputSpec};
+use std::path::{Path, PathBuf};
+
+// Simulation settings shared by every command that runs simulations. (Not a doc
+// comment: clap would use it as the about text of the commands flattening it.)
+#[derive(Args, Debug, Clone)]
+pub struct SettingsArgs {
+    /// Gravitational constant (e.g., "6.67430e-11")
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+
+    /// Number of seconds to simulate (e.g., "60*60*24*365")
+    #[arg(short, long, default_value = "60*60*24*365", value_parser = parse_expression)]
+    pub total_time: f64,
+
+    /// Time step in seconds for finite difference method (e.g., "1.0 / 1000.0")
+    #[arg(short, long, default_value = "0.001", value_parser = parse_expression)]
+    pub delta_t: f64,
+
+    /// Record every N seconds (e.g., "60*10"); the final state is always recorded
+    #[arg(short, long, default_value = "1", value_parser = parse_expression)]
+    pub record_interval: f64,
+
+    /// Record exactly N evenly spaced frames instead of using an interval
+    #[arg(long, conflicts_with = "record_interval")]
+    pub record_count: Option<usize>,
+
+    /// Only record bodies tagged KEY=VALUE (e.g., "category=asteroid"); repeat to require several tags
+    #[arg(long = "record-tag", value_name = "KEY=VALUE", value_parser = parse_assignment)]
+    pub record_tags: Vec<(String, String)>,
+
+    /// Integration scheme: "euler", "verlet" (bounded energy error over long runs), "rk4"
+    /// (records exactly at the requested times) or "patched-conics" (two-body arcs within
+    /// spheres of influence, fast and approximate)
+    #[arg(short, long, default_value = "euler")]
+    pub integrator: Integrator,
+
+    /// Step the bodies tagged KEY=VALUE with another integrator, sharing the force
+    /// evaluations with the rest (e.g., "category=spacecraft=rk4"); repeat for several
+    /// groups, a body following the first it belongs to
+    #[arg(long = "group-integrator", value_name = "KEY=VALUE=INTEGRATOR", value_parser = parse_group_integrator)]
+    pub group_integrators: Vec<(String, String, Integrator)>,
+
+    /// Regularize pairs of bodies closer than this many meters: each pair moves as its
+    /// center of mass and its relative motion is solved in KS variables, so tight
+    /// binaries and close encounters don't need a tiny time step
+    #[arg(long, value_name = "DISTANCE", value_parser = parse_expression)]
+    pub regularize: Option<f64>,
+
+    /// Memory the run may use (e.g., "2G"); runs that can't fit fail before starting
+    /// and the output is flushed to disk in smaller row groups to stay within it
+    #[arg(long, value_parser = memory::parse_size)]
+    pub max_memory: Option<usize>,
+
+    /// Run in a periodic cube of this side in meters (corner at the origin), where
+    /// bodies pull on the nearest image of each other and leave through one face to
+    /// come back through the opposite one
+    #[arg(long, value_name = "SIZE", value_parser = parse_expression)]
+    pub periodic_box: Option<f64>,
+
+    /// Distance beyond which bodies don't pull on each other, found through a grid of
+    /// cells instead of all pairs (in a periodic box, at most half its side, the default)
+    #[arg(long, value_parser = parse_expression)]
+    pub cutoff: Option<f64>,
+
+    /// Treat bodies as gas particles (SPH) with this smoothing length in meters,
+    /// adding isothermal pressure and artificial viscosity to gravity
+    #[arg(long, value_name = "H", value_parser = parse_expression)]
+    pub sph_smoothing: Option<f64>,
+
+    /// Isothermal sound speed of the SPH gas in m/s
+    #[arg(long, default_value = "1000", requires = "sph_smoothing", value_parser = parse_expression)]
+    pub sound_speed: f64,
+
+    /// Linear artificial viscosity coefficient α of the SPH gas (β is 2α)
+    #[arg(long, default_value = "1", requires = "sph_smoothing", value_parser = parse_expression)]
+    pub sph_viscosity: f64,
+
+    /// Only bodies tagged KEY=VALUE are gas (e.g., "phase=gas"); repeat to require several
+    #[arg(long = "gas-tag", value_name = "KEY=VALUE", requires = "sph_smoothing", value_parser = parse_assignment)]
+    pub gas_tags: Vec<(String, String)>,
+
+    /// Evolve the temperature of the bodies that start with one (a `temperature` in
+    /// kelvin in the scenario), relaxing over this many seconds toward the equilibrium
+    /// under the light of the luminous bodies; outputs get a `temperature` column
+    #[arg(long, value_name = "SECONDS", value_parser = parse_expression)]
+    pub thermal_relaxation: Option<f64>,
+
+    /// Luminosity of a body in watts (e.g., "Sun=3.828e26"); repeat for several
+    #[arg(long = "luminosity", value_name = "NAME=WATTS", requires = "thermal_relaxation", value_parser = parse_named_expression)]
+    pub luminosities: Vec<(String, f64)>,
+
+    /// Adiabatic index of the gas whose temperature is tracked
+    #[arg(long, default_value = "5/3", requires = "thermal_relaxation", value_parser = parse_expression)]
+    pub adiabatic_index: f64,
+
+    /// Mean molecular weight of the gas whose temperature is tracked, in hydrogen masses
+    #[arg(long, default_value = "2.34", requires = "thermal_relaxation", value_parser = parse_expression)]
+    pub molecular_weight: f64,
+
+    /// Approximate the gravity of distant groups of bodies with a Barnes-Hut tree,
+    /// opening the cells seen under at least this angle (cell side over distance;
+    /// e.g., "0.5"); 0 gives the exact sum over all pairs
+    #[arg(long, value_name = "THETA", value_parser = parse_expression)]
+    pub opening_angle: Option<f64>,
+
+    /// Expansion of the tree cells: "quadrupole" (2) or "monopole" (0), less
+    /// accurate but cheaper per cell
+    #[arg(long, default_value = "quadrupole", requires = "opening_angle")]
+    pub multipole_order: MultipoleOrder,
+
+    /// Sort the bodies in memory along a Morton (Z-order) curve every N steps, so
+    /// neighbors in space are neighbors in memory; outputs keep the scenario order
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub reorder_every: Option<u64>,
+
+    /// Split gravity at this distance in meters: bodies farther apart pull with the
+    /// positions of the last refresh of the far field, for strongly hierarchical
+    /// systems (e.g., planets and moons among distant stars)
+    #[arg(long, value_name = "RADIUS", value_parser = parse_expression)]
+    pub far_field: Option<f64>,
+
+    /// Steps between refreshes of the far field
+    #[arg(long, value_name = "N", default_value_t = 10, requires = "far_field")]
+    pub far_field_every: usize,
+
+    /// Radius in meters of a solid body (e.g., "Lander=2"); touching solid bodies
+    /// bounce off or rest on each other, resolved by rapier3d; repeat for several
+    #[arg(long = "contact-radius", value_name = "NAME=METERS", value_parser = parse_named_expression)]
+    pub contact_radii: Vec<(String, f64)>,
+
+    /// Share of the normal speed kept when solid bodies bounce, from 0 to 1
+    #[arg(long, default_value = "0.5", requires = "contact_radii", value_parser = parse_expression)]
+    pub restitution: f64,
+
+    /// Friction coefficient between solid bodies
+    #[arg(long, default_value = "0.5", requires = "contact_radii", value_parser = parse_expression)]
+    pub friction: f64,
+
+    /// Add the pull of a galactic halo centered at the origin, a logarithmic potential
+    /// whose rotation curve is flat at this speed in m/s (e.g., "220e3")
+    #[arg(long, value_name = "SPEED", value_parser = parse_expression)]
+    pub halo_speed: Option<f64>,
+
+    /// Core radius of the halo in meters, within which its rotation curve rises
+    #[arg(long, value_name = "RADIUS", default_value = "0", requires = "halo_speed", value_parser = parse_expression)]
+    pub halo_core: f64,
+
+    /// Move mass from a star to its companion while it overflows its Roche lobe
+    /// (e.g., "Donor=Accretor"), keeping the pair's momentum and angular momentum;
+    /// outputs must keep the mass column
+    #[arg(long, value_name = "DONOR=ACCRETOR", requires = "donor_radius", value_parser = parse_assignment)]
+    pub mass_transfer: Option<(String, String)>,
+
+    /// Radius of the donor star, in meters (e.g., "6.957e8")
+    #[arg(long, value_name = "METERS", requires = "mass_transfer", value_parser = parse_expression)]
+    pub donor_radius: Option<f64>,
+
+    /// Mass the donor loses per second when it overfills its Roche lobe by the size
+    /// of the lobe, in kg/s (1e-6 solar masses a year by default); the rate grows as
+    /// the cube of the overfill
+    #[arg(
+        long,
+        value_name = "KG_PER_S",
+        default_value = "1e-6 * 1.989e30 / 3.15576e7",
+        requires = "mass_transfer",
+        value_parser = parse_expression
+    )]
+    pub transfer_rate: f64,
+}
+
+impl SettingsArgs {
+    pub fn settings(&self) -> Settings {
+        Settings {
+            gravity: self.gravity,
+            total_time: self.total_time,
+            dt: self.delta_t,
+            recording: match self.record_count {
+                Some(count) => Recording::Count(count),
+                None => Recording::Interval(self.record_interval),
+            },
+            record_tags: self.record_tags.iter().cloned().collect(),
+            integrator: self.integrator,
+            integrators: self
+                .group_integrators
+                .iter()
+                .map(|(key, value, integrator)| (Tags::from([(key.clone(), value.clone())]), *integrator))
+                .collect(),
+            regularization: self.regularize.map(|separation| Regularization { separation }),
+            progress: true,
+            max_memory: self.max_memory,
+            periodic: self.periodic_box.map(PeriodicBox::new),
+            cutoff: self.cutoff,
+            sph: self.sph_smoothing.map(|h| Sph {
+                alpha: self.sph_viscosity,
+                beta: 2.0 * self.sph_viscosity,
+                gas: self.gas_tags.iter().cloned().collect(),
+                ..Sph::new(h, self.sound_speed)
+            }),
+            thermal: self.thermal_relaxation.map(|relaxation_time| Thermal {
+                gamma: self.adiabatic_index,
+                molecular_weight: self.molecular_weight,
+                relaxation_time,
+                luminosities: self.luminosities.iter().cloned().collect(),
+            }),
+            tree: self.opening_angle.map(|opening_angle| BarnesHut {
+                opening_angle,
+                order: self.multipole_order,
+            }),
+            reorder_every: self.reorder_every.map(|steps| steps as usize),
+            workers: None,
+            far_field: self.far_field.map(|radius| FarField {
+                radius,
+                every: self.far_field_every,
+            }),
+            contacts: (!self.contact_radii.is_empty()).then(|| Contacts {
+                radii: self.contact_radii.iter().cloned().collect(),
+                restitution: self.restitution,
+                friction: self.friction,
+            }),
+            halo: self.halo_speed.map(|circular_speed| Halo {
+                circular_speed,
+                core_radius: self.halo_core,
+            }),
+            mass_transfer: self.mass_transfer.clone().map(|(donor, accretor)| MassTransfer {
+                donor,
+                accretor,
+                donor_radius: self.donor_radius.unwrap_or_default(),
+                rate: self.transfer_rate,
+            }),
+            script: None,
+            plugins: Vec::new(),
+            console: None,
+        }
+    }
+}
+
+// Values of the ${NAME} placeholders of scenario files.
+#[derive(Args, Debug, Clone)]
+pub struct ScenarioArgs {
+    /// Value of a ${NAME} placeholder in scenario files (e.g., "MASS=5.972e24");
+    /// placeholders without one are read from the environment
+    #[arg(long = "set", value_name = "NAME=VALUE", value_parser = parse_assignment)]
+    pub variables: Vec<(String, String)>,
+}
+
+impl ScenarioArgs {
+    pub fn variables(&self) -> Variables {
+        self.variables.iter().cloned().collect()
+    }
+}
+
+/// A recorded frame of an earlier run to start from; see `--initial-from`.
+#[derive(Debug, Clone)]
+pub struct InitialFrom {
+    pub path: PathBuf,
+    /// Seconds into the earlier run; the closest recorded frame is taken.
+    pub time: f64,
+}
+
+impl InitialFrom {
+    pub fn bodies(&self) -> Result<Vec<Body>, Box<dyn Error>> {
+        let frame = reader::frame_at(&self.path, self.time)?;
+        eprintln!("starting from the frame at t = {} s of {}", frame.time, self.path.display());
+        Ok(frame.bodies)
+    }
+}
+
+// Columns and precision of the outputs of runs, unless an output overrides them.
+#[derive(Args, Debug, Clone)]
+pub struct OutputArgs {
+    /// Precision of positions and velocities in the output: "f64", "f32", or a
+    /// number of decimals to round to (e.g., "0" for whole meters, "-3" for kilometers)
+    #[arg(long, default_value = "f64")]
+    pub output_precision: Precision,
+
+    /// Columns to leave out of the output, separated by commas (masses are then
+    /// kept in the file metadata)
+    #[arg(long, value_delimiter = ',')]
+    pub drop_columns: Vec<Column>,
+
+    /// Store positions as differences from each body's previous frame, with
+    /// absolute positions every N frames; smooth trajectories compress much better
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub keyframe_interval: Option<u64>,
+
+    /// Write Parquet outputs as a directory with a file per value of this column
+    /// (Hive-style `name=Earth/` directories), for fast queries of one body's time series
+    #[arg(long, value_name = "COLUMN")]
+    pub partition_by: Option<PartitionBy>,
+
+    /// Frames queued for the writer thread; the simulation waits when the queue is full
+    #[arg(long, value_name = "FRAMES", default_value_t = 8, value_parser = parse_queue)]
+    pub writer_queue: usize,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    /// The mass of every body, which doesn't change during a run
+    Mass,
+    /// vel_x, vel_y and vel_z
+    Velocity,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionBy {
+    /// A file per body
+    Name,
+}
+
+// Pairs of bodies whose relative state is recorded in a table of its own.
+#[derive(Args, Debug, Clone, Default)]
+pub struct PairArgs {
+    /// Record the distance, relative speed and specific orbital energy of two bodies
+    /// at every recorded time (e.g., "Earth,Moon"); repeatable
+    #[arg(long = "pair", value_name = "A,B", value_parser = parse_pair)]
+    pub pairs: Vec<(String, String)>,
+
+    /// CSV file receiving the pairwise quantities
+    #[arg(long, default_value = "pairs.csv")]
+    pub pairs_output: PathBuf,
+}
+
+impl PairArgs {
+    fn check(&self, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        for name in self.pairs.iter().flat_map(|(a, b)| [a, b]) {
+            if !bodies.iter().any(|body| &body.name == name) {
+                return Err(format!("--pair: the scenario has no body named '{}'", name).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+// Events logged in tables of their own next to the outputs.
+#[derive(Args, Debug, Clone, Default)]
+pub struct EventArgs {
+    /// Log the recorded times at which bodies pass from one sphere of influence to
+    /// another, with their state relative to the body they then orbit, to this CSV file
+    #[arg(long, value_name = "FILE", num_args = 0..=1, default_missing_value = "soi_events.csv")]
+    pub soi_events: Option<PathBuf>,
+
+    /// Log the recorded times at which the donor of --mass-transfer starts and stops
+    /// overflowing its Roche lobe, with the masses and separation, to this CSV file
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        default_missing_value = "mass_transfer.csv",
+        requires = "mass_transfer"
+    )]
+    pub transfer_events: Option<PathBuf>,
+}
+
+impl EventArgs {
+    /// Number of event tables logged.
+    fn count(&self) -> usize {
+        usize::from(self.soi_events.is_some()) + usize::from(self.transfer_events.is_some())
+    }
+}
+
+// Initial-state uncertainties propagated alongside a run.
+#[derive(Args, Debug, Clone)]
+pub struct UncertaintyArgs {
+    /// Propagate the uncertainty of a body's initial state, as the standard deviation
+    /// of each position and velocity component (e.g., "Apophis=1e4,0.01"); repeatable
+    #[arg(long = "uncertain", value_name = "NAME=POSITION,VELOCITY", value_parser = parse_assignment)]
+    pub uncertain: Vec<(String, String)>,
+
+    /// CSV file receiving the position uncertainty ellipsoids of every recorded time
+    #[arg(long, default_value = "uncertainty.csv")]
+    pub uncertainty_output: PathBuf,
+}
+
+impl UncertaintyArgs {
+    pub fn uncertain(&self, bodies: &[Body]) -> Result<Vec<Uncertain>, Box<dyn Error>> {
+        self.uncertain
+            .iter()
+            .map(|(name, sigmas)| {
+                let body = bodies
+                    .iter()
+                    .position(|body| &body.name == name)
+                    .ok_or_else(|| format!("--uncertain: the scenario has no body named '{}'", name))?;
+                let sigmas = sigmas.split(',').map(parse_expression).collect::<Result<Vec<_>, _>>()?;
+                match sigmas[..] {
+                    [position, velocity] => Ok(Uncertain::diagonal(body, position, velocity)),
+                    _ => Err(format!("--uncertain {}: expected POSITION,VELOCITY sigmas", name).into()),
+                }
+            })
+            .collect()
+    }
+}
+
+// State transition matrices computed alongside a run.
+#[derive(Args, Debug, Clone)]
+pub struct TransitionArgs {
+    /// Compute the state transition matrix of a body (its sensitivity to its initial
+    /// position and velocity) at every recorded time; repeatable
+    #[arg(long = "stm", value_name = "NAME")]
+    pub stm: Vec<String>,
+
+    /// CSV file receiving the state transition matrices
+    #[arg(long, default_value = "stm.csv")]
+    pub stm_output: PathBuf,
+}
+
+impl TransitionArgs {
+    pub fn selected(&self, bodies: &[Body]) -> Result<Vec<usize>, Box<dyn Error>> {
+        self.stm
+            .iter()
+            .map(|name| {
+                bodies
+                    .iter()
+                    .position(|body| &body.name == name)
+                    .ok_or_else(|| format!("--stm: the scenario has no body named '{}'", name).into())
+            })
+            .collect()
+    }
+}
+
+/// The outputs of a run, each written on its own thread.
+pub type Outputs = FanOut<Background<Downsample<Output>>>;
+
+/// Opens the outputs and the pairs table, if any, each on its own thread,
+/// splitting whatever the simulation state and the frame queues leave of the
+/// memory budget between the Parquet outputs. Returns the writers and the
+/// estimated size of the state, queues included.
+pub fn open_writer(
+    outputs: &[OutputSpec],
+    pairs: &PairArgs,
+    events: &EventArgs,
+    settings: &Settings,
+    args: &OutputArgs,
+    bodies: &[Body],
+    observers: Vec<PluginObserver>,
+) -> Result<(Outputs, usize), Box<dyn Error>> {
+    let state = settings
+        .integrators
+        .iter()
+        .map(|(_, integrator)| *integrator)
+        .chain([settings.integrator])
+        .map(|integrator| memory::simulation_bytes(bodies, integrator, settings.recording.max_frames(settings.total_time)))
+        .max()
+        .unwrap_or_default()
+        + (outputs.len() + usize::from(!pairs.pairs.is_empty()) + events.count() + observers.len())
+        * args.writer_queue
+        * memory::bodies_bytes(bodies);
+    // Checked before creating the outputs so a run that can't fit leaves no file behind.
+    memory::check_budget("the simulation state", state, settings.max_memory)?;
+    pairs.check(bodies)?;
+    if let Some(transfer) = &settings.mass_transfer {
+        transfer.pair(bodies)?;
+        if outputs.iter().any(|spec| !spec.layout(args).mass) {
+            return Err("mass transfer changes the masses, so the outputs must keep the mass column".into());
+        }
+    }
+    let parquet = outputs.iter().filter(|o| o.format() == OutputFormat::Parquet).count();
+    let max_buffer = settings.max_memory.map(|max| max.saturating_sub(state) / parquet.max(1));
+    let temperature = settings.thermal.is_some() && bodies.iter().any(|body| body.temperature.is_some());
+    let mut writers = outputs
+        .iter()
+        .map(|spec| {
+            let layout = Layout {
+                temperature,
+                ..spec.layout(args)
+            };
+            // The shared option only applies to Parquet outputs.
+            let partition_by = match spec.format() {
+                OutputFormat::Parquet => spec.partition_by.or(args.partition_by),
+                _ => spec.partition_by,
+            };
+            let output = match partition_by {
+                Some(PartitionBy::Name) if spec.format() == OutputFormat::Parquet => {
+                    let writer = PartitionedWriter::new(spec.path.clone(), layout)?;
+                    Output::Partitioned(Box::new(match max_buffer {
+                        Some(bytes) => writer.with_max_buffer(bytes),
+                        None => writer,
+                    }))
+                }
+                Some(_) => {
+                    return Err(format!("{}: only Parquet outputs can be partitioned", spec.path.display()).into())
+                }
+                None => match Output::open(spec, layout, max_buffer)? {
+                    Output::Snapshot(writer) => Output::Snapshot(writer.with_gravity(settings.gravity)),
+                    output => output,
+                },
+            };
+            Ok(Background::new(Downsample::new(output, spec.every), args.writer_queue))
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    if !pairs.pairs.is_empty() {
+        let table = pairs::Writer::new(&pairs.pairs_output, pairs.pairs.clone(), settings.gravity)?;
+        writers.push(Background::new(Downsample::new(Output::Pairs(table), 1), args.writer_queue));
+    }
+    if let Some(path) = &events.soi_events {
+        let events = spheres::Writer::new(path)?;
+        writers.push(Background::new(Downsample::new(Output::Spheres(events), 1), args.writer_queue));
+    }
+    if let (Some(path), Some(transfer)) = (&events.transfer_events, &settings.mass_transfer) {
+        let events = mass_transfer::Writer::new(path, transfer.clone())?;
+        writers.push(Background::new(Downsample::new(Output::MassTransfer(events), 1), args.writer_queue));
+    }
+    for observer in observers {
+        writers.push(Background::new(Downsample::new(Output::Plugin(Box::new(observer)), 1), args.writer_queue));
+    }
+    Ok((FanOut::new(writers), state))
+}
+
+/// Waits for every output to be written and closes it. Returns the combined
+/// peak of the writer buffers and the queue metrics of each output.
+pub fn close_outputs(outputs: Outputs) -> Result<(usize, Vec<QueueMetrics>), Box<dyn Error>> {
+    let mut peak_buffer = 0;
+    let mut queues = Vec::new();
+    for writer in outputs.into_inner() {
+        queues.push(writer.metrics());
+        let output = writer.finish()?.into_inner();
+        peak_buffer += output.peak_buffer();
+        output.close()?;
+    }
+    Ok((peak_buffer, queues))
+}
+
+/// Prints how much each writer thread held back the simulation on stderr, given
+/// the files of the writers in the order they were opened.
+pub fn report_queue(outputs: &[&Path], queues: &[QueueMetrics]) {
+    for (output, metrics) in outputs.iter().zip(queues) {
+        let label = if outputs.len() > 1 {
+            format!(" ({})", output.display())
+        } else {
+            String::new()
+        };
+        eprintln!(
+            "writer queue{}: peak {} of {} frames, simulation waited on {} frames",
+            label,
+            metrics.peak_depth,
+            metrics.capacity,
+            metrics.stalls
+        );
+    }
+}
+
+/// Prints the estimated peak memory of a run on stderr.
+pub fn report_memory(usage: &MemoryUsage) {
+    eprintln!(
+        "estimated peak memory: {} (state {}, writer buffers {})",
+        memory::format_size(usage.total()),
+        memory::format_size(usage.state),
+        memory::format_size(usage.writer)
+    );
+}
+
+/// Prints how far the f64 run drifted from the double-double reference on stderr.
+pub fn report_precision(divergence: &[Divergence]) {
+    eprintln!("precision check (f64 against double-double reference):");
+    eprintln!("{:>14}  {:>12}  {:>12}  {:>12}", "time", "position", "relative", "velocity");
+    for d in divergence {
+        eprintln!(
+            "{:>14.6e}  {:>12.3e}  {:>12.3e}  {:>12.3e}",
+            d.time, d.position, d.relative, d.velocity
+        );
+    }
+}
+
+fn parse_queue(text: &str) -> Result<usize, String> {
+    match text.parse() {
+        Ok(0) => Err("the queue needs room for at least one frame".to_string()),
+        Ok(frames) => Ok(frames),
+        Err(e) => Err(format!("{}", e)),
+    }
+}
+
+fn parse_pair(pair: &str) -> Result<(String, String), String> {
+    match pair.split_once(',') {
+        Some((a, b)) if !a.is_empty() && !b.is_empty() && a != b => Ok((a.to_string(), b.to_string())),
+        _ => Err(format!("expected two different body names separated by a comma, got '{}'", pair)),
+    }
+}
+
+/// Parses a `NAME=VALUE` pair.
+pub fn parse_assignment(assignment: &str) -> Result<(String, String), String> {
+    match assignment.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("expected NAME=VALUE, got '{}'", assignment)),
+    }
+}
+
+/// Parses a `FILE:t=TIME` recorded frame.
+pub fn parse_initial_from(text: &str) -> Result<InitialFrom, String> {
+    match text.rsplit_once(":t=") {
+        Some((path, time)) if !path.is_empty() => Ok(InitialFrom {
+            path: PathBuf::from(path),
+            time: parse_expression(time)?,
+        }),
+        _ => Err(format!("expected FILE:t=TIME, got '{}'", text)),
+    }
+}
+
+/// Parses a `KEY=VALUE=INTEGRATOR` group of bodies and its integrator.
+fn parse_group_integrator(group: &str) -> Result<(String, String, Integrator), String> {
+    let (tag, integrator) = group
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE=INTEGRATOR, got '{}'", group))?;
+    let (key, value) = parse_assignment(tag)?;
+    Ok((key, value, integrator.parse()?))
+}
+
+/// Parses a `NAME=WATTS` luminosity.
+fn parse_named_expression(assignment: &str) -> Result<(String, f64), String> {
+    let (name, value) = parse_assignment(assignment)?;
+    Ok((name, parse_expression(&value)?))
+}
+
+/// Parses a string expression (e.g., "60*60*24") into an f64 value.
+pub fn parse_expression(expr_str: &str) -> Result<f64, String> {
+    meval::eval_str(expr_str).map_err(|e| e.to_string())
+}
@@ -0,0 +1,200 @@
+use clap::Args;
+use newtonian_solar_system::dynamics::SequentialWriter;
+use newtonian_solar_system::Body;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Args, Debug, Clone)]
+pub struct NotifyArgs {
+    /// Webhook URL receiving JSON progress updates and a completion (or failure) summary
+    #[arg(long)]
+    pub notify_url: Option<String>,
+
+    /// Minimum number of wall-clock seconds between two progress updates
+    #[arg(long, default_value_t = 60.0)]
+    pub notify_every: f64,
+}
+
+/// Posts JSON events to a webhook.
+///
+/// Delivery problems never interrupt a run: the first failure is reported on
+/// stderr and later ones are ignored.
+pub struct Notifier {
+    agent: ureq::Agent,
+    url: Option<String>,
+    every: Duration,
+    started: Instant,
+    last_progress: Mutex<Option<Instant>>,
+    warned: AtomicBool,
+}
+
+impl Notifier {
+    pub fn new(args: &NotifyArgs) -> Self {
+        Notifier {
+            agent: ureq::AgentBuilder::new().timeout(Duration::from_secs(10)).build(),
+            url: args.notify_url.clone(),
+            every: Duration::from_secs_f64(args.notify_every.max(0.0)),
+            started: Instant::now(),
+            last_progress: Mutex::new(None),
+            warned: AtomicBool::new(false),
+        }
+    }
+
+    /// Sends a progress event unless one was sent less than `notify_every` seconds ago.
+    pub fn progress(&self, fields: Value) {
+        if self.url.is_none() {
+            return;
+        }
+        {
+            let mut last = self.last_progress.lock().unwrap();
+            if last.is_some_and(|at| at.elapsed() < self.every) {
+                return;
+            }
+            *last = Some(Instant::now());
+        }
+        self.send("progress", fields);
+    }
+
+    pub fn completed(&self, fields: Value) {
+        self.send("completed", fields);
+    }
+
+    pub fn failed(&self, error: &dyn Error) {
+        self.send("failed", json!({ "error": error.to_string() }));
+    }
+
+    fn send(&self, event: &str, fields: Value) {
+        let Some(url) = &self.url else { return };
+
+        let mut payload = json!({
+            "event": event,
+            "elapsed_seconds": self.started.elapsed().as_secs_f64(),
+        });
+        if let (Some(payload), Value::Object(fields)) = (payload.as_object_mut(), fields) {
+            payload.extend(fields);
+        }
+
+        let result = self
+            .agent
+            .post(url)
+            .set("Content-Type", "application/json")
+            .send_string(&payload.to_string());
+        if let Err(e) = result
+            && !self.warned.swap(true, Ordering::Relaxed)
+        {
+            eprintln!("warning: could not notify {}: {}", url, e);
+        }
+    }
+}
+
+/// Reports the progress of a run to the webhook as frames are recorded.
+pub struct Notifying<'a, W> {
+    pub inner: W,
+    notifier: &'a Notifier,
+    run: String,
+    total_time: f64,
+    frames: usize,
+}
+
+impl<'a, W> Notifying<'a, W> {
+    pub fn new(inner: W, notifier: &'a Notifier, run: String, total_time: f64) -> Self {
+        Notifying {
+            inner,
+            notifier,
+            run,
+            total_time,
+            frames: 0,
+        }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+}
+
+impl<W: SequentialWriter> SequentialWriter for Notifying<'_, W> {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        self.inner.add(time, bodies)?;
+        self.frames += 1;
+        self.notifier.progress(json!({
+            "run": self.run,
+            "time": time,
+            "total_time": self.total_time,
+            "fraction": if self.total_time > 0.0 { time / self.total_time } else { 1.0 },
+            "frames": self.frames,
+        }));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Accepts `count` requests and returns their bodies.
+    fn serve(listener: TcpListener, count: usize) -> thread::JoinHandle<Vec<Value>> {
+        thread::spawn(move || {
+            (0..count)
+                .map(|_| {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream);
+                    let mut length = 0;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+                    reader
+                        .get_mut()
+                        .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                        .unwrap();
+                    serde_json::from_slice(&body).unwrap()
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn test_progress_is_rate_limited_and_completion_is_sent() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = serve(listener, 2);
+
+        let notifier = Notifier::new(&NotifyArgs {
+            notify_url: Some(url),
+            notify_every: 3600.0,
+        });
+        notifier.progress(json!({ "time": 1.0 }));
+        notifier.progress(json!({ "time": 2.0 }));
+        notifier.completed(json!({ "frames": 3 }));
+
+        let events = server.join().unwrap();
+        assert_eq!(events[0]["event"], "progress");
+        assert_eq!(events[0]["time"], 1.0);
+        assert_eq!(events[1]["event"], "completed");
+        assert_eq!(events[1]["frames"], 3);
+    }
+
+    #[test]
+    fn test_unreachable_webhook_does_not_fail() {
+        let notifier = Notifier::new(&NotifyArgs {
+            notify_url: Some("http://127.0.0.1:9/unreachable".to_string()),
+            notify_every: 0.0,
+        });
+        notifier.completed(json!({}));
+        assert!(notifier.warned.load(Ordering::Relaxed));
+    }
+}
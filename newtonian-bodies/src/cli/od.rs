@@ -0,0 +1,112 @@
+use super::{parse_expression, ScenarioArgs, SettingsArgs};
+use clap::Args;
+use newtonian_solar_system::determination::{self, Fit, Options};
+use newtonian_solar_system::observation::{self, Corrections, Observation};
+use newtonian_solar_system::scenario;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Args, Debug)]
+pub struct OdArgs {
+    /// Scenario with a first guess of the initial conditions
+    pub input: PathBuf,
+
+    /// Body whose initial position and velocity are fitted
+    #[arg(long)]
+    pub body: String,
+
+    /// CSV table of observations with the columns time, observer, target, kind
+    /// (range, range_rate, ra or dec), value and sigma
+    #[arg(long)]
+    pub observations: PathBuf,
+
+    /// Stop once an iteration lowers the weighted RMS of the residuals by less
+    /// than this fraction
+    #[arg(long, default_value = "1e-3", value_parser = parse_expression)]
+    pub tolerance: f64,
+
+    /// Least-squares iterations before giving up
+    #[arg(long, default_value_t = 20)]
+    pub max_iterations: usize,
+
+    /// The observations see the body where it was a light time earlier
+    #[arg(long)]
+    pub light_time: bool,
+
+    /// The observed angles are aberrated by the velocity of the observer
+    #[arg(long)]
+    pub aberration: bool,
+
+    /// Scenario file receiving the fitted initial conditions
+    #[arg(short, long, default_value = "fitted.json")]
+    pub output: PathBuf,
+
+    /// CSV file receiving the residual of every observation of the body
+    #[arg(long)]
+    pub residuals: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+
+    #[command(flatten)]
+    pub scenario: ScenarioArgs,
+}
+
+pub fn run(args: &OdArgs) -> Result<(), Box<dyn Error>> {
+    let bodies = scenario::load_with(&args.input, &args.scenario.variables())?;
+    let body = bodies
+        .iter()
+        .position(|body| body.name == args.body)
+        .ok_or_else(|| format!("the scenario has no body named '{}'", args.body))?;
+    let observations = observation::read_csv(&args.observations)?;
+    let options = Options {
+        tolerance: args.tolerance,
+        max_iterations: args.max_iterations,
+        corrections: Corrections {
+            light_time: args.light_time,
+            aberration: args.aberration,
+        },
+    };
+
+    let fit = determination::fit(&bodies, &args.settings.settings(), body, &observations, options)?;
+    for (iteration, rms) in fit.rms.iter().enumerate() {
+        eprintln!("iteration {:>3}: weighted RMS {:.6e}", iteration, rms);
+    }
+    let (before, after) = (&bodies[body], &fit.bodies[body]);
+    let sigma = |from: usize| (from..from + 3).map(|i| fit.covariance[i][i]).sum::<f64>().sqrt();
+    eprintln!(
+        "{}: position moved {:.6e} m (1-sigma {:.3e} m), velocity changed {:.6e} m/s (1-sigma {:.3e} m/s)",
+        after.name,
+        (after.position - before.position).norm(),
+        sigma(0),
+        (after.velocity - before.velocity).norm(),
+        sigma(3)
+    );
+    if let Some(path) = &args.residuals {
+        let used: Vec<&Observation> = observations.iter().filter(|o| o.target == after.name).collect();
+        write_residuals(path, &used, &fit)?;
+        eprintln!("residuals written to {}", path.display());
+    }
+    scenario::save(&args.output, &fit.bodies)
+}
+
+fn write_residuals(path: &Path, observations: &[&Observation], fit: &Fit) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "time,observer,kind,value,residual,sigma")?;
+    for (observation, residual) in observations.iter().zip(&fit.residuals) {
+        writeln!(
+            writer,
+            "{},\"{}\",{},{},{},{}",
+            observation.time,
+            observation.observer.replace('"', "\"\""),
+            observation.kind,
+            observation.value,
+            residual,
+            observation.sigma
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use super::{Column, OutputArgs, PartitionBy};
+use clap::ValueEnum;
+use newtonian_solar_system::dynamics::SequentialWriter;
+use newtonian_solar_system::partition::PartitionedWriter;
+use newtonian_solar_system::plugin::PluginObserver;
+use newtonian_solar_system::schema::{Layout, Precision};
+use newtonian_solar_system::writer::{CsvWriter, Writer};
+use newtonian_solar_system::jsonl::{self, Grouping};
+use newtonian_solar_system::{blender, mass_transfer, pairs, snapshot, spheres, vtk, Body};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// One output of a run: `FILE[,KEY=VALUE...]`, where the keys override the
+/// output options for this file (e.g., "plot.csv,every=10,precision=f32").
+#[derive(Debug, Clone)]
+pub struct OutputSpec {
+    pub path: PathBuf,
+    pub format: Option<OutputFormat>,
+    /// Write every n-th recorded frame.
+    pub every: usize,
+    pub precision: Option<Precision>,
+    pub drop_columns: Option<Vec<Column>>,
+    pub keyframe_interval: Option<usize>,
+    pub partition_by: Option<PartitionBy>,
+    /// Lines of a JSON Lines output: a body each or a frame each.
+    pub group: Option<Grouping>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Parquet,
+    /// Same columns as Parquet, for plotting and spreadsheets
+    Csv,
+    /// ParaView time series (.pvd collection of .vtp frames)
+    Vtk,
+    /// Keyframe CSV plus a Blender import script
+    Blender,
+    /// A JSON object per body and frame (or per frame), for jq and streaming
+    Jsonl,
+    /// MessagePack snapshot of the last recorded frame, replaced at every frame
+    Snapshot,
+}
+
+impl OutputSpec {
+    /// Writes every frame to `path` with the shared output options.
+    pub fn new(path: PathBuf) -> Self {
+        OutputSpec {
+            path,
+            format: None,
+            every: 1,
+            precision: None,
+            drop_columns: None,
+            keyframe_interval: None,
+            partition_by: None,
+            group: None,
+        }
+    }
+
+    /// Format given explicitly or by the extension; JSON Lines for the
+    /// standard output (`-`) and Parquet otherwise.
+    pub fn format(&self) -> OutputFormat {
+        if self.path == Path::new("-") {
+            return self.format.unwrap_or(OutputFormat::Jsonl);
+        }
+        let extension = self.path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+        self.format.unwrap_or(match extension.to_ascii_lowercase().as_str() {
+            "csv" => OutputFormat::Csv,
+            "pvd" => OutputFormat::Vtk,
+            "jsonl" | "ndjson" => OutputFormat::Jsonl,
+            "msgpack" => OutputFormat::Snapshot,
+            _ => OutputFormat::Parquet,
+        })
+    }
+
+    pub fn layout(&self, defaults: &OutputArgs) -> Layout {
+        let drop_columns = self.drop_columns.as_ref().unwrap_or(&defaults.drop_columns);
+        Layout {
+            precision: self.precision.unwrap_or(defaults.output_precision),
+            mass: !drop_columns.contains(&Column::Mass),
+            velocities: !drop_columns.contains(&Column::Velocity),
+            keyframe_interval: self.keyframe_interval.or(defaults.keyframe_interval.map(|n| n as usize)),
+            temperature: false,
+        }
+    }
+}
+
+impl FromStr for OutputSpec {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parts = text.split(',');
+        let path = parts.next().filter(|path| !path.is_empty()).ok_or("missing output file")?;
+        let mut spec = OutputSpec::new(PathBuf::from(path));
+        for option in parts {
+            let (key, value) = option
+                .split_once('=')
+                .ok_or_else(|| format!("expected KEY=VALUE after the output file, got '{}'", option))?;
+            match key {
+                "format" => spec.format = Some(OutputFormat::from_str(value, true)?),
+                "every" => spec.every = value.parse().ok().filter(|&n| n > 0).ok_or("every needs a positive count")?,
+                "precision" => spec.precision = Some(value.parse()?),
+                "drop" => spec
+                    .drop_columns
+                    .get_or_insert_with(Vec::new)
+                    .push(Column::from_str(value, true)?),
+                "partition" => spec.partition_by = Some(PartitionBy::from_str(value, true)?),
+                "group" => spec.group = Some(value.parse()?),
+                "keyframes" => {
+                    spec.keyframe_interval =
+                        Some(value.parse().ok().filter(|&n| n > 0).ok_or("keyframes needs a positive count")?)
+                }
+                _ => {
+                    return Err(format!(
+                        "unknown output option '{}' \
+                         (expected format, every, precision, drop, keyframes, partition or group)",
+                        key
+                    ))
+                }
+            }
+        }
+        if spec.group.is_some() && spec.format() != OutputFormat::Jsonl {
+            return Err(format!("{}: only JSON Lines outputs can be grouped", spec.path.display()));
+        }
+        if spec.path == Path::new("-") && spec.format() != OutputFormat::Jsonl {
+            return Err("only JSON Lines outputs can be written to the standard output".to_string());
+        }
+        Ok(spec)
+    }
+}
+
+/// A writer of any of the output formats, of the pairwise quantities table, of
+/// the sphere of influence or mass transfer events or of a plugin.
+pub enum Output {
+    // Boxed: the Parquet writer and plugin instances are much larger than the others.
+    Parquet(Box<Writer>),
+    Partitioned(Box<PartitionedWriter>),
+    Csv(CsvWriter),
+    Vtk(vtk::Writer),
+    Blender(blender::Writer),
+    Jsonl(jsonl::Writer),
+    Snapshot(snapshot::Writer),
+    Pairs(pairs::Writer),
+    Spheres(spheres::Writer),
+    MassTransfer(mass_transfer::Writer),
+    Plugin(Box<PluginObserver>),
+}
+
+impl Output {
+    pub fn open(spec: &OutputSpec, layout: Layout, max_buffer: Option<usize>) -> Result<Self, Box<dyn Error>> {
+        let path = spec.path.as_path();
+        Ok(match spec.format() {
+            OutputFormat::Parquet => {
+                let writer = Writer::with_layout(path.to_path_buf(), layout)?;
+                Output::Parquet(Box::new(match max_buffer {
+                    Some(bytes) => writer.with_max_buffer(bytes),
+                    None => writer,
+                }))
+            }
+            OutputFormat::Csv => Output::Csv(CsvWriter::new(path.to_path_buf(), layout)?),
+            OutputFormat::Vtk => Output::Vtk(vtk::Writer::new(path)?),
+            OutputFormat::Blender => Output::Blender(blender::Writer::new(path)?),
+            OutputFormat::Jsonl => Output::Jsonl(jsonl::Writer::new(path, layout, spec.group.unwrap_or_default())?),
+            OutputFormat::Snapshot => Output::Snapshot(snapshot::Writer::new(path)?),
+        })
+    }
+
+    /// Largest amount of memory held by rows not yet flushed to disk.
+    pub fn peak_buffer(&self) -> usize {
+        match self {
+            Output::Parquet(writer) => writer.peak_buffer(),
+            Output::Partitioned(writer) => writer.peak_buffer(),
+            _ => 0,
+        }
+    }
+
+    pub fn close(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Output::Parquet(writer) => writer.close(),
+            Output::Partitioned(writer) => writer.close(),
+            Output::Csv(writer) => writer.close(),
+            Output::Vtk(writer) => writer.close(),
+            Output::Blender(writer) => writer.close(),
+            Output::Jsonl(writer) => writer.close(),
+            Output::Snapshot(writer) => writer.close(),
+            Output::Pairs(writer) => writer.close(),
+            Output::Spheres(writer) => writer.close(),
+            Output::MassTransfer(writer) => writer.close(),
+            Output::Plugin(observer) => observer.close(),
+        }
+    }
+}
+
+impl SequentialWriter for Output {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        match self {
+            Output::Parquet(writer) => writer.add(time, bodies),
+            Output::Partitioned(writer) => writer.add(time, bodies),
+            Output::Csv(writer) => writer.add(time, bodies),
+            Output::Vtk(writer) => writer.add(time, bodies),
+            Output::Blender(writer) => writer.add(time, bodies),
+            Output::Jsonl(writer) => writer.add(time, bodies),
+            Output::Snapshot(writer) => writer.add(time, bodies),
+            Output::Pairs(writer) => writer.add(time, bodies),
+            Output::Spheres(writer) => writer.add(time, bodies),
+            Output::MassTransfer(writer) => writer.add(time, bodies),
+            Output::Plugin(observer) => observer.add(time, bodies),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use newtonian_solar_system::dynamics::Settings;
+use newtonian_solar_system::plugin::{Plugin, PluginObserver};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// A WebAssembly plugin of a run: `FILE[,output=FILE]`, where the output
+/// receives what the plugin emits while observing the frames (the standard
+/// output by default).
+#[derive(Debug, Clone)]
+pub struct PluginSpec {
+    pub path: PathBuf,
+    pub output: Option<PathBuf>,
+}
+
+impl FromStr for PluginSpec {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parts = text.split(',');
+        let path = parts.next().filter(|path| !path.is_empty()).ok_or("missing plugin file")?;
+        let mut spec = PluginSpec {
+            path: PathBuf::from(path),
+            output: None,
+        };
+        for option in parts {
+            match option.split_once('=') {
+                Some(("output", output)) => spec.output = Some(PathBuf::from(output)),
+                _ => return Err(format!("expected output=FILE after the plugin file, got '{}'", option)),
+            }
+        }
+        Ok(spec)
+    }
+}
+
+/// Loads the plugins of a run: their forces go to `settings`, and the observers
+/// of the frames are returned.
+pub fn load(specs: &[PluginSpec], settings: &mut Settings) -> Result<Vec<PluginObserver>, Box<dyn Error>> {
+    let mut observers = Vec::new();
+    for spec in specs {
+        let plugin = Plugin::load(&spec.path)?;
+        if plugin.records() {
+            let output: Box<dyn Write + Send> = match &spec.output {
+                Some(path) => Box::new(BufWriter::new(
+                    File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+                )),
+                None => Box::new(io::stdout()),
+            };
+            observers.push(PluginObserver::new(&plugin, output)?);
+        } else if spec.output.is_some() {
+            return Err(format!("{} only adds forces, so it has no output", spec.path.display()).into());
+        }
+        settings.plugins.push(plugin);
+    }
+    Ok(observers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plugin_spec() {
+        let spec: PluginSpec = "ephemeris.wasm".parse().unwrap();
+        assert_eq!(spec.path, PathBuf::from("ephemeris.wasm"));
+        assert_eq!(spec.output, None);
+        let spec: PluginSpec = "sp3.wasm,output=orbits.sp3".parse().unwrap();
+        assert_eq!(spec.output, Some(PathBuf::from("orbits.sp3")));
+        assert!("sp3.wasm,every=2".parse::<PluginSpec>().is_err());
+        assert!(",output=x".parse::<PluginSpec>().is_err());
+    }
+}
@@ -0,0 +1,58 @@
+use clap::Args;
+use newtonian_solar_system::query::{self, Table, DEFAULT_TABLE};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct QueryArgs {
+    /// SQL to run (e.g., "SELECT max(pos_x) FROM run WHERE name = 'Moon'")
+    pub sql: String,
+
+    /// Outputs to query: Parquet or CSV files, or directories written with
+    /// --partition-by. Each is the table `run` unless named with NAME=FILE; files
+    /// sharing a name are stacked, with a `file` column
+    #[arg(required = true, value_name = "[NAME=]FILE", value_parser = parse_table)]
+    pub tables: Vec<Table>,
+
+    /// CSV file receiving the result instead of printing it as a table
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+/// Parses a `[NAME=]FILE` table; paths like `orbits/name=Earth` are files.
+fn parse_table(text: &str) -> Result<Table, String> {
+    let (name, path) = match text.split_once('=') {
+        Some((name, path)) if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+            (name, path)
+        }
+        _ => (DEFAULT_TABLE, text),
+    };
+    if path.is_empty() {
+        return Err(format!("missing file for table '{}'", name));
+    }
+    Ok(Table {
+        name: name.to_string(),
+        path: PathBuf::from(path),
+    })
+}
+
+pub fn run(args: &QueryArgs) -> Result<(), Box<dyn Error>> {
+    for table in &args.tables {
+        if !table.path.exists() {
+            return Err(format!("{} doesn't exist", table.path.display()).into());
+        }
+    }
+    let rows = query::query(&args.sql, &args.tables)?;
+    match &args.output {
+        Some(path) => {
+            let mut csv = BufWriter::new(File::create(path)?);
+            rows.write_csv(&mut csv)?;
+            csv.flush()?;
+            eprintln!("{} rows written to {}", rows.rows.len(), path.display());
+        }
+        None => rows.write_table(&mut io::stdout().lock())?,
+    }
+    Ok(())
+}
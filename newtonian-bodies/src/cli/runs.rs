@@ -0,0 +1,105 @@
+use clap::{Args, Subcommand};
+use newtonian_solar_system::registry::{self, Registry, RunRecord};
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Registry directory used when `--registry` is given without one.
+pub const DEFAULT_REGISTRY: &str = "newtonian-runs";
+
+#[derive(Args, Debug)]
+pub struct RunsArgs {
+    /// Registry directory the runs were recorded in
+    #[arg(long, global = true, value_name = "DIR", default_value = DEFAULT_REGISTRY)]
+    pub registry: PathBuf,
+
+    #[command(subcommand)]
+    pub command: RunsCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RunsCommand {
+    /// Print a table of the recorded runs, oldest first
+    List {
+        /// Only the runs of scenarios whose path contains this text
+        #[arg(long)]
+        scenario: Option<String>,
+
+        /// Only the runs that failed
+        #[arg(long)]
+        failed: bool,
+    },
+    /// Print everything recorded about a run, as JSON
+    Show {
+        /// Number of the run, as listed
+        id: u64,
+    },
+}
+
+pub fn run(args: &RunsArgs) -> Result<(), Box<dyn Error>> {
+    let registry = Registry::open(&args.registry)?;
+    match &args.command {
+        RunsCommand::List { scenario, failed } => {
+            let runs: Vec<RunRecord> = registry
+                .runs()?
+                .into_iter()
+                .filter(|run| !failed || !run.succeeded())
+                .filter(|run| {
+                    scenario
+                        .as_ref()
+                        .is_none_or(|text| run.manifest.scenario.to_string_lossy().contains(text.as_str()))
+                })
+                .collect();
+            if runs.is_empty() {
+                println!("no runs recorded in {}", args.registry.display());
+            } else {
+                print_runs(&runs);
+            }
+        }
+        RunsCommand::Show { id } => println!("{}", serde_json::to_string_pretty(&registry.run(*id)?)?),
+    }
+    Ok(())
+}
+
+fn print_runs(runs: &[RunRecord]) {
+    let rows: Vec<[String; 7]> = runs
+        .iter()
+        .map(|run| {
+            let (bodies, frames, drift) = match &run.summary {
+                Some(summary) => (
+                    summary.bodies.to_string(),
+                    summary.frames.to_string(),
+                    summary.energy_drift.map_or("-".to_string(), |drift| format!("{:.2e}", drift)),
+                ),
+                None => ("-".to_string(), "-".to_string(), "-".to_string()),
+            };
+            let status = match &run.error {
+                Some(e) => format!("failed: {}", e),
+                None => format!(
+                    "ok -> {}",
+                    run.outputs.iter().map(|output| output.display().to_string()).collect::<Vec<_>>().join(", ")
+                ),
+            };
+            [
+                run.id.to_string(),
+                registry::format_time(run.started),
+                run.manifest.scenario.display().to_string(),
+                bodies,
+                frames,
+                drift,
+                status,
+            ]
+        })
+        .collect();
+
+    let header = ["run", "started (UTC)", "scenario", "bodies", "frames", "energy drift", "status"].map(String::from);
+    let widths: Vec<usize> = (0..6)
+        .map(|c| rows.iter().chain([&header]).map(|r| r[c].len()).max().unwrap_or(0))
+        .collect();
+    for row in [&header].into_iter().chain(&rows) {
+        println!(
+            "{:>w0$}  {:<w1$}  {:<w2$}  {:>w3$}  {:>w4$}  {:>w5$}  {}",
+            row[0], row[1], row[2], row[3], row[4], row[5], row[6],
+            w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3], w4 = widths[4], w5 = widths[5]
+        );
+    }
+}
@@ -0,0 +1,33 @@
+use super::parse_expression;
+use clap::Args;
+use newtonian_solar_system::{scenario, spice};
+use std::error::Error;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct SpiceArgs {
+    /// Bodies to include (e.g., "sun earth moon jupiter")
+    #[arg(required = true)]
+    pub bodies: Vec<String>,
+
+    /// SPK kernel with the ephemeris (e.g., "de440.bsp"); repeat to combine kernels
+    #[arg(short, long = "kernel", required = true)]
+    pub kernels: Vec<PathBuf>,
+
+    /// Epoch of the initial conditions: "YYYY-MM-DD[THH:MM:SS]" (TDB) or seconds past J2000
+    #[arg(short, long, default_value = "2000-01-01T12:00:00", value_parser = spice::parse_epoch)]
+    pub epoch: f64,
+
+    /// Scenario file to write
+    #[arg(short, long, default_value = "scenario.json")]
+    pub output: PathBuf,
+
+    /// Gravitational constant the scenario will be simulated with, used to turn GM into masses
+    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
+    pub gravity: f64,
+}
+
+pub fn run(args: &SpiceArgs) -> Result<(), Box<dyn Error>> {
+    let bodies = spice::bodies(&args.kernels, &args.bodies, args.epoch, args.gravity)?;
+    scenario::save(&args.output, &bodies)
+}
@@ -0,0 +1,109 @@
+use super::{parse_expression, ScenarioArgs, SettingsArgs};
+use clap::Args;
+use newtonian_solar_system::body::Vector;
+use newtonian_solar_system::scenario;
+use newtonian_solar_system::targeting::{self, Component, Goal, Options};
+use newtonian_solar_system::Body;
+use std::error::Error;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct TargetArgs {
+    /// Scenario with the initial conditions to correct
+    pub input: PathBuf,
+
+    /// Body whose initial state is adjusted (e.g., a spacecraft)
+    #[arg(long)]
+    pub body: String,
+
+    /// Components of its initial state to adjust: x, y, z, vx, vy, vz
+    #[arg(long, value_delimiter = ',', default_value = "vx,vy,vz")]
+    pub vary: Vec<Component>,
+
+    /// Position to reach at the end of the run, as "x,y,z" (e.g., "1.5e11,0,0")
+    #[arg(long, value_parser = parse_position, required_unless_present = "flyby", conflicts_with = "flyby")]
+    pub final_position: Option<Vector>,
+
+    /// Body the final position is measured from; defaults to the origin
+    #[arg(long, requires = "final_position")]
+    pub relative_to: Option<String>,
+
+    /// Body to fly by at --distance
+    #[arg(long, requires = "distance")]
+    pub flyby: Option<String>,
+
+    /// Closest approach to the --flyby body, in meters
+    #[arg(long, value_parser = parse_expression)]
+    pub distance: Option<f64>,
+
+    /// Largest acceptable miss, in meters
+    #[arg(long, default_value = "1", value_parser = parse_expression)]
+    pub tolerance: f64,
+
+    /// Newton iterations before giving up
+    #[arg(long, default_value_t = 20)]
+    pub max_iterations: usize,
+
+    /// Scenario file receiving the corrected initial conditions
+    #[arg(short, long, default_value = "targeted.json")]
+    pub output: PathBuf,
+
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+
+    #[command(flatten)]
+    pub scenario: ScenarioArgs,
+}
+
+pub fn run(args: &TargetArgs) -> Result<(), Box<dyn Error>> {
+    let bodies = scenario::load_with(&args.input, &args.scenario.variables())?;
+    let index = |name: &str| {
+        bodies
+            .iter()
+            .position(|body| body.name == name)
+            .ok_or_else(|| format!("the scenario has no body named '{}'", name))
+    };
+    let goal = match (&args.final_position, &args.flyby, args.distance) {
+        (Some(position), _, _) => Goal::FinalPosition {
+            position: *position,
+            relative_to: args.relative_to.as_deref().map(index).transpose()?,
+        },
+        (None, Some(target), Some(distance)) => Goal::Flyby {
+            target: index(target)?,
+            distance,
+        },
+        _ => return Err("give either --final-position or --flyby with --distance".into()),
+    };
+    let options = Options {
+        tolerance: args.tolerance,
+        max_iterations: args.max_iterations,
+    };
+
+    let body = index(&args.body)?;
+    let correction = targeting::correct(&bodies, &args.settings.settings(), body, &args.vary, &goal, options)?;
+    for (iteration, miss) in correction.misses.iter().enumerate() {
+        eprintln!("iteration {:>3}: miss {:.6e} m", iteration, miss);
+    }
+    report(&bodies[body], &correction.bodies[body]);
+    scenario::save(&args.output, &correction.bodies)
+}
+
+/// Prints the change of the adjusted body's initial state on stderr.
+fn report(before: &Body, after: &Body) {
+    let delta = |a: &Vector, b: &Vector| ((b.x - a.x).powi(2) + (b.y - a.y).powi(2) + (b.z - a.z).powi(2)).sqrt();
+    eprintln!(
+        "{}: position moved {:.6e} m, velocity changed {:.6e} m/s",
+        after.name,
+        delta(&before.position, &after.position),
+        delta(&before.velocity, &after.velocity)
+    );
+}
+
+/// Parses "x,y,z", each coordinate being an expression.
+fn parse_position(text: &str) -> Result<Vector, String> {
+    let coordinates = text.split(',').map(parse_expression).collect::<Result<Vec<_>, _>>()?;
+    match coordinates[..] {
+        [x, y, z] => Ok(Vector { x, y, z }),
+        _ => Err(format!("expected three coordinates \"x,y,z\", got '{}'", text)),
+    }
+}
@@ -0,0 +1,89 @@
+use clap::Args;
+use cpu_time::ProcessTime;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// Threads of the parallel tree, SPH and gravity sums, for every command.
+#[derive(Args, Debug, Clone)]
+pub struct ThreadArgs {
+    /// Threads of the parallel computations (default: RAYON_NUM_THREADS, or one per
+    /// logical core)
+    #[arg(long, global = true, value_parser = clap::value_parser!(u64).range(1..))]
+    pub threads: Option<u64>,
+
+    /// Pin each thread to its own core (round robin when there are more threads than
+    /// cores), so it keeps its caches and the memory it first touches stays on its
+    /// NUMA node
+    #[arg(long, global = true)]
+    pub pin_threads: bool,
+}
+
+/// Sets up the global thread pool; must run before any parallel computation.
+pub fn configure(args: &ThreadArgs) -> Result<(), Box<dyn Error>> {
+    if args.threads.is_none() && !args.pin_threads {
+        return Ok(());
+    }
+    let mut pool = rayon::ThreadPoolBuilder::new();
+    if let Some(threads) = args.threads {
+        pool = pool.num_threads(threads as usize);
+    }
+    if args.pin_threads {
+        let cores = core_affinity::get_core_ids().filter(|cores| !cores.is_empty()).ok_or("cannot list the cores to pin threads to")?;
+        pool = pool.start_handler(move |index| {
+            if !core_affinity::set_for_current(cores[index % cores.len()]) {
+                eprintln!("cannot pin thread {} to a core", index);
+            }
+        });
+    }
+    pool.build_global()?;
+    Ok(())
+}
+
+/// Wall-clock and CPU time of the process since a start, for the parallel
+/// efficiency of a run.
+pub struct Usage {
+    wall: Instant,
+    cpu: Option<ProcessTime>,
+}
+
+impl Usage {
+    pub fn start() -> Self {
+        Usage {
+            wall: Instant::now(),
+            cpu: ProcessTime::try_now().ok(),
+        }
+    }
+
+    /// Prints on stderr the share of the time the threads were busy: the CPU time
+    /// of the process over the wall-clock time of all the threads of the pool.
+    pub fn report(&self) {
+        let Some(cpu) = self.cpu.and_then(|cpu| cpu.try_elapsed().ok()) else {
+            return;
+        };
+        let wall = self.wall.elapsed();
+        let threads = rayon::current_num_threads();
+        eprintln!(
+            "parallel efficiency: {:.0}% (CPU {:.2} s over {:.2} s on {} threads)",
+            100.0 * efficiency(cpu, wall, threads),
+            cpu.as_secs_f64(),
+            wall.as_secs_f64(),
+            threads
+        );
+    }
+}
+
+fn efficiency(cpu: Duration, wall: Duration, threads: usize) -> f64 {
+    cpu.as_secs_f64() / (wall.as_secs_f64() * threads as f64).max(f64::MIN_POSITIVE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_efficiency() {
+        let second = Duration::from_secs(1);
+        assert_eq!(efficiency(6 * second, 2 * second, 4), 0.75);
+        assert_eq!(efficiency(Duration::ZERO, Duration::ZERO, 4), 0.0);
+    }
+}
@@ -0,0 +1,20 @@
+use clap::Args;
+use newtonian_solar_system::distributed;
+use std::error::Error;
+use std::net::TcpListener;
+
+#[derive(Args, Debug)]
+pub struct WorkerArgs {
+    /// Address to listen on for runs (port 0 picks a free one); workers don't
+    /// authenticate runs, so only listen on trusted networks
+    #[arg(long, default_value = "127.0.0.1:7878")]
+    pub listen: String,
+}
+
+/// Sums the gravity on shares of the bodies for runs given `--worker`, one run
+/// after another.
+pub fn run(args: &WorkerArgs) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(&args.listen).map_err(|e| format!("cannot listen on {}: {}", args.listen, e))?;
+    println!("listening on {}", listener.local_addr()?);
+    distributed::serve(listener, |peer, e| eprintln!("run from {} failed: {}", peer, e))
+}
@@ -0,0 +1,198 @@
+use super::body::Vector;
+use super::Body;
+
+/// Fractions of the bound mass enclosed by the Lagrangian radii.
+pub const LAGRANGIAN_FRACTIONS: [f64; 3] = [0.1, 0.5, 0.9];
+
+/// Local densities are estimated within the distance of this neighbor
+/// (Casertano & Hut 1985).
+const DENSITY_NEIGHBOR: usize = 6;
+
+/// Most passes removing unbound stars and recomputing the energies of the rest.
+const MAX_ITERATIONS: usize = 100;
+
+/// Standard statistics of a star cluster at one time, for the usual
+/// cluster-dynamics plots.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statistics {
+    /// Whether each star is bound, in the order given.
+    pub bound: Vec<bool>,
+    /// Share of the total mass in bound stars.
+    pub bound_fraction: f64,
+    /// Density center of the bound stars.
+    pub center: Vector,
+    /// Distances from the density center enclosing each of
+    /// [`LAGRANGIAN_FRACTIONS`] of the bound mass.
+    pub lagrangian_radii: [f64; 3],
+    /// Density-weighted distance of the bound stars from the density center
+    /// (Casertano & Hut 1985).
+    pub core_radius: f64,
+    /// Mass-weighted root mean square of the bound stars' velocities about
+    /// their mean, in three dimensions (√3 times the one-dimensional one).
+    pub velocity_dispersion: f64,
+}
+
+impl Statistics {
+    /// Statistics of `stars`, a cluster in isolation or not.
+    ///
+    /// A star is bound when its kinetic energy about the mean velocity of the
+    /// bound stars falls short of their potential on it; stars are removed
+    /// until that settles, so escapers don't count. The density center weights
+    /// the stars by their local density, which keeps it on the core when the
+    /// cluster is lopsided.
+    pub fn new(stars: &[Body], gravity: f64) -> Self {
+        let mut bound = vec![true; stars.len()];
+        for _ in 0..MAX_ITERATIONS {
+            let (mass, velocity) = mean_velocity(stars, &bound);
+            if mass <= 0.0 {
+                break;
+            }
+            let members: Vec<bool> = (0..stars.len())
+                .map(|i| {
+                    let potential: f64 = (0..stars.len())
+                        .filter(|&j| j != i && bound[j])
+                        .map(|j| -gravity * stars[j].mass / (stars[j].position - stars[i].position).norm())
+                        .sum();
+                    0.5 * (stars[i].velocity - velocity).norm_squared() + potential < 0.0
+                })
+                .collect();
+            if members == bound {
+                break;
+            }
+            bound = members;
+        }
+
+        let total: f64 = stars.iter().map(|star| star.mass).sum();
+        let (mass, velocity) = mean_velocity(stars, &bound);
+        let members: Vec<&Body> = stars.iter().zip(&bound).filter(|(_, bound)| **bound).map(|(star, _)| star).collect();
+        let mut densities = densities(&members);
+        if densities.iter().all(|&rho| rho <= 0.0) {
+            // Too few stars for densities: masses weigh them instead.
+            densities = members.iter().map(|star| star.mass).collect();
+        }
+        let weight: f64 = densities.iter().sum();
+        let center = members.iter().zip(&densities).fold(Vector::null(), |c, (star, rho)| c + star.position * *rho)
+            / weight.max(f64::MIN_POSITIVE);
+        let squares: f64 = densities.iter().map(|rho| rho * rho).sum();
+        let spread: f64 =
+            members.iter().zip(&densities).map(|(star, rho)| rho * rho * (star.position - center).norm_squared()).sum();
+        let core_radius = if squares > 0.0 { (spread / squares).sqrt() } else { 0.0 };
+
+        let mut shells: Vec<(f64, f64)> =
+            members.iter().map(|star| ((star.position - center).norm(), star.mass)).collect();
+        shells.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let lagrangian_radii = LAGRANGIAN_FRACTIONS.map(|fraction| {
+            let mut enclosed = 0.0;
+            shells
+                .iter()
+                .find(|(_, m)| {
+                    enclosed += m;
+                    enclosed >= fraction * mass
+                })
+                .map_or(0.0, |(radius, _)| *radius)
+        });
+        let kinetic: f64 = members.iter().map(|star| star.mass * (star.velocity - velocity).norm_squared()).sum();
+
+        Statistics {
+            bound,
+            bound_fraction: if total > 0.0 { mass / total } else { 0.0 },
+            center,
+            lagrangian_radii,
+            core_radius,
+            velocity_dispersion: if mass > 0.0 { (kinetic / mass).sqrt() } else { 0.0 },
+        }
+    }
+
+    pub fn bound_count(&self) -> usize {
+        self.bound.iter().filter(|&&bound| bound).count()
+    }
+}
+
+/// Mass and mean velocity of the selected stars.
+fn mean_velocity(stars: &[Body], selected: &[bool]) -> (f64, Vector) {
+    let (mut mass, mut momentum) = (0.0, Vector::null());
+    for (star, _) in stars.iter().zip(selected).filter(|(_, selected)| **selected) {
+        mass += star.mass;
+        momentum += star.velocity * star.mass;
+    }
+    (mass, if mass > 0.0 { momentum / mass } else { momentum })
+}
+
+/// Density about each star, from the mass of its nearest neighbors within
+/// the distance of the `DENSITY_NEIGHBOR`-th (or farthest, in small clusters).
+fn densities(stars: &[&Body]) -> Vec<f64> {
+    let neighbor = DENSITY_NEIGHBOR.min(stars.len().saturating_sub(1));
+    if neighbor < 2 {
+        return vec![0.0; stars.len()];
+    }
+    (0..stars.len())
+        .map(|i| {
+            let mut neighbors: Vec<(f64, f64)> = (0..stars.len())
+                .filter(|&j| j != i)
+                .map(|j| ((stars[j].position - stars[i].position).norm(), stars[j].mass))
+                .collect();
+            neighbors.select_nth_unstable_by(neighbor - 1, |a, b| a.0.total_cmp(&b.0));
+            let inner: f64 = neighbors[..neighbor - 1].iter().map(|(_, mass)| mass).sum();
+            let radius = neighbors[neighbor - 1].0;
+            if radius > 0.0 { inner / radius.powi(3) } else { 0.0 }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Tags;
+    use crate::generate::TidalStream;
+
+    const G: f64 = 6.67430e-11;
+
+    #[test]
+    fn test_plummer_sphere_statistics() {
+        let setup = TidalStream {
+            stars: 2000,
+            ..TidalStream::default()
+        };
+        let stars = setup.bodies(G).unwrap();
+
+        let statistics = Statistics::new(&stars, G);
+
+        let a = setup.scale_radius;
+        // r = a / √(f^(-2/3) - 1) encloses a share f of a Plummer sphere.
+        for (radius, fraction) in statistics.lagrangian_radii.iter().zip(LAGRANGIAN_FRACTIONS) {
+            let expected = a / (fraction.powf(-2.0 / 3.0) - 1.0).sqrt();
+            assert!((radius / expected - 1.0).abs() < 0.1, "{} != {}", radius, expected);
+        }
+        assert!(statistics.bound_fraction > 0.98, "{}", statistics.bound_fraction);
+        assert!(statistics.core_radius > 0.2 * a && statistics.core_radius < a, "{}", statistics.core_radius / a);
+        assert!((statistics.center - Vector::new(setup.distance, 0.0, 0.0)).norm() < 0.2 * a);
+        // ⟨v²⟩ = 3π G M / (32 a) for a Plummer sphere.
+        let expected = (3.0 * std::f64::consts::PI * G * setup.cluster_mass / (32.0 * a)).sqrt();
+        assert!((statistics.velocity_dispersion / expected - 1.0).abs() < 0.1, "{}", statistics.velocity_dispersion);
+    }
+
+    #[test]
+    fn test_escapers_are_not_bound() {
+        let star = |x: f64, vx: f64| Body {
+            name: format!("Star at {}", x),
+            mass: 1.0,
+            position: Vector::new(x, (x * 7.0).sin(), (x * 3.0).cos()),
+            velocity: Vector::new(vx, 0.0, 0.0),
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        };
+        // Ten slow stars close together and two leaving much faster than escape speed.
+        let mut stars: Vec<Body> = (0..10).map(|i| star(i as f64 * 0.3, 0.0)).collect();
+        stars.push(star(20.0, 10.0));
+        stars.push(star(-20.0, -10.0));
+
+        let statistics = Statistics::new(&stars, 1.0);
+
+        assert_eq!(statistics.bound_count(), 10);
+        assert!(!statistics.bound[10] && !statistics.bound[11]);
+        assert!((statistics.bound_fraction - 10.0 / 12.0).abs() < 1e-12);
+        assert_eq!(statistics.velocity_dispersion, 0.0);
+        assert!(statistics.lagrangian_radii[2] < 3.0);
+    }
+}
@@ -0,0 +1,154 @@
+use super::dynamics::{simulate_with, SequentialWriter, Settings};
+use super::memory;
+use super::reader::Frame;
+use super::Body;
+use std::error::Error;
+
+/// Bytes of frames kept in memory when `Settings::max_memory` doesn't say otherwise.
+pub const DEFAULT_LIMIT: usize = 1 << 30;
+
+/// Recorded frames of a run kept in memory, in time order.
+#[derive(Debug, Clone, Default)]
+pub struct SimulationResult {
+    pub frames: Vec<Frame>,
+}
+
+impl SimulationResult {
+    pub fn times(&self) -> Vec<f64> {
+        self.frames.iter().map(|frame| frame.time).collect()
+    }
+
+    /// States of the body named `name` at every frame it was recorded in.
+    pub fn trajectory(&self, name: &str) -> Vec<(f64, &Body)> {
+        self.frames
+            .iter()
+            .filter_map(|frame| {
+                let body = frame.bodies.iter().find(|body| body.name == name)?;
+                Some((frame.time, body))
+            })
+            .collect()
+    }
+}
+
+/// Keeps every frame it is given in memory, failing once they would take more
+/// than `limit` bytes.
+pub struct Collector {
+    frames: Vec<Frame>,
+    bytes: usize,
+    limit: usize,
+}
+
+impl Collector {
+    pub fn new(limit: usize) -> Self {
+        Collector {
+            frames: Vec::new(),
+            bytes: 0,
+            limit,
+        }
+    }
+
+    pub fn into_result(self) -> SimulationResult {
+        SimulationResult { frames: self.frames }
+    }
+}
+
+impl SequentialWriter for Collector {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        self.bytes += memory::bodies_bytes(bodies);
+        if self.bytes > self.limit {
+            return Err(format!(
+                "the recorded frames take more than {}; record fewer frames or write them to a file",
+                memory::format_size(self.limit)
+            )
+            .into());
+        }
+        self.frames.push(Frame {
+            time,
+            bodies: bodies.to_vec(),
+        });
+        Ok(())
+    }
+}
+
+/// Runs the simulation described by `settings` and returns the recorded frames
+/// instead of writing them, e.g., for tests and notebooks.
+///
+/// The frames share `settings.max_memory` with the simulation state, or may
+/// take up to `DEFAULT_LIMIT` without one. Runs whose frames can't fit fail
+/// before the first step.
+pub fn simulate_collect(bodies: &mut Vec<Body>, settings: &Settings) -> Result<SimulationResult, Box<dyn Error>> {
+    let frames = settings.recording.max_frames(settings.total_time);
+    let state = memory::simulation_bytes(bodies, settings.integrator, frames);
+    let limit = match settings.max_memory {
+        Some(max_memory) => max_memory.saturating_sub(state),
+        None => DEFAULT_LIMIT,
+    };
+    let required = frames.saturating_mul(memory::bodies_bytes(bodies));
+    if required > limit {
+        return Err(format!(
+            "{} frames need about {} but only {} is available to keep them in memory",
+            frames,
+            memory::format_size(required),
+            memory::format_size(limit)
+        )
+        .into());
+    }
+    let mut collector = Collector::new(limit);
+    simulate_with(bodies, settings, &mut collector)?;
+    Ok(collector.into_result())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::{Tags, Vector};
+    use crate::dynamics::Recording;
+
+    fn bodies() -> Vec<Body> {
+        let body = |name: &str, mass: f64, x: f64, vy: f64| Body {
+            name: name.to_string(),
+            mass,
+            position: Vector { x, y: 0.0, z: 0.0 },
+            velocity: Vector { x: 0.0, y: vy, z: 0.0 },
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        };
+        vec![body("Earth", 5.972e24, 0.0, 0.0), body("Moon", 7.342e22, 3.844e8, 1022.0)]
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            total_time: 100.0,
+            dt: 1.0,
+            recording: Recording::Count(5),
+            progress: false,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn test_collects_every_recorded_frame() {
+        let mut bodies = bodies();
+        let result = simulate_collect(&mut bodies, &settings()).unwrap();
+
+        assert_eq!(result.times(), vec![0.0, 25.0, 50.0, 75.0, 100.0]);
+        let moon = result.trajectory("Moon");
+        assert_eq!(moon.len(), 5);
+        assert_eq!(moon[0].1.position.x, 3.844e8);
+        assert_eq!(moon[4].1.position.y, bodies[1].position.y);
+    }
+
+    #[test]
+    fn test_frames_that_cant_fit_fail_before_running() {
+        let mut bodies = bodies();
+        let settings = Settings {
+            max_memory: Some(memory::simulation_bytes(&bodies, settings().integrator, 5) + 100),
+            ..settings()
+        };
+        let error = simulate_collect(&mut bodies, &settings).unwrap_err();
+
+        assert!(error.to_string().contains("5 frames"), "{}", error);
+        assert_eq!(bodies[1].position.x, 3.844e8);
+    }
+}
@@ -0,0 +1,517 @@
+use super::body::{energy, Tags, Vector};
+use super::Body;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+/// A console where a running simulation answers line commands between steps,
+/// over TCP (e.g., with `nc localhost 7879`) or from another thread of the
+/// program with [`Console::send`].
+///
+/// Commands are read by a thread per connection and answered by the run after
+/// its current step, so queries see a consistent state. While paused, the run
+/// waits for commands instead of stepping. Changes to the bodies are applied
+/// at once and logged as [`Intervention`]s.
+#[derive(Debug)]
+pub struct Console {
+    address: Option<SocketAddr>,
+    sender: Sender<Request>,
+    state: Mutex<State>,
+    /// Kept apart from the state, which the run holds while paused.
+    log: Mutex<Vec<Intervention>>,
+    /// Set once the run is over, so later commands aren't left waiting.
+    finished: AtomicBool,
+}
+
+#[derive(Debug)]
+struct State {
+    requests: Receiver<Request>,
+    paused: bool,
+}
+
+/// A command line, with the way back to its connection.
+#[derive(Debug)]
+struct Request {
+    line: String,
+    reply: Sender<String>,
+}
+
+/// What the console can see and change of a run, between two steps.
+pub(crate) struct Control<'a> {
+    pub bodies: &'a mut Vec<Body>,
+    /// Original index of each body in its current place, which the bodies
+    /// added since don't have yet.
+    pub order: &'a [usize],
+    pub time: f64,
+    pub steps: usize,
+    pub dt: f64,
+    pub gravity: f64,
+}
+
+/// What the commands answered between two steps asked of the run.
+pub(crate) struct Served {
+    /// The new time step, if one was set.
+    pub dt: Option<f64>,
+    /// Whether the bodies were changed.
+    pub changed: bool,
+}
+
+/// A change to the bodies of a run between two steps.
+#[derive(Debug, Clone)]
+pub enum Change {
+    /// Adds a body, which needs a name of its own.
+    Add(Body),
+    /// Changes the velocity of the named body by `delta_v`, in m/s.
+    Impulse { body: String, delta_v: Vector },
+}
+
+/// A change made to a run, as logged in its manifest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Intervention {
+    /// Simulated seconds and steps before the change.
+    pub time: f64,
+    pub steps: usize,
+    /// The change, as the console command making it.
+    pub change: String,
+}
+
+const HELP: &str = "commands: time, bodies, pos NAME, vel NAME, energy, set dt SECONDS, \
+                    impulse NAME DVX DVY DVZ, add NAME MASS X Y Z VX VY VZ, pause, resume, quit";
+
+/// How often a command sent from the program checks whether the run is over.
+const POLL: Duration = Duration::from_millis(100);
+
+impl Change {
+    /// Applies the change to `bodies`, failing when it doesn't fit them.
+    pub fn apply(&self, bodies: &mut Vec<Body>) -> Result<(), Box<dyn Error>> {
+        match self {
+            Change::Add(body) => {
+                if bodies.iter().any(|other| other.name == body.name) {
+                    return Err(format!("there is already a body named '{}'", body.name).into());
+                }
+                if !(body.mass >= 0.0 && body.mass.is_finite()) {
+                    return Err(format!("the mass of '{}' must not be negative, got {}", body.name, body.mass).into());
+                }
+                if !body.position.to_array().iter().chain(&body.velocity.to_array()).all(|x| x.is_finite()) {
+                    return Err(format!("the state of '{}' must be finite", body.name).into());
+                }
+                bodies.push(body.clone());
+            }
+            Change::Impulse { body, delta_v } => {
+                if !delta_v.to_array().iter().all(|x| x.is_finite()) {
+                    return Err("the impulse must be finite".into());
+                }
+                let target = bodies
+                    .iter_mut()
+                    .find(|other| other.name == *body)
+                    .ok_or_else(|| format!("no body named '{}'", body))?;
+                target.velocity += *delta_v;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Change {
+    /// The console command making the change.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Add(body) => write!(
+                f,
+                "add {} {} {} {} {} {} {} {}",
+                body.name,
+                body.mass,
+                body.position.x,
+                body.position.y,
+                body.position.z,
+                body.velocity.x,
+                body.velocity.y,
+                body.velocity.z
+            ),
+            Change::Impulse { body, delta_v } => {
+                write!(f, "impulse {} {} {} {}", body, delta_v.x, delta_v.y, delta_v.z)
+            }
+        }
+    }
+}
+
+impl Console {
+    /// A console taking commands only from the program, with [`Console::send`].
+    /// A `paused` console holds the run before its first step until `resume`.
+    pub fn local(paused: bool) -> Self {
+        let (sender, requests) = mpsc::channel();
+        Console {
+            address: None,
+            sender,
+            state: Mutex::new(State { requests, paused }),
+            log: Mutex::new(Vec::new()),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// Listens for connections at `address` (port 0 picks a free one), on top
+    /// of taking commands from the program.
+    pub fn listen(address: &str, paused: bool) -> Result<Self, Box<dyn Error>> {
+        let listener = TcpListener::bind(address).map_err(|e| format!("cannot listen on {}: {}", address, e))?;
+        let mut console = Console::local(paused);
+        console.address = Some(listener.local_addr()?);
+        let sender = console.sender.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                thread::spawn(move || session(stream, sender));
+            }
+        });
+        Ok(console)
+    }
+
+    /// The address the console listens on, unless it is local.
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.address
+    }
+
+    /// Passes a command line to the run and waits for its reply, after the
+    /// current step; call it from another thread than the run's.
+    pub fn send(&self, line: &str) -> String {
+        let over = || "the run is over".to_string();
+        let (reply, replies) = mpsc::channel();
+        let request = Request {
+            line: line.to_string(),
+            reply,
+        };
+        if self.finished.load(Ordering::Acquire) || self.sender.send(request).is_err() {
+            return over();
+        }
+        loop {
+            match replies.recv_timeout(POLL) {
+                Ok(text) => return text,
+                Err(RecvTimeoutError::Timeout) if !self.finished.load(Ordering::Acquire) => continue,
+                Err(_) => return over(),
+            }
+        }
+    }
+
+    /// The changes made to the bodies so far, oldest first.
+    pub fn interventions(&self) -> Vec<Intervention> {
+        self.log.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// Answers the pending commands, and waits for more while paused.
+    pub(crate) fn serve(&self, control: &mut Control) -> Served {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let State { requests, paused } = &mut *state;
+        let (mut dt, mut changed) = (None, false);
+        loop {
+            let request = if *paused {
+                requests.recv().map_err(|_| TryRecvError::Disconnected)
+            } else {
+                requests.try_recv()
+            };
+            let Ok(request) = request else {
+                return Served { dt, changed };
+            };
+            let mut log = self.log.lock().unwrap_or_else(PoisonError::into_inner);
+            let logged = log.len();
+            let reply = answer(&request.line, control, paused, &mut dt, &mut log);
+            changed |= log.len() > logged;
+            drop(log);
+            // The connection may be gone already.
+            let _ = request.reply.send(reply);
+        }
+    }
+
+    /// Marks the run as over, so pending and later commands get told so.
+    pub(crate) fn finish(&self) {
+        self.finished.store(true, Ordering::Release);
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        while let Ok(request) = state.requests.try_recv() {
+            let _ = request.reply.send("the run is over".to_string());
+        }
+    }
+}
+
+/// The reply to a command.
+fn answer(
+    line: &str,
+    control: &mut Control,
+    paused: &mut bool,
+    dt: &mut Option<f64>,
+    log: &mut Vec<Intervention>,
+) -> String {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let bodies: &[Body] = control.bodies;
+    let body = |name: &[&str]| {
+        let name = name.join(" ");
+        bodies.iter().find(|body| body.name == name).ok_or(format!("no body named '{}'", name))
+    };
+    let change = match words.as_slice() {
+        ["impulse", rest @ ..] if rest.len() > 3 => {
+            let (name, numbers) = rest.split_at(rest.len() - 3);
+            numbers_of(numbers).map(|[x, y, z]| Change::Impulse {
+                body: name.join(" "),
+                delta_v: Vector::new(x, y, z),
+            })
+        }
+        ["add", rest @ ..] if rest.len() > 7 => {
+            let (name, numbers) = rest.split_at(rest.len() - 7);
+            numbers_of(numbers).map(|[mass, x, y, z, vx, vy, vz]| {
+                Change::Add(Body {
+                    name: name.join(" "),
+                    mass,
+                    position: Vector::new(x, y, z),
+                    velocity: Vector::new(vx, vy, vz),
+                    acceleration: Vector::null(),
+                    tags: Tags::new(),
+                    temperature: None,
+                })
+            })
+        }
+        _ => Err(String::new()),
+    };
+    let reply = match (change, words.as_slice()) {
+        (Ok(change), _) => match change.apply(control.bodies) {
+            Ok(()) => {
+                log.push(Intervention {
+                    time: control.time,
+                    steps: control.steps,
+                    change: change.to_string(),
+                });
+                Ok(format!("{} at t = {} s", change, control.time))
+            }
+            Err(e) => Err(e.to_string()),
+        },
+        (Err(e), _) if !e.is_empty() => Err(e),
+        (_, ["help"]) => Ok(HELP.to_string()),
+        (_, ["time"]) => Ok(format!(
+            "t = {} s after {} steps of {} s{}",
+            control.time,
+            control.steps,
+            dt.unwrap_or(control.dt),
+            if *paused { ", paused" } else { "" }
+        )),
+        (_, ["bodies"]) => {
+            let mut names = vec![""; bodies.len()];
+            for (i, body) in bodies.iter().enumerate() {
+                names[control.order.get(i).copied().unwrap_or(i)] = &body.name;
+            }
+            Ok(names.join("\n"))
+        }
+        (_, ["pos", name @ ..]) if !name.is_empty() => {
+            body(name).map(|b| format!("{} {} {}", b.position.x, b.position.y, b.position.z))
+        }
+        (_, ["vel", name @ ..]) if !name.is_empty() => {
+            body(name).map(|b| format!("{} {} {}", b.velocity.x, b.velocity.y, b.velocity.z))
+        }
+        (_, ["energy"]) => {
+            let (kinetic, potential) = energy(bodies, control.gravity);
+            Ok(format!("{} J (kinetic {} J, potential {} J)", kinetic + potential, kinetic, potential))
+        }
+        (_, ["set", "dt", seconds]) => match seconds.parse::<f64>() {
+            Ok(seconds) if seconds.is_finite() && seconds > 0.0 => {
+                *dt = Some(seconds);
+                Ok(format!("dt = {} s from the next step", seconds))
+            }
+            _ => Err(format!("time step must be a positive number of seconds, got '{}'", seconds)),
+        },
+        (_, ["pause"]) => {
+            *paused = true;
+            Ok(format!("paused at t = {} s", control.time))
+        }
+        (_, ["resume"]) => {
+            *paused = false;
+            Ok(format!("resumed at t = {} s", control.time))
+        }
+        _ => Err(format!("unknown command '{}'; {}", line.trim(), HELP)),
+    };
+    reply.unwrap_or_else(|e| format!("error: {}", e))
+}
+
+/// The numbers of a command, all of which must parse.
+fn numbers_of<const N: usize>(words: &[&str]) -> Result<[f64; N], String> {
+    let mut numbers = [0.0; N];
+    for (number, word) in numbers.iter_mut().zip(words) {
+        *number = word.parse().map_err(|_| format!("'{}' is not a number", word))?;
+    }
+    Ok(numbers)
+}
+
+/// Passes the commands of a connection to the run, one line at a time, and
+/// writes back the replies.
+fn session(stream: TcpStream, sender: Sender<Request>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        match line.trim() {
+            "" => continue,
+            "quit" => break,
+            _ => {}
+        }
+        let (reply, replies) = mpsc::channel();
+        let mut text = match sender.send(Request { line, reply }) {
+            Ok(()) => replies.recv().unwrap_or_else(|_| "the run is over".to_string()),
+            Err(_) => "the run is over".to_string(),
+        };
+        text.push('\n');
+        writer.write_all(text.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Vector;
+    use crate::dynamics::{simulate_with, Recording, SequentialWriter, Settings};
+    use std::sync::Arc;
+
+    /// Counts the recorded frames.
+    struct Frames(usize);
+
+    impl SequentialWriter for Frames {
+        fn add(&mut self, _time: f64, _bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+            self.0 += 1;
+            Ok(())
+        }
+    }
+
+    /// The number of bodies of each recorded frame.
+    struct Sizes(Vec<usize>);
+
+    impl SequentialWriter for Sizes {
+        fn add(&mut self, _time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+            self.0.push(bodies.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_answers() {
+        let mut bodies = vec![
+            Body::new("Sun", 2.0, Vector::null(), Vector::null()),
+            Body::new("Far Planet", 1.0, Vector::new(4.0, 0.0, 0.0), Vector::new(0.0, 3.0, 0.0)),
+        ];
+        let mut control = Control {
+            bodies: &mut bodies,
+            order: &[0, 1],
+            time: 10.0,
+            steps: 5,
+            dt: 2.0,
+            gravity: 1.0,
+        };
+        let (mut paused, mut dt, mut log) = (false, None, Vec::new());
+        let mut ask = |line: &str| answer(line, &mut control, &mut paused, &mut dt, &mut log);
+        assert_eq!(ask("pos Far Planet"), "4 0 0");
+        assert_eq!(ask("vel Far Planet"), "0 3 0");
+        assert_eq!(ask("energy"), "4 J (kinetic 4.5 J, potential -0.5 J)");
+        assert_eq!(ask("pos Moon"), "error: no body named 'Moon'");
+        assert!(ask("set dt -1").starts_with("error"));
+        assert_eq!(ask("set dt 0.5"), "dt = 0.5 s from the next step");
+        assert_eq!(ask("pause"), "paused at t = 10 s");
+        assert_eq!(ask("time"), "t = 10 s after 5 steps of 0.5 s, paused");
+        assert!(ask("jump").starts_with("error: unknown command 'jump'"));
+        assert_eq!((paused, dt), (true, Some(0.5)));
+    }
+
+    #[test]
+    fn test_changes_are_applied_and_logged() {
+        let mut bodies = vec![
+            Body::new("Sun", 2.0, Vector::null(), Vector::null()),
+            Body::new("Far Planet", 1.0, Vector::new(4.0, 0.0, 0.0), Vector::new(0.0, 3.0, 0.0)),
+        ];
+        // The bodies were reordered, so the planet came first.
+        bodies.swap(0, 1);
+        let mut control = Control {
+            bodies: &mut bodies,
+            order: &[1, 0],
+            time: 10.0,
+            steps: 5,
+            dt: 2.0,
+            gravity: 1.0,
+        };
+        let (mut paused, mut dt, mut log) = (false, None, Vec::new());
+        let mut ask = |line: &str| answer(line, &mut control, &mut paused, &mut dt, &mut log);
+        assert_eq!(ask("impulse Far Planet 0.5 -1 0"), "impulse Far Planet 0.5 -1 0 at t = 10 s");
+        assert_eq!(ask("vel Far Planet"), "0.5 2 0");
+        assert_eq!(ask("add Comet 0.1 0 9 0 -2 0 0"), "add Comet 0.1 0 9 0 -2 0 0 at t = 10 s");
+        assert_eq!(ask("pos Comet"), "0 9 0");
+        assert_eq!(ask("bodies"), "Sun\nFar Planet\nComet");
+        assert_eq!(ask("add Comet 1 0 0 0 0 0 0"), "error: there is already a body named 'Comet'");
+        assert_eq!(ask("add Rock -1 0 0 0 0 0 0"), "error: the mass of 'Rock' must not be negative, got -1");
+        assert_eq!(ask("impulse Moon 1 0 0"), "error: no body named 'Moon'");
+        assert_eq!(ask("impulse Sun 1 x 0"), "error: 'x' is not a number");
+        assert!(ask("impulse Sun 1").starts_with("error: unknown command"));
+
+        let changes: Vec<_> = log.iter().map(|i| (i.time, i.steps, i.change.as_str())).collect();
+        assert_eq!(changes, [(10.0, 5, "impulse Far Planet 0.5 -1 0"), (10.0, 5, "add Comet 0.1 0 9 0 -2 0 0")]);
+        assert_eq!(bodies.len(), 3);
+    }
+
+    #[test]
+    fn test_paused_run_takes_commands_over_tcp() {
+        let console = Arc::new(Console::listen("127.0.0.1:0", true).unwrap());
+        let mut stream = TcpStream::connect(console.address().unwrap()).unwrap();
+        stream.write_all(b"time\n\nset dt 10\nresume\nquit\n").unwrap();
+
+        let mut bodies = vec![Body::new("Probe", 1.0, Vector::null(), Vector::new(1.0, 0.0, 0.0))];
+        let settings = Settings {
+            total_time: 100.0,
+            dt: 1.0,
+            recording: Recording::Interval(1.0),
+            progress: false,
+            console: Some(console),
+            ..Settings::default()
+        };
+        let mut frames = Frames(0);
+        simulate_with(&mut bodies, &settings, &mut frames).unwrap();
+
+        let replies: Vec<String> = BufReader::new(stream).lines().map(Result::unwrap).collect();
+        assert_eq!(
+            replies,
+            ["t = 0 s after 0 steps of 1 s, paused", "dt = 10 s from the next step", "resumed at t = 0 s"]
+        );
+        // The initial state, then one frame per step of 10 s.
+        assert_eq!(frames.0, 11);
+        assert_eq!(bodies[0].position, Vector::new(100.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_program_steers_a_paused_run() {
+        let console = Arc::new(Console::local(true));
+        let steering = {
+            let console = Arc::clone(&console);
+            thread::spawn(move || {
+                ["add Rover 0 0 5 0 0 2 0", "impulse Probe 1 0 0", "resume"].map(|line| console.send(line))
+            })
+        };
+
+        let mut bodies = vec![Body::new("Probe", 1.0, Vector::null(), Vector::new(1.0, 0.0, 0.0))];
+        let settings = Settings {
+            gravity: 0.0,
+            total_time: 10.0,
+            dt: 1.0,
+            recording: Recording::Count(3),
+            progress: false,
+            console: Some(Arc::clone(&console)),
+            ..Settings::default()
+        };
+        let mut sizes = Sizes(Vec::new());
+        simulate_with(&mut bodies, &settings, &mut sizes).unwrap();
+
+        let replies = steering.join().unwrap();
+        assert_eq!(replies[2], "resumed at t = 0 s");
+        // The initial state was recorded before the rover came.
+        assert_eq!(sizes.0, [1, 2, 2]);
+        assert_eq!(bodies[0].position, Vector::new(20.0, 0.0, 0.0));
+        assert_eq!(bodies[1].position, Vector::new(0.0, 25.0, 0.0));
+        let changes: Vec<_> = console.interventions().into_iter().map(|i| i.change).collect();
+        assert_eq!(changes, ["add Rover 0 0 5 0 0 2 0", "impulse Probe 1 0 0"]);
+        assert_eq!(console.send("time"), "the run is over");
+    }
+}
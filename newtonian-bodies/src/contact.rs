@@ -0,0 +1,236 @@
+use super::Body;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Contacts between bodies of given radii, resolved by the rapier3d physics
+/// engine while this crate keeps the gravity.
+///
+/// After every step, bodies touching (or about to touch) are handed to a
+/// rapier world of their own, in a frame centered on them so its single
+/// precision is enough. Rapier's solver changes their velocities to stop the
+/// contact, bouncing with the restitution and sliding with the friction, and
+/// pushes overlapping bodies apart. Bodies are rigid spheres without spin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contacts {
+    /// Radius in meters of each solid body, by name; the others pass through.
+    pub radii: BTreeMap<String, f64>,
+    /// Share of the normal speed kept after a bounce, from 0 (none) to 1.
+    pub restitution: f64,
+    /// Coulomb friction coefficient of the surfaces.
+    pub friction: f64,
+}
+
+impl Contacts {
+    /// Solid bodies with the given radii, bouncing off each other halfway.
+    pub fn new(radii: BTreeMap<String, f64>) -> Self {
+        Contacts {
+            radii,
+            restitution: 0.5,
+            friction: 0.5,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        if cfg!(not(feature = "rapier3d")) {
+            return Err("contacts need the rapier3d feature (cargo build --features rapier3d)".into());
+        }
+        if let Some((name, radius)) = self.radii.iter().find(|(_, r)| !(r.is_finite() && **r > 0.0)) {
+            return Err(format!("radius of '{}' must be positive, got {}", name, radius).into());
+        }
+        if !(0.0..=1.0).contains(&self.restitution) {
+            return Err(format!("restitution must be between 0 and 1, got {}", self.restitution).into());
+        }
+        if !(self.friction.is_finite() && self.friction >= 0.0) {
+            return Err(format!("friction must not be negative, got {}", self.friction).into());
+        }
+        Ok(())
+    }
+
+    /// Groups of indices of solid bodies in contact at the end of a step of
+    /// `dt`, through chains of pairs closer than their radii plus the distance
+    /// they close in a step.
+    #[cfg_attr(not(feature = "rapier3d"), allow(dead_code))]
+    fn groups(&self, bodies: &[Body], dt: f64) -> Vec<Vec<usize>> {
+        let solid: Vec<(usize, f64)> = bodies
+            .iter()
+            .enumerate()
+            .filter_map(|(i, body)| Some((i, *self.radii.get(&body.name)?)))
+            .collect();
+        // Union-find over the solid bodies.
+        let mut parent: Vec<usize> = (0..solid.len()).collect();
+        fn root(parent: &mut [usize], mut k: usize) -> usize {
+            while parent[k] != k {
+                parent[k] = parent[parent[k]];
+                k = parent[k];
+            }
+            k
+        }
+        for (a, &(i, ri)) in solid.iter().enumerate() {
+            for (b, &(j, rj)) in solid.iter().enumerate().skip(a + 1) {
+                let d = bodies[j].position - bodies[i].position;
+                let closing = (bodies[j].velocity - bodies[i].velocity).norm() * dt;
+                if d.norm() < ri + rj + closing {
+                    let (ra, rb) = (root(&mut parent, a), root(&mut parent, b));
+                    parent[ra] = rb;
+                }
+            }
+        }
+        let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for (a, &(i, _)) in solid.iter().enumerate() {
+            groups.entry(root(&mut parent, a)).or_default().push(i);
+        }
+        groups.into_values().filter(|group| group.len() > 1).collect()
+    }
+}
+
+#[cfg(feature = "rapier3d")]
+mod solver {
+    use super::super::body::Vector;
+    use super::super::Body;
+    use super::Contacts;
+    use rapier3d::prelude::*;
+
+    impl Contacts {
+        /// Resolves the contacts of the bodies at the end of a step of `dt`.
+        pub(crate) fn resolve(&self, bodies: &mut [Body], dt: f64) {
+            for group in self.groups(bodies, dt) {
+                self.resolve_group(bodies, &group, dt);
+            }
+        }
+
+        fn resolve_group(&self, bodies: &mut [Body], group: &[usize], dt: f64) {
+            // Center of mass and its velocity, the origin of the rapier world.
+            let mass: f64 = group.iter().map(|&i| bodies[i].mass).sum();
+            let weighted = |value: fn(&Body) -> Vector| {
+                group.iter().fold(Vector::null(), |sum, &i| sum + value(&bodies[i]) * bodies[i].mass) / mass
+            };
+            let center = weighted(|b| b.position);
+            let drift = weighted(|b| b.velocity);
+            let local = |v: Vector| vector![v.x as f32, v.y as f32, v.z as f32];
+
+            let mut rigid_bodies = RigidBodySet::new();
+            let mut colliders = ColliderSet::new();
+            let handles: Vec<(RigidBodyHandle, ColliderHandle)> = group
+                .iter()
+                .map(|&i| {
+                    let body = &bodies[i];
+                    let rigid = RigidBodyBuilder::dynamic()
+                        .translation(local(body.position - center))
+                        .linvel(local(body.velocity - drift))
+                        .lock_rotations()
+                        .build();
+                    let handle = rigid_bodies.insert(rigid);
+                    let collider = ColliderBuilder::ball(self.radii[&body.name] as f32)
+                        .mass(body.mass as f32)
+                        .restitution(self.restitution as f32)
+                        .friction(self.friction as f32)
+                        .build();
+                    (handle, colliders.insert_with_parent(collider, handle, &mut rigid_bodies))
+                })
+                .collect();
+
+            let smallest = group.iter().map(|&i| self.radii[&bodies[i].name]).fold(f64::INFINITY, f64::min);
+            let parameters = IntegrationParameters {
+                dt: dt as f32,
+                length_unit: smallest as f32,
+                ..IntegrationParameters::default()
+            };
+            let mut narrow_phase = NarrowPhase::new();
+            PhysicsPipeline::new().step(
+                &vector![0.0, 0.0, 0.0],
+                &parameters,
+                &mut IslandManager::new(),
+                &mut DefaultBroadPhase::new(),
+                &mut narrow_phase,
+                &mut rigid_bodies,
+                &mut colliders,
+                &mut ImpulseJointSet::new(),
+                &mut MultibodyJointSet::new(),
+                &mut CCDSolver::new(),
+                None,
+                &(),
+                &(),
+            );
+
+            // Rapier drifted the bodies over the step again: keep only the
+            // changes of velocity and the push out of overlaps.
+            for (&i, &(handle, collider)) in group.iter().zip(&handles) {
+                let touching = narrow_phase.contact_pairs_with(collider).any(|pair| pair.has_any_active_contact);
+                if !touching {
+                    continue;
+                }
+                let rigid = &rigid_bodies[handle];
+                let before = local(bodies[i].position - center);
+                let velocity = *rigid.linvel();
+                let push = rigid.translation() - before - velocity * dt as f32;
+                let body = &mut bodies[i];
+                body.position += Vector::new(push.x as f64, push.y as f64, push.z as f64);
+                body.velocity = drift + Vector::new(velocity.x as f64, velocity.y as f64, velocity.z as f64);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Vector;
+
+    #[test]
+    fn test_groups_of_touching_bodies() {
+        let contacts = Contacts::new(BTreeMap::from([
+            ("A".to_string(), 1.0),
+            ("B".to_string(), 1.0),
+            ("C".to_string(), 1.0),
+            ("D".to_string(), 1.0),
+        ]));
+        let bodies = vec![
+            Body::new("A", 1.0, Vector::new(0.0, 0.0, 0.0), Vector::null()),
+            Body::new("B", 1.0, Vector::new(1.9, 0.0, 0.0), Vector::null()),
+            Body::new("C", 1.0, Vector::new(3.8, 0.0, 0.0), Vector::null()),
+            // Two meters off, but closing in by three in a step.
+            Body::new("D", 1.0, Vector::new(0.0, 4.0, 0.0), Vector::new(0.0, -3.0, 0.0)),
+            Body::new("Ghost", 1.0, Vector::new(0.5, 0.0, 0.0), Vector::null()),
+        ];
+        assert_eq!(contacts.groups(&bodies, 1.0), vec![vec![0, 1, 2, 3]]);
+        assert_eq!(contacts.groups(&bodies, 0.1), vec![vec![0, 1, 2]]);
+    }
+
+    #[cfg(not(feature = "rapier3d"))]
+    #[test]
+    fn test_contacts_need_rapier() {
+        assert!(Contacts::new(BTreeMap::new()).check().is_err());
+    }
+
+    #[cfg(feature = "rapier3d")]
+    #[test]
+    fn test_lander_comes_to_rest_on_an_asteroid() {
+        use crate::dynamics::{simulate_with, Recording, Settings};
+        use crate::simulation::Discard;
+
+        // A 1 t lander dropped from 20 m above a 500 m asteroid of 1e12 kg.
+        let radius = 500.0;
+        let mut bodies = vec![
+            Body::new("Asteroid", 1.0e12, Vector::null(), Vector::null()),
+            Body::new("Lander", 1000.0, Vector::new(0.0, 0.0, radius + 1.0 + 20.0), Vector::new(0.0, 0.0, -0.05)),
+        ];
+        let contacts = Contacts {
+            restitution: 0.0,
+            ..Contacts::new(BTreeMap::from([("Asteroid".to_string(), radius), ("Lander".to_string(), 1.0)]))
+        };
+        contacts.check().unwrap();
+        let settings = Settings {
+            total_time: 3600.0,
+            dt: 1.0,
+            recording: Recording::Count(2),
+            progress: false,
+            contacts: Some(contacts),
+            ..Settings::default()
+        };
+        simulate_with(&mut bodies, &settings, &mut Discard).unwrap();
+
+        let height = (bodies[1].position - bodies[0].position).norm() - radius - 1.0;
+        assert!(height.abs() < 0.05, "{}", height);
+        assert!((bodies[1].velocity - bodies[0].velocity).norm() < 1e-3);
+    }
+}
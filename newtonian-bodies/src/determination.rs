@@ -0,0 +1,273 @@
+use super::body::Vector;
+use super::dynamics::{Recording, Settings};
+use super::observation::{self, Corrections, Observation};
+use super::simulation::Simulation;
+use super::targeting::solve;
+use super::transition::{self, set_state, state};
+use super::Body;
+use std::error::Error;
+
+/// Stopping rules of the least-squares iterations.
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// The fit has converged once an iteration lowers the weighted RMS of the
+    /// residuals by less than this fraction of it.
+    pub tolerance: f64,
+    pub max_iterations: usize,
+    /// Effects of the speed of light the observations include.
+    pub corrections: Corrections,
+}
+
+/// Outcome of a successful fit.
+#[derive(Debug, Clone)]
+pub struct Fit {
+    /// Initial conditions with the fitted state of the body.
+    pub bodies: Vec<Body>,
+    /// Weighted RMS of the residuals before each iteration and after the last one.
+    pub rms: Vec<f64>,
+    /// Observed minus computed value of each observation of the body, in
+    /// their order.
+    pub residuals: Vec<f64>,
+    /// Covariance of the fitted initial (x, y, z, vx, vy, vz).
+    pub covariance: [[f64; 6]; 6],
+}
+
+/// An observation with the index of its observer.
+type Used<'a> = (usize, &'a Observation);
+
+/// Initial conditions with their residuals and the covariance of the
+/// linearized problem about them.
+type Iterate = (Vec<Body>, Vec<f64>, [[f64; 6]; 6]);
+
+/// Fits the initial position and velocity of body `body` to the observations
+/// of it by weighted least squares; observations of other targets are ignored.
+///
+/// Each Gauss-Newton iteration chains the sensitivity of every observation to
+/// the body's state at its time with the state transition matrix from the
+/// start (see [`transition::at_times`]), at the cost of 13 runs. The observers
+/// are taken as unaffected by the body.
+pub fn fit(
+    bodies: &[Body],
+    settings: &Settings,
+    body: usize,
+    observations: &[Observation],
+    options: Options,
+) -> Result<Fit, Box<dyn Error>> {
+    let target = bodies.get(body).ok_or_else(|| format!("no body at index {}", body))?;
+    let mut used: Vec<Used> = Vec::new();
+    for observation in observations.iter().filter(|observation| observation.target == target.name) {
+        let observer = bodies
+            .iter()
+            .position(|body| body.name == observation.observer)
+            .ok_or_else(|| format!("the scenario has no observer named '{}'", observation.observer))?;
+        if observer == body {
+            return Err(format!("{} can't observe itself", target.name).into());
+        }
+        if observation.time.is_nan() || observation.time < 0.0 {
+            return Err(format!("observations must be after the initial state, got {} s", observation.time).into());
+        }
+        used.push((observer, observation));
+    }
+    if used.len() < 6 {
+        return Err(format!("{} observations of {} can't fix its 6 state components", used.len(), target.name).into());
+    }
+    // The runs visit the observations by time.
+    let mut order: Vec<usize> = (0..used.len()).collect();
+    order.sort_by(|&a, &b| used[a].1.time.total_cmp(&used[b].1.time));
+    let times: Vec<f64> = order.iter().map(|&k| used[k].1.time).collect();
+    // The runs stop at the last observation and record nothing.
+    let settings = Settings {
+        total_time: times[times.len() - 1],
+        recording: Recording::Count(1),
+        progress: false,
+        ..settings.clone()
+    };
+
+    let mut bodies = bodies.to_vec();
+    let mut rms = Vec::new();
+    // The state before the last update, with what was computed for it.
+    let mut previous: Option<Iterate> = None;
+    loop {
+        let states = states_at(&bodies, &settings, body, &used, &order, options.corrections)?;
+        let residuals: Vec<f64> = used
+            .iter()
+            .zip(&states)
+            .map(|((_, o), (position, velocity, observer))| {
+                o.kind.difference(o.value, o.kind.measure_apparent(position, velocity, observer.as_ref()))
+            })
+            .collect();
+        let current = (used.iter().zip(&residuals).map(|((_, o), r)| (r / o.sigma).powi(2)).sum::<f64>()
+            / used.len() as f64)
+            .sqrt();
+        if !current.is_finite() {
+            return Err("the predicted observations are not finite (colliding bodies?)".into());
+        }
+        if let (Some(&last), Some((before, before_residuals, covariance))) = (rms.last(), &previous)
+            && current >= last * (1.0 - options.tolerance)
+        {
+            rms.push(current);
+            // The update is kept unless it made things worse.
+            let (bodies, residuals) = if current < last {
+                (bodies, residuals)
+            } else {
+                (before.clone(), before_residuals.clone())
+            };
+            return Ok(Fit {
+                bodies,
+                rms,
+                residuals,
+                covariance: *covariance,
+            });
+        }
+        rms.push(current);
+        if rms.len() > options.max_iterations {
+            return Err(format!(
+                "no convergence after {} iterations (weighted RMS {:.6e})",
+                options.max_iterations, current
+            )
+            .into());
+        }
+
+        // Normal equations of the weighted, linearized problem.
+        let matrices = transition::at_times(&bodies, &settings, body, &times)?;
+        let mut normal = vec![vec![0.0; 6]; 6];
+        let mut rhs = vec![0.0; 6];
+        for (&k, matrix) in order.iter().zip(&matrices) {
+            let observation = used[k].1;
+            let local = partials(observation, &states[k]);
+            let row: Vec<f64> = (0..6).map(|j| (0..6).map(|i| local[i] * matrix[i][j]).sum()).collect();
+            let weight = observation.sigma.powi(-2);
+            for a in 0..6 {
+                rhs[a] += weight * row[a] * residuals[k];
+                for b in 0..6 {
+                    normal[a][b] += weight * row[a] * row[b];
+                }
+            }
+        }
+        let singular = || format!("the observations don't fix the state of {}", bodies[body].name);
+        let columns = (0..6)
+            .map(|j| {
+                let unit = (0..6).map(|i| if i == j { 1.0 } else { 0.0 }).collect();
+                solve(normal.clone(), unit).ok_or_else(singular)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let covariance = std::array::from_fn(|i| std::array::from_fn(|j| columns[j][i]));
+        let update = solve(normal, rhs).ok_or_else(singular)?;
+
+        let mut initial = state(&bodies[body]);
+        for (value, delta) in initial.iter_mut().zip(update) {
+            *value += delta;
+        }
+        previous = Some((bodies.clone(), residuals, covariance));
+        set_state(&mut bodies[body], &initial);
+    }
+}
+
+/// Position and velocity of the target relative to the observer, and the
+/// velocity of the observer when it aberrates the angles.
+type Seen = (Vector, Vector, Option<Vector>);
+
+/// What the observer sees of the target at the time of each observation,
+/// visited in `order`.
+fn states_at(
+    bodies: &[Body],
+    settings: &Settings,
+    body: usize,
+    used: &[Used],
+    order: &[usize],
+    corrections: Corrections,
+) -> Result<Vec<Seen>, Box<dyn Error>> {
+    let mut simulation = Simulation::builder().bodies(bodies.to_vec()).settings(settings.clone()).build()?;
+    let mut states = vec![(Vector::null(), Vector::null(), None); used.len()];
+    for &k in order {
+        let (observer, observation) = used[k];
+        simulation.advance_to(observation.time)?;
+        let (observer, target) = (&simulation.bodies()[observer], &simulation.bodies()[body]);
+        let (position, velocity) = observation::relative_state(observer, target, corrections.light_time);
+        states[k] = (position, velocity, corrections.aberration.then_some(observer.velocity));
+    }
+    Ok(states)
+}
+
+/// Derivatives of the measured value with respect to the relative state of
+/// the target at the time of the observation, by central differences.
+fn partials(observation: &Observation, (position, velocity, observer): &Seen) -> [f64; 6] {
+    let kind = observation.kind;
+    let nominal = [position.x, position.y, position.z, velocity.x, velocity.y, velocity.z];
+    let mut partials = [0.0; 6];
+    for (j, partial) in partials.iter_mut().enumerate() {
+        let step = if j < 3 {
+            1e-7 * position.norm().max(1.0)
+        } else {
+            1e-7 * velocity.norm().max(1e-3)
+        };
+        let measure = |sign: f64| {
+            let mut shifted = nominal;
+            shifted[j] += sign * step;
+            let [x, y, z, vx, vy, vz] = shifted;
+            kind.measure_apparent(&Vector::new(x, y, z), &Vector::new(vx, vy, vz), observer.as_ref())
+        };
+        *partial = kind.difference(measure(1.0), measure(-1.0)) / (2.0 * step);
+    }
+    partials
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrator::Integrator;
+    use crate::observation::Kind;
+
+    #[test]
+    fn test_recovers_the_orbit_that_made_the_observations() {
+        let truth = vec![
+            Body::new("Star", 1.0, Vector::null(), Vector::null()),
+            Body::new("Station", 1e-12, Vector::new(0.0, 2.0, 0.0), Vector::new(-0.7, 0.0, 0.0)),
+            Body::new("Rock", 1e-12, Vector::new(1.0, 0.0, 0.1), Vector::new(0.0, 1.0, 0.05)),
+        ];
+        let settings = Settings {
+            gravity: 1.0,
+            dt: 1e-2,
+            integrator: Integrator::Rk4,
+            progress: false,
+            ..Settings::default()
+        };
+        // Exact ranges and angles from the station, at ten times.
+        let mut simulation = Simulation::builder().bodies(truth.clone()).settings(settings.clone()).build().unwrap();
+        let mut observations = Vec::new();
+        for k in 1..=10 {
+            let time = 0.3 * k as f64;
+            simulation.advance_to(time).unwrap();
+            let [_, station, rock] = simulation.bodies() else { unreachable!() };
+            let (position, velocity) = (rock.position - station.position, rock.velocity - station.velocity);
+            for (kind, sigma) in [(Kind::Range, 1e-6), (Kind::RightAscension, 1e-4), (Kind::Declination, 1e-4)] {
+                observations.push(Observation {
+                    time,
+                    observer: "Station".to_string(),
+                    target: "Rock".to_string(),
+                    kind,
+                    value: kind.measure(&position, &velocity),
+                    sigma,
+                });
+            }
+        }
+
+        let mut guess = truth.clone();
+        guess[2].position.x += 0.02;
+        guess[2].velocity.y -= 0.01;
+        let options = Options {
+            tolerance: 1e-3,
+            max_iterations: 10,
+            corrections: Corrections::default(),
+        };
+        let fit = fit(&guess, &settings, 2, &observations, options).unwrap();
+
+        assert!(fit.rms[0] > 100.0 && *fit.rms.last().unwrap() < 1e-2, "{:?}", fit.rms);
+        assert!((fit.bodies[2].position - truth[2].position).norm() < 1e-6);
+        assert!((fit.bodies[2].velocity - truth[2].velocity).norm() < 1e-6);
+        assert_eq!(fit.residuals.len(), observations.len());
+        assert!(fit.covariance[0][0] > 0.0 && fit.covariance[0][0].sqrt() < 1e-5);
+
+        assert!(super::fit(&guess, &settings, 2, &observations[..5], options).is_err());
+    }
+}
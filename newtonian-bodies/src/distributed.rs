@@ -0,0 +1,337 @@
+use super::body::Vector;
+use super::Body;
+use rayon::prelude::*;
+use std::error::Error;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::Range;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Most bodies a worker accepts in a request, so a bad request can't make it
+/// allocate without bound.
+pub const MAX_BODIES: usize = 1 << 24;
+
+/// How long a run waits on a worker, and a worker on the rest of a request or
+/// on the run taking its answer, before giving up on the other side.
+pub const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Connections to worker processes (`newtonian-solar-system worker`) summing
+/// the gravity on a share of the bodies.
+///
+/// The data is replicated: at every force evaluation, each worker receives the
+/// masses and positions of all the bodies and returns the accelerations of its
+/// share, while this process sums an equal share of its own. A worker that
+/// fails is dropped, and its share is summed here from then on; see
+/// [`Workers::failures`].
+#[derive(Debug)]
+pub struct Workers {
+    addresses: Vec<String>,
+    connections: Mutex<Vec<Option<TcpStream>>>,
+    failures: Mutex<Vec<String>>,
+}
+
+/// Each run holds its own connections.
+impl PartialEq for Workers {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Workers {
+    /// Connects to the workers listening at `addresses` (e.g., "node2:7878").
+    pub fn connect(addresses: &[String]) -> Result<Self, Box<dyn Error>> {
+        Workers::connect_with_timeout(addresses, TIMEOUT)
+    }
+
+    /// Like [`Workers::connect`], dropping workers that take longer than
+    /// `timeout` to take a request or to answer it (e.g., one busy with
+    /// another run).
+    pub fn connect_with_timeout(addresses: &[String], timeout: Duration) -> Result<Self, Box<dyn Error>> {
+        if timeout.is_zero() {
+            return Err("the worker timeout must be positive".into());
+        }
+        let connections = addresses
+            .iter()
+            .map(|address| {
+                let stream = TcpStream::connect(address).map_err(|e| format!("cannot connect to worker {}: {}", address, e))?;
+                stream.set_nodelay(true)?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                Ok(Some(stream))
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+        Ok(Workers {
+            addresses: addresses.to_vec(),
+            connections: Mutex::new(connections),
+            failures: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Number of workers connected at the start.
+    pub fn count(&self) -> usize {
+        self.addresses.len()
+    }
+
+    /// Workers dropped so far, each with the error it failed on.
+    pub fn failures(&self) -> Vec<String> {
+        self.failures.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// Sets the acceleration of every body to the pull of the others.
+    pub(crate) fn accelerate(&self, bodies: &mut [Body], gravity: f64) {
+        let sources: Vec<[f64; 4]> = bodies.iter().map(source).collect();
+        let shares = shares(bodies.len(), self.count() + 1);
+        let mut connections = self.connections.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // Ask every worker first, so they sum while this process does.
+        let mut asked = vec![false; self.count()];
+        for (k, connection) in connections.iter_mut().enumerate() {
+            if let Some(stream) = connection {
+                match request(stream, gravity, &sources, shares[k + 1].clone()) {
+                    Ok(()) => asked[k] = true,
+                    Err(e) => self.drop_worker(connection, k, &e),
+                }
+            }
+        }
+        let mut accelerations = pulls(&sources, shares[0].clone(), gravity);
+        for (k, connection) in connections.iter_mut().enumerate() {
+            let share = shares[k + 1].clone();
+            let pulled = match connection {
+                Some(stream) if asked[k] => response(stream, share.len()),
+                _ => Err(io::Error::new(ErrorKind::NotConnected, "dropped")),
+            };
+            let pulled = pulled.unwrap_or_else(|e| {
+                if connection.is_some() {
+                    self.drop_worker(connection, k, &e);
+                }
+                pulls(&sources, share, gravity)
+            });
+            accelerations.extend(pulled);
+        }
+
+        for (body, acceleration) in bodies.iter_mut().zip(accelerations) {
+            body.acceleration = Vector::new(acceleration[0], acceleration[1], acceleration[2]);
+        }
+    }
+
+    fn drop_worker(&self, connection: &mut Option<TcpStream>, k: usize, error: &io::Error) {
+        *connection = None;
+        let failure = format!("worker {} failed ({})", self.addresses[k], error);
+        self.failures.lock().unwrap_or_else(PoisonError::into_inner).push(failure);
+    }
+}
+
+/// Serves the force evaluations of one run after another, until the listener
+/// fails. Runs that fail are handed to `failed` with their address, and the
+/// next one is served.
+pub fn serve(listener: TcpListener, mut failed: impl FnMut(&str, io::Error)) -> Result<(), Box<dyn Error>> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = stream.peer_addr().map_or("unknown".to_string(), |peer| peer.to_string());
+        if let Err(e) = serve_run(&stream) {
+            failed(&peer, e);
+        }
+    }
+    Ok(())
+}
+
+/// Answers requests until the run closes the connection. Runs may take their
+/// time between requests (e.g., while paused), but not within one.
+fn serve_run(mut stream: &TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    loop {
+        stream.set_read_timeout(None)?;
+        let header = match read_words(&mut stream, 4) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            header => header?,
+        };
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        let [count, start, end] = [header[0], header[1], header[2]].map(|word| word as usize);
+        let gravity = f64::from_bits(header[3]);
+        if header[0] > MAX_BODIES as u64 {
+            return Err(io::Error::new(ErrorKind::InvalidData, format!("{} bodies, at most {}", header[0], MAX_BODIES)));
+        }
+        if start > end || end > count {
+            return Err(io::Error::new(ErrorKind::InvalidData, format!("share {}..{} of {} bodies", start, end, count)));
+        }
+        let sources: Vec<[f64; 4]> = read_words(&mut stream, 4 * count)?
+            .chunks_exact(4)
+            .map(|words| [0, 1, 2, 3].map(|k| f64::from_bits(words[k])))
+            .collect();
+        let pulled = pulls(&sources, start..end, gravity);
+        write_words(&mut stream, pulled.iter().flatten().map(|x| x.to_bits()))?;
+    }
+}
+
+/// Mass and position of a body.
+fn source(body: &Body) -> [f64; 4] {
+    [body.mass, body.position.x, body.position.y, body.position.z]
+}
+
+/// `parts` contiguous ranges of nearly equal length covering `count` bodies.
+fn shares(count: usize, parts: usize) -> Vec<Range<usize>> {
+    (0..parts).map(|k| k * count / parts..(k + 1) * count / parts).collect()
+}
+
+/// Accelerations of the bodies in `share` from the pull of all the others.
+fn pulls(sources: &[[f64; 4]], share: Range<usize>, gravity: f64) -> Vec<[f64; 3]> {
+    share
+        .into_par_iter()
+        .map(|i| {
+            let body = sources[i];
+            let mut acceleration = [0.0; 3];
+            for (j, other) in sources.iter().enumerate() {
+                if j == i {
+                    continue;
+                }
+                let d = [other[1] - body[1], other[2] - body[2], other[3] - body[3]];
+                let r2 = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+                let f = gravity * other[0] / (r2 * r2.sqrt());
+                for axis in 0..3 {
+                    acceleration[axis] += f * d[axis];
+                }
+            }
+            acceleration
+        })
+        .collect()
+}
+
+// The messages are little-endian 64-bit words. A request is the number of
+// bodies, the first and the end of the share, the gravitational constant and
+// the mass and position of every body; the response is the acceleration of
+// every body of the share.
+
+fn request(mut stream: &TcpStream, gravity: f64, sources: &[[f64; 4]], share: Range<usize>) -> io::Result<()> {
+    let header = [sources.len() as u64, share.start as u64, share.end as u64, gravity.to_bits()];
+    let words = header.into_iter().chain(sources.iter().flatten().map(|x| x.to_bits()));
+    write_words(&mut stream, words)
+}
+
+fn response(mut stream: &TcpStream, count: usize) -> io::Result<Vec<[f64; 3]>> {
+    let words = read_words(&mut stream, 3 * count)?;
+    Ok(words.chunks_exact(3).map(|words| [0, 1, 2].map(|k| f64::from_bits(words[k]))).collect())
+}
+
+fn write_words(stream: &mut impl Write, words: impl Iterator<Item = u64>) -> io::Result<()> {
+    let bytes: Vec<u8> = words.flat_map(u64::to_le_bytes).collect();
+    stream.write_all(&bytes)
+}
+
+fn read_words(stream: &mut impl Read, count: usize) -> io::Result<Vec<u64>> {
+    let length = count
+        .checked_mul(8)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("{} words is too many", count)))?;
+    let mut bytes = vec![0; length];
+    stream.read_exact(&mut bytes)?;
+    Ok(bytes.chunks_exact(8).map(|word| u64::from_le_bytes(word.try_into().unwrap())).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Tags;
+    use crate::forces::Forces;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn cluster() -> Vec<Body> {
+        (0..50)
+            .map(|i| {
+                let t = i as f64;
+                Body {
+                    name: format!("B{}", i),
+                    mass: 1.0 + t,
+                    position: Vector::new((t * 0.37).sin(), (t * 1.3).cos(), (t * 0.71).sin()) * 10.0,
+                    velocity: Vector::null(),
+                    acceleration: Vector::null(),
+                    tags: Tags::new(),
+                    temperature: None,
+                }
+            })
+            .collect()
+    }
+
+    fn assert_direct(bodies: &[Body]) {
+        let mut direct = bodies.to_vec();
+        Forces::newtonian(2.0).accelerate(&mut direct);
+        for (a, b) in bodies.iter().zip(&direct) {
+            assert!((a.acceleration - b.acceleration).norm() <= 1e-12 * b.acceleration.norm(), "{:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_workers_sum_their_share() {
+        let addresses: Vec<String> = (0..2)
+            .map(|_| {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                let address = listener.local_addr().unwrap().to_string();
+                thread::spawn(move || serve(listener, |_, _| ()).ok());
+                address
+            })
+            .collect();
+        let workers = Workers::connect(&addresses).unwrap();
+        let mut bodies = cluster();
+        for _ in 0..2 {
+            workers.accelerate(&mut bodies, 2.0);
+            assert_direct(&bodies);
+        }
+        assert!(workers.connections.lock().unwrap().iter().all(Option::is_some));
+        assert_eq!(shares(5, 3), vec![0..1, 1..3, 3..5]);
+    }
+
+    #[test]
+    fn test_workers_reject_oversized_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let failed = Arc::clone(&failures);
+        thread::spawn(move || serve(listener, move |_, e| failed.lock().unwrap().push(e.to_string())).ok());
+
+        for count in [u64::MAX, MAX_BODIES as u64 + 1] {
+            let mut stream = TcpStream::connect(address).unwrap();
+            write_words(&mut stream, [count, 0, 1, 1.0f64.to_bits()].into_iter()).unwrap();
+            // The worker hangs up instead of waiting for the bodies.
+            assert_eq!(stream.read(&mut [0; 8]).unwrap(), 0);
+        }
+        let failures = failures.lock().unwrap();
+        assert!(failures.len() == 2 && failures.iter().all(|e| e.contains("at most")), "{:?}", failures);
+    }
+
+    #[test]
+    fn test_stalled_worker_is_dropped() {
+        // A worker that takes the connection but never answers.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addresses = [listener.local_addr().unwrap().to_string()];
+        let workers = Workers::connect_with_timeout(&addresses, Duration::from_millis(200)).unwrap();
+        let (stalled, _) = listener.accept().unwrap();
+
+        let mut bodies = cluster();
+        workers.accelerate(&mut bodies, 2.0);
+        assert_direct(&bodies);
+        assert!(workers.connections.lock().unwrap()[0].is_none());
+        assert_eq!(workers.failures().len(), 1);
+        assert!(Workers::connect_with_timeout(&addresses, Duration::ZERO).is_err());
+        drop(stalled);
+    }
+
+    #[test]
+    fn test_failed_worker_share_is_summed_here() {
+        // A worker that hangs up as soon as the run connects.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addresses = [listener.local_addr().unwrap().to_string()];
+        let hangup = thread::spawn(move || drop(listener.accept()));
+        let workers = Workers::connect(&addresses).unwrap();
+        hangup.join().unwrap();
+
+        let mut bodies = cluster();
+        workers.accelerate(&mut bodies, 2.0);
+        assert_direct(&bodies);
+        assert!(workers.connections.lock().unwrap()[0].is_none());
+        let failures = workers.failures();
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].starts_with(&format!("worker {} failed", addresses[0])), "{:?}", failures);
+        assert!(Workers::connect(&["127.0.0.1:1".to_string()]).is_err());
+    }
+}
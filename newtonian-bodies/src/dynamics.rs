@@ -1,7 +1,202 @@
+use super::console::{Console, Control};
+use super::contact::Contacts;
+use super::distributed::Workers;
+use super::far_field::{FarField, SplitGravity};
+use super::forces::{Forces, PeriodicBox};
+use super::galaxy::Halo;
+use super::mass_transfer::MassTransfer;
+use super::allocations;
+use super::integrator::{interpolate_step, Integrator, Workspace};
+use super::memory;
+use super::plugin::{Plugin, PluginForces};
+use super::regularization::Regularization;
+use super::script::Script;
+use super::sph::Sph;
+use super::thermal::Thermal;
+use super::tree::{self, BarnesHut};
+use super::body::Tags;
 use super::Body;
 use std::error::Error;
+use std::sync::Arc;
 use indicatif::{ProgressBar, ProgressStyle};
 
+/// Parameters of a simulation run.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub gravity: f64,
+    pub total_time: f64,
+    pub dt: f64,
+    pub recording: Recording,
+    /// Only bodies carrying all of these tags are recorded; empty records every body.
+    pub record_tags: Tags,
+    pub integrator: Integrator,
+    /// Integrators of the bodies carrying the given tags, in place of
+    /// `integrator`; a body follows the first group it belongs to.
+    pub integrators: Vec<(Tags, Integrator)>,
+    /// Move close pairs as their centers of mass, with their relative motion
+    /// in KS variables.
+    pub regularization: Option<Regularization>,
+    /// Whether to draw a progress bar on the terminal.
+    pub progress: bool,
+    /// Bytes the simulation state may use; runs needing more fail before the first step.
+    pub max_memory: Option<usize>,
+    /// Run in a periodic box instead of open space.
+    pub periodic: Option<PeriodicBox>,
+    /// Distance beyond which bodies don't pull on each other.
+    pub cutoff: Option<f64>,
+    /// Gas dynamics between the gas particles.
+    pub sph: Option<Sph>,
+    /// Evolution of the temperatures of the bodies that have one.
+    pub thermal: Option<Thermal>,
+    /// Tree code approximating the gravity of distant groups of bodies.
+    pub tree: Option<BarnesHut>,
+    /// Sort the bodies along a Morton curve every this many steps, so bodies
+    /// close in space are close in memory; frames and the final state keep the
+    /// original order.
+    pub reorder_every: Option<usize>,
+    /// Worker processes summing the gravity on shares of the bodies.
+    pub workers: Option<Arc<Workers>>,
+    /// Reuse the gravity of distant bodies for a few steps.
+    pub far_field: Option<FarField>,
+    /// Contacts between solid bodies, resolved by rapier3d.
+    pub contacts: Option<Contacts>,
+    /// Static galactic halo pulling on every body.
+    pub halo: Option<Halo>,
+    /// Roche-lobe overflow between the stars of a binary.
+    pub mass_transfer: Option<MassTransfer>,
+    /// Custom forces and event handlers.
+    pub script: Option<Script>,
+    /// WebAssembly plugins; those exporting `accelerate` add their forces.
+    pub plugins: Vec<Plugin>,
+    /// Console answering queries between steps, and pausing the run or
+    /// changing its time step on demand.
+    pub console: Option<Arc<Console>>,
+}
+
+impl Settings {
+    pub fn forces(&self) -> Forces {
+        Forces {
+            gravity: self.gravity,
+            periodic: self.periodic,
+            cutoff: self.cutoff,
+            sph: self.sph.clone(),
+            thermal: self.thermal.clone(),
+            tree: self.tree,
+            workers: self.workers.clone(),
+            far_field: self.far_field.map(|far_field| Arc::new(SplitGravity::new(far_field))),
+            contacts: self.contacts.clone(),
+            halo: self.halo,
+            mass_transfer: self.mass_transfer.clone(),
+            script: self.script.clone(),
+            plugins: self
+                .plugins
+                .iter()
+                .filter(|plugin| plugin.accelerates())
+                .map(|plugin| PluginForces::new(plugin.clone()))
+                .collect(),
+        }
+    }
+
+    /// These settings for runs of copies of the bodies made alongside the run
+    /// itself (shifted states, sigma points, sampled impactors): without the
+    /// progress bar or the console, and with the plugins quiet.
+    ///
+    /// Fails when the script handles steps: its handler would run once more in
+    /// every copy, and the copies would stray from the run without it.
+    pub fn for_copies(&self) -> Result<Settings, Box<dyn Error>> {
+        if let Some(script) = self.script.as_ref().filter(|script| script.handles_steps()) {
+            return Err(format!("{}: on_step handlers can't run in copies of the run", script.path().display()).into());
+        }
+        Ok(Settings {
+            progress: false,
+            console: None,
+            plugins: self.plugins.iter().map(Plugin::quiet).collect(),
+            ..self.clone()
+        })
+    }
+}
+
+impl Default for Settings {
+    /// The defaults of the command line: one year of SI gravity recorded every second.
+    fn default() -> Self {
+        Settings {
+            gravity: 6.67430e-11,
+            total_time: 60.0 * 60.0 * 24.0 * 365.0,
+            dt: 0.001,
+            recording: Recording::Interval(1.0),
+            record_tags: Tags::new(),
+            integrator: Integrator::Euler,
+            integrators: Vec::new(),
+            regularization: None,
+            progress: true,
+            max_memory: None,
+            periodic: None,
+            cutoff: None,
+            sph: None,
+            thermal: None,
+            tree: None,
+            reorder_every: None,
+            workers: None,
+            far_field: None,
+            contacts: None,
+            halo: None,
+            mass_transfer: None,
+            script: None,
+            plugins: Vec::new(),
+            console: None,
+        }
+    }
+}
+
+/// When frames are written during a run.
+///
+/// The initial and the final state are always recorded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Recording {
+    /// Every given number of seconds.
+    Interval(f64),
+    /// Exactly this many frames, evenly spaced over the run.
+    Count(usize),
+}
+
+impl Recording {
+    /// Times at which frames are requested in a run lasting `total_time` seconds.
+    pub fn times(&self, total_time: f64) -> Result<Vec<f64>, Box<dyn Error>> {
+        let total_time = total_time.max(0.0);
+        let mut times = match *self {
+            Recording::Interval(interval) => {
+                if interval.is_nan() || interval <= 0.0 {
+                    return Err(format!("record interval must be positive, got {}", interval).into());
+                }
+                // Stop short of the end so rounding doesn't produce a frame right before the last one.
+                let tolerance = interval * 1e-9;
+                let mut times: Vec<f64> = (0..)
+                    .map(|k| k as f64 * interval)
+                    .take_while(|t| *t < total_time - tolerance)
+                    .collect();
+                times.push(total_time);
+                times
+            }
+            Recording::Count(0) => return Err("record count must be at least 1".into()),
+            Recording::Count(1) => vec![0.0],
+            Recording::Count(count) => (0..count)
+                .map(|k| total_time * k as f64 / (count - 1) as f64)
+                .collect(),
+        };
+        times.dedup();
+        Ok(times)
+    }
+
+    /// Upper bound of the number of frames requested in a run lasting `total_time` seconds.
+    pub fn max_frames(&self, total_time: f64) -> usize {
+        match *self {
+            Recording::Interval(interval) if interval > 0.0 => (total_time.max(0.0) / interval) as usize + 2,
+            Recording::Interval(_) => 1,
+            Recording::Count(count) => count.max(1),
+        }
+    }
+}
+
 pub fn simulate(
     bodies: &mut Vec<Body>,
     gravity: f64,
@@ -10,117 +205,356 @@ pub fn simulate(
     record_interval: u64,
     writer: &mut impl SequentialWriter,
 ) -> Result<(), Box<dyn Error>> {
-    let steps = (total_time as f64 / dt).ceil() as usize;
-    let record_steps = (record_interval as f64 / dt).ceil() as usize;
+    let settings = Settings {
+        gravity,
+        total_time,
+        dt,
+        recording: Recording::Interval(record_interval as f64),
+        ..Settings::default()
+    };
+    simulate_with(bodies, &settings, writer)
+}
+
+/// Runs the simulation described by `settings`.
+///
+/// The last step is shortened so the run ends exactly at `total_time`.
+/// Integrators with dense output record at exactly the requested times; the
+/// others record the first step reached at or after each of them.
+pub fn simulate_with(
+    bodies: &mut Vec<Body>,
+    settings: &Settings,
+    writer: &mut impl SequentialWriter,
+) -> Result<(), Box<dyn Error>> {
+    let Settings {
+        total_time,
+        dt,
+        recording,
+        ref record_tags,
+        integrator,
+        ref integrators,
+        regularization,
+        progress,
+        max_memory,
+        reorder_every,
+        ..
+    } = *settings;
+    if dt.is_nan() || dt <= 0.0 {
+        return Err(format!("time step must be positive, got {}", dt).into());
+    }
+    if reorder_every == Some(0) {
+        return Err("reorder interval must be at least 1 step".into());
+    }
+    let forces = settings.forces();
+    forces.check()?;
+    integrator.check_groups(integrators, &forces)?;
+    if let Some(regularization) = regularization {
+        regularization.check(integrator, integrators, &forces)?;
+    }
+
+    let total_time = total_time.max(0.0);
+    let required = integrators
+        .iter()
+        .map(|(_, integrator)| *integrator)
+        .chain([integrator])
+        .map(|integrator| memory::simulation_bytes(bodies, integrator, recording.max_frames(total_time)))
+        .max()
+        .unwrap_or_default();
+    memory::check_budget("the simulation state", required, max_memory)?;
+    let record_times = recording.times(total_time)?;
+    let mut schedule = Schedule::new(total_time, dt);
+    // Interpolating regularized pairs would cut across the orbits they make within a step.
+    let dense = integrator.has_dense_output()
+        && integrators.iter().all(|(_, integrator)| integrator.has_dense_output())
+        && regularization.is_none();
+    // Whether the steps should leave the heap alone, once the buffers have grown.
+    let allocation_free = forces.allocation_free()
+        && reorder_every.is_none()
+        && settings.console.is_none()
+        && regularization.is_none();
+    // Slack absorbing the rounding of accumulated step times.
+    let tolerance = dt * 1e-6;
 
     // 1. Setup the progress bar
-    let pb = ProgressBar::new(record_steps as u64);
+    let total_intervals = record_times.len().saturating_sub(1).max(1);
+    let interval_steps = (schedule.steps as f64 / total_intervals as f64).ceil().max(1.0) as u64;
+    let pb = if progress { ProgressBar::new(interval_steps) } else { ProgressBar::hidden() };
     pb.set_style(ProgressStyle::default_bar()
         .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
         .unwrap()
         .progress_chars("=>-"));
 
-    let total_intervals = (steps as f64 / record_steps as f64).ceil() as u32;
-    
-    for step in 0..steps {
-        // 2. Update the message at the start of each interval
-        if step % record_steps == 0 {
-            let current_interval = (step / record_steps) + 1;
-            pb.set_message(format!("Interval {}/{}", current_interval, total_intervals));
-            writer.add(step as u64, bodies)?;
+    forces.wrap(bodies);
+    let mut relayout = Relayout::new(reorder_every, bodies.len());
+    relayout.update(bodies, 0);
+    forces.clock(0.0);
+    integrator.initialize(bodies, &forces);
+    let mut workspace = Workspace::default();
+    // The state at the start of the step, and the frames interpolated from it.
+    let mut start = if dense { bodies.to_vec() } else { Vec::new() };
+    let mut frame = start.clone();
+    let mut selected = Vec::new();
+
+    let mut time = 0.0;
+    let mut next_record = 0;
+    let mut steps_since_record = 0;
+    record(writer, record_tags, time, relayout.restore(bodies), &mut selected)?;
+    while next_record < record_times.len() && record_times[next_record] <= tolerance {
+        next_record += 1;
+    }
+
+    let mut step = 0;
+    while step < schedule.steps {
+        if let Some(console) = &settings.console {
+            let mut control = Control {
+                bodies,
+                order: &relayout.origin,
+                time,
+                steps: step,
+                dt: schedule.dt,
+                gravity: settings.gravity,
+            };
+            let served = console.serve(&mut control);
+            if let Some(dt) = served.dt {
+                schedule.change(step, time, dt);
+            }
+            if served.changed {
+                // Added bodies keep their place, and every body its new pull.
+                relayout.grow(bodies.len());
+                if dense {
+                    start = bodies.to_vec();
+                    frame = start.clone();
+                }
+                forces.expire_far_field();
+                integrator.initialize(bodies, &forces);
+            }
         }
+        let end_time = schedule.end_time(step);
+        let h = end_time - time;
 
-        update_acceleration(bodies, gravity);
-        update_velocity(bodies, dt);
-        update_position(bodies, dt);
+        if settings.far_field.is_some_and(|far_field| step.is_multiple_of(far_field.every)) {
+            forces.expire_far_field();
+        }
+        let allocations = allocations::count();
+        if dense {
+            start.clone_from_slice(bodies);
+        }
+        forces.clock(time);
+        match regularization {
+            Some(regularization) => regularization.step(integrator, integrators, bodies, &forces, h, &mut workspace),
+            None => integrator.step_groups(integrators, bodies, &forces, h, &mut workspace),
+        }
+        forces.heat(bodies, h);
+        forces.transfer_mass(bodies, h);
+        forces.touch(bodies, h);
+        forces.events(bodies, end_time)?;
+        if forces.reshapes() {
+            // The next step starts from the pull of the changed bodies.
+            forces.clock(end_time);
+            forces.accelerate(bodies);
+        }
+        debug_assert!(
+            !allocation_free || step == 0 || allocations::count() == allocations,
+            "step {} allocated on the heap",
+            step + 1
+        );
+
+        let mut recorded = false;
+        while next_record < record_times.len() && record_times[next_record] <= end_time + tolerance {
+            if dense {
+                // Emit the requested time from the step interpolant.
+                let record_time = record_times[next_record];
+                let theta = if h > 0.0 { (record_time - time) / h } else { 1.0 };
+                frame.clone_from_slice(&start);
+                interpolate_step(&mut frame, bodies, h, theta);
+                forces.wrap(&mut frame);
+                record(writer, record_tags, record_time, relayout.restore(&frame), &mut selected)?;
+            } else if !recorded {
+                // Several requested times within one step share its single frame.
+                forces.wrap(bodies);
+                record(writer, record_tags, end_time, relayout.restore(bodies), &mut selected)?;
+            }
+            recorded = true;
+            next_record += 1;
+        }
+        forces.wrap(bodies);
+        // The accelerations move along, so the integrator carries on unaware.
+        if relayout.update(bodies, step + 1) {
+            forces.expire_far_field();
+        }
 
+        // 2. Restart the bar at the start of each interval
+        steps_since_record += 1;
+        if recorded {
+            steps_since_record = 0;
+            pb.set_message(format!("Interval {}/{}", next_record.min(total_intervals), total_intervals));
+        }
         // 3. Set the position. The modulo operator makes it "restart".
-        pb.set_position((step % record_steps) as u64 + 1);
+        pb.set_position(steps_since_record % interval_steps + 1);
+
+        time = end_time;
+        step += 1;
     }
 
     // 4. Finish the progress bar
     pb.finish_with_message("Simulation complete!");
+    if let Some(console) = &settings.console {
+        console.finish();
+    }
 
+    if reorder_every.is_some() {
+        let restored = relayout.restore(bodies).to_vec();
+        bodies.clone_from_slice(&restored);
+    }
     Ok(())
 }
 
-pub trait SequentialWriter {
-    fn add(&mut self, time: u64, bodies: &[Body]) -> Result<(), Box<dyn Error>>;
+/// Ends of the steps of a run, which may change their length midway.
+struct Schedule {
+    total_time: f64,
+    dt: f64,
+    /// First step and start time of the current length.
+    first: usize,
+    origin: f64,
+    /// Number of steps of the whole run.
+    steps: usize,
 }
 
-fn update_acceleration(bodies: &mut Vec<Body>, gravity: f64) {
-    let bodies_clone = bodies.clone();
+impl Schedule {
+    fn new(total_time: f64, dt: f64) -> Self {
+        Schedule {
+            total_time,
+            dt,
+            first: 0,
+            origin: 0.0,
+            steps: (total_time / dt).ceil() as usize,
+        }
+    }
 
-    for body in bodies.iter_mut() {
-        let mut ax = 0.0;
-        let mut ay = 0.0;
-        let mut az = 0.0;
+    /// Steps of `dt` from `step`, which starts at `time`; the last one is
+    /// shortened to end the run on time.
+    fn change(&mut self, step: usize, time: f64, dt: f64) {
+        self.dt = dt;
+        self.first = step;
+        self.origin = time;
+        self.steps = step + ((self.total_time - time) / dt).ceil().max(0.0) as usize;
+    }
 
-        for other in bodies_clone.iter() {
-            if body.name == other.name {
-                continue;
-            }
+    fn end_time(&self, step: usize) -> f64 {
+        if step + 1 == self.steps {
+            self.total_time
+        } else {
+            self.origin + (step + 1 - self.first) as f64 * self.dt
+        }
+    }
+}
 
-            let dx = other.position.x - body.position.x;
-            let dy = other.position.y - body.position.y;
-            let dz = other.position.z - body.position.z;
+/// Keeps the bodies of a run in Morton order, remembering the original
+/// position of each.
+struct Relayout {
+    every: Option<usize>,
+    /// Original index of each body in its current place.
+    origin: Vec<usize>,
+    /// The bodies last put back in their original order.
+    restored: Vec<Body>,
+}
 
-            let r = (dx * dx + dy * dy + dz * dz).sqrt();
-            let f = gravity * body.mass * other.mass / (r * r);
+impl Relayout {
+    fn new(every: Option<usize>, count: usize) -> Self {
+        Relayout {
+            every,
+            origin: (0..count).collect(),
+            restored: Vec::new(),
+        }
+    }
 
-            ax += f * dx / (r * body.mass);
-            ay += f * dy / (r * body.mass);
-            az += f * dz / (r * body.mass);
+    /// Sorts the bodies along the Morton curve when `step` is a multiple of
+    /// the interval, returning whether it did.
+    fn update(&mut self, bodies: &mut [Body], step: usize) -> bool {
+        let Some(every) = self.every else {
+            return false;
+        };
+        if !step.is_multiple_of(every) {
+            return false;
         }
+        let order = tree::morton_order(bodies);
+        let sorted: Vec<Body> = order.iter().map(|&i| bodies[i].clone()).collect();
+        bodies.clone_from_slice(&sorted);
+        self.origin = order.iter().map(|&i| self.origin[i]).collect();
+        true
+    }
 
-        body.acceleration.x = ax;
-        body.acceleration.y = ay;
-        body.acceleration.z = az;
+    /// Gives the bodies added at the end since, up to `count`, their place.
+    fn grow(&mut self, count: usize) {
+        self.origin.extend(self.origin.len()..count);
     }
-}
 
-fn update_velocity(bodies: &mut [Body], dt: f64) {
-    for body in bodies.iter_mut() {
-        body.velocity.x += body.acceleration.x * dt;
-        body.velocity.y += body.acceleration.y * dt;
-        body.velocity.z += body.acceleration.z * dt;
+    /// The bodies in their original order.
+    fn restore<'a>(&'a mut self, bodies: &'a [Body]) -> &'a [Body] {
+        if self.every.is_none() {
+            return bodies;
+        }
+        self.restored.resize_with(bodies.len(), || bodies[0].clone());
+        for (body, &origin) in bodies.iter().zip(&self.origin) {
+            self.restored[origin].clone_from(body);
+        }
+        &self.restored
     }
 }
 
-fn update_position(bodies: &mut [Body], dt: f64) {
-    for body in bodies.iter_mut() {
-        body.position.x += body.velocity.x * dt;
-        body.position.y += body.velocity.y * dt;
-        body.position.z += body.velocity.z * dt;
+/// Writes the bodies carrying `tags` (all of them when there are none),
+/// copying them into `selected` to reuse its memory from frame to frame.
+pub(crate) fn record(
+    writer: &mut impl SequentialWriter,
+    tags: &Tags,
+    time: f64,
+    bodies: &[Body],
+    selected: &mut Vec<Body>,
+) -> Result<(), Box<dyn Error>> {
+    if tags.is_empty() {
+        return writer.add(time, bodies);
+    }
+    let mut count = 0;
+    for body in bodies.iter().filter(|body| body.has_tags(tags)) {
+        match selected.get_mut(count) {
+            Some(copy) => copy.clone_from(body),
+            None => selected.push(body.clone()),
+        }
+        count += 1;
     }
+    selected.truncate(count);
+    writer.add(time, selected)
+}
+
+pub trait SequentialWriter {
+    /// Records the state of the bodies at `time`, in seconds since the start of the simulation.
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>>;
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::body::Vector;
-    use std::collections::HashMap;
+    use crate::body::{Tags, Vector};
 
     // Mock implementation of SequentialWriter for testing
     struct MockWriter {
-        records: HashMap<u64, Vec<Body>>,
+        records: Vec<(f64, Vec<Body>)>,
     }
 
     impl MockWriter {
         fn new() -> Self {
             MockWriter {
-                records: HashMap::new(),
+                records: Vec::new(),
             }
         }
 
-        fn get_records(&self) -> &HashMap<u64, Vec<Body>> {
+        fn get_records(&self) -> &Vec<(f64, Vec<Body>)> {
             &self.records
         }
     }
 
     impl SequentialWriter for MockWriter {
-        fn add(&mut self, time: u64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
-            self.records.insert(time, bodies.to_vec());
+        fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+            self.records.push((time, bodies.to_vec()));
             Ok(())
         }
     }
@@ -134,6 +568,8 @@ mod tests {
                 position: Vector { x: 0.0, y: 0.0, z: 0.0 },
                 velocity: Vector { x: 0.0, y: 0.0, z: 0.0 },
                 acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: None,
             },
             Body {
                 name: "Moon".to_string(),
@@ -141,6 +577,8 @@ mod tests {
                 position: Vector { x: 384400000.0, y: 0.0, z: 0.0 },
                 velocity: Vector { x: 0.0, y: 1022.0, z: 0.0 },
                 acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: None,
             },
         ]
     }
@@ -172,8 +610,8 @@ mod tests {
         let result = simulate(&mut bodies, gravity, total_time, dt, record_interval, &mut writer);
         
         assert!(result.is_ok());
-        // With zero time, no steps are taken, so no records are written
-        assert!(writer.get_records().is_empty());
+        // With zero time, no steps are taken; the initial state is also the final one
+        assert_eq!(writer.get_records().len(), 1);
     }
 
     #[test]
@@ -188,9 +626,9 @@ mod tests {
         let result = simulate(&mut bodies, gravity, total_time, dt, record_interval, &mut writer);
         
         assert!(result.is_ok());
-        // With small dt (0.001) and record_interval (1), record_steps = 1000
-        // So we only record every 1000 steps, resulting in 1 record for 1000 steps
-        assert_eq!(writer.get_records().len(), 1);
+        // With small dt (0.001) and record_interval (1), the run spans exactly one
+        // interval, so only the initial and the final states are recorded
+        assert_eq!(writer.get_records().len(), 2);
     }
 
     #[test]
@@ -229,7 +667,7 @@ mod tests {
     #[test]
     fn test_simulate_updates_positions() {
         let mut bodies = create_test_bodies();
-        let initial_positions: Vec<Vector> = bodies.iter().map(|b| b.position.clone()).collect();
+        let initial_positions: Vec<Vector> = bodies.iter().map(|b| b.position).collect();
         let mut writer = MockWriter::new();
         let gravity = 6.67430e-11;
         let total_time = 1.0;
@@ -257,7 +695,7 @@ mod tests {
     #[test]
     fn test_simulate_updates_velocities() {
         let mut bodies = create_test_bodies();
-        let initial_velocities: Vec<Vector> = bodies.iter().map(|b| b.velocity.clone()).collect();
+        let initial_velocities: Vec<Vector> = bodies.iter().map(|b| b.velocity).collect();
         let mut writer = MockWriter::new();
         let gravity = 6.67430e-11;
         let total_time = 1.0;
@@ -291,6 +729,8 @@ mod tests {
                 position: Vector { x: 0.0, y: 0.0, z: 0.0 },
                 velocity: Vector { x: 0.0, y: 0.0, z: 0.0 },
                 acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: None,
             }
         ];
         let mut writer = MockWriter::new();
@@ -323,4 +763,212 @@ mod tests {
         // Should handle negative time gracefully (will result in 0 steps)
         assert!(result.is_ok());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_dense_output_records_at_exact_times() {
+        let mut bodies = create_test_bodies();
+        let mut writer = MockWriter::new();
+        let settings = Settings {
+            gravity: 6.67430e-11,
+            total_time: 3.0,
+            dt: 0.7,
+            recording: Recording::Interval(1.0),
+            integrator: Integrator::Rk4,
+            progress: false,
+            ..Settings::default()
+        };
+
+        let result = simulate_with(&mut bodies, &settings, &mut writer);
+
+        assert!(result.is_ok());
+        let times: Vec<f64> = writer.get_records().iter().map(|(t, _)| *t).collect();
+        assert_eq!(times, vec![0.0, 1.0, 2.0, 3.0]);
+        // The first frame is the initial state, untouched by interpolation.
+        assert_eq!(writer.get_records()[0].1[1].position.x, 384400000.0);
+    }
+
+    #[test]
+    fn test_records_on_the_first_step_after_each_interval() {
+        let mut bodies = create_test_bodies();
+        let mut writer = MockWriter::new();
+
+        // dt doesn't divide the interval: frames land on the step reached right after each second
+        let result = simulate(&mut bodies, 6.67430e-11, 3.0, 0.3, 1, &mut writer);
+
+        assert!(result.is_ok());
+        let times: Vec<f64> = writer.get_records().iter().map(|(t, _)| *t).collect();
+        let expected = [0.0, 1.2, 2.1, 3.0];
+        assert_eq!(times.len(), expected.len());
+        for (time, expected) in times.iter().zip(expected) {
+            assert!((time - expected).abs() < 1e-9, "{:?}", times);
+        }
+    }
+
+    #[test]
+    fn test_final_state_is_recorded() {
+        let mut bodies = create_test_bodies();
+        let mut writer = MockWriter::new();
+        let settings = Settings {
+            gravity: 6.67430e-11,
+            total_time: 2.5,
+            dt: 0.1,
+            recording: Recording::Interval(1.0),
+            integrator: Integrator::Euler,
+            progress: false,
+            ..Settings::default()
+        };
+
+        simulate_with(&mut bodies, &settings, &mut writer).unwrap();
+
+        let (time, last) = writer.get_records().last().unwrap();
+        assert_eq!(*time, 2.5);
+        assert_eq!(last[1].position.x, bodies[1].position.x);
+    }
+
+    #[test]
+    fn test_record_count_gives_evenly_spaced_frames() {
+        let mut bodies = create_test_bodies();
+        let mut writer = MockWriter::new();
+        let settings = Settings {
+            gravity: 6.67430e-11,
+            total_time: 10.0,
+            dt: 0.1,
+            recording: Recording::Count(5),
+            integrator: Integrator::Rk4,
+            progress: false,
+            ..Settings::default()
+        };
+
+        simulate_with(&mut bodies, &settings, &mut writer).unwrap();
+
+        let times: Vec<f64> = writer.get_records().iter().map(|(t, _)| *t).collect();
+        assert_eq!(times, vec![0.0, 2.5, 5.0, 7.5, 10.0]);
+    }
+
+    #[test]
+    fn test_max_memory_fails_before_the_first_step() {
+        let mut bodies = create_test_bodies();
+        let mut writer = MockWriter::new();
+        let settings = Settings {
+            total_time: 1.0,
+            dt: 0.1,
+            progress: false,
+            max_memory: Some(64),
+            ..Settings::default()
+        };
+
+        assert!(simulate_with(&mut bodies, &settings, &mut writer).is_err());
+        assert!(writer.get_records().is_empty());
+    }
+
+    #[test]
+    fn test_record_tags_filter_recorded_bodies() {
+        let mut bodies = create_test_bodies();
+        bodies[1].tags.insert("category".to_string(), "moon".to_string());
+        let mut writer = MockWriter::new();
+        let settings = Settings {
+            total_time: 1.0,
+            dt: 0.1,
+            record_tags: Tags::from([("category".to_string(), "moon".to_string())]),
+            progress: false,
+            ..Settings::default()
+        };
+
+        simulate_with(&mut bodies, &settings, &mut writer).unwrap();
+
+        assert_eq!(writer.get_records().len(), 2);
+        for (_, frame) in writer.get_records() {
+            assert_eq!(frame.len(), 1);
+            assert_eq!(frame[0].tags["category"], "moon");
+        }
+    }
+
+    #[test]
+    fn test_recording_times() {
+        assert_eq!(Recording::Interval(4.0).times(10.0).unwrap(), vec![0.0, 4.0, 8.0, 10.0]);
+        assert_eq!(Recording::Interval(5.0).times(10.0).unwrap(), vec![0.0, 5.0, 10.0]);
+        assert_eq!(Recording::Count(1).times(10.0).unwrap(), vec![0.0]);
+        assert_eq!(Recording::Count(3).times(0.0).unwrap(), vec![0.0]);
+        assert!(Recording::Interval(0.0).times(10.0).is_err());
+        assert!(Recording::Count(0).times(10.0).is_err());
+    }
+
+    #[test]
+    fn test_reordering_keeps_the_original_order() {
+        // A lattice listed in an order scattered across space.
+        let lattice: Vec<Body> = (0..27)
+            .map(|k| (k * 10) % 27)
+            .map(|k| Body {
+                name: format!("Body {}", k),
+                mass: 1.0e20,
+                position: Vector::new((k % 3) as f64, (k / 3 % 3) as f64, (k / 9) as f64) * 1.0e6,
+                velocity: Vector::new(0.0, 0.0, k as f64),
+                acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: None,
+            })
+            .collect();
+        let settings = Settings {
+            total_time: 100.0,
+            dt: 10.0,
+            recording: Recording::Count(4),
+            integrator: Integrator::Rk4,
+            progress: false,
+            ..Settings::default()
+        };
+        let mut plain = lattice.clone();
+        let mut plain_writer = MockWriter::new();
+        simulate_with(&mut plain, &settings, &mut plain_writer).unwrap();
+        let mut reordered = lattice.clone();
+        let mut reordered_writer = MockWriter::new();
+        let reordering = Settings { reorder_every: Some(3), ..settings.clone() };
+        simulate_with(&mut reordered, &reordering, &mut reordered_writer).unwrap();
+
+        let mut frames = plain_writer.get_records().clone();
+        frames.push((100.0, plain));
+        let mut reordered_frames = reordered_writer.get_records().clone();
+        reordered_frames.push((100.0, reordered));
+        assert_eq!(frames.len(), reordered_frames.len());
+        for ((time, plain), (reordered_time, reordered)) in frames.iter().zip(&reordered_frames) {
+            assert_eq!(time, reordered_time);
+            for (a, b) in plain.iter().zip(reordered) {
+                assert_eq!(a.name, b.name);
+                // Only the summation order of the forces differs.
+                assert!((a.position - b.position).norm() < 1e-3, "{:?} {:?}", a, b);
+            }
+        }
+        let never = Settings { reorder_every: Some(0), ..settings };
+        assert!(simulate_with(&mut lattice.clone(), &never, &mut MockWriter::new()).is_err());
+    }
+
+    #[cfg(feature = "count-allocations")]
+    #[test]
+    fn test_steps_do_not_allocate() {
+        use crate::forces::Forces;
+
+        for integrator in [Integrator::Euler, Integrator::Verlet, Integrator::Rk4] {
+            let mut bodies = create_test_bodies();
+            let forces = Forces::newtonian(6.67430e-11);
+            let mut workspace = Workspace::default();
+            integrator.initialize(&mut bodies, &forces);
+            integrator.step_in(&mut bodies, &forces, 1.0, &mut workspace);
+            let allocations = allocations::count();
+            integrator.step_in(&mut bodies, &forces, 1.0, &mut workspace);
+            assert!(allocations.is_some());
+            assert_eq!(allocations::count(), allocations, "{}", integrator);
+
+            // Runs check every step themselves in debug builds.
+            bodies[1].tags.insert("category".to_string(), "moon".to_string());
+            let settings = Settings {
+                total_time: 10.0,
+                dt: 1.0,
+                recording: Recording::Interval(3.0),
+                record_tags: Tags::from([("category".to_string(), "moon".to_string())]),
+                integrator,
+                progress: false,
+                ..Settings::default()
+            };
+            simulate_with(&mut bodies, &settings, &mut MockWriter::new()).unwrap();
+        }
+    }
+}
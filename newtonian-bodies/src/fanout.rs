@@ -0,0 +1,97 @@
+use super::dynamics::SequentialWriter;
+use super::Body;
+use std::error::Error;
+
+/// Records every frame with each of several writers, e.g., a full-precision
+/// Parquet output plus a downsampled CSV for plotting. A failing writer stops
+/// the run.
+pub struct FanOut<W> {
+    writers: Vec<W>,
+}
+
+impl<W> FanOut<W> {
+    pub fn new(writers: Vec<W>) -> Self {
+        FanOut { writers }
+    }
+
+    pub fn writers(&self) -> &[W] {
+        &self.writers
+    }
+
+    /// The writers, in order, e.g., to close them.
+    pub fn into_inner(self) -> Vec<W> {
+        self.writers
+    }
+}
+
+impl<W: SequentialWriter> SequentialWriter for FanOut<W> {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        for writer in &mut self.writers {
+            writer.add(time, bodies)?;
+        }
+        Ok(())
+    }
+}
+
+/// Passes every `every`-th frame on to a writer, starting with the first.
+pub struct Downsample<W> {
+    inner: W,
+    every: usize,
+    frames: usize,
+}
+
+impl<W> Downsample<W> {
+    pub fn new(inner: W, every: usize) -> Self {
+        Downsample {
+            inner,
+            every: every.max(1),
+            frames: 0,
+        }
+    }
+
+    pub fn inner(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: SequentialWriter> SequentialWriter for Downsample<W> {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        let keep = self.frames.is_multiple_of(self.every);
+        self.frames += 1;
+        if keep {
+            self.inner.add(time, bodies)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Times(Vec<f64>);
+
+    impl SequentialWriter for Times {
+        fn add(&mut self, time: f64, _bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+            self.0.push(time);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_every_writer_sees_its_own_frames() {
+        let mut fan_out = FanOut::new(vec![Downsample::new(Times::default(), 1), Downsample::new(Times::default(), 3)]);
+        for i in 0..7 {
+            fan_out.add(i as f64, &[]).unwrap();
+        }
+
+        let writers = fan_out.into_inner();
+        assert_eq!(writers[0].inner().0.len(), 7);
+        assert_eq!(writers[1].inner().0, vec![0.0, 3.0, 6.0]);
+    }
+}
@@ -0,0 +1,170 @@
+use super::body::Vector;
+use super::Body;
+use std::error::Error;
+use std::sync::{Mutex, PoisonError};
+
+/// Splits gravity into a near field, summed at every force evaluation, and a
+/// far field refreshed only every few steps.
+///
+/// In hierarchical systems (a planet with its moons among distant stars), the
+/// pull of distant bodies changes much more slowly than that of close ones, so
+/// reusing it for a few steps costs little accuracy. The pairs are sorted into
+/// near and far when the far field is refreshed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FarField {
+    /// Distance in meters beyond which bodies belong to each other's far field.
+    pub radius: f64,
+    /// Steps between refreshes of the far field.
+    pub every: usize,
+}
+
+impl FarField {
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        if !(self.radius.is_finite() && self.radius > 0.0) {
+            return Err(format!("far-field radius must be positive, got {}", self.radius).into());
+        }
+        if self.every == 0 {
+            return Err("the far field must be refreshed at least every step".into());
+        }
+        Ok(())
+    }
+}
+
+/// Gravity split by a far field, with its last refresh.
+#[derive(Debug)]
+pub(crate) struct SplitGravity {
+    pub(crate) far_field: FarField,
+    split: Mutex<Option<Split>>,
+}
+
+/// The near partners of every body, and the pull of all the others.
+#[derive(Debug)]
+struct Split {
+    near: Vec<Vec<usize>>,
+    far: Vec<Vector>,
+}
+
+/// The refresh is a cache.
+impl PartialEq for SplitGravity {
+    fn eq(&self, other: &Self) -> bool {
+        self.far_field == other.far_field
+    }
+}
+
+impl SplitGravity {
+    pub(crate) fn new(far_field: FarField) -> Self {
+        SplitGravity {
+            far_field,
+            split: Mutex::new(None),
+        }
+    }
+
+    /// Forgets the far field, so the next evaluation refreshes it.
+    pub(crate) fn expire(&self) {
+        *self.split.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    /// Sets the acceleration of every body to the pull of the others: the near
+    /// ones where they are now, the far ones where they were at the refresh.
+    pub(crate) fn accelerate(&self, bodies: &mut [Body], gravity: f64) {
+        let mut split = self.split.lock().unwrap_or_else(PoisonError::into_inner);
+        let split = match &mut *split {
+            Some(split) if split.far.len() == bodies.len() => split,
+            split => split.insert(self.refresh(bodies, gravity)),
+        };
+        for i in 0..bodies.len() {
+            let near = split.near[i].iter().map(|&j| pull(gravity, &bodies[i], &bodies[j]));
+            bodies[i].acceleration = near.fold(split.far[i], |sum, a| sum + a);
+        }
+    }
+
+    fn refresh(&self, bodies: &[Body], gravity: f64) -> Split {
+        let radius = self.far_field.radius;
+        let mut near = vec![Vec::new(); bodies.len()];
+        let mut far = vec![Vector::null(); bodies.len()];
+        for (i, body) in bodies.iter().enumerate() {
+            for (j, other) in bodies.iter().enumerate() {
+                if body.name == other.name {
+                    continue;
+                }
+                if (other.position - body.position).norm() <= radius {
+                    near[i].push(j);
+                } else {
+                    far[i] += pull(gravity, body, other);
+                }
+            }
+        }
+        Split { near, far }
+    }
+}
+
+/// Acceleration of `body` from the pull of `other`.
+fn pull(gravity: f64, body: &Body, other: &Body) -> Vector {
+    let d = other.position - body.position;
+    let r = d.norm();
+    d * (gravity * other.mass / (r * r * r))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamics::{simulate_with, Recording, Settings};
+    use crate::integrator::Integrator;
+    use crate::simulation::Discard;
+
+    /// The Earth and the Moon around the Sun, with a few stars a light-year away.
+    fn hierarchy() -> Vec<Body> {
+        let light_year = 9.461e15;
+        let mut bodies = vec![
+            Body::new("Sun", 1.989e30, Vector::null(), Vector::null()),
+            Body::new("Earth", 5.972e24, Vector::new(1.496e11, 0.0, 0.0), Vector::new(0.0, 29780.0, 0.0)),
+            Body::new(
+                "Moon",
+                7.348e22,
+                Vector::new(1.496e11 + 3.844e8, 0.0, 0.0),
+                Vector::new(0.0, 29780.0 + 1022.0, 0.0),
+            ),
+        ];
+        for k in 0..4 {
+            let angle = k as f64;
+            let position = Vector::new(angle.cos(), angle.sin(), 0.3) * light_year;
+            bodies.push(Body::new(format!("Star {}", k), 2.0e30, position, Vector::new(0.0, 0.0, 1.0e4)));
+        }
+        bodies
+    }
+
+    #[test]
+    fn test_far_field_matches_direct_gravity() {
+        let mut bodies = hierarchy();
+        let split = SplitGravity::new(FarField { radius: 1.0e13, every: 1 });
+        split.accelerate(&mut bodies, 6.67430e-11);
+        let mut direct = bodies.clone();
+        crate::forces::Forces::newtonian(6.67430e-11).accelerate(&mut direct);
+        for (a, b) in bodies.iter().zip(&direct) {
+            assert!((a.acceleration - b.acceleration).norm() <= 1e-12 * b.acceleration.norm(), "{}", a.name);
+        }
+    }
+
+    #[test]
+    fn test_stale_far_field_keeps_the_hierarchy() {
+        let settings = Settings {
+            total_time: 30.0 * 86400.0,
+            dt: 3600.0,
+            recording: Recording::Count(2),
+            integrator: Integrator::Rk4,
+            progress: false,
+            ..Settings::default()
+        };
+        let mut direct = hierarchy();
+        simulate_with(&mut direct, &settings, &mut Discard).unwrap();
+        let mut split = hierarchy();
+        let far_field = FarField { radius: 1.0e13, every: 100 };
+        simulate_with(&mut split, &Settings { far_field: Some(far_field), ..settings }, &mut Discard).unwrap();
+
+        // The Moon, pulled by everything, ends up within a meter of the direct run.
+        let moon = (split[2].position - direct[2].position).norm();
+        assert!(moon < 1.0, "{}", moon);
+        assert!(FarField { radius: 0.0, every: 1 }.check().is_err());
+        assert!(FarField { radius: 1.0, every: 0 }.check().is_err());
+    }
+}
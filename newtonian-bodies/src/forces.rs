@@ -0,0 +1,495 @@
+use super::body::Vector;
+use super::contact::Contacts;
+use super::distributed::Workers;
+use super::far_field::SplitGravity;
+use super::galaxy::Halo;
+use super::mass_transfer::MassTransfer;
+use super::plugin::PluginForces;
+use super::script::Script;
+use super::sph::Sph;
+use super::thermal::Thermal;
+use super::tree::BarnesHut;
+use super::Body;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+/// The interactions that accelerate the bodies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Forces {
+    pub gravity: f64,
+    /// Space is a periodic box instead of open.
+    pub periodic: Option<PeriodicBox>,
+    /// Distance beyond which bodies don't pull on each other. Bodies then only
+    /// look for partners in nearby cells of a grid instead of among all others,
+    /// which makes dense, short-range scenarios much cheaper. Periodic boxes
+    /// default to half their side.
+    pub cutoff: Option<f64>,
+    /// Gas pressure and viscosity between the gas particles, on top of gravity.
+    pub sph: Option<Sph>,
+    /// Temperatures of the bodies that have one, which set the pressure of SPH gas.
+    pub thermal: Option<Thermal>,
+    /// Approximates the pull of distant groups of bodies with a tree code,
+    /// instead of summing over all pairs. Only in open space, without a cutoff.
+    pub tree: Option<BarnesHut>,
+    /// Worker processes summing the pulls on shares of the bodies. Only in
+    /// open space, without a cutoff or a tree.
+    pub workers: Option<Arc<Workers>>,
+    /// Gravity of distant bodies reused for a few steps. Only in open space,
+    /// without a cutoff, a tree or workers.
+    pub(crate) far_field: Option<Arc<SplitGravity>>,
+    /// Contacts between solid bodies, resolved after every step.
+    pub contacts: Option<Contacts>,
+    /// Pull of a static galactic halo, on top of the gravity between the bodies.
+    pub halo: Option<Halo>,
+    /// Mass flowing between the stars of a binary, moved after every step.
+    pub mass_transfer: Option<MassTransfer>,
+    /// Accelerations and event handlers of a script, on top of the rest.
+    pub script: Option<Script>,
+    /// Force models of WebAssembly plugins, on top of the rest.
+    pub(crate) plugins: Vec<PluginForces>,
+}
+
+impl Forces {
+    /// Plain Newtonian gravity in open space.
+    pub fn newtonian(gravity: f64) -> Self {
+        Forces {
+            gravity,
+            periodic: None,
+            cutoff: None,
+            sph: None,
+            thermal: None,
+            tree: None,
+            workers: None,
+            far_field: None,
+            contacts: None,
+            halo: None,
+            mass_transfer: None,
+            script: None,
+            plugins: Vec::new(),
+        }
+    }
+
+    /// The cutoff in effect, if any.
+    pub fn cutoff(&self) -> Option<f64> {
+        self.cutoff.or(self.periodic.map(|periodic| periodic.size / 2.0))
+    }
+
+    /// Sets the acceleration of every body to the sum of the pulls of the others.
+    pub fn accelerate(&self, bodies: &mut [Body]) {
+        if let Some(tree) = &self.tree {
+            tree.accelerate(bodies, self.gravity);
+        } else if let Some(workers) = &self.workers {
+            workers.accelerate(bodies, self.gravity);
+        } else if let Some(far_field) = &self.far_field {
+            far_field.accelerate(bodies, self.gravity);
+        } else {
+            self.accelerate_pairs(bodies);
+        }
+        if let Some(halo) = &self.halo {
+            halo.accelerate(bodies);
+        }
+
+        if let Some(sph) = &self.sph {
+            sph.accelerate(bodies, self.periodic.as_ref(), self.thermal.as_ref());
+        }
+        #[cfg(feature = "rhai")]
+        if let Some(script) = &self.script {
+            script.accelerate(bodies);
+        }
+        for plugin in &self.plugins {
+            plugin.accelerate(bodies);
+        }
+    }
+
+    fn accelerate_pairs(&self, bodies: &mut [Body]) {
+        let cutoff = self.cutoff();
+        let cells = cutoff.and_then(|cutoff| Cells::new(bodies, cutoff, self.periodic.as_ref()));
+
+        // Each body is only written once its acceleration is summed, so the
+        // others are read in place rather than from a copy.
+        for i in 0..bodies.len() {
+            let body = &bodies[i];
+            let mut ax = 0.0;
+            let mut ay = 0.0;
+            let mut az = 0.0;
+
+            let mut pull = |other: &Body| {
+                if body.name == other.name {
+                    return;
+                }
+
+                let mut d = other.position - body.position;
+                if let Some(periodic) = &self.periodic {
+                    d = periodic.separation(d);
+                }
+
+                let r = d.norm();
+                if cutoff.is_some_and(|cutoff| r > cutoff) {
+                    return;
+                }
+                // Per unit mass of the body, so massless test particles work.
+                let f = self.gravity * other.mass / (r * r * r);
+
+                ax += f * d.x;
+                ay += f * d.y;
+                az += f * d.z;
+            };
+            match &cells {
+                Some(cells) => cells.neighbors(i).for_each(|j| pull(&bodies[j])),
+                None => bodies.iter().for_each(pull),
+            }
+
+            bodies[i].acceleration = Vector::new(ax, ay, az);
+        }
+    }
+
+    /// Whether evaluating the forces and temperatures needs no memory beyond the
+    /// bodies: gravity over all pairs in open space, without SPH or temperatures.
+    pub fn allocation_free(&self) -> bool {
+        self.cutoff().is_none()
+            && self.tree.is_none()
+            && self.workers.is_none()
+            && self.far_field.is_none()
+            && self.sph.is_none()
+            && self.thermal.is_none()
+            && self.contacts.is_none()
+            && self.script.is_none()
+            && self.plugins.is_empty()
+    }
+
+    /// Makes the next evaluation refresh the far field, if any.
+    pub fn expire_far_field(&self) {
+        if let Some(far_field) = &self.far_field {
+            far_field.expire();
+        }
+    }
+
+    /// Evolves the temperatures over a step of `dt` seconds that just ended.
+    pub fn heat(&self, bodies: &mut [Body], dt: f64) {
+        if let Some(thermal) = &self.thermal {
+            let heating = match &self.sph {
+                Some(sph) => sph.heating(bodies, self.periodic.as_ref(), Some(thermal)),
+                None => vec![0.0; bodies.len()],
+            };
+            thermal.heat(bodies, &heating, self.periodic.as_ref(), dt);
+        }
+    }
+
+    /// Moves the mass lost by the donor of a binary over a step of `dt` seconds
+    /// to its companion.
+    pub fn transfer_mass(&self, bodies: &mut [Body], dt: f64) {
+        if let Some(transfer) = &self.mass_transfer {
+            transfer.transfer(bodies, dt);
+        }
+    }
+
+    /// Resolves the contacts between solid bodies at the end of a step of `dt`
+    /// seconds.
+    pub fn touch(&self, bodies: &mut [Body], dt: f64) {
+        #[cfg(feature = "rapier3d")]
+        if let Some(contacts) = &self.contacts {
+            contacts.resolve(bodies, dt);
+        }
+        #[cfg(not(feature = "rapier3d"))]
+        let _ = (bodies, dt);
+    }
+
+    /// Whether the hooks at the end of a step (mass transfer, contacts and
+    /// `on_step` handlers) can change the bodies, leaving the accelerations the
+    /// integrator stored for the next step stale.
+    pub fn reshapes(&self) -> bool {
+        self.mass_transfer.is_some()
+            || self.contacts.is_some()
+            || self.script.as_ref().is_some_and(Script::handles_steps)
+    }
+
+    /// Tells time-dependent forces the start of the coming step.
+    pub fn clock(&self, time: f64) {
+        if let Some(script) = &self.script {
+            script.clock(time);
+        }
+        for plugin in &self.plugins {
+            plugin.clock(time);
+        }
+    }
+
+    /// Runs the event handlers at the end of a step, ending at `time`.
+    pub fn events(&self, bodies: &mut [Body], time: f64) -> Result<(), Box<dyn Error>> {
+        for plugin in &self.plugins {
+            plugin.check()?;
+        }
+        #[cfg(feature = "rhai")]
+        if let Some(script) = &self.script {
+            script.events(bodies, time)?;
+        }
+        #[cfg(not(feature = "rhai"))]
+        let _ = (bodies, time);
+        Ok(())
+    }
+
+    /// Brings bodies that left a periodic box back in through the opposite face.
+    pub fn wrap(&self, bodies: &mut [Body]) {
+        if let Some(periodic) = &self.periodic {
+            for body in bodies {
+                body.position = periodic.wrap(body.position);
+            }
+        }
+    }
+
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(periodic) = &self.periodic {
+            periodic.check()?;
+        }
+        if let Some(cutoff) = self.cutoff {
+            let most = self.periodic.map_or(f64::INFINITY, |periodic| periodic.size / 2.0);
+            if !(cutoff > 0.0 && cutoff <= most) {
+                return Err(match self.periodic {
+                    Some(_) => format!("cutoff must be positive and at most half the box size ({}), got {}", most, cutoff),
+                    None => format!("cutoff must be positive, got {}", cutoff),
+                }
+                .into());
+            }
+        }
+        if let Some(sph) = &self.sph {
+            sph.check()?;
+        }
+        if let Some(thermal) = &self.thermal {
+            thermal.check()?;
+        }
+        if let Some(contacts) = &self.contacts {
+            if self.periodic.is_some() {
+                return Err("contacts only work in open space".into());
+            }
+            contacts.check()?;
+        }
+        if let Some(tree) = &self.tree {
+            if self.periodic.is_some() || self.cutoff.is_some() {
+                return Err("the tree code only works in open space, without a cutoff".into());
+            }
+            tree.check()?;
+        }
+        if self.workers.is_some() && (self.periodic.is_some() || self.cutoff.is_some() || self.tree.is_some()) {
+            return Err("workers only sum the gravity of all pairs in open space, without a cutoff or a tree".into());
+        }
+        if let Some(split) = &self.far_field {
+            if self.periodic.is_some() || self.cutoff.is_some() || self.tree.is_some() || self.workers.is_some() {
+                return Err("the far field only splits the gravity of all pairs in open space, without a cutoff, tree or workers".into());
+            }
+            split.far_field.check()?;
+        }
+        if let Some(halo) = &self.halo {
+            if self.periodic.is_some() {
+                return Err("a galactic halo only works in open space".into());
+            }
+            halo.check()?;
+        }
+        if let Some(transfer) = &self.mass_transfer {
+            transfer.check()?;
+        }
+        Ok(())
+    }
+}
+
+/// Bodies sorted into a grid of cubic cells at least as wide as the cutoff, so
+/// every partner of a body is in its own cell or one of the 26 around it.
+pub(crate) struct Cells {
+    cells: HashMap<[i64; 3], Vec<usize>>,
+    /// Cell of each body.
+    of: Vec<[i64; 3]>,
+    /// Cells per side of a periodic box, whose grid wraps around.
+    wrap: Option<i64>,
+}
+
+impl Cells {
+    /// `None` when a periodic box is too small for a grid of at least three
+    /// cells per side, where all pairs are as cheap.
+    pub(crate) fn new(bodies: &[Body], cutoff: f64, periodic: Option<&PeriodicBox>) -> Option<Self> {
+        let (origin, width, wrap) = match periodic {
+            Some(periodic) => {
+                let count = (periodic.size / cutoff).floor();
+                if count < 3.0 {
+                    return None;
+                }
+                (Vector::null(), periodic.size / count, Some(count as i64))
+            }
+            None => {
+                let lowest = |coordinate: fn(&Body) -> f64| bodies.iter().map(coordinate).fold(f64::INFINITY, f64::min);
+                let origin = Vector::new(
+                    lowest(|b| b.position.x),
+                    lowest(|b| b.position.y),
+                    lowest(|b| b.position.z),
+                );
+                (origin, cutoff, None)
+            }
+        };
+        let index = |x: f64| {
+            let i = (x / width).floor() as i64;
+            wrap.map_or(i, |count| i.rem_euclid(count))
+        };
+        let of: Vec<[i64; 3]> = bodies
+            .iter()
+            .map(|body| {
+                let p = body.position - origin;
+                [index(p.x), index(p.y), index(p.z)]
+            })
+            .collect();
+        let mut cells: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+        for (i, cell) in of.iter().enumerate() {
+            cells.entry(*cell).or_default().push(i);
+        }
+        Some(Cells { cells, of, wrap })
+    }
+
+    /// Bodies in the cell of body `i` and the cells around it, `i` included.
+    pub(crate) fn neighbors(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        let [x, y, z] = self.of[i];
+        let offsets = (-1..=1).flat_map(|dx| (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| [dx, dy, dz])));
+        offsets.flat_map(move |[dx, dy, dz]| {
+            let mut cell = [x + dx, y + dy, z + dz];
+            if let Some(count) = self.wrap {
+                cell = cell.map(|c| c.rem_euclid(count));
+            }
+            self.cells.get(&cell).into_iter().flatten().copied()
+        })
+    }
+}
+
+/// A cube of side `size` with a corner at the origin, whose opposite faces are
+/// joined: every body interacts with the nearest image of each other body
+/// (minimum-image convention), up to the cutoff of the `Forces`.
+///
+/// The cutoff stands in for an Ewald sum, so forces from beyond it, including
+/// those of the periodic images, are neglected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeriodicBox {
+    pub size: f64,
+}
+
+impl PeriodicBox {
+    pub fn new(size: f64) -> Self {
+        PeriodicBox { size }
+    }
+
+    /// Nearest image of a separation between two bodies.
+    pub fn separation(&self, d: Vector) -> Vector {
+        let nearest = |x: f64| x - self.size * (x / self.size).round();
+        Vector::new(nearest(d.x), nearest(d.y), nearest(d.z))
+    }
+
+    /// The position inside the box equivalent to `position`.
+    pub fn wrap(&self, position: Vector) -> Vector {
+        let inside = |x: f64| x.rem_euclid(self.size);
+        Vector::new(inside(position.x), inside(position.y), inside(position.z))
+    }
+
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        if !(self.size.is_finite() && self.size > 0.0) {
+            return Err(format!("periodic box size must be positive, got {}", self.size).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Tags;
+
+    fn body(name: &str, x: f64) -> Body {
+        Body {
+            name: name.to_string(),
+            mass: 1.0,
+            position: Vector::new(x, 5.0, 5.0),
+            velocity: Vector::null(),
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    #[test]
+    fn test_bodies_pull_through_the_faces_of_the_box() {
+        let periodic = Forces {
+            periodic: Some(PeriodicBox::new(10.0)),
+            ..Forces::newtonian(1.0)
+        };
+        let mut bodies = vec![body("A", 1.0), body("B", 9.0)];
+        periodic.accelerate(&mut bodies);
+
+        // The nearest image of B is at x = -1, two units to the left of A.
+        assert_eq!(bodies[0].acceleration.x, -0.25);
+        assert_eq!(bodies[1].acceleration.x, 0.25);
+
+        let mut open = bodies.clone();
+        Forces::newtonian(1.0).accelerate(&mut open);
+        assert_eq!(open[0].acceleration.x, 1.0 / 64.0);
+    }
+
+    #[test]
+    fn test_cutoff_and_wrapping() {
+        let periodic = Forces {
+            periodic: Some(PeriodicBox::new(10.0)),
+            cutoff: Some(1.5),
+            ..Forces::newtonian(1.0)
+        };
+        let mut bodies = vec![body("A", 1.0), body("B", 9.0), body("C", 13.0)];
+        periodic.accelerate(&mut bodies);
+        assert_eq!(bodies[0].acceleration.x, 0.0);
+
+        periodic.wrap(&mut bodies);
+        assert_eq!(bodies[2].position, Vector::new(3.0, 5.0, 5.0));
+        assert!(Forces { cutoff: Some(6.0), ..periodic }.check().is_err());
+        assert!(PeriodicBox::new(-1.0).check().is_err());
+    }
+
+    #[test]
+    fn test_cells_find_the_same_partners_as_all_pairs() {
+        // A dense cluster, with a few bodies beyond the cutoff of the others.
+        let mut bodies: Vec<Body> = (0..200)
+            .map(|i| {
+                let t = i as f64;
+                let mut b = body(&format!("B{}", i), (t * 0.37).sin() * 4.0);
+                b.position = Vector::new(b.position.x, (t * 1.3).cos() * 4.0, (t * 0.71).sin() * 4.0);
+                b
+            })
+            .collect();
+        bodies.push(body("Far", 10.0));
+
+        for periodic in [None, Some(PeriodicBox::new(20.0))] {
+            let forces = Forces {
+                periodic,
+                cutoff: Some(1.5),
+                ..Forces::newtonian(1.0)
+            };
+            let mut cells = bodies.clone();
+            forces.wrap(&mut cells);
+            let mut pairs = cells.clone();
+            forces.accelerate(&mut cells);
+            all_pairs(&forces, &mut pairs);
+
+            for (a, b) in cells.iter().zip(&pairs) {
+                assert!((a.acceleration - b.acceleration).norm() <= 1e-9 * b.acceleration.norm().max(1e-9));
+            }
+            assert_eq!(cells.last().unwrap().acceleration, Vector::null());
+        }
+    }
+
+    /// Accelerations by brute force over all pairs, with the same cutoff.
+    fn all_pairs(forces: &Forces, bodies: &mut [Body]) {
+        let others = bodies.to_vec();
+        for body in bodies.iter_mut() {
+            body.acceleration = Vector::null();
+            for other in others.iter().filter(|other| other.name != body.name) {
+                let mut d = other.position - body.position;
+                if let Some(periodic) = &forces.periodic {
+                    d = periodic.separation(d);
+                }
+                let r = d.norm();
+                if r <= forces.cutoff().unwrap() {
+                    body.acceleration += forces.gravity * other.mass / (r * r * r) * d;
+                }
+            }
+        }
+    }
+}
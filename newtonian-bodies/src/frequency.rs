@@ -0,0 +1,122 @@
+use std::f64::consts::PI;
+
+/// Sinusoidal component `amplitude · exp(2πi · frequency · t)` of a complex signal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Line {
+    /// Cycles per second; negative for clockwise (retrograde) rotation.
+    pub frequency: f64,
+    pub amplitude: f64,
+}
+
+/// Strongest `count` lines of a complex signal, given as (real, imaginary)
+/// samples taken every `dt` seconds.
+///
+/// The signal is Hann-windowed and zero-padded before its FFT, and each peak is
+/// refined by parabolic interpolation, which locates frequencies to a small
+/// fraction of the 1/(N·dt) resolution of the raw transform.
+pub fn lines(signal: &[(f64, f64)], dt: f64, count: usize) -> Vec<Line> {
+    let n = signal.len();
+    if n < 4 {
+        return Vec::new();
+    }
+    let size = (4 * n).next_power_of_two();
+    let window = |k: usize| 0.5 * (1.0 - (2.0 * PI * k as f64 / n as f64).cos());
+    let mut spectrum = vec![(0.0, 0.0); size];
+    for (k, &(re, im)) in signal.iter().enumerate() {
+        spectrum[k] = (re * window(k), im * window(k));
+    }
+    fft(&mut spectrum);
+
+    let gain: f64 = (0..n).map(window).sum();
+    let magnitude: Vec<f64> = spectrum.iter().map(|(re, im)| (re * re + im * im).sqrt()).collect();
+    let at = |k: isize| magnitude[k.rem_euclid(size as isize) as usize];
+    let mut peaks: Vec<usize> = (0..size)
+        .filter(|&k| {
+            let k = k as isize;
+            at(k) > at(k - 1) && at(k) >= at(k + 1)
+        })
+        .collect();
+    peaks.sort_by(|&a, &b| magnitude[b].total_cmp(&magnitude[a]));
+
+    peaks
+        .into_iter()
+        .take(count)
+        .map(|k| {
+            let (left, middle, right) = (at(k as isize - 1).ln(), magnitude[k].ln(), at(k as isize + 1).ln());
+            let curvature = left - 2.0 * middle + right;
+            let offset = if curvature < 0.0 { 0.5 * (left - right) / curvature } else { 0.0 };
+            let mut index = k as f64 + offset;
+            if index > size as f64 / 2.0 {
+                index -= size as f64;
+            }
+            Line {
+                frequency: index / (size as f64 * dt),
+                amplitude: (middle - 0.25 * (left - right) * offset).exp() / gain,
+            }
+        })
+        .collect()
+}
+
+/// In-place iterative radix-2 FFT; the length must be a power of two.
+fn fft(data: &mut [(f64, f64)]) {
+    let n = data.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= n {
+        let angle = -2.0 * PI / length as f64;
+        for start in (0..n).step_by(length) {
+            for k in 0..length / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let (a, b) = (data[start + k], data[start + k + length / 2]);
+                let twisted = (b.0 * cos - b.1 * sin, b.0 * sin + b.1 * cos);
+                data[start + k] = (a.0 + twisted.0, a.1 + twisted.1);
+                data[start + k + length / 2] = (a.0 - twisted.0, a.1 - twisted.1);
+            }
+        }
+        length <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finds_prograde_and_retrograde_lines() {
+        let signal: Vec<(f64, f64)> = (0..500)
+            .map(|k| {
+                let t = k as f64 * 2.0;
+                let (a, b) = (2.0 * PI * 0.013 * t, -2.0 * PI * 0.081 * t);
+                (2.0 * a.cos() + 0.5 * b.cos(), 2.0 * a.sin() + 0.5 * b.sin())
+            })
+            .collect();
+
+        let lines = lines(&signal, 2.0, 2);
+
+        assert_eq!(lines.len(), 2);
+        assert!((lines[0].frequency - 0.013).abs() < 1e-5, "{:?}", lines);
+        assert!((lines[0].amplitude - 2.0).abs() < 0.05, "{:?}", lines);
+        assert!((lines[1].frequency + 0.081).abs() < 1e-5, "{:?}", lines);
+        assert!((lines[1].amplitude - 0.5).abs() < 0.02, "{:?}", lines);
+    }
+
+    #[test]
+    fn test_fft_of_an_impulse_is_flat() {
+        let mut data = vec![(0.0, 0.0); 8];
+        data[0] = (1.0, 0.0);
+        fft(&mut data);
+        assert!(data.iter().all(|&(re, im)| (re - 1.0).abs() < 1e-12 && im.abs() < 1e-12));
+    }
+}
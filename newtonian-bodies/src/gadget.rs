@@ -0,0 +1,106 @@
+use super::Body;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const HEADER_BYTES: usize = 256;
+/// Gadget particle type used for every body ("halo", plain gravitating particles).
+const PARTICLE_TYPE: usize = 1;
+
+/// Writes the bodies as a Gadget-2 snapshot (format 1, one file).
+///
+/// Every body is a type 1 particle with its own mass and an id following its
+/// position in `bodies`, starting at 1. Values are written in SI units (m, m/s,
+/// kg) as single precision, so tools should be told to use these units instead
+/// of the Gadget defaults.
+pub fn write(path: &Path, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_to(&mut writer, time, bodies)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_to(mut writer: impl Write, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+    let count = bodies.len() as u32;
+    let mut npart = [0u32; 6];
+    npart[PARTICLE_TYPE] = count;
+
+    let mut header = Vec::with_capacity(HEADER_BYTES);
+    npart.iter().for_each(|n| header.extend(n.to_le_bytes()));
+    // Mass table: zero, so masses are read from the MASS block.
+    header.extend([0u8; 6 * 8]);
+    header.extend(time.to_le_bytes());
+    header.extend(0.0f64.to_le_bytes()); // redshift
+    header.extend(0i32.to_le_bytes()); // flag_sfr
+    header.extend(0i32.to_le_bytes()); // flag_feedback
+    npart.iter().for_each(|n| header.extend(n.to_le_bytes())); // npartTotal
+    header.extend(0i32.to_le_bytes()); // flag_cooling
+    header.extend(1i32.to_le_bytes()); // num_files
+    for value in [0.0f64, 0.0, 0.0, 1.0] {
+        header.extend(value.to_le_bytes()); // BoxSize, Omega0, OmegaLambda, HubbleParam
+    }
+    header.resize(HEADER_BYTES, 0);
+    write_block(&mut writer, &header)?;
+
+    let vectors = |f: fn(&Body) -> [f64; 3]| -> Vec<u8> {
+        bodies
+            .iter()
+            .flat_map(|b| f(b).map(|v| (v as f32).to_le_bytes()))
+            .flatten()
+            .collect()
+    };
+    write_block(&mut writer, &vectors(|b| [b.position.x, b.position.y, b.position.z]))?;
+    write_block(&mut writer, &vectors(|b| [b.velocity.x, b.velocity.y, b.velocity.z]))?;
+    let ids: Vec<u8> = (1..=count).flat_map(|id| id.to_le_bytes()).collect();
+    write_block(&mut writer, &ids)?;
+    let masses: Vec<u8> = bodies.iter().flat_map(|b| (b.mass as f32).to_le_bytes()).collect();
+    write_block(&mut writer, &masses)?;
+    Ok(())
+}
+
+/// Writes a Fortran unformatted record: the data between two copies of its length.
+fn write_block(writer: &mut impl Write, data: &[u8]) -> Result<(), Box<dyn Error>> {
+    let size = u32::try_from(data.len())?.to_le_bytes();
+    writer.write_all(&size)?;
+    writer.write_all(data)?;
+    writer.write_all(&size)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Vector;
+
+    #[test]
+    fn test_snapshot_layout() {
+        let bodies = vec![
+            Body::new("Sun", 2.0, Vector::new(1.0, 2.0, 3.0), Vector::new(4.0, 5.0, 6.0));
+            2
+        ];
+        let mut bytes = Vec::new();
+        write_to(&mut bytes, 1.5, &bodies).unwrap();
+
+        let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let f32_at = |offset: usize| f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        // Header record, with npart[1] and the time.
+        assert_eq!(u32_at(0), 256);
+        assert_eq!(u32_at(4 + 4), 2);
+        assert_eq!(f64::from_le_bytes(bytes[4 + 72..4 + 80].try_into().unwrap()), 1.5);
+        assert_eq!(u32_at(4 + 256), 256);
+
+        // Positions, velocities, ids and masses follow, each in its own record.
+        let positions = 264;
+        assert_eq!(u32_at(positions), 24);
+        assert_eq!(f32_at(positions + 4 + 8), 3.0);
+        let velocities = positions + 32;
+        assert_eq!(f32_at(velocities + 4), 4.0);
+        let ids = velocities + 32;
+        assert_eq!(u32_at(ids), 8);
+        assert_eq!(u32_at(ids + 8), 2);
+        let masses = ids + 16;
+        assert_eq!(f32_at(masses + 4), 2.0);
+        assert_eq!(bytes.len(), masses + 16);
+    }
+}
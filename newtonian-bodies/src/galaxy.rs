@@ -0,0 +1,268 @@
+use super::body::Vector;
+use super::Body;
+use std::error::Error;
+
+/// Spheres shrink around the densest part of a cluster until they hold this
+/// many stars (or a hundredth of them, if more).
+const SHRINK_TO: usize = 10;
+
+/// Most refinements of the bound members around their center.
+const MAX_ITERATIONS: usize = 100;
+
+/// Static logarithmic potential of a galactic halo centered at the origin,
+/// Φ = v₀²/2 · ln(r_c² + r²): its rotation curve rises through the core and
+/// is flat at v₀ beyond it. It pulls on every body without being pulled back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Halo {
+    /// Circular speed far outside the core, in m/s.
+    pub circular_speed: f64,
+    /// Core radius, in meters.
+    pub core_radius: f64,
+}
+
+impl Halo {
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        if !(self.circular_speed > 0.0 && self.circular_speed.is_finite()) {
+            return Err(format!("the halo's circular speed must be positive, got {}", self.circular_speed).into());
+        }
+        if !(self.core_radius >= 0.0 && self.core_radius.is_finite()) {
+            return Err(format!("the halo's core radius must not be negative, got {}", self.core_radius).into());
+        }
+        Ok(())
+    }
+
+    /// Acceleration of a body at `position`.
+    pub fn acceleration(&self, position: &Vector) -> Vector {
+        let v2 = self.circular_speed * self.circular_speed;
+        -*position * (v2 / (self.core_radius * self.core_radius + position.norm_squared()))
+    }
+
+    /// Adds the pull of the halo to the accelerations of the bodies.
+    pub fn accelerate(&self, bodies: &mut [Body]) {
+        for body in bodies {
+            body.acceleration += self.acceleration(&body.position);
+        }
+    }
+
+    /// Gradient of the acceleration at `position`, ∂aᵢ/∂xⱼ.
+    pub fn tidal_tensor(&self, position: &Vector) -> [[f64; 3]; 3] {
+        let v2 = self.circular_speed * self.circular_speed;
+        let s = self.core_radius * self.core_radius + position.norm_squared();
+        let x = [position.x, position.y, position.z];
+        std::array::from_fn(|i| {
+            std::array::from_fn(|j| 2.0 * v2 * x[i] * x[j] / (s * s) - if i == j { v2 / s } else { 0.0 })
+        })
+    }
+
+    /// Potential energy per unit mass at `position`.
+    pub fn potential(&self, position: &Vector) -> f64 {
+        let v2 = self.circular_speed * self.circular_speed;
+        v2 / 2.0 * (self.core_radius * self.core_radius + position.norm_squared()).ln()
+    }
+
+    /// Speed of a circular orbit at distance `radius` from the center.
+    pub fn circular_speed_at(&self, radius: f64) -> f64 {
+        self.circular_speed * radius / (self.core_radius * self.core_radius + radius * radius).sqrt()
+    }
+
+    /// Jacobi (tidal) radius of a cluster of `mass` on a circular orbit of
+    /// `radius`: stars farther than this from its center are stripped by the
+    /// tides of the halo.
+    pub fn tidal_radius(&self, mass: f64, radius: f64, gravity: f64) -> f64 {
+        let (v2, c2, r2) = (self.circular_speed.powi(2), self.core_radius.powi(2), radius * radius);
+        // Ω² - ∂²Φ/∂r², from the angular speed and the radial tidal field.
+        let omega2 = v2 / (c2 + r2);
+        let curvature = v2 * (c2 - r2) / (c2 + r2).powi(2);
+        (gravity * mass / (omega2 - curvature)).cbrt()
+    }
+}
+
+/// Stars of a cluster still bound to it, at one time, and the extent of the
+/// stream of those the halo stripped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Census {
+    /// Whether each star is bound, in the order given.
+    pub bound: Vec<bool>,
+    pub bound_mass: f64,
+    /// Center of mass and mean velocity of the bound stars.
+    pub center: Vector,
+    pub velocity: Vector,
+    /// Tidal radius of the bound stars at their distance from the halo's center,
+    /// zero once the cluster has dissolved.
+    pub tidal_radius: f64,
+    /// Arc spanned by the stripped stars along the cluster's orbit, ahead of it
+    /// and behind it, at its distance from the halo's center.
+    pub stream_length: f64,
+}
+
+impl Census {
+    /// Finds the stars of a cluster within the tidal radius of the rest.
+    ///
+    /// Shrinking spheres locate the densest part of the cluster first, so a
+    /// long stream doesn't drag the center off it. Then the members are those
+    /// within the tidal radius of the mass they add up to, until they settle.
+    pub fn new(stars: &[Body], halo: &Halo, gravity: f64) -> Self {
+        let everyone = vec![true; stars.len()];
+        let (_, mut center, _) = center_of_mass(stars, &everyone);
+        let mut radius = stars.iter().map(|star| (star.position - center).norm()).fold(0.0, f64::max);
+        let smallest = SHRINK_TO.max(stars.len() / 100);
+        loop {
+            let inside: Vec<bool> = stars.iter().map(|star| (star.position - center).norm() <= radius).collect();
+            if inside.iter().filter(|&&inside| inside).count() < smallest {
+                break;
+            }
+            center = center_of_mass(stars, &inside).1;
+            radius *= 0.9;
+        }
+
+        let mut bound = everyone;
+        let mut tidal_radius = 0.0;
+        for _ in 0..MAX_ITERATIONS {
+            let (mass, _, _) = center_of_mass(stars, &bound);
+            if mass <= 0.0 {
+                break;
+            }
+            tidal_radius = halo.tidal_radius(mass, center.norm(), gravity);
+            let members: Vec<bool> = stars.iter().map(|star| (star.position - center).norm() <= tidal_radius).collect();
+            if members == bound {
+                break;
+            }
+            bound = members;
+            if bound.contains(&true) {
+                center = center_of_mass(stars, &bound).1;
+            }
+        }
+
+        let (bound_mass, center, velocity) = match bound.contains(&true) {
+            true => center_of_mass(stars, &bound),
+            false => {
+                tidal_radius = 0.0;
+                let (_, center, velocity) = center_of_mass(stars, &vec![true; stars.len()]);
+                (0.0, center, velocity)
+            }
+        };
+        // Signed angles of the stripped stars from the center, about the normal
+        // of its orbit: positive ahead of it, negative behind.
+        let normal = center.cross(&velocity);
+        let normal = normal / normal.norm().max(f64::MIN_POSITIVE);
+        let (behind, ahead) = stars
+            .iter()
+            .zip(&bound)
+            .filter(|(_, bound)| !**bound)
+            .map(|(star, _)| normal.dot(&center.cross(&star.position)).atan2(center.dot(&star.position)))
+            .fold((0.0f64, 0.0f64), |(behind, ahead), angle| (behind.min(angle), ahead.max(angle)));
+        Census {
+            bound,
+            bound_mass,
+            center,
+            velocity,
+            tidal_radius,
+            stream_length: (ahead - behind) * center.norm(),
+        }
+    }
+
+    pub fn bound_count(&self) -> usize {
+        self.bound.iter().filter(|&&bound| bound).count()
+    }
+
+    pub fn stripped_count(&self) -> usize {
+        self.bound.len() - self.bound_count()
+    }
+}
+
+/// Mass, center of mass and mean velocity of the selected stars.
+fn center_of_mass(stars: &[Body], selected: &[bool]) -> (f64, Vector, Vector) {
+    let (mut mass, mut position, mut velocity) = (0.0, Vector::null(), Vector::null());
+    for (star, _) in stars.iter().zip(selected).filter(|(_, selected)| **selected) {
+        mass += star.mass;
+        position += star.position * star.mass;
+        velocity += star.velocity * star.mass;
+    }
+    match mass > 0.0 {
+        true => (mass, position / mass, velocity / mass),
+        false => (0.0, position, velocity),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Tags;
+
+    const G: f64 = 6.67430e-11;
+
+    fn star(position: Vector, velocity: Vector) -> Body {
+        Body {
+            name: format!("Star at {:?}", position),
+            mass: 2e31,
+            position,
+            velocity,
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    #[test]
+    fn test_flat_rotation_curve_outside_the_core() {
+        let halo = Halo {
+            circular_speed: 2.2e5,
+            core_radius: 1e19,
+        };
+        let position = Vector::new(0.0, 2.5e20, 0.0);
+
+        let pull = halo.acceleration(&position);
+        let speed = halo.circular_speed_at(position.norm());
+        assert!((pull.norm() - speed * speed / position.norm()).abs() < 1e-12 * pull.norm());
+        assert!((speed / 2.2e5 - 1.0).abs() < 1e-3 && pull.y < 0.0);
+        // The pull is the slope of the potential.
+        let step = Vector::new(0.0, 1e15, 0.0);
+        let slope = (halo.potential(&(position + step)) - halo.potential(&(position - step))) / 2e15;
+        assert!((slope + pull.y).abs() < 1e-6 * pull.norm());
+        // And the tidal tensor is the slope of the pull.
+        let tidal = halo.tidal_tensor(&position)[1][1];
+        let difference = (halo.acceleration(&(position + step)) - halo.acceleration(&(position - step))).y / 2e15;
+        assert!((tidal - difference).abs() < 1e-6 * tidal.abs());
+
+        // r_t³ = G m r² / (2 v₀²) for a flat rotation curve.
+        let expected = (G * 2e35 * position.norm_squared() / (2.0 * 2.2e5 * 2.2e5)).cbrt();
+        assert!((halo.tidal_radius(2e35, position.norm(), G) / expected - 1.0).abs() < 1e-2);
+        assert!(Halo { circular_speed: 0.0, ..halo }.check().is_err());
+    }
+
+    #[test]
+    fn test_census_of_a_cluster_with_tails() {
+        let halo = Halo {
+            circular_speed: 2.2e5,
+            core_radius: 0.0,
+        };
+        let distance = 3e20;
+        let velocity = Vector::new(0.0, 2.2e5, 0.0);
+        // A tight core of 100 stars, 6 stars ahead on the orbit and 3 behind.
+        let mut stars: Vec<Body> = (0..100)
+            .map(|i| {
+                let t = i as f64;
+                let offset = Vector::new((t * 0.37).sin(), (t * 1.3).cos(), (t * 0.71).sin()) * 1e17;
+                star(Vector::new(distance, 0.0, 0.0) + offset, velocity)
+            })
+            .collect();
+        let at = |angle: f64| Vector::new(distance * angle.cos(), distance * angle.sin(), 0.0);
+        stars.extend([0.02, 0.05, 0.08, 0.1, 0.15, 0.2].map(|angle| star(at(angle), velocity)));
+        stars.extend([-0.03, -0.06, -0.1].map(|angle| star(at(angle), velocity)));
+
+        let census = Census::new(&stars, &halo, G);
+
+        assert_eq!((census.bound_count(), census.stripped_count()), (100, 9));
+        assert!(census.bound[..100].iter().all(|&bound| bound));
+        assert!((census.bound_mass - 2e33).abs() < 1e20);
+        assert!((census.center.x / distance - 1.0).abs() < 1e-3 && census.center.y.abs() < 2e17);
+        assert!((census.tidal_radius / halo.tidal_radius(2e33, census.center.norm(), G) - 1.0).abs() < 1e-9);
+        assert!((census.stream_length / (0.3 * distance) - 1.0).abs() < 1e-2, "{}", census.stream_length);
+
+        // Scattered stars with no core are all stripped.
+        let scattered: Vec<Body> = [0.0, 1.0, 2.0, 3.0].iter().map(|&angle| star(at(angle), velocity)).collect();
+        let census = Census::new(&scattered, &halo, G);
+        assert_eq!(census.bound_count(), 0);
+        assert_eq!(census.tidal_radius, 0.0);
+    }
+}
@@ -0,0 +1,568 @@
+use super::body::{Tags, Vector};
+use super::galaxy::Halo;
+use super::kepler;
+use super::Body;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::error::Error;
+use std::f64::consts::PI;
+
+/// Close approach of a rubble pile to a planet, optionally with a ring of test
+/// particles around the planet.
+///
+/// The planet starts at rest at the origin. The clump of particles comes in
+/// from `start_distance` on a parabolic or hyperbolic trajectory whose
+/// periapsis lies in the planet's equatorial (xy) plane.
+#[derive(Debug, Clone)]
+pub struct TidalDisruption {
+    pub planet_mass: f64,
+    /// Total mass of the rubble pile.
+    pub clump_mass: f64,
+    /// Radius of the sphere the rubble particles are drawn in.
+    pub clump_radius: f64,
+    pub particles: usize,
+    /// Closest approach of the clump's center; None passes at half the Roche limit.
+    pub periapsis: Option<f64>,
+    /// Initial distance of the clump's center; None starts at five Roche limits.
+    pub start_distance: Option<f64>,
+    /// Speed of the clump far from the planet, zero for a parabolic approach.
+    pub excess_speed: f64,
+    pub ring_particles: usize,
+    /// Inner and outer radius of the ring, on circular orbits.
+    pub ring: (f64, f64),
+    pub seed: u64,
+}
+
+impl Default for TidalDisruption {
+    /// A 100 km wide clump of 10¹⁸ kg (about 1900 kg/m³) passing an Earth-mass planet.
+    fn default() -> Self {
+        TidalDisruption {
+            planet_mass: 5.972e24,
+            clump_mass: 1e18,
+            clump_radius: 5e4,
+            particles: 200,
+            periapsis: None,
+            start_distance: None,
+            excess_speed: 0.0,
+            ring_particles: 0,
+            ring: (1e7, 2e7),
+            seed: 0,
+        }
+    }
+}
+
+/// Mass of test particles: small enough not to disturb anything, but not
+/// zero, which the dynamics cannot handle.
+const TEST_PARTICLE_MASS: f64 = 1.0;
+
+/// Astronomical unit, in meters.
+pub const AU: f64 = 1.495978707e11;
+
+/// Mean-motion resonances with the perturber that clear the Kirkwood gaps, as
+/// ratios of the particle's period to the perturber's: 3:1, 5:2, 7:3 and 2:1.
+const KIRKWOOD_RESONANCES: [f64; 4] = [1.0 / 3.0, 2.0 / 5.0, 3.0 / 7.0, 1.0 / 2.0];
+
+/// Half-width of a Kirkwood gap, relative to the semi-major axis of its resonance.
+const KIRKWOOD_HALF_WIDTH: f64 = 0.01;
+
+/// Distance inside which a fluid body held together by its own gravity is torn
+/// apart by the tides of a planet.
+pub fn roche_limit(planet_mass: f64, clump_mass: f64, clump_radius: f64) -> f64 {
+    2.44 * clump_radius * (planet_mass / clump_mass).cbrt()
+}
+
+impl TidalDisruption {
+    /// The planet, then the rubble particles and the ring particles, tagged
+    /// `group=planet`, `group=rubble` and `group=ring` respectively.
+    pub fn bodies(&self, gravity: f64) -> Result<Vec<Body>, Box<dyn Error>> {
+        if self.particles == 0 || self.clump_mass <= 0.0 || self.clump_radius <= 0.0 || self.planet_mass <= 0.0 {
+            return Err("the planet and the clump need a positive mass, and the clump a positive radius and particles".into());
+        }
+        if self.excess_speed < 0.0 {
+            return Err("the excess speed cannot be negative".into());
+        }
+        let roche = roche_limit(self.planet_mass, self.clump_mass, self.clump_radius);
+        let periapsis = self.periapsis.unwrap_or(0.5 * roche);
+        let start_distance = self.start_distance.unwrap_or(5.0 * roche);
+        if periapsis <= 0.0 || start_distance <= periapsis + self.clump_radius {
+            return Err("the clump must start outside its periapsis".into());
+        }
+        let (center, center_velocity) = self.approach(gravity, periapsis, start_distance)?;
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut bodies = vec![body("Planet", self.planet_mass, Vector::null(), Vector::null(), "planet")];
+        for i in 0..self.particles {
+            let offset = inside_unit_sphere(&mut rng);
+            let position = Vector {
+                x: center.x + self.clump_radius * offset.x,
+                y: center.y + self.clump_radius * offset.y,
+                z: center.z + self.clump_radius * offset.z,
+            };
+            // The clump starts cold, moving as one.
+            let mass = self.clump_mass / self.particles as f64;
+            bodies.push(body(&format!("Rubble {}", i + 1), mass, position, center_velocity, "rubble"));
+        }
+        for i in 0..self.ring_particles {
+            let (inner, outer) = self.ring;
+            let radius = rng.random_range(inner.min(outer)..=inner.max(outer));
+            let angle = rng.random_range(0.0..2.0 * PI);
+            let speed = (gravity * self.planet_mass / radius).sqrt();
+            let position = Vector {
+                x: radius * angle.cos(),
+                y: radius * angle.sin(),
+                z: 0.0,
+            };
+            let velocity = Vector {
+                x: -speed * angle.sin(),
+                y: speed * angle.cos(),
+                z: 0.0,
+            };
+            bodies.push(body(&format!("Ring {}", i + 1), TEST_PARTICLE_MASS, position, velocity, "ring"));
+        }
+        Ok(bodies)
+    }
+
+    /// State of the clump's center at `start_distance` before its periapsis.
+    fn approach(&self, gravity: f64, periapsis: f64, start_distance: f64) -> Result<(Vector, Vector), Box<dyn Error>> {
+        let mu = gravity * (self.planet_mass + self.clump_mass);
+        let position = Vector { x: periapsis, y: 0.0, z: 0.0 };
+        let velocity = Vector {
+            x: 0.0,
+            y: (self.excess_speed.powi(2) + 2.0 * mu / periapsis).sqrt(),
+            z: 0.0,
+        };
+        let (position, velocity, _) = kepler::before_periapsis(&position, &velocity, mu, start_distance)?;
+        Ok((position, velocity))
+    }
+}
+
+/// Star cluster on an eccentric orbit through a galactic halo, whose tides
+/// strip its outer stars into a leading and a trailing stream.
+///
+/// The stars follow a Plummer sphere in equilibrium, truncated at ten scale
+/// radii. The cluster starts at `distance` from the halo's center on the x
+/// axis, moving along y at `speed_fraction` of the circular speed there: below
+/// 1 it starts at the apocenter of its orbit.
+#[derive(Debug, Clone)]
+pub struct TidalStream {
+    pub halo: Halo,
+    pub cluster_mass: f64,
+    /// Plummer radius of the cluster, which holds half its mass within 1.3 of it.
+    pub scale_radius: f64,
+    pub stars: usize,
+    pub distance: f64,
+    pub speed_fraction: f64,
+    pub seed: u64,
+}
+
+impl Default for TidalStream {
+    /// A globular cluster of 10⁵ solar masses and 5 pc starting 10 kpc from the
+    /// center of a Milky Way-like halo (220 km/s, 1 kpc core).
+    fn default() -> Self {
+        TidalStream {
+            halo: Halo {
+                circular_speed: 2.2e5,
+                core_radius: 3.0857e19,
+            },
+            cluster_mass: 1.989e35,
+            scale_radius: 1.5429e17,
+            stars: 500,
+            distance: 3.0857e20,
+            speed_fraction: 0.6,
+            seed: 0,
+        }
+    }
+}
+
+/// Plummer spheres are truncated at this many scale radii.
+const PLUMMER_TRUNCATION: f64 = 10.0;
+
+impl TidalStream {
+    /// The stars of the cluster, tagged `group=cluster`.
+    pub fn bodies(&self, gravity: f64) -> Result<Vec<Body>, Box<dyn Error>> {
+        self.halo.check()?;
+        if self.stars == 0 || self.cluster_mass <= 0.0 || self.scale_radius <= 0.0 {
+            return Err("the cluster needs a positive mass, scale radius and number of stars".into());
+        }
+        if self.distance <= 0.0 || !(0.0..=2.0).contains(&self.speed_fraction) {
+            return Err("the cluster must start away from the halo's center, at 0 to 2 circular speeds".into());
+        }
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mass = self.cluster_mass / self.stars as f64;
+        let stars: Vec<(Vector, Vector)> = (0..self.stars).map(|_| self.plummer(&mut rng, gravity)).collect();
+        // The cluster's center of mass follows the orbit exactly.
+        let n = self.stars as f64;
+        let drift = stars.iter().fold((Vector::null(), Vector::null()), |(p, v), (position, velocity)| {
+            (p + *position / n, v + *velocity / n)
+        });
+        let center = Vector::new(self.distance, 0.0, 0.0);
+        let orbit = Vector::new(0.0, self.speed_fraction * self.halo.circular_speed_at(self.distance), 0.0);
+        Ok(stars
+            .iter()
+            .enumerate()
+            .map(|(i, (position, velocity))| {
+                body(
+                    &format!("Star {}", i + 1),
+                    mass,
+                    center + *position - drift.0,
+                    orbit + *velocity - drift.1,
+                    "cluster",
+                )
+            })
+            .collect())
+    }
+
+    /// Position and velocity of a star of the Plummer sphere, relative to its
+    /// center (Aarseth, Hénon and Wielen 1974).
+    fn plummer(&self, rng: &mut StdRng, gravity: f64) -> (Vector, Vector) {
+        let a = self.scale_radius;
+        let radius = loop {
+            let share: f64 = rng.random_range(f64::MIN_POSITIVE..1.0);
+            let radius = a / (share.powf(-2.0 / 3.0) - 1.0).sqrt();
+            if radius <= PLUMMER_TRUNCATION * a {
+                break radius;
+            }
+        };
+        // Speed as a fraction q of the escape speed, with density q²(1 - q²)^7/2.
+        let q = loop {
+            let q: f64 = rng.random_range(0.0..1.0);
+            if rng.random_range(0.0..0.1) < q * q * (1.0 - q * q).powf(3.5) {
+                break q;
+            }
+        };
+        let escape = (2.0 * gravity * self.cluster_mass / (radius * radius + a * a).sqrt()).sqrt();
+        (on_unit_sphere(rng) * radius, on_unit_sphere(rng) * (q * escape))
+    }
+}
+
+/// A population of minor bodies on orbits about a primary of a scenario, as
+/// test particles.
+///
+/// Semi-major axes are uniform over their range, eccentricities and
+/// inclinations (to the scenario's xy plane) follow Rayleigh distributions,
+/// as they do in the asteroid and Kuiper belts, and the other angles are
+/// uniform. Orbits are drawn about the primary alone.
+#[derive(Debug, Clone)]
+pub struct Population {
+    /// Name of the body the particles orbit.
+    pub primary: String,
+    pub count: usize,
+    /// Smallest and largest semi-major axis, in meters.
+    pub semi_major_axis: (f64, f64),
+    /// Scale of the Rayleigh distribution of the eccentricities.
+    pub eccentricity: f64,
+    /// Scale of the Rayleigh distribution of the inclinations, in radians.
+    pub inclination: f64,
+    /// Body whose Kirkwood gaps (3:1, 5:2, 7:3 and 2:1 resonances) are left
+    /// empty, e.g., Jupiter for the main belt.
+    pub kirkwood: Option<String>,
+    /// Tag `group` of the particles, and the prefix of their names.
+    pub group: String,
+    pub seed: u64,
+}
+
+impl Population {
+    /// The main asteroid belt between 2.1 and 3.3 AU of the Sun, with its
+    /// Kirkwood gaps if Jupiter is in the scenario.
+    pub fn main_belt(count: usize) -> Self {
+        Population {
+            primary: "Sun".to_string(),
+            count,
+            semi_major_axis: (2.1 * AU, 3.3 * AU),
+            eccentricity: 0.1,
+            inclination: 7.0f64.to_radians(),
+            kirkwood: Some("Jupiter".to_string()),
+            group: "asteroid".to_string(),
+            seed: 0,
+        }
+    }
+
+    /// The classical Kuiper belt between 39 and 48 AU of the Sun.
+    pub fn kuiper_belt(count: usize) -> Self {
+        Population {
+            primary: "Sun".to_string(),
+            count,
+            semi_major_axis: (39.0 * AU, 48.0 * AU),
+            eccentricity: 0.05,
+            inclination: 5.0f64.to_radians(),
+            kirkwood: None,
+            group: "kbo".to_string(),
+            seed: 0,
+        }
+    }
+
+    /// The particles, named "{group} 1", "{group} 2", etc., about the primary
+    /// of `scenario`.
+    pub fn bodies(&self, scenario: &[Body], gravity: f64) -> Result<Vec<Body>, Box<dyn Error>> {
+        let find = |name: &str| {
+            scenario
+                .iter()
+                .find(|body| body.name == name)
+                .ok_or_else(|| format!("no body named '{}' in the scenario", name))
+        };
+        let primary = find(&self.primary)?;
+        let (inner, outer) = self.semi_major_axis;
+        if !(inner > 0.0 && inner <= outer && outer.is_finite()) {
+            return Err(format!("the semi-major axes must be a positive range, got {} to {} m", inner, outer).into());
+        }
+        if !(self.eccentricity >= 0.0 && self.inclination >= 0.0) {
+            return Err("the eccentricity and inclination scales cannot be negative".into());
+        }
+        let mu = gravity * primary.mass;
+        let gaps: Vec<f64> = match &self.kirkwood {
+            Some(name) => {
+                let perturber = find(name)?;
+                let orbit = kepler::Elements::from_state(
+                    &(perturber.position - primary.position),
+                    &(perturber.velocity - primary.velocity),
+                    gravity * (primary.mass + perturber.mass),
+                );
+                if orbit.eccentricity >= 1.0 {
+                    return Err(format!("'{}' is not on a bound orbit about '{}'", name, self.primary).into());
+                }
+                // Kepler's third law: a ∝ P^(2/3).
+                KIRKWOOD_RESONANCES.iter().map(|ratio| orbit.semi_major_axis * ratio.powf(2.0 / 3.0)).collect()
+            }
+            None => Vec::new(),
+        };
+        if gaps.iter().any(|gap| (inner - gap).abs() <= KIRKWOOD_HALF_WIDTH * gap && (outer - gap).abs() <= KIRKWOOD_HALF_WIDTH * gap) {
+            return Err("the semi-major axes lie within a Kirkwood gap".into());
+        }
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let rayleigh = |rng: &mut StdRng, scale: f64| scale * (-2.0 * (1.0 - rng.random::<f64>()).ln()).sqrt();
+        let mut bodies = Vec::with_capacity(self.count);
+        while bodies.len() < self.count {
+            let semi_major_axis = rng.random_range(inner..=outer);
+            let eccentricity = rayleigh(&mut rng, self.eccentricity);
+            let inclination = rayleigh(&mut rng, self.inclination);
+            if eccentricity >= 1.0 || inclination > PI || gaps.iter().any(|gap| (semi_major_axis - gap).abs() < KIRKWOOD_HALF_WIDTH * gap) {
+                continue;
+            }
+            let elements = kepler::Elements {
+                semi_major_axis,
+                eccentricity,
+                inclination,
+                ascending_node: rng.random_range(0.0..2.0 * PI),
+                argument_of_periapsis: rng.random_range(0.0..2.0 * PI),
+                mean_anomaly: rng.random_range(0.0..2.0 * PI),
+            };
+            let (position, velocity) = elements.state(mu);
+            let name = format!("{} {}", self.group, bodies.len() + 1);
+            bodies.push(body(
+                &name,
+                TEST_PARTICLE_MASS,
+                primary.position + position,
+                primary.velocity + velocity,
+                &self.group,
+            ));
+        }
+        Ok(bodies)
+    }
+}
+
+/// Gives `body` the velocity of a circular orbit about `primary` at its
+/// current distance, v = √(G(M + m)/r), moving with the primary.
+///
+/// The orbit keeps the plane of the body's current motion relative to the
+/// primary; a body without such motion orbits counterclockwise in a plane
+/// parallel to xy. Moons should be set after their planets, since they take
+/// the planets' velocities.
+pub fn circularize(bodies: &mut [Body], body: &str, primary: &str, gravity: f64) -> Result<(), Box<dyn Error>> {
+    let index = |name: &str| {
+        bodies
+            .iter()
+            .position(|b| b.name == name)
+            .ok_or_else(|| format!("no body named '{}' in the scenario", name))
+    };
+    let (i, j) = (index(body)?, index(primary)?);
+    if i == j {
+        return Err(format!("'{}' cannot orbit itself", body).into());
+    }
+    let offset = bodies[i].position - bodies[j].position;
+    let distance = offset.norm();
+    if distance == 0.0 {
+        return Err(format!("'{}' is at the position of '{}'", body, primary).into());
+    }
+    let relative = bodies[i].velocity - bodies[j].velocity;
+    let moving = relative.cross(&offset);
+    let normal = if moving.norm() > 1e-12 * relative.norm() * distance {
+        offset.cross(&relative)
+    } else {
+        Vector::new(0.0, 0.0, 1.0)
+    };
+    let direction = normal.cross(&offset);
+    if direction.norm() <= 1e-12 * distance {
+        return Err(format!("'{}' is right above or below '{}'; give it a velocity to set its orbital plane", body, primary).into());
+    }
+    let speed = (gravity * (bodies[i].mass + bodies[j].mass) / distance).sqrt();
+    bodies[i].velocity = bodies[j].velocity + direction * (speed / direction.norm());
+    Ok(())
+}
+
+fn inside_unit_sphere(rng: &mut StdRng) -> Vector {
+    loop {
+        let v = Vector {
+            x: rng.random_range(-1.0..=1.0),
+            y: rng.random_range(-1.0..=1.0),
+            z: rng.random_range(-1.0..=1.0),
+        };
+        if v.x * v.x + v.y * v.y + v.z * v.z <= 1.0 {
+            return v;
+        }
+    }
+}
+
+fn on_unit_sphere(rng: &mut StdRng) -> Vector {
+    loop {
+        let v = inside_unit_sphere(rng);
+        if v.norm() > 1e-6 {
+            return v / v.norm();
+        }
+    }
+}
+
+fn body(name: &str, mass: f64, position: Vector, velocity: Vector, group: &str) -> Body {
+    Body {
+        name: name.to_string(),
+        mass,
+        position,
+        velocity,
+        acceleration: Vector::null(),
+        tags: Tags::from([("group".to_string(), group.to_string())]),
+        temperature: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const G: f64 = 6.67430e-11;
+
+    #[test]
+    fn test_clump_is_inbound_at_the_start_distance() {
+        let setup = TidalDisruption {
+            start_distance: Some(1e8),
+            ring_particles: 10,
+            ..TidalDisruption::default()
+        };
+
+        let bodies = setup.bodies(G).unwrap();
+
+        assert_eq!(bodies.len(), 1 + 200 + 10);
+        let rubble: Vec<&Body> = bodies.iter().filter(|b| b.tags["group"] == "rubble").collect();
+        let n = rubble.len() as f64;
+        let center = rubble.iter().fold(Vector::null(), |c, b| Vector {
+            x: c.x + b.position.x / n,
+            y: c.y + b.position.y / n,
+            z: c.z + b.position.z / n,
+        });
+        let distance = (center.x.powi(2) + center.y.powi(2) + center.z.powi(2)).sqrt();
+        assert!((distance - 1e8).abs() < 5e3, "{}", distance);
+        // Moving towards the planet.
+        let v = &rubble[0].velocity;
+        assert!(center.x * v.x + center.y * v.y + center.z * v.z < 0.0);
+        let mass: f64 = rubble.iter().map(|b| b.mass).sum();
+        assert!((mass - 1e18).abs() < 1e6);
+    }
+
+    #[test]
+    fn test_main_belt_leaves_the_kirkwood_gaps_empty() {
+        let sun = body("Sun", 1.989e30, Vector::null(), Vector::null(), "star");
+        // Jupiter on a circular orbit at 5.2 AU.
+        let jupiter_speed = (G * 1.989e30 / (5.2 * AU)).sqrt();
+        let jupiter = body("Jupiter", 1.898e27, Vector::new(5.2 * AU, 0.0, 0.0), Vector::new(0.0, jupiter_speed, 0.0), "planet");
+        let belt = Population::main_belt(2000);
+
+        let asteroids = belt.bodies(&[sun.clone(), jupiter], G).unwrap();
+
+        assert_eq!(asteroids.len(), 2000);
+        assert_eq!(asteroids[0].name, "asteroid 1");
+        assert_eq!(asteroids[0].tags["group"], "asteroid");
+        let orbits: Vec<kepler::Elements> = asteroids
+            .iter()
+            .map(|asteroid| kepler::Elements::from_state(&asteroid.position, &asteroid.velocity, G * 1.989e30))
+            .collect();
+        let (inner, outer) = (2.1 * AU * (1.0 - 1e-9), 3.3 * AU * (1.0 + 1e-9));
+        assert!(orbits.iter().all(|orbit| (inner..=outer).contains(&orbit.semi_major_axis)));
+        // The 3:1 gap at 2.50 AU is empty, and its neighborhood is not.
+        let near = |a: f64, width: f64| orbits.iter().filter(|orbit| (orbit.semi_major_axis / AU - a).abs() < width).count();
+        assert_eq!(near(2.50, 0.02), 0);
+        assert!(near(2.40, 0.02) > 0);
+        let mean_eccentricity = orbits.iter().map(|orbit| orbit.eccentricity).sum::<f64>() / 2000.0;
+        // The mean of a Rayleigh distribution is its scale times √(π/2).
+        assert!((mean_eccentricity - 0.1 * (PI / 2.0).sqrt()).abs() < 0.01, "{}", mean_eccentricity);
+
+        // The gaps need Jupiter in the scenario; the Kuiper belt has none.
+        assert!(belt.bodies(std::slice::from_ref(&sun), G).is_err());
+        let kuiper = Population::kuiper_belt(10).bodies(&[sun], G).unwrap();
+        assert!(kuiper.iter().all(|kbo| kbo.position.norm() > 30.0 * AU));
+    }
+
+    #[test]
+    fn test_circular_velocities_of_a_planet_and_its_moon() {
+        let mut bodies = vec![
+            body("Sun", 1.989e30, Vector::null(), Vector::null(), "star"),
+            body("Earth", 5.972e24, Vector::new(AU, 0.0, 0.0), Vector::null(), "planet"),
+            // Moving mostly along z, so its orbit stands upright.
+            body("Moon", 7.342e22, Vector::new(AU, 3.844e8, 0.0), Vector::new(0.0, 1.0, 100.0), "moon"),
+        ];
+        circularize(&mut bodies, "Earth", "Sun", G).unwrap();
+        circularize(&mut bodies, "Moon", "Earth", G).unwrap();
+
+        let earth_speed = (G * (1.989e30 + 5.972e24) / AU).sqrt();
+        assert!((bodies[1].velocity - Vector::new(0.0, earth_speed, 0.0)).norm() < 1e-9);
+        let moon = bodies[2].velocity - bodies[1].velocity;
+        let moon_speed = (G * (5.972e24 + 7.342e22) / 3.844e8).sqrt();
+        assert!((moon - Vector::new(0.0, 0.0, moon_speed)).norm() < 1e-9, "{:?}", moon);
+        let orbit = kepler::Elements::from_state(&(bodies[2].position - bodies[1].position), &moon, G * (5.972e24 + 7.342e22));
+        assert!(orbit.eccentricity < 1e-12);
+
+        assert!(circularize(&mut bodies, "Moon", "Moon", G).is_err());
+        assert!(circularize(&mut bodies, "Mars", "Sun", G).is_err());
+    }
+
+    #[test]
+    fn test_roche_limit_and_reproducible_draws() {
+        let roche = roche_limit(5.972e24, 1e18, 5e4);
+        // About 3.5 Earth radii for a body of ~1900 kg/m³.
+        assert!((roche / 6.371e6 - 3.5).abs() < 0.1, "{}", roche);
+        let (first, second) = (TidalDisruption::default().bodies(G).unwrap(), TidalDisruption::default().bodies(G).unwrap());
+        assert_eq!(first[7].position.x, second[7].position.x);
+        assert_eq!(first[7].position.z, second[7].position.z);
+    }
+
+    #[test]
+    fn test_stream_cluster_is_in_equilibrium_on_its_orbit() {
+        let setup = TidalStream {
+            stars: 1000,
+            ..TidalStream::default()
+        };
+
+        let stars = setup.bodies(G).unwrap();
+
+        assert_eq!(stars.len(), 1000);
+        assert_eq!(stars[0].tags["group"], "cluster");
+        let mass: f64 = stars.iter().map(|star| star.mass).sum();
+        assert!((mass / setup.cluster_mass - 1.0).abs() < 1e-12);
+        let n = stars.len() as f64;
+        let center = stars.iter().fold(Vector::null(), |c, star| c + star.position / n);
+        let velocity = stars.iter().fold(Vector::null(), |v, star| v + star.velocity / n);
+        assert!((center - Vector::new(setup.distance, 0.0, 0.0)).norm() < 1e-6 * setup.distance);
+        let speed = 0.6 * setup.halo.circular_speed_at(setup.distance);
+        assert!((velocity - Vector::new(0.0, speed, 0.0)).norm() < 1e-6 * speed);
+
+        // Virial equilibrium, 2K = -W, up to sampling noise.
+        let kinetic: f64 = stars.iter().map(|star| 0.5 * star.mass * (star.velocity - velocity).norm_squared()).sum();
+        let mut potential = 0.0;
+        for (i, a) in stars.iter().enumerate() {
+            for b in &stars[i + 1..] {
+                potential -= G * a.mass * b.mass / (a.position - b.position).norm();
+            }
+        }
+        let ratio = 2.0 * kinetic / -potential;
+        assert!((ratio - 1.0).abs() < 0.15, "{}", ratio);
+        assert!(TidalStream { speed_fraction: 3.0, ..setup }.bodies(G).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use super::body::Vector;
+use std::error::Error;
+use std::f64::consts::PI;
+
+/// Uniform rotation of a central body about a fixed axis.
+#[derive(Debug, Clone, Copy)]
+pub struct Rotation {
+    /// Unit vectors of the body-fixed frame at time 0: `x` towards the prime
+    /// meridian on the equator and `z` along the spin axis.
+    x: Vector,
+    y: Vector,
+    z: Vector,
+    /// Spin rate in radians per second, counterclockwise about the axis.
+    rate: f64,
+}
+
+impl Rotation {
+    /// Rotation about `axis` (any length, in the simulation axes) once every
+    /// `period` seconds, negative for a retrograde spin. At time 0 the prime
+    /// meridian is `prime_meridian` degrees east of the simulation's x axis
+    /// projected on the equator (of its y axis for an axis along x).
+    pub fn new(axis: Vector, period: f64, prime_meridian: f64) -> Result<Self, Box<dyn Error>> {
+        if !(axis.norm() > 0.0 && axis.norm().is_finite()) {
+            return Err("the spin axis must be a nonzero vector".into());
+        }
+        if period == 0.0 || !period.is_finite() {
+            return Err(format!("the rotation period must be nonzero, got {}", period).into());
+        }
+        let z = axis / axis.norm();
+        let reference = if z.x.abs() < 0.9 {
+            Vector::new(1.0, 0.0, 0.0)
+        } else {
+            Vector::new(0.0, 1.0, 0.0)
+        };
+        let east = reference - z * reference.dot(&z);
+        let east = east / east.norm();
+        let north = z.cross(&east);
+        let (sin, cos) = prime_meridian.to_radians().sin_cos();
+        let x = east * cos + north * sin;
+        Ok(Rotation {
+            x,
+            y: z.cross(&x),
+            z,
+            rate: 2.0 * PI / period,
+        })
+    }
+
+    /// Latitude and east longitude, in degrees, of the point under `position`
+    /// (relative to the center of the body) at `time`.
+    pub fn geographic(&self, position: &Vector, time: f64) -> (f64, f64) {
+        let latitude = (position.dot(&self.z) / position.norm()).clamp(-1.0, 1.0).asin();
+        let longitude = position.dot(&self.y).atan2(position.dot(&self.x)) - self.rate * time;
+        (latitude.to_degrees(), wrap(longitude.to_degrees()))
+    }
+}
+
+/// `angle` in degrees, wrapped to [-180, 180).
+fn wrap(angle: f64) -> f64 {
+    (angle + 180.0).rem_euclid(360.0) - 180.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a_fixed_point_drifts_west_with_the_rotation() {
+        let day = 86164.0905;
+        let rotation = Rotation::new(Vector::new(0.0, 0.0, 2.0), day, 0.0).unwrap();
+        let above = Vector::new(7e6, 0.0, 7e6);
+
+        let (latitude, longitude) = rotation.geographic(&above, 0.0);
+        assert!((latitude - 45.0).abs() < 1e-9 && longitude.abs() < 1e-9);
+        let (latitude, longitude) = rotation.geographic(&above, day / 4.0);
+        assert!((latitude - 45.0).abs() < 1e-9 && (longitude + 90.0).abs() < 1e-9, "{}", longitude);
+
+        let shifted = Rotation::new(Vector::new(0.0, 0.0, 1.0), day, 30.0).unwrap();
+        assert!((shifted.geographic(&above, 0.0).1 + 30.0).abs() < 1e-9);
+        let retrograde = Rotation::new(Vector::new(0.0, 0.0, 1.0), -day, 0.0).unwrap();
+        assert!((retrograde.geographic(&above, day / 4.0).1 - 90.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_tilted_axes_measure_latitudes_from_their_equator() {
+        let rotation = Rotation::new(Vector::new(1.0, 0.0, 0.0), 1.0, 0.0).unwrap();
+        assert!((rotation.geographic(&Vector::new(5.0, 0.0, 0.0), 0.3).0 - 90.0).abs() < 1e-9);
+        let (latitude, longitude) = rotation.geographic(&Vector::new(0.0, 3.0, 0.0), 0.0);
+        assert!(latitude.abs() < 1e-9 && longitude.abs() < 1e-9);
+        assert!(Rotation::new(Vector::null(), 1.0, 0.0).is_err());
+        assert!(Rotation::new(Vector::new(0.0, 0.0, 1.0), 0.0, 0.0).is_err());
+    }
+}
@@ -0,0 +1,273 @@
+use super::dynamics::{simulate_with, Recording, SequentialWriter, Settings};
+use super::uncertainty::{cholesky, Uncertain};
+use super::Body;
+use rand::rngs::StdRng;
+use rayon::prelude::*;
+use rand::SeedableRng;
+use rand_distr::{Distribution, StandardNormal};
+use std::error::Error;
+use std::fmt;
+
+/// Normal quantile of the two-sided 95% confidence intervals.
+const Z_95: f64 = 1.959963984540054;
+
+/// Monte Carlo setup: which body may hit which, and how many draws to make.
+#[derive(Debug, Clone)]
+pub struct Campaign {
+    /// Uncertain initial state of the possible impactor.
+    pub impactor: Uncertain,
+    /// Index of the body that may be hit.
+    pub target: usize,
+    /// Distance between the centers counting as an impact, in meters.
+    pub radius: f64,
+    pub realizations: usize,
+    /// Realization `i` draws from a generator seeded with `seed + i`, so results
+    /// don't depend on how runs are spread over threads or shards.
+    pub seed: u64,
+    /// Only realizations `i` with `i % shard_count == shard_index` are
+    /// simulated, so the tasks of a job array can split a campaign.
+    pub shard_index: usize,
+    pub shard_count: usize,
+}
+
+/// Outcome of one realization.
+#[derive(Debug, Clone, Copy)]
+pub struct Realization {
+    /// Number of the realization in the campaign.
+    pub index: usize,
+    /// Time of the first step inside the impact radius.
+    pub impact_time: Option<f64>,
+    /// Smallest distance to the target over the steps simulated.
+    pub closest_approach: f64,
+}
+
+/// Impact probability estimated from the realizations of a campaign's shard.
+#[derive(Debug, Clone)]
+pub struct Estimate {
+    pub realizations: Vec<Realization>,
+    pub impacts: usize,
+    pub probability: f64,
+    /// Wilson score interval at 95% confidence, which stays meaningful when
+    /// no realization (or every one) impacts.
+    pub interval: (f64, f64),
+}
+
+/// Draws initial states of the impactor from its Gaussian uncertainty and
+/// simulates each with the full N-body dynamics, counting those that come
+/// within `radius` of the target. A realization stops at its impact.
+/// Realizations run in parallel on the threads of the rayon pool.
+pub fn estimate(bodies: &[Body], settings: &Settings, campaign: &Campaign) -> Result<Estimate, Box<dyn Error>> {
+    let (body, target) = (campaign.impactor.body, campaign.target);
+    if body >= bodies.len() || target >= bodies.len() || body == target {
+        return Err(format!("invalid impactor {} and target {} for {} bodies", body, target, bodies.len()).into());
+    }
+    if campaign.realizations == 0 {
+        return Err("at least one realization is needed".into());
+    }
+    let (shard, shards) = (campaign.shard_index, campaign.shard_count);
+    if shards == 0 || shard >= shards {
+        return Err(format!("shard index {} is out of range for {} shards", shard, shards).into());
+    }
+    if shard >= campaign.realizations {
+        return Err(format!("shard {} of {} has no realizations", shard, shards).into());
+    }
+    let covariance: Vec<Vec<f64>> = campaign.impactor.covariance.iter().map(|row| row.to_vec()).collect();
+    let factor = cholesky(&covariance)?;
+    let settings = Settings {
+        // Impacts are checked on every step.
+        recording: Recording::Interval(settings.dt),
+        ..settings.for_copies()?
+    };
+
+    let realizations = (shard..campaign.realizations)
+        .into_par_iter()
+        .step_by(shards)
+        .map(|i| {
+            let mut rng = StdRng::seed_from_u64(campaign.seed.wrapping_add(i as u64));
+            let normal: Vec<f64> = (0..6).map(|_| StandardNormal.sample(&mut rng)).collect();
+            let offset: Vec<f64> = factor
+                .iter()
+                .map(|row| row.iter().zip(&normal).map(|(l, z)| l * z).sum())
+                .collect();
+
+            let mut realization = bodies.to_vec();
+            let impactor = &mut realization[body];
+            impactor.position.x += offset[0];
+            impactor.position.y += offset[1];
+            impactor.position.z += offset[2];
+            impactor.velocity.x += offset[3];
+            impactor.velocity.y += offset[4];
+            impactor.velocity.z += offset[5];
+
+            let mut detector = Detector {
+                body,
+                target,
+                radius: campaign.radius,
+                outcome: Realization {
+                    index: i,
+                    impact_time: None,
+                    closest_approach: f64::INFINITY,
+                },
+            };
+            match simulate_with(&mut realization, &settings, &mut detector) {
+                Err(e) if !e.is::<Impacted>() => Err(e.to_string()),
+                _ => Ok(detector.outcome),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let impacts = realizations.iter().filter(|r| r.impact_time.is_some()).count();
+    let probability = impacts as f64 / realizations.len() as f64;
+    Ok(Estimate {
+        interval: wilson_interval(impacts, realizations.len()),
+        realizations,
+        impacts,
+        probability,
+    })
+}
+
+fn wilson_interval(successes: usize, trials: usize) -> (f64, f64) {
+    let n = trials as f64;
+    let p = successes as f64 / n;
+    let z2 = Z_95 * Z_95;
+    let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+    let half_width = Z_95 / (1.0 + z2 / n) * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+    ((center - half_width).max(0.0), (center + half_width).min(1.0))
+}
+
+/// Returned by the detector to stop a run at the impact.
+#[derive(Debug)]
+struct Impacted;
+
+impl fmt::Display for Impacted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "impact")
+    }
+}
+
+impl Error for Impacted {}
+
+/// Writer watching the distance between the impactor and the target.
+struct Detector {
+    body: usize,
+    target: usize,
+    radius: f64,
+    outcome: Realization,
+}
+
+impl SequentialWriter for Detector {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        let (a, b) = (&bodies[self.body].position, &bodies[self.target].position);
+        let distance = ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt();
+        self.outcome.closest_approach = self.outcome.closest_approach.min(distance);
+        if distance <= self.radius {
+            self.outcome.impact_time = Some(time);
+            return Err(Box::new(Impacted));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::{Tags, Vector};
+
+    fn body(name: &str, x: f64, vx: f64) -> Body {
+        Body {
+            name: name.to_string(),
+            mass: 1.0,
+            position: Vector { x, y: 0.0, z: 0.0 },
+            velocity: Vector { x: vx, y: 0.0, z: 0.0 },
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    fn campaign(sigma: f64) -> Campaign {
+        Campaign {
+            impactor: Uncertain::diagonal(1, sigma, 0.0),
+            target: 0,
+            radius: 1.0,
+            realizations: 200,
+            seed: 7,
+            shard_index: 0,
+            shard_count: 1,
+        }
+    }
+
+    #[test]
+    fn test_head_on_approach_always_impacts() {
+        let bodies = [body("Target", 0.0, 0.0), body("Rock", 10.0, -2.0)];
+        let settings = Settings {
+            gravity: 0.0,
+            total_time: 10.0,
+            dt: 0.1,
+            ..Settings::default()
+        };
+
+        let estimate = estimate(&bodies, &settings, &campaign(0.1)).unwrap();
+
+        assert_eq!(estimate.impacts, 200);
+        assert_eq!(estimate.probability, 1.0);
+        // Stopped at the impact, about 4.5 time units in.
+        let time = estimate.realizations[0].impact_time.unwrap();
+        assert!((4.0..5.0).contains(&time), "{}", time);
+        assert!(estimate.interval.0 > 0.95 && estimate.interval.1 == 1.0);
+    }
+
+    #[test]
+    fn test_partial_impacts_are_reproducible() {
+        // Sideways offsets of a few meters decide between hit and miss.
+        let mut rock = body("Rock", 10.0, -2.0);
+        rock.position.y = 0.5;
+        let bodies = [body("Target", 0.0, 0.0), rock];
+        let settings = Settings {
+            gravity: 0.0,
+            total_time: 10.0,
+            dt: 0.05,
+            ..Settings::default()
+        };
+
+        let first = estimate(&bodies, &settings, &campaign(3.0)).unwrap();
+        let second = estimate(&bodies, &settings, &campaign(3.0)).unwrap();
+
+        assert!(first.impacts > 0 && first.impacts < 200, "{}", first.impacts);
+        assert_eq!(first.impacts, second.impacts);
+        assert!(first.interval.0 < first.probability && first.probability < first.interval.1);
+    }
+
+    #[test]
+    fn test_shards_split_the_realizations() {
+        let mut rock = body("Rock", 10.0, -2.0);
+        rock.position.y = 0.5;
+        let bodies = [body("Target", 0.0, 0.0), rock];
+        let settings = Settings {
+            gravity: 0.0,
+            total_time: 10.0,
+            dt: 0.05,
+            ..Settings::default()
+        };
+        let shard = |shard_index| Campaign {
+            shard_index,
+            shard_count: 3,
+            ..campaign(3.0)
+        };
+
+        let whole = estimate(&bodies, &settings, &campaign(3.0)).unwrap();
+        let shards: Vec<Estimate> = (0..3).map(|i| estimate(&bodies, &settings, &shard(i)).unwrap()).collect();
+
+        assert_eq!(shards.iter().map(|s| s.realizations.len()).collect::<Vec<_>>(), [67, 67, 66]);
+        assert_eq!(shards.iter().map(|s| s.impacts).sum::<usize>(), whole.impacts);
+        assert_eq!(shards[1].realizations[2].index, 7);
+        assert_eq!(shards[1].realizations[2].impact_time, whole.realizations[7].impact_time);
+        assert!(estimate(&bodies, &settings, &shard(3)).is_err());
+    }
+
+    #[test]
+    fn test_wilson_interval_without_impacts() {
+        let (low, high) = wilson_interval(0, 100);
+        assert!(low < 1e-12, "{}", low);
+        assert!(high > 0.03 && high < 0.04, "{}", high);
+    }
+}
@@ -0,0 +1,497 @@
+use super::body::{Tags, Vector};
+use super::forces::Forces;
+use super::interpolate::hermite_state;
+use super::patched_conics::{self, Hierarchy};
+use super::Body;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Time-stepping scheme used to advance the bodies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Integrator {
+    /// Semi-implicit Euler: velocities are kicked first, then positions drift.
+    #[default]
+    Euler,
+    /// Velocity Verlet (kick-drift-kick leapfrog): second order and
+    /// symplectic, so the energy error of orbits stays bounded, for one force
+    /// evaluation per step.
+    Verlet,
+    /// Classic fourth-order Runge-Kutta, with continuous (dense) output.
+    Rk4,
+    /// Two-body arcs about the body whose sphere of influence each body is
+    /// in: approximate, but exact for isolated orbits whatever the step.
+    PatchedConics,
+}
+
+impl Integrator {
+    /// Prepares the bodies before the first step.
+    pub fn initialize(&self, bodies: &mut [Body], forces: &Forces) {
+        match self {
+            Integrator::PatchedConics => {
+                let mut hierarchy = Hierarchy::default();
+                hierarchy.update(bodies);
+                patched_conics::accelerate(bodies, forces.gravity, &hierarchy);
+            }
+            _ => forces.accelerate(bodies),
+        }
+    }
+
+    /// Fails when the integrator can't follow the `forces`.
+    pub fn check(&self, forces: &Forces) -> Result<(), Box<dyn Error>> {
+        let plain = forces.periodic.is_none()
+            && forces.cutoff.is_none()
+            && forces.sph.is_none()
+            && forces.thermal.is_none()
+            && forces.tree.is_none()
+            && forces.workers.is_none()
+            && forces.far_field.is_none()
+            && forces.contacts.is_none()
+            && forces.halo.is_none()
+            && forces.mass_transfer.is_none()
+            && forces.script.is_none()
+            && forces.plugins.is_empty();
+        if *self == Integrator::PatchedConics && !plain {
+            return Err("patched conics only follow plain gravity in open space".into());
+        }
+        Ok(())
+    }
+
+    /// Integrator `body` follows: that of the first of `groups` whose tags it
+    /// carries, or this one.
+    pub fn of(&self, groups: &[(Tags, Integrator)], body: &Body) -> Integrator {
+        groups
+            .iter()
+            .find(|(tags, _)| body.has_tags(tags))
+            .map_or(*self, |(_, integrator)| *integrator)
+    }
+
+    /// Fails when an integrator of the run can't follow the `forces`, or can't
+    /// share its steps with the integrators of other groups.
+    pub fn check_groups(&self, groups: &[(Tags, Integrator)], forces: &Forces) -> Result<(), Box<dyn Error>> {
+        self.check(forces)?;
+        for (_, integrator) in groups {
+            integrator.check(forces)?;
+        }
+        let mut integrators = groups.iter().map(|(_, integrator)| integrator).chain([self]);
+        if !groups.is_empty() && integrators.any(|integrator| *integrator == Integrator::PatchedConics) {
+            return Err("patched conics can't share their steps with the integrators of other groups".into());
+        }
+        Ok(())
+    }
+
+    /// Like `step_in`, with the bodies of `groups` following their own
+    /// integrators.
+    ///
+    /// Bodies of different integrators share the force evaluations of each
+    /// step: when any of them follows RK4, the stages move every body and its
+    /// accelerations there are seen by all, but the Euler bodies only take the
+    /// kick and drift of the accelerations at the start of the step, and the
+    /// Verlet bodies those and the accelerations at its end. Without RK4, the
+    /// step costs the single force evaluation at its end.
+    pub fn step_groups(
+        &self,
+        groups: &[(Tags, Integrator)],
+        bodies: &mut [Body],
+        forces: &Forces,
+        dt: f64,
+        workspace: &mut Workspace,
+    ) {
+        workspace.schemes.clear();
+        if !groups.is_empty() {
+            workspace.schemes.extend(bodies.iter().map(|body| self.of(groups, body)));
+        }
+        let first = workspace.schemes.first().copied().unwrap_or(*self);
+        if workspace.schemes.iter().all(|scheme| *scheme == first) {
+            workspace.schemes.clear();
+            first.step_in(bodies, forces, dt, workspace);
+        } else if workspace.schemes.contains(&Integrator::Rk4) {
+            rk4_step(bodies, forces, dt, workspace);
+        } else {
+            kick_drift_step(bodies, forces, dt, &workspace.schemes);
+        }
+    }
+
+    /// Advances the bodies by `dt`, leaving their accelerations consistent
+    /// with the new positions.
+    pub fn step(&self, bodies: &mut [Body], forces: &Forces, dt: f64) {
+        self.step_in(bodies, forces, dt, &mut Workspace::default());
+    }
+
+    /// Like `step`, keeping the intermediate values in `workspace`: once it has
+    /// grown to the number of bodies, steps don't allocate beyond what the
+    /// forces do.
+    pub fn step_in(&self, bodies: &mut [Body], forces: &Forces, dt: f64, workspace: &mut Workspace) {
+        match self {
+            Integrator::Euler => euler_step(bodies, forces, dt),
+            Integrator::Verlet => verlet_step(bodies, forces, dt),
+            Integrator::Rk4 => rk4_step(bodies, forces, dt, workspace),
+            Integrator::PatchedConics => patched_conics::step(
+                bodies,
+                forces.gravity,
+                dt,
+                &mut workspace.hierarchy,
+                &mut workspace.position,
+                &mut workspace.velocity,
+            ),
+        }
+    }
+
+    /// Whether states between two steps can be evaluated with [`dense_output`].
+    pub fn has_dense_output(&self) -> bool {
+        matches!(self, Integrator::Rk4)
+    }
+}
+
+/// Intermediate values of the steps of an integrator, kept between steps to
+/// reuse their memory.
+#[derive(Debug, Clone, Default)]
+pub struct Workspace {
+    position: Vec<Vector>,
+    velocity: Vec<Vector>,
+    /// Weighted sums of the stage derivatives.
+    dx: Vec<Vector>,
+    dv: Vec<Vector>,
+    /// Parents of the bodies in patched-conic steps.
+    hierarchy: Hierarchy,
+    /// Integrator of each body in steps mixing several, and the accelerations
+    /// the Euler and Verlet ones start from.
+    schemes: Vec<Integrator>,
+    acceleration: Vec<Vector>,
+}
+
+impl fmt::Display for Integrator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Integrator::Euler => write!(f, "euler"),
+            Integrator::Verlet => write!(f, "verlet"),
+            Integrator::Rk4 => write!(f, "rk4"),
+            Integrator::PatchedConics => write!(f, "patched-conics"),
+        }
+    }
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "euler" => Ok(Integrator::Euler),
+            "verlet" => Ok(Integrator::Verlet),
+            "rk4" => Ok(Integrator::Rk4),
+            "patched-conics" => Ok(Integrator::PatchedConics),
+            other => Err(format!("unknown integrator '{}' (expected euler, verlet, rk4 or patched-conics)", other)),
+        }
+    }
+}
+
+/// Evaluates the state at fraction `theta` of a step of length `dt` that took
+/// the bodies from `start` to `end`.
+///
+/// Positions and velocities each follow the cubic Hermite interpolant of their
+/// values and derivatives at both ends of the step, which matches the order of
+/// the RK4 error within the step.
+pub fn dense_output(start: &[Body], end: &[Body], dt: f64, theta: f64) -> Vec<Body> {
+    let mut state = start.to_vec();
+    interpolate_step(&mut state, end, dt, theta);
+    state
+}
+
+/// Like [`dense_output`], moving `state`, a copy of the bodies at the start of
+/// the step, in place.
+pub fn interpolate_step(state: &mut [Body], end: &[Body], dt: f64, theta: f64) {
+    for (a, b) in state.iter_mut().zip(end) {
+        let (position, _) = hermite_state(&a.position, &a.velocity, &b.position, &b.velocity, dt, theta);
+        let (velocity, acceleration) = hermite_state(&a.velocity, &a.acceleration, &b.velocity, &b.acceleration, dt, theta);
+        a.position = position;
+        a.velocity = velocity;
+        a.acceleration = acceleration;
+        a.temperature = a.temperature.zip(b.temperature).map(|(a, b)| a + (b - a) * theta);
+    }
+}
+
+fn euler_step(bodies: &mut [Body], forces: &Forces, dt: f64) {
+    forces.accelerate(bodies);
+    update_velocity(bodies, dt);
+    update_position(bodies, dt);
+}
+
+/// Kicks the velocities by half a step with the accelerations left by the
+/// previous step (or `initialize`), drifts the positions by a whole step, and
+/// kicks again with the accelerations there.
+fn verlet_step(bodies: &mut [Body], forces: &Forces, dt: f64) {
+    update_velocity(bodies, dt / 2.0);
+    update_position(bodies, dt);
+    forces.accelerate(bodies);
+    update_velocity(bodies, dt / 2.0);
+}
+
+/// Steps Euler and Verlet bodies together, from the accelerations left by the
+/// previous step (or `initialize`) and those at the new positions.
+fn kick_drift_step(bodies: &mut [Body], forces: &Forces, dt: f64, schemes: &[Integrator]) {
+    for (body, scheme) in bodies.iter_mut().zip(schemes) {
+        let kick = if *scheme == Integrator::Verlet { dt / 2.0 } else { dt };
+        body.velocity += body.acceleration * kick;
+        body.position += body.velocity * dt;
+    }
+    forces.accelerate(bodies);
+    for (body, scheme) in bodies.iter_mut().zip(schemes) {
+        if *scheme == Integrator::Verlet {
+            body.velocity += body.acceleration * (dt / 2.0);
+        }
+    }
+}
+
+/// The stages are evaluated on the bodies themselves, whose start is kept in
+/// the workspace. Bodies whose scheme in the workspace is Euler or Verlet end
+/// up with a step of that scheme instead.
+fn rk4_step(bodies: &mut [Body], forces: &Forces, dt: f64, workspace: &mut Workspace) {
+    let Workspace {
+        position,
+        velocity,
+        dx,
+        dv,
+        schemes,
+        acceleration,
+        ..
+    } = workspace;
+    position.clear();
+    position.extend(bodies.iter().map(|b| b.position));
+    velocity.clear();
+    velocity.extend(bodies.iter().map(|b| b.velocity));
+    // k1 uses the accelerations left by the previous step (or `initialize`).
+    dx.clone_from(velocity);
+    dv.clear();
+    dv.extend(bodies.iter().map(|b| b.acceleration));
+    acceleration.clear();
+    if !schemes.is_empty() {
+        acceleration.extend_from_slice(dv);
+    }
+
+    for (h, weight) in [(dt / 2.0, 2.0), (dt / 2.0, 2.0), (dt, 1.0)] {
+        rk4_stage(bodies, position, velocity, h);
+        forces.accelerate(bodies);
+        for (i, body) in bodies.iter().enumerate() {
+            dx[i] += weight * body.velocity;
+            dv[i] += weight * body.acceleration;
+        }
+    }
+
+    for (i, body) in bodies.iter_mut().enumerate() {
+        match schemes.get(i) {
+            Some(Integrator::Euler) => {
+                body.velocity = velocity[i] + acceleration[i] * dt;
+                body.position = position[i] + body.velocity * dt;
+            }
+            // Kicked by the other half step below, at the new positions.
+            Some(Integrator::Verlet) => {
+                body.velocity = velocity[i] + acceleration[i] * (dt / 2.0);
+                body.position = position[i] + body.velocity * dt;
+            }
+            _ => {
+                body.position = position[i] + dx[i] * dt / 6.0;
+                body.velocity = velocity[i] + dv[i] * dt / 6.0;
+            }
+        }
+    }
+
+    forces.accelerate(bodies);
+    for (body, scheme) in bodies.iter_mut().zip(schemes.iter()) {
+        if *scheme == Integrator::Verlet {
+            body.velocity += body.acceleration * (dt / 2.0);
+        }
+    }
+}
+
+/// Moves the bodies to `start + h * (velocity, acceleration)`, with the
+/// derivatives of the previous stage.
+fn rk4_stage(bodies: &mut [Body], position: &[Vector], velocity: &[Vector], h: f64) {
+    for (i, body) in bodies.iter_mut().enumerate() {
+        body.position = position[i] + body.velocity * h;
+        body.velocity = velocity[i] + body.acceleration * h;
+    }
+}
+
+fn update_velocity(bodies: &mut [Body], dt: f64) {
+    for body in bodies.iter_mut() {
+        body.velocity.x += body.acceleration.x * dt;
+        body.velocity.y += body.acceleration.y * dt;
+        body.velocity.z += body.acceleration.z * dt;
+    }
+}
+
+fn update_position(bodies: &mut [Body], dt: f64) {
+    for body in bodies.iter_mut() {
+        body.position.x += body.velocity.x * dt;
+        body.position.y += body.velocity.y * dt;
+        body.position.z += body.velocity.z * dt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Tags;
+
+    const GRAVITY: f64 = 1.0;
+
+    // A massless probe on a circular orbit of radius 1 and period 2π around a unit mass.
+    fn circular_orbit() -> Vec<Body> {
+        vec![
+            Body {
+                name: "Star".to_string(),
+                mass: 1.0,
+                position: Vector::null(),
+                velocity: Vector::null(),
+                acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: None,
+            },
+            Body {
+                name: "Probe".to_string(),
+                mass: 1e-12,
+                position: Vector { x: 1.0, y: 0.0, z: 0.0 },
+                velocity: Vector { x: 0.0, y: 1.0, z: 0.0 },
+                acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: None,
+            },
+        ]
+    }
+
+    fn radius_error_after_one_orbit(integrator: Integrator) -> f64 {
+        let mut bodies = circular_orbit();
+        let steps = 1000;
+        let dt = 2.0 * std::f64::consts::PI / steps as f64;
+        integrator.initialize(&mut bodies, &Forces::newtonian(GRAVITY));
+        for _ in 0..steps {
+            integrator.step(&mut bodies, &Forces::newtonian(GRAVITY), dt);
+        }
+        let p = &bodies[1].position;
+        ((p.x * p.x + p.y * p.y).sqrt() - 1.0).abs()
+    }
+
+    #[test]
+    fn test_rk4_is_more_accurate_than_euler() {
+        let euler = radius_error_after_one_orbit(Integrator::Euler);
+        let rk4 = radius_error_after_one_orbit(Integrator::Rk4);
+        assert!(rk4 < 1e-9, "RK4 radius drift too large: {}", rk4);
+        assert!(rk4 < euler);
+    }
+
+    fn energy_drift_over_orbits(integrator: Integrator, orbits: usize) -> f64 {
+        // An orbit of eccentricity 0.5 about a unit mass, with period 2π.
+        let mut bodies = circular_orbit();
+        bodies[1].position = Vector::new(0.5, 0.0, 0.0);
+        bodies[1].velocity = Vector::new(0.0, 3f64.sqrt(), 0.0);
+        let energy = |bodies: &[Body]| 0.5 * bodies[1].velocity.norm_squared() - 1.0 / bodies[1].position.norm();
+        let forces = Forces::newtonian(GRAVITY);
+        let initial = energy(&bodies);
+        let dt = 2.0 * std::f64::consts::PI / 200.0;
+        let mut workspace = Workspace::default();
+        integrator.initialize(&mut bodies, &forces);
+        let mut drift: f64 = 0.0;
+        for _ in 0..200 * orbits {
+            integrator.step_in(&mut bodies, &forces, dt, &mut workspace);
+            drift = drift.max((energy(&bodies) / initial - 1.0).abs());
+        }
+        drift
+    }
+
+    #[test]
+    fn test_verlet_keeps_the_energy_bounded() {
+        let euler = radius_error_after_one_orbit(Integrator::Euler);
+        let verlet = radius_error_after_one_orbit(Integrator::Verlet);
+        assert!(verlet < 1e-4 && verlet < euler / 10.0, "{} vs {}", verlet, euler);
+
+        // The energy error of an eccentric orbit doesn't grow from orbit to orbit.
+        let short = energy_drift_over_orbits(Integrator::Verlet, 10);
+        let long = energy_drift_over_orbits(Integrator::Verlet, 100);
+        assert!(short < 1e-2 && long < 1.1 * short, "{} then {}", short, long);
+        assert!(energy_drift_over_orbits(Integrator::Euler, 100) > long);
+    }
+
+    #[test]
+    fn test_dense_output_matches_the_orbit_within_a_step() {
+        let mut bodies = circular_orbit();
+        let dt = 0.1;
+        Integrator::Rk4.initialize(&mut bodies, &Forces::newtonian(GRAVITY));
+        let start = bodies.clone();
+        Integrator::Rk4.step(&mut bodies, &Forces::newtonian(GRAVITY), dt);
+
+        let middle = dense_output(&start, &bodies, dt, 0.5);
+        let angle = dt / 2.0;
+        assert!((middle[1].position.x - angle.cos()).abs() < 1e-6);
+        assert!((middle[1].position.y - angle.sin()).abs() < 1e-6);
+        assert!((middle[1].velocity.x + angle.sin()).abs() < 1e-6);
+
+        let end = dense_output(&start, &bodies, dt, 1.0);
+        assert!((end[1].position.x - bodies[1].position.x).abs() < 1e-15);
+    }
+
+    #[test]
+    fn test_groups_follow_their_own_integrators() {
+        // Two probes on opposite sides of the same orbit, only one of them tagged for RK4.
+        let mut bodies = circular_orbit();
+        let mut fast = bodies[1].clone();
+        fast.name = "Fast probe".to_string();
+        fast.position = -fast.position;
+        fast.velocity = -fast.velocity;
+        fast.tags.insert("scheme".to_string(), "rk4".to_string());
+        bodies.push(fast);
+        let groups = [(Tags::from([("scheme".to_string(), "rk4".to_string())]), Integrator::Rk4)];
+        let forces = Forces::newtonian(GRAVITY);
+        let (mut euler, mut rk4) = (bodies.clone(), bodies.clone());
+        let mut workspace = Workspace::default();
+
+        Integrator::Euler.initialize(&mut bodies, &forces);
+        Integrator::Euler.initialize(&mut euler, &forces);
+        Integrator::Rk4.initialize(&mut rk4, &forces);
+        for _ in 0..100 {
+            Integrator::Euler.step_groups(&groups, &mut bodies, &forces, 0.01, &mut workspace);
+            Integrator::Euler.step(&mut euler, &forces, 0.01);
+            Integrator::Rk4.step(&mut rk4, &forces, 0.01);
+        }
+
+        assert_eq!(Integrator::Euler.of(&groups, &bodies[2]), Integrator::Rk4);
+        assert!((bodies[1].position - euler[1].position).norm() < 1e-12);
+        assert!((bodies[2].position - rk4[2].position).norm() < 1e-12);
+        assert!((euler[2].position - rk4[2].position).norm() > 1e-4);
+
+        // Verlet bodies step as they would alone when they share the RK4 stages.
+        let mut verlet = bodies.clone();
+        let mut mixed = bodies.clone();
+        let groups = [(Tags::from([("scheme".to_string(), "rk4".to_string())]), Integrator::Rk4)];
+        for _ in 0..100 {
+            Integrator::Verlet.step_groups(&groups, &mut mixed, &forces, 0.01, &mut workspace);
+            Integrator::Verlet.step(&mut verlet, &forces, 0.01);
+        }
+        assert!((mixed[1].position - verlet[1].position).norm() < 1e-12);
+        assert!((mixed[1].velocity - verlet[1].velocity).norm() < 1e-12);
+
+        // Without RK4, Euler and Verlet bodies step together on one force evaluation.
+        let (mut euler, mut verlet) = (bodies.clone(), bodies.clone());
+        let mut mixed = bodies.clone();
+        let groups = [(Tags::from([("scheme".to_string(), "rk4".to_string())]), Integrator::Verlet)];
+        for _ in 0..100 {
+            Integrator::Euler.step_groups(&groups, &mut mixed, &forces, 0.01, &mut workspace);
+            Integrator::Euler.step(&mut euler, &forces, 0.01);
+            Integrator::Verlet.step(&mut verlet, &forces, 0.01);
+        }
+        assert!((mixed[1].position - euler[1].position).norm() < 1e-12);
+        assert!((mixed[2].position - verlet[2].position).norm() < 1e-12);
+        assert!((mixed[2].velocity - verlet[2].velocity).norm() < 1e-12);
+        assert!((euler[2].position - verlet[2].position).norm() > 1e-6);
+        let patched = [(Tags::new(), Integrator::PatchedConics)];
+        assert!(Integrator::Euler.check_groups(&patched, &forces).is_err());
+        assert!(Integrator::Euler.check_groups(&groups, &forces).is_ok());
+    }
+
+    #[test]
+    fn test_integrator_names_round_trip() {
+        for integrator in [Integrator::Euler, Integrator::Verlet, Integrator::Rk4] {
+            assert_eq!(integrator.to_string().parse::<Integrator>().unwrap(), integrator);
+        }
+        assert!("leapfrog".parse::<Integrator>().is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use super::body::Vector;
+use super::reader::{Frame, SimulationReader};
+use super::Body;
+use std::error::Error;
+
+/// Cubic Hermite interpolation of the bodies between frames `a` and `b`.
+///
+/// Positions follow the cubic that matches the recorded positions and
+/// velocities at both frames; velocities are its derivative. Bodies are matched
+/// by name and those missing from `b` are left out.
+pub fn hermite(a: &Frame, b: &Frame, time: f64) -> Vec<Body> {
+    let h = b.time - a.time;
+    let s = if h == 0.0 { 0.0 } else { (time - a.time) / h };
+
+    matching_pairs(a, b)
+        .map(|(start, end)| {
+            let (position, velocity) =
+                hermite_state(&start.position, &start.velocity, &end.position, &end.velocity, h, s);
+            Body {
+                position,
+                velocity,
+                acceleration: Vector::null(),
+                ..start.clone()
+            }
+        })
+        .collect()
+}
+
+/// Linear interpolation of the bodies between frames `a` and `b`.
+///
+/// Used for recordings without velocities (schema version 1).
+pub fn linear(a: &Frame, b: &Frame, time: f64) -> Vec<Body> {
+    let h = b.time - a.time;
+    let s = if h == 0.0 { 0.0 } else { (time - a.time) / h };
+    let lerp = |p: f64, q: f64| p + (q - p) * s;
+
+    matching_pairs(a, b)
+        .map(|(start, end)| Body {
+            position: Vector {
+                x: lerp(start.position.x, end.position.x),
+                y: lerp(start.position.y, end.position.y),
+                z: lerp(start.position.z, end.position.z),
+            },
+            velocity: Vector {
+                x: lerp(start.velocity.x, end.velocity.x),
+                y: lerp(start.velocity.y, end.velocity.y),
+                z: lerp(start.velocity.z, end.velocity.z),
+            },
+            acceleration: Vector::null(),
+            ..start.clone()
+        })
+        .collect()
+}
+
+/// Evaluates the cubic Hermite segment from `(p0, v0)` to `(p1, v1)` spanning
+/// `h` seconds at the fraction `s` of the interval, returning position and velocity.
+pub fn hermite_state(p0: &Vector, v0: &Vector, p1: &Vector, v1: &Vector, h: f64, s: f64) -> (Vector, Vector) {
+    let s2 = s * s;
+    let s3 = s2 * s;
+
+    let h00 = 2.0 * s3 - 3.0 * s2 + 1.0;
+    let h10 = s3 - 2.0 * s2 + s;
+    let h01 = -2.0 * s3 + 3.0 * s2;
+    let h11 = s3 - s2;
+
+    // Derivatives of the basis with respect to time rather than `s`.
+    let (d00, d01) = if h == 0.0 {
+        (0.0, 0.0)
+    } else {
+        ((6.0 * s2 - 6.0 * s) / h, (-6.0 * s2 + 6.0 * s) / h)
+    };
+    let d10 = 3.0 * s2 - 4.0 * s + 1.0;
+    let d11 = 3.0 * s2 - 2.0 * s;
+
+    let position = |p0: f64, v0: f64, p1: f64, v1: f64| h00 * p0 + h10 * h * v0 + h01 * p1 + h11 * h * v1;
+    let velocity = |p0: f64, v0: f64, p1: f64, v1: f64| d00 * p0 + d10 * v0 + d01 * p1 + d11 * v1;
+
+    (
+        Vector {
+            x: position(p0.x, v0.x, p1.x, v1.x),
+            y: position(p0.y, v0.y, p1.y, v1.y),
+            z: position(p0.z, v0.z, p1.z, v1.z),
+        },
+        Vector {
+            x: velocity(p0.x, v0.x, p1.x, v1.x),
+            y: velocity(p0.y, v0.y, p1.y, v1.y),
+            z: velocity(p0.z, v0.z, p1.z, v1.z),
+        },
+    )
+}
+
+fn matching_pairs<'a>(a: &'a Frame, b: &'a Frame) -> impl Iterator<Item = (&'a Body, &'a Body)> {
+    a.bodies.iter().enumerate().filter_map(move |(i, start)| {
+        match b.bodies.get(i) {
+            Some(end) if end.name == start.name => Some(end),
+            _ => b.bodies.iter().find(|end| end.name == start.name),
+        }
+        .map(|end| (start, end))
+    })
+}
+
+/// Samples a recording at arbitrary, non-decreasing times.
+///
+/// Frames are streamed from the reader as needed, so a recording can be
+/// resampled without loading it whole. Cubic Hermite interpolation is used when
+/// the file stored velocities, linear interpolation otherwise.
+pub struct Sampler {
+    reader: SimulationReader,
+    previous: Option<Frame>,
+    next: Option<Frame>,
+    use_velocities: bool,
+}
+
+impl Sampler {
+    pub fn new(mut reader: SimulationReader) -> Result<Self, Box<dyn Error>> {
+        let use_velocities = reader.has_velocities();
+        let next = reader.next().transpose()?;
+        Ok(Sampler {
+            reader,
+            previous: None,
+            next,
+            use_velocities,
+        })
+    }
+
+    /// Time of the first frame that has not been passed yet.
+    pub fn start_time(&self) -> Option<f64> {
+        self.previous.as_ref().or(self.next.as_ref()).map(|frame| frame.time)
+    }
+
+    /// Bodies at `time`, or `None` once `time` is past the last recorded frame.
+    pub fn sample(&mut self, time: f64) -> Result<Option<Vec<Body>>, Box<dyn Error>> {
+        while self.next.as_ref().is_some_and(|frame| frame.time < time) {
+            self.previous = self.next.take();
+            self.next = self.reader.next().transpose()?;
+        }
+
+        match (&self.previous, &self.next) {
+            (_, Some(next)) if next.time == time => Ok(Some(next.bodies.clone())),
+            (Some(previous), Some(next)) if self.use_velocities => Ok(Some(hermite(previous, next, time))),
+            (Some(previous), Some(next)) => Ok(Some(linear(previous, next, time))),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Tags;
+
+    fn frame(time: f64, position: Vector, velocity: Vector) -> Frame {
+        Frame {
+            time,
+            bodies: vec![Body {
+                name: "Probe".to_string(),
+                mass: 1.0,
+                position,
+                velocity,
+                acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_hermite_reproduces_cubic_motion() {
+        // x(t) = t^3 - t, so v(t) = 3t^2 - 1
+        let x = |t: f64| t * t * t - t;
+        let v = |t: f64| 3.0 * t * t - 1.0;
+        let a = frame(1.0, Vector { x: x(1.0), y: 0.0, z: 0.0 }, Vector { x: v(1.0), y: 0.0, z: 0.0 });
+        let b = frame(3.0, Vector { x: x(3.0), y: 0.0, z: 0.0 }, Vector { x: v(3.0), y: 0.0, z: 0.0 });
+
+        for t in [1.0, 1.5, 2.25, 3.0] {
+            let bodies = hermite(&a, &b, t);
+            assert!((bodies[0].position.x - x(t)).abs() < 1e-9);
+            assert!((bodies[0].velocity.x - v(t)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_linear_interpolates_positions() {
+        let a = frame(0.0, Vector { x: 0.0, y: 10.0, z: 0.0 }, Vector::null());
+        let b = frame(10.0, Vector { x: 10.0, y: 0.0, z: 0.0 }, Vector::null());
+
+        let bodies = linear(&a, &b, 2.5);
+        assert_eq!(bodies[0].name, "Probe");
+        assert!((bodies[0].position.x - 2.5).abs() < 1e-12);
+        assert!((bodies[0].position.y - 7.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_bodies_are_matched_by_name() {
+        let mut a = frame(0.0, Vector::null(), Vector::null());
+        a.bodies.push(Body {
+            name: "Other".to_string(),
+            ..a.bodies[0].clone()
+        });
+        let mut b = frame(1.0, Vector { x: 1.0, y: 0.0, z: 0.0 }, Vector::null());
+        b.bodies.insert(0, Body {
+            name: "Other".to_string(),
+            position: Vector { x: -1.0, y: 0.0, z: 0.0 },
+            ..b.bodies[0].clone()
+        });
+
+        let bodies = linear(&a, &b, 0.5);
+        assert_eq!(bodies[0].name, "Probe");
+        assert!((bodies[0].position.x - 0.5).abs() < 1e-12);
+        assert!((bodies[1].position.x + 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_sampler_walks_through_a_recording() {
+        use crate::dynamics::SequentialWriter;
+        use crate::writer::Writer;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("sampled.parquet");
+        let mut writer = Writer::new(path.clone()).unwrap();
+        for time in [0.0, 10.0, 20.0] {
+            let state = frame(time, Vector { x: time, y: 0.0, z: 0.0 }, Vector { x: 1.0, y: 0.0, z: 0.0 });
+            writer.add(time, &state.bodies).unwrap();
+        }
+        writer.close().unwrap();
+
+        let mut sampler = Sampler::new(SimulationReader::open(&path).unwrap()).unwrap();
+        assert_eq!(sampler.start_time(), Some(0.0));
+        for t in [0.0, 2.5, 10.0, 17.0, 20.0] {
+            let bodies = sampler.sample(t).unwrap().unwrap();
+            assert!((bodies[0].position.x - t).abs() < 1e-9);
+        }
+        assert!(sampler.sample(20.5).unwrap().is_none());
+    }
+}
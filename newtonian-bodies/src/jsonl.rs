@@ -0,0 +1,203 @@
+use super::dynamics::SequentialWriter;
+use super::schema::{Layout, Precision};
+use super::Body;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// What each line of a JSON Lines output holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Grouping {
+    /// One body at one recorded time: `{"time":0,"name":"Earth",...}`.
+    #[default]
+    Body,
+    /// A whole frame: `{"time":0,"bodies":[{"name":"Earth",...},...]}`.
+    Frame,
+}
+
+impl FromStr for Grouping {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "body" => Ok(Grouping::Body),
+            "frame" => Ok(Grouping::Frame),
+            _ => Err(format!("expected body or frame, got '{}'", text)),
+        }
+    }
+}
+
+/// Writes frames as JSON Lines, with the field names of the Parquet columns
+/// (`time`, `name`, `mass`, `pos_x`, ..., `vel_z`, `temperature`) and the
+/// body's `tags` when it has any. Each line is flushed with its frame, so the
+/// output can be followed as the run goes (e.g., piped into `jq`).
+pub struct Writer {
+    out: BufWriter<Box<dyn Write + Send>>,
+    layout: Layout,
+    grouping: Grouping,
+    /// Line being built, reused from frame to frame.
+    line: String,
+}
+
+impl Writer {
+    /// Writes to the file at `path`, or to the standard output when it is `-`.
+    pub fn new(path: &Path, layout: Layout, grouping: Grouping) -> Result<Self, Box<dyn Error>> {
+        if layout.keyframe_interval.is_some() {
+            return Err("JSON Lines outputs keep absolute positions".into());
+        }
+        let out: Box<dyn Write + Send> = match path.to_str() {
+            Some("-") => Box::new(io::stdout()),
+            _ => Box::new(File::create(path)?),
+        };
+        Ok(Writer {
+            out: BufWriter::new(out),
+            layout,
+            grouping,
+            line: String::new(),
+        })
+    }
+
+    pub fn close(mut self) -> Result<(), Box<dyn Error>> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    /// Appends the fields of `body` to the line, after an opening brace.
+    fn push_body(&mut self, body: &Body) -> Result<(), Box<dyn Error>> {
+        let (line, layout) = (&mut self.line, &self.layout);
+        write!(line, "\"name\":{}", serde_json::to_string(&body.name)?)?;
+        if layout.mass {
+            write!(line, ",\"mass\":{}", number(body.mass, Precision::Double))?;
+        }
+        let mut fields = vec![("pos_x", body.position.x), ("pos_y", body.position.y), ("pos_z", body.position.z)];
+        if layout.velocities {
+            fields.extend([("vel_x", body.velocity.x), ("vel_y", body.velocity.y), ("vel_z", body.velocity.z)]);
+        }
+        for (field, value) in fields {
+            write!(line, ",\"{}\":{}", field, number(value, layout.precision))?;
+        }
+        if layout.temperature {
+            let temperature = body.temperature.map_or("null".to_string(), |t| number(t, Precision::Double));
+            write!(line, ",\"temperature\":{}", temperature)?;
+        }
+        if !body.tags.is_empty() {
+            write!(line, ",\"tags\":{}", serde_json::to_string(&body.tags)?)?;
+        }
+        line.push('}');
+        Ok(())
+    }
+}
+
+impl SequentialWriter for Writer {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        let time = number(time, Precision::Double);
+        self.line.clear();
+        match self.grouping {
+            Grouping::Body => {
+                for body in bodies {
+                    write!(self.line, "{{\"time\":{},", time)?;
+                    self.push_body(body)?;
+                    self.line.push('\n');
+                }
+            }
+            Grouping::Frame => {
+                write!(self.line, "{{\"time\":{},\"bodies\":[", time)?;
+                for (i, body) in bodies.iter().enumerate() {
+                    self.line.push_str(if i == 0 { "{" } else { ",{" });
+                    self.push_body(body)?;
+                }
+                self.line.push_str("]}\n");
+            }
+        }
+        self.out.write_all(self.line.as_bytes())?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// A value as a JSON number with the output's precision: the shortest text
+/// reading back as the same f64 (or f32), and `null` when it isn't finite.
+fn number(value: f64, precision: Precision) -> String {
+    let json = match precision {
+        Precision::Single => serde_json::to_string(&(value as f32)),
+        _ => serde_json::to_string(&precision.round(value)),
+    };
+    json.unwrap_or_else(|_| "null".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::{Tags, Vector};
+    use serde_json::{json, Value};
+
+    fn body(name: &str, x: f64) -> Body {
+        Body {
+            name: name.to_string(),
+            mass: 5.972e24,
+            position: Vector::new(x, 0.1, 0.0),
+            velocity: Vector::new(0.0, 29780.0, 0.0),
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    fn lines(path: &Path) -> Vec<Value> {
+        std::fs::read_to_string(path).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_a_line_per_body() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("orbits.jsonl");
+        let mut tagged = body("Probe \"1\"", 7e6);
+        tagged.tags.insert("category".to_string(), "spacecraft".to_string());
+
+        let mut writer = Writer::new(&path, Layout::default(), Grouping::Body).unwrap();
+        writer.add(0.0, &[body("Earth", 1.496e11), tagged]).unwrap();
+        writer.add(60.5, &[body("Earth", 1.5e11)]).unwrap();
+        writer.close().unwrap();
+
+        let lines = lines(&path);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            json!({"time": 0.0, "name": "Earth", "mass": 5.972e24, "pos_x": 1.496e11, "pos_y": 0.1, "pos_z": 0.0,
+                   "vel_x": 0.0, "vel_y": 29780.0, "vel_z": 0.0})
+        );
+        assert_eq!(lines[1]["name"], "Probe \"1\"");
+        assert_eq!(lines[1]["tags"], json!({"category": "spacecraft"}));
+        assert_eq!(lines[2]["time"], 60.5);
+    }
+
+    #[test]
+    fn test_a_line_per_frame() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("frames.jsonl");
+        let layout = Layout {
+            precision: Precision::Single,
+            mass: false,
+            velocities: false,
+            ..Layout::default()
+        };
+
+        let mut writer = Writer::new(&path, layout, Grouping::Frame).unwrap();
+        writer.add(0.0, &[body("Earth", 1.0), body("Moon", 2.0)]).unwrap();
+        writer.add(1.0, &[]).unwrap();
+        writer.close().unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        // Single precision keeps the shortest text of the f32.
+        let first = r#"{"time":0.0,"bodies":[{"name":"Earth","pos_x":1.0,"pos_y":0.1,"pos_z":0.0},"#;
+        assert!(text.starts_with(first), "{}", text);
+        let lines = lines(&path);
+        assert_eq!(lines[0]["bodies"][1], json!({"name": "Moon", "pos_x": 2.0, "pos_y": 0.1, "pos_z": 0.0}));
+        assert_eq!(lines[1], json!({"time": 1.0, "bodies": []}));
+        assert!(Writer::new(&path, Layout { keyframe_interval: Some(10), ..layout }, Grouping::Frame).is_err());
+        assert_eq!("frame".parse(), Ok(Grouping::Frame));
+    }
+}
@@ -0,0 +1,726 @@
+use super::body::Vector;
+use std::error::Error;
+use std::f64::consts::PI;
+
+/// Iterations of the bisections and Newton solves below.
+const MAX_ITERATIONS: usize = 200;
+
+/// Advances a two-body state by `dt` seconds around a central mass with
+/// gravitational parameter `mu` (G·M), using universal variables so elliptic
+/// and hyperbolic orbits are handled alike. Position and velocity are relative
+/// to the central body.
+pub fn propagate(position: &Vector, velocity: &Vector, mu: f64, dt: f64) -> Result<(Vector, Vector), Box<dyn Error>> {
+    let r0 = position.norm();
+    let radial_velocity = position.dot(velocity) / r0;
+    // Reciprocal of the semi-major axis: positive for ellipses, negative for hyperbolas.
+    let alpha = 2.0 / r0 - velocity.norm_squared() / mu;
+    let sqrt_mu = mu.sqrt();
+
+    let mut chi = sqrt_mu * alpha.abs() * dt;
+    if alpha < 0.0 {
+        // Far along a hyperbola the guess above overflows; start from its
+        // asymptotic solution instead (Vallado, algorithm 8).
+        let a = 1.0 / alpha;
+        let direction = dt.signum();
+        let guess = direction
+            * (-a).sqrt()
+            * ((-2.0 * mu * alpha * dt) / (position.dot(velocity) + direction * (-mu * a).sqrt() * (1.0 - r0 * alpha))).ln();
+        if guess.is_finite() && guess != 0.0 {
+            chi = guess;
+        }
+    }
+    let mut converged = false;
+    for _ in 0..MAX_ITERATIONS {
+        let z = alpha * chi * chi;
+        let (c, s) = (stumpff_c(z), stumpff_s(z));
+        let f = r0 * radial_velocity / sqrt_mu * chi * chi * c + (1.0 - alpha * r0) * chi.powi(3) * s + r0 * chi
+            - sqrt_mu * dt;
+        let df = r0 * radial_velocity / sqrt_mu * chi * (1.0 - z * s) + (1.0 - alpha * r0) * chi * chi * c + r0;
+        let step = f / df;
+        chi -= step;
+        if step.abs() <= 1e-12 * chi.abs().max(1.0) {
+            converged = true;
+            break;
+        }
+    }
+    if !converged || !chi.is_finite() {
+        return Err(format!("Kepler's equation did not converge for a time of {} s", dt).into());
+    }
+
+    let z = alpha * chi * chi;
+    let (c, s) = (stumpff_c(z), stumpff_s(z));
+    let f = 1.0 - chi * chi / r0 * c;
+    let g = dt - chi.powi(3) * s / sqrt_mu;
+    let new_position = f * *position + g * *velocity;
+    let r = new_position.norm();
+    let f_dot = sqrt_mu / (r * r0) * (alpha * chi.powi(3) * s - chi);
+    let g_dot = 1.0 - chi * chi / r * c;
+    Ok((new_position, f_dot * *position + g_dot * *velocity))
+}
+
+/// State at `distance` from the central body on the way in to a periapsis
+/// state, and the time from there to the periapsis, found by running the
+/// two-body orbit back.
+pub fn before_periapsis(
+    position: &Vector,
+    velocity: &Vector,
+    mu: f64,
+    distance: f64,
+) -> Result<(Vector, Vector, f64), Box<dyn Error>> {
+    let periapsis = position.norm();
+    let alpha = 2.0 / periapsis - velocity.norm_squared() / mu;
+    // Bound orbits only go out to their apoapsis, half a period away.
+    let (apoapsis, half_period) = if alpha > 0.0 {
+        (2.0 / alpha - periapsis, PI / (alpha.powi(3) * mu).sqrt())
+    } else {
+        (f64::INFINITY, f64::INFINITY)
+    };
+    if !(distance > periapsis && distance < apoapsis) {
+        return Err(format!("the orbit never gets {} m away from its center", distance).into());
+    }
+    let distance_at = |time: f64| -> Result<f64, Box<dyn Error>> { Ok(propagate(position, velocity, mu, -time)?.0.norm()) };
+
+    let mut high = (periapsis / velocity.norm()).min(half_period);
+    while distance_at(high)? < distance {
+        high = (2.0 * high).min(half_period);
+    }
+    let mut low = 0.0;
+    for _ in 0..100 {
+        let middle = 0.5 * (low + high);
+        if distance_at(middle)? < distance {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    let time = 0.5 * (low + high);
+    let (position, velocity) = propagate(position, velocity, mu, -time)?;
+    Ok((position, velocity, time))
+}
+
+/// Hyperbolic flyby of a planet, from the velocity relative to the planet far
+/// before the encounter (v∞).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Flyby {
+    /// Angle between the incoming and outgoing v∞.
+    pub turn_angle: f64,
+    /// B-plane vector: from the planet to where the incoming asymptote crosses
+    /// the plane through the planet perpendicular to it.
+    pub b_vector: Vector,
+    /// Velocity relative to the planet far after the encounter.
+    pub outgoing: Vector,
+    /// State at the closest approach, relative to the planet.
+    pub periapsis_position: Vector,
+    pub periapsis_velocity: Vector,
+}
+
+/// Flyby of a planet with gravitational parameter `mu`, passing at
+/// `periapsis` from its center, arriving with `incoming` v∞.
+///
+/// `b_angle` places the B vector in the B-plane, measured from T = Ŝ × ẑ
+/// towards R = Ŝ × T, where Ŝ is the direction of the incoming v∞: 0 passes
+/// on the T side, turning the velocity within the xy plane.
+pub fn flyby(incoming: &Vector, mu: f64, periapsis: f64, b_angle: f64) -> Result<Flyby, Box<dyn Error>> {
+    let speed = incoming.norm();
+    if !(speed > 0.0 && periapsis > 0.0 && mu > 0.0) {
+        return Err("a flyby needs an incoming velocity, a periapsis and a planet's mass".into());
+    }
+    let s = *incoming / speed;
+    let t = s.cross(&Vector::new(0.0, 0.0, 1.0));
+    if t.norm() < 1e-12 {
+        return Err("the B-plane is undefined for an incoming velocity along z".into());
+    }
+    let t = t / t.norm();
+    let r = s.cross(&t);
+    let b_direction = b_angle.cos() * t + b_angle.sin() * r;
+
+    let bending = 1.0 + periapsis * speed * speed / mu;
+    let turn_angle = 2.0 * (1.0 / bending).asin();
+    let b = mu / (speed * speed) * (bending * bending - 1.0).sqrt();
+    // Gravity pulls the path towards the planet, away from the B vector.
+    let outgoing = speed * (turn_angle.cos() * s - turn_angle.sin() * b_direction);
+    // Periapsis is on the B side, halfway between the asymptotes.
+    let towards = s - outgoing / speed;
+    let along = s + outgoing / speed;
+    Ok(Flyby {
+        turn_angle,
+        b_vector: b * b_direction,
+        outgoing,
+        periapsis_position: periapsis * towards / towards.norm(),
+        periapsis_velocity: (speed * speed + 2.0 * mu / periapsis).sqrt() * along / along.norm(),
+    })
+}
+
+/// One solution of Lambert's problem: the velocities at both ends of a conic arc.
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub departure: Vector,
+    pub arrival: Vector,
+    /// Complete revolutions around the central body before arriving.
+    pub revolutions: u32,
+}
+
+/// Velocities at both ends of the prograde conic arc joining two positions in
+/// `time_of_flight` seconds (less than one revolution) around a central mass
+/// with gravitational parameter `mu`. "Prograde" means counterclockwise seen
+/// from +z.
+pub fn lambert(
+    start: &Vector,
+    end: &Vector,
+    time_of_flight: f64,
+    mu: f64,
+) -> Result<(Vector, Vector), Box<dyn Error>> {
+    let transfer = lambert_revolutions(start, end, time_of_flight, mu, 0)?
+        .pop()
+        .ok_or("no conic arc matches the time of flight")?;
+    Ok((transfer.departure, transfer.arrival))
+}
+
+/// Prograde arcs joining two positions in `time_of_flight` seconds after
+/// `revolutions` complete turns around the central mass.
+///
+/// Without revolutions there is a single arc. With them there are two, one on
+/// each side of the fastest arc with that many turns, and none when the time of
+/// flight is shorter than that fastest arc.
+pub fn lambert_revolutions(
+    start: &Vector,
+    end: &Vector,
+    time_of_flight: f64,
+    mu: f64,
+    revolutions: u32,
+) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    if time_of_flight <= 0.0 {
+        return Err(format!("time of flight must be positive, got {}", time_of_flight).into());
+    }
+    let problem = Problem::new(start, end, mu)?;
+    let flight_time = |z: f64| problem.flight_time(z);
+    let transfer = |z: f64| problem.transfer(z, revolutions);
+
+    // z is the square of the eccentric anomaly swept (times -1 for hyperbolas),
+    // so arcs with N revolutions have z between (2πN)² and (2π(N+1))².
+    let turn = 2.0 * PI;
+    let high = (turn * (revolutions + 1) as f64).powi(2) * (1.0 - 1e-12);
+    if revolutions == 0 {
+        // The time of flight grows monotonically from zero (where y vanishes)
+        // to infinity at z = 4π².
+        let mut low = -turn * turn;
+        while flight_time(low) > time_of_flight {
+            low *= 2.0;
+            if !low.is_finite() {
+                return Err("no conic arc matches the time of flight".into());
+            }
+        }
+        return Ok(vec![transfer(bisect(&flight_time, low, high, time_of_flight))]);
+    }
+
+    // The time of flight is infinite at both ends of the interval, with a
+    // single minimum in between: the fastest arc with this many revolutions.
+    let low = (turn * revolutions as f64).powi(2) * (1.0 + 1e-12);
+    let fastest = minimize(&flight_time, low, high);
+    if flight_time(fastest) > time_of_flight {
+        return Ok(Vec::new());
+    }
+    Ok(vec![
+        transfer(bisect(&|z| -flight_time(z), low, fastest, -time_of_flight)),
+        transfer(bisect(&flight_time, fastest, high, time_of_flight)),
+    ])
+}
+
+/// Geometry of a Lambert problem in the universal-variable formulation.
+struct Problem<'a> {
+    start: &'a Vector,
+    end: &'a Vector,
+    r1: f64,
+    r2: f64,
+    a: f64,
+    mu: f64,
+}
+
+impl<'a> Problem<'a> {
+    fn new(start: &'a Vector, end: &'a Vector, mu: f64) -> Result<Self, Box<dyn Error>> {
+        let (r1, r2) = (start.norm(), end.norm());
+        let cos_angle = (start.dot(end) / (r1 * r2)).clamp(-1.0, 1.0);
+        let mut angle = cos_angle.acos();
+        if cross_z(start, end) < 0.0 {
+            angle = 2.0 * PI - angle;
+        }
+        let a = angle.sin() * (r1 * r2 / (1.0 - cos_angle)).sqrt();
+        if !a.is_finite() || a.abs() < 1e-12 * (r1 + r2) {
+            return Err("the transfer plane is undefined for positions 0 or 180 degrees apart".into());
+        }
+        Ok(Problem { start, end, r1, r2, a, mu })
+    }
+
+    fn y(&self, z: f64) -> f64 {
+        self.r1 + self.r2 + self.a * (z * stumpff_s(z) - 1.0) / stumpff_c(z).sqrt()
+    }
+
+    fn flight_time(&self, z: f64) -> f64 {
+        let y = self.y(z);
+        if y < 0.0 {
+            return 0.0;
+        }
+        ((y / stumpff_c(z)).powf(1.5) * stumpff_s(z) + self.a * y.sqrt()) / self.mu.sqrt()
+    }
+
+    fn transfer(&self, z: f64, revolutions: u32) -> Transfer {
+        let y = self.y(z);
+        let f = 1.0 - y / self.r1;
+        let g = self.a * (y / self.mu).sqrt();
+        let g_dot = 1.0 - y / self.r2;
+        Transfer {
+            departure: (*self.end - f * *self.start) / g,
+            arrival: (g_dot * *self.end - *self.start) / g,
+            revolutions,
+        }
+    }
+}
+
+/// Hohmann transfer between two coplanar circular orbits: a half ellipse
+/// tangent to both, the cheapest two-burn transfer for radius ratios below
+/// about 11.9.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hohmann {
+    /// Change of speed along the motion at departure, negative towards a
+    /// lower orbit.
+    pub departure: f64,
+    /// Change of speed along the motion on arrival at the other orbit.
+    pub arrival: f64,
+    /// Half the period of the transfer ellipse.
+    pub time: f64,
+    /// Angle the target must lead the departing body by, in radians, to be
+    /// met on arrival; negative when it must trail.
+    pub phase_angle: f64,
+}
+
+impl Hohmann {
+    pub fn delta_v(&self) -> f64 {
+        self.departure.abs() + self.arrival.abs()
+    }
+}
+
+/// Hohmann transfer from a circular orbit of radius `from` to one of radius
+/// `to` around a central mass with gravitational parameter `mu`.
+pub fn hohmann(from: f64, to: f64, mu: f64) -> Result<Hohmann, Box<dyn Error>> {
+    if !(from > 0.0 && to > 0.0 && from.is_finite() && to.is_finite() && mu > 0.0) {
+        return Err(format!("the orbits need positive radii and a central mass, got {} and {} m", from, to).into());
+    }
+    let semi_major_axis = 0.5 * (from + to);
+    let time = PI * (semi_major_axis.powi(3) / mu).sqrt();
+    Ok(Hohmann {
+        departure: (mu / from).sqrt() * ((to / semi_major_axis).sqrt() - 1.0),
+        arrival: (mu / to).sqrt() * (1.0 - (from / semi_major_axis).sqrt()),
+        time,
+        // The target covers π minus this angle during the transfer.
+        phase_angle: PI - time * (mu / to.powi(3)).sqrt(),
+    })
+}
+
+/// Osculating Keplerian elements; angles are in radians.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Elements {
+    /// Negative for hyperbolic orbits.
+    pub semi_major_axis: f64,
+    pub eccentricity: f64,
+    pub inclination: f64,
+    /// Longitude of the ascending node, zero for orbits in the reference plane.
+    pub ascending_node: f64,
+    /// Measured from the ascending node, zero for circular orbits.
+    pub argument_of_periapsis: f64,
+    /// Hyperbolic mean anomaly for hyperbolic orbits.
+    pub mean_anomaly: f64,
+}
+
+impl Elements {
+    /// Osculating elements of a state relative to a central mass with
+    /// gravitational parameter `mu`.
+    pub fn from_state(position: &Vector, velocity: &Vector, mu: f64) -> Self {
+        let r = position.norm();
+        let v2 = velocity.norm_squared();
+        let h = position.cross(velocity);
+        let node = Vector { x: -h.y, y: h.x, z: 0.0 };
+        let e_vector = ((v2 - mu / r) * *position - position.dot(velocity) * *velocity) / mu;
+        let eccentricity = e_vector.norm();
+        let inclination = (h.z / h.norm()).clamp(-1.0, 1.0).acos();
+
+        // Angles about h, so they grow in the direction of motion.
+        let angle = |from: &Vector, to: &Vector| {
+            let sine = from.cross(to).dot(&h) / h.norm();
+            sine.atan2(from.dot(to)).rem_euclid(2.0 * PI)
+        };
+        let flat = node.norm() <= 1e-12 * h.norm();
+        let circular = eccentricity <= 1e-12;
+        let reference = if flat { Vector { x: 1.0, y: 0.0, z: 0.0 } } else { node };
+        let ascending_node = if flat { 0.0 } else { node.y.atan2(node.x).rem_euclid(2.0 * PI) };
+        let argument_of_periapsis = if circular { 0.0 } else { angle(&reference, &e_vector) };
+        let true_anomaly = if circular { angle(&reference, position) } else { angle(&e_vector, position) };
+
+        let mean_anomaly = if eccentricity < 1.0 {
+            let eccentric = 2.0 * (((1.0 - eccentricity) / (1.0 + eccentricity)).sqrt() * (true_anomaly / 2.0).tan()).atan();
+            (eccentric - eccentricity * eccentric.sin()).rem_euclid(2.0 * PI)
+        } else {
+            let hyperbolic =
+                2.0 * (((eccentricity - 1.0) / (eccentricity + 1.0)).sqrt() * (true_anomaly / 2.0).tan()).atanh();
+            eccentricity * hyperbolic.sinh() - hyperbolic
+        };
+
+        Elements {
+            semi_major_axis: 1.0 / (2.0 / r - v2 / mu),
+            eccentricity,
+            inclination,
+            ascending_node,
+            argument_of_periapsis,
+            mean_anomaly,
+        }
+    }
+
+    /// Longitude of periapsis, ϖ = Ω + ω.
+    pub fn longitude_of_periapsis(&self) -> f64 {
+        (self.ascending_node + self.argument_of_periapsis).rem_euclid(2.0 * PI)
+    }
+
+    /// Mean longitude, λ = ϖ + M.
+    pub fn mean_longitude(&self) -> f64 {
+        (self.longitude_of_periapsis() + self.mean_anomaly).rem_euclid(2.0 * PI)
+    }
+
+    /// Point of the orbit at a true anomaly, relative to the central body.
+    pub fn position_at(&self, true_anomaly: f64) -> Vector {
+        let (p, q) = self.axes();
+        let r = self.semi_latus_rectum() / (1.0 + self.eccentricity * true_anomaly.cos());
+        r * true_anomaly.cos() * p + r * true_anomaly.sin() * q
+    }
+
+    /// Position and velocity on an elliptic orbit at the mean anomaly, relative
+    /// to a central mass with gravitational parameter `mu`.
+    pub fn state(&self, mu: f64) -> (Vector, Vector) {
+        let e = self.eccentricity;
+        // Kepler's equation by Newton's method, from a start that converges for e < 1.
+        let mean = self.mean_anomaly.rem_euclid(2.0 * PI);
+        let mut eccentric = if e < 0.8 { mean } else { PI };
+        for _ in 0..50 {
+            let delta = (eccentric - e * eccentric.sin() - mean) / (1.0 - e * eccentric.cos());
+            eccentric -= delta;
+            if delta.abs() < 1e-15 {
+                break;
+            }
+        }
+        let true_anomaly = 2.0 * ((1.0 + e).sqrt() * (eccentric / 2.0).sin()).atan2((1.0 - e).sqrt() * (eccentric / 2.0).cos());
+        let (p, q) = self.axes();
+        let speed = (mu / self.semi_latus_rectum()).sqrt();
+        let velocity = -speed * true_anomaly.sin() * p + speed * (e + true_anomaly.cos()) * q;
+        (self.position_at(true_anomaly), velocity)
+    }
+
+    fn semi_latus_rectum(&self) -> f64 {
+        self.semi_major_axis * (1.0 - self.eccentricity * self.eccentricity)
+    }
+
+    /// Unit vectors towards the periapsis and 90° ahead of it.
+    fn axes(&self) -> (Vector, Vector) {
+        let (sin_node, cos_node) = self.ascending_node.sin_cos();
+        let (sin_periapsis, cos_periapsis) = self.argument_of_periapsis.sin_cos();
+        let (sin_inclination, cos_inclination) = self.inclination.sin_cos();
+        let p = Vector {
+            x: cos_node * cos_periapsis - sin_node * sin_periapsis * cos_inclination,
+            y: sin_node * cos_periapsis + cos_node * sin_periapsis * cos_inclination,
+            z: sin_periapsis * sin_inclination,
+        };
+        let q = Vector {
+            x: -cos_node * sin_periapsis - sin_node * cos_periapsis * cos_inclination,
+            y: -sin_node * sin_periapsis + cos_node * cos_periapsis * cos_inclination,
+            z: cos_periapsis * sin_inclination,
+        };
+        (p, q)
+    }
+
+    /// True anomaly along the orbit as `u` goes around [0, 2π); hyperbolic
+    /// orbits are swept back and forth within their asymptotes.
+    fn anomaly(&self, u: f64) -> f64 {
+        if self.eccentricity < 1.0 {
+            u
+        } else {
+            0.999 * (-1.0 / self.eccentricity).acos() * u.sin()
+        }
+    }
+}
+
+/// Points per orbit in the MOID search grid.
+const MOID_GRID: usize = 120;
+
+/// Grid minima refined by the MOID search, lowest first.
+const MOID_CANDIDATES: usize = 16;
+
+/// Minimum orbit intersection distance: the smallest distance between a point
+/// of one orbit and a point of the other, wherever the bodies are along them.
+/// Both orbits must be about the same central body.
+///
+/// The distance is sampled on a grid of anomalies, and the lowest local minima
+/// of the grid are refined by nested golden-section searches.
+pub fn moid(a: &Elements, b: &Elements) -> f64 {
+    let distance = |u: f64, v: f64| (a.position_at(a.anomaly(u)) - b.position_at(b.anomaly(v))).norm();
+    let step = 2.0 * PI / MOID_GRID as f64;
+    let grid: Vec<Vec<f64>> = (0..MOID_GRID)
+        .map(|i| (0..MOID_GRID).map(|j| distance(i as f64 * step, j as f64 * step)).collect())
+        .collect();
+    let at = |i: usize, j: usize, di: isize, dj: isize| {
+        let wrap = |k: usize, d: isize| (k as isize + d).rem_euclid(MOID_GRID as isize) as usize;
+        grid[wrap(i, di)][wrap(j, dj)]
+    };
+
+    let mut candidates: Vec<(usize, usize)> = (0..MOID_GRID)
+        .flat_map(|i| (0..MOID_GRID).map(move |j| (i, j)))
+        .filter(|&(i, j)| {
+            (-1..=1).all(|di| (-1..=1).all(|dj| grid[i][j] <= at(i, j, di, dj)))
+        })
+        .collect();
+    candidates.sort_by(|&(i, j), &(k, l)| grid[i][j].total_cmp(&grid[k][l]));
+    candidates
+        .into_iter()
+        .take(MOID_CANDIDATES)
+        .map(|(i, j)| {
+            let (u, v) = (i as f64 * step, j as f64 * step);
+            let closest = |u: f64| distance(u, minimize(&|v| distance(u, v), v - step, v + step));
+            closest(minimize(&closest, u - step, u + step))
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+/// Solves `f(z) = target` for an increasing `f` on `[low, high]`.
+fn bisect(f: &impl Fn(f64) -> f64, mut low: f64, mut high: f64, target: f64) -> f64 {
+    for _ in 0..MAX_ITERATIONS {
+        let middle = 0.5 * (low + high);
+        if f(middle) < target {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    0.5 * (low + high)
+}
+
+/// Minimum of a unimodal `f` on `[low, high]`, by golden-section search.
+fn minimize(f: &impl Fn(f64) -> f64, mut low: f64, mut high: f64) -> f64 {
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    for _ in 0..MAX_ITERATIONS {
+        if high - low <= f64::EPSILON * (low.abs() + high.abs()) {
+            break;
+        }
+        let left = high - ratio * (high - low);
+        let right = low + ratio * (high - low);
+        if f(left) < f(right) {
+            high = right;
+        } else {
+            low = left;
+        }
+    }
+    0.5 * (low + high)
+}
+
+fn stumpff_c(z: f64) -> f64 {
+    if z.abs() < 1e-6 {
+        0.5 - z / 24.0
+    } else if z > 0.0 {
+        (1.0 - z.sqrt().cos()) / z
+    } else {
+        ((-z).sqrt().cosh() - 1.0) / -z
+    }
+}
+
+fn stumpff_s(z: f64) -> f64 {
+    if z.abs() < 1e-6 {
+        1.0 / 6.0 - z / 120.0
+    } else if z > 0.0 {
+        let s = z.sqrt();
+        (s - s.sin()) / s.powi(3)
+    } else {
+        let s = (-z).sqrt();
+        (s.sinh() - s) / s.powi(3)
+    }
+}
+
+fn cross_z(a: &Vector, b: &Vector) -> f64 {
+    a.x * b.y - a.y * b.x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MU: f64 = 1.0;
+
+    fn assert_close(a: &Vector, b: &Vector, tolerance: f64) {
+        assert!((*a - *b).norm() < tolerance, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_circular_orbit_quarter_period() {
+        let position = Vector { x: 1.0, y: 0.0, z: 0.0 };
+        let velocity = Vector { x: 0.0, y: 1.0, z: 0.0 };
+
+        let (position, velocity) = propagate(&position, &velocity, MU, PI / 2.0).unwrap();
+
+        assert_close(&position, &Vector { x: 0.0, y: 1.0, z: 0.0 }, 1e-9);
+        assert_close(&velocity, &Vector { x: -1.0, y: 0.0, z: 0.0 }, 1e-9);
+    }
+
+    #[test]
+    fn test_lambert_recovers_propagated_arcs() {
+        let start = Vector { x: 1.0, y: 0.2, z: 0.1 };
+        // An ellipse and a hyperbola, both prograde.
+        for velocity in [Vector { x: -0.1, y: 1.1, z: 0.0 }, Vector { x: 0.3, y: 1.6, z: 0.05 }] {
+            let (end, end_velocity) = propagate(&start, &velocity, MU, 2.0).unwrap();
+
+            let (departure, arrival) = lambert(&start, &end, 2.0, MU).unwrap();
+
+            assert_close(&departure, &velocity, 1e-8);
+            assert_close(&arrival, &end_velocity, 1e-8);
+        }
+    }
+
+    #[test]
+    fn test_lambert_with_revolutions() {
+        let start = Vector { x: 1.0, y: 0.0, z: 0.0 };
+        let velocity = Vector { x: 0.0, y: 1.1, z: 0.0 };
+        // One full period (about 8.95) plus a short arc.
+        let (end, end_velocity) = propagate(&start, &velocity, MU, 11.0).unwrap();
+
+        let transfers = lambert_revolutions(&start, &end, 11.0, MU, 1).unwrap();
+
+        assert_eq!(transfers.len(), 2);
+        let matching = transfers
+            .iter()
+            .filter(|t| (t.departure - velocity).norm() < 1e-8)
+            .collect::<Vec<_>>();
+        assert_eq!(matching.len(), 1);
+        assert_eq!(matching[0].revolutions, 1);
+        assert_close(&matching[0].arrival, &end_velocity, 1e-8);
+        // Every branch really takes that long.
+        for transfer in &transfers {
+            let (arrived, _) = propagate(&start, &transfer.departure, MU, 11.0).unwrap();
+            assert_close(&arrived, &end, 1e-7);
+        }
+        // Too little time for five revolutions.
+        assert!(lambert_revolutions(&start, &end, 11.0, MU, 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_elements_of_an_inclined_ellipse() {
+        // Periapsis at radius 1 on the ascending node, speed for a = 2.
+        let speed = (2.0f64 - 0.5).sqrt();
+        let tilt = 0.3f64;
+        let position = Vector { x: 1.0, y: 0.0, z: 0.0 };
+        let velocity = Vector { x: 0.0, y: speed * tilt.cos(), z: speed * tilt.sin() };
+
+        let elements = Elements::from_state(&position, &velocity, MU);
+
+        assert!((elements.semi_major_axis - 2.0).abs() < 1e-12);
+        assert!((elements.eccentricity - 0.5).abs() < 1e-12);
+        assert!((elements.inclination - tilt).abs() < 1e-12);
+        assert!(elements.ascending_node.abs() < 1e-12);
+        assert!(elements.argument_of_periapsis.abs() < 1e-9 || (elements.argument_of_periapsis - 2.0 * PI).abs() < 1e-9);
+        assert!(elements.mean_anomaly.abs() < 1e-9 || (elements.mean_anomaly - 2.0 * PI).abs() < 1e-9);
+
+        // A quarter period later the mean anomaly has grown by π/2.
+        let period = 2.0 * PI * 2f64.powf(1.5);
+        let (position, velocity) = propagate(&position, &velocity, MU, period / 4.0).unwrap();
+        let later = Elements::from_state(&position, &velocity, MU);
+        assert!((later.mean_anomaly - PI / 2.0).abs() < 1e-9, "{:?}", later);
+        assert!((later.mean_longitude() - PI / 2.0).abs() < 1e-9);
+
+        assert_close(&elements.position_at(0.0), &Vector { x: 1.0, y: 0.0, z: 0.0 }, 1e-12);
+        assert_close(&elements.position_at(PI), &Vector { x: -3.0, y: 0.0, z: 0.0 }, 1e-12);
+        let side = Vector { x: 0.0, y: 1.5 * tilt.cos(), z: 1.5 * tilt.sin() };
+        assert_close(&elements.position_at(PI / 2.0), &side, 1e-12);
+
+        // The state at a mean anomaly has the same elements back.
+        for e in [0.0, 0.5, 0.95] {
+            let elements = Elements {
+                semi_major_axis: 2.0,
+                eccentricity: e,
+                inclination: 0.3,
+                ascending_node: 1.0,
+                argument_of_periapsis: 2.0,
+                mean_anomaly: 4.0,
+            };
+            let (position, velocity) = elements.state(MU);
+            let back = Elements::from_state(&position, &velocity, MU);
+            assert!((back.semi_major_axis - 2.0).abs() < 1e-12 && (back.eccentricity - e).abs() < 1e-12, "{:?}", back);
+            assert!((back.mean_longitude() - elements.mean_longitude()).abs() < 1e-9, "{:?}", back);
+        }
+    }
+
+    #[test]
+    fn test_hohmann_from_low_orbit_to_geostationary() {
+        let earth = 3.986004418e14;
+        let up = hohmann(6678e3, 42164e3, earth).unwrap();
+        assert!((up.departure - 2425.769).abs() < 1e-3, "{:?}", up);
+        assert!((up.arrival - 1466.839).abs() < 1e-3, "{:?}", up);
+        assert!((up.time / 3600.0 - 5.27501).abs() < 1e-5, "{:?}", up);
+        assert!((up.phase_angle.to_degrees() - 100.6577).abs() < 1e-4, "{:?}", up);
+
+        // The way down takes the same burns, backwards.
+        let down = hohmann(42164e3, 6678e3, earth).unwrap();
+        assert!((down.departure + up.arrival).abs() < 1e-9 && (down.arrival + up.departure).abs() < 1e-9);
+        assert_eq!(down.delta_v(), up.delta_v());
+        assert!(hohmann(0.0, 1.0, earth).is_err());
+    }
+
+    #[test]
+    fn test_flyby_turns_the_velocity_away_from_the_b_vector() {
+        let incoming = Vector::new(5.0, 0.0, 0.0);
+        let flyby = flyby(&incoming, MU, 0.1, 0.0).unwrap();
+        // sin(δ/2) = 1 / (1 + rp v∞² / μ)
+        assert!(((flyby.turn_angle / 2.0).sin() - 1.0 / 3.5).abs() < 1e-12);
+        assert!((flyby.outgoing.norm() - 5.0).abs() < 1e-12);
+        // T = x × z = -y, so the path passes on -y and bends towards +y.
+        assert!(flyby.b_vector.y < 0.0 && flyby.outgoing.y > 0.0 && flyby.outgoing.z.abs() < 1e-12);
+
+        // Coming from far away along the incoming asymptote, it leaves along the outgoing one.
+        let (start, start_velocity, time) =
+            before_periapsis(&flyby.periapsis_position, &flyby.periapsis_velocity, MU, 1e4).unwrap();
+        assert!((start.norm() - 1e4).abs() < 1e-6);
+        assert!((start_velocity / start_velocity.norm() - incoming / 5.0).norm() < 1e-4);
+        let (_, end_velocity) = propagate(&start, &start_velocity, MU, 2.0 * time).unwrap();
+        assert!((end_velocity / end_velocity.norm() - flyby.outgoing / 5.0).norm() < 1e-4);
+        // The asymptote misses the planet by the length of the B vector.
+        let miss = (start - start.dot(&incoming) / 25.0 * incoming).norm();
+        assert!((miss - flyby.b_vector.norm()).abs() < 1e-3 * miss, "{} {}", miss, flyby.b_vector.norm());
+
+        // Bound orbits cannot start beyond their apoapsis.
+        let circular = (Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0));
+        assert!(before_periapsis(&circular.0, &circular.1, MU, 2.0).is_err());
+    }
+
+    #[test]
+    fn test_moid_of_crossing_and_separate_orbits() {
+        let orbit = |a: f64, e: f64, i: f64, node: f64| Elements {
+            semi_major_axis: a,
+            eccentricity: e,
+            inclination: i,
+            ascending_node: node,
+            argument_of_periapsis: 0.0,
+            mean_anomaly: 0.0,
+        };
+        let earth = orbit(1.0, 0.0, 0.0, 0.0);
+
+        // Concentric coplanar circles are as far apart as their radii.
+        assert!((moid(&earth, &orbit(1.5, 0.0, 0.0, 0.0)) - 0.5).abs() < 1e-9);
+        // An inclined ellipse with periapsis at radius 0.8 on its ascending
+        // node: the node at radius 0.8 gives the closest point.
+        let inclined = orbit(2.0, 0.6, 0.4, 1.0);
+        assert!((moid(&earth, &inclined) - 0.2).abs() < 1e-6, "{}", moid(&earth, &inclined));
+        // Raising the periapsis to the circle makes the orbits touch.
+        assert!(moid(&earth, &orbit(2.0, 0.5, 0.4, 1.0)) < 1e-6);
+        // A coplanar hyperbola with its periapsis inside the circle crosses it.
+        assert!(moid(&earth, &orbit(-1.0, 1.5, 0.0, 0.0)) < 1e-9);
+    }
+
+    #[test]
+    fn test_lambert_rejects_degenerate_transfers() {
+        let start = Vector { x: 1.0, y: 0.0, z: 0.0 };
+        let opposite = Vector { x: -2.0, y: 0.0, z: 0.0 };
+        assert!(lambert(&start, &opposite, 1.0, MU).is_err());
+        assert!(lambert(&start, &Vector { x: 0.0, y: 1.0, z: 0.0 }, 0.0, MU).is_err());
+    }
+}
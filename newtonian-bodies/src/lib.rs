@@ -0,0 +1,58 @@
+pub mod alignment;
+pub mod allocations;
+pub mod background;
+pub mod blender;
+pub mod body;
+pub mod cluster;
+pub mod collect;
+pub mod console;
+pub mod contact;
+pub mod distributed;
+pub mod determination;
+pub mod dynamics;
+pub mod fanout;
+pub mod far_field;
+pub mod forces;
+pub mod frequency;
+pub mod galaxy;
+pub mod generate;
+pub mod ground_track;
+pub mod gadget;
+pub mod impact;
+pub mod integrator;
+pub mod interpolate;
+pub mod jsonl;
+pub mod kepler;
+pub mod mass_transfer;
+pub mod memory;
+pub mod observation;
+pub mod pairs;
+pub mod partition;
+pub mod patched_conics;
+pub mod plugin;
+pub mod precision;
+pub mod query;
+pub mod reader;
+pub mod regularization;
+pub mod rebound;
+pub mod registry;
+pub mod scenario;
+pub mod schema;
+pub mod script;
+pub mod simulation;
+pub mod snapshot;
+pub mod spheres;
+pub mod spice;
+pub mod stability;
+pub mod sph;
+pub mod targeting;
+pub mod thermal;
+pub mod tipsy;
+pub mod transition;
+pub mod tree;
+pub mod uncertainty;
+pub mod vector;
+pub mod vtk;
+pub mod writer;
+
+pub use body::Body;
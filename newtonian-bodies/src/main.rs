@@ -1,79 +1,349 @@
-mod body;
-mod dynamics;
-mod writer;
+mod cli;
 
-use body::Body;
-use dynamics::simulate;
+use newtonian_solar_system::console::Console;
+use newtonian_solar_system::distributed::Workers;
+use newtonian_solar_system::dynamics::{simulate_with, Settings};
+use newtonian_solar_system::memory::MemoryUsage;
+use newtonian_solar_system::plugin::PluginObserver;
+use newtonian_solar_system::precision::Reference;
+use newtonian_solar_system::registry::{self, Registry, RunRecord, Summary};
+use newtonian_solar_system::scenario;
+use newtonian_solar_system::script::Script;
+use newtonian_solar_system::snapshot;
+use newtonian_solar_system::transition;
+use newtonian_solar_system::uncertainty;
+use newtonian_solar_system::Body;
 
-use clap::Parser;
-use serde_json;
+use clap::{Args, Parser, Subcommand};
+use cli::notify::{Notifier, Notifying};
+use cli::threads::Usage;
+use serde_json::json;
 use std::error::Error;
-use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of times the precision check compares the f64 run with its reference.
+const PRECISION_CHECKPOINTS: usize = 10;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
-struct Args {
-    /// JSON file with initial conditions
-    input: PathBuf,
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: RunArgs,
+
+    #[command(flatten)]
+    threads: cli::threads::ThreadArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run several scenario files with shared settings and print a summary
+    RunBatch(cli::batch::BatchArgs),
+    /// Export a recorded state or initial conditions to another format (e.g., REBOUND)
+    Convert(cli::convert::ConvertArgs),
+    /// Write a scenario with the states of solar-system bodies read from SPICE SPK kernels
+    Spice(cli::spice::SpiceArgs),
+    /// Analyses of a scenario (e.g., porkchop plots of transfers between two bodies)
+    Analyze(cli::analyze::AnalyzeArgs),
+    /// Adjust a body's initial state until its N-body trajectory reaches a goal
+    Target(cli::target::TargetArgs),
+    /// Fit a body's initial state to observations of it (ranges, range rates, angles)
+    Od(cli::od::OdArgs),
+    /// Map the chaos (MEGNO and FLI) of test-particle orbits over a grid of two orbital elements
+    Map(cli::map::MapArgs),
+    /// Write generated showcase scenarios (e.g., the tidal disruption of a rubble pile)
+    Generate(cli::generate::GenerateArgs),
+    /// Time the phases of the tree force evaluation on a scenario
+    Benchmark(cli::benchmark::BenchmarkArgs),
+    /// Sum the gravity on shares of the bodies for runs on other processes or nodes
+    Worker(cli::worker::WorkerArgs),
+    /// List or show the runs recorded in a registry (see --registry)
+    Runs(cli::runs::RunsArgs),
+    /// Run SQL against outputs with DuckDB (needs the duckdb feature)
+    Query(cli::query::QueryArgs),
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// File with initial conditions: JSON, CSV/TSV (name,mass,x,y,z,vx,vy,vz) or REBOUND .bin
+    #[arg(required_unless_present = "initial_from")]
+    input: Option<PathBuf>,
+
+    /// Start from the frame of an earlier run's output recorded closest to TIME
+    /// seconds (e.g., "orbits.parquet:t=3.15e7") instead of a scenario file
+    #[arg(long, value_name = "FILE:t=TIME", value_parser = cli::parse_initial_from, conflicts_with = "input")]
+    initial_from: Option<cli::InitialFrom>,
+
+    /// File to store results of the simulation, optionally followed by options for
+    /// this file only (e.g., "plot.csv,every=10,precision=f32"); repeat to write several
+    #[arg(short, long = "output", value_name = "FILE[,KEY=VALUE...]", default_value = "newtonian.parquet")]
+    outputs: Vec<cli::output::OutputSpec>,
+
+    #[command(flatten)]
+    settings: cli::SettingsArgs,
+
+    #[command(flatten)]
+    scenario: cli::ScenarioArgs,
+
+    #[command(flatten)]
+    output_options: cli::OutputArgs,
+
+    /// Also integrate the scenario in double-double precision (up to 16 bodies)
+    /// and report how far the f64 run drifts from it
+    #[arg(long)]
+    precision_check: bool,
 
-    /// File to store results of the simulation
-    #[arg(short, long, default_value = "newtonian.parquet")]
-    output: Option<PathBuf>,
+    /// Worker process (`newtonian-solar-system worker`) summing the gravity on a share
+    /// of the bodies (e.g., "node2:7878"); repeat for several
+    #[arg(long = "worker", value_name = "HOST:PORT")]
+    workers: Vec<String>,
 
-    /// Gravitational constant (e.g., "6.67430e-11")
-    #[arg(short, long, default_value = "6.67430e-11", value_parser = parse_expression)]
-    gravity: f64,
+    /// Seconds to wait on a worker for an answer before summing its share here
+    #[arg(long, default_value_t = 60.0, value_name = "SECONDS", requires = "workers")]
+    worker_timeout: f64,
 
-    /// Number of seconds to simulate (e.g., "60*60*24*365")
-    #[arg(short, long, default_value = "60*60*24*365", value_parser = parse_expression)]
-    total_time: f64,
+    /// Rhai script adding forces or handling events (see the README); overrides
+    /// the `script` of a JSON scenario
+    #[arg(long, value_name = "FILE")]
+    script: Option<PathBuf>,
 
-    /// Time step in seconds for finite difference method (e.g., "1.0 / 1000.0")
-    #[arg(short, long, default_value = "0.001", value_parser = parse_expression)]
-    delta_t: f64,
+    /// WebAssembly plugin adding forces or observing the frames (see the README),
+    /// optionally followed by the file its observer writes to (e.g.,
+    /// "sp3.wasm,output=orbits.sp3"); repeat for several
+    #[arg(long = "plugin", value_name = "FILE[,output=FILE]")]
+    plugins: Vec<cli::plugin::PluginSpec>,
 
-    /// Record every N seconds (e.g., "60*10")
-    #[arg(short, long, default_value = "1", value_parser = parse_expression_to_u32)]
-    record_interval: u64,
+    /// Answer commands about the running simulation on this address (e.g.,
+    /// "127.0.0.1:7879"): connect with `nc` and type `help`
+    #[arg(long, value_name = "HOST:PORT")]
+    console: Option<String>,
+
+    /// Hold the run before its first step until `resume` on the console
+    #[arg(long, requires = "console")]
+    console_paused: bool,
+
+    /// Record the run, its outputs and diagnostics in this registry directory
+    /// (list them with `runs list`)
+    #[arg(long, value_name = "DIR", num_args = 0..=1, default_missing_value = cli::runs::DEFAULT_REGISTRY)]
+    registry: Option<PathBuf>,
+
+    #[command(flatten)]
+    pairs: cli::PairArgs,
+
+    #[command(flatten)]
+    events: cli::EventArgs,
+
+    #[command(flatten)]
+    uncertainty: cli::UncertaintyArgs,
+
+    #[command(flatten)]
+    transition: cli::TransitionArgs,
+
+    #[command(flatten)]
+    notify: cli::notify::NotifyArgs,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let args = Args::parse();
-
-    let bodies = load_initial_conditions(&args.input)?;
-    let output_file = args
-        .output
-        .unwrap_or_else(|| PathBuf::from("newtonian.parquet"));
-    let mut writer = writer::Writer::new(output_file)?;
-    simulate(
-        &mut bodies.clone(),
-        args.gravity,
-        args.total_time,
-        args.delta_t,
-        args.record_interval,
-        &mut writer,
-    )?;
+    let args = Cli::parse();
+    cli::threads::configure(&args.threads)?;
 
-    writer.close()?;
-    Ok(())
+    match args.command {
+        Some(Command::RunBatch(batch)) => cli::batch::run(&batch),
+        Some(Command::Convert(convert)) => cli::convert::run(&convert),
+        Some(Command::Spice(spice)) => cli::spice::run(&spice),
+        Some(Command::Analyze(analyze)) => cli::analyze::run(&analyze),
+        Some(Command::Target(target)) => cli::target::run(&target),
+        Some(Command::Od(od)) => cli::od::run(&od),
+        Some(Command::Map(map)) => cli::map::run(&map),
+        Some(Command::Generate(generate)) => cli::generate::run(&generate),
+        Some(Command::Benchmark(benchmark)) => cli::benchmark::run(&benchmark),
+        Some(Command::Worker(worker)) => cli::worker::run(&worker),
+        Some(Command::Runs(runs)) => cli::runs::run(&runs),
+        Some(Command::Query(query)) => cli::query::run(&query),
+        None => run(args.run),
+    }
 }
 
-fn load_initial_conditions(file_path: &PathBuf) -> Result<Vec<Body>, Box<dyn Error>> {
-    let file = File::open(file_path)?;
-    let reader = BufReader::new(file);
-    let bodies: Vec<Body> = serde_json::from_reader(reader)?;
-    Ok(bodies)
+fn run(args: RunArgs) -> Result<(), Box<dyn Error>> {
+    let input = match &args.initial_from {
+        Some(initial_from) => initial_from.path.as_path(),
+        None => args.input.as_deref().ok_or("missing input file")?,
+    };
+    let mut settings = args.settings.settings();
+    if !args.workers.is_empty() {
+        let timeout = Duration::try_from_secs_f64(args.worker_timeout)
+            .map_err(|_| format!("the worker timeout must be positive, got {}", args.worker_timeout))?;
+        settings.workers = Some(Arc::new(Workers::connect_with_timeout(&args.workers, timeout)?));
+    }
+    let script = match (&args.script, &args.initial_from) {
+        (Some(script), _) => Some(script.clone()),
+        (None, Some(_)) => None,
+        (None, None) => scenario::script(input, &args.scenario.variables())?,
+    };
+    if let Some(script) = script {
+        settings.script = Some(Script::load(&script)?);
+    }
+    let observers = cli::plugin::load(&args.plugins, &mut settings)?;
+    if let Some(address) = &args.console {
+        let console = Console::listen(address, args.console_paused)?;
+        if let Some(address) = console.address() {
+            eprintln!("console listening on {}", address);
+        }
+        settings.console = Some(Arc::new(console));
+    }
+
+    let registry = args.registry.as_deref().map(Registry::open).transpose()?;
+
+    let notifier = Notifier::new(&args.notify);
+    let usage = Usage::start();
+    let mut record = RunRecord::start(input, &settings, args.outputs.iter().map(|output| output.path.clone()).collect());
+    let start = Instant::now();
+    let result = simulate_file(&args, input, &settings, observers, &notifier);
+    if result.is_ok() {
+        usage.report();
+    }
+    if let (Some(registry), Some(directory)) = (&registry, &args.registry) {
+        record.elapsed = start.elapsed().as_secs_f64();
+        if let Some(console) = &settings.console {
+            record.manifest.interventions = console.interventions();
+        }
+        match &result {
+            Ok(summary) => record.summary = Some(summary.clone()),
+            Err(e) => record.error = Some(e.to_string()),
+        }
+        let id = registry.record(record)?;
+        eprintln!("recorded as run {} in {}", id, directory.display());
+    }
+    match &result {
+        Ok(summary) => notifier.completed(json!({
+            "run": input.display().to_string(),
+            "frames": summary.frames,
+            "output": args
+                .outputs
+                .iter()
+                .map(|output| output.path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        })),
+        Err(e) => notifier.failed(e.as_ref()),
+    }
+    result.map(|_| ())
 }
 
-/// Parses a string expression (e.g., "60*60*24") into an f64 value.
-fn parse_expression(expr_str: &str) -> Result<f64, String> {
-    meval::eval_str(expr_str).map_err(|e| e.to_string())
+/// Bodies of a snapshot, warning when the run won't go on from it as it was:
+/// runs count their time from zero, with their own gravitational constant.
+fn resume(input: &Path, settings: &Settings) -> Result<Vec<Body>, Box<dyn Error>> {
+    let snapshot = snapshot::read(input)?;
+    if snapshot.time != 0.0 {
+        eprintln!(
+            "warning: {} was taken at {} s, and this run counts its time from 0",
+            input.display(),
+            snapshot.time
+        );
+    }
+    if let Some(gravity) = snapshot.gravity.filter(|&gravity| gravity != settings.gravity) {
+        eprintln!(
+            "warning: {} was run with G = {}, and this run uses {}",
+            input.display(),
+            gravity,
+            settings.gravity
+        );
+    }
+    Ok(snapshot.bodies)
 }
 
-fn parse_expression_to_u32(expr_str: &str) -> Result<u64, String> {
-    meval::eval_str(expr_str)
-        .map(|val: f64| val.round() as u64)
-        .map_err(|e| e.to_string())
+/// Runs one scenario into its outputs, returning its diagnostics.
+fn simulate_file(
+    args: &RunArgs,
+    input: &Path,
+    settings: &Settings,
+    observers: Vec<PluginObserver>,
+    notifier: &Notifier,
+) -> Result<Summary, Box<dyn Error>> {
+    let uncertainty = &args.uncertainty;
+    let mut bodies = match &args.initial_from {
+        Some(initial_from) => initial_from.bodies()?,
+        None if input.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("msgpack")) => resume(input, settings)?,
+        None => scenario::load_with(input, &args.scenario.variables())?,
+    };
+    let reference = if args.precision_check {
+        Some(Reference::new(&bodies, settings)?)
+    } else {
+        None
+    };
+    // Summing the energy is quadratic, so only for recorded runs.
+    let initial_energy = args.registry.as_ref().and_then(|_| registry::total_energy(&bodies, settings.gravity));
+    let uncertain = uncertainty.uncertain(&bodies)?;
+    let selected = args.transition.selected(&bodies)?;
+    let initial = (!uncertain.is_empty() || !selected.is_empty()).then(|| bodies.clone());
+    let observed: Vec<PathBuf> = observers.iter().map(|observer| observer.plugin().path().to_path_buf()).collect();
+    let (writer, state) = cli::open_writer(
+        &args.outputs,
+        &args.pairs,
+        &args.events,
+        settings,
+        &args.output_options,
+        &bodies,
+        observers,
+    )?;
+    let mut writer = Notifying::new(writer, notifier, input.display().to_string(), settings.total_time);
+
+    // The reference integration, the sigma points and the shifted states of
+    // the transition matrices run on their own threads alongside the simulation.
+    let (divergence, ellipsoids, transitions) = thread::scope(|scope| {
+        let check = reference.map(|reference| scope.spawn(|| reference.run(PRECISION_CHECKPOINTS)));
+        let spread = initial.as_ref().filter(|_| !uncertain.is_empty()).map(|initial| {
+            scope.spawn(|| uncertainty::propagate(initial, settings, &uncertain).map_err(|e| e.to_string()))
+        });
+        let sensitivity = initial.as_ref().filter(|_| !selected.is_empty()).map(|initial| {
+            scope.spawn(|| transition::propagate(initial, settings, &selected).map_err(|e| e.to_string()))
+        });
+        simulate_with(&mut bodies, settings, &mut writer)?;
+        let divergence = check.map(|check| check.join().expect("precision check panicked"));
+        let ellipsoids = spread
+            .map(|spread| spread.join().expect("uncertainty propagation panicked"))
+            .transpose()?;
+        let transitions = sensitivity
+            .map(|sensitivity| sensitivity.join().expect("transition matrices panicked"))
+            .transpose()?;
+        Ok::<_, Box<dyn Error>>((divergence, ellipsoids, transitions))
+    })?;
+
+    if let Some(workers) = &settings.workers {
+        for failure in workers.failures() {
+            eprintln!("{}; its share was summed here from then on", failure);
+        }
+    }
+    let frames = writer.frames();
+    let (peak_buffer, queues) = cli::close_outputs(writer.inner)?;
+    cli::report_memory(&MemoryUsage {
+        state,
+        writer: peak_buffer,
+    });
+    let mut files: Vec<&Path> = args.outputs.iter().map(|output| output.path.as_path()).collect();
+    if !args.pairs.pairs.is_empty() {
+        files.push(&args.pairs.pairs_output);
+    }
+    files.extend(args.events.soi_events.as_deref());
+    files.extend(args.events.transfer_events.as_deref());
+    files.extend(observed.iter().map(PathBuf::as_path));
+    cli::report_queue(&files, &queues);
+    if let Some(divergence) = divergence {
+        cli::report_precision(&divergence);
+    }
+    if let (Some(ellipsoids), Some(initial)) = (ellipsoids, &initial) {
+        uncertainty::write_csv(&uncertainty.uncertainty_output, &ellipsoids, initial)?;
+        eprintln!("uncertainty ellipsoids written to {}", uncertainty.uncertainty_output.display());
+    }
+    if let (Some(transitions), Some(initial)) = (transitions, &initial) {
+        transition::write_csv(&args.transition.stm_output, &transitions, initial)?;
+        eprintln!("state transition matrices written to {}", args.transition.stm_output.display());
+    }
+    Ok(Summary::new(initial_energy, &bodies, frames, settings.gravity))
 }
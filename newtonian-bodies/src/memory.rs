@@ -0,0 +1,147 @@
+use super::body::Vector;
+use super::integrator::Integrator;
+use super::Body;
+use std::error::Error;
+use std::mem::size_of;
+
+/// Estimated peak memory of a run, split by what holds it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Bodies, the integrator's working copies and the recording schedule, as
+    /// estimated up front by [`simulation_bytes`] rather than measured.
+    pub state: usize,
+    /// Peak of the rows encoded by the writer but not yet flushed to disk.
+    pub writer: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.state + self.writer
+    }
+}
+
+/// Bytes used by the bodies themselves, names included.
+pub fn bodies_bytes(bodies: &[Body]) -> usize {
+    bodies
+        .iter()
+        .map(|b| size_of::<Body>() + b.name.capacity())
+        .sum()
+}
+
+/// Estimates the memory a simulation of `bodies` holds while stepping.
+///
+/// Besides the bodies, the cells, the tree or the gas of a force evaluation
+/// take up to about as much again, and RK4 keeps the start of the step, the
+/// interpolated frame and four vectors per body. Patched conics keep two
+/// vectors and the parent of every body.
+pub fn simulation_bytes(bodies: &[Body], integrator: Integrator, record_times: usize) -> usize {
+    let state = bodies_bytes(bodies);
+    let scratch = match integrator {
+        Integrator::Euler | Integrator::Verlet => state,
+        Integrator::Rk4 => 3 * state + 4 * bodies.len() * size_of::<Vector>(),
+        Integrator::PatchedConics => {
+            bodies.len() * (2 * size_of::<Vector>() + size_of::<usize>() + size_of::<Option<usize>>() + size_of::<f64>())
+        }
+    };
+    state + scratch + record_times * size_of::<f64>()
+}
+
+/// Fails when `required` bytes don't fit in `budget`.
+pub fn check_budget(what: &str, required: usize, budget: Option<usize>) -> Result<(), Box<dyn Error>> {
+    match budget {
+        Some(budget) if required > budget => Err(format!(
+            "{} needs about {} but --max-memory is {}",
+            what,
+            format_size(required),
+            format_size(budget)
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Parses a byte count such as "512M", "1.5GiB" or "1000000".
+///
+/// Suffixes are binary multiples (K = 1024), with or without a trailing "B" or "iB".
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", s))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let unit = unit.trim_end_matches("IB").trim_end_matches('B');
+    let multiplier: f64 = match unit {
+        "" => 1.0,
+        "K" => 1024.0,
+        "M" => 1024.0 * 1024.0,
+        "G" => 1024.0 * 1024.0 * 1024.0,
+        "T" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(format!("unknown size unit in '{}' (expected K, M, G or T)", s)),
+    };
+    Ok((number * multiplier).round() as usize)
+}
+
+/// Formats a byte count with a binary unit, e.g. "1.5 MiB".
+pub fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Tags;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1000").unwrap(), 1000);
+        assert_eq!(parse_size("2K").unwrap(), 2048);
+        assert_eq!(parse_size("512MiB").unwrap(), 512 * 1024 * 1024);
+        assert_eq!(parse_size("1.5gb").unwrap(), 1536 * 1024 * 1024);
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("3X").is_err());
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536 * 1024), "1.5 MiB");
+    }
+
+    #[test]
+    fn test_rk4_needs_more_memory_than_euler() {
+        let bodies = vec![
+            Body {
+                name: "Sun".to_string(),
+                mass: 1.0,
+                position: Vector::null(),
+                velocity: Vector::null(),
+                acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: None,
+            };
+            100
+        ];
+        let euler = simulation_bytes(&bodies, Integrator::Euler, 10);
+        let rk4 = simulation_bytes(&bodies, Integrator::Rk4, 10);
+        assert!(euler >= 2 * bodies_bytes(&bodies));
+        assert!(rk4 > euler);
+        assert!(check_budget("simulation", euler, Some(euler)).is_ok());
+        assert!(check_budget("simulation", euler, Some(euler - 1)).is_err());
+        assert!(check_budget("simulation", euler, None).is_ok());
+    }
+}
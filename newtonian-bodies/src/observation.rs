@@ -0,0 +1,349 @@
+use super::body::Vector;
+use super::scenario::split;
+use super::Body;
+use rand::Rng;
+use rand_distr::{Distribution, StandardNormal};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Columns of an observation table, in any order and case.
+const COLUMNS: [&str; 6] = ["time", "observer", "target", "kind", "value", "sigma"];
+
+/// Speed of light in vacuum, in m/s.
+pub const SPEED_OF_LIGHT: f64 = 299_792_458.0;
+
+/// Iterations of the light-time equation, enough for any body of a scenario.
+const LIGHT_TIME_ITERATIONS: usize = 4;
+
+/// Quantity measured from an observer body to a target body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Distance, in meters.
+    Range,
+    /// Rate of change of the distance, in m/s.
+    RangeRate,
+    /// Right ascension in the axes of the scenario, in degrees from 0 to 360.
+    RightAscension,
+    /// Declination in the axes of the scenario, in degrees.
+    Declination,
+}
+
+impl Kind {
+    /// Value for the position and velocity of the target relative to the observer.
+    pub fn measure(&self, position: &Vector, velocity: &Vector) -> f64 {
+        match self {
+            Kind::Range => position.norm(),
+            Kind::RangeRate => position.dot(velocity) / position.norm(),
+            Kind::RightAscension => position.y.atan2(position.x).to_degrees().rem_euclid(360.0),
+            Kind::Declination => (position.z / position.norm()).asin().to_degrees(),
+        }
+    }
+
+    /// Like `measure`, with the direction of angles aberrated by the velocity
+    /// of the observer, if given (see [`aberrate`]).
+    pub fn measure_apparent(&self, position: &Vector, velocity: &Vector, observer_velocity: Option<&Vector>) -> f64 {
+        match (self, observer_velocity) {
+            (Kind::RightAscension | Kind::Declination, Some(observer_velocity)) => {
+                self.measure(&aberrate(position, observer_velocity), velocity)
+            }
+            _ => self.measure(position, velocity),
+        }
+    }
+
+    /// `a - b`, the long way around the circle never taken for right ascensions.
+    pub fn difference(&self, a: f64, b: f64) -> f64 {
+        match self {
+            Kind::RightAscension => (a - b + 180.0).rem_euclid(360.0) - 180.0,
+            _ => a - b,
+        }
+    }
+}
+
+/// Effects of the finite speed of light on what an observer sees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Corrections {
+    /// Targets are seen where they were when the light left them (see
+    /// [`relative_state`]).
+    pub light_time: bool,
+    /// Directions lean towards the motion of the observer (see [`aberrate`]).
+    pub aberration: bool,
+}
+
+/// Position and velocity of `target` relative to `observer`.
+///
+/// With `light_time`, the target is where it was when the light reaching the
+/// observer now left it, a light time earlier; its motion over that time is
+/// extrapolated from its velocity and acceleration.
+pub fn relative_state(observer: &Body, target: &Body, light_time: bool) -> (Vector, Vector) {
+    let mut state = (target.position - observer.position, target.velocity - observer.velocity);
+    if light_time {
+        for _ in 0..LIGHT_TIME_ITERATIONS {
+            let delay = state.0.norm() / SPEED_OF_LIGHT;
+            let position = target.position - target.velocity * delay + target.acceleration * (delay * delay / 2.0);
+            let velocity = target.velocity - target.acceleration * delay;
+            state = (position - observer.position, velocity - observer.velocity);
+        }
+    }
+    state
+}
+
+/// `position` moved, at the same distance, to the direction an observer moving
+/// at `observer_velocity` sees it in: the classical aberration of light, which
+/// tilts directions by up to v/c (about 20 arcseconds for the Earth's orbit).
+pub fn aberrate(position: &Vector, observer_velocity: &Vector) -> Vector {
+    let distance = position.norm();
+    let direction = *position / distance + *observer_velocity / SPEED_OF_LIGHT;
+    direction * (distance / direction.norm())
+}
+
+/// Position of `target` as `observer` sees it, relative to the observer.
+pub fn apparent_position(observer: &Body, target: &Body, corrections: Corrections) -> Vector {
+    let (position, _) = relative_state(observer, target, corrections.light_time);
+    if corrections.aberration {
+        aberrate(&position, &observer.velocity)
+    } else {
+        position
+    }
+}
+
+/// Observations of `target` from `observer` at `time`, one per kind with its
+/// standard deviation.
+pub fn observe(
+    time: f64,
+    observer: &Body,
+    target: &Body,
+    kinds: &[(Kind, f64)],
+    corrections: Corrections,
+) -> Vec<Observation> {
+    let (position, velocity) = relative_state(observer, target, corrections.light_time);
+    let aberration = corrections.aberration.then_some(&observer.velocity);
+    kinds
+        .iter()
+        .map(|&(kind, sigma)| Observation {
+            time,
+            observer: observer.name.clone(),
+            target: target.name.clone(),
+            kind,
+            value: kind.measure_apparent(&position, &velocity, aberration),
+            sigma,
+        })
+        .collect()
+}
+
+/// Adds a normally distributed error of standard deviation `sigma` to every
+/// observation, keeping right ascensions within 0 to 360°.
+pub fn add_noise(observations: &mut [Observation], rng: &mut impl Rng) {
+    for observation in observations {
+        let error: f64 = StandardNormal.sample(rng);
+        observation.value += error * observation.sigma;
+        if observation.kind == Kind::RightAscension {
+            observation.value = observation.value.rem_euclid(360.0);
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Range => write!(f, "range"),
+            Kind::RangeRate => write!(f, "range_rate"),
+            Kind::RightAscension => write!(f, "ra"),
+            Kind::Declination => write!(f, "dec"),
+        }
+    }
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "range" => Ok(Kind::Range),
+            "range_rate" => Ok(Kind::RangeRate),
+            "ra" => Ok(Kind::RightAscension),
+            "dec" => Ok(Kind::Declination),
+            other => Err(format!("unknown observation kind '{}' (expected range, range_rate, ra or dec)", other)),
+        }
+    }
+}
+
+/// One measurement of a target body from an observer body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    /// Seconds since the initial state of the scenario.
+    pub time: f64,
+    pub observer: String,
+    pub target: String,
+    pub kind: Kind,
+    pub value: f64,
+    /// Standard deviation of the measurement error, in the unit of `value`.
+    pub sigma: f64,
+}
+
+/// Reads observations from a CSV table with the columns `time`, `observer`,
+/// `target`, `kind`, `value` and `sigma`, in any order. Fields may be quoted,
+/// and blank lines and lines starting with `#` are skipped.
+pub fn read_csv(path: &Path) -> Result<Vec<Observation>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+
+    let (_, header) = lines.next().ok_or("the observation table has no header")?;
+    let header: Vec<String> = split(header, ',').iter().map(|column| column.trim().to_string()).collect();
+    let positions: Vec<usize> = COLUMNS
+        .iter()
+        .map(|column| {
+            header
+                .iter()
+                .position(|h| h.eq_ignore_ascii_case(column))
+                .ok_or_else(|| format!("the observation table has no '{}' column", column))
+        })
+        .collect::<Result<_, _>>()?;
+
+    lines
+        .map(|(index, line)| {
+            let fields = split(line, ',');
+            let field = |column: usize| {
+                fields
+                    .get(positions[column])
+                    .map(|f| f.trim())
+                    .ok_or_else(|| format!("line {}: missing '{}'", index + 1, COLUMNS[column]))
+            };
+            let number = |column: usize| -> Result<f64, Box<dyn Error>> {
+                let value = field(column)?;
+                value
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid {} '{}'", index + 1, COLUMNS[column], value).into())
+            };
+            let sigma = number(5)?;
+            if !(sigma > 0.0 && sigma.is_finite()) {
+                return Err(format!("line {}: sigma must be positive, got {}", index + 1, sigma).into());
+            }
+            Ok(Observation {
+                time: number(0)?,
+                observer: field(1)?.to_string(),
+                target: field(2)?.to_string(),
+                kind: field(3)?.parse().map_err(|e| format!("line {}: {}", index + 1, e))?,
+                value: number(4)?,
+                sigma,
+            })
+        })
+        .collect()
+}
+
+/// Writes observations as the CSV table `read_csv` reads.
+pub fn write_csv(path: &Path, observations: &[Observation]) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "{}", COLUMNS.join(","))?;
+    for o in observations {
+        writeln!(
+            writer,
+            "{},\"{}\",\"{}\",{},{},{}",
+            o.time,
+            o.observer.replace('"', "\"\""),
+            o.target.replace('"', "\"\""),
+            o.kind,
+            o.value,
+            o.sigma
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_measures_from_the_observer() {
+        let position = Vector::new(0.0, -3.0, 4.0);
+        let velocity = Vector::new(1.0, 0.0, 5.0);
+        assert_eq!(Kind::Range.measure(&position, &velocity), 5.0);
+        assert_eq!(Kind::RangeRate.measure(&position, &velocity), 4.0);
+        assert_eq!(Kind::RightAscension.measure(&position, &velocity), 270.0);
+        assert!((Kind::Declination.measure(&position, &velocity) - 53.130102).abs() < 1e-6);
+        assert_eq!(Kind::RightAscension.difference(1.0, 359.0), 2.0);
+        assert_eq!(Kind::Declination.difference(1.0, 359.0), -358.0);
+    }
+
+    #[test]
+    fn test_reads_quoted_columns_in_any_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("observations.csv");
+        fs::write(&path, "# radar\nkind,time,target,observer,sigma,value\nrange,60,\"Probe, 1\",Earth,10,1.5e7\nRA,120,\"Probe, 1\",Earth,0.001,42\n").unwrap();
+
+        let observations = read_csv(&path).unwrap();
+
+        assert_eq!(observations.len(), 2);
+        assert_eq!(observations[0].target, "Probe, 1");
+        assert_eq!((observations[0].kind, observations[0].value), (Kind::Range, 1.5e7));
+        assert_eq!((observations[1].time, observations[1].kind), (120.0, Kind::RightAscension));
+
+        fs::write(&path, "time,observer,target,kind,value,sigma\n0,Earth,Probe,range,1,0\n").unwrap();
+        assert!(read_csv(&path).unwrap_err().to_string().contains("sigma must be positive"));
+
+        write_csv(&path, &observations).unwrap();
+        assert_eq!(read_csv(&path).unwrap(), observations);
+    }
+
+    #[test]
+    fn test_light_time_sees_the_target_where_it_was() {
+        let body = |name: &str, position: Vector, velocity: Vector| Body {
+            name: name.to_string(),
+            mass: 1.0,
+            position,
+            velocity,
+            acceleration: Vector::null(),
+            tags: Default::default(),
+            temperature: None,
+        };
+        let earth = body("Earth", Vector::null(), Vector::null());
+        // Ten light seconds away, moving sideways at 30 km/s.
+        let probe = body("Probe", Vector::new(0.0, 10.0 * SPEED_OF_LIGHT, 0.0), Vector::new(3e4, 0.0, 0.0));
+
+        let (instant, _) = relative_state(&earth, &probe, false);
+        let (apparent, velocity) = relative_state(&earth, &probe, true);
+        assert_eq!(instant, probe.position);
+        assert!((apparent.x + 3e5).abs() < 1e-2, "{:?}", apparent);
+        assert_eq!(velocity, probe.velocity);
+
+        let kinds = [(Kind::Range, 10.0), (Kind::RightAscension, 1e-4)];
+        let corrections = Corrections {
+            light_time: true,
+            aberration: false,
+        };
+        let mut observations = observe(60.0, &earth, &probe, &kinds, corrections);
+        assert_eq!(observations.len(), 2);
+        assert_eq!((observations[1].time, observations[1].sigma), (60.0, 1e-4));
+        assert!(observations[1].value > 90.0);
+        let exact = observations.clone();
+        add_noise(&mut observations, &mut rand::rngs::StdRng::seed_from_u64(1));
+        assert_ne!(observations[0].value, exact[0].value);
+        assert!((observations[0].value - exact[0].value).abs() < 100.0);
+    }
+
+    #[test]
+    fn test_aberration_tilts_directions_towards_the_motion() {
+        // A star straight up, seen from an observer moving at 30 km/s along x.
+        let star = Vector::new(0.0, 0.0, 1e17);
+        let velocity = Vector::new(3e4, 0.0, 0.0);
+
+        let apparent = aberrate(&star, &velocity);
+
+        assert!((apparent.norm() / star.norm() - 1.0).abs() < 1e-12);
+        let tilt = (apparent.x / apparent.z).atan().to_degrees() * 3600.0;
+        assert!((tilt - 20.64).abs() < 0.01, "{}", tilt);
+        assert_eq!(Kind::Range.measure_apparent(&star, &velocity, Some(&velocity)), 1e17);
+        let declination = Kind::Declination.measure_apparent(&star, &Vector::null(), Some(&velocity));
+        assert!((90.0 - declination) * 3600.0 > 20.0);
+    }
+}
@@ -0,0 +1,127 @@
+use super::dynamics::SequentialWriter;
+use super::Body;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Relative state of two bodies at one time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantities {
+    pub distance: f64,
+    pub relative_speed: f64,
+    /// Specific orbital energy of the two-body problem, v²/2 - G(m1 + m2)/r:
+    /// negative while the pair is bound.
+    pub specific_energy: f64,
+}
+
+pub fn quantities(a: &Body, b: &Body, gravity: f64) -> Quantities {
+    let distance = (b.position - a.position).norm();
+    let relative_speed = (b.velocity - a.velocity).norm();
+    Quantities {
+        distance,
+        relative_speed,
+        specific_energy: relative_speed * relative_speed / 2.0 - gravity * (a.mass + b.mass) / distance,
+    }
+}
+
+/// Writes the quantities of selected pairs of bodies at every recorded time as
+/// CSV (`time,body_a,body_b,distance,relative_speed,specific_energy`), next to
+/// the main output, so close approaches or binaries can be followed without
+/// joining the full state table with itself.
+pub struct Writer {
+    csv: BufWriter<File>,
+    pairs: Vec<(String, String)>,
+    gravity: f64,
+}
+
+impl Writer {
+    pub fn new(path: &Path, pairs: Vec<(String, String)>, gravity: f64) -> Result<Self, Box<dyn Error>> {
+        let mut csv = BufWriter::new(File::create(path)?);
+        writeln!(csv, "time,body_a,body_b,distance,relative_speed,specific_energy")?;
+        Ok(Writer { csv, pairs, gravity })
+    }
+
+    pub fn close(mut self) -> Result<(), Box<dyn Error>> {
+        self.csv.flush()?;
+        Ok(())
+    }
+}
+
+impl SequentialWriter for Writer {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        let find = |name: &str| {
+            bodies
+                .iter()
+                .find(|body| body.name == name)
+                .ok_or_else(|| format!("pair body '{}' isn't among the recorded bodies", name))
+        };
+        let rows = self
+            .pairs
+            .iter()
+            .map(|(a, b)| Ok((a, b, quantities(find(a)?, find(b)?, self.gravity))))
+            .collect::<Result<Vec<_>, String>>()?;
+        for (a, b, q) in rows {
+            writeln!(
+                self.csv,
+                "{},\"{}\",\"{}\",{},{},{}",
+                time,
+                a.replace('"', "\"\""),
+                b.replace('"', "\"\""),
+                q.distance,
+                q.relative_speed,
+                q.specific_energy
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::{Tags, Vector};
+
+    fn body(name: &str, mass: f64, x: f64, vy: f64) -> Body {
+        Body {
+            name: name.to_string(),
+            mass,
+            position: Vector { x, y: 0.0, z: 0.0 },
+            velocity: Vector { x: 0.0, y: vy, z: 0.0 },
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    #[test]
+    fn test_circular_pair_has_half_the_potential_energy() {
+        let (gravity, mass, distance): (f64, f64, f64) = (6.67430e-11, 5.972e24, 4.0e8);
+        let speed = (gravity * (mass + 1.0) / distance).sqrt();
+        let q = quantities(&body("Earth", mass, 0.0, 0.0), &body("Moon", 1.0, distance, speed), gravity);
+
+        assert_eq!(q.distance, distance);
+        assert_eq!(q.relative_speed, speed);
+        let potential = -gravity * (mass + 1.0) / distance;
+        assert!((q.specific_energy - potential / 2.0).abs() < 1e-12 * potential.abs());
+    }
+
+    #[test]
+    fn test_writes_one_row_per_pair_and_frame() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("pairs.csv");
+        let pairs = vec![("A".to_string(), "B".to_string()), ("A".to_string(), "C".to_string())];
+        let bodies = [body("A", 1.0, 0.0, 0.0), body("B", 1.0, 3.0, 0.0), body("C", 1.0, 5.0, 2.0)];
+
+        let mut writer = Writer::new(&path, pairs, 1.0).unwrap();
+        writer.add(0.0, &bodies).unwrap();
+        writer.add(1.0, &bodies).unwrap();
+        assert!(writer.add(2.0, &bodies[..2]).is_err());
+        writer.close().unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[4], "1,\"A\",\"C\",5,2,1.6");
+    }
+}
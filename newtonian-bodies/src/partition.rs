@@ -0,0 +1,212 @@
+use super::dynamics::SequentialWriter;
+use super::schema::Layout;
+use super::writer::Writer;
+use super::Body;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Rows buffered over all the bodies before they are written, as a batch per
+/// body.
+const BUFFERED_ROWS: usize = 1 << 16;
+
+/// Memory held by the row group of each body's file before it is flushed,
+/// unless a smaller budget is given.
+const PARTITION_BUFFER: usize = 1 << 20;
+
+/// Name of the file in each body's directory.
+const PART: &str = "part-0.parquet";
+
+/// Writes the frames of each body to a Parquet file of its own, in Hive-style
+/// `name=<body>/` directories of `directory` (e.g., `orbits/name=Earth/part-0.parquet`),
+/// so queries of one body's time series read only its file. DuckDB reads them
+/// all with `read_parquet('orbits/*/*.parquet', hive_partitioning = true)`.
+///
+/// Each file has the columns of the plain output, so the `reader` module reads
+/// it back too. Every body keeps a file open for the whole run.
+pub struct PartitionedWriter {
+    directory: PathBuf,
+    layout: Layout,
+    max_buffer: Option<usize>,
+    partitions: HashMap<String, Partition>,
+    /// Frames of each body buffered before they are written.
+    chunk: usize,
+    peak_buffer: usize,
+}
+
+/// The file of one body and its frames not yet written.
+struct Partition {
+    writer: Writer,
+    times: Vec<f64>,
+    states: Vec<Body>,
+}
+
+impl Partition {
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.times.is_empty() {
+            self.writer.add_series(&self.times, &self.states)?;
+            self.times.clear();
+            self.states.clear();
+        }
+        Ok(())
+    }
+}
+
+impl PartitionedWriter {
+    /// Creates `directory`, replacing the partitions an earlier run left in it.
+    pub fn new(directory: PathBuf, layout: Layout) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(&directory)?;
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() && entry.file_name().to_string_lossy().starts_with("name=") {
+                fs::remove_dir_all(entry.path())?;
+            }
+        }
+        Ok(PartitionedWriter {
+            directory,
+            layout,
+            max_buffer: None,
+            partitions: HashMap::new(),
+            chunk: 1,
+            peak_buffer: 0,
+        })
+    }
+
+    /// Shares `bytes` among the row groups the files buffer.
+    pub fn with_max_buffer(mut self, bytes: usize) -> Self {
+        self.max_buffer = Some(bytes);
+        self
+    }
+
+    /// Largest amount of memory, in bytes, held by a file's rows not yet
+    /// flushed to disk, times the number of files.
+    pub fn peak_buffer(&self) -> usize {
+        self.peak_buffer * self.partitions.len()
+    }
+
+    pub fn close(self) -> Result<(), Box<dyn Error>> {
+        for (_, mut partition) in self.partitions {
+            partition.flush()?;
+            partition.writer.close()?;
+        }
+        Ok(())
+    }
+
+    fn open(&self, name: &str, bodies: usize) -> Result<Partition, Box<dyn Error>> {
+        let path = partition_of(&self.directory, name);
+        fs::create_dir_all(path.parent().unwrap_or(&self.directory))?;
+        let bytes = self.max_buffer.map_or(PARTITION_BUFFER, |max| (max / bodies.max(1)).min(PARTITION_BUFFER));
+        Ok(Partition {
+            writer: Writer::with_layout(path, self.layout)?.with_max_buffer(bytes),
+            times: Vec::new(),
+            states: Vec::new(),
+        })
+    }
+}
+
+impl SequentialWriter for PartitionedWriter {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        if self.partitions.is_empty() {
+            self.chunk = (BUFFERED_ROWS / bodies.len().max(1)).max(1);
+        }
+        for body in bodies {
+            if !self.partitions.contains_key(&body.name) {
+                let partition = self.open(&body.name, bodies.len())?;
+                self.partitions.insert(body.name.clone(), partition);
+            }
+            let partition = self.partitions.get_mut(&body.name).expect("opened above");
+            partition.times.push(time);
+            partition.states.push(body.clone());
+            if partition.times.len() >= self.chunk {
+                partition.flush()?;
+                self.peak_buffer = self.peak_buffer.max(partition.writer.peak_buffer());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A body name as the value of a partition directory, with the characters
+/// Hive escapes written as `%XX`.
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_control() || "\"#%'*/:=?\\{[]^".contains(c) {
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                escaped.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// File of a body's partition in `directory`.
+pub fn partition_of(directory: &Path, name: &str) -> PathBuf {
+    directory.join(format!("name={}", escape(name))).join(PART)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::{Tags, Vector};
+    use crate::reader::{Frame, SimulationReader};
+    use tempfile::TempDir;
+
+    fn body(name: &str, x: f64) -> Body {
+        Body {
+            name: name.to_string(),
+            mass: 2.0,
+            position: Vector::new(x, 0.0, 0.0),
+            velocity: Vector::new(0.0, x, 0.0),
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    #[test]
+    fn test_escapes_like_hive() {
+        assert_eq!(escape("Earth"), "Earth");
+        assert_eq!(escape("Halley's Comet"), "Halley%27s Comet");
+        assert_eq!(escape("a/b=c%"), "a%2Fb%3Dc%25");
+    }
+
+    #[test]
+    fn test_each_body_gets_its_own_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let directory = temp_dir.path().join("orbits");
+        let stale = directory.join("name=Pluto");
+        fs::create_dir_all(&stale).unwrap();
+
+        let mut writer = PartitionedWriter::new(directory.clone(), Layout::default()).unwrap();
+        assert!(!stale.exists());
+        for step in 0..10 {
+            let time = step as f64;
+            let mut bodies = vec![body("Sun", time), body("Earth/Moon", -time)];
+            // A body appearing midway starts its file then.
+            if step >= 4 {
+                bodies.push(body("Probe", 100.0 + time));
+            }
+            writer.add(time, &bodies).unwrap();
+            // Write in chunks of three frames.
+            writer.chunk = 3;
+        }
+        writer.close().unwrap();
+
+        let read = |name: &str| -> Vec<Frame> {
+            SimulationReader::open(&partition_of(&directory, name)).unwrap().collect::<Result<_, _>>().unwrap()
+        };
+        let moon = read("Earth/Moon");
+        assert!(directory.join("name=Earth%2FMoon").is_dir());
+        assert_eq!(moon.iter().map(|frame| frame.time).collect::<Vec<_>>(), (0..10).map(f64::from).collect::<Vec<_>>());
+        assert!(moon.iter().all(|frame| frame.bodies.len() == 1 && frame.bodies[0].position.x == -frame.time));
+        let probe = read("Probe");
+        assert_eq!(probe.len(), 6);
+        assert_eq!((probe[0].time, probe[0].bodies[0].velocity.y), (4.0, 104.0));
+        assert_eq!(read("Sun")[9].bodies[0].mass, 2.0);
+    }
+}
@@ -0,0 +1,179 @@
+use super::body::Vector;
+use super::kepler;
+use super::Body;
+
+/// Bodies lighter than this fraction of the heaviest one are small: they move
+/// within the spheres of influence of the others without having one.
+pub const SMALL_MASS_RATIO: f64 = 1e-15;
+
+/// Which body each body orbits during a step.
+///
+/// The heaviest body is the root, with an infinite sphere of influence. Every
+/// other body orbits the heavier body with the smallest sphere of influence it
+/// is in, and bodies that are not small get a sphere of their own, of Laplace
+/// radius `d (m / M)^(2/5)` about their parent.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Hierarchy {
+    /// Bodies from the heaviest, so parents come before what orbits them.
+    order: Vec<usize>,
+    /// Body each one orbits; the root orbits none.
+    parents: Vec<Option<usize>>,
+    /// Radius of the sphere of influence of each body, zero for small ones.
+    spheres: Vec<f64>,
+}
+
+impl Hierarchy {
+    /// Assigns every body to the sphere of influence it is in.
+    pub(crate) fn update(&mut self, bodies: &[Body]) {
+        let n = bodies.len();
+        self.order.clear();
+        self.order.extend(0..n);
+        // Unstable sorts don't allocate; ties are broken by index.
+        self.order.sort_unstable_by(|&a, &b| bodies[b].mass.total_cmp(&bodies[a].mass).then(a.cmp(&b)));
+        self.parents.clear();
+        self.parents.resize(n, None);
+        self.spheres.clear();
+        self.spheres.resize(n, 0.0);
+        let Some(&root) = self.order.first() else {
+            return;
+        };
+        self.spheres[root] = f64::INFINITY;
+        let small = bodies[root].mass * SMALL_MASS_RATIO;
+        // The bodies with a sphere of influence come first in the order.
+        let majors = self.order.iter().take_while(|&&i| bodies[i].mass >= small).count();
+        for k in 1..n {
+            let i = self.order[k];
+            let parent = self.order[..k.min(majors)]
+                .iter()
+                .copied()
+                .filter(|&j| (bodies[i].position - bodies[j].position).norm() < self.spheres[j])
+                .min_by(|&a, &b| self.spheres[a].total_cmp(&self.spheres[b]))
+                .unwrap_or(root);
+            self.parents[i] = Some(parent);
+            if k < majors {
+                let distance = (bodies[i].position - bodies[parent].position).norm();
+                self.spheres[i] = distance * (bodies[i].mass / bodies[parent].mass).powf(0.4);
+            }
+        }
+    }
+
+    /// Body that `body` orbits, if any.
+    pub(crate) fn parent(&self, body: usize) -> Option<usize> {
+        self.parents.get(body).copied().flatten()
+    }
+}
+
+/// Sets the accelerations of the bodies to the pull of their parents, on top
+/// of their parents' own accelerations.
+pub(crate) fn accelerate(bodies: &mut [Body], gravity: f64, hierarchy: &Hierarchy) {
+    for &i in &hierarchy.order {
+        bodies[i].acceleration = match hierarchy.parent(i) {
+            Some(p) => {
+                let offset = bodies[i].position - bodies[p].position;
+                let distance = offset.norm();
+                bodies[p].acceleration - offset * (gravity * bodies[p].mass / distance.powi(3))
+            }
+            None => Vector::null(),
+        };
+    }
+}
+
+/// Advances every body along the two-body orbit about its parent, the root
+/// drifting in a straight line. `position` and `velocity` keep the states
+/// relative to the parents.
+pub(crate) fn step(
+    bodies: &mut [Body],
+    gravity: f64,
+    dt: f64,
+    hierarchy: &mut Hierarchy,
+    position: &mut Vec<Vector>,
+    velocity: &mut Vec<Vector>,
+) {
+    hierarchy.update(bodies);
+    position.clear();
+    velocity.clear();
+    for (i, body) in bodies.iter().enumerate() {
+        let (p, v) = match hierarchy.parent(i) {
+            Some(parent) => (body.position - bodies[parent].position, body.velocity - bodies[parent].velocity),
+            None => (body.position, body.velocity),
+        };
+        position.push(p);
+        velocity.push(v);
+    }
+    // Parents move before what orbits them.
+    for &i in &hierarchy.order {
+        match hierarchy.parent(i) {
+            Some(parent) => {
+                let mu = gravity * (bodies[parent].mass + bodies[i].mass);
+                // Only bodies on top of each other fail to propagate; they drift.
+                let (p, v) = kepler::propagate(&position[i], &velocity[i], mu, dt)
+                    .unwrap_or((position[i] + velocity[i] * dt, velocity[i]));
+                bodies[i].position = bodies[parent].position + p;
+                bodies[i].velocity = bodies[parent].velocity + v;
+            }
+            None => bodies[i].position = position[i] + velocity[i] * dt,
+        }
+    }
+    accelerate(bodies, gravity, hierarchy);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamics::{simulate_with, Recording, Settings};
+    use crate::integrator::Integrator;
+    use crate::simulation::Discard;
+
+    const G: f64 = 6.67430e-11;
+    const AU: f64 = 1.495978707e11;
+
+    /// The Sun, the Earth on a circular orbit and a probe `distance` from the
+    /// Earth, moving with it.
+    fn system(distance: f64) -> Vec<Body> {
+        let earth_speed = (G * (1.989e30 + 5.972e24) / AU).sqrt();
+        vec![
+            Body::new("Probe", 1000.0, Vector::new(AU + distance, 0.0, 0.0), Vector::new(0.0, earth_speed, 0.0)),
+            Body::new("Sun", 1.989e30, Vector::null(), Vector::null()),
+            Body::new("Earth", 5.972e24, Vector::new(AU, 0.0, 0.0), Vector::new(0.0, earth_speed, 0.0)),
+        ]
+    }
+
+    #[test]
+    fn test_bodies_orbit_the_sphere_they_are_in() {
+        let mut hierarchy = Hierarchy::default();
+        // The Earth's sphere of influence reaches about 9.2e8 m.
+        hierarchy.update(&system(5e8));
+        assert_eq!((hierarchy.parent(0), hierarchy.parent(1), hierarchy.parent(2)), (Some(2), None, Some(1)));
+        assert!((hierarchy.spheres[2] / 9.25e8 - 1.0).abs() < 0.01, "{}", hierarchy.spheres[2]);
+        assert_eq!(hierarchy.spheres[0], 0.0);
+        hierarchy.update(&system(2e9));
+        assert_eq!(hierarchy.parent(0), Some(1));
+    }
+
+    #[test]
+    fn test_year_long_steps_keep_the_earth_on_its_orbit() {
+        let mut bodies = system(5e8);
+        let settings = Settings {
+            total_time: 10.0 * 365.25 * 86400.0,
+            dt: 30.0 * 86400.0,
+            integrator: Integrator::PatchedConics,
+            recording: Recording::Count(2),
+            progress: false,
+            ..Settings::default()
+        };
+        simulate_with(&mut bodies, &settings, &mut Discard).unwrap();
+
+        // The arcs are exact: ten years of month-long steps stay on the circle.
+        assert!(((bodies[2].position - bodies[1].position).norm() / AU - 1.0).abs() < 1e-9);
+        assert_eq!(bodies[1].position, Vector::null());
+        // The probe still circles the Earth, at its distance.
+        let probe = (bodies[0].position - bodies[2].position).norm();
+        assert!(probe > 1e8 && probe < 9e8, "{}", probe);
+
+        let sph = Settings {
+            cutoff: Some(1.0),
+            ..settings
+        };
+        assert!(simulate_with(&mut system(5e8), &sph, &mut Discard).is_err());
+    }
+}
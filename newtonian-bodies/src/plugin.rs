@@ -0,0 +1,514 @@
+use super::dynamics::SequentialWriter;
+use super::Body;
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Force models and observers compiled to WebAssembly and loaded at run time,
+/// so third parties can ship them without rebuilding this crate.
+///
+/// A plugin is a core WebAssembly module (without WASI) exporting:
+///
+/// - `memory`, and `alloc(bytes: i32) -> i32` returning the address of a
+///   buffer the host fills. The host never frees it, and reuses it while it is
+///   big enough.
+/// - `accelerate(time: f64, bodies: i32, count: i32)` to add forces, and/or
+///   `record(time: f64, bodies: i32, count: i32)` to observe recorded frames.
+/// - Optionally `names(names: i32, bytes: i32)`, called with the names of the
+///   bodies, one per line in UTF-8, before the first call and whenever they
+///   change or move, and `finish()`, called once the run is over.
+///
+/// `bodies` holds `count` records of ten little-endian f64: the mass, the
+/// position, the velocity and the acceleration of a body, in SI units.
+/// `accelerate` adds its own acceleration to the last three, with `time` the
+/// start of the step; changes to anything else are ignored.
+///
+/// Plugins may import `env.emit(bytes: i32, length: i32)`, which writes bytes to
+/// the output of the plugin, so observers can write files of their own format.
+#[derive(Clone)]
+pub struct Plugin {
+    program: Arc<Program>,
+    /// Whether what `accelerate` emits is dropped rather than written.
+    #[cfg_attr(not(feature = "wasmi"), allow(dead_code))]
+    quiet: bool,
+}
+
+struct Program {
+    path: PathBuf,
+    #[cfg(feature = "wasmi")]
+    engine: wasmi::Engine,
+    #[cfg(feature = "wasmi")]
+    module: wasmi::Module,
+}
+
+impl fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Plugin").field("path", &self.program.path).finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for Plugin {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.program, &other.program)
+    }
+}
+
+impl Plugin {
+    /// Compiles the WebAssembly module in the file at `path`.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        #[cfg(not(feature = "wasmi"))]
+        return Err(format!("{}: plugins need the wasmi feature (cargo build --features wasmi)", path.display()).into());
+
+        #[cfg(feature = "wasmi")]
+        {
+            let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let engine = wasmi::Engine::default();
+            let module = wasmi::Module::new(&engine, &bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let plugin = Plugin {
+                program: Arc::new(Program {
+                    path: path.to_path_buf(),
+                    engine,
+                    module,
+                }),
+                quiet: false,
+            };
+            if let Some(missing) = ["memory", "alloc"].into_iter().find(|name| !plugin.exports(name)) {
+                return Err(format!("{} doesn't export {}", path.display(), missing).into());
+            }
+            if !plugin.accelerates() && !plugin.records() {
+                return Err(format!("{} exports neither accelerate nor record", path.display()).into());
+            }
+            Ok(plugin)
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.program.path
+    }
+
+    /// The same plugin, with what its forces emit dropped: for runs made
+    /// alongside the run itself, which would repeat it.
+    pub fn quiet(&self) -> Plugin {
+        Plugin {
+            quiet: true,
+            ..self.clone()
+        }
+    }
+
+    /// Whether the plugin adds forces.
+    pub fn accelerates(&self) -> bool {
+        self.exports("accelerate")
+    }
+
+    /// Whether the plugin observes the recorded frames.
+    pub fn records(&self) -> bool {
+        self.exports("record")
+    }
+
+    fn exports(&self, name: &str) -> bool {
+        #[cfg(feature = "wasmi")]
+        let exported = self.program.module.get_export(name).is_some();
+        #[cfg(not(feature = "wasmi"))]
+        let exported = {
+            let _ = name;
+            false
+        };
+        exported
+    }
+}
+
+/// The forces of a plugin during a run, from an instance of its own created at
+/// the first evaluation. What the plugin emits goes to the standard error,
+/// unless it is quiet.
+pub(crate) struct PluginForces {
+    plugin: Plugin,
+    #[cfg(feature = "wasmi")]
+    instance: Mutex<Option<runtime::Instance>>,
+    /// Start of the step whose forces are being evaluated.
+    time: Mutex<f64>,
+    /// First failure of the plugin, reported at the end of the step.
+    error: Mutex<Option<String>>,
+}
+
+impl Clone for PluginForces {
+    fn clone(&self) -> Self {
+        PluginForces::new(self.plugin.clone())
+    }
+}
+
+impl fmt::Debug for PluginForces {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PluginForces").field(&self.plugin).finish()
+    }
+}
+
+impl PartialEq for PluginForces {
+    fn eq(&self, other: &Self) -> bool {
+        self.plugin == other.plugin
+    }
+}
+
+impl PluginForces {
+    pub(crate) fn new(plugin: Plugin) -> Self {
+        PluginForces {
+            plugin,
+            #[cfg(feature = "wasmi")]
+            instance: Mutex::new(None),
+            time: Mutex::new(0.0),
+            error: Mutex::new(None),
+        }
+    }
+
+    /// Sets the time of the coming force evaluations.
+    pub(crate) fn clock(&self, time: f64) {
+        *self.time.lock().unwrap_or_else(PoisonError::into_inner) = time;
+    }
+
+    /// Adds the acceleration of the plugin to every body.
+    pub(crate) fn accelerate(&self, bodies: &mut [Body]) {
+        #[cfg(feature = "wasmi")]
+        {
+            let time = *self.time.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut instance = self.instance.lock().unwrap_or_else(PoisonError::into_inner);
+            let result = match &mut *instance {
+                Some(instance) => Ok(instance),
+                None => {
+                    let output: Box<dyn Write + Send> = match self.plugin.quiet {
+                        true => Box::new(std::io::sink()),
+                        false => Box::new(std::io::stderr()),
+                    };
+                    runtime::Instance::new(&self.plugin.program, output).map(|created| instance.insert(created))
+                }
+            }
+            .and_then(|instance| instance.accelerate(time, bodies));
+            if let Err(e) = result {
+                let mut error = self.error.lock().unwrap_or_else(PoisonError::into_inner);
+                error.get_or_insert_with(|| format!("{}: {}", self.plugin.path().display(), e));
+            }
+        }
+        #[cfg(not(feature = "wasmi"))]
+        let _ = bodies;
+    }
+
+    /// Fails with the first failure of the plugin since the last check.
+    pub(crate) fn check(&self) -> Result<(), Box<dyn Error>> {
+        match self.error.lock().unwrap_or_else(PoisonError::into_inner).take() {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A plugin observing the recorded frames, e.g., to write a file of its own
+/// format through what it emits.
+pub struct PluginObserver {
+    plugin: Plugin,
+    #[cfg(feature = "wasmi")]
+    instance: runtime::Instance,
+}
+
+impl PluginObserver {
+    /// Instantiates `plugin`, which emits to `output`.
+    pub fn new(plugin: &Plugin, output: Box<dyn Write + Send>) -> Result<Self, Box<dyn Error>> {
+        if !plugin.records() {
+            return Err(format!("{} doesn't export record", plugin.path().display()).into());
+        }
+        #[cfg(feature = "wasmi")]
+        let instance = runtime::Instance::new(&plugin.program, output)?;
+        #[cfg(not(feature = "wasmi"))]
+        drop(output);
+        Ok(PluginObserver {
+            plugin: plugin.clone(),
+            #[cfg(feature = "wasmi")]
+            instance,
+        })
+    }
+
+    pub fn plugin(&self) -> &Plugin {
+        &self.plugin
+    }
+
+    /// Tells the plugin the run is over and flushes its output.
+    pub fn close(self) -> Result<(), Box<dyn Error>> {
+        #[cfg(feature = "wasmi")]
+        {
+            let mut instance = self.instance;
+            instance.finish()?;
+        }
+        Ok(())
+    }
+}
+
+impl SequentialWriter for PluginObserver {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        #[cfg(feature = "wasmi")]
+        self.instance
+            .record(time, bodies)
+            .map_err(|e| format!("{}: {}", self.plugin.path().display(), e))?;
+        #[cfg(not(feature = "wasmi"))]
+        let _ = (time, bodies);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "wasmi")]
+mod runtime {
+    use super::super::body::Vector;
+    use super::super::Body;
+    use super::Program;
+    use std::error::Error;
+    use std::io::Write;
+    use wasmi::{Caller, Extern, Linker, Memory, Store, TypedFunc};
+
+    /// f64 per body in the buffer.
+    const RECORD: usize = 10;
+
+    type Output = Box<dyn Write + Send>;
+    type Frame = TypedFunc<(f64, i32, i32), ()>;
+
+    /// An instance of a plugin, with its buffer.
+    pub(super) struct Instance {
+        store: Store<Output>,
+        memory: Memory,
+        alloc: TypedFunc<i32, i32>,
+        accelerate: Option<Frame>,
+        record: Option<Frame>,
+        names: Option<TypedFunc<(i32, i32), ()>>,
+        finish: Option<TypedFunc<(), ()>>,
+        /// Address and size of the buffer of bodies.
+        buffer: (i32, usize),
+        /// Names last sent to the plugin.
+        sent: Vec<String>,
+        bytes: Vec<u8>,
+    }
+
+    impl Instance {
+        pub(super) fn new(program: &Program, output: Output) -> Result<Self, Box<dyn Error>> {
+            let mut store = Store::new(&program.engine, output);
+            let mut linker = Linker::new(&program.engine);
+            linker.func_wrap("env", "emit", emit)?;
+            let instance = linker.instantiate(&mut store, &program.module)?.start(&mut store)?;
+            let memory = instance.get_memory(&store, "memory").ok_or("the exported memory isn't a memory")?;
+            macro_rules! optional {
+                ($name:literal) => {
+                    instance.get_func(&store, $name).map(|f| f.typed(&store)).transpose()?
+                };
+            }
+            Ok(Instance {
+                memory,
+                alloc: instance.get_typed_func(&store, "alloc")?,
+                accelerate: optional!("accelerate"),
+                record: optional!("record"),
+                names: optional!("names"),
+                finish: optional!("finish"),
+                store,
+                buffer: (0, 0),
+                sent: Vec::new(),
+                bytes: Vec::new(),
+            })
+        }
+
+        /// Adds the acceleration of the plugin to the bodies at `time`.
+        pub(super) fn accelerate(&mut self, time: f64, bodies: &mut [Body]) -> Result<(), Box<dyn Error>> {
+            let Some(accelerate) = self.accelerate else {
+                return Ok(());
+            };
+            let address = self.send(bodies)?;
+            accelerate.call(&mut self.store, (time, address, bodies.len() as i32))?;
+            self.memory.read(&self.store, address as usize, &mut self.bytes).map_err(wasmi::Error::from)?;
+            for (body, record) in bodies.iter_mut().zip(self.bytes.chunks_exact(8 * RECORD)) {
+                let value = |k: usize| f64::from_le_bytes(record[8 * k..8 * k + 8].try_into().unwrap());
+                body.acceleration = Vector::new(value(7), value(8), value(9));
+            }
+            Ok(())
+        }
+
+        pub(super) fn record(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+            if let Some(record) = self.record {
+                let address = self.send(bodies)?;
+                record.call(&mut self.store, (time, address, bodies.len() as i32))?;
+            }
+            Ok(())
+        }
+
+        pub(super) fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+            if let Some(finish) = self.finish {
+                finish.call(&mut self.store, ())?;
+            }
+            self.store.data_mut().flush()?;
+            Ok(())
+        }
+
+        /// Copies the bodies to the buffer, after their names if they changed,
+        /// and returns its address.
+        fn send(&mut self, bodies: &[Body]) -> Result<i32, Box<dyn Error>> {
+            if let Some(names) = self.names
+                && !self.sent.iter().eq(bodies.iter().map(|body| &body.name))
+            {
+                self.sent = bodies.iter().map(|body| body.name.clone()).collect();
+                let text = self.sent.join("\n");
+                let address = self.alloc.call(&mut self.store, text.len() as i32)?;
+                self.memory.write(&mut self.store, address as usize, text.as_bytes()).map_err(wasmi::Error::from)?;
+                names.call(&mut self.store, (address, text.len() as i32))?;
+            }
+
+            self.bytes.clear();
+            for body in bodies {
+                let (p, v, a) = (body.position, body.velocity, body.acceleration);
+                for value in [body.mass, p.x, p.y, p.z, v.x, v.y, v.z, a.x, a.y, a.z] {
+                    self.bytes.extend(value.to_le_bytes());
+                }
+            }
+            if self.buffer.1 < self.bytes.len() {
+                self.buffer = (self.alloc.call(&mut self.store, self.bytes.len() as i32)?, self.bytes.len());
+            }
+            self.memory.write(&mut self.store, self.buffer.0 as usize, &self.bytes).map_err(wasmi::Error::from)?;
+            Ok(self.buffer.0)
+        }
+    }
+
+    /// `env.emit`: writes bytes of the plugin memory to its output.
+    fn emit(mut caller: Caller<'_, Output>, bytes: i32, length: i32) -> Result<(), wasmi::Error> {
+        let memory = caller
+            .get_export("memory")
+            .and_then(Extern::into_memory)
+            .ok_or_else(|| wasmi::Error::new("emit needs an exported memory"))?;
+        let mut buffer = vec![0; length.max(0) as usize];
+        memory.read(&caller, bytes as usize, &mut buffer)?;
+        caller.data_mut().write_all(&buffer).map_err(|e| wasmi::Error::new(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(feature = "wasmi"))]
+    #[test]
+    fn test_plugins_need_wasmi() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("force.wasm");
+        std::fs::write(&path, b"\0asm\x01\0\0\0").unwrap();
+        assert!(Plugin::load(&path).is_err());
+    }
+
+    #[cfg(feature = "wasmi")]
+    mod wasm {
+        use super::*;
+        use crate::body::{Tags, Vector};
+        use crate::dynamics::{simulate_with, Recording, Settings};
+        use std::fs;
+        use std::io;
+
+        /// A constant push of 1 m/s² along x on every body, and an observer
+        /// emitting the time and the first mass of every frame, and "end" when
+        /// the run is over. `alloc` bumps a pointer from 1 KiB.
+        const PLUGIN: &str = r#"
+            (module
+              (import "env" "emit" (func $emit (param i32 i32)))
+              (memory (export "memory") 1)
+              (global $next (mut i32) (i32.const 1024))
+              (data (i32.const 0) "end")
+              (func (export "alloc") (param $bytes i32) (result i32)
+                (local $address i32)
+                (local.set $address (global.get $next))
+                (global.set $next (i32.add (global.get $next) (local.get $bytes)))
+                (local.get $address))
+              (func (export "accelerate") (param $time f64) (param $bodies i32) (param $count i32)
+                (local $ax i32)
+                (block $done
+                  (loop $next
+                    (br_if $done (i32.eqz (local.get $count)))
+                    (local.set $ax (i32.add (local.get $bodies) (i32.const 56)))
+                    (f64.store (local.get $ax) (f64.add (f64.load (local.get $ax)) (f64.const 1)))
+                    (local.set $bodies (i32.add (local.get $bodies) (i32.const 80)))
+                    (local.set $count (i32.sub (local.get $count) (i32.const 1)))
+                    (br $next))))
+              (func (export "record") (param $time f64) (param $bodies i32) (param $count i32)
+                (f64.store (i32.const 8) (local.get $time))
+                (f64.store (i32.const 16) (f64.load (local.get $bodies)))
+                (call $emit (i32.const 8) (i32.const 16)))
+              (func (export "finish")
+                (call $emit (i32.const 0) (i32.const 3))))
+        "#;
+
+        /// Collects what a plugin emits.
+        #[derive(Clone, Default)]
+        struct Emitted(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Emitted {
+            fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(bytes);
+                Ok(bytes.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        fn load(text: &str) -> Result<Plugin, Box<dyn Error>> {
+            let temp_dir = tempfile::TempDir::new().unwrap();
+            let path = temp_dir.path().join("plugin.wasm");
+            fs::write(&path, wat::parse_str(text).unwrap()).unwrap();
+            Plugin::load(&path)
+        }
+
+        #[test]
+        fn test_plugin_pushes_and_observes() {
+            let plugin = load(PLUGIN).unwrap();
+            assert!(plugin.accelerates() && plugin.records());
+            let emitted = Emitted::default();
+            let mut observer = PluginObserver::new(&plugin, Box::new(emitted.clone())).unwrap();
+
+            let mut bodies = vec![Body {
+                name: "Probe".to_string(),
+                mass: 3.0,
+                position: Vector::null(),
+                velocity: Vector::null(),
+                acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: None,
+            }];
+            let settings = Settings {
+                total_time: 10.0,
+                dt: 1.0,
+                recording: Recording::Count(2),
+                progress: false,
+                plugins: vec![plugin],
+                ..Settings::default()
+            };
+            simulate_with(&mut bodies, &settings, &mut observer).unwrap();
+            observer.close().unwrap();
+
+            assert_eq!(bodies[0].velocity, Vector::new(10.0, 0.0, 0.0));
+            let emitted = emitted.0.lock().unwrap();
+            let values: Vec<f64> = emitted[..32].chunks_exact(8).map(|x| f64::from_le_bytes(x.try_into().unwrap())).collect();
+            assert_eq!(values, [0.0, 3.0, 10.0, 3.0]);
+            assert_eq!(&emitted[32..], b"end");
+        }
+
+        #[test]
+        fn test_plugins_must_follow_the_interface() {
+            assert!(load("(module (memory (export \"memory\") 1))").is_err());
+            let trap = r#"
+                (module
+                  (memory (export "memory") 1)
+                  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                  (func (export "accelerate") (param f64 i32 i32) unreachable))
+            "#;
+            let settings = Settings {
+                total_time: 1.0,
+                dt: 1.0,
+                progress: false,
+                plugins: vec![load(trap).unwrap()],
+                ..Settings::default()
+            };
+            let mut bodies: Vec<Body> = Vec::new();
+            let error = simulate_with(&mut bodies, &settings, &mut crate::simulation::Discard).unwrap_err();
+            assert!(error.to_string().contains("plugin.wasm"), "{}", error);
+        }
+    }
+}
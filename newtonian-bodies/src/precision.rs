@@ -0,0 +1,472 @@
+use super::dynamics::Settings;
+use super::integrator::Integrator;
+use super::Body;
+use std::error::Error;
+use std::ops::{Add, Div, Mul, Sub};
+
+/// Largest number of bodies accepted by the reference integration, whose
+/// extended-precision arithmetic is an order of magnitude slower than f64.
+pub const MAX_BODIES: usize = 16;
+
+/// Difference between the f64 integration and the extended-precision reference
+/// after the step ending at `time`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Divergence {
+    pub time: f64,
+    /// Largest position difference over all bodies.
+    pub position: f64,
+    /// Largest position difference relative to the distance of that body from the origin.
+    pub relative: f64,
+    /// Largest velocity difference over all bodies.
+    pub velocity: f64,
+}
+
+/// Integrates the same bodies, steps and scheme twice: in f64 and in
+/// double-double arithmetic (about 32 significant digits).
+///
+/// Both runs use the same formulas, so their difference measures the rounding
+/// error the f64 simulation accumulates, independently of the truncation error
+/// of the integrator.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    fast: State<f64>,
+    exact: State<DoubleDouble>,
+    gravity: f64,
+    total_time: f64,
+    dt: f64,
+    integrator: Integrator,
+}
+
+impl Reference {
+    pub fn new(bodies: &[Body], settings: &Settings) -> Result<Self, Box<dyn Error>> {
+        if bodies.len() > MAX_BODIES {
+            return Err(format!(
+                "the precision check supports at most {} bodies, got {}",
+                MAX_BODIES,
+                bodies.len()
+            )
+            .into());
+        }
+        if settings.dt.is_nan() || settings.dt <= 0.0 {
+            return Err(format!("time step must be positive, got {}", settings.dt).into());
+        }
+        if settings.periodic.is_some()
+            || settings.cutoff.is_some()
+            || settings.sph.is_some()
+            || settings.tree.is_some()
+            || settings.halo.is_some()
+            || settings.far_field.is_some()
+            || settings.mass_transfer.is_some()
+        {
+            return Err(
+                "the precision check only supports direct gravity in open space, \
+                 without a cutoff, far field, halo or mass transfer"
+                    .into(),
+            );
+        }
+        if settings.contacts.is_some() || settings.script.is_some() || !settings.plugins.is_empty() {
+            return Err("the precision check doesn't support contacts, scripts or plugins".into());
+        }
+        if settings.console.is_some() {
+            // Changes typed in the console would steer the run but not the reference.
+            return Err("the precision check doesn't support the console".into());
+        }
+        if settings.regularization.is_some() {
+            return Err("the precision check doesn't support regularization".into());
+        }
+        if !settings.integrators.is_empty() {
+            return Err("the precision check needs a single integrator for all the bodies".into());
+        }
+        if settings.integrator == Integrator::PatchedConics {
+            return Err("the precision check needs the euler, verlet or rk4 integrator".into());
+        }
+        Ok(Reference {
+            fast: State::new(bodies),
+            exact: State::new(bodies),
+            gravity: settings.gravity,
+            total_time: settings.total_time.max(0.0),
+            dt: settings.dt,
+            integrator: settings.integrator,
+        })
+    }
+
+    /// Runs both integrations to the end, measuring their divergence at
+    /// `checkpoints` evenly spaced steps (the last one included).
+    pub fn run(mut self, checkpoints: usize) -> Vec<Divergence> {
+        let steps = (self.total_time / self.dt).ceil() as usize;
+        let checkpoints = checkpoints.clamp(1, steps.max(1));
+        let mut next = 1;
+        let mut report = Vec::with_capacity(checkpoints);
+        let mut time = 0.0;
+
+        for step in 0..steps {
+            // Same step times as `simulate_with`, including the shortened last step.
+            let end_time = if step + 1 == steps { self.total_time } else { (step + 1) as f64 * self.dt };
+            let h = end_time - time;
+            self.fast.step(self.integrator, self.gravity, h);
+            self.exact.step(self.integrator, self.gravity, h);
+            time = end_time;
+
+            if (step + 1) * checkpoints >= next * steps {
+                report.push(self.divergence(time));
+                next += 1;
+            }
+        }
+        if report.is_empty() {
+            report.push(self.divergence(time));
+        }
+        report
+    }
+
+    fn divergence(&self, time: f64) -> Divergence {
+        let mut divergence = Divergence {
+            time,
+            position: 0.0,
+            relative: 0.0,
+            velocity: 0.0,
+        };
+        for i in 0..self.fast.mass.len() {
+            let position = distance(&self.exact.position[i], &self.fast.position[i]);
+            let velocity = distance(&self.exact.velocity[i], &self.fast.velocity[i]);
+            let radius = norm(self.exact.position[i].map(DoubleDouble::to_f64));
+
+            divergence.position = divergence.position.max(position);
+            divergence.velocity = divergence.velocity.max(velocity);
+            if radius > 0.0 {
+                divergence.relative = divergence.relative.max(position / radius);
+            }
+        }
+        divergence
+    }
+}
+
+/// Distance between an extended-precision vector and its f64 counterpart,
+/// evaluated in extended precision before rounding.
+fn distance(exact: &[DoubleDouble; 3], fast: &[f64; 3]) -> f64 {
+    let d = [0, 1, 2].map(|k| (exact[k] - DoubleDouble::from_f64(fast[k])).to_f64());
+    norm(d)
+}
+
+fn norm(v: [f64; 3]) -> f64 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+/// Arithmetic needed by the integrators, implemented by both precisions.
+trait Real: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> {
+    fn from_f64(value: f64) -> Self;
+    fn sqrt(self) -> Self;
+}
+
+impl Real for f64 {
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn sqrt(self) -> Self {
+        f64::sqrt(self)
+    }
+}
+
+type Vec3<T> = [T; 3];
+
+#[derive(Debug, Clone)]
+struct State<T> {
+    mass: Vec<T>,
+    position: Vec<Vec3<T>>,
+    velocity: Vec<Vec3<T>>,
+}
+
+impl<T: Real> State<T> {
+    fn new(bodies: &[Body]) -> Self {
+        State {
+            mass: bodies.iter().map(|b| T::from_f64(b.mass)).collect(),
+            position: bodies
+                .iter()
+                .map(|b| [b.position.x, b.position.y, b.position.z].map(T::from_f64))
+                .collect(),
+            velocity: bodies
+                .iter()
+                .map(|b| [b.velocity.x, b.velocity.y, b.velocity.z].map(T::from_f64))
+                .collect(),
+        }
+    }
+
+    fn accelerations(&self, position: &[Vec3<T>], gravity: T) -> Vec<Vec3<T>> {
+        let zero = T::from_f64(0.0);
+        (0..position.len())
+            .map(|i| {
+                let mut a = [zero; 3];
+                for j in 0..position.len() {
+                    if i == j {
+                        continue;
+                    }
+                    let d = [0, 1, 2].map(|k| position[j][k] - position[i][k]);
+                    let r = (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt();
+                    let f = gravity * self.mass[j] / (r * r * r);
+                    for k in 0..3 {
+                        a[k] = a[k] + f * d[k];
+                    }
+                }
+                a
+            })
+            .collect()
+    }
+
+    fn step(&mut self, integrator: Integrator, gravity: f64, dt: f64) {
+        let gravity = T::from_f64(gravity);
+        let dt = T::from_f64(dt);
+        match integrator {
+            Integrator::Euler => {
+                let a = self.accelerations(&self.position, gravity);
+                for ((position, velocity), a) in self.position.iter_mut().zip(&mut self.velocity).zip(a) {
+                    for k in 0..3 {
+                        velocity[k] = velocity[k] + a[k] * dt;
+                        position[k] = position[k] + velocity[k] * dt;
+                    }
+                }
+            }
+            Integrator::Verlet => {
+                let half = dt / T::from_f64(2.0);
+                let a = self.accelerations(&self.position, gravity);
+                for ((position, velocity), a) in self.position.iter_mut().zip(&mut self.velocity).zip(a) {
+                    for k in 0..3 {
+                        velocity[k] = velocity[k] + a[k] * half;
+                        position[k] = position[k] + velocity[k] * dt;
+                    }
+                }
+                let a = self.accelerations(&self.position, gravity);
+                for (velocity, a) in self.velocity.iter_mut().zip(a) {
+                    for k in 0..3 {
+                        velocity[k] = velocity[k] + a[k] * half;
+                    }
+                }
+            }
+            Integrator::Rk4 => self.rk4_step(gravity, dt),
+            Integrator::PatchedConics => unreachable!("rejected by Reference::new"),
+        }
+    }
+
+    fn rk4_step(&mut self, gravity: T, dt: T) {
+        let half = dt / T::from_f64(2.0);
+        let offset = |base: &[Vec3<T>], slope: &[Vec3<T>], h: T| -> Vec<Vec3<T>> {
+            base.iter()
+                .zip(slope)
+                .map(|(b, s)| [0, 1, 2].map(|k| b[k] + s[k] * h))
+                .collect()
+        };
+
+        let v1 = self.velocity.clone();
+        let a1 = self.accelerations(&self.position, gravity);
+        let v2 = offset(&self.velocity, &a1, half);
+        let a2 = self.accelerations(&offset(&self.position, &v1, half), gravity);
+        let v3 = offset(&self.velocity, &a2, half);
+        let a3 = self.accelerations(&offset(&self.position, &v2, half), gravity);
+        let v4 = offset(&self.velocity, &a3, dt);
+        let a4 = self.accelerations(&offset(&self.position, &v3, dt), gravity);
+
+        let two = T::from_f64(2.0);
+        let sixth = dt / T::from_f64(6.0);
+        for i in 0..self.mass.len() {
+            for k in 0..3 {
+                self.position[i][k] =
+                    self.position[i][k] + (v1[i][k] + two * v2[i][k] + two * v3[i][k] + v4[i][k]) * sixth;
+                self.velocity[i][k] =
+                    self.velocity[i][k] + (a1[i][k] + two * a2[i][k] + two * a3[i][k] + a4[i][k]) * sixth;
+            }
+        }
+    }
+}
+
+/// An unevaluated sum `hi + lo` of two f64 values with `|lo| <= ulp(hi) / 2`,
+/// giving about 106 bits of mantissa.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DoubleDouble {
+    hi: f64,
+    lo: f64,
+}
+
+impl DoubleDouble {
+    fn to_f64(self) -> f64 {
+        self.hi + self.lo
+    }
+
+    /// Renormalizes `hi + lo` assuming `|hi| >= |lo|`.
+    fn quick_two_sum(hi: f64, lo: f64) -> Self {
+        let s = hi + lo;
+        DoubleDouble {
+            hi: s,
+            lo: lo - (s - hi),
+        }
+    }
+
+    /// Exact sum of two f64 values.
+    fn two_sum(a: f64, b: f64) -> (f64, f64) {
+        let s = a + b;
+        let bb = s - a;
+        (s, (a - (s - bb)) + (b - bb))
+    }
+}
+
+impl Real for DoubleDouble {
+    fn from_f64(value: f64) -> Self {
+        DoubleDouble { hi: value, lo: 0.0 }
+    }
+
+    fn sqrt(self) -> Self {
+        if self.hi <= 0.0 {
+            return DoubleDouble::from_f64(self.hi.sqrt());
+        }
+        // One Newton iteration from the f64 root doubles its precision.
+        let x = DoubleDouble::from_f64(self.hi.sqrt());
+        x + (self - x * x) / (x * DoubleDouble::from_f64(2.0))
+    }
+}
+
+impl Add for DoubleDouble {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let (s, e) = DoubleDouble::two_sum(self.hi, other.hi);
+        let (t, f) = DoubleDouble::two_sum(self.lo, other.lo);
+        let s = DoubleDouble::quick_two_sum(s, e + t);
+        DoubleDouble::quick_two_sum(s.hi, s.lo + f)
+    }
+}
+
+impl Sub for DoubleDouble {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self + DoubleDouble {
+            hi: -other.hi,
+            lo: -other.lo,
+        }
+    }
+}
+
+impl Mul for DoubleDouble {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let p = self.hi * other.hi;
+        let e = self.hi.mul_add(other.hi, -p);
+        DoubleDouble::quick_two_sum(p, e + (self.hi * other.lo + self.lo * other.hi))
+    }
+}
+
+impl Div for DoubleDouble {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        // Long division: an f64 quotient, then a correction from the remainder.
+        let q1 = self.hi / other.hi;
+        let r = self - other * DoubleDouble::from_f64(q1);
+        let q2 = r.hi / other.hi;
+        let r = r - other * DoubleDouble::from_f64(q2);
+        let q3 = r.hi / other.hi;
+        let q = DoubleDouble::quick_two_sum(q1, q2);
+        q + DoubleDouble::from_f64(q3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::{Tags, Vector};
+
+    #[test]
+    fn test_double_double_keeps_digits_f64_loses() {
+        let one = DoubleDouble::from_f64(1.0);
+        let tiny = DoubleDouble::from_f64(1e-20);
+        assert_eq!(((one + tiny) - one).to_f64(), 1e-20);
+
+        let third = one / DoubleDouble::from_f64(3.0);
+        let residual = (third * DoubleDouble::from_f64(3.0) - one).to_f64();
+        assert!(residual.abs() < 1e-30, "{}", residual);
+
+        let root = DoubleDouble::from_f64(2.0).sqrt();
+        assert!(((root * root) - DoubleDouble::from_f64(2.0)).to_f64().abs() < 1e-30);
+    }
+
+    #[test]
+    fn test_reference_reports_small_growing_divergence() {
+        let bodies = vec![
+            Body {
+                name: "Star".to_string(),
+                mass: 1.0,
+                position: Vector::null(),
+                velocity: Vector::null(),
+                acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: None,
+            },
+            Body {
+                name: "Planet".to_string(),
+                mass: 1e-6,
+                position: Vector { x: 1.0, y: 0.0, z: 0.0 },
+                velocity: Vector { x: 0.0, y: 1.0, z: 0.0 },
+                acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: None,
+            },
+        ];
+        let settings = Settings {
+            gravity: 1.0,
+            total_time: 10.0,
+            dt: 0.01,
+            integrator: Integrator::Rk4,
+            ..Settings::default()
+        };
+
+        let report = Reference::new(&bodies, &settings).unwrap().run(5);
+
+        assert_eq!(report.len(), 5);
+        assert!((report.last().unwrap().time - 10.0).abs() < 1e-12);
+        for divergence in &report {
+            assert!(divergence.position < 1e-10, "{:?}", divergence);
+        }
+        assert!(report.last().unwrap().position > 0.0);
+    }
+
+    #[test]
+    fn test_reference_rejects_large_systems() {
+        let bodies = vec![
+            Body {
+                name: "Dust".to_string(),
+                mass: 1.0,
+                position: Vector::null(),
+                velocity: Vector::null(),
+                acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: None,
+            };
+            MAX_BODIES + 1
+        ];
+        assert!(Reference::new(&bodies, &Settings::default()).is_err());
+    }
+
+    #[test]
+    fn test_reference_rejects_other_forces_and_steering() {
+        let bodies = [Body {
+            name: "Dust".to_string(),
+            mass: 1.0,
+            position: Vector::null(),
+            velocity: Vector::null(),
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }];
+        let error = |settings: Settings| Reference::new(&bodies, &settings).unwrap_err().to_string();
+        let far_field = Some(crate::far_field::FarField { radius: 1.0e13, every: 10 });
+        assert!(error(Settings { far_field, ..Settings::default() }).contains("far field"));
+        let contacts = Some(crate::contact::Contacts {
+            radii: [("Dust".to_string(), 1.0)].into(),
+            restitution: 1.0,
+            friction: 0.0,
+        });
+        assert!(error(Settings { contacts, ..Settings::default() }).contains("contacts"));
+        let console = Some(std::sync::Arc::new(crate::console::Console::local(false)));
+        assert!(error(Settings { console, ..Settings::default() }).contains("console"));
+        assert!(Reference::new(&bodies, &Settings::default()).is_ok());
+    }
+}
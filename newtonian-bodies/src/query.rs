@@ -0,0 +1,250 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the view of the outputs given without one.
+pub const DEFAULT_TABLE: &str = "run";
+
+/// An output file queried as a view of its own name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// The result of a query, with every value as text (NULL as an empty string).
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Rows {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Runs `sql` with DuckDB against the outputs of `tables`, each a view of the
+/// rows of its file: Parquet (or a partitioned output directory) or CSV.
+/// Tables sharing a name are stacked, with the file of each row in a `file`
+/// column.
+pub fn query(sql: &str, tables: &[Table]) -> Result<Rows, Box<dyn Error>> {
+    let views = views(tables)?;
+    #[cfg(not(feature = "duckdb"))]
+    {
+        let _ = (sql, views);
+        Err("queries need the duckdb feature (cargo build --features duckdb)".into())
+    }
+
+    #[cfg(feature = "duckdb")]
+    {
+        let connection = duckdb::Connection::open_in_memory()?;
+        connection.execute_batch(&views)?;
+        let mut statement = connection.prepare(sql)?;
+        let mut rows = statement.query([])?;
+        let mut result = Rows {
+            columns: rows.as_ref().map(|statement| statement.column_names()).unwrap_or_default(),
+            rows: Vec::new(),
+        };
+        while let Some(row) = rows.next()? {
+            let values = (0..result.columns.len())
+                .map(|i| row.get::<_, duckdb::types::Value>(i).map(|value| text(&value)))
+                .collect::<Result<_, _>>()?;
+            result.rows.push(values);
+        }
+        Ok(result)
+    }
+}
+
+/// A value as text: numbers as Rust prints them, so floats read back exactly.
+#[cfg(feature = "duckdb")]
+fn text(value: &duckdb::types::Value) -> String {
+    use duckdb::types::Value;
+    match value {
+        Value::Null => String::new(),
+        Value::Boolean(value) => value.to_string(),
+        Value::TinyInt(value) => value.to_string(),
+        Value::SmallInt(value) => value.to_string(),
+        Value::Int(value) => value.to_string(),
+        Value::BigInt(value) => value.to_string(),
+        Value::HugeInt(value) => value.to_string(),
+        Value::UTinyInt(value) => value.to_string(),
+        Value::USmallInt(value) => value.to_string(),
+        Value::UInt(value) => value.to_string(),
+        Value::UBigInt(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Double(value) => value.to_string(),
+        Value::Decimal(value) => value.to_string(),
+        Value::Text(value) => value.clone(),
+        other => format!("{:?}", other),
+    }
+}
+
+/// SQL creating a view per table name.
+fn views(tables: &[Table]) -> Result<String, Box<dyn Error>> {
+    let mut names: Vec<&str> = tables.iter().map(|table| table.name.as_str()).collect();
+    names.dedup();
+    let mut sql = String::new();
+    for (i, name) in names.iter().enumerate() {
+        if names[..i].contains(name) {
+            return Err(format!("the files of table '{}' must be given together", name).into());
+        }
+        let files: Vec<&Path> = tables.iter().filter(|table| table.name == *name).map(|table| table.path.as_path()).collect();
+        let selects: Vec<String> = match files[..] {
+            [path] => vec![format!("SELECT * FROM {}", source(path))],
+            _ => files
+                .iter()
+                .map(|path| format!("SELECT *, {} AS file FROM {}", literal(&path.to_string_lossy()), source(path)))
+                .collect(),
+        };
+        sql.push_str(&format!("CREATE VIEW {} AS {};\n", identifier(name), selects.join(" UNION ALL BY NAME ")));
+    }
+    Ok(sql)
+}
+
+/// The DuckDB table function reading the rows of an output.
+fn source(path: &Path) -> String {
+    if path.is_dir() {
+        // A partitioned output, with the partition column from the directory names.
+        let files = path.join("*").join("*.parquet");
+        return format!("read_parquet({}, hive_partitioning = true)", literal(&files.to_string_lossy()));
+    }
+    let file = literal(&path.to_string_lossy());
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("csv") => format!("read_csv({}, header = true)", file),
+        _ => format!("read_parquet({})", file),
+    }
+}
+
+fn literal(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+fn identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+impl Rows {
+    /// Writes the rows as CSV, quoting the values that need it.
+    pub fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        let line = |values: &[String]| values.iter().map(|value| quote(value)).collect::<Vec<_>>().join(",");
+        writeln!(out, "{}", line(&self.columns))?;
+        for row in &self.rows {
+            writeln!(out, "{}", line(row))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the rows as a table with aligned columns, numbers to the right.
+    pub fn write_table(&self, out: &mut impl Write) -> io::Result<()> {
+        let widths: Vec<usize> = (0..self.columns.len())
+            .map(|c| self.rows.iter().map(|row| row[c].chars().count()).chain([self.columns[c].chars().count()]).max().unwrap_or(0))
+            .collect();
+        let numeric: Vec<bool> = (0..self.columns.len())
+            .map(|c| !self.rows.is_empty() && self.rows.iter().all(|row| row[c].is_empty() || row[c].parse::<f64>().is_ok()))
+            .collect();
+        for row in [&self.columns].into_iter().chain(&self.rows) {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(widths.iter().zip(&numeric))
+                .map(|(value, (&width, &numeric))| match numeric {
+                    true => format!("{:>width$}", value),
+                    false => format!("{:<width$}", value),
+                })
+                .collect();
+            writeln!(out, "{}", cells.join("  ").trim_end())?;
+        }
+        writeln!(out, "({} row{})", self.rows.len(), if self.rows.len() == 1 { "" } else { "s" })
+    }
+}
+
+fn quote(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(name: &str, path: &str) -> Table {
+        Table {
+            name: name.to_string(),
+            path: PathBuf::from(path),
+        }
+    }
+
+    #[test]
+    fn test_views_of_the_outputs() {
+        let sql = views(&[table("run", "a.parquet"), table("moon", "it's.csv")]).unwrap();
+        assert_eq!(
+            sql,
+            "CREATE VIEW \"run\" AS SELECT * FROM read_parquet('a.parquet');\n\
+             CREATE VIEW \"moon\" AS SELECT * FROM read_csv('it''s.csv', header = true);\n"
+        );
+        let sql = views(&[table("run", "a.parquet"), table("run", "b.parquet")]).unwrap();
+        assert_eq!(
+            sql,
+            "CREATE VIEW \"run\" AS SELECT *, 'a.parquet' AS file FROM read_parquet('a.parquet') \
+             UNION ALL BY NAME SELECT *, 'b.parquet' AS file FROM read_parquet('b.parquet');\n"
+        );
+        assert!(views(&[table("run", "a.parquet"), table("moon", "m.parquet"), table("run", "b.parquet")]).is_err());
+    }
+
+    #[test]
+    fn test_writes_tables_and_csv() {
+        let rows = Rows {
+            columns: vec!["name".to_string(), "max(pos_x)".to_string()],
+            rows: vec![vec!["Moon".to_string(), "3.8e8".to_string()], vec!["Comet, Halley".to_string(), String::new()]],
+        };
+        let mut table = Vec::new();
+        rows.write_table(&mut table).unwrap();
+        assert_eq!(
+            String::from_utf8(table).unwrap(),
+            "name           max(pos_x)\nMoon                3.8e8\nComet, Halley\n(2 rows)\n"
+        );
+        let mut csv = Vec::new();
+        rows.write_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "name,max(pos_x)\nMoon,3.8e8\n\"Comet, Halley\",\n");
+    }
+
+    #[cfg(not(feature = "duckdb"))]
+    #[test]
+    fn test_queries_need_duckdb() {
+        assert!(query("SELECT 1", &[]).is_err());
+    }
+
+    #[cfg(feature = "duckdb")]
+    #[test]
+    fn test_query_of_a_recorded_run() {
+        use crate::body::{Tags, Vector};
+        use crate::dynamics::SequentialWriter;
+        use crate::writer::Writer;
+        use crate::Body;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("run.parquet");
+        let body = |name: &str, x: f64| Body {
+            name: name.to_string(),
+            mass: 1.0,
+            position: Vector::new(x, 0.0, 0.0),
+            velocity: Vector::null(),
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        };
+        let mut writer = Writer::new(path.clone()).unwrap();
+        for time in 0..3 {
+            let x = time as f64;
+            writer.add(x, &[body("Earth", x), body("Moon", 10.0 * x)]).unwrap();
+        }
+        writer.close().unwrap();
+
+        let tables = [Table {
+            name: DEFAULT_TABLE.to_string(),
+            path,
+        }];
+        let rows = query("SELECT name, max(pos_x) AS x FROM run GROUP BY name ORDER BY name", &tables).unwrap();
+        assert_eq!(rows.columns, ["name", "x"]);
+        assert_eq!(rows.rows, [["Earth", "2"], ["Moon", "20"]]);
+        assert!(query("SELECT nothing FROM run", &tables).is_err());
+    }
+}
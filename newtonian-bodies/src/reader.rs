@@ -0,0 +1,534 @@
+use super::body::{Tags, Vector};
+use super::schema::{self, Columns};
+use super::Body;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fs::File;
+use std::io::Seek;
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow::array::{Array, ArrayRef, BooleanArray, Float64Array, StringArray};
+use arrow::compute::cast;
+use arrow::csv::reader::Format;
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+type Batches = Box<dyn Iterator<Item = Result<RecordBatch, Box<dyn Error>>>>;
+
+/// State of one body at one recorded time.
+///
+/// For version 1 files `time` holds the step index rather than seconds, and
+/// the velocity is zero because it was not recorded.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub time: f64,
+    pub body: Body,
+}
+
+/// Snapshot of every body recorded at the same time.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub time: f64,
+    pub bodies: Vec<Body>,
+}
+
+/// Iterates the frames of a simulation output, one snapshot at a time.
+///
+/// Parquet files of every supported schema version are read, as well as CSV
+/// files using the same column names (e.g. exported from a Parquet output).
+pub struct SimulationReader {
+    batches: Batches,
+    columns: Columns,
+    pending: VecDeque<Record>,
+    tags: BTreeMap<String, Tags>,
+    /// Masses of files written without the `mass` column.
+    masses: BTreeMap<String, f64>,
+    /// Last absolute position of each body, in delta-encoded files.
+    positions: HashMap<String, Vector>,
+}
+
+impl SimulationReader {
+    /// Opens an output file, choosing the format from its extension.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => Self::from_csv(path),
+            _ => Self::from_parquet(path),
+        }
+    }
+
+    pub fn from_parquet(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        let metadata = builder.metadata().file_metadata().key_value_metadata();
+        let version = schema::version_from_metadata(metadata)?;
+        let tags = schema::tags_from_metadata(metadata)?;
+        let masses = schema::masses_from_metadata(metadata)?;
+        let columns = Columns::resolve(builder.schema(), version)?;
+        let batches = builder.build()?.map(|batch| batch.map_err(Into::into));
+
+        Ok(Self {
+            tags,
+            masses,
+            ..Self::new(Box::new(batches), columns)
+        })
+    }
+
+    pub fn from_csv(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut file = File::open(path)?;
+        let (csv_schema, _) = Format::default().with_header(true).infer_schema(&mut file, None)?;
+        file.rewind()?;
+
+        // CSV carries no metadata, so only the version 1 columns are required.
+        let columns = Columns::resolve(&csv_schema, 1)?;
+        let batches = arrow::csv::ReaderBuilder::new(Arc::new(csv_schema))
+            .with_header(true)
+            .build(file)?
+            .map(|batch| batch.map_err(Into::into));
+
+        Ok(Self::new(Box::new(batches), columns))
+    }
+
+    fn new(batches: Batches, columns: Columns) -> Self {
+        SimulationReader {
+            batches,
+            columns,
+            pending: VecDeque::new(),
+            tags: BTreeMap::new(),
+            masses: BTreeMap::new(),
+            positions: HashMap::new(),
+        }
+    }
+
+    /// Whether the file recorded velocities, which interpolation relies on.
+    pub fn has_velocities(&self) -> bool {
+        self.columns.has_velocities()
+    }
+
+    /// Flattens the remaining frames into one record per body and time.
+    pub fn records(self) -> impl Iterator<Item = Result<Record, Box<dyn Error>>> {
+        self.flat_map(|frame| -> Vec<Result<Record, Box<dyn Error>>> {
+            match frame {
+                Ok(frame) => frame
+                    .bodies
+                    .into_iter()
+                    .map(|body| Ok(Record { time: frame.time, body }))
+                    .collect(),
+                Err(e) => vec![Err(e)],
+            }
+        })
+    }
+
+    /// Turns the position differences of delta-encoded rows into absolute positions.
+    fn decode_deltas(&mut self, batch: &RecordBatch, column: usize, records: &mut [Record]) -> Result<(), Box<dyn Error>> {
+        let delta = downcast::<BooleanArray>(batch.column(column), "delta")?;
+        for (row, record) in records.iter_mut().enumerate() {
+            let body = &mut record.body;
+            if delta.value(row) {
+                let previous = self
+                    .positions
+                    .get(&body.name)
+                    .ok_or_else(|| format!("'{}' has a position difference before any absolute position", body.name))?;
+                body.position.x += previous.x;
+                body.position.y += previous.y;
+                body.position.z += previous.z;
+            }
+            self.positions.insert(body.name.clone(), body.position);
+        }
+        Ok(())
+    }
+
+    /// Buffers batches until a complete frame (or the end of the file) is available.
+    fn fill(&mut self) -> Result<bool, Box<dyn Error>> {
+        loop {
+            if let Some(first) = self.pending.front()
+                && self.pending.iter().any(|r| r.time != first.time)
+            {
+                return Ok(true);
+            }
+            match self.batches.next() {
+                Some(batch) => {
+                    let batch = batch?;
+                    let mut records = batch_to_records(&batch, &self.columns)?;
+                    if let Some(delta) = self.columns.delta {
+                        self.decode_deltas(&batch, delta, &mut records)?;
+                    }
+                    self.pending.extend(records);
+                }
+                None => return Ok(!self.pending.is_empty()),
+            }
+        }
+    }
+}
+
+impl Iterator for SimulationReader {
+    type Item = Result<Frame, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.fill() {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => return Some(Err(e)),
+        }
+
+        let time = self.pending.front()?.time;
+        let mut bodies = Vec::new();
+        while self.pending.front().is_some_and(|r| r.time == time) {
+            bodies.extend(self.pending.pop_front().map(|r| r.body));
+        }
+        for body in &mut bodies {
+            if let Some(tags) = self.tags.get(&body.name) {
+                body.tags = tags.clone();
+            }
+            if let Some(&mass) = self.masses.get(&body.name) {
+                body.mass = mass;
+            }
+        }
+        Some(Ok(Frame { time, bodies }))
+    }
+}
+
+/// The frame of an output file recorded closest to `time`, e.g., to start a
+/// new run from it. Files without velocities can't give one.
+pub fn frame_at(path: &Path, time: f64) -> Result<Frame, Box<dyn Error>> {
+    let reader = SimulationReader::open(path)?;
+    if !reader.has_velocities() {
+        return Err(format!("{} has no velocities to start a run from", path.display()).into());
+    }
+    let mut nearest: Option<Frame> = None;
+    for frame in reader {
+        let frame = frame?;
+        // Frames come in order, so the distance only grows past the closest.
+        if nearest.as_ref().is_some_and(|nearest| (nearest.time - time).abs() <= (frame.time - time).abs()) {
+            break;
+        }
+        nearest = Some(frame);
+    }
+    nearest.ok_or_else(|| format!("{} has no frames", path.display()).into())
+}
+
+/// Loads every row of an output file, whatever schema version wrote it.
+pub fn read_records(path: &Path) -> Result<Vec<Record>, Box<dyn Error>> {
+    SimulationReader::open(path)?.records().collect()
+}
+
+/// Converts the rows of a batch into records using the resolved column positions.
+pub fn batch_to_records(batch: &RecordBatch, columns: &Columns) -> Result<Vec<Record>, Box<dyn Error>> {
+    let time = float_column(batch, columns.time, "time")?;
+    let name = cast(batch.column(columns.name), &DataType::Utf8)?;
+    let name = downcast::<StringArray>(&name, "name")?;
+    let mass = columns.mass.map(|mass| float_column(batch, mass, "mass")).transpose()?;
+    let pos_x = float_column(batch, columns.pos_x, "pos_x")?;
+    let pos_y = float_column(batch, columns.pos_y, "pos_y")?;
+    let pos_z = float_column(batch, columns.pos_z, "pos_z")?;
+    let velocity = match (columns.vel_x, columns.vel_y, columns.vel_z) {
+        (Some(x), Some(y), Some(z)) => Some((
+            float_column(batch, x, "vel_x")?,
+            float_column(batch, y, "vel_y")?,
+            float_column(batch, z, "vel_z")?,
+        )),
+        _ => None,
+    };
+    let temperature = columns
+        .temperature
+        .map(|temperature| float_column(batch, temperature, "temperature"))
+        .transpose()?;
+
+    Ok((0..batch.num_rows())
+        .map(|row| Record {
+            time: time.value(row),
+            body: Body {
+                name: name.value(row).to_string(),
+                // Filled in from the file metadata when the column is left out.
+                mass: mass.as_ref().map_or(0.0, |mass| mass.value(row)),
+                position: Vector {
+                    x: pos_x.value(row),
+                    y: pos_y.value(row),
+                    z: pos_z.value(row),
+                },
+                velocity: match &velocity {
+                    Some((x, y, z)) => Vector {
+                        x: x.value(row),
+                        y: y.value(row),
+                        z: z.value(row),
+                    },
+                    None => Vector::null(),
+                },
+                acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: temperature.as_ref().filter(|t| t.is_valid(row)).map(|t| t.value(row)),
+            },
+        })
+        .collect())
+}
+
+fn float_column(batch: &RecordBatch, index: usize, name: &str) -> Result<Float64Array, Box<dyn Error>> {
+    let array = cast(batch.column(index), &DataType::Float64)?;
+    Ok(downcast::<Float64Array>(&array, name)?.clone())
+}
+
+fn downcast<'a, T: 'static>(array: &'a ArrayRef, name: &str) -> Result<&'a T, Box<dyn Error>> {
+    array
+        .as_any()
+        .downcast_ref::<T>()
+        .ok_or_else(|| format!("column '{}' has unexpected type {}", name, array.data_type()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamics::SequentialWriter;
+    use crate::schema::{Layout, Precision};
+    use crate::writer::Writer;
+    use arrow::array::UInt64Array;
+    use arrow::datatypes::{Field, Schema};
+    use parquet::arrow::ArrowWriter;
+    use tempfile::TempDir;
+
+    fn create_test_body(name: &str, x: f64) -> Body {
+        Body {
+            name: name.to_string(),
+            mass: 1.0e24,
+            position: Vector { x, y: 2.0, z: 3.0 },
+            velocity: Vector { x: -x, y: 0.5, z: 0.0 },
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    #[test]
+    fn test_reads_back_what_the_writer_produced() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("round_trip.parquet");
+
+        let mut writer = Writer::new(path.clone()).unwrap();
+        writer.add(0.0, &[create_test_body("Earth", 1.0), create_test_body("Moon", 4.0)]).unwrap();
+        writer.add(10.0, &[create_test_body("Earth", 5.0), create_test_body("Moon", 6.0)]).unwrap();
+        writer.close().unwrap();
+
+        let records = read_records(&path).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[2].time, 10.0);
+        assert_eq!(records[2].body.name, "Earth");
+        assert_eq!(records[2].body.position.x, 5.0);
+        assert_eq!(records[2].body.velocity.x, -5.0);
+        assert_eq!(records[3].body.position.z, 3.0);
+        assert_eq!(records[3].body.velocity.y, 0.5);
+    }
+
+    #[test]
+    fn test_body_tags_survive_the_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("tags.parquet");
+        let mut ceres = create_test_body("Ceres", 1.0);
+        ceres.tags.insert("category".to_string(), "asteroid".to_string());
+
+        let mut writer = Writer::new(path.clone()).unwrap();
+        writer.add(0.0, &[create_test_body("Earth", 1.0), ceres]).unwrap();
+        writer.close().unwrap();
+
+        let records = read_records(&path).unwrap();
+        assert!(records[0].body.tags.is_empty());
+        assert_eq!(records[1].body.tags["category"], "asteroid");
+    }
+
+    #[test]
+    fn test_reads_pruned_single_precision_output() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("pruned.parquet");
+        let layout = Layout {
+            precision: Precision::Single,
+            mass: false,
+            velocities: false,
+            keyframe_interval: None,
+            temperature: false,
+        };
+
+        let mut writer = Writer::with_layout(path.clone(), layout).unwrap();
+        writer.add(0.0, &[create_test_body("Earth", 1.0 / 3.0)]).unwrap();
+        writer.close().unwrap();
+
+        let reader = SimulationReader::open(&path).unwrap();
+        assert!(!reader.has_velocities());
+        let records: Vec<Record> = reader.records().collect::<Result<_, _>>().unwrap();
+        assert_eq!(records[0].body.mass, 1.0e24);
+        assert_eq!(records[0].body.position.x, (1.0f32 / 3.0) as f64);
+        assert_eq!(records[0].body.velocity.x, 0.0);
+    }
+
+    #[test]
+    fn test_reads_temperatures() {
+        let temp_dir = TempDir::new().unwrap();
+        let layout = Layout {
+            temperature: true,
+            ..Layout::default()
+        };
+        let mut warm = create_test_body("Earth", 1.0);
+        warm.temperature = Some(288.0);
+        for file in ["thermal.parquet", "thermal.csv"] {
+            let path = temp_dir.path().join(file);
+            let bodies = [warm.clone(), create_test_body("Moon", 2.0)];
+            if file.ends_with(".csv") {
+                let mut writer = crate::writer::CsvWriter::new(path.clone(), layout).unwrap();
+                writer.add(0.0, &bodies).unwrap();
+                writer.close().unwrap();
+            } else {
+                let mut writer = Writer::with_layout(path.clone(), layout).unwrap();
+                writer.add(0.0, &bodies).unwrap();
+                writer.close().unwrap();
+            }
+
+            let records = read_records(&path).unwrap();
+            assert_eq!(records[0].body.temperature, Some(288.0));
+            assert_eq!(records[1].body.temperature, None);
+        }
+    }
+
+    #[test]
+    fn test_delta_encoded_positions_are_reconstructed() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("delta.parquet");
+        let layout = Layout {
+            precision: Precision::Decimals(2),
+            keyframe_interval: Some(3),
+            ..Layout::default()
+        };
+
+        let mut writer = Writer::with_layout(path.clone(), layout).unwrap();
+        for step in 0..7 {
+            let x = 1.0e6 + 0.123 * step as f64;
+            writer.add(step as f64, &[create_test_body("Earth", x), create_test_body("Moon", -x)]).unwrap();
+        }
+        writer.close().unwrap();
+
+        let records = read_records(&path).unwrap();
+        assert_eq!(records.len(), 14);
+        for (step, pair) in records.chunks(2).enumerate() {
+            let x = Precision::Decimals(2).round(1.0e6 + 0.123 * step as f64);
+            assert!((pair[0].body.position.x - x).abs() < 1e-6, "{} != {}", pair[0].body.position.x, x);
+            assert!((pair[1].body.position.x + x).abs() < 1e-6);
+            assert_eq!(pair[0].body.position.y, 2.0);
+        }
+
+        // Between keyframes the stored values are the small differences.
+        let file = File::open(&path).unwrap();
+        let batch = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap().next().unwrap().unwrap();
+        let columns = Columns::resolve(batch.schema().as_ref(), 4).unwrap();
+        let stored = batch_to_records(&batch, &columns).unwrap();
+        assert_eq!(stored[0].body.position.x, 1.0e6);
+        assert!((stored[2].body.position.x - 0.12).abs() < 1e-9);
+        assert_eq!(stored[2].body.position.y, 0.0);
+        assert_eq!(stored[6].body.position.x, Precision::Decimals(2).round(1.0e6 + 0.369));
+    }
+
+    #[test]
+    fn test_iterates_frames_in_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("frames.parquet");
+
+        let mut writer = Writer::new(path.clone()).unwrap();
+        for time in 0..3 {
+            let x = time as f64;
+            writer.add(x, &[create_test_body("Earth", x), create_test_body("Moon", -x)]).unwrap();
+        }
+        writer.close().unwrap();
+
+        let frames: Vec<Frame> = SimulationReader::open(&path)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(frames.len(), 3);
+        for (time, frame) in frames.iter().enumerate() {
+            assert_eq!(frame.time, time as f64);
+            assert_eq!(frame.bodies.len(), 2);
+            assert_eq!(frame.bodies[1].name, "Moon");
+            assert_eq!(frame.bodies[1].position.x, -(time as f64));
+        }
+    }
+
+    #[test]
+    fn test_frame_closest_to_a_time() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("frames.parquet");
+
+        let mut writer = Writer::new(path.clone()).unwrap();
+        for time in [0.0, 10.0, 20.0, 30.0] {
+            writer.add(time, &[create_test_body("Earth", time), create_test_body("Moon", -time)]).unwrap();
+        }
+        writer.close().unwrap();
+
+        for (time, expected) in [(-5.0, 0.0), (12.0, 10.0), (18.0, 20.0), (30.0, 30.0), (1e9, 30.0)] {
+            let frame = frame_at(&path, time).unwrap();
+            assert_eq!(frame.time, expected);
+            assert_eq!(frame.bodies[1].position.x, -expected);
+            assert_eq!(frame.bodies[1].velocity.x, expected);
+        }
+
+        let csv = temp_dir.path().join("v1.csv");
+        std::fs::write(&csv, "time,name,mass,pos_x,pos_y,pos_z\n0,Earth,1,2,3,4\n").unwrap();
+        assert!(frame_at(&csv, 0.0).unwrap_err().to_string().contains("no velocities"));
+    }
+
+    #[test]
+    fn test_reads_csv_outputs() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("frames.csv");
+        std::fs::write(
+            &path,
+            "time,name,mass,pos_x,pos_y,pos_z\n\
+             0,Earth,5.972e24,1.496e11,0,0\n\
+             0,Moon,7.342e22,1.4998e11,0,0\n\
+             60,Earth,5.972e24,1.496e11,1.5,0\n",
+        )
+        .unwrap();
+
+        let frames: Vec<Frame> = SimulationReader::open(&path)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].bodies.len(), 2);
+        assert_eq!(frames[1].time, 60.0);
+        assert_eq!(frames[1].bodies[0].position.y, 1.5);
+    }
+
+    #[test]
+    fn test_reads_unversioned_v1_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("v1.parquet");
+
+        // Written the way files were produced before the version was recorded.
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("time", DataType::UInt64, false),
+            Field::new("name", DataType::Utf8, false),
+            Field::new("mass", DataType::Float64, false),
+            Field::new("pos_x", DataType::Float64, false),
+            Field::new("pos_y", DataType::Float64, false),
+            Field::new("pos_z", DataType::Float64, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(UInt64Array::from(vec![7])),
+                Arc::new(StringArray::from(vec!["Sun"])),
+                Arc::new(Float64Array::from(vec![1.989e30])),
+                Arc::new(Float64Array::from(vec![1.0])),
+                Arc::new(Float64Array::from(vec![2.0])),
+                Arc::new(Float64Array::from(vec![3.0])),
+            ],
+        )
+        .unwrap();
+        let mut writer = ArrowWriter::try_new(File::create(&path).unwrap(), schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let records = read_records(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].time, 7.0);
+        assert_eq!(records[0].body.name, "Sun");
+        assert_eq!(records[0].body.position.y, 2.0);
+        assert_eq!(records[0].body.velocity.x, 0.0);
+    }
+}
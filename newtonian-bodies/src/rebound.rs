@@ -0,0 +1,244 @@
+use super::body::Vector;
+use super::Body;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Start of the 64-byte header of REBOUND binary files and SimulationArchives.
+const HEADER_PREFIX: &str = "REBOUND Binary File. Version: ";
+const HEADER_SIZE: usize = 64;
+/// REBOUND release whose layout is written; readers of other versions warn but still load it.
+const WRITTEN_VERSION: &str = "3.28.0";
+
+// Field types of `enum REB_BINARY_FIELD_TYPE` used here; every other field is skipped.
+const FIELD_T: u32 = 0;
+const FIELD_G: u32 = 1;
+const FIELD_N: u32 = 4;
+const FIELD_PARTICLES: u32 = 85;
+const FIELD_END: u32 = 9999;
+
+/// `struct reb_binary_field`: a u32 type, 4 bytes of padding and a u64 size.
+const FIELD_HEADER_SIZE: usize = 16;
+/// `struct reb_particle` on 64-bit platforms: 12 doubles, 3 pointers and a padded u32 hash.
+const PARTICLE_SIZE: usize = 128;
+const PARTICLE_HASH_OFFSET: usize = 104;
+
+/// State of a REBOUND simulation: its time, gravitational constant and particles.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub time: f64,
+    pub gravity: f64,
+    pub bodies: Vec<Body>,
+}
+
+/// Reads the first snapshot of a REBOUND binary file or SimulationArchive.
+///
+/// Later snapshots of an archive are stored as differences to the first one
+/// and are not decoded. REBOUND particles have no names, so bodies are named
+/// `particle<index>`.
+pub fn read(path: &Path) -> Result<Snapshot, Box<dyn Error>> {
+    read_from(BufReader::new(File::open(path)?))
+}
+
+/// Writes a snapshot as a REBOUND binary file, loadable with `rebound.Simulation(path)`.
+///
+/// Each particle's hash is that of the body name, so `sim.particles["Earth"]` works.
+pub fn write(path: &Path, snapshot: &Snapshot) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_to(&mut writer, snapshot)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn read_from(mut reader: impl Read) -> Result<Snapshot, Box<dyn Error>> {
+    let mut header = [0u8; HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+    if !header.starts_with(HEADER_PREFIX.as_bytes()) {
+        return Err("not a REBOUND binary file".into());
+    }
+
+    let mut snapshot = Snapshot {
+        time: 0.0,
+        gravity: 1.0,
+        bodies: Vec::new(),
+    };
+    let mut count = None;
+    loop {
+        let mut field = [0u8; FIELD_HEADER_SIZE];
+        reader.read_exact(&mut field)?;
+        let kind = u32::from_le_bytes(field[0..4].try_into()?);
+        let size = u64::from_le_bytes(field[8..16].try_into()?);
+        if kind == FIELD_END {
+            break;
+        }
+
+        // The size comes from the file, so read what is there rather than allocating it up front.
+        let mut data = Vec::new();
+        reader.by_ref().take(size).read_to_end(&mut data)?;
+        if (data.len() as u64) < size {
+            return Err(format!("REBOUND field of {} bytes is cut short at {}", size, data.len()).into());
+        }
+        match kind {
+            FIELD_T => snapshot.time = f64_at(&data, 0)?,
+            FIELD_G => snapshot.gravity = f64_at(&data, 0)?,
+            FIELD_N => {
+                let bytes = data.get(0..4).ok_or("truncated REBOUND field")?;
+                count = Some(u32::from_le_bytes(bytes.try_into()?) as usize);
+            }
+            FIELD_PARTICLES => {
+                if !data.len().is_multiple_of(PARTICLE_SIZE) {
+                    return Err(format!("unexpected particle data size {}", size).into());
+                }
+                snapshot.bodies = data
+                    .chunks(PARTICLE_SIZE)
+                    .enumerate()
+                    .map(|(i, particle)| particle_to_body(i, particle))
+                    .collect::<Result<_, _>>()?;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(count) = count
+        && count != snapshot.bodies.len()
+    {
+        return Err(format!("file declares {} particles but stores {}", count, snapshot.bodies.len()).into());
+    }
+    Ok(snapshot)
+}
+
+pub fn write_to(mut writer: impl Write, snapshot: &Snapshot) -> Result<(), Box<dyn Error>> {
+    let mut header = [0u8; HEADER_SIZE];
+    let text = format!("{}{}", HEADER_PREFIX, WRITTEN_VERSION);
+    header[..text.len()].copy_from_slice(text.as_bytes());
+    writer.write_all(&header)?;
+
+    write_field(&mut writer, FIELD_T, &snapshot.time.to_le_bytes())?;
+    write_field(&mut writer, FIELD_G, &snapshot.gravity.to_le_bytes())?;
+    write_field(&mut writer, FIELD_N, &(snapshot.bodies.len() as u32).to_le_bytes())?;
+    let particles: Vec<u8> = snapshot.bodies.iter().flat_map(body_to_particle).collect();
+    write_field(&mut writer, FIELD_PARTICLES, &particles)?;
+    write_field(&mut writer, FIELD_END, &[])?;
+    Ok(())
+}
+
+fn write_field(writer: &mut impl Write, kind: u32, data: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut field = [0u8; FIELD_HEADER_SIZE];
+    field[0..4].copy_from_slice(&kind.to_le_bytes());
+    field[8..16].copy_from_slice(&(data.len() as u64).to_le_bytes());
+    writer.write_all(&field)?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+fn f64_at(data: &[u8], offset: usize) -> Result<f64, Box<dyn Error>> {
+    let bytes = data.get(offset..offset + 8).ok_or("truncated REBOUND field")?;
+    Ok(f64::from_le_bytes(bytes.try_into()?))
+}
+
+fn particle_to_body(index: usize, particle: &[u8]) -> Result<Body, Box<dyn Error>> {
+    let value = |i: usize| f64_at(particle, i * 8);
+    Ok(Body::new(
+        format!("particle{}", index),
+        value(9)?,
+        Vector::new(value(0)?, value(1)?, value(2)?),
+        Vector::new(value(3)?, value(4)?, value(5)?),
+    ))
+}
+
+fn body_to_particle(body: &Body) -> Vec<u8> {
+    let mut particle = vec![0u8; PARTICLE_SIZE];
+    let values = [
+        body.position.x,
+        body.position.y,
+        body.position.z,
+        body.velocity.x,
+        body.velocity.y,
+        body.velocity.z,
+        body.acceleration.x,
+        body.acceleration.y,
+        body.acceleration.z,
+        body.mass,
+    ];
+    for (i, value) in values.iter().enumerate() {
+        particle[i * 8..i * 8 + 8].copy_from_slice(&value.to_le_bytes());
+    }
+    // Radius and last collision time stay zero, pointers are null.
+    particle[PARTICLE_HASH_OFFSET..PARTICLE_HASH_OFFSET + 4].copy_from_slice(&hash(&body.name).to_le_bytes());
+    particle
+}
+
+/// REBOUND's `reb_hash`: the djb2 string hash.
+fn hash(name: &str) -> u32 {
+    name.bytes()
+        .fold(5381u32, |hash, c| hash.wrapping_mul(33).wrapping_add(c as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            time: 12.5,
+            gravity: 6.67430e-11,
+            bodies: vec![
+                Body::new("Sun", 1.989e30, Vector::null(), Vector::null()),
+                Body::new("Earth", 5.972e24, Vector::new(1.496e11, 0.0, 1.0), Vector::new(0.0, 29780.0, -1.0)),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut bytes = Vec::new();
+        write_to(&mut bytes, &snapshot()).unwrap();
+        assert_eq!(bytes.len(), HEADER_SIZE + 5 * FIELD_HEADER_SIZE + 8 + 8 + 4 + 2 * PARTICLE_SIZE);
+
+        let read = read_from(bytes.as_slice()).unwrap();
+        assert_eq!(read.time, 12.5);
+        assert_eq!(read.gravity, 6.67430e-11);
+        assert_eq!(read.bodies.len(), 2);
+        assert_eq!(read.bodies[1].name, "particle1");
+        assert_eq!(read.bodies[1].mass, 5.972e24);
+        assert_eq!(read.bodies[1].position.x, 1.496e11);
+        assert_eq!(read.bodies[1].velocity.z, -1.0);
+    }
+
+    #[test]
+    fn test_unknown_fields_are_skipped() {
+        let mut bytes = Vec::new();
+        write_to(&mut bytes, &snapshot()).unwrap();
+        // Splice a field REBOUND would write (the time step) right after the header.
+        let mut extra = Vec::new();
+        write_field(&mut extra, 3, &0.01f64.to_le_bytes()).unwrap();
+        bytes.splice(HEADER_SIZE..HEADER_SIZE, extra);
+
+        assert_eq!(read_from(bytes.as_slice()).unwrap().bodies.len(), 2);
+    }
+
+    #[test]
+    fn test_truncated_fields_are_rejected() {
+        let mut bytes = Vec::new();
+        write_to(&mut bytes, &snapshot()).unwrap();
+        let mut huge = Vec::new();
+        write_field(&mut huge, 3, &0.01f64.to_le_bytes()).unwrap();
+        huge[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        bytes.splice(HEADER_SIZE..HEADER_SIZE, huge);
+
+        assert!(read_from(bytes.as_slice()).unwrap_err().to_string().contains("cut short"));
+    }
+
+    #[test]
+    fn test_other_files_are_rejected() {
+        assert!(read_from(&[0u8; 128][..]).is_err());
+    }
+
+    #[test]
+    fn test_hash_matches_rebound() {
+        // djb2, the algorithm behind reb_hash
+        assert_eq!(hash(""), 5381);
+        assert_eq!(hash("a"), 5381 * 33 + 97);
+    }
+}
@@ -0,0 +1,242 @@
+use super::body::energy;
+use super::console::Intervention;
+use super::dynamics::Settings;
+use super::Body;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the index file in a registry directory.
+pub const INDEX: &str = "runs.jsonl";
+
+/// Most bodies whose energy is summed for the diagnostics, over all pairs.
+const MAX_ENERGY_BODIES: usize = 5000;
+
+/// A directory keeping a record of every run made with it, one JSON object
+/// per line of its index, so the results of large sweeps stay findable.
+#[derive(Debug)]
+pub struct Registry {
+    index: PathBuf,
+    /// Serializes the numbering of the runs of this process.
+    lock: Mutex<()>,
+}
+
+/// What a run was asked to do and how it went.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// Number of the run in its registry, from 1.
+    pub id: u64,
+    /// Unix time at which the run started, in seconds.
+    pub started: u64,
+    pub manifest: Manifest,
+    pub outputs: Vec<PathBuf>,
+    /// Wall-clock seconds the run took.
+    pub elapsed: f64,
+    /// Why the run failed, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Summary>,
+}
+
+/// The command line of a run and the settings it resolved to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub command: Vec<String>,
+    pub scenario: PathBuf,
+    pub gravity: f64,
+    pub dt: f64,
+    pub total_time: f64,
+    pub integrator: String,
+    /// Changes made to the bodies while the run went on, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub interventions: Vec<Intervention>,
+}
+
+/// Diagnostics of a run that completed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    pub bodies: usize,
+    pub frames: usize,
+    /// Change of the total energy over the run, relative to its initial
+    /// magnitude; only for runs of at most a few thousand bodies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub energy_drift: Option<f64>,
+}
+
+impl RunRecord {
+    /// A run of `scenario` starting now, numbered when recorded.
+    pub fn start(scenario: &Path, settings: &Settings, outputs: Vec<PathBuf>) -> Self {
+        RunRecord {
+            id: 0,
+            started: now(),
+            manifest: Manifest {
+                command: env::args().collect(),
+                scenario: scenario.to_path_buf(),
+                gravity: settings.gravity,
+                dt: settings.dt,
+                total_time: settings.total_time,
+                integrator: settings.integrator.to_string(),
+                interventions: Vec::new(),
+            },
+            outputs,
+            elapsed: 0.0,
+            error: None,
+            summary: None,
+        }
+    }
+
+    /// Whether the run completed.
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl Summary {
+    /// Summarizes a run from `initial_energy` (see [`total_energy`]) to the
+    /// `last` state.
+    pub fn new(initial_energy: Option<f64>, last: &[Body], frames: usize, gravity: f64) -> Self {
+        let energy_drift = initial_energy
+            .zip(total_energy(last, gravity))
+            .map(|(initial, last)| (last - initial) / initial.abs());
+        Summary {
+            bodies: last.len(),
+            frames,
+            energy_drift,
+        }
+    }
+}
+
+/// Current Unix time in seconds.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+/// Total energy of the bodies, unless they are too many to sum over all pairs.
+pub fn total_energy(bodies: &[Body], gravity: f64) -> Option<f64> {
+    (bodies.len() <= MAX_ENERGY_BODIES).then(|| {
+        let (kinetic, potential) = energy(bodies, gravity);
+        kinetic + potential
+    })
+}
+
+impl Registry {
+    /// The registry in `directory`, created if needed.
+    pub fn open(directory: &Path) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(directory).map_err(|e| format!("{}: {}", directory.display(), e))?;
+        Ok(Registry {
+            index: directory.join(INDEX),
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Appends `run` to the index under the next number, which it returns.
+    pub fn record(&self, mut run: RunRecord) -> Result<u64, Box<dyn Error>> {
+        let _numbering = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        run.id = self.runs()?.iter().map(|run| run.id).max().unwrap_or(0) + 1;
+        let mut line = serde_json::to_string(&run)?;
+        line.push('\n');
+        let mut index = OpenOptions::new().create(true).append(true).open(&self.index)?;
+        index.write_all(line.as_bytes())?;
+        Ok(run.id)
+    }
+
+    /// Every recorded run, oldest first.
+    pub fn runs(&self) -> Result<Vec<RunRecord>, Box<dyn Error>> {
+        let text = match fs::read_to_string(&self.index) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            text => text?,
+        };
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| serde_json::from_str(line).map_err(|e| format!("{}:{}: {}", self.index.display(), i + 1, e).into()))
+            .collect()
+    }
+
+    pub fn run(&self, id: u64) -> Result<RunRecord, Box<dyn Error>> {
+        self.runs()?
+            .into_iter()
+            .find(|run| run.id == id)
+            .ok_or_else(|| format!("no run {} in {}", id, self.index.display()).into())
+    }
+}
+
+/// Formats a Unix time as a UTC date and time, e.g., "2025-03-14 09:26".
+pub fn format_time(unix: u64) -> String {
+    let days = (unix / 86400) as i64;
+    let minutes = unix % 86400 / 60;
+    // Civil date from days since 1970-01-01 in the proleptic Gregorian calendar.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::{Tags, Vector};
+
+    #[test]
+    fn test_runs_are_numbered_in_order() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let registry = Registry::open(&temp_dir.path().join("runs")).unwrap();
+        assert!(registry.runs().unwrap().is_empty());
+
+        let settings = Settings::default();
+        let mut failed = RunRecord::start(Path::new("a.json"), &settings, vec![PathBuf::from("a.parquet")]);
+        failed.error = Some("time step must be positive".to_string());
+        assert_eq!(registry.record(failed).unwrap(), 1);
+
+        let bodies = vec![
+            Body {
+                name: "A".to_string(),
+                mass: 2.0,
+                position: Vector::null(),
+                velocity: Vector::null(),
+                acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: None,
+            },
+            Body {
+                name: "B".to_string(),
+                mass: 1.0,
+                position: Vector::new(4.0, 0.0, 0.0),
+                velocity: Vector::new(0.0, 1.0, 0.0),
+                acceleration: Vector::null(),
+                tags: Tags::new(),
+                temperature: None,
+            },
+        ];
+        let mut completed = RunRecord::start(Path::new("b.json"), &settings, Vec::new());
+        // Kinetic 0.5 J and potential -0.5 J from an initial -1 J.
+        completed.summary = Some(Summary::new(Some(-1.0), &bodies, 3, 1.0));
+        assert_eq!(registry.record(completed).unwrap(), 2);
+
+        let runs = registry.runs().unwrap();
+        assert_eq!(runs.iter().map(|run| run.id).collect::<Vec<_>>(), [1, 2]);
+        assert!(!runs[0].succeeded() && runs[1].succeeded());
+        assert_eq!(runs[1].summary.as_ref().unwrap().energy_drift, Some(1.0));
+        assert_eq!(registry.run(2).unwrap().manifest.scenario, PathBuf::from("b.json"));
+        assert!(registry.run(3).is_err());
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "1970-01-01 00:00");
+        assert_eq!(format_time(951_782_400 + 3_600 + 120), "2000-02-29 01:02");
+        assert_eq!(format_time(1_741_944_360), "2025-03-14 09:26");
+    }
+}
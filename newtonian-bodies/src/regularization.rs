@@ -0,0 +1,379 @@
+use super::body::{Tags, Vector};
+use super::forces::Forces;
+use super::integrator::{Integrator, Workspace};
+use super::Body;
+use std::error::Error;
+
+/// Phase of the KS oscillator covered by one substep of a regularized pair.
+const SUBSTEP: f64 = 0.02;
+
+/// Most substeps of one pair in one step, so a collision can't stall the run.
+const MAX_SUBSTEPS: usize = 1_000_000;
+
+/// Kustaanheimo-Stiefel regularization of close encounters.
+///
+/// At the start of every step, bodies closer than `separation` to each other
+/// (and closer to each other than to anyone else) form a pair. The step moves
+/// each pair as one body at its center of mass, so the global time step only
+/// has to follow the pair's orbit about the others; the relative motion of
+/// the pair is then advanced over the step in KS variables, where it turns
+/// into a harmonic oscillator that stays smooth through the closest
+/// approaches, under the tidal field of the others (and the halo) at the pair's
+/// center at the start of the step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Regularization {
+    /// Distance in meters below which two bodies are regularized.
+    pub separation: f64,
+}
+
+impl Regularization {
+    /// Fails when the separation isn't positive, or the forces or the
+    /// integrator can't follow composite bodies.
+    pub fn check(
+        &self,
+        integrator: Integrator,
+        groups: &[(Tags, Integrator)],
+        forces: &Forces,
+    ) -> Result<(), Box<dyn Error>> {
+        if !(self.separation > 0.0 && self.separation.is_finite()) {
+            return Err(format!("the regularization separation must be positive, got {}", self.separation).into());
+        }
+        let plain = forces.periodic.is_none()
+            && forces.cutoff.is_none()
+            && forces.sph.is_none()
+            && forces.thermal.is_none()
+            && forces.tree.is_none()
+            && forces.workers.is_none()
+            && forces.far_field.is_none()
+            && forces.contacts.is_none()
+            && forces.mass_transfer.is_none()
+            && forces.script.is_none()
+            && forces.plugins.is_empty();
+        if !plain {
+            return Err("regularization only follows direct gravity in open space, optionally with a halo".into());
+        }
+        let mut integrators = groups.iter().map(|(_, integrator)| integrator).chain([&integrator]);
+        if integrators.any(|integrator| *integrator == Integrator::PatchedConics) {
+            return Err("patched conics can't be regularized".into());
+        }
+        Ok(())
+    }
+
+    /// Pairs of bodies to regularize, each the nearest neighbor of the other
+    /// and within the separation.
+    pub fn pairs(&self, bodies: &[Body]) -> Vec<(usize, usize)> {
+        let nearest: Vec<Option<usize>> = (0..bodies.len())
+            .map(|i| {
+                (0..bodies.len())
+                    .filter(|&j| j != i)
+                    .map(|j| (j, (bodies[j].position - bodies[i].position).norm()))
+                    .filter(|(_, distance)| *distance < self.separation)
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(j, _)| j)
+            })
+            .collect();
+        (0..bodies.len())
+            .filter_map(|i| nearest[i].filter(|&j| i < j && nearest[j] == Some(i)).map(|j| (i, j)))
+            .filter(|&(i, j)| bodies[i].mass + bodies[j].mass > 0.0)
+            .collect()
+    }
+
+    /// Advances the bodies by `dt` like [`Integrator::step_groups`], moving
+    /// the regularized pairs as their centers of mass and their relative
+    /// motion in KS variables. The accelerations are left consistent with the
+    /// new positions.
+    pub fn step(
+        &self,
+        integrator: Integrator,
+        groups: &[(Tags, Integrator)],
+        bodies: &mut [Body],
+        forces: &Forces,
+        dt: f64,
+        workspace: &mut Workspace,
+    ) {
+        let pairs = self.pairs(bodies);
+        if pairs.is_empty() {
+            integrator.step_groups(groups, bodies, forces, dt, workspace);
+            return;
+        }
+
+        // Index of each body in the reduced list, where a pair is one body.
+        let mut slots = vec![usize::MAX; bodies.len()];
+        let mut paired = vec![false; bodies.len()];
+        let mut reduced = Vec::with_capacity(bodies.len() - pairs.len());
+        let mut relative = Vec::with_capacity(pairs.len());
+        for &(i, j) in &pairs {
+            let (a, b) = (&bodies[i], &bodies[j]);
+            let mass = a.mass + b.mass;
+            let center = (a.position * a.mass + b.position * b.mass) / mass;
+            let tidal = tidal_tensor(bodies, i, j, &center, forces);
+            relative.push((a.position - b.position, a.velocity - b.velocity, tidal));
+            slots[i] = reduced.len();
+            slots[j] = reduced.len();
+            paired[i] = true;
+            paired[j] = true;
+            reduced.push(Body {
+                name: format!("{}+{}", a.name, b.name),
+                mass,
+                position: center,
+                velocity: (a.velocity * a.mass + b.velocity * b.mass) / mass,
+                acceleration: (a.acceleration * a.mass + b.acceleration * b.mass) / mass,
+                tags: if a.mass >= b.mass { a.tags.clone() } else { b.tags.clone() },
+                temperature: None,
+            });
+        }
+        for (i, body) in bodies.iter().enumerate() {
+            if !paired[i] {
+                slots[i] = reduced.len();
+                reduced.push(body.clone());
+            }
+        }
+
+        integrator.step_groups(groups, &mut reduced, forces, dt, workspace);
+
+        for (&(i, j), &(position, velocity, tidal)) in pairs.iter().zip(&relative) {
+            let mu = forces.gravity * (bodies[i].mass + bodies[j].mass);
+            let (position, velocity) = Ks::new(&position, &velocity, mu).advance(mu, &tidal, dt);
+            let center = &reduced[slots[i]];
+            let total = bodies[i].mass + bodies[j].mass;
+            let (share_i, share_j) = (bodies[j].mass / total, bodies[i].mass / total);
+            bodies[i].position = center.position + position * share_i;
+            bodies[i].velocity = center.velocity + velocity * share_i;
+            bodies[j].position = center.position - position * share_j;
+            bodies[j].velocity = center.velocity - velocity * share_j;
+        }
+        for (i, body) in bodies.iter_mut().enumerate().filter(|(i, _)| !paired[*i]) {
+            body.position = reduced[slots[i]].position;
+            body.velocity = reduced[slots[i]].velocity;
+        }
+        forces.accelerate(bodies);
+    }
+}
+
+/// Gradient of the acceleration at `center` from the bodies other than `i`
+/// and `j` and the halo, which gives the difference of their pulls on the
+/// two as T r for a small separation r.
+fn tidal_tensor(bodies: &[Body], i: usize, j: usize, center: &Vector, forces: &Forces) -> [[f64; 3]; 3] {
+    let mut tensor = forces.halo.map_or([[0.0; 3]; 3], |halo| halo.tidal_tensor(center));
+    for (k, body) in bodies.iter().enumerate() {
+        if k == i || k == j {
+            continue;
+        }
+        let d = body.position - *center;
+        let r2 = d.norm_squared();
+        let f = forces.gravity * body.mass / (r2 * r2.sqrt());
+        let d = [d.x, d.y, d.z];
+        for (m, row) in tensor.iter_mut().enumerate() {
+            for (n, value) in row.iter_mut().enumerate() {
+                *value += f * (3.0 * d[m] * d[n] / r2 - if m == n { 1.0 } else { 0.0 });
+            }
+        }
+    }
+    tensor
+}
+
+/// Relative motion of a pair in KS variables: the 4-vector `u` with
+/// r = |u|², its derivative with respect to the fictitious time s (dt = r ds),
+/// the Kepler energy per unit reduced mass and the physical time.
+#[derive(Debug, Clone, Copy)]
+struct Ks {
+    u: [f64; 4],
+    du: [f64; 4],
+    energy: f64,
+    time: f64,
+}
+
+impl Ks {
+    /// KS state of the relative `position` and `velocity` of a pair of
+    /// gravitational parameter `mu`.
+    fn new(position: &Vector, velocity: &Vector, mu: f64) -> Self {
+        let r = position.norm();
+        // Of the two charts, the one keeping u away from zero.
+        let u = if position.x >= 0.0 {
+            let u1 = ((r + position.x) / 2.0).sqrt();
+            [u1, position.y / (2.0 * u1), position.z / (2.0 * u1), 0.0]
+        } else {
+            let u2 = ((r - position.x) / 2.0).sqrt();
+            [position.y / (2.0 * u2), u2, 0.0, position.z / (2.0 * u2)]
+        };
+        let du = transposed(&u, velocity).map(|component| component / 2.0);
+        Ks {
+            u,
+            du,
+            energy: velocity.norm_squared() / 2.0 - mu / r,
+            time: 0.0,
+        }
+    }
+
+    fn radius(&self) -> f64 {
+        self.u.iter().map(|u| u * u).sum()
+    }
+
+    fn position(&self) -> Vector {
+        let [u1, u2, u3, u4] = self.u;
+        Vector::new(u1 * u1 - u2 * u2 - u3 * u3 + u4 * u4, 2.0 * (u1 * u2 - u3 * u4), 2.0 * (u1 * u3 + u2 * u4))
+    }
+
+    fn velocity(&self) -> Vector {
+        let ([u1, u2, u3, u4], [d1, d2, d3, d4]) = (self.u, self.du);
+        Vector::new(
+            u1 * d1 - u2 * d2 - u3 * d3 + u4 * d4,
+            u2 * d1 + u1 * d2 - u4 * d3 - u3 * d4,
+            u3 * d1 + u4 * d2 + u1 * d3 + u2 * d4,
+        ) * (2.0 / self.radius())
+    }
+
+    /// Derivatives with respect to s under the perturbing acceleration
+    /// P = T r of the tidal tensor: u'' = (h/2) u + (r/2) Lᵀ(u) P,
+    /// h' = 2 u'·Lᵀ(u) P and t' = r.
+    fn derivative(&self, tidal: &[[f64; 3]; 3]) -> Ks {
+        let r = self.radius();
+        let position = self.position();
+        let [x, y, z] = tidal.map(|row| row[0] * position.x + row[1] * position.y + row[2] * position.z);
+        let perturbation = transposed(&self.u, &Vector::new(x, y, z));
+        let mut ddu = [0.0; 4];
+        for (k, ddu) in ddu.iter_mut().enumerate() {
+            *ddu = self.energy / 2.0 * self.u[k] + r / 2.0 * perturbation[k];
+        }
+        Ks {
+            u: self.du,
+            du: ddu,
+            energy: 2.0 * (0..4).map(|k| self.du[k] * perturbation[k]).sum::<f64>(),
+            time: r,
+        }
+    }
+
+    /// This state moved by `h` times the derivative `d`.
+    fn shifted(&self, d: &Ks, h: f64) -> Ks {
+        Ks {
+            u: std::array::from_fn(|k| self.u[k] + d.u[k] * h),
+            du: std::array::from_fn(|k| self.du[k] + d.du[k] * h),
+            energy: self.energy + d.energy * h,
+            time: self.time + d.time * h,
+        }
+    }
+
+    /// One RK4 step of `h` in fictitious time.
+    fn rk4(&self, tidal: &[[f64; 3]; 3], h: f64) -> Ks {
+        let k1 = self.derivative(tidal);
+        let k2 = self.shifted(&k1, h / 2.0).derivative(tidal);
+        let k3 = self.shifted(&k2, h / 2.0).derivative(tidal);
+        let k4 = self.shifted(&k3, h).derivative(tidal);
+        let sum = Ks {
+            u: std::array::from_fn(|k| k1.u[k] + 2.0 * k2.u[k] + 2.0 * k3.u[k] + k4.u[k]),
+            du: std::array::from_fn(|k| k1.du[k] + 2.0 * k2.du[k] + 2.0 * k3.du[k] + k4.du[k]),
+            energy: k1.energy + 2.0 * k2.energy + 2.0 * k3.energy + k4.energy,
+            time: k1.time + 2.0 * k2.time + 2.0 * k3.time + k4.time,
+        };
+        self.shifted(&sum, h / 6.0)
+    }
+
+    /// Relative position and velocity after `dt` seconds. Substeps cover a
+    /// fixed phase of the oscillator, shortened near the end to land on `dt`.
+    fn advance(mut self, mu: f64, tidal: &[[f64; 3]; 3], dt: f64) -> (Vector, Vector) {
+        for _ in 0..MAX_SUBSTEPS {
+            let remaining = dt - self.time;
+            if remaining.abs() <= dt * 1e-14 {
+                break;
+            }
+            let r = self.radius();
+            let longest = SUBSTEP / (mu / (2.0 * r) + self.energy.abs() / 2.0).sqrt();
+            self = self.rk4(tidal, (remaining / r).clamp(-longest, longest));
+        }
+        (self.position(), self.velocity())
+    }
+}
+
+/// Lᵀ(u) v, for the KS matrix L(u) and v padded with a zero fourth component.
+fn transposed(u: &[f64; 4], v: &Vector) -> [f64; 4] {
+    let [u1, u2, u3, u4] = *u;
+    [
+        u1 * v.x + u2 * v.y + u3 * v.z,
+        -u2 * v.x + u1 * v.y + u4 * v.z,
+        -u3 * v.x - u4 * v.y + u1 * v.z,
+        u4 * v.x - u3 * v.y + u2 * v.z,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kepler;
+
+    fn run(bodies: &mut [Body], integrator: Integrator, regularization: Option<Regularization>, dt: f64, steps: usize) {
+        let forces = Forces::newtonian(1.0);
+        let mut workspace = Workspace::default();
+        integrator.initialize(bodies, &forces);
+        for _ in 0..steps {
+            match regularization {
+                Some(regularization) => regularization.step(integrator, &[], bodies, &forces, dt, &mut workspace),
+                None => integrator.step_in(bodies, &forces, dt, &mut workspace),
+            }
+        }
+    }
+
+    #[test]
+    fn test_eccentric_binary_follows_its_kepler_orbit_across_long_steps() {
+        // e = 0.9 and a = 1 about a total mass of 1.5: a period of about 5.1,
+        // so each step of 7 spans more than an orbit and several periapses.
+        let (mu, a, e) = (1.5, 1.0, 0.9f64);
+        let position = Vector::new(a * (1.0 + e), 0.0, 0.0);
+        let velocity = Vector::new(0.0, (mu / a * (1.0 - e) / (1.0 + e)).sqrt(), 0.0);
+        let mut bodies = vec![
+            Body::new("Primary", 1.0, -position / 3.0, -velocity / 3.0),
+            Body::new("Secondary", 0.5, position * 2.0 / 3.0, velocity * 2.0 / 3.0),
+        ];
+        let regularization = Regularization { separation: 5.0 };
+
+        run(&mut bodies, Integrator::Euler, Some(regularization), 7.0, 10);
+
+        let (expected, _) = kepler::propagate(&position, &velocity, mu, 70.0).unwrap();
+        let relative = bodies[1].position - bodies[0].position;
+        assert!((relative - expected).norm() < 1e-7, "{:?} != {:?}", relative, expected);
+        let center = bodies[0].position * 1.0 + bodies[1].position * 0.5;
+        assert!(center.norm() < 1e-12);
+        assert_eq!(regularization.pairs(&bodies), [(0, 1)]);
+        assert!(Regularization { separation: 0.5 }.pairs(&bodies).is_empty());
+    }
+
+    #[test]
+    fn test_hard_binary_in_a_triple_keeps_a_long_time_step() {
+        // A binary 0.01 apart, with a period of about 0.0044, and a third star
+        // on a circular orbit of radius 1 about it.
+        let (a, v) = (0.01, (2.0f64 / 0.01).sqrt() / 2.0);
+        let orbit = 3f64.sqrt();
+        let triple = || {
+            vec![
+                Body::new(
+                    "A",
+                    1.0,
+                    Vector::new(-1.0 / 3.0 + a / 2.0, 0.0, 0.0),
+                    Vector::new(0.0, v - orbit / 3.0, 0.0),
+                ),
+                Body::new(
+                    "B",
+                    1.0,
+                    Vector::new(-1.0 / 3.0 - a / 2.0, 0.0, 0.0),
+                    Vector::new(0.0, -v - orbit / 3.0, 0.0),
+                ),
+                Body::new("C", 1.0, Vector::new(2.0 / 3.0, 0.0, 0.0), Vector::new(0.0, 2.0 * orbit / 3.0, 0.0)),
+            ]
+        };
+        let mut reference = triple();
+        run(&mut reference, Integrator::Rk4, None, 1e-5, 100_000);
+        let mut regularized = triple();
+        run(&mut regularized, Integrator::Rk4, Some(Regularization { separation: 0.1 }), 0.01, 100);
+        let mut direct = triple();
+        run(&mut direct, Integrator::Rk4, None, 0.01, 100);
+
+        let separation = |bodies: &[Body]| bodies[0].position - bodies[1].position;
+        assert!((regularized[2].position - reference[2].position).norm() < 1e-4);
+        assert!((separation(&regularized) - separation(&reference)).norm() < 1e-3 * a);
+        // The same steps without regularization tear the binary apart.
+        assert!((separation(&direct) - separation(&reference)).norm() > a);
+
+        let forces = Forces::newtonian(1.0);
+        assert!(Regularization { separation: 0.1 }.check(Integrator::PatchedConics, &[], &forces).is_err());
+        assert!(Regularization { separation: 0.0 }.check(Integrator::Rk4, &[], &forces).is_err());
+    }
+}
@@ -0,0 +1,407 @@
+use super::body::Vector;
+use super::{rebound, snapshot};
+use super::Body;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Extensions of the files [`load`] understands.
+pub const EXTENSIONS: [&str; 5] = ["json", "csv", "tsv", "bin", "msgpack"];
+
+/// Values of the `${NAME}` placeholders of scenario files. Names missing here
+/// are looked up in the environment.
+pub type Variables = HashMap<String, String>;
+
+/// Columns of delimited scenario files.
+const COLUMNS: [&str; 8] = ["name", "mass", "x", "y", "z", "vx", "vy", "vz"];
+
+/// Loads the initial conditions stored in a scenario file.
+///
+/// The format follows the extension: `.csv` and `.tsv` are tables with a
+/// `name,mass,x,y,z,vx,vy,vz` header (see [`from_delimited`]), `.bin` files are
+/// REBOUND binaries (see [`rebound::read`]), `.msgpack` files are snapshots
+/// (see [`snapshot::read`]; of both, only the bodies are kept, not the time
+/// or the gravitational constant) and anything else is JSON: either
+/// an array of bodies or an object including other scenarios and overriding
+/// some of their bodies.
+///
+/// Placeholders in text formats are resolved from the environment; see [`load_with`].
+pub fn load(path: &Path) -> Result<Vec<Body>, Box<dyn Error>> {
+    load_with(path, &Variables::new())
+}
+
+/// Loads a scenario file, replacing its `${NAME}` placeholders with `variables`
+/// (or environment variables) before parsing. `$${` stands for a literal `${`.
+pub fn load_with(path: &Path, variables: &Variables) -> Result<Vec<Body>, Box<dyn Error>> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "bin" => Ok(rebound::read(path)?.bodies),
+        "msgpack" => Ok(snapshot::read(path)?.bodies),
+        "csv" => from_delimited(&read_text(path, variables)?, ','),
+        "tsv" => from_delimited(&read_text(path, variables)?, '\t'),
+        _ => compose(path, variables, &mut Vec::new())?
+            .into_iter()
+            .map(|body| {
+                let name = body.get("name").and_then(Value::as_str).unwrap_or("?").to_string();
+                serde_json::from_value(body).map_err(|e| format!("body '{}': {}", name, e).into())
+            })
+            .collect(),
+    }
+}
+
+/// The script of a JSON scenario object (`"script": "forces.rhai"`), relative
+/// to the scenario; see [`Script`](crate::script::Script). Scripts of included
+/// scenarios don't count.
+pub fn script(path: &Path, variables: &Variables) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let is_json = path
+        .extension()
+        .is_none_or(|ext| !["bin", "msgpack", "csv", "tsv"].iter().any(|other| ext.eq_ignore_ascii_case(other)));
+    if !is_json {
+        return Ok(None);
+    }
+    let scenario: Value = serde_json::from_str(&read_text(path, variables)?)?;
+    match scenario.get("script") {
+        None => Ok(None),
+        Some(Value::String(script)) => Ok(Some(path.parent().unwrap_or(Path::new("")).join(script))),
+        Some(other) => Err(format!("{}: script must be a file name, got {}", path.display(), other).into()),
+    }
+}
+
+/// Reads a JSON scenario as body objects, resolving its includes.
+///
+/// Besides a plain array of bodies, a scenario can be an object
+/// `{"include": [...], "bodies": [...]}`. Included files (of any supported
+/// format, relative to the including file) are loaded in order; then each of
+/// `bodies` either overrides the fields of the included body with the same
+/// name (e.g. only `mass`, or only `position.x`) or is added as a new body.
+fn compose(path: &Path, variables: &Variables, including: &mut Vec<PathBuf>) -> Result<Vec<Value>, Box<dyn Error>> {
+    let canonical = fs::canonicalize(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if including.contains(&canonical) {
+        return Err(format!("{} includes itself", path.display()).into());
+    }
+
+    let scenario: Value = serde_json::from_str(&read_text(path, variables)?)?;
+    let (includes, local) = match scenario {
+        Value::Array(bodies) => (Vec::new(), bodies),
+        Value::Object(mut fields) => {
+            let includes = match fields.remove("include") {
+                Some(Value::String(include)) => vec![include],
+                Some(include) => serde_json::from_value(include)?,
+                None => Vec::new(),
+            };
+            let bodies = match fields.remove("bodies") {
+                Some(bodies) => serde_json::from_value(bodies)?,
+                None => Vec::new(),
+            };
+            (includes, bodies)
+        }
+        _ => return Err(format!("{} is neither a list of bodies nor a scenario object", path.display()).into()),
+    };
+
+    including.push(canonical);
+    let mut bodies = Vec::new();
+    let directory = path.parent().unwrap_or(Path::new(""));
+    for include in includes {
+        let include = directory.join(include);
+        let is_json = include
+            .extension()
+            .is_none_or(|ext| ext.eq_ignore_ascii_case("json"));
+        if is_json {
+            bodies.extend(compose(&include, variables, including)?);
+        } else {
+            for body in load_with(&include, variables)? {
+                bodies.push(serde_json::to_value(body)?);
+            }
+        }
+    }
+    including.pop();
+
+    for body in local {
+        let name = body.get("name").and_then(Value::as_str).map(str::to_string);
+        let existing = bodies
+            .iter_mut()
+            .find(|b| name.is_some() && b.get("name").and_then(Value::as_str) == name.as_deref());
+        match existing {
+            Some(existing) => merge(existing, body),
+            None => bodies.push(body),
+        }
+    }
+    Ok(bodies)
+}
+
+fn read_text(path: &Path, variables: &Variables) -> Result<String, Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    substitute(&text, variables).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// Replaces every `${NAME}` in `text` with its value in `variables` or, failing
+/// that, in the environment.
+pub fn substitute(text: &str, variables: &Variables) -> Result<String, String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            result.push_str("${");
+            rest = escaped;
+        } else if let Some(placeholder) = rest.strip_prefix("${") {
+            let end = placeholder
+                .find('}')
+                .ok_or_else(|| format!("unterminated placeholder '{}'", rest.lines().next().unwrap_or(rest)))?;
+            let name = &placeholder[..end];
+            let value = match variables.get(name) {
+                Some(value) => value.clone(),
+                None => env::var(name).map_err(|_| format!("no value for ${{{}}}; pass --set {}=<value>", name, name))?,
+            };
+            result.push_str(&value);
+            rest = &placeholder[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Overwrites the fields of `base` with those of `patch`, recursing into objects.
+fn merge(base: &mut Value, patch: Value) {
+    match (base, patch) {
+        (Value::Object(base), Value::Object(patch)) => {
+            for (key, value) in patch {
+                merge(base.entry(key).or_insert_with(|| Value::Object(Map::new())), value);
+            }
+        }
+        (base, patch) => *base = patch,
+    }
+}
+
+/// Parses a table of bodies, as exported from a spreadsheet.
+///
+/// The header names the columns `name`, `mass`, `x`, `y`, `z`, `vx`, `vy` and
+/// `vz` in any order and case, plus an optional `temperature` in kelvin; other
+/// columns hold tags, empty fields meaning no tag. Fields may be quoted, and
+/// blank lines, lines starting with `#` and a leading byte order mark are
+/// skipped.
+pub fn from_delimited(text: &str, delimiter: char) -> Result<Vec<Body>, Box<dyn Error>> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+
+    let (_, header) = lines.next().ok_or("the scenario table has no header")?;
+    let header: Vec<String> = split(header, delimiter)
+        .iter()
+        .map(|column| column.trim().to_string())
+        .collect();
+    let positions: Vec<usize> = COLUMNS
+        .iter()
+        .map(|column| {
+            header
+                .iter()
+                .position(|h| h.eq_ignore_ascii_case(column))
+                .ok_or_else(|| format!("the scenario table has no '{}' column", column))
+        })
+        .collect::<Result<_, _>>()?;
+    let temperature = header.iter().position(|h| h.eq_ignore_ascii_case("temperature"));
+    let tag_columns: Vec<usize> = (0..header.len())
+        .filter(|i| !positions.contains(i) && Some(*i) != temperature)
+        .collect();
+
+    lines
+        .map(|(index, line)| {
+            let fields = split(line, delimiter);
+            let field = |column: usize| {
+                fields
+                    .get(positions[column])
+                    .map(|f| f.trim())
+                    .ok_or_else(|| format!("line {}: missing '{}'", index + 1, COLUMNS[column]))
+            };
+            let number = |column: usize| -> Result<f64, Box<dyn Error>> {
+                let value = field(column)?;
+                value
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid {} '{}'", index + 1, COLUMNS[column], value).into())
+            };
+            let position = Vector::new(number(2)?, number(3)?, number(4)?);
+            let velocity = Vector::new(number(5)?, number(6)?, number(7)?);
+            let body = Body::new(field(0)?, number(1)?, position, velocity);
+            Ok(Body {
+                tags: tag_columns
+                    .iter()
+                    .filter_map(|&i| {
+                        let value = fields.get(i)?.trim();
+                        (!value.is_empty()).then(|| (header[i].clone(), value.to_string()))
+                    })
+                    .collect(),
+                temperature: match temperature.and_then(|i| fields.get(i)).map(|f| f.trim()) {
+                    Some(value) if !value.is_empty() => Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("line {}: invalid temperature '{}'", index + 1, value))?,
+                    ),
+                    _ => None,
+                },
+                ..body
+            })
+        })
+        .collect()
+}
+
+/// Splits a line on `delimiter`, honoring double-quoted fields (`""` is a quote).
+pub(crate) fn split(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Stores bodies as a JSON scenario file.
+pub fn save(path: &Path, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, bodies)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_with_reordered_columns_and_quotes() {
+        let text = "# inner system\nName,x,y,z,vx,vy,vz,Mass,notes\n\
+                    Sun,0,0,0,0,0,0,1.989e30,\n\
+                    \"Earth, \"\"blue\"\"\",1.496e11,0,0,0,29780,0,5.972e24,home\n";
+
+        let bodies = from_delimited(text, ',').unwrap();
+
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[1].name, "Earth, \"blue\"");
+        assert_eq!(bodies[1].mass, 5.972e24);
+        assert_eq!(bodies[1].position.x, 1.496e11);
+        assert_eq!(bodies[1].velocity.y, 29780.0);
+        assert!(bodies[0].tags.is_empty());
+        assert_eq!(bodies[1].tags["notes"], "home");
+    }
+
+    #[test]
+    fn test_tsv() {
+        let text = "name\tmass\tx\ty\tz\tvx\tvy\tvz\nProbe\t1\t1\t2\t3\t4\t5\t6\n";
+        let bodies = from_delimited(text, '\t').unwrap();
+        assert_eq!(bodies[0].velocity.z, 6.0);
+    }
+
+    #[test]
+    fn test_byte_order_mark_is_skipped() {
+        let text = "\u{feff}name,mass,x,y,z,vx,vy,vz\nProbe,1,1,2,3,4,5,6\n";
+        let bodies = from_delimited(text, ',').unwrap();
+        assert_eq!(bodies[0].name, "Probe");
+        assert!(bodies[0].tags.is_empty());
+    }
+
+    #[test]
+    fn test_errors_name_the_problem() {
+        let missing = from_delimited("name,mass,x,y,z\n", ',').unwrap_err();
+        assert!(missing.to_string().contains("'vx'"));
+
+        let invalid = from_delimited("name,mass,x,y,z,vx,vy,vz\nSun,heavy,0,0,0,0,0,0\n", ',').unwrap_err();
+        assert_eq!(invalid.to_string(), "line 2: invalid mass 'heavy'");
+    }
+
+    #[test]
+    fn test_includes_with_overrides_and_additions() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("library")).unwrap();
+        fs::write(
+            temp_dir.path().join("library/inner.csv"),
+            "name,mass,x,y,z,vx,vy,vz\nSun,1.989e30,0,0,0,0,0,0\nEarth,5.972e24,1.496e11,0,0,0,29780,0\n",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("library/planets.json"),
+            r#"{"include": "inner.csv"}"#,
+        )
+        .unwrap();
+        let scenario = temp_dir.path().join("scenario.json");
+        fs::write(
+            &scenario,
+            r#"{
+                "include": ["library/planets.json"],
+                "bodies": [
+                    {"name": "Earth", "position": {"z": 1000.0}},
+                    {"name": "Probe", "mass": 1000.0,
+                     "position": {"x": 7e6, "y": 0.0, "z": 0.0},
+                     "velocity": {"x": 0.0, "y": 7.5e3, "z": 0.0}}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let bodies = load(&scenario).unwrap();
+
+        let names: Vec<&str> = bodies.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["Sun", "Earth", "Probe"]);
+        assert_eq!(bodies[1].position.x, 1.496e11);
+        assert_eq!(bodies[1].position.z, 1000.0);
+        assert_eq!(bodies[2].velocity.y, 7.5e3);
+    }
+
+    #[test]
+    fn test_placeholders_are_substituted() {
+        let variables = Variables::from([("MASS".to_string(), "2.5e3".to_string())]);
+        assert_eq!(
+            substitute(r#"{"mass": ${MASS}, "note": "$${MASS} costs $5"}"#, &variables).unwrap(),
+            r#"{"mass": 2.5e3, "note": "${MASS} costs $5"}"#
+        );
+
+        let error = substitute("${SCENARIO_TEST_UNDEFINED}", &variables).unwrap_err();
+        assert!(error.contains("--set SCENARIO_TEST_UNDEFINED="), "{}", error);
+        assert!(substitute("${MASS", &variables).is_err());
+    }
+
+    #[test]
+    fn test_include_cycles_are_rejected() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.json"), r#"{"include": "b.json"}"#).unwrap();
+        fs::write(temp_dir.path().join("b.json"), r#"{"include": ["a.json"]}"#).unwrap();
+
+        let error = load(&temp_dir.path().join("a.json")).unwrap_err();
+        assert!(error.to_string().contains("includes itself"), "{}", error);
+    }
+
+    #[test]
+    fn test_script_is_relative_to_the_scenario() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let scenario = temp_dir.path().join("scenario.json");
+        fs::write(&scenario, r#"{"script": "${NAME}.rhai", "bodies": []}"#).unwrap();
+        let variables = Variables::from([("NAME".to_string(), "thrust".to_string())]);
+        assert_eq!(script(&scenario, &variables).unwrap(), Some(temp_dir.path().join("thrust.rhai")));
+        assert!(load_with(&scenario, &variables).unwrap().is_empty());
+
+        fs::write(&scenario, "[]").unwrap();
+        assert_eq!(script(&scenario, &variables).unwrap(), None);
+        fs::write(&scenario, r#"{"script": 3}"#).unwrap();
+        assert!(script(&scenario, &variables).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use super::body::Tags;
+use arrow::datatypes::{DataType, Field, Schema};
+use parquet::format::KeyValue;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::str::FromStr;
+
+/// Key of the Parquet file metadata entry holding the output schema version.
+pub const VERSION_KEY: &str = "newtonian.schema_version";
+
+/// Key of the Parquet file metadata entry holding the tags of each body, as a
+/// JSON object from body names to their tags.
+pub const TAGS_KEY: &str = "newtonian.tags";
+
+/// Key of the Parquet file metadata entry holding the mass of each body when
+/// the `mass` column is left out, as a JSON object from body names to masses.
+pub const MASSES_KEY: &str = "newtonian.masses";
+
+/// Version of the layout produced by [`output_schema`].
+///
+/// - 1: `time` (step index), `name`, `mass`, `pos_x`, `pos_y`, `pos_z`
+/// - 2: `time` in seconds as a float, plus `vel_x`, `vel_y`, `vel_z`
+/// - 3: `mass` (then stored under [`MASSES_KEY`]) and the velocities may be
+///   left out, and positions and velocities may be Float32
+/// - 4: optional boolean `delta` column; rows where it is true store the
+///   position as the difference from the body's previous row
+/// - 5: optional nullable `temperature` column, in kelvin
+pub const CURRENT_VERSION: u32 = 5;
+
+/// How positions and velocities are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Precision {
+    #[default]
+    Double,
+    /// Float32 columns, about 7 significant digits.
+    Single,
+    /// Float64 rounded to this many decimals (negative rounds to tens, hundreds, ...),
+    /// which compresses better.
+    Decimals(i32),
+}
+
+impl Precision {
+    /// Value as stored in the output.
+    pub fn round(&self, value: f64) -> f64 {
+        match *self {
+            Precision::Double => value,
+            Precision::Single => value as f32 as f64,
+            Precision::Decimals(decimals) => {
+                let scale = 10f64.powi(decimals);
+                (value * scale).round() / scale
+            }
+        }
+    }
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    /// Parses "f64", "f32" or a number of decimals (e.g., "0" for whole meters).
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "f64" | "double" => Ok(Precision::Double),
+            "f32" | "single" => Ok(Precision::Single),
+            other => other
+                .parse()
+                .map(Precision::Decimals)
+                .map_err(|_| format!("expected f64, f32 or a number of decimals, got '{}'", text)),
+        }
+    }
+}
+
+/// Columns and precision of an output file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub precision: Precision,
+    /// Whether to write the `mass` column; masses only change during a run with
+    /// mass transfer.
+    pub mass: bool,
+    pub velocities: bool,
+    /// Stores positions as differences from the body's previous frame, with
+    /// absolute positions every this many frames. Smooth trajectories then
+    /// compress much better.
+    pub keyframe_interval: Option<usize>,
+    /// Whether to write the `temperature` column, empty for bodies without one.
+    pub temperature: bool,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            precision: Precision::Double,
+            mass: true,
+            velocities: true,
+            keyframe_interval: None,
+            temperature: false,
+        }
+    }
+}
+
+/// Arrow schema of the files written by this version of the crate.
+pub fn output_schema() -> Schema {
+    layout_schema(&Layout::default())
+}
+
+/// Arrow schema of the files written with a given layout.
+pub fn layout_schema(layout: &Layout) -> Schema {
+    let float = match layout.precision {
+        Precision::Single => DataType::Float32,
+        _ => DataType::Float64,
+    };
+    let mut fields = vec![
+        Field::new("time", DataType::Float64, false),
+        Field::new("name", DataType::Utf8, false),
+    ];
+    if layout.mass {
+        fields.push(Field::new("mass", DataType::Float64, false));
+    }
+    let vectors: &[&str] = if layout.velocities { &["pos", "vel"] } else { &["pos"] };
+    for vector in vectors {
+        for axis in ["x", "y", "z"] {
+            fields.push(Field::new(format!("{}_{}", vector, axis), float.clone(), false));
+        }
+    }
+    if layout.keyframe_interval.is_some() {
+        fields.push(Field::new("delta", DataType::Boolean, false));
+    }
+    if layout.temperature {
+        fields.push(Field::new("temperature", DataType::Float64, true));
+    }
+    Schema::new(fields)
+}
+
+/// Metadata entry stamping a file with [`CURRENT_VERSION`].
+pub fn version_metadata() -> KeyValue {
+    KeyValue::new(VERSION_KEY.to_string(), CURRENT_VERSION.to_string())
+}
+
+/// Metadata entry storing the tags of the recorded bodies.
+pub fn tags_metadata(tags: &BTreeMap<String, Tags>) -> Result<KeyValue, Box<dyn Error>> {
+    Ok(KeyValue::new(TAGS_KEY.to_string(), serde_json::to_string(tags)?))
+}
+
+/// Reads the tags of each body from the key-value metadata of an output file;
+/// files without tags give an empty map.
+pub fn tags_from_metadata(metadata: Option<&Vec<KeyValue>>) -> Result<BTreeMap<String, Tags>, Box<dyn Error>> {
+    let entry = metadata.into_iter().flatten().find(|kv| kv.key == TAGS_KEY);
+    match entry.and_then(|kv| kv.value.as_deref()) {
+        Some(value) => serde_json::from_str(value).map_err(|e| format!("invalid body tags: {}", e).into()),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Metadata entry storing the masses of the recorded bodies.
+pub fn masses_metadata(masses: &BTreeMap<String, f64>) -> Result<KeyValue, Box<dyn Error>> {
+    Ok(KeyValue::new(MASSES_KEY.to_string(), serde_json::to_string(masses)?))
+}
+
+/// Reads the mass of each body from the key-value metadata of an output file;
+/// files with a `mass` column give an empty map.
+pub fn masses_from_metadata(metadata: Option<&Vec<KeyValue>>) -> Result<BTreeMap<String, f64>, Box<dyn Error>> {
+    let entry = metadata.into_iter().flatten().find(|kv| kv.key == MASSES_KEY);
+    match entry.and_then(|kv| kv.value.as_deref()) {
+        Some(value) => serde_json::from_str(value).map_err(|e| format!("invalid body masses: {}", e).into()),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Reads the schema version from the key-value metadata of an output file.
+///
+/// Files written before the version was recorded carry no entry and are version 1.
+pub fn version_from_metadata(metadata: Option<&Vec<KeyValue>>) -> Result<u32, Box<dyn Error>> {
+    let entry = metadata
+        .into_iter()
+        .flatten()
+        .find(|kv| kv.key == VERSION_KEY);
+
+    let version = match entry.and_then(|kv| kv.value.as_deref()) {
+        Some(value) => value
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| format!("invalid output schema version '{}'", value))?,
+        None => 1,
+    };
+
+    if version == 0 || version > CURRENT_VERSION {
+        return Err(format!(
+            "unsupported output schema version {} (this build reads up to {})",
+            version, CURRENT_VERSION
+        )
+        .into());
+    }
+    Ok(version)
+}
+
+/// Positions of the known columns within a record batch.
+///
+/// Columns are looked up by name so every supported version is read the same
+/// way regardless of column order or of columns added by later versions.
+#[derive(Debug, Clone)]
+pub struct Columns {
+    pub time: usize,
+    pub name: usize,
+    /// Missing from version 3 files storing the masses in metadata.
+    pub mass: Option<usize>,
+    pub pos_x: usize,
+    pub pos_y: usize,
+    pub pos_z: usize,
+    pub vel_x: Option<usize>,
+    pub vel_y: Option<usize>,
+    pub vel_z: Option<usize>,
+    /// Whether each row holds a position difference, in delta-encoded files.
+    pub delta: Option<usize>,
+    pub temperature: Option<usize>,
+}
+
+impl Columns {
+    /// Resolves the columns of `schema`, requiring those mandated by `version`.
+    pub fn resolve(schema: &Schema, version: u32) -> Result<Self, Box<dyn Error>> {
+        let index = |name: &str| {
+            schema
+                .index_of(name)
+                .map_err(|_| format!("output file is missing the '{}' column", name))
+        };
+        // Optional outside the versions in `required`.
+        let between = |name: &str, required: std::ops::RangeInclusive<u32>| -> Result<Option<usize>, String> {
+            match schema.index_of(name) {
+                Ok(i) => Ok(Some(i)),
+                Err(_) if !required.contains(&version) => Ok(None),
+                Err(_) => Err(format!("output file is missing the '{}' column", name)),
+            }
+        };
+
+        Ok(Columns {
+            time: index("time")?,
+            name: index("name")?,
+            mass: between("mass", 1..=2)?,
+            pos_x: index("pos_x")?,
+            pos_y: index("pos_y")?,
+            pos_z: index("pos_z")?,
+            vel_x: between("vel_x", 2..=2)?,
+            vel_y: between("vel_y", 2..=2)?,
+            vel_z: between("vel_z", 2..=2)?,
+            delta: schema.index_of("delta").ok(),
+            temperature: schema.index_of("temperature").ok(),
+        })
+    }
+
+    /// Whether the velocity of the bodies was recorded.
+    pub fn has_velocities(&self) -> bool {
+        self.vel_x.is_some() && self.vel_y.is_some() && self.vel_z.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_version_is_treated_as_v1() {
+        assert_eq!(version_from_metadata(None).unwrap(), 1);
+        assert_eq!(version_from_metadata(Some(&vec![])).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_current_version_round_trips() {
+        let metadata = vec![version_metadata()];
+        assert_eq!(version_from_metadata(Some(&metadata)).unwrap(), CURRENT_VERSION);
+    }
+
+    #[test]
+    fn test_newer_version_is_rejected() {
+        let metadata = vec![KeyValue::new(
+            VERSION_KEY.to_string(),
+            (CURRENT_VERSION + 1).to_string(),
+        )];
+        assert!(version_from_metadata(Some(&metadata)).is_err());
+    }
+
+    #[test]
+    fn test_columns_are_resolved_by_name() {
+        let schema = Schema::new(vec![
+            Field::new("pos_z", DataType::Float64, false),
+            Field::new("pos_y", DataType::Float64, false),
+            Field::new("pos_x", DataType::Float64, false),
+            Field::new("mass", DataType::Float64, false),
+            Field::new("name", DataType::Utf8, false),
+            Field::new("time", DataType::UInt64, false),
+        ]);
+
+        let columns = Columns::resolve(&schema, 1).unwrap();
+        assert_eq!(columns.time, 5);
+        assert_eq!(columns.name, 4);
+        assert_eq!(columns.pos_x, 2);
+        assert!(!columns.has_velocities());
+    }
+
+    #[test]
+    fn test_velocities_are_required_from_v2() {
+        let v1 = Schema::new(output_schema().fields()[..6].to_vec());
+        assert!(Columns::resolve(&v1, 1).is_ok());
+        assert!(Columns::resolve(&v1, 2).is_err());
+
+        let columns = Columns::resolve(&output_schema(), 2).unwrap();
+        assert!(columns.has_velocities());
+        assert_eq!(columns.vel_z, Some(8));
+    }
+
+    #[test]
+    fn test_pruned_layout_resolves_from_v3() {
+        let layout = Layout {
+            precision: Precision::Single,
+            mass: false,
+            velocities: false,
+            keyframe_interval: None,
+            temperature: false,
+        };
+        let schema = layout_schema(&layout);
+        assert_eq!(schema.fields().len(), 5);
+        assert_eq!(schema.field(2).data_type(), &DataType::Float32);
+        assert!(Columns::resolve(&schema, 2).is_err());
+
+        let columns = Columns::resolve(&schema, 3).unwrap();
+        assert_eq!(columns.mass, None);
+        assert!(!columns.has_velocities());
+        assert_eq!(columns.delta, None);
+    }
+
+    #[test]
+    fn test_precision_parsing_and_rounding() {
+        assert_eq!("f32".parse::<Precision>().unwrap(), Precision::Single);
+        assert_eq!("-3".parse::<Precision>().unwrap(), Precision::Decimals(-3));
+        assert!("half".parse::<Precision>().is_err());
+        assert_eq!(Precision::Decimals(-3).round(1_496_012_345.6), 1_496_012_000.0);
+        assert_eq!(Precision::Decimals(1).round(0.26), 0.3);
+        assert_eq!(Precision::Single.round(0.1), 0.1f32 as f64);
+    }
+}
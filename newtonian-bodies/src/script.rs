@@ -0,0 +1,323 @@
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Custom forces and event handlers written in Rhai, a small scripting
+/// language embedded in this crate.
+///
+/// A script defines either or both of these functions:
+///
+/// - `acceleration(body, time)` returns the acceleration in m/s² to add to a
+///   body, as `[ax, ay, az]`, or nothing. It runs at every force evaluation,
+///   with `time` the start of the step.
+/// - `on_step(time)` runs after every step, ending at `time`. The masses,
+///   positions and velocities it changes are kept.
+///
+/// A body is a map with `name`, `mass`, `x`, `y`, `z`, `vx`, `vy`, `vz`, `ax`,
+/// `ay`, `az` and `tags`, and `temperature` if it has one. In both functions
+/// `this` is the array of all the bodies, in no particular order.
+///
+/// Clones share the compiled script, but each keeps its own clock, so
+/// concurrent runs don't mix their times.
+pub struct Script {
+    program: Arc<Program>,
+    /// Start of the step whose forces are being evaluated.
+    time: Mutex<f64>,
+    /// First failure of `acceleration`, reported at the end of the step.
+    #[cfg_attr(not(feature = "rhai"), allow(dead_code))]
+    error: Mutex<Option<String>>,
+}
+
+struct Program {
+    path: PathBuf,
+    #[cfg(feature = "rhai")]
+    engine: rhai::Engine,
+    #[cfg(feature = "rhai")]
+    ast: rhai::AST,
+}
+
+impl Clone for Script {
+    fn clone(&self) -> Self {
+        Script::new(self.program.clone())
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Script").field("path", &self.program.path).finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for Script {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.program, &other.program)
+    }
+}
+
+impl Script {
+    fn new(program: Arc<Program>) -> Self {
+        Script {
+            program,
+            time: Mutex::new(0.0),
+            error: Mutex::new(None),
+        }
+    }
+
+    /// Compiles the script in the file at `path`.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        #[cfg(not(feature = "rhai"))]
+        return Err(format!("{}: scripts need the rhai feature (cargo build --features rhai)", path.display()).into());
+
+        #[cfg(feature = "rhai")]
+        {
+            let engine = rhai::Engine::new();
+            let ast = engine
+                .compile_file(path.to_path_buf())
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            if !ast.iter_functions().any(|f| f.name == "acceleration" || f.name == "on_step") {
+                return Err(format!("{} defines neither acceleration(body, time) nor on_step(time)", path.display()).into());
+            }
+            let path = path.to_path_buf();
+            Ok(Script::new(Arc::new(Program { path, engine, ast })))
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.program.path
+    }
+
+    /// Whether the script defines `on_step`, which changes the bodies.
+    pub fn handles_steps(&self) -> bool {
+        #[cfg(feature = "rhai")]
+        let handles = self.program.ast.iter_functions().any(|f| f.name == "on_step");
+        #[cfg(not(feature = "rhai"))]
+        let handles = false;
+        handles
+    }
+
+    /// Sets the time of the coming force evaluations.
+    pub(crate) fn clock(&self, time: f64) {
+        *self.time.lock().unwrap_or_else(PoisonError::into_inner) = time;
+    }
+}
+
+#[cfg(feature = "rhai")]
+mod engine {
+    use super::super::body::Vector;
+    use super::super::Body;
+    use super::Script;
+    use rhai::{Array, CallFnOptions, Dynamic, Map, Scope};
+    use std::error::Error;
+    use std::sync::PoisonError;
+
+    impl Script {
+        fn defines(&self, name: &str) -> bool {
+            self.program.ast.iter_functions().any(|f| f.name == name)
+        }
+
+        /// Adds the acceleration of the script to every body.
+        pub(crate) fn accelerate(&self, bodies: &mut [Body]) {
+            if !self.defines("acceleration") {
+                return;
+            }
+            let time = *self.time.lock().unwrap_or_else(PoisonError::into_inner);
+            let state = state(bodies);
+            let mut this = Dynamic::from_array(state.clone());
+            for (body, map) in bodies.iter_mut().zip(state) {
+                let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut this);
+                let result = self
+                    .program
+                    .engine
+                    .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.program.ast, "acceleration", (map, time))
+                    .map_err(|e| e.to_string())
+                    .and_then(|value| acceleration(&body.name, value));
+                match result {
+                    Ok(extra) => body.acceleration += extra,
+                    Err(e) => {
+                        let mut error = self.error.lock().unwrap_or_else(PoisonError::into_inner);
+                        error.get_or_insert_with(|| format!("{}: acceleration of '{}': {}", self.path().display(), body.name, e));
+                        return;
+                    }
+                }
+            }
+        }
+
+        /// Runs the `on_step` handler at the end of a step, and reports the
+        /// failures of the step.
+        pub(crate) fn events(&self, bodies: &mut [Body], time: f64) -> Result<(), Box<dyn Error>> {
+            if let Some(error) = self.error.lock().unwrap_or_else(PoisonError::into_inner).take() {
+                return Err(error.into());
+            }
+            if !self.defines("on_step") {
+                return Ok(());
+            }
+            let mut this = Dynamic::from_array(state(bodies));
+            let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut this);
+            self.program
+                .engine
+                .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.program.ast, "on_step", (time,))
+                .map(|_| ())
+                .map_err(|e| format!("{}: on_step: {}", self.path().display(), e))?;
+
+            let state = this
+                .try_cast::<Array>()
+                .filter(|state| state.len() == bodies.len())
+                .ok_or_else(|| format!("{}: on_step must keep `this` an array of all the bodies", self.path().display()))?;
+            for (body, map) in bodies.iter_mut().zip(state) {
+                let map = map.try_cast::<Map>().ok_or("on_step must keep the bodies maps")?;
+                let number = |key: &str| -> Result<f64, Box<dyn Error>> {
+                    let value = map.get(key).ok_or_else(|| format!("on_step removed the {} of '{}'", key, body.name))?;
+                    float(value).ok_or_else(|| format!("{} of '{}' must be a number, got {}", key, body.name, value).into())
+                };
+                body.mass = number("mass")?;
+                body.position = Vector::new(number("x")?, number("y")?, number("z")?);
+                body.velocity = Vector::new(number("vx")?, number("vy")?, number("vz")?);
+            }
+            Ok(())
+        }
+    }
+
+    /// The bodies as script maps.
+    fn state(bodies: &[Body]) -> Array {
+        bodies
+            .iter()
+            .map(|body| {
+                let mut map = Map::new();
+                map.insert("name".into(), body.name.clone().into());
+                let fields = [
+                    ("mass", body.mass),
+                    ("x", body.position.x),
+                    ("y", body.position.y),
+                    ("z", body.position.z),
+                    ("vx", body.velocity.x),
+                    ("vy", body.velocity.y),
+                    ("vz", body.velocity.z),
+                    ("ax", body.acceleration.x),
+                    ("ay", body.acceleration.y),
+                    ("az", body.acceleration.z),
+                ];
+                for (key, value) in fields {
+                    map.insert(key.into(), value.into());
+                }
+                let tags: Map = body.tags.iter().map(|(key, value)| (key.into(), value.clone().into())).collect();
+                map.insert("tags".into(), tags.into());
+                if let Some(temperature) = body.temperature {
+                    map.insert("temperature".into(), temperature.into());
+                }
+                Dynamic::from_map(map)
+            })
+            .collect()
+    }
+
+    /// The acceleration returned by the script: `[ax, ay, az]` or nothing.
+    fn acceleration(name: &str, value: Dynamic) -> Result<Vector, String> {
+        if value.is_unit() {
+            return Ok(Vector::null());
+        }
+        let invalid = || format!("expected [ax, ay, az] or nothing for '{}', got {}", name, value);
+        let components = value.clone().try_cast::<Array>().filter(|array| array.len() == 3).ok_or_else(invalid)?;
+        let components: Vec<f64> = components.iter().map(float).collect::<Option<_>>().ok_or_else(invalid)?;
+        Ok(Vector::new(components[0], components[1], components[2]))
+    }
+
+    /// Numbers written as integers count too.
+    fn float(value: &Dynamic) -> Option<f64> {
+        value.as_float().ok().or_else(|| value.as_int().ok().map(|n| n as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[cfg(not(feature = "rhai"))]
+    #[test]
+    fn test_scripts_need_rhai() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("events.rhai");
+        fs::write(&path, "fn on_step(time) {}").unwrap();
+        assert!(Script::load(&path).is_err());
+    }
+
+    #[cfg(feature = "rhai")]
+    #[test]
+    fn test_script_pushes_and_kicks() {
+        use crate::body::{Tags, Vector};
+        use crate::Body;
+        use crate::dynamics::{simulate_with, Recording, Settings};
+        use crate::simulation::Discard;
+
+        // A probe thrusting at 1 m/s² along x for ten seconds, then stopped
+        // dead when it passes 100 m.
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("probe.rhai");
+        fs::write(
+            &path,
+            r#"
+            fn acceleration(body, time) {
+                if body.name == "Probe" && time < 10.0 { [1.0, 0, 0] }
+            }
+            fn on_step(time) {
+                for i in 0..this.len() {
+                    if this[i].tags.kind == "probe" && this[i].x > 100.0 { this[i].vx = 0.0; }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let script = Script::load(&path).unwrap();
+
+        let mut bodies = vec![Body {
+            name: "Probe".to_string(),
+            mass: 1.0,
+            position: Vector::null(),
+            velocity: Vector::null(),
+            acceleration: Vector::null(),
+            tags: Tags::from([("kind".to_string(), "probe".to_string())]),
+            temperature: None,
+        }];
+        let settings = Settings {
+            total_time: 200.0,
+            dt: 1.0,
+            recording: Recording::Count(2),
+            progress: false,
+            script: Some(script),
+            ..Settings::default()
+        };
+        simulate_with(&mut bodies, &settings, &mut Discard).unwrap();
+        // 10 m/s after the thrust, stopped a step after passing 100 m.
+        assert_eq!(bodies[0].velocity, Vector::null());
+        assert!(bodies[0].position.x > 100.0 && bodies[0].position.x <= 110.0, "{}", bodies[0].position.x);
+    }
+
+    #[cfg(feature = "rhai")]
+    #[test]
+    fn test_script_errors_stop_the_run() {
+        use crate::dynamics::{simulate_with, Settings};
+        use crate::simulation::Discard;
+        use crate::Body;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("broken.rhai");
+        fs::write(&path, "fn acceleration(body, time) { \"fast\" }").unwrap();
+        let script = Script::load(&path).unwrap();
+        fs::write(&path, "fn force(body) {}").unwrap();
+        assert!(Script::load(&path).is_err());
+
+        let mut bodies: Vec<Body> = serde_json::from_str(
+            r#"[{"name": "A", "mass": 1, "position": {"x": 0, "y": 0, "z": 0}, "velocity": {"x": 0, "y": 0, "z": 0}}]"#,
+        )
+        .unwrap();
+        let settings = Settings {
+            total_time: 10.0,
+            dt: 1.0,
+            progress: false,
+            script: Some(script),
+            ..Settings::default()
+        };
+        let error = simulate_with(&mut bodies, &settings, &mut Discard).unwrap_err();
+        assert!(error.to_string().contains("expected [ax, ay, az]"), "{}", error);
+    }
+}
@@ -0,0 +1,598 @@
+use super::body::Tags;
+use super::console::{Change, Intervention};
+use super::contact::Contacts;
+use super::distributed::Workers;
+use super::dynamics::{self, simulate_with, Recording, SequentialWriter, Settings};
+use super::far_field::FarField;
+use super::forces::{Forces, PeriodicBox};
+use super::galaxy::Halo;
+use super::mass_transfer::MassTransfer;
+use super::integrator::{Integrator, Workspace};
+use super::plugin::Plugin;
+use super::reader::Frame;
+use super::regularization::Regularization;
+use super::script::Script;
+use super::sph::Sph;
+use super::thermal::Thermal;
+use super::tree::BarnesHut;
+use super::Body;
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::Arc;
+
+/// Observer that ignores every frame, for runs only interested in the final state.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Discard;
+
+impl SequentialWriter for Discard {
+    fn add(&mut self, _time: f64, _bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// A validated run: bodies, settings and the observer receiving its frames.
+///
+/// ```no_run
+/// use newtonian_solar_system::integrator::Integrator;
+/// use newtonian_solar_system::simulation::Simulation;
+/// use newtonian_solar_system::writer::Writer;
+/// # fn run(bodies: Vec<newtonian_solar_system::Body>) -> Result<(), Box<dyn std::error::Error>> {
+/// let mut simulation = Simulation::builder()
+///     .bodies(bodies)
+///     .integrator(Integrator::Rk4)
+///     .dt(60.0)
+///     .duration(365.25 * 86400.0)
+///     .record_interval(86400.0)
+///     .observer(Writer::new("orbits.parquet".into())?)
+///     .build()?;
+/// simulation.run()?;
+/// simulation.into_observer().close()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Host applications that drive time themselves (games, GUIs, notebooks) call
+/// `step` instead of `run`, or iterate over `steps`.
+pub struct Simulation<W> {
+    bodies: Vec<Body>,
+    settings: Settings,
+    observer: W,
+    /// Seconds stepped so far.
+    time: f64,
+    /// Whether the accelerations match the positions, as the integrators expect.
+    initialized: bool,
+    workspace: Workspace,
+    /// The forces of the settings, kept from step to step for their far field.
+    forces: Forces,
+    /// Steps taken by `step`.
+    steps: usize,
+    /// Changes made with `apply`, oldest first.
+    interventions: Vec<Intervention>,
+}
+
+impl Simulation<Discard> {
+    pub fn builder() -> SimulationBuilder<Discard> {
+        SimulationBuilder {
+            bodies: Vec::new(),
+            settings: Settings {
+                progress: false,
+                ..Settings::default()
+            },
+            observer: Discard,
+        }
+    }
+}
+
+impl<W: SequentialWriter> Simulation<W> {
+    /// Integrates over the whole duration from the current state, handing every
+    /// recorded frame to the observer. The bodies are left in their final state.
+    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        simulate_with(&mut self.bodies, &self.settings, &mut self.observer)?;
+        self.time += self.settings.total_time.max(0.0);
+        self.initialized = true;
+        self.forces.expire_far_field();
+        Ok(())
+    }
+
+    /// Hands the current state to the observer (only the bodies carrying the
+    /// record tags, if any).
+    pub fn record(&mut self) -> Result<(), Box<dyn Error>> {
+        dynamics::record(&mut self.observer, &self.settings.record_tags, self.time, &self.bodies, &mut Vec::new())
+    }
+}
+
+impl<W> Simulation<W> {
+    /// Advances the bodies by one step of `dt` seconds with the configured
+    /// integrator; the observer isn't involved.
+    pub fn step(&mut self, dt: f64) -> Result<(), Box<dyn Error>> {
+        if !(dt.is_finite() && dt > 0.0) {
+            return Err(format!("time step must be positive, got {}", dt).into());
+        }
+        let integrator = self.settings.integrator;
+        let forces = &self.forces;
+        if self.settings.far_field.is_some_and(|far_field| self.steps.is_multiple_of(far_field.every)) {
+            forces.expire_far_field();
+        }
+        forces.clock(self.time);
+        if !self.initialized {
+            forces.wrap(&mut self.bodies);
+            integrator.initialize(&mut self.bodies, forces);
+            self.initialized = true;
+        }
+        let (groups, bodies, workspace) = (&self.settings.integrators, &mut self.bodies, &mut self.workspace);
+        match self.settings.regularization {
+            Some(regularization) => regularization.step(integrator, groups, bodies, forces, dt, workspace),
+            None => integrator.step_groups(groups, bodies, forces, dt, workspace),
+        }
+        forces.heat(&mut self.bodies, dt);
+        forces.transfer_mass(&mut self.bodies, dt);
+        forces.touch(&mut self.bodies, dt);
+        forces.events(&mut self.bodies, self.time + dt)?;
+        if forces.reshapes() {
+            // The next step starts from the pull of the changed bodies.
+            forces.clock(self.time + dt);
+            forces.accelerate(&mut self.bodies);
+        }
+        forces.wrap(&mut self.bodies);
+        self.time += dt;
+        self.steps += 1;
+        Ok(())
+    }
+
+    /// Steps of the configured `dt` until `time` seconds since the initial
+    /// state, the last one shortened to end exactly on it. Earlier times are
+    /// left alone.
+    pub fn advance_to(&mut self, time: f64) -> Result<(), Box<dyn Error>> {
+        // Slack absorbing the rounding of accumulated step times.
+        let tolerance = self.settings.dt * 1e-6;
+        while self.time < time - tolerance {
+            self.step(self.settings.dt.min(time - self.time))?;
+        }
+        Ok(())
+    }
+
+    /// Endless steps of the configured `dt`, yielding the state after each one.
+    pub fn steps(&mut self) -> Steps<'_, W> {
+        Steps { simulation: self }
+    }
+
+    /// Seconds elapsed since the initial state.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// The current state of the bodies.
+    pub fn state(&self) -> &[Body] {
+        &self.bodies
+    }
+
+    pub fn bodies(&self) -> &[Body] {
+        &self.bodies
+    }
+
+    /// The bodies, to change them between steps; their accelerations are
+    /// computed again before the next step.
+    pub(crate) fn bodies_mut(&mut self) -> &mut [Body] {
+        self.initialized = false;
+        &mut self.bodies
+    }
+
+    /// Adds a body or kicks one between steps, logging the change with the
+    /// time it was made at. The next step carries on from the changed state.
+    pub fn apply(&mut self, change: Change) -> Result<(), Box<dyn Error>> {
+        change.apply(&mut self.bodies)?;
+        self.interventions.push(Intervention {
+            time: self.time,
+            steps: self.steps,
+            change: change.to_string(),
+        });
+        self.initialized = false;
+        self.forces.expire_far_field();
+        Ok(())
+    }
+
+    /// The changes made with `apply`, oldest first.
+    pub fn interventions(&self) -> &[Intervention] {
+        &self.interventions
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn observer(&self) -> &W {
+        &self.observer
+    }
+
+    /// The observer, e.g., to close an output file after the run.
+    pub fn into_observer(self) -> W {
+        self.observer
+    }
+}
+
+/// Collects the parts of a `Simulation`; see `Simulation::builder`. Settings
+/// not given keep the defaults of `Settings`, without the progress bar.
+pub struct SimulationBuilder<W> {
+    bodies: Vec<Body>,
+    settings: Settings,
+    observer: W,
+}
+
+impl<W> SimulationBuilder<W> {
+    pub fn bodies(mut self, bodies: Vec<Body>) -> Self {
+        self.bodies = bodies;
+        self
+    }
+
+    /// All the settings at once, e.g., those of the command line; the other
+    /// methods then adjust them.
+    pub fn settings(mut self, settings: Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn gravity(mut self, gravity: f64) -> Self {
+        self.settings.gravity = gravity;
+        self
+    }
+
+    pub fn integrator(mut self, integrator: Integrator) -> Self {
+        self.settings.integrator = integrator;
+        self
+    }
+
+    /// Steps the bodies tagged `key=value` with `integrator` instead, sharing
+    /// the force evaluations with the others; the first group a body belongs to
+    /// wins.
+    pub fn group_integrator(mut self, key: &str, value: &str, integrator: Integrator) -> Self {
+        let tags = Tags::from([(key.to_string(), value.to_string())]);
+        self.settings.integrators.push((tags, integrator));
+        self
+    }
+
+    /// Time step in seconds.
+    pub fn dt(mut self, dt: f64) -> Self {
+        self.settings.dt = dt;
+        self
+    }
+
+    /// Seconds to simulate.
+    pub fn duration(mut self, duration: f64) -> Self {
+        self.settings.total_time = duration;
+        self
+    }
+
+    pub fn record_interval(mut self, interval: f64) -> Self {
+        self.settings.recording = Recording::Interval(interval);
+        self
+    }
+
+    pub fn record_count(mut self, count: usize) -> Self {
+        self.settings.recording = Recording::Count(count);
+        self
+    }
+
+    /// Only records bodies carrying this tag; repeat to require several.
+    pub fn record_tag(mut self, key: &str, value: &str) -> Self {
+        self.settings.record_tags.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Runs in a periodic box instead of open space.
+    pub fn periodic(mut self, periodic: PeriodicBox) -> Self {
+        self.settings.periodic = Some(periodic);
+        self
+    }
+
+    /// Distance beyond which bodies don't pull on each other.
+    pub fn cutoff(mut self, cutoff: f64) -> Self {
+        self.settings.cutoff = Some(cutoff);
+        self
+    }
+
+    /// Adds gas pressure and viscosity between the gas particles.
+    pub fn sph(mut self, sph: Sph) -> Self {
+        self.settings.sph = Some(sph);
+        self
+    }
+
+    /// Evolves the temperatures of the bodies that have one.
+    pub fn thermal(mut self, thermal: Thermal) -> Self {
+        self.settings.thermal = Some(thermal);
+        self
+    }
+
+    /// Approximates the gravity of distant groups of bodies with a tree code.
+    pub fn tree(mut self, tree: BarnesHut) -> Self {
+        self.settings.tree = Some(tree);
+        self
+    }
+
+    /// Sorts the bodies along a Morton curve every `steps` steps of `run`, for
+    /// cache locality; the recorded frames and the state keep the original order.
+    pub fn reorder_every(mut self, steps: usize) -> Self {
+        self.settings.reorder_every = Some(steps);
+        self
+    }
+
+    /// Reuses the gravity of bodies farther than `far_field.radius` for
+    /// `far_field.every` steps.
+    pub fn far_field(mut self, far_field: FarField) -> Self {
+        self.settings.far_field = Some(far_field);
+        self
+    }
+
+    /// Resolves the contacts between solid bodies with rapier3d.
+    pub fn contacts(mut self, contacts: Contacts) -> Self {
+        self.settings.contacts = Some(contacts);
+        self
+    }
+
+    /// Regularizes pairs of bodies closer than `separation` meters, moving
+    /// them as their centers of mass and their relative motion in KS variables.
+    pub fn regularization(mut self, separation: f64) -> Self {
+        self.settings.regularization = Some(Regularization { separation });
+        self
+    }
+
+    /// Adds the pull of a static galactic halo.
+    pub fn halo(mut self, halo: Halo) -> Self {
+        self.settings.halo = Some(halo);
+        self
+    }
+
+    /// Moves mass from the donor of a binary to its companion while it
+    /// overflows its Roche lobe.
+    pub fn mass_transfer(mut self, transfer: MassTransfer) -> Self {
+        self.settings.mass_transfer = Some(transfer);
+        self
+    }
+
+    /// Adds the forces and event handlers of a script.
+    pub fn script(mut self, script: Script) -> Self {
+        self.settings.script = Some(script);
+        self
+    }
+
+    /// Adds the forces of a WebAssembly plugin; repeat for several.
+    pub fn plugin(mut self, plugin: Plugin) -> Self {
+        self.settings.plugins.push(plugin);
+        self
+    }
+
+    /// Shares the gravity sums with worker processes.
+    pub fn workers(mut self, workers: Workers) -> Self {
+        self.settings.workers = Some(Arc::new(workers));
+        self
+    }
+
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.settings.max_memory = Some(bytes);
+        self
+    }
+
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.settings.progress = progress;
+        self
+    }
+
+    /// Receives the recorded frames, e.g., a Parquet `writer::Writer`.
+    pub fn observer<O: SequentialWriter>(self, observer: O) -> SimulationBuilder<O> {
+        SimulationBuilder {
+            bodies: self.bodies,
+            settings: self.settings,
+            observer,
+        }
+    }
+
+    /// Checks the combination of bodies and settings.
+    pub fn build(self) -> Result<Simulation<W>, Box<dyn Error>> {
+        let settings = &self.settings;
+        if self.bodies.is_empty() {
+            return Err("a simulation needs at least one body".into());
+        }
+        let mut names = HashSet::new();
+        if let Some(body) = self.bodies.iter().find(|body| !names.insert(&body.name)) {
+            return Err(format!("more than one body is named '{}'", body.name).into());
+        }
+        if !(settings.dt.is_finite() && settings.dt > 0.0) {
+            return Err(format!("time step must be positive, got {}", settings.dt).into());
+        }
+        if !(settings.total_time.is_finite() && settings.total_time >= 0.0) {
+            return Err(format!("duration must not be negative, got {}", settings.total_time).into());
+        }
+        if !settings.gravity.is_finite() {
+            return Err(format!("gravitational constant must be finite, got {}", settings.gravity).into());
+        }
+        let forces = settings.forces();
+        forces.check()?;
+        settings.integrator.check_groups(&settings.integrators, &forces)?;
+        if let Some(regularization) = settings.regularization {
+            regularization.check(settings.integrator, &settings.integrators, &forces)?;
+        }
+        settings.recording.times(settings.total_time)?;
+        Ok(Simulation {
+            bodies: self.bodies,
+            settings: self.settings,
+            observer: self.observer,
+            time: 0.0,
+            initialized: false,
+            workspace: Workspace::default(),
+            forces,
+            steps: 0,
+            interventions: Vec::new(),
+        })
+    }
+}
+
+/// Iterator over the states of a simulation after each step; see `Simulation::steps`.
+pub struct Steps<'a, W> {
+    simulation: &'a mut Simulation<W>,
+}
+
+impl<W> Iterator for Steps<'_, W> {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        // The time step was validated when building the simulation.
+        self.simulation.step(self.simulation.settings.dt).ok()?;
+        Some(Frame {
+            time: self.simulation.time,
+            bodies: self.simulation.bodies.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::{Tags, Vector};
+    use crate::collect::Collector;
+
+    fn body(name: &str, mass: f64, x: f64, vy: f64) -> Body {
+        Body {
+            name: name.to_string(),
+            mass,
+            position: Vector { x, y: 0.0, z: 0.0 },
+            velocity: Vector { x: 0.0, y: vy, z: 0.0 },
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    #[test]
+    fn test_builds_and_runs() {
+        let mut simulation = Simulation::builder()
+            .bodies(vec![body("Earth", 5.972e24, 0.0, 0.0), body("Moon", 7.342e22, 3.844e8, 1022.0)])
+            .integrator(Integrator::Rk4)
+            .dt(60.0)
+            .duration(3600.0)
+            .record_count(3)
+            .observer(Collector::new(usize::MAX))
+            .build()
+            .unwrap();
+        simulation.run().unwrap();
+
+        assert!(simulation.bodies()[1].position.y > 0.0);
+        let result = simulation.into_observer().into_result();
+        assert_eq!(result.times(), vec![0.0, 1800.0, 3600.0]);
+    }
+
+    #[test]
+    fn test_steps_match_a_full_run() {
+        let bodies = vec![body("Earth", 5.972e24, 0.0, 0.0), body("Moon", 7.342e22, 3.844e8, 1022.0)];
+        let builder = || {
+            Simulation::builder()
+                .bodies(bodies.clone())
+                .integrator(Integrator::Rk4)
+                .dt(60.0)
+                .duration(600.0)
+        };
+        let mut full = builder().build().unwrap();
+        full.run().unwrap();
+
+        let mut stepped = builder().observer(Collector::new(usize::MAX)).build().unwrap();
+        let last = stepped.steps().take(9).last().unwrap();
+        assert_eq!(last.time, 540.0);
+        stepped.step(60.0).unwrap();
+        stepped.record().unwrap();
+
+        assert_eq!(stepped.time(), full.time());
+        assert_eq!(stepped.state()[1].position.y, full.state()[1].position.y);
+        assert_eq!(stepped.into_observer().into_result().times(), vec![600.0]);
+        assert!(full.step(-1.0).is_err());
+    }
+
+    #[test]
+    fn test_advance_to_lands_on_the_time() {
+        let mut simulation = Simulation::builder()
+            .bodies(vec![body("Earth", 5.972e24, 0.0, 0.0), body("Moon", 7.342e22, 3.844e8, 1022.0)])
+            .dt(60.0)
+            .build()
+            .unwrap();
+        simulation.advance_to(150.0).unwrap();
+        assert_eq!(simulation.time(), 150.0);
+        simulation.advance_to(100.0).unwrap();
+        assert_eq!(simulation.time(), 150.0);
+    }
+
+    #[test]
+    fn test_changes_between_steps_are_followed_and_logged() {
+        let mut simulation = Simulation::builder()
+            .bodies(vec![body("Earth", 5.972e24, 0.0, 0.0), body("Moon", 7.342e22, 3.844e8, 1022.0)])
+            .integrator(Integrator::Rk4)
+            .dt(60.0)
+            .build()
+            .unwrap();
+        simulation.advance_to(600.0).unwrap();
+        let kick = Change::Impulse {
+            body: "Moon".to_string(),
+            delta_v: Vector::new(0.0, 10.0, 0.0),
+        };
+        simulation.apply(kick).unwrap();
+        simulation.apply(Change::Add(body("Probe", 1e3, -4e7, -3e3))).unwrap();
+        assert!(simulation.apply(Change::Add(body("Probe", 1.0, 0.0, 0.0))).is_err());
+        assert!(simulation.apply(Change::Impulse { body: "Mars".to_string(), delta_v: Vector::null() }).is_err());
+
+        // A run starting from the changed state goes the same way.
+        let mut fresh = Simulation::builder()
+            .bodies(simulation.bodies().to_vec())
+            .integrator(Integrator::Rk4)
+            .dt(60.0)
+            .build()
+            .unwrap();
+        simulation.advance_to(1200.0).unwrap();
+        fresh.advance_to(600.0).unwrap();
+        for (body, expected) in simulation.bodies().iter().zip(fresh.bodies()) {
+            assert_eq!(body.position, expected.position, "{}", body.name);
+        }
+
+        let changes: Vec<_> = simulation.interventions().iter().map(|i| (i.time, i.steps, i.change.as_str())).collect();
+        assert_eq!(
+            changes,
+            [(600.0, 10, "impulse Moon 0 10 0"), (600.0, 10, "add Probe 1000 -40000000 0 0 0 -3000 0")]
+        );
+    }
+
+    #[test]
+    fn test_steps_start_from_the_pull_of_the_transferred_masses() {
+        let transfer = MassTransfer {
+            donor: "Donor".to_string(),
+            accretor: "Accretor".to_string(),
+            donor_radius: 0.5,
+            rate: 1.0,
+        };
+        for integrator in [Integrator::Verlet, Integrator::Rk4] {
+            let mut simulation = Simulation::builder()
+                .bodies(vec![body("Donor", 2.0, 0.4, 0.3), body("Accretor", 1.0, -0.6, -0.5)])
+                .gravity(1.0)
+                .integrator(integrator)
+                .mass_transfer(transfer.clone())
+                .dt(0.01)
+                .build()
+                .unwrap();
+            simulation.step(0.01).unwrap();
+            assert!(simulation.bodies()[0].mass < 2.0);
+
+            let mut fresh = simulation.bodies().to_vec();
+            Forces::newtonian(1.0).accelerate(&mut fresh);
+            for (body, expected) in simulation.bodies().iter().zip(&fresh) {
+                assert_eq!(body.acceleration, expected.acceleration, "{:?} {}", integrator, body.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid_combinations() {
+        let error = |builder: SimulationBuilder<Discard>| builder.build().err().unwrap().to_string();
+        let bodies = || vec![body("Earth", 5.972e24, 0.0, 0.0)];
+
+        assert!(error(Simulation::builder()).contains("at least one body"));
+        assert!(error(Simulation::builder().bodies(bodies()).dt(-1.0)).contains("time step"));
+        assert!(error(Simulation::builder().bodies(bodies()).duration(f64::NAN)).contains("duration"));
+        assert!(error(Simulation::builder().bodies(bodies()).record_count(0)).contains("record count"));
+        let twice = [bodies(), bodies()].concat();
+        assert!(error(Simulation::builder().bodies(twice)).contains("'Earth'"));
+    }
+}
@@ -0,0 +1,218 @@
+use super::dynamics::SequentialWriter;
+use super::Body;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// First bytes of every snapshot file.
+const MAGIC: &[u8; 8] = b"NBODYSNP";
+/// Version of the layout written, a little-endian u16 after the magic bytes.
+/// Readers load every version up to theirs.
+pub const VERSION: u16 = 1;
+
+/// State of the bodies at one time, stored as MessagePack after a short
+/// versioned header. Unlike the Parquet outputs it keeps everything about the
+/// bodies (accelerations, tags and temperatures included), so a run can go on
+/// from it exactly.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub time: f64,
+    /// Gravitational constant of the run, when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gravity: Option<f64>,
+    pub bodies: Vec<Body>,
+}
+
+pub fn read(path: &Path) -> Result<Snapshot, Box<dyn Error>> {
+    read_from(BufReader::new(File::open(path)?)).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// Writes `snapshot` to a temporary file next to `path` and then moves it
+/// there, so a run stopped while writing leaves the previous snapshot whole.
+pub fn write(path: &Path, snapshot: &Snapshot) -> Result<(), Box<dyn Error>> {
+    replace(path, snapshot, true)
+}
+
+/// Writes `snapshot` to the temporary file of `path` and moves it there,
+/// waiting for it to reach the disk first if `sync`.
+fn replace(path: &Path, snapshot: &Snapshot, sync: bool) -> Result<(), Box<dyn Error>> {
+    let temporary = temporary(path)?;
+    let mut writer = BufWriter::new(File::create(&temporary)?);
+    write_to(&mut writer, snapshot)?;
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    if sync {
+        file.sync_all()?;
+    }
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// File next to `path` that snapshots are written to before replacing it.
+fn temporary(path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let mut name = path.file_name().ok_or("missing snapshot file name")?.to_os_string();
+    name.push(".tmp");
+    Ok(path.with_file_name(name))
+}
+
+pub fn read_from(mut reader: impl Read) -> Result<Snapshot, Box<dyn Error>> {
+    let mut header = [0u8; MAGIC.len() + 2];
+    reader.read_exact(&mut header).map_err(|_| "not a snapshot file")?;
+    if !header.starts_with(MAGIC) {
+        return Err("not a snapshot file".into());
+    }
+    let version = u16::from_le_bytes([header[8], header[9]]);
+    if version > VERSION {
+        return Err(format!("snapshot version {} is newer than the supported {}", version, VERSION).into());
+    }
+    Ok(rmp_serde::decode::from_read(reader)?)
+}
+
+pub fn write_to(mut writer: impl Write, snapshot: &Snapshot) -> Result<(), Box<dyn Error>> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    // Fields by name, so later versions can add some and still read these.
+    rmp_serde::encode::write_named(&mut writer, snapshot)?;
+    Ok(())
+}
+
+/// Keeps the last recorded frame of a run in a snapshot file, replaced at
+/// every frame: a checkpoint to go on from if the run is stopped. Frames only
+/// reach the disk when the operating system flushes them, or at `close`.
+pub struct Writer {
+    path: PathBuf,
+    gravity: Option<f64>,
+    /// Whether a frame was written.
+    written: bool,
+}
+
+impl Writer {
+    pub fn new(path: &Path) -> Result<Self, Box<dyn Error>> {
+        // Fail now rather than at the first frame if the file can't be written,
+        // leaving any previous snapshot alone until then.
+        let temporary = temporary(path)?;
+        File::create(&temporary)?;
+        fs::remove_file(&temporary)?;
+        Ok(Writer {
+            path: path.to_path_buf(),
+            gravity: None,
+            written: false,
+        })
+    }
+
+    /// Stores the gravitational constant of the run in the snapshots.
+    pub fn with_gravity(mut self, gravity: f64) -> Self {
+        self.gravity = Some(gravity);
+        self
+    }
+
+    /// Waits for the last frame to reach the disk.
+    pub fn close(self) -> Result<(), Box<dyn Error>> {
+        if self.written {
+            File::open(&self.path)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl SequentialWriter for Writer {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        let snapshot = Snapshot {
+            time,
+            gravity: self.gravity,
+            bodies: bodies.to_vec(),
+        };
+        replace(&self.path, &snapshot, false)?;
+        self.written = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::{Tags, Vector};
+
+    fn snapshot() -> Snapshot {
+        let mut probe = Body {
+            name: "Probe".to_string(),
+            mass: 720.0,
+            position: Vector::new(1.496e11, -2.0, 1e-300),
+            velocity: Vector::new(0.0, 29780.0, f64::NAN),
+            acceleration: Vector::new(-5.9e-3, 0.0, 0.0),
+            tags: Tags::from([("category".to_string(), "spacecraft".to_string())]),
+            temperature: Some(280.5),
+        };
+        let sun = Body {
+            name: "Sun".to_string(),
+            mass: 1.989e30,
+            tags: Tags::new(),
+            temperature: None,
+            ..probe.clone()
+        };
+        probe.position.y = 0.1 + 0.2;
+        Snapshot {
+            time: 86400.25,
+            gravity: Some(6.67430e-11),
+            bodies: vec![sun, probe],
+        }
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut bytes = Vec::new();
+        write_to(&mut bytes, &snapshot()).unwrap();
+        assert!(bytes.starts_with(b"NBODYSNP\x01\x00"));
+
+        let read = read_from(bytes.as_slice()).unwrap();
+        assert_eq!((read.time, read.gravity), (86400.25, Some(6.67430e-11)));
+        let (sun, probe) = (&read.bodies[0], &read.bodies[1]);
+        assert_eq!((sun.name.as_str(), sun.mass, sun.temperature), ("Sun", 1.989e30, None));
+        assert!(sun.tags.is_empty());
+        assert_eq!(probe.position.to_array(), [1.496e11, 0.1 + 0.2, 1e-300]);
+        assert!(probe.velocity.z.is_nan());
+        assert_eq!(probe.acceleration.x, -5.9e-3);
+        assert_eq!(probe.tags["category"], "spacecraft");
+        assert_eq!(probe.temperature, Some(280.5));
+    }
+
+    #[test]
+    fn test_versions_are_checked() {
+        let mut bytes = Vec::new();
+        write_to(&mut bytes, &snapshot()).unwrap();
+        bytes[8] = 2;
+        let error = read_from(bytes.as_slice()).unwrap_err().to_string();
+        assert!(error.contains("version 2"), "{}", error);
+        assert!(read_from(&b"PAR1"[..]).is_err());
+        assert!(read_from(&b"NBODYSNP\x01\x00\x93"[..]).is_err());
+    }
+
+    #[test]
+    fn test_writer_keeps_the_last_frame() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("run.msgpack");
+        let bodies = snapshot().bodies;
+
+        let mut writer = Writer::new(&path).unwrap().with_gravity(1.0);
+        writer.add(0.0, &bodies).unwrap();
+        writer.add(60.0, &bodies[1..]).unwrap();
+        writer.close().unwrap();
+
+        let last = read(&path).unwrap();
+        assert_eq!((last.time, last.gravity, last.bodies.len()), (60.0, Some(1.0), 1));
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_writer_keeps_the_previous_snapshot_until_the_first_frame() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("run.msgpack");
+        write(&path, &snapshot()).unwrap();
+
+        Writer::new(&path).unwrap().close().unwrap();
+        assert_eq!(read(&path).unwrap().time, 86400.25);
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+        assert!(Writer::new(&temp_dir.path().join("missing").join("run.msgpack")).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+use super::body::{Tags, Vector};
+use super::forces::{Cells, PeriodicBox};
+use super::thermal::Thermal;
+use super::Body;
+use std::error::Error;
+use std::f64::consts::PI;
+
+/// Smoothed-particle hydrodynamics: bodies carrying the `gas` tags are fluid
+/// elements that, besides gravity, push on each other with the pressure of an
+/// isothermal gas (`P = c²ρ`) and resist compression with Monaghan's
+/// artificial viscosity. With `thermal` settings, particles that have a
+/// temperature use the pressure of an ideal gas at that temperature instead.
+///
+/// Densities come from the cubic spline kernel with a fixed smoothing length
+/// `h`, so each particle interacts with the others within `2h`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sph {
+    pub smoothing_length: f64,
+    /// Isothermal sound speed in m/s.
+    pub sound_speed: f64,
+    /// Linear and quadratic (shock) coefficients of the artificial viscosity.
+    pub alpha: f64,
+    pub beta: f64,
+    /// Tags of the gas particles; empty makes every body gas.
+    pub gas: Tags,
+}
+
+impl Sph {
+    /// Every body is gas, with the usual viscosity coefficients (α = 1, β = 2).
+    pub fn new(smoothing_length: f64, sound_speed: f64) -> Self {
+        Sph {
+            smoothing_length,
+            sound_speed,
+            alpha: 1.0,
+            beta: 2.0,
+            gas: Tags::new(),
+        }
+    }
+
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        if !(self.smoothing_length.is_finite() && self.smoothing_length > 0.0) {
+            return Err(format!("SPH smoothing length must be positive, got {}", self.smoothing_length).into());
+        }
+        if !(self.sound_speed.is_finite() && self.sound_speed >= 0.0) {
+            return Err(format!("sound speed must not be negative, got {}", self.sound_speed).into());
+        }
+        if !(self.alpha >= 0.0 && self.beta >= 0.0) {
+            return Err("viscosity coefficients must not be negative".into());
+        }
+        Ok(())
+    }
+
+    /// Density at every gas particle, in the order of `gas_indices`.
+    pub fn densities(&self, bodies: &[Body], periodic: Option<&PeriodicBox>) -> Vec<f64> {
+        self.gas(bodies, periodic, None).density
+    }
+
+    /// Indices of the gas particles among `bodies`.
+    pub fn gas_indices(&self, bodies: &[Body]) -> Vec<usize> {
+        (0..bodies.len()).filter(|&i| bodies[i].has_tags(&self.gas)).collect()
+    }
+
+    /// Adds the pressure and viscosity accelerations to the gas particles.
+    pub(crate) fn accelerate(&self, bodies: &mut [Body], periodic: Option<&PeriodicBox>, thermal: Option<&Thermal>) {
+        let gas = self.gas(bodies, periodic, thermal);
+        for i in 0..gas.bodies.len() {
+            let mut acceleration = Vector::null();
+            self.interact(&gas, i, periodic, |j, d, slope, viscosity| {
+                let term = gas.pressure(i) + gas.pressure(j) + viscosity;
+                acceleration -= gas.bodies[j].mass * term * slope * d;
+            });
+            bodies[gas.indices[i]].acceleration += acceleration;
+        }
+    }
+
+    /// Heating of every body in W/kg: the compression work and the viscous
+    /// dissipation on the gas particles, zero for the other bodies.
+    pub(crate) fn heating(&self, bodies: &[Body], periodic: Option<&PeriodicBox>, thermal: Option<&Thermal>) -> Vec<f64> {
+        let gas = self.gas(bodies, periodic, thermal);
+        let mut heating = vec![0.0; bodies.len()];
+        for i in 0..gas.bodies.len() {
+            let mut rate = 0.0;
+            self.interact(&gas, i, periodic, |j, d, slope, viscosity| {
+                let v = gas.bodies[i].velocity - gas.bodies[j].velocity;
+                let term = gas.pressure(i) + viscosity / 2.0;
+                rate += gas.bodies[j].mass * term * slope * v.dot(&d);
+            });
+            heating[gas.indices[i]] = rate;
+        }
+        heating
+    }
+
+    /// The gas particles of `bodies`, with their densities and sound speeds.
+    fn gas(&self, bodies: &[Body], periodic: Option<&PeriodicBox>, thermal: Option<&Thermal>) -> Gas {
+        let indices = self.gas_indices(bodies);
+        let gas: Vec<Body> = indices.iter().map(|&i| bodies[i].clone()).collect();
+        let neighbors = Neighbors::new(&gas, 2.0 * self.smoothing_length, periodic);
+        let density: Vec<f64> = (0..gas.len())
+            .map(|i| {
+                neighbors
+                    .of(i)
+                    .map(|j| {
+                        let d = separation(&gas[i].position, &gas[j].position, periodic);
+                        gas[j].mass * kernel(d.norm(), self.smoothing_length)
+                    })
+                    .sum()
+            })
+            .collect();
+        let sound_speed = gas
+            .iter()
+            .map(|body| match (thermal, body.temperature) {
+                (Some(thermal), Some(temperature)) => thermal.pressure_per_density(temperature).sqrt(),
+                _ => self.sound_speed,
+            })
+            .collect();
+        Gas {
+            indices,
+            bodies: gas,
+            neighbors,
+            density,
+            sound_speed,
+        }
+    }
+
+    /// Calls `f` for every neighbor `j` of gas particle `i` within the kernel
+    /// support, with their separation `d = r_i - r_j`, the kernel slope over
+    /// the distance and the artificial viscosity Π_ij.
+    fn interact(&self, gas: &Gas, i: usize, periodic: Option<&PeriodicBox>, mut f: impl FnMut(usize, Vector, f64, f64)) {
+        let h = self.smoothing_length;
+        for j in gas.neighbors.of(i).filter(|&j| j != i) {
+            let d = separation(&gas.bodies[i].position, &gas.bodies[j].position, periodic);
+            let r = d.norm();
+            if r == 0.0 || r >= 2.0 * h {
+                continue;
+            }
+            let mut viscosity = 0.0;
+            let v = gas.bodies[i].velocity - gas.bodies[j].velocity;
+            let approach = v.dot(&d);
+            if approach < 0.0 {
+                let mu = h * approach / (r * r + 0.01 * h * h);
+                let density = (gas.density[i] + gas.density[j]) / 2.0;
+                let sound_speed = (gas.sound_speed[i] + gas.sound_speed[j]) / 2.0;
+                viscosity = (-self.alpha * sound_speed * mu + self.beta * mu * mu) / density;
+            }
+            f(j, d, kernel_slope(r, h) / r, viscosity);
+        }
+    }
+}
+
+/// Gas particles copied out of the bodies, with what their interactions need.
+struct Gas {
+    /// Index of each particle among the bodies.
+    indices: Vec<usize>,
+    bodies: Vec<Body>,
+    neighbors: Neighbors,
+    density: Vec<f64>,
+    /// Isothermal sound speed of each particle, so that `P = c²ρ`.
+    sound_speed: Vec<f64>,
+}
+
+impl Gas {
+    /// P/ρ² of particle `i`.
+    fn pressure(&self, i: usize) -> f64 {
+        self.sound_speed[i] * self.sound_speed[i] / self.density[i]
+    }
+}
+
+/// The cubic spline (M4) kernel in 3D, normalized to integrate to one over its
+/// support of radius `2h`.
+pub fn kernel(r: f64, h: f64) -> f64 {
+    let q = r / h;
+    let sigma = 1.0 / (PI * h * h * h);
+    if q < 1.0 {
+        sigma * (1.0 - 1.5 * q * q + 0.75 * q * q * q)
+    } else if q < 2.0 {
+        sigma * 0.25 * (2.0 - q).powi(3)
+    } else {
+        0.0
+    }
+}
+
+/// dW/dr of the `kernel`.
+fn kernel_slope(r: f64, h: f64) -> f64 {
+    let q = r / h;
+    let sigma = 1.0 / (PI * h * h * h * h);
+    if q < 1.0 {
+        sigma * (-3.0 * q + 2.25 * q * q)
+    } else if q < 2.0 {
+        -sigma * 0.75 * (2.0 - q).powi(2)
+    } else {
+        0.0
+    }
+}
+
+/// `a - b`, through the faces of a periodic box if there is one.
+fn separation(a: &Vector, b: &Vector, periodic: Option<&PeriodicBox>) -> Vector {
+    let d = *a - *b;
+    periodic.map_or(d, |periodic| periodic.separation(d))
+}
+
+/// Candidate partners of each particle: those in nearby cells, or all of them.
+enum Neighbors {
+    Cells(Cells),
+    All(usize),
+}
+
+impl Neighbors {
+    fn new(bodies: &[Body], range: f64, periodic: Option<&PeriodicBox>) -> Self {
+        match Cells::new(bodies, range, periodic) {
+            Some(cells) => Neighbors::Cells(cells),
+            None => Neighbors::All(bodies.len()),
+        }
+    }
+
+    fn of(&self, i: usize) -> Box<dyn Iterator<Item = usize> + '_> {
+        match self {
+            Neighbors::Cells(cells) => Box::new(cells.neighbors(i)),
+            Neighbors::All(count) => Box::new(0..*count),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn particle(name: &str, position: Vector, velocity: Vector) -> Body {
+        Body {
+            name: name.to_string(),
+            mass: 1.0,
+            position,
+            velocity,
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    #[test]
+    fn test_kernel_is_normalized() {
+        let h = 0.5;
+        let dr = 0.001;
+        let integral: f64 = (0..(2.0 * h / dr) as usize)
+            .map(|k| {
+                let r = (k as f64 + 0.5) * dr;
+                4.0 * PI * r * r * kernel(r, h) * dr
+            })
+            .sum();
+        assert!((integral - 1.0).abs() < 1e-6, "{}", integral);
+    }
+
+    #[test]
+    fn test_lattice_density_matches_mass_per_volume() {
+        let spacing = 1.0;
+        let bodies: Vec<Body> = (0..1000)
+            .map(|i| {
+                let (x, y, z) = ((i % 10) as f64, ((i / 10) % 10) as f64, (i / 100) as f64);
+                particle(&format!("P{}", i), Vector::new(x, y, z) * spacing, Vector::null())
+            })
+            .collect();
+        let density = Sph::new(1.2 * spacing, 1.0).densities(&bodies, Some(&PeriodicBox::new(10.0)));
+        for rho in density {
+            assert!((rho - 1.0).abs() < 0.02, "{}", rho);
+        }
+    }
+
+    #[test]
+    fn test_pressure_pushes_apart_and_viscosity_resists_approach() {
+        let sph = Sph::new(1.0, 1.0);
+        let at_rest = |vx: f64| {
+            vec![
+                particle("A", Vector::null(), Vector::new(vx, 0.0, 0.0)),
+                particle("B", Vector::new(1.0, 0.0, 0.0), Vector::null()),
+            ]
+        };
+        let mut still = at_rest(0.0);
+        sph.accelerate(&mut still, None, None);
+        assert!(still[0].acceleration.x < 0.0);
+        assert_eq!(still[0].acceleration.x, -still[1].acceleration.x);
+
+        let mut approaching = at_rest(1.0);
+        sph.accelerate(&mut approaching, None, None);
+        assert!(approaching[0].acceleration.x < still[0].acceleration.x);
+
+        let mut tagged = at_rest(0.0);
+        let sph = Sph {
+            gas: Tags::from([("phase".to_string(), "gas".to_string())]),
+            ..sph
+        };
+        sph.accelerate(&mut tagged, None, None);
+        assert_eq!(tagged[0].acceleration, Vector::null());
+    }
+
+    #[test]
+    fn test_compression_heats_and_expansion_cools() {
+        let sph = Sph { alpha: 0.0, beta: 0.0, ..Sph::new(1.0, 1.0) };
+        let thermal = Thermal::new(f64::INFINITY);
+        let pair = |vx: f64| {
+            let mut bodies = vec![
+                particle("A", Vector::null(), Vector::new(vx, 0.0, 0.0)),
+                particle("B", Vector::new(1.0, 0.0, 0.0), Vector::null()),
+            ];
+            bodies[0].temperature = Some(100.0);
+            bodies
+        };
+        let approaching = sph.heating(&pair(1.0), None, Some(&thermal));
+        assert!(approaching[0] > 0.0 && approaching[1] > 0.0);
+        assert_eq!(approaching[0], -sph.heating(&pair(-1.0), None, Some(&thermal))[0]);
+
+        // Warmer gas pushes harder than the isothermal sound speed of 1 m/s.
+        let mut warm = pair(0.0);
+        sph.accelerate(&mut warm, None, Some(&thermal));
+        let mut isothermal = pair(0.0);
+        sph.accelerate(&mut isothermal, None, None);
+        assert!(warm[0].acceleration.x < isothermal[0].acceleration.x);
+        // Viscous heating adds to the compression work.
+        let viscous = Sph::new(1.0, 1.0).heating(&pair(1.0), None, Some(&thermal));
+        assert!(viscous[0] > approaching[0]);
+    }
+}
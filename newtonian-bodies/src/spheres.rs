@@ -0,0 +1,115 @@
+use super::dynamics::SequentialWriter;
+use super::patched_conics::Hierarchy;
+use super::Body;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Writes an event whenever a body passes from the sphere of influence of one
+/// body to another's between two recorded times, as CSV
+/// (`time,name,from,to,x,y,z,vx,vy,vz`) with its state relative to the body
+/// it now orbits at the first of them. The spheres are those of the patched
+/// conics (see [`Hierarchy`]).
+pub struct Writer {
+    csv: BufWriter<File>,
+    hierarchy: Hierarchy,
+    /// Body each body orbited at the previous recorded time, by name.
+    parents: HashMap<String, String>,
+}
+
+impl Writer {
+    pub fn new(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut csv = BufWriter::new(File::create(path)?);
+        writeln!(csv, "time,name,from,to,x,y,z,vx,vy,vz")?;
+        Ok(Writer {
+            csv,
+            hierarchy: Hierarchy::default(),
+            parents: HashMap::new(),
+        })
+    }
+
+    pub fn close(mut self) -> Result<(), Box<dyn Error>> {
+        self.csv.flush()?;
+        Ok(())
+    }
+}
+
+impl SequentialWriter for Writer {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        self.hierarchy.update(bodies);
+        for (i, body) in bodies.iter().enumerate() {
+            let Some(parent) = self.hierarchy.parent(i).map(|p| &bodies[p]) else {
+                continue;
+            };
+            let Some(previous) = self.parents.insert(body.name.clone(), parent.name.clone()) else {
+                continue;
+            };
+            if previous == parent.name {
+                continue;
+            }
+            let (position, velocity) = (body.position - parent.position, body.velocity - parent.velocity);
+            writeln!(
+                self.csv,
+                "{},\"{}\",\"{}\",\"{}\",{},{},{},{},{},{}",
+                time,
+                body.name.replace('"', "\"\""),
+                previous.replace('"', "\"\""),
+                parent.name.replace('"', "\"\""),
+                position.x,
+                position.y,
+                position.z,
+                velocity.x,
+                velocity.y,
+                velocity.z
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::{Tags, Vector};
+
+    fn body(name: &str, mass: f64, x: f64, vx: f64) -> Body {
+        Body {
+            name: name.to_string(),
+            mass,
+            position: Vector::new(x, 0.0, 0.0),
+            velocity: Vector::new(vx, 0.0, 0.0),
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    #[test]
+    fn test_writes_a_row_per_change_of_sphere() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("soi.csv");
+        // The planet's sphere reaches 100 (0.01^0.4) ≈ 15.8 from it.
+        let frame = |x: f64| {
+            [
+                body("Sun", 1.0, 0.0, 0.0),
+                body("Planet", 0.01, 100.0, 0.0),
+                body("Probe", 1e-20, x, 2.0),
+            ]
+        };
+
+        let mut writer = Writer::new(&path).unwrap();
+        writer.add(0.0, &frame(80.0)).unwrap();
+        writer.add(1.0, &frame(95.0)).unwrap();
+        writer.add(2.0, &frame(97.0)).unwrap();
+        writer.add(3.0, &frame(120.0)).unwrap();
+        writer.close().unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1], "1,\"Probe\",\"Sun\",\"Planet\",-5,0,0,2,0,0");
+        assert_eq!(lines[2], "3,\"Probe\",\"Planet\",\"Sun\",120,0,0,2,0,0");
+    }
+}
@@ -0,0 +1,445 @@
+use super::body::Vector;
+use super::Body;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const RECORD_BYTES: u64 = 1024;
+/// Segment summaries fitting in a summary record after its three control words.
+const MAX_SUMMARIES: usize = 25;
+/// NAIF id of the solar system barycenter, the root every state is chained to.
+const SOLAR_SYSTEM_BARYCENTER: i32 = 0;
+/// Seconds per day in the TDB time scale used by SPK kernels.
+const DAY: f64 = 86400.0;
+
+/// NAIF ids and GM (km³/s², DE440 values) of the bodies that can be named.
+///
+/// Mercury, Venus, the Earth and the Moon are the bodies themselves; the other
+/// planets are the barycenters of their systems, as in the DE kernels, and carry
+/// the mass of the whole system.
+const BODIES: [(&str, i32, f64); 11] = [
+    ("sun", 10, 132712440041.27942),
+    ("mercury", 199, 22031.868551),
+    ("venus", 299, 324858.592000),
+    ("earth", 399, 398600.435507),
+    ("moon", 301, 4902.800118),
+    ("mars", 4, 42828.375816),
+    ("jupiter", 5, 126712764.100000),
+    ("saturn", 6, 37940584.841800),
+    ("uranus", 7, 5794556.400000),
+    ("neptune", 8, 6836527.100580),
+    ("pluto", 9, 975.500000),
+];
+
+/// Ephemeris segments loaded from SPICE SPK kernels (types 2 and 3, the
+/// Chebyshev formats of the JPL DE planetary ephemerides).
+///
+/// Only the segment directories are read up front; coefficients are read from
+/// the files as states are evaluated.
+pub struct Ephemeris {
+    kernels: Vec<Kernel>,
+    segments: Vec<Segment>,
+}
+
+struct Segment {
+    kernel: usize,
+    target: i32,
+    center: i32,
+    start: f64,
+    end: f64,
+    kind: i32,
+    /// First and last double-precision word of the segment data (1-based).
+    begin_word: u64,
+    end_word: u64,
+}
+
+struct Kernel {
+    file: File,
+    little_endian: bool,
+}
+
+impl Ephemeris {
+    /// Opens the kernels. Later kernels take precedence, as in SPICE.
+    pub fn open(paths: &[impl AsRef<Path>]) -> Result<Self, Box<dyn Error>> {
+        let mut ephemeris = Ephemeris {
+            kernels: Vec::new(),
+            segments: Vec::new(),
+        };
+        for path in paths {
+            let mut kernel = Kernel::open(path.as_ref())?;
+            ephemeris.segments.extend(kernel.segments(ephemeris.kernels.len())?);
+            ephemeris.kernels.push(kernel);
+        }
+        Ok(ephemeris)
+    }
+
+    /// Position (m) and velocity (m/s) of `target` relative to the solar
+    /// system barycenter, in the J2000 frame, at `et` seconds past J2000 TDB.
+    pub fn state(&mut self, target: i32, et: f64) -> Result<(Vector, Vector), Box<dyn Error>> {
+        let mut position = [0.0; 3];
+        let mut velocity = [0.0; 3];
+        let mut body = target;
+        while body != SOLAR_SYSTEM_BARYCENTER {
+            let segment = self
+                .segments
+                .iter()
+                .rev()
+                .find(|s| s.target == body && s.start <= et && et <= s.end)
+                .ok_or_else(|| format!("no ephemeris data for NAIF id {} at {} s past J2000", body, et))?;
+            let (p, v) = segment.evaluate(&mut self.kernels[segment.kernel], et)?;
+            for k in 0..3 {
+                position[k] += p[k];
+                velocity[k] += v[k];
+            }
+            body = segment.center;
+        }
+        // Kernels store kilometers.
+        let to_vector = |v: [f64; 3]| Vector {
+            x: v[0] * 1e3,
+            y: v[1] * 1e3,
+            z: v[2] * 1e3,
+        };
+        Ok((to_vector(position), to_vector(velocity)))
+    }
+}
+
+impl Kernel {
+    fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut file = File::open(path)?;
+        let mut record = [0u8; RECORD_BYTES as usize];
+        file.read_exact(&mut record)?;
+        if &record[0..8] != b"DAF/SPK " {
+            return Err(format!("{} is not an SPK kernel", path.display()).into());
+        }
+        let little_endian = match &record[88..96] {
+            b"LTL-IEEE" => true,
+            b"BIG-IEEE" => false,
+            other => return Err(format!("unsupported kernel byte order '{}'", String::from_utf8_lossy(other)).into()),
+        };
+        Ok(Kernel { file, little_endian })
+    }
+
+    fn int(&self, bytes: &[u8]) -> i32 {
+        let bytes = bytes.try_into().unwrap();
+        if self.little_endian { i32::from_le_bytes(bytes) } else { i32::from_be_bytes(bytes) }
+    }
+
+    fn double(&self, bytes: &[u8]) -> f64 {
+        let bytes = bytes.try_into().unwrap();
+        if self.little_endian { f64::from_le_bytes(bytes) } else { f64::from_be_bytes(bytes) }
+    }
+
+    /// Reads `count` doubles starting at the 1-based word address `word`.
+    fn doubles(&mut self, word: u64, count: usize) -> Result<Vec<f64>, Box<dyn Error>> {
+        let start = word.checked_sub(1).and_then(|word| word.checked_mul(8));
+        let length = (count as u64).checked_mul(8);
+        let end = start.zip(length).and_then(|(start, length)| start.checked_add(length));
+        match (start, length, end) {
+            (Some(start), Some(length), Some(end)) if end <= self.file.metadata()?.len() => {
+                let mut bytes = vec![0u8; length as usize];
+                self.file.seek(SeekFrom::Start(start))?;
+                self.file.read_exact(&mut bytes)?;
+                Ok(bytes.chunks(8).map(|b| self.double(b)).collect())
+            }
+            _ => Err(format!("corrupt SPK kernel: {} words at word {} are outside the file", count, word).into()),
+        }
+    }
+
+    /// Walks the linked list of summary records.
+    fn segments(&mut self, kernel: usize) -> Result<Vec<Segment>, Box<dyn Error>> {
+        let mut record = [0u8; RECORD_BYTES as usize];
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_exact(&mut record)?;
+        let (nd, ni) = (self.int(&record[8..12]), self.int(&record[12..16]));
+        if (nd, ni) != (2, 6) {
+            return Err(format!("unexpected SPK summary layout ND={} NI={}", nd, ni).into());
+        }
+        // Two doubles, then six integers packed in three doubles.
+        let summary_bytes = 40;
+
+        let mut segments = Vec::new();
+        let mut visited = HashSet::new();
+        let mut next = self.int(&record[76..80]) as u64;
+        while next != 0 {
+            if !visited.insert(next) {
+                return Err(format!("corrupt SPK kernel: summary record {} links back to itself", next).into());
+            }
+            let start = (next - 1).checked_mul(RECORD_BYTES).ok_or("corrupt SPK kernel: invalid summary record")?;
+            self.file.seek(SeekFrom::Start(start))?;
+            self.file.read_exact(&mut record)?;
+            let count = self.double(&record[16..24]) as usize;
+            if count > MAX_SUMMARIES {
+                let error = format!("corrupt SPK kernel: {} summaries in a record, at most {}", count, MAX_SUMMARIES);
+                return Err(error.into());
+            }
+            for i in 0..count {
+                let summary = &record[24 + i * summary_bytes..24 + (i + 1) * summary_bytes];
+                let int = |k: usize| self.int(&summary[16 + 4 * k..20 + 4 * k]);
+                segments.push(Segment {
+                    kernel,
+                    target: int(0),
+                    center: int(1),
+                    start: self.double(&summary[0..8]),
+                    end: self.double(&summary[8..16]),
+                    kind: int(3),
+                    begin_word: int(4) as u64,
+                    end_word: int(5) as u64,
+                });
+            }
+            next = self.double(&record[0..8]) as u64;
+        }
+        Ok(segments)
+    }
+}
+
+impl Segment {
+    /// State of the target relative to the segment center, in km and km/s.
+    fn evaluate(&self, kernel: &mut Kernel, et: f64) -> Result<([f64; 3], [f64; 3]), Box<dyn Error>> {
+        if self.kind != 2 && self.kind != 3 {
+            return Err(format!("SPK segment type {} is not supported (only types 2 and 3)", self.kind).into());
+        }
+        let corrupt = |what: &str| format!("corrupt SPK kernel: segment of body {} has {}", self.target, what);
+        // The directory at the end of the segment: INIT, INTLEN, RSIZE, N.
+        if self.end_word < self.begin_word || self.end_word < 4 {
+            return Err(corrupt("no directory").into());
+        }
+        let directory = kernel.doubles(self.end_word - 3, 4)?;
+        let (init, interval, size, count) = (directory[0], directory[1], directory[2] as usize, directory[3] as usize);
+        let components = if self.kind == 2 { 3 } else { 6 };
+        // MID and RADIUS, then at least one coefficient per component.
+        if count == 0 || size < 2 + components {
+            return Err(corrupt(&format!("{} records of {} words", count, size)).into());
+        }
+        let index = (((et - init) / interval).floor().max(0.0) as usize).min(count - 1);
+        let first = (index as u64).checked_mul(size as u64).and_then(|offset| self.begin_word.checked_add(offset));
+        let first = match first {
+            Some(first) if first.saturating_add(size as u64 + 3) <= self.end_word => first,
+            _ => return Err(corrupt(&format!("record {} past its end", index)).into()),
+        };
+        let record = kernel.doubles(first, size)?;
+
+        let (mid, radius) = (record[0], record[1]);
+        let s = (et - mid) / radius;
+        let n = (size - 2) / components;
+        let series = |component: usize| &record[2 + component * n..2 + (component + 1) * n];
+
+        let mut position = [0.0; 3];
+        let mut velocity = [0.0; 3];
+        for k in 0..3 {
+            let (value, derivative) = chebyshev(series(k), s);
+            position[k] = value;
+            // Type 3 stores velocity series; type 2 differentiates the position one.
+            velocity[k] = if self.kind == 2 { derivative / radius } else { chebyshev(series(k + 3), s).0 };
+        }
+        Ok((position, velocity))
+    }
+}
+
+/// Evaluates a Chebyshev series and its derivative at `s` in [-1, 1].
+fn chebyshev(coefficients: &[f64], s: f64) -> (f64, f64) {
+    let (mut t0, mut t1) = (1.0, s);
+    let (mut d0, mut d1) = (0.0, 1.0);
+    let mut value = 0.0;
+    let mut derivative = 0.0;
+    for (k, c) in coefficients.iter().enumerate() {
+        let (t, d) = match k {
+            0 => (t0, d0),
+            1 => (t1, d1),
+            _ => {
+                let t = 2.0 * s * t1 - t0;
+                let d = 2.0 * t1 + 2.0 * s * d1 - d0;
+                (t0, t1, d0, d1) = (t1, t, d1, d);
+                (t, d)
+            }
+        };
+        value += c * t;
+        derivative += c * d;
+    }
+    (value, derivative)
+}
+
+/// Looks up the NAIF id and GM (m³/s²) of a body by name, case-insensitively.
+pub fn lookup(name: &str) -> Option<(i32, f64)> {
+    BODIES
+        .iter()
+        .find(|(known, _, _)| known.eq_ignore_ascii_case(name))
+        .map(|(_, id, gm)| (*id, gm * 1e9))
+}
+
+/// Builds the initial conditions of the named bodies at `et` seconds past J2000 TDB.
+///
+/// Masses are derived from the tabulated GM so that `gravity` times the mass
+/// reproduces the ephemeris dynamics.
+pub fn bodies(kernels: &[impl AsRef<Path>], names: &[String], et: f64, gravity: f64) -> Result<Vec<Body>, Box<dyn Error>> {
+    let mut ephemeris = Ephemeris::open(kernels)?;
+    names
+        .iter()
+        .map(|name| {
+            let (id, gm) = lookup(name).ok_or_else(|| {
+                let known: Vec<&str> = BODIES.iter().map(|(name, _, _)| *name).collect();
+                format!("unknown body '{}' (expected one of {})", name, known.join(", "))
+            })?;
+            let (position, velocity) = ephemeris.state(id, et)?;
+            Ok(Body::new(name.clone(), gm / gravity, position, velocity))
+        })
+        .collect()
+}
+
+/// Parses an epoch as seconds past J2000 TDB: either a number of seconds or a
+/// calendar date "YYYY-MM-DD" with an optional "THH:MM:SS" time, read as TDB.
+pub fn parse_epoch(text: &str) -> Result<f64, String> {
+    if let Ok(seconds) = text.parse::<f64>() {
+        return Ok(seconds);
+    }
+    let invalid = || format!("invalid epoch '{}' (expected YYYY-MM-DD[THH:MM:SS] or seconds past J2000)", text);
+    let (date, time) = text.split_once(['T', ' ']).unwrap_or((text, "00:00:00"));
+
+    let date: Vec<i64> = date.split('-').map(|p| p.parse().map_err(|_| invalid())).collect::<Result<_, _>>()?;
+    let [year, month, day] = date[..] else { return Err(invalid()) };
+    let time: Vec<f64> = time.split(':').map(|p| p.parse().map_err(|_| invalid())).collect::<Result<_, _>>()?;
+    let (hours, minutes, seconds) = match time[..] {
+        [h] => (h, 0.0, 0.0),
+        [h, m] => (h, m, 0.0),
+        [h, m, s] => (h, m, s),
+        _ => return Err(invalid()),
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+
+    // J2000 is 2000-01-01T12:00:00.
+    let days = days_from_civil(year, month, day) - days_from_civil(2000, 1, 1);
+    Ok(days as f64 * DAY + (hours - 12.0) * 3600.0 + minutes * 60.0 + seconds)
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    /// Writes a little-endian SPK with one type 2 segment per (target, center, coefficients).
+    fn write_kernel(segments: &[(i32, i32, [[f64; 3]; 3])]) -> NamedTempFile {
+        let mut words: Vec<f64> = Vec::new();
+        let mut summaries = Vec::new();
+        // Data starts after the file record (1), summary record (2) and name record (3).
+        let first_word = 3 * 128 + 1;
+        for (target, center, coefficients) in segments {
+            let begin = first_word + words.len() as i32;
+            // One record covering [-100, 100] s: MID, RADIUS, then degree-2 series per axis.
+            words.extend([0.0, 100.0]);
+            for axis in coefficients {
+                words.extend(axis);
+            }
+            words.extend([-100.0, 200.0, 11.0, 1.0]);
+            let end = first_word + words.len() as i32 - 1;
+            summaries.push((*target, *center, begin, end));
+        }
+
+        let mut bytes = vec![0u8; 3 * RECORD_BYTES as usize];
+        bytes[0..8].copy_from_slice(b"DAF/SPK ");
+        bytes[8..12].copy_from_slice(&2i32.to_le_bytes());
+        bytes[12..16].copy_from_slice(&6i32.to_le_bytes());
+        bytes[76..80].copy_from_slice(&2i32.to_le_bytes());
+        bytes[88..96].copy_from_slice(b"LTL-IEEE");
+
+        let summary_record = &mut bytes[1024..2048];
+        summary_record[16..24].copy_from_slice(&(summaries.len() as f64).to_le_bytes());
+        for (i, (target, center, begin, end)) in summaries.iter().enumerate() {
+            let summary = &mut summary_record[24 + i * 40..64 + i * 40];
+            summary[0..8].copy_from_slice(&(-100.0f64).to_le_bytes());
+            summary[8..16].copy_from_slice(&100.0f64.to_le_bytes());
+            // Target, center, frame (J2000), type, data addresses.
+            for (k, value) in [*target, *center, 1, 2, *begin, *end].iter().enumerate() {
+                summary[16 + 4 * k..20 + 4 * k].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+        for word in words {
+            bytes.extend(word.to_le_bytes());
+        }
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&bytes).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_states_are_chained_to_the_barycenter() {
+        // Earth-Moon barycenter at x = 1000 + 10 s km; Earth at y = 5 km from it.
+        let kernel = write_kernel(&[
+            (3, 0, [[1000.0, 1000.0, 0.0], [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]]),
+            (399, 3, [[0.0, 0.0, 0.0], [5.0, 0.0, 0.0], [0.0, 0.0, 0.0]]),
+        ]);
+        let mut ephemeris = Ephemeris::open(&[kernel.path()]).unwrap();
+
+        let (position, velocity) = ephemeris.state(399, 50.0).unwrap();
+        assert!((position.x - 1.5e6).abs() < 1e-6);
+        assert!((position.y - 5e3).abs() < 1e-9);
+        // d/dt (1000 + 1000 * t / 100) km = 10 km/s
+        assert!((velocity.x - 1e4).abs() < 1e-9);
+
+        assert!(ephemeris.state(499, 0.0).is_err());
+        assert!(ephemeris.state(399, 500.0).is_err());
+    }
+
+    #[test]
+    fn test_damaged_kernels_are_errors() {
+        let kernel = write_kernel(&[(399, 0, [[1.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]])]);
+        let bytes = std::fs::read(kernel.path()).unwrap();
+        // The directory follows MID, RADIUS and three series of three coefficients.
+        let directory = 3 * RECORD_BYTES as usize + 11 * 8;
+        let damaged = |offset: usize, value: f64| {
+            let mut bytes = bytes.clone();
+            bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(&bytes).unwrap();
+            let error = Ephemeris::open(&[file.path()]).and_then(|mut ephemeris| ephemeris.state(399, 0.0));
+            error.err().map(|e| e.to_string()).unwrap_or_default()
+        };
+
+        // Too many summaries, and a summary record linking back to itself.
+        assert!(damaged(1024 + 16, 26.0).contains("26 summaries"));
+        assert!(damaged(1024, 2.0).contains("links back to itself"));
+        // No records, records too short for their series, or longer than the segment.
+        assert!(damaged(directory + 24, 0.0).contains("0 records"));
+        assert!(damaged(directory + 16, 1.0).contains("records of 1 words"));
+        assert!(damaged(directory + 16, 1e12).contains("past its end"));
+        assert!(damaged(directory + 24, 1.0).is_empty());
+    }
+
+    #[test]
+    fn test_chebyshev_matches_polynomials() {
+        // 1 + 2 T1 + 3 T2 = 1 + 2s + 3(2s² - 1)
+        let (value, derivative) = chebyshev(&[1.0, 2.0, 3.0], 0.5);
+        assert!((value - (1.0 + 1.0 + 3.0 * (0.5 - 1.0))).abs() < 1e-12);
+        assert!((derivative - (2.0 + 12.0 * 0.5)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_parse_epoch() {
+        assert_eq!(parse_epoch("2000-01-01T12:00:00").unwrap(), 0.0);
+        assert_eq!(parse_epoch("2000-01-02").unwrap(), 43200.0);
+        assert_eq!(parse_epoch("1999-12-31T12:00").unwrap(), -86400.0);
+        assert_eq!(parse_epoch("3600").unwrap(), 3600.0);
+        assert!(parse_epoch("2000-13-01").is_err());
+        assert!(parse_epoch("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_lookup_is_case_insensitive() {
+        assert_eq!(lookup("Earth").map(|(id, _)| id), Some(399));
+        assert!(lookup("Vulcan").is_none());
+    }
+}
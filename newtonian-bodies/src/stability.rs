@@ -0,0 +1,278 @@
+use super::body::{Tags, Vector};
+use super::dynamics::{Recording, Settings};
+use super::kepler::Elements;
+use super::simulation::Simulation;
+use super::Body;
+use rayon::prelude::*;
+use std::error::Error;
+use std::f64::consts::LN_10;
+use std::fmt;
+use std::str::FromStr;
+
+/// Initial separation of the shadow particle, relative to the distance and
+/// speed of the particle about the heaviest body.
+const SHIFT: f64 = 1e-9;
+
+/// The shadow is pulled back to the initial separation once the separation has
+/// grown or shrunk by this factor, before it leaves the linear regime.
+const RENORMALIZE_AT: f64 = 1e3;
+
+/// Chaos indicators of an orbit over the duration of the settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Indicators {
+    /// Mean exponential growth factor of nearby orbits, ⟨Y⟩: it tends to 2 for
+    /// quasi-periodic orbits and grows with time for chaotic ones.
+    pub megno: f64,
+    /// Fast Lyapunov indicator: the largest base-10 logarithm of the growth of
+    /// the separation from a nearby orbit, about log10(t) for regular orbits.
+    pub fli: f64,
+}
+
+impl Indicators {
+    /// Indicators of a particle lost to a collision or a numerical blow-up.
+    const UNDEFINED: Indicators = Indicators {
+        megno: f64::NAN,
+        fli: f64::NAN,
+    };
+}
+
+/// Orbital element of a test particle varied over a stability map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Element {
+    SemiMajorAxis,
+    Eccentricity,
+    Inclination,
+    AscendingNode,
+    ArgumentOfPeriapsis,
+    MeanAnomaly,
+}
+
+impl Element {
+    /// Sets the element to `value`, in meters for the semi-major axis and
+    /// degrees for the angles.
+    pub fn set(&self, elements: &mut Elements, value: f64) {
+        match self {
+            Element::SemiMajorAxis => elements.semi_major_axis = value,
+            Element::Eccentricity => elements.eccentricity = value,
+            Element::Inclination => elements.inclination = value.to_radians(),
+            Element::AscendingNode => elements.ascending_node = value.to_radians(),
+            Element::ArgumentOfPeriapsis => elements.argument_of_periapsis = value.to_radians(),
+            Element::MeanAnomaly => elements.mean_anomaly = value.to_radians(),
+        }
+    }
+}
+
+impl fmt::Display for Element {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Element::SemiMajorAxis => "a",
+            Element::Eccentricity => "e",
+            Element::Inclination => "i",
+            Element::AscendingNode => "node",
+            Element::ArgumentOfPeriapsis => "peri",
+            Element::MeanAnomaly => "mean_anomaly",
+        })
+    }
+}
+
+impl FromStr for Element {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "a" => Ok(Element::SemiMajorAxis),
+            "e" => Ok(Element::Eccentricity),
+            "i" => Ok(Element::Inclination),
+            "node" => Ok(Element::AscendingNode),
+            "peri" => Ok(Element::ArgumentOfPeriapsis),
+            "mean_anomaly" => Ok(Element::MeanAnomaly),
+            other => Err(format!(
+                "unknown orbital element '{}' (expected a, e, i, node, peri or mean_anomaly)",
+                other
+            )),
+        }
+    }
+}
+
+/// Massless particle named `name` on the elliptic orbit `elements` about
+/// `central`.
+pub fn particle(name: &str, central: &Body, elements: &Elements, gravity: f64) -> Result<Body, Box<dyn Error>> {
+    if !(elements.semi_major_axis > 0.0 && (0.0..1.0).contains(&elements.eccentricity)) {
+        return Err(format!(
+            "a test particle needs an elliptic orbit, got a = {} and e = {}",
+            elements.semi_major_axis, elements.eccentricity
+        )
+        .into());
+    }
+    let (position, velocity) = elements.state(gravity * central.mass);
+    Ok(Body {
+        name: name.to_string(),
+        mass: 0.0,
+        position: central.position + position,
+        velocity: central.velocity + velocity,
+        acceleration: Vector::null(),
+        tags: Tags::new(),
+        temperature: None,
+    })
+}
+
+/// MEGNO and FLI of `particle`, a massless body added to `bodies`, over the
+/// duration of the settings.
+///
+/// A shadow of the particle starts a small distance away in phase space, and
+/// the growth of their separation over each step stands for that of the
+/// tangent vector; the shadow is pulled back whenever the separation gets far
+/// from its initial size. Particles lost along the way get NaN indicators.
+pub fn indicators(bodies: &[Body], settings: &Settings, particle: &Body) -> Result<Indicators, Box<dyn Error>> {
+    let heaviest = bodies
+        .iter()
+        .max_by(|a, b| a.mass.total_cmp(&b.mass))
+        .ok_or("a stability map needs at least one body besides the particle")?;
+    // Phase-space distances mix positions and velocities through these scales.
+    let length = (particle.position - heaviest.position).norm().max(f64::MIN_POSITIVE);
+    let speed = (particle.velocity - heaviest.velocity).norm().max(f64::MIN_POSITIVE);
+    let separation = |a: &Body, b: &Body| {
+        ((b.position - a.position).norm_squared() / (length * length)
+            + (b.velocity - a.velocity).norm_squared() / (speed * speed))
+            .sqrt()
+    };
+
+    let mut shadow = Body {
+        name: format!("{} (shadow)", particle.name),
+        mass: 0.0,
+        ..particle.clone()
+    };
+    let direction = Vector::new(1.0, 1.0, 1.0) / 3f64.sqrt();
+    shadow.position += direction * (SHIFT * length);
+    shadow.velocity += direction * (SHIFT * speed);
+    let initial = separation(particle, &shadow);
+
+    let mut all = bodies.to_vec();
+    all.push(Body {
+        mass: 0.0,
+        ..particle.clone()
+    });
+    all.push(shadow);
+    let (particle, shadow) = (all.len() - 2, all.len() - 1);
+    let total = settings.total_time;
+    if total <= 0.0 {
+        return Err("the chaos indicators need a positive duration".into());
+    }
+    let settings = Settings {
+        recording: Recording::Count(1),
+        progress: false,
+        ..settings.clone()
+    };
+    let mut simulation = Simulation::builder().bodies(all).settings(settings.clone()).build()?;
+
+    let (mut previous, mut growth, mut fli) = (initial, 0.0, 0.0f64);
+    // ∫ (d ln δ/ds) s ds and ∫ Y ds of the MEGNO.
+    let (mut weighted, mut integral) = (0.0, 0.0);
+    let tolerance = settings.dt * 1e-6;
+    while simulation.time() < total - tolerance {
+        let start = simulation.time();
+        simulation.step(settings.dt.min(total - start))?;
+        let time = simulation.time();
+        let current = separation(&simulation.bodies()[particle], &simulation.bodies()[shadow]);
+        let rate = (current / previous).ln();
+        if !rate.is_finite() {
+            return Ok(Indicators::UNDEFINED);
+        }
+        growth += rate;
+        fli = fli.max(growth / LN_10);
+        weighted += rate * (start + time) / 2.0;
+        integral += 2.0 * weighted / time * (time - start);
+        previous = current;
+        if !(1.0 / RENORMALIZE_AT..RENORMALIZE_AT).contains(&(current / initial)) {
+            let bodies = simulation.bodies_mut();
+            let (nominal, offset) = (&bodies[particle], &bodies[shadow]);
+            let scale = initial / current;
+            let position = nominal.position + (offset.position - nominal.position) * scale;
+            let velocity = nominal.velocity + (offset.velocity - nominal.velocity) * scale;
+            bodies[shadow].position = position;
+            bodies[shadow].velocity = velocity;
+            previous = initial;
+        }
+    }
+    Ok(Indicators {
+        megno: integral / simulation.time(),
+        fli,
+    })
+}
+
+/// Indicators of each of `particles` added alone to `bodies`, computed in
+/// parallel on the threads of the rayon pool.
+pub fn scan(bodies: &[Body], settings: &Settings, particles: &[Body]) -> Result<Vec<Indicators>, Box<dyn Error>> {
+    let results: Result<Vec<Indicators>, String> = particles
+        .par_iter()
+        .map(|particle| indicators(bodies, settings, particle).map_err(|e| e.to_string()))
+        .collect();
+    Ok(results?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrator::Integrator;
+
+    fn star() -> Body {
+        Body {
+            name: "Star".to_string(),
+            mass: 1.0,
+            position: Vector::null(),
+            velocity: Vector::null(),
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    fn settings(total_time: f64) -> Settings {
+        Settings {
+            gravity: 1.0,
+            total_time,
+            dt: 1e-2,
+            integrator: Integrator::Rk4,
+            progress: false,
+            ..Settings::default()
+        }
+    }
+
+    fn orbit(semi_major_axis: f64, eccentricity: f64) -> Elements {
+        Elements {
+            semi_major_axis,
+            eccentricity,
+            inclination: 0.0,
+            ascending_node: 0.0,
+            argument_of_periapsis: 0.0,
+            mean_anomaly: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_kepler_orbits_are_regular() {
+        let particle = particle("Particle", &star(), &orbit(1.0, 0.2), 1.0).unwrap();
+        let indicators = indicators(&[star()], &settings(200.0), &particle).unwrap();
+
+        assert!((indicators.megno - 2.0).abs() < 0.2, "{:?}", indicators);
+        assert!(indicators.fli > 1.0 && indicators.fli < 4.0, "{:?}", indicators);
+    }
+
+    #[test]
+    fn test_orbits_crossing_a_planet_are_chaotic() {
+        let mut planet = particle("Planet", &star(), &orbit(1.0, 0.0), 1.0).unwrap();
+        planet.mass = 1e-3;
+        let bodies = [star(), planet];
+        let particles = [
+            particle("Regular", &star(), &orbit(0.5, 0.0), 1.0).unwrap(),
+            particle("Crossing", &star(), &orbit(1.1, 0.2), 1.0).unwrap(),
+        ];
+
+        let map = scan(&bodies, &settings(300.0), &particles).unwrap();
+
+        assert!(map[0].megno < 2.5, "{:?}", map);
+        assert!(map[1].megno > 4.0 && map[1].fli > map[0].fli + 1.0, "{:?}", map);
+        assert!(super::particle("Escaping", &star(), &orbit(1.0, 1.5), 1.0).is_err());
+        assert_eq!("mean_anomaly".parse::<Element>().unwrap().to_string(), "mean_anomaly");
+    }
+}
@@ -0,0 +1,346 @@
+use super::body::Vector;
+use super::dynamics::{simulate_with, Recording, SequentialWriter, Settings};
+use super::Body;
+use std::error::Error;
+use std::str::FromStr;
+
+/// Component of a body's initial state that differential correction may adjust.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    X,
+    Y,
+    Z,
+    Vx,
+    Vy,
+    Vz,
+}
+
+impl Component {
+    fn get(&self, body: &Body) -> f64 {
+        match self {
+            Component::X => body.position.x,
+            Component::Y => body.position.y,
+            Component::Z => body.position.z,
+            Component::Vx => body.velocity.x,
+            Component::Vy => body.velocity.y,
+            Component::Vz => body.velocity.z,
+        }
+    }
+
+    fn set(&self, body: &mut Body, value: f64) {
+        match self {
+            Component::X => body.position.x = value,
+            Component::Y => body.position.y = value,
+            Component::Z => body.position.z = value,
+            Component::Vx => body.velocity.x = value,
+            Component::Vy => body.velocity.y = value,
+            Component::Vz => body.velocity.z = value,
+        }
+    }
+}
+
+impl FromStr for Component {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "x" => Ok(Component::X),
+            "y" => Ok(Component::Y),
+            "z" => Ok(Component::Z),
+            "vx" => Ok(Component::Vx),
+            "vy" => Ok(Component::Vy),
+            "vz" => Ok(Component::Vz),
+            other => Err(format!("unknown component '{}' (expected x, y, z, vx, vy or vz)", other)),
+        }
+    }
+}
+
+/// What the corrected trajectory must achieve, for the body being adjusted.
+#[derive(Debug, Clone)]
+pub enum Goal {
+    /// Be at `position` at the end of the run, measured from body `relative_to`
+    /// (by index) or from the origin.
+    FinalPosition { position: Vector, relative_to: Option<usize> },
+    /// Pass body `target` (by index) at a closest distance of `distance`.
+    Flyby { target: usize, distance: f64 },
+}
+
+/// Stopping rules of the Newton iterations.
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// Largest acceptable miss, in meters.
+    pub tolerance: f64,
+    pub max_iterations: usize,
+}
+
+/// Outcome of a successful correction.
+#[derive(Debug, Clone)]
+pub struct Correction {
+    /// Initial conditions with the adjusted components.
+    pub bodies: Vec<Body>,
+    /// Miss distance before each iteration and after the last one.
+    pub misses: Vec<f64>,
+}
+
+/// Adjusts `components` of body `body` so the N-body trajectory reaches `goal`.
+///
+/// Each Newton (or, when the number of components differs from the goal's
+/// dimension, minimum-norm Gauss-Newton) iteration estimates the sensitivities
+/// by finite differences, costing one simulation per component.
+pub fn correct(
+    bodies: &[Body],
+    settings: &Settings,
+    body: usize,
+    components: &[Component],
+    goal: &Goal,
+    options: Options,
+) -> Result<Correction, Box<dyn Error>> {
+    if body >= bodies.len() {
+        return Err(format!("no body at index {}", body).into());
+    }
+    if components.is_empty() {
+        return Err("at least one component must be adjusted".into());
+    }
+    let settings = Settings {
+        // A flyby is judged on every step; a final position only on the last one.
+        recording: match goal {
+            Goal::Flyby { .. } => Recording::Interval(settings.dt),
+            Goal::FinalPosition { .. } => Recording::Interval(settings.total_time.max(settings.dt)),
+        },
+        progress: false,
+        ..settings.clone()
+    };
+
+    let mut bodies = bodies.to_vec();
+    let mut misses = Vec::new();
+    loop {
+        let residual = residual(&bodies, &settings, body, goal)?;
+        let miss = norm(&residual);
+        if !miss.is_finite() {
+            return Err("the trajectory is not finite (massless or colliding bodies?)".into());
+        }
+        misses.push(miss);
+        if miss <= options.tolerance {
+            return Ok(Correction { bodies, misses });
+        }
+        if misses.len() > options.max_iterations {
+            return Err(format!(
+                "no convergence after {} iterations (miss {:.6e} m, tolerance {:.6e} m)",
+                options.max_iterations, miss, options.tolerance
+            )
+            .into());
+        }
+
+        // Finite-difference Jacobian, one column per component.
+        let mut jacobian = vec![vec![0.0; components.len()]; residual.len()];
+        for (j, component) in components.iter().enumerate() {
+            let value = component.get(&bodies[body]);
+            let step = 1e-7 * value.abs().max(1.0);
+            let mut perturbed = bodies.clone();
+            component.set(&mut perturbed[body], value + step);
+            let shifted = self::residual(&perturbed, &settings, body, goal)?;
+            for (row, (shifted, base)) in jacobian.iter_mut().zip(shifted.iter().zip(&residual)) {
+                row[j] = (shifted - base) / step;
+            }
+        }
+
+        let update = least_squares(&jacobian, &residual)
+            .ok_or("the goal does not depend on the adjusted components")?;
+        for (component, delta) in components.iter().zip(update) {
+            let value = component.get(&bodies[body]);
+            component.set(&mut bodies[body], value - delta);
+        }
+    }
+}
+
+/// Difference between the achieved and the wanted outcome.
+fn residual(bodies: &[Body], settings: &Settings, body: usize, goal: &Goal) -> Result<Vec<f64>, Box<dyn Error>> {
+    let mut tracker = Tracker {
+        body,
+        goal,
+        closest: f64::INFINITY,
+        last: Vector::null(),
+    };
+    simulate_with(&mut bodies.to_vec(), settings, &mut tracker)?;
+    Ok(match goal {
+        Goal::FinalPosition { position, .. } => vec![
+            tracker.last.x - position.x,
+            tracker.last.y - position.y,
+            tracker.last.z - position.z,
+        ],
+        Goal::Flyby { distance, .. } => vec![tracker.closest - distance],
+    })
+}
+
+/// Writer keeping only what the goal is judged on.
+struct Tracker<'a> {
+    body: usize,
+    goal: &'a Goal,
+    closest: f64,
+    last: Vector,
+}
+
+impl SequentialWriter for Tracker<'_> {
+    fn add(&mut self, _time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        let position = &bodies[self.body].position;
+        match *self.goal {
+            Goal::FinalPosition { relative_to, .. } => {
+                self.last = match relative_to {
+                    Some(origin) => difference(position, &bodies[origin].position),
+                    None => *position,
+                };
+            }
+            Goal::Flyby { target, .. } => {
+                let distance = norm(&[
+                    position.x - bodies[target].position.x,
+                    position.y - bodies[target].position.y,
+                    position.z - bodies[target].position.z,
+                ]);
+                self.closest = self.closest.min(distance);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Solves `jacobian · x = residual` in the least-squares sense, taking the
+/// minimum-norm solution when there are more unknowns than equations.
+fn least_squares(jacobian: &[Vec<f64>], residual: &[f64]) -> Option<Vec<f64>> {
+    let (rows, columns) = (jacobian.len(), jacobian[0].len());
+    let entry = |i: usize, j: usize| jacobian[i][j];
+    if columns <= rows {
+        // (JᵀJ) x = Jᵀr
+        let normal: Vec<Vec<f64>> = (0..columns)
+            .map(|a| (0..columns).map(|b| (0..rows).map(|i| entry(i, a) * entry(i, b)).sum()).collect())
+            .collect();
+        let rhs: Vec<f64> = (0..columns).map(|a| (0..rows).map(|i| entry(i, a) * residual[i]).sum()).collect();
+        solve(normal, rhs)
+    } else {
+        // x = Jᵀ y with (JJᵀ) y = r
+        let normal: Vec<Vec<f64>> = (0..rows)
+            .map(|a| (0..rows).map(|b| (0..columns).map(|j| entry(a, j) * entry(b, j)).sum()).collect())
+            .collect();
+        let y = solve(normal, residual.to_vec())?;
+        Some((0..columns).map(|j| (0..rows).map(|i| entry(i, j) * y[i]).sum()).collect())
+    }
+}
+
+/// Gaussian elimination with partial pivoting; `None` for singular systems.
+pub(crate) fn solve(mut matrix: Vec<Vec<f64>>, mut rhs: Vec<f64>) -> Option<Vec<f64>> {
+    let n = rhs.len();
+    let scale = matrix.iter().flatten().fold(0.0f64, |max, v| max.max(v.abs()));
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&a, &b| matrix[a][col].abs().total_cmp(&matrix[b][col].abs()))?;
+        if matrix[pivot][col].abs() <= 1e-14 * scale || scale == 0.0 {
+            return None;
+        }
+        matrix.swap(col, pivot);
+        rhs.swap(col, pivot);
+        let pivot_row = matrix[col].clone();
+        for row in col + 1..n {
+            let factor = matrix[row][col] / pivot_row[col];
+            for (value, pivot) in matrix[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *value -= factor * pivot;
+            }
+            rhs[row] -= factor * rhs[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| matrix[row][k] * x[k]).sum();
+        x[row] = (rhs[row] - sum) / matrix[row][row];
+    }
+    Some(x)
+}
+
+fn difference(a: &Vector, b: &Vector) -> Vector {
+    Vector {
+        x: a.x - b.x,
+        y: a.y - b.y,
+        z: a.z - b.z,
+    }
+}
+
+fn norm(values: &[f64]) -> f64 {
+    values.iter().map(|v| v * v).sum::<f64>().sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Tags;
+    use crate::integrator::Integrator;
+
+    fn body(name: &str, mass: f64, x: f64, vy: f64) -> Body {
+        Body {
+            name: name.to_string(),
+            mass,
+            position: Vector { x, y: 0.0, z: 0.0 },
+            velocity: Vector { x: 0.0, y: vy, z: 0.0 },
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            gravity: 1.0,
+            total_time: 2.0,
+            dt: 0.01,
+            integrator: Integrator::Rk4,
+            progress: false,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn test_hits_a_final_position() {
+        let bodies = vec![body("Sun", 1.0, 0.0, 0.0), body("Probe", 1e-12, 1.0, 1.0)];
+        let goal = Goal::FinalPosition {
+            position: Vector { x: 0.0, y: 1.5, z: 0.0 },
+            relative_to: Some(0),
+        };
+        let options = Options {
+            tolerance: 1e-9,
+            max_iterations: 20,
+        };
+
+        let correction = correct(&bodies, &settings(), 1, &[Component::Vx, Component::Vy], &goal, options).unwrap();
+
+        assert!(correction.misses.len() > 1);
+        assert!(*correction.misses.last().unwrap() <= 1e-9);
+        // Only the velocity moved.
+        assert_eq!(correction.bodies[1].position.x, 1.0);
+        assert_ne!(correction.bodies[1].velocity.x, 0.0);
+    }
+
+    #[test]
+    fn test_hits_a_flyby_distance() {
+        // The probe crosses in front of the planet, missing it by about 1.
+        let mut probe = body("Probe", 1e-12, -5.0, 0.0);
+        probe.position.y = 1.0;
+        probe.velocity.x = 1.0;
+        let bodies = vec![body("Planet", 1.0, 0.0, 0.0), probe];
+        let goal = Goal::Flyby { target: 0, distance: 0.5 };
+        let options = Options {
+            tolerance: 1e-6,
+            max_iterations: 20,
+        };
+        let settings = Settings {
+            total_time: 10.0,
+            ..settings()
+        };
+
+        let correction = correct(&bodies, &settings, 1, &[Component::Vy], &goal, options).unwrap();
+
+        assert!(*correction.misses.last().unwrap() <= 1e-6);
+        assert_ne!(correction.bodies[1].velocity.y, 0.0);
+    }
+
+    #[test]
+    fn test_components_parse() {
+        assert_eq!("VX".parse::<Component>().unwrap(), Component::Vx);
+        assert!("w".parse::<Component>().is_err());
+    }
+}
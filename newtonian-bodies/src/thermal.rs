@@ -0,0 +1,177 @@
+use super::body::Vector;
+use super::forces::PeriodicBox;
+use super::Body;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::f64::consts::PI;
+
+/// Boltzmann constant in J/K.
+pub const BOLTZMANN: f64 = 1.380649e-23;
+/// Stefan-Boltzmann constant in W/(m² K⁴).
+pub const STEFAN_BOLTZMANN: f64 = 5.670374419e-8;
+/// Mass of a hydrogen atom in kg.
+pub const HYDROGEN_MASS: f64 = 1.6735575e-27;
+
+/// Evolves the temperature of the bodies that start with one.
+///
+/// Every such body relaxes toward the equilibrium temperature of a black body
+/// lit by the luminous bodies. SPH gas particles besides warm up when
+/// compressed and cool down when expanding, as an ideal gas of index `gamma`,
+/// and are heated by the artificial viscosity; their temperature then sets
+/// their pressure instead of the isothermal sound speed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thermal {
+    /// Adiabatic index of the gas.
+    pub gamma: f64,
+    /// Mean molecular weight of the gas, in hydrogen masses.
+    pub molecular_weight: f64,
+    /// Seconds over which temperatures approach the equilibrium under the
+    /// light of the luminous bodies; infinite leaves out the irradiation.
+    pub relaxation_time: f64,
+    /// Luminosity in watts of each luminous body, by name.
+    pub luminosities: BTreeMap<String, f64>,
+}
+
+impl Thermal {
+    /// Monatomic gas (γ = 5/3) of molecular hydrogen and helium (μ = 2.34),
+    /// without luminous bodies.
+    pub fn new(relaxation_time: f64) -> Self {
+        Thermal {
+            gamma: 5.0 / 3.0,
+            molecular_weight: 2.34,
+            relaxation_time,
+            luminosities: BTreeMap::new(),
+        }
+    }
+
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        if !(self.gamma.is_finite() && self.gamma > 1.0) {
+            return Err(format!("adiabatic index must be greater than 1, got {}", self.gamma).into());
+        }
+        if !(self.molecular_weight.is_finite() && self.molecular_weight > 0.0) {
+            return Err(format!("mean molecular weight must be positive, got {}", self.molecular_weight).into());
+        }
+        if self.relaxation_time.is_nan() || self.relaxation_time <= 0.0 {
+            return Err(format!("thermal relaxation time must be positive, got {}", self.relaxation_time).into());
+        }
+        if let Some((name, luminosity)) = self.luminosities.iter().find(|(_, l)| !(l.is_finite() && **l >= 0.0)) {
+            return Err(format!("luminosity of '{}' must not be negative, got {}", name, luminosity).into());
+        }
+        Ok(())
+    }
+
+    /// Pressure over density of gas at `temperature`, k T / (μ m_H): the square
+    /// of its isothermal sound speed.
+    pub fn pressure_per_density(&self, temperature: f64) -> f64 {
+        BOLTZMANN * temperature / (self.molecular_weight * HYDROGEN_MASS)
+    }
+
+    /// Temperature change of gas gaining `energy` J/kg of internal energy.
+    pub fn temperature_change(&self, energy: f64) -> f64 {
+        (self.gamma - 1.0) * self.molecular_weight * HYDROGEN_MASS * energy / BOLTZMANN
+    }
+
+    /// Temperature of a fast-rotating black body at `position` in the light of
+    /// the luminous `bodies`, other than the one named `name`.
+    pub fn equilibrium_temperature(
+        &self,
+        bodies: &[Body],
+        name: &str,
+        position: Vector,
+        periodic: Option<&PeriodicBox>,
+    ) -> f64 {
+        let flux: f64 = bodies
+            .iter()
+            .filter(|body| body.name != name)
+            .filter_map(|body| {
+                let luminosity = self.luminosities.get(&body.name)?;
+                let d = position - body.position;
+                let d = periodic.map_or(d, |periodic| periodic.separation(d));
+                Some(luminosity / (4.0 * PI * d.norm_squared()))
+            })
+            .sum();
+        (flux / (4.0 * STEFAN_BOLTZMANN)).powf(0.25)
+    }
+
+    /// Advances the temperatures over a step of `dt` seconds, given the heating
+    /// of each body in W/kg.
+    ///
+    /// The heating is applied first, then the relaxation toward the
+    /// equilibrium, which is exact for a constant equilibrium temperature.
+    pub(crate) fn heat(&self, bodies: &mut [Body], heating: &[f64], periodic: Option<&PeriodicBox>, dt: f64) {
+        let lit = !self.relaxation_time.is_infinite();
+        let luminous: Vec<Body> = bodies
+            .iter()
+            .filter(|body| lit && self.luminosities.contains_key(&body.name))
+            .cloned()
+            .collect();
+        let decay = (-dt / self.relaxation_time).exp();
+        for (body, heating) in bodies.iter_mut().zip(heating) {
+            let Some(temperature) = body.temperature else {
+                continue;
+            };
+            let mut temperature = (temperature + self.temperature_change(heating * dt)).max(0.0);
+            if lit {
+                let equilibrium = self.equilibrium_temperature(&luminous, &body.name, body.position, periodic);
+                temperature = equilibrium + (temperature - equilibrium) * decay;
+            }
+            body.temperature = Some(temperature);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Tags;
+
+    fn body(name: &str, x: f64, temperature: Option<f64>) -> Body {
+        Body {
+            name: name.to_string(),
+            mass: 1.0,
+            position: Vector::new(x, 0.0, 0.0),
+            velocity: Vector::null(),
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature,
+        }
+    }
+
+    #[test]
+    fn test_earth_equilibrium_temperature() {
+        let thermal = Thermal {
+            luminosities: BTreeMap::from([("Sun".to_string(), 3.828e26)]),
+            ..Thermal::new(1.0)
+        };
+        let bodies = [body("Sun", 0.0, None)];
+        let earth = thermal.equilibrium_temperature(&bodies, "Earth", Vector::new(1.496e11, 0.0, 0.0), None);
+        // Without albedo, about 278 K.
+        assert!((earth - 278.3).abs() < 0.5, "{}", earth);
+        assert_eq!(thermal.equilibrium_temperature(&bodies, "Sun", Vector::null(), None), 0.0);
+    }
+
+    #[test]
+    fn test_relaxes_toward_equilibrium() {
+        let thermal = Thermal {
+            luminosities: BTreeMap::from([("Sun".to_string(), 3.828e26)]),
+            ..Thermal::new(10.0)
+        };
+        let mut bodies = vec![body("Sun", 0.0, None), body("Earth", 1.496e11, Some(1000.0)), body("Rock", 1.0, None)];
+        let equilibrium = thermal.equilibrium_temperature(&bodies, "Earth", bodies[1].position, None);
+        for _ in 0..100 {
+            thermal.heat(&mut bodies, &[0.0; 3], None, 1.0);
+        }
+        let earth = bodies[1].temperature.unwrap();
+        assert!((earth - equilibrium).abs() < 1e-3 * (1000.0 - equilibrium), "{}", earth);
+        assert_eq!(bodies[0].temperature, None);
+        assert_eq!(bodies[2].temperature, None);
+
+        // Heating alone, without irradiation: 1 J/kg warms a monatomic gas by
+        // (γ - 1) μ m_H / k.
+        let mut gas = vec![body("Gas", 0.0, Some(10.0))];
+        Thermal::new(f64::INFINITY).heat(&mut gas, &[2.0], None, 0.5);
+        let expected = 10.0 + Thermal::new(1.0).temperature_change(1.0);
+        assert!((gas[0].temperature.unwrap() - expected).abs() < 1e-12);
+        assert!(Thermal { gamma: 1.0, ..Thermal::new(1.0) }.check().is_err());
+    }
+}
@@ -0,0 +1,69 @@
+use super::Body;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Writes the bodies as a standard (big-endian) TIPSY binary.
+///
+/// Every body is a dark matter particle without softening, in the order of
+/// `bodies`. Values are written in SI units (m, m/s, kg) as single precision.
+pub fn write(path: &Path, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_to(&mut writer, time, bodies)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_to(mut writer: impl Write, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+    let count = i32::try_from(bodies.len())?;
+
+    // Header: time, then the numbers of bodies, dimensions, gas, dark and star
+    // particles, padded to 32 bytes.
+    writer.write_all(&time.to_be_bytes())?;
+    for value in [count, 3, 0, count, 0, 0] {
+        writer.write_all(&value.to_be_bytes())?;
+    }
+
+    for body in bodies {
+        let values = [
+            body.mass,
+            body.position.x,
+            body.position.y,
+            body.position.z,
+            body.velocity.x,
+            body.velocity.y,
+            body.velocity.z,
+            0.0, // softening
+            0.0, // potential
+        ];
+        for value in values {
+            writer.write_all(&(value as f32).to_be_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Vector;
+
+    #[test]
+    fn test_snapshot_layout() {
+        let bodies = vec![
+            Body::new("Sun", 2.0, Vector::new(1.0, 2.0, 3.0), Vector::new(4.0, 5.0, 6.0));
+            3
+        ];
+        let mut bytes = Vec::new();
+        write_to(&mut bytes, 0.25, &bodies).unwrap();
+
+        assert_eq!(bytes.len(), 32 + 3 * 36);
+        assert_eq!(f64::from_be_bytes(bytes[0..8].try_into().unwrap()), 0.25);
+        assert_eq!(i32::from_be_bytes(bytes[8..12].try_into().unwrap()), 3);
+        assert_eq!(i32::from_be_bytes(bytes[20..24].try_into().unwrap()), 3);
+        let f32_at = |offset: usize| f32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        assert_eq!(f32_at(32), 2.0);
+        assert_eq!(f32_at(32 + 16), 4.0);
+    }
+}
@@ -0,0 +1,307 @@
+use super::dynamics::{simulate_with, Recording, SequentialWriter, Settings};
+use super::simulation::Simulation;
+use super::Body;
+use rayon::prelude::*;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Finite-difference steps, relative to the distance of the body from the
+/// origin and to its speed.
+pub const RELATIVE_STEP: f64 = 1e-6;
+
+/// State transition matrix of a body at one recorded time.
+#[derive(Debug, Clone)]
+pub struct Transition {
+    pub time: f64,
+    pub body: usize,
+    /// Derivatives of (x, y, z, vx, vy, vz) at `time` with respect to their
+    /// initial values: `matrix[i][j]` is ∂stateᵢ(t)/∂stateⱼ(0).
+    pub matrix: [[f64; 6]; 6],
+}
+
+/// Computes the state transition matrices of `selected` bodies at every
+/// recorded time by central finite differences: each component of each
+/// body's initial state is shifted both ways and simulated with the full
+/// N-body dynamics.
+///
+/// This costs 12 simulations per body, run in parallel on the threads of the
+/// rayon pool. Unlike variational
+/// equations, it works with every force model and integrator.
+pub fn propagate(bodies: &[Body], settings: &Settings, selected: &[usize]) -> Result<Vec<Transition>, Box<dyn Error>> {
+    let settings = settings.for_copies()?;
+    shifted_runs(bodies, selected, |mut shifted, body| {
+        let mut tracker = Tracker {
+            body,
+            frames: Vec::new(),
+        };
+        simulate_with(&mut shifted, &settings, &mut tracker)?;
+        Ok(tracker.frames)
+    })
+}
+
+/// Like [`propagate`] for a single body, at each of `times` (in seconds since
+/// the initial state, in increasing order) instead of the recorded times.
+pub fn at_times(
+    bodies: &[Body],
+    settings: &Settings,
+    body: usize,
+    times: &[f64],
+) -> Result<Vec<[[f64; 6]; 6]>, Box<dyn Error>> {
+    let settings = Settings {
+        total_time: times.last().copied().unwrap_or(0.0),
+        recording: Recording::Count(1),
+        ..settings.for_copies()?
+    };
+    let transitions = shifted_runs(bodies, &[body], |shifted, body| {
+        let mut simulation = Simulation::builder().bodies(shifted).settings(settings.clone()).build()?;
+        times
+            .iter()
+            .map(|&time| {
+                simulation.advance_to(time)?;
+                Ok((time, state(&simulation.bodies()[body])))
+            })
+            .collect()
+    })?;
+    Ok(transitions.into_iter().map(|transition| transition.matrix).collect())
+}
+
+/// Runs `simulate` on the bodies with each component of each selected body
+/// shifted both ways, in parallel, and differentiates the states it returns
+/// for that body.
+fn shifted_runs<F>(bodies: &[Body], selected: &[usize], simulate: F) -> Result<Vec<Transition>, Box<dyn Error>>
+where
+    F: Fn(Vec<Body>, usize) -> Result<Vec<(f64, [f64; 6])>, Box<dyn Error>> + Sync,
+{
+    if let Some(body) = selected.iter().find(|&&body| body >= bodies.len()) {
+        return Err(format!("no body at index {}", body).into());
+    }
+    // Run 12k + 2j shifts component j of body k up, the next one down.
+    let steps: Vec<[f64; 6]> = selected.iter().map(|&body| steps(&bodies[body])).collect();
+    let runs = (0..12 * selected.len())
+        .into_par_iter()
+        .map(|run| {
+            let (k, j, sign) = (run / 12, run % 12 / 2, if run.is_multiple_of(2) { 1.0 } else { -1.0 });
+            let body = selected[k];
+            let mut shifted = bodies.to_vec();
+            let mut state = state(&shifted[body]);
+            state[j] += sign * steps[k][j];
+            set_state(&mut shifted[body], &state);
+            simulate(shifted, body).map_err(|e| e.to_string())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut transitions = Vec::new();
+    for (frame, (time, _)) in runs.first().into_iter().flatten().enumerate() {
+        for (k, &body) in selected.iter().enumerate() {
+            let mut matrix = [[0.0; 6]; 6];
+            for (j, step) in steps[k].iter().enumerate() {
+                let (up, down) = (&runs[12 * k + 2 * j][frame].1, &runs[12 * k + 2 * j + 1][frame].1);
+                for (i, row) in matrix.iter_mut().enumerate() {
+                    row[j] = (up[i] - down[i]) / (2.0 * step);
+                }
+            }
+            transitions.push(Transition {
+                time: *time,
+                body,
+                matrix,
+            });
+        }
+    }
+    Ok(transitions)
+}
+
+/// Writes transition matrices as CSV, one row per body and recorded time,
+/// with the matrix by rows in `phi_11` to `phi_66`.
+pub fn write_csv(path: &Path, transitions: &[Transition], bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "time,name")?;
+    for i in 1..=6 {
+        for j in 1..=6 {
+            write!(writer, ",phi_{}{}", i, j)?;
+        }
+    }
+    writeln!(writer)?;
+    for t in transitions {
+        write!(writer, "{},\"{}\"", t.time, bodies[t.body].name.replace('"', "\"\""))?;
+        for value in t.matrix.iter().flatten() {
+            write!(writer, ",{}", value)?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Shifts of the position and velocity components of `body`.
+fn steps(body: &Body) -> [f64; 6] {
+    let position = RELATIVE_STEP * body.position.norm().max(1.0);
+    let velocity = RELATIVE_STEP * body.velocity.norm().max(1e-3);
+    [position, position, position, velocity, velocity, velocity]
+}
+
+/// Position and velocity components of `body`.
+pub(crate) fn state(body: &Body) -> [f64; 6] {
+    let (p, v) = (&body.position, &body.velocity);
+    [p.x, p.y, p.z, v.x, v.y, v.z]
+}
+
+pub(crate) fn set_state(body: &mut Body, state: &[f64; 6]) {
+    body.position.x = state[0];
+    body.position.y = state[1];
+    body.position.z = state[2];
+    body.velocity.x = state[3];
+    body.velocity.y = state[4];
+    body.velocity.z = state[5];
+}
+
+/// Writer keeping the state of one body at every recorded time.
+struct Tracker {
+    body: usize,
+    frames: Vec<(f64, [f64; 6])>,
+}
+
+impl SequentialWriter for Tracker {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        self.frames.push((time, state(&bodies[self.body])));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Vector;
+    use crate::dynamics::Recording;
+    use crate::integrator::Integrator;
+    use crate::simulation::Discard;
+
+    #[test]
+    fn test_shifted_runs_leave_the_console_alone() {
+        use crate::console::Console;
+        use std::sync::{mpsc, Arc};
+        use std::time::Duration;
+
+        // Nothing resumes this console, so a shifted run serving it would wait forever.
+        let console = Arc::new(Console::local(true));
+        let settings = Settings {
+            total_time: 10.0,
+            dt: 1.0,
+            recording: Recording::Count(2),
+            progress: false,
+            console: Some(Arc::clone(&console)),
+            ..Settings::default()
+        };
+        let rock = Body::new("Rock", 1.0, Vector::null(), Vector::new(1.0, 0.0, 0.0));
+        let (done, finished) = mpsc::channel();
+        std::thread::spawn(move || {
+            let transitions = propagate(&[rock], &settings, &[0]).map(|t| t.len()).map_err(|e| e.to_string());
+            done.send(transitions)
+        });
+
+        let transitions = finished.recv_timeout(Duration::from_secs(60)).expect("shifted runs waited for the console");
+        assert_eq!(transitions, Ok(2));
+        assert!(console.interventions().is_empty());
+    }
+
+    #[cfg(feature = "rhai")]
+    #[test]
+    fn test_step_handlers_are_not_run_again_in_shifted_runs() {
+        use crate::script::Script;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (events, push) = (temp_dir.path().join("events.rhai"), temp_dir.path().join("push.rhai"));
+        std::fs::write(&events, "fn on_step(time) { print(time); }").unwrap();
+        std::fs::write(&push, "fn acceleration(body, time) { [1.0, 0, 0] }").unwrap();
+        let settings = Settings {
+            total_time: 2.0,
+            dt: 1.0,
+            recording: Recording::Count(2),
+            progress: false,
+            script: Some(Script::load(&events).unwrap()),
+            ..Settings::default()
+        };
+        let rock = Body::new("Rock", 1.0, Vector::null(), Vector::null());
+
+        let error = propagate(std::slice::from_ref(&rock), &settings, &[0]).unwrap_err().to_string();
+        assert!(error.contains("on_step"), "{}", error);
+        // Forces alone are followed like any other.
+        let pushed = Settings {
+            script: Some(Script::load(&push).unwrap()),
+            ..settings
+        };
+        assert_eq!(propagate(&[rock], &pushed, &[0]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_free_motion_shifts_positions_by_the_elapsed_time() {
+        let settings = Settings {
+            total_time: 10.0,
+            dt: 0.5,
+            recording: Recording::Interval(5.0),
+            progress: false,
+            ..Settings::default()
+        };
+        let rock = Body::new("Rock", 1.0, Vector::null(), Vector::new(1.0, 0.0, 0.0));
+
+        let transitions = propagate(std::slice::from_ref(&rock), &settings, &[0]).unwrap();
+
+        assert_eq!(transitions.len(), 3);
+        let last = &transitions[2];
+        assert_eq!(last.time, 10.0);
+        for i in 0..6 {
+            for j in 0..6 {
+                let expected = if i == j {
+                    1.0
+                } else if j == i + 3 {
+                    10.0
+                } else {
+                    0.0
+                };
+                assert!((last.matrix[i][j] - expected).abs() < 1e-6, "{:?}", last.matrix);
+            }
+        }
+        let matrices = at_times(std::slice::from_ref(&rock), &settings, 0, &[0.0, 2.5]).unwrap();
+        assert!((matrices[0][0][3]).abs() < 1e-6 && (matrices[1][0][3] - 2.5).abs() < 1e-6);
+        assert!(propagate(&[Body::new("Rock", 1.0, Vector::null(), Vector::null())], &settings, &[1]).is_err());
+    }
+
+    #[test]
+    fn test_matrix_predicts_a_nearby_orbit() {
+        let (gravity, mass, radius) = (1.0, 1.0, 1.0);
+        let bodies = vec![
+            Body::new("Star", mass, Vector::null(), Vector::null()),
+            Body::new("Planet", 1e-9, Vector::new(radius, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0)),
+        ];
+        let settings = Settings {
+            gravity,
+            total_time: 3.0,
+            dt: 1e-3,
+            integrator: Integrator::Rk4,
+            recording: Recording::Count(2),
+            progress: false,
+            ..Settings::default()
+        };
+        let transitions = propagate(&bodies, &settings, &[1]).unwrap();
+        let matrix = transitions[1].matrix;
+
+        let end = |shift: [f64; 6]| {
+            let mut bodies = bodies.clone();
+            let mut initial = state(&bodies[1]);
+            for (value, shift) in initial.iter_mut().zip(shift) {
+                *value += shift;
+            }
+            set_state(&mut bodies[1], &initial);
+            simulate_with(&mut bodies, &settings, &mut Discard).unwrap();
+            state(&bodies[1])
+        };
+        let shift = [1e-7, -2e-7, 1e-7, 3e-7, 1e-7, -1e-7];
+        let (nominal, shifted) = (end([0.0; 6]), end(shift));
+        // Up to the second-order terms, about the square of the shift.
+        for i in 0..6 {
+            let predicted: f64 = (0..6).map(|j| matrix[i][j] * shift[j]).sum();
+            assert!((shifted[i] - nominal[i] - predicted).abs() < 1e-11, "{} {}", shifted[i] - nominal[i], predicted);
+        }
+    }
+}
@@ -0,0 +1,495 @@
+use super::body::Vector;
+use super::Body;
+use rayon::prelude::*;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Bits of each coordinate in the Morton keys, and so the depth of the tree:
+/// bodies closer than the bounding cube over 2²¹ share a leaf.
+const KEY_BITS: u32 = 21;
+
+/// Cells with at least this many bodies build their octants on several threads.
+const PARALLEL_BODIES: usize = 4096;
+
+/// Terms of the expansion standing in for the bodies of a distant cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MultipoleOrder {
+    /// The total mass at the center of mass.
+    Monopole,
+    /// The monopole plus the quadrupole moment about the center of mass (the
+    /// dipole vanishes there), for errors an order smaller at the same
+    /// opening angle.
+    #[default]
+    Quadrupole,
+}
+
+impl fmt::Display for MultipoleOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipoleOrder::Monopole => write!(f, "monopole"),
+            MultipoleOrder::Quadrupole => write!(f, "quadrupole"),
+        }
+    }
+}
+
+impl FromStr for MultipoleOrder {
+    type Err = String;
+
+    /// Parses the name of the expansion or its order, 0 or 2.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "monopole" | "0" => Ok(MultipoleOrder::Monopole),
+            "quadrupole" | "2" => Ok(MultipoleOrder::Quadrupole),
+            other => Err(format!("unknown multipole order '{}' (expected monopole (0) or quadrupole (2))", other)),
+        }
+    }
+}
+
+/// Barnes-Hut tree code: bodies are sorted into an octree, and cells seen from
+/// a body under less than `opening_angle` (cell side over distance to its center
+/// of mass) pull as a multipole expansion instead of body by body, in
+/// O(N log N) instead of O(N²). An opening angle of zero gives the direct sum.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BarnesHut {
+    pub opening_angle: f64,
+    pub order: MultipoleOrder,
+}
+
+impl BarnesHut {
+    /// Quadrupole expansions of the cells opened under `opening_angle`.
+    pub fn new(opening_angle: f64) -> Self {
+        BarnesHut {
+            opening_angle,
+            order: MultipoleOrder::default(),
+        }
+    }
+
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        if !(self.opening_angle.is_finite() && self.opening_angle >= 0.0) {
+            return Err(format!("opening angle must not be negative, got {}", self.opening_angle).into());
+        }
+        Ok(())
+    }
+
+    /// Sets the acceleration of every body to the pull of the others.
+    pub(crate) fn accelerate(&self, bodies: &mut [Body], gravity: f64) {
+        self.accelerate_timed(bodies, gravity);
+    }
+
+    /// Like `accelerate`, returning the time spent in each phase. Every phase
+    /// runs on the threads of the rayon pool.
+    pub fn accelerate_timed(&self, bodies: &mut [Body], gravity: f64) -> TreeTimings {
+        let start = Instant::now();
+        let sorted = Sorted::new(bodies);
+        let sort = start.elapsed();
+
+        let start = Instant::now();
+        let tree = Octree::new(bodies, sorted);
+        let build = start.elapsed();
+
+        let start = Instant::now();
+        let accelerations: Vec<Vector> = (0..bodies.len())
+            .into_par_iter()
+            .map(|i| self.pull(&tree, bodies, i, gravity))
+            .collect();
+        for (body, acceleration) in bodies.iter_mut().zip(accelerations) {
+            body.acceleration = acceleration;
+        }
+        TreeTimings {
+            sort,
+            build,
+            traverse: start.elapsed(),
+        }
+    }
+
+    fn pull(&self, tree: &Octree, bodies: &[Body], i: usize, gravity: f64) -> Vector {
+        let position = bodies[i].position;
+        let rank = tree.sorted.rank[i];
+        let mut acceleration = Vector::null();
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &tree.nodes[index];
+            match &node.kind {
+                Kind::Leaf => {
+                    for &j in tree.sorted.order[node.start..node.end].iter().filter(|&&j| j != i) {
+                        let d = bodies[j].position - position;
+                        let r = d.norm();
+                        // Bodies at the same position, only possible in the
+                        // deepest cells, don't pull on each other.
+                        if r == 0.0 {
+                            continue;
+                        }
+                        acceleration += gravity * bodies[j].mass / (r * r * r) * d;
+                    }
+                }
+                Kind::Branch(children) => {
+                    let r = position - node.center_of_mass;
+                    let distance = r.norm();
+                    let inside = (node.start..node.end).contains(&rank);
+                    if !inside && 2.0 * node.half_size < self.opening_angle * distance {
+                        acceleration += node.pull(r, distance, gravity, self.order);
+                    } else {
+                        stack.extend(children.iter().flatten());
+                    }
+                }
+            }
+        }
+        acceleration
+    }
+}
+
+/// Time spent in each phase of a tree force evaluation.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TreeTimings {
+    /// Sorting the bodies along the Morton curve.
+    pub sort: Duration,
+    /// Building the cells and their multipoles.
+    pub build: Duration,
+    /// Walking the tree for the acceleration of every body.
+    pub traverse: Duration,
+}
+
+impl TreeTimings {
+    pub fn total(&self) -> Duration {
+        self.sort + self.build + self.traverse
+    }
+}
+
+/// Indices of the bodies in Morton (Z-order) within their bounding cube, so
+/// bodies close in space come close in the list.
+pub fn morton_order(bodies: &[Body]) -> Vec<usize> {
+    Sorted::new(bodies).order
+}
+
+/// Bodies in Morton (Z-order) within their bounding cube, so the bodies of
+/// every cell of the octree are contiguous.
+struct Sorted {
+    /// Corner of the bounding cube with the lowest coordinates.
+    low: Vector,
+    size: f64,
+    /// Morton key of each body, by rank.
+    keys: Vec<u64>,
+    /// Index of the body of each rank.
+    order: Vec<usize>,
+    /// Rank of each body.
+    rank: Vec<usize>,
+}
+
+impl Sorted {
+    fn new(bodies: &[Body]) -> Self {
+        let mut low = Vector::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut high = -low;
+        for body in bodies {
+            let p = body.position;
+            low = Vector::new(low.x.min(p.x), low.y.min(p.y), low.z.min(p.z));
+            high = Vector::new(high.x.max(p.x), high.y.max(p.y), high.z.max(p.z));
+        }
+        let extent = (high - low).to_array().into_iter().fold(0.0, f64::max);
+        // Slightly larger, so bodies on the far faces are inside.
+        let size = if extent > 0.0 { extent * (1.0 + 1e-9) } else { 1.0 };
+
+        let cells = (1u64 << KEY_BITS) as f64;
+        let mut keyed: Vec<(u64, usize)> = bodies
+            .par_iter()
+            .enumerate()
+            .map(|(i, body)| {
+                let cell = ((body.position - low) / size * cells).to_array().map(|x| (x as u64).min((1 << KEY_BITS) - 1));
+                (morton_key(cell), i)
+            })
+            .collect();
+        keyed.par_sort_unstable();
+
+        let mut rank = vec![0; bodies.len()];
+        for (r, &(_, i)) in keyed.iter().enumerate() {
+            rank[i] = r;
+        }
+        let (keys, order) = keyed.into_iter().unzip();
+        Sorted {
+            low,
+            size,
+            keys,
+            order,
+            rank,
+        }
+    }
+}
+
+/// Interleaves the bits of the cell coordinates, x lowest.
+fn morton_key(cell: [u64; 3]) -> u64 {
+    let mut key = 0;
+    for bit in 0..KEY_BITS {
+        for (axis, coordinate) in cell.iter().enumerate() {
+            key |= ((coordinate >> bit) & 1) << (3 * bit + axis as u32);
+        }
+    }
+    key
+}
+
+/// Cells of the octree, the root first.
+struct Octree {
+    nodes: Vec<Node>,
+    sorted: Sorted,
+}
+
+struct Node {
+    half_size: f64,
+    /// Ranks of the bodies in the cell.
+    start: usize,
+    end: usize,
+    mass: f64,
+    center_of_mass: Vector,
+    /// Traceless quadrupole moment about the center of mass,
+    /// Q_ij = Σ m (3 x_i x_j - |x|² δ_ij).
+    quadrupole: [[f64; 3]; 3],
+    kind: Kind,
+}
+
+enum Kind {
+    Leaf,
+    /// Nodes of the non-empty octants.
+    Branch([Option<usize>; 8]),
+}
+
+impl Octree {
+    fn new(bodies: &[Body], sorted: Sorted) -> Self {
+        let mut nodes = Vec::new();
+        if !bodies.is_empty() {
+            let half_size = sorted.size / 2.0;
+            let center = sorted.low + Vector::new(half_size, half_size, half_size);
+            build(&mut nodes, bodies, &sorted, 0..bodies.len(), center, half_size, 0);
+        }
+        Octree { nodes, sorted }
+    }
+}
+
+/// Appends the node of the cell holding the bodies of `ranks`, followed by
+/// those of its octants, to `nodes`, and returns its index. Octants of large
+/// cells are built on several threads.
+fn build(
+    nodes: &mut Vec<Node>,
+    bodies: &[Body],
+    sorted: &Sorted,
+    ranks: Range<usize>,
+    center: Vector,
+    half_size: f64,
+    level: u32,
+) -> usize {
+    let index = nodes.len();
+    nodes.push(Node {
+        half_size,
+        start: ranks.start,
+        end: ranks.end,
+        mass: 0.0,
+        center_of_mass: center,
+        quadrupole: [[0.0; 3]; 3],
+        kind: Kind::Leaf,
+    });
+
+    if ranks.len() <= 1 || level == KEY_BITS {
+        let points: Vec<(f64, Vector)> = sorted.order[ranks]
+            .iter()
+            .map(|&j| (bodies[j].mass, bodies[j].position))
+            .collect();
+        nodes[index].set_moments(&points, [[0.0; 3]; 3]);
+        return index;
+    }
+
+    // The keys are sorted, so each octant is a contiguous run of them.
+    let shift = 3 * (KEY_BITS - 1 - level);
+    let keys = &sorted.keys[ranks.clone()];
+    let bounds: Vec<usize> = (0..=8u64)
+        .map(|octant| ranks.start + keys.partition_point(|key| (key >> shift) & 7 < octant))
+        .collect();
+    let octants: Vec<(usize, Range<usize>, Vector)> = (0..8)
+        .filter(|&k| bounds[k] < bounds[k + 1])
+        .map(|k| {
+            let sign = |bit: usize| if k & bit != 0 { 1.0 } else { -1.0 };
+            let offset = Vector::new(sign(1), sign(2), sign(4)) * (half_size / 2.0);
+            (k, bounds[k]..bounds[k + 1], center + offset)
+        })
+        .collect();
+
+    let mut children = [None; 8];
+    if ranks.len() >= PARALLEL_BODIES {
+        let subtrees: Vec<(usize, Vec<Node>)> = octants
+            .into_par_iter()
+            .map(|(k, ranks, center)| {
+                let mut subtree = Vec::new();
+                build(&mut subtree, bodies, sorted, ranks, center, half_size / 2.0, level + 1);
+                (k, subtree)
+            })
+            .collect();
+        for (k, subtree) in subtrees {
+            let offset = nodes.len();
+            children[k] = Some(offset);
+            nodes.extend(subtree.into_iter().map(|node| node.shifted(offset)));
+        }
+    } else {
+        for (k, ranks, center) in octants {
+            children[k] = Some(build(nodes, bodies, sorted, ranks, center, half_size / 2.0, level + 1));
+        }
+    }
+
+    let points: Vec<(f64, Vector)> = children
+        .iter()
+        .flatten()
+        .map(|&child| (nodes[child].mass, nodes[child].center_of_mass))
+        .collect();
+    let own = children.iter().flatten().fold([[0.0; 3]; 3], |sum, &child| add(sum, nodes[child].quadrupole));
+    nodes[index].set_moments(&points, own);
+    nodes[index].kind = Kind::Branch(children);
+    index
+}
+
+impl Node {
+    /// Sets the mass, center of mass and quadrupole of a node made of point
+    /// masses, plus the quadrupoles the parts have about their own centers.
+    fn set_moments(&mut self, points: &[(f64, Vector)], own: [[f64; 3]; 3]) {
+        self.mass = points.iter().map(|(mass, _)| mass).sum();
+        if self.mass > 0.0 {
+            let weighted = points.iter().fold(Vector::null(), |sum, &(mass, position)| sum + mass * position);
+            self.center_of_mass = weighted / self.mass;
+        }
+        let mut quadrupole = own;
+        for &(mass, position) in points {
+            let x = (position - self.center_of_mass).to_array();
+            let r2 = x.iter().map(|x| x * x).sum::<f64>();
+            for a in 0..3 {
+                for b in 0..3 {
+                    let delta = if a == b { r2 } else { 0.0 };
+                    quadrupole[a][b] += mass * (3.0 * x[a] * x[b] - delta);
+                }
+            }
+        }
+        self.quadrupole = quadrupole;
+    }
+
+    /// The node with its children `offset` places further in the list.
+    fn shifted(mut self, offset: usize) -> Self {
+        if let Kind::Branch(children) = &mut self.kind {
+            for child in children.iter_mut().flatten() {
+                *child += offset;
+            }
+        }
+        self
+    }
+
+    /// Acceleration at `r` from the center of mass, `distance` away, from the
+    /// expansion of the cell.
+    fn pull(&self, r: Vector, distance: f64, gravity: f64, order: MultipoleOrder) -> Vector {
+        let r3 = distance * distance * distance;
+        let mut acceleration = -(gravity * self.mass / r3) * r;
+        if order == MultipoleOrder::Quadrupole {
+            // Minus the gradient of the quadrupole potential -G rᵀQr / (2 r⁵).
+            let x = r.to_array();
+            let qr = self.quadrupole.map(|row| row[0] * x[0] + row[1] * x[1] + row[2] * x[2]);
+            let rqr = qr[0] * x[0] + qr[1] * x[1] + qr[2] * x[2];
+            let r5 = r3 * distance * distance;
+            acceleration += gravity / r5 * Vector::from(qr) - 2.5 * gravity * rqr / (r5 * distance * distance) * r;
+        }
+        acceleration
+    }
+}
+
+fn add(a: [[f64; 3]; 3], b: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    [0, 1, 2].map(|i| [0, 1, 2].map(|j| a[i][j] + b[i][j]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Tags;
+    use crate::forces::Forces;
+
+    /// A cloud of bodies of unequal masses, scattered deterministically.
+    fn cloud(count: usize) -> Vec<Body> {
+        (0..count)
+            .map(|i| {
+                let t = i as f64;
+                Body {
+                    name: format!("B{}", i),
+                    mass: 1.0 + (t * 0.61).sin().abs(),
+                    position: Vector::new((t * 0.37).sin(), (t * 1.3).cos(), (t * 0.71).sin() * (t * 0.23).cos()) * 10.0,
+                    velocity: Vector::null(),
+                    acceleration: Vector::null(),
+                    tags: Tags::new(),
+                    temperature: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Largest error relative to the direct sum, over the typical acceleration.
+    fn error(tree: BarnesHut, bodies: &[Body], direct: &[Body]) -> f64 {
+        let mut approximate = bodies.to_vec();
+        tree.accelerate(&mut approximate, 1.0);
+        let scale = direct.iter().map(|b| b.acceleration.norm()).sum::<f64>() / direct.len() as f64;
+        approximate
+            .iter()
+            .zip(direct)
+            .map(|(a, b)| (a.acceleration - b.acceleration).norm() / scale)
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn test_zero_opening_angle_is_the_direct_sum() {
+        let bodies = cloud(300);
+        let mut direct = bodies.clone();
+        Forces::newtonian(1.0).accelerate(&mut direct);
+        assert!(error(BarnesHut::new(0.0), &bodies, &direct) < 1e-12);
+    }
+
+    #[test]
+    fn test_quadrupoles_are_more_accurate() {
+        let bodies = cloud(500);
+        let mut direct = bodies.clone();
+        Forces::newtonian(1.0).accelerate(&mut direct);
+
+        let monopole = error(BarnesHut { order: MultipoleOrder::Monopole, ..BarnesHut::new(0.5) }, &bodies, &direct);
+        let quadrupole = error(BarnesHut::new(0.5), &bodies, &direct);
+        assert!(monopole < 0.05, "{}", monopole);
+        assert!(quadrupole < monopole / 3.0, "{} vs {}", quadrupole, monopole);
+    }
+
+    #[test]
+    fn test_large_trees_built_in_parallel() {
+        let mut bodies = cloud(2 * PARALLEL_BODIES);
+        let direct: Vec<Vector> = bodies[..20]
+            .iter()
+            .map(|body| {
+                bodies
+                    .iter()
+                    .filter(|other| other.name != body.name)
+                    .map(|other| {
+                        let d = other.position - body.position;
+                        other.mass / d.norm().powi(3) * d
+                    })
+                    .fold(Vector::null(), |sum, pull| sum + pull)
+            })
+            .collect();
+        let timings = BarnesHut::new(0.3).accelerate_timed(&mut bodies, 1.0);
+
+        for (body, direct) in bodies.iter().zip(direct) {
+            assert!((body.acceleration - direct).norm() < 1e-3 * direct.norm(), "{:?} vs {:?}", body.acceleration, direct);
+        }
+        assert_eq!(timings.total(), timings.sort + timings.build + timings.traverse);
+    }
+
+    #[test]
+    fn test_coincident_and_massless_bodies() {
+        let mut bodies = cloud(3);
+        bodies[1].position = bodies[0].position;
+        bodies[1].mass = 0.0;
+        bodies[2].mass = 0.0;
+        BarnesHut::new(0.5).accelerate(&mut bodies, 1.0);
+        assert_eq!(bodies[0].acceleration, Vector::null());
+        assert!(bodies[2].acceleration.norm() > 0.0);
+        assert_eq!("2".parse::<MultipoleOrder>().unwrap(), MultipoleOrder::Quadrupole);
+        assert_eq!(morton_key([1, 0, 0]), 1);
+        assert_eq!(morton_key([0, 1, 2]), 0b100_010);
+        assert!(BarnesHut::new(-1.0).check().is_err());
+    }
+}
@@ -0,0 +1,294 @@
+use super::body::Vector;
+use super::dynamics::{simulate_with, SequentialWriter, Settings};
+use super::Body;
+use rayon::prelude::*;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Uncertainty of a body's initial state: the covariance of (x, y, z, vx, vy, vz).
+#[derive(Debug, Clone)]
+pub struct Uncertain {
+    /// Index of the body in the scenario.
+    pub body: usize,
+    pub covariance: [[f64; 6]; 6],
+}
+
+impl Uncertain {
+    /// Independent errors with the same standard deviation on every position
+    /// and on every velocity component.
+    pub fn diagonal(body: usize, position_sigma: f64, velocity_sigma: f64) -> Self {
+        let mut covariance = [[0.0; 6]; 6];
+        for (i, row) in covariance.iter_mut().enumerate() {
+            let sigma = if i < 3 { position_sigma } else { velocity_sigma };
+            row[i] = sigma * sigma;
+        }
+        Uncertain { body, covariance }
+    }
+}
+
+/// Position uncertainty of a body at one recorded time.
+#[derive(Debug, Clone)]
+pub struct Ellipsoid {
+    pub time: f64,
+    pub body: usize,
+    pub mean: Vector,
+    /// Covariance of the position, in m².
+    pub covariance: [[f64; 3]; 3],
+    /// One-sigma semi-axes of the uncertainty ellipsoid, largest first.
+    pub semi_axes: [f64; 3],
+}
+
+/// Propagates the initial uncertainties with the unscented transform: each
+/// sigma point of the joint initial state is simulated with the full N-body
+/// dynamics, and the spread of the results gives the position covariance of
+/// the uncertain bodies at every recorded time.
+///
+/// This costs 12 simulations per uncertain body, plus one, run in parallel on
+/// the threads of the rayon pool.
+pub fn propagate(bodies: &[Body], settings: &Settings, uncertain: &[Uncertain]) -> Result<Vec<Ellipsoid>, Box<dyn Error>> {
+    if let Some(u) = uncertain.iter().find(|u| u.body >= bodies.len()) {
+        return Err(format!("no body at index {}", u.body).into());
+    }
+    let points = sigma_points(bodies, uncertain)?;
+    let settings = settings.for_copies()?;
+
+    let runs = points
+        .par_iter()
+        .map(|point| {
+            let mut tracker = Tracker {
+                uncertain,
+                frames: Vec::new(),
+            };
+            simulate_with(&mut point.clone(), &settings, &mut tracker)
+                .map(|_| tracker.frames)
+                .map_err(|e| e.to_string())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Every sigma point has the same weight (the center point none).
+    let weight = 1.0 / (points.len() - 1) as f64;
+    let mut ellipsoids = Vec::new();
+    for (frame, (time, _)) in runs[0].iter().enumerate() {
+        for (k, u) in uncertain.iter().enumerate() {
+            let positions: Vec<[f64; 3]> = runs[1..].iter().map(|run| run[frame].1[k]).collect();
+            let mut mean = [0.0; 3];
+            for p in &positions {
+                for i in 0..3 {
+                    mean[i] += weight * p[i];
+                }
+            }
+            let mut covariance = [[0.0; 3]; 3];
+            for p in &positions {
+                for i in 0..3 {
+                    for j in 0..3 {
+                        covariance[i][j] += weight * (p[i] - mean[i]) * (p[j] - mean[j]);
+                    }
+                }
+            }
+            ellipsoids.push(Ellipsoid {
+                time: *time,
+                body: u.body,
+                mean: Vector {
+                    x: mean[0],
+                    y: mean[1],
+                    z: mean[2],
+                },
+                semi_axes: eigenvalues(&covariance).map(|value| value.max(0.0).sqrt()),
+                covariance,
+            });
+        }
+    }
+    Ok(ellipsoids)
+}
+
+/// Writes ellipsoids as CSV, one row per body and recorded time.
+pub fn write_csv(path: &Path, ellipsoids: &[Ellipsoid], bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(
+        writer,
+        "time,name,x,y,z,cov_xx,cov_xy,cov_xz,cov_yy,cov_yz,cov_zz,sigma_1,sigma_2,sigma_3"
+    )?;
+    for e in ellipsoids {
+        let c = &e.covariance;
+        writeln!(
+            writer,
+            "{},\"{}\",{},{},{},{},{},{},{},{},{},{},{},{}",
+            e.time,
+            bodies[e.body].name.replace('"', "\"\""),
+            e.mean.x,
+            e.mean.y,
+            e.mean.z,
+            c[0][0],
+            c[0][1],
+            c[0][2],
+            c[1][1],
+            c[1][2],
+            c[2][2],
+            e.semi_axes[0],
+            e.semi_axes[1],
+            e.semi_axes[2]
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// The nominal bodies followed by the symmetric sigma points `±√n·Lᵢ`, where L
+/// is the Cholesky factor of the joint covariance and n its dimension.
+fn sigma_points(bodies: &[Body], uncertain: &[Uncertain]) -> Result<Vec<Vec<Body>>, Box<dyn Error>> {
+    let n = 6 * uncertain.len();
+    let mut covariance = vec![vec![0.0; n]; n];
+    for (k, u) in uncertain.iter().enumerate() {
+        for i in 0..6 {
+            covariance[6 * k + i][6 * k..6 * k + 6].copy_from_slice(&u.covariance[i]);
+        }
+    }
+    let factor = cholesky(&covariance)?;
+    let scale = (n as f64).sqrt();
+    let columns = (0..n).map(|j| factor.iter().map(|row| row[j]).collect::<Vec<_>>());
+
+    let mut points = vec![bodies.to_vec()];
+    for column in columns {
+        for sign in [1.0, -1.0] {
+            let mut point = bodies.to_vec();
+            for (k, u) in uncertain.iter().enumerate() {
+                let body = &mut point[u.body];
+                let shift = |i: usize| sign * scale * column[6 * k + i];
+                body.position.x += shift(0);
+                body.position.y += shift(1);
+                body.position.z += shift(2);
+                body.velocity.x += shift(3);
+                body.velocity.y += shift(4);
+                body.velocity.z += shift(5);
+            }
+            points.push(point);
+        }
+    }
+    Ok(points)
+}
+
+/// Lower Cholesky factor of a positive semi-definite matrix; directions
+/// without uncertainty get zero columns.
+pub(crate) fn cholesky(matrix: &[Vec<f64>]) -> Result<Vec<Vec<f64>>, Box<dyn Error>> {
+    let n = matrix.len();
+    let scale = matrix.iter().enumerate().fold(0.0f64, |max, (i, row)| max.max(row[i].abs()));
+    let mut factor = vec![vec![0.0; n]; n];
+    for j in 0..n {
+        let diagonal = matrix[j][j] - factor[j][..j].iter().map(|v| v * v).sum::<f64>();
+        if diagonal < -1e-12 * scale {
+            return Err("the covariance matrix is not positive semi-definite".into());
+        }
+        if diagonal <= 1e-15 * scale {
+            continue;
+        }
+        let pivot = diagonal.sqrt();
+        factor[j][j] = pivot;
+        for i in j + 1..n {
+            let dot: f64 = (0..j).map(|k| factor[i][k] * factor[j][k]).sum();
+            factor[i][j] = (matrix[i][j] - dot) / pivot;
+        }
+    }
+    Ok(factor)
+}
+
+/// Eigenvalues of a symmetric 3×3 matrix, largest first (trigonometric solution).
+fn eigenvalues(m: &[[f64; 3]; 3]) -> [f64; 3] {
+    let off = m[0][1] * m[0][1] + m[0][2] * m[0][2] + m[1][2] * m[1][2];
+    if off == 0.0 {
+        let mut values = [m[0][0], m[1][1], m[2][2]];
+        values.sort_by(|a, b| b.total_cmp(a));
+        return values;
+    }
+    let q = (m[0][0] + m[1][1] + m[2][2]) / 3.0;
+    let p = (((m[0][0] - q).powi(2) + (m[1][1] - q).powi(2) + (m[2][2] - q).powi(2) + 2.0 * off) / 6.0).sqrt();
+    let b = |i: usize, j: usize| (m[i][j] - if i == j { q } else { 0.0 }) / p;
+    let determinant = b(0, 0) * (b(1, 1) * b(2, 2) - b(1, 2) * b(2, 1)) - b(0, 1) * (b(1, 0) * b(2, 2) - b(1, 2) * b(2, 0))
+        + b(0, 2) * (b(1, 0) * b(2, 1) - b(1, 1) * b(2, 0));
+    let phi = (determinant / 2.0).clamp(-1.0, 1.0).acos() / 3.0;
+    let largest = q + 2.0 * p * phi.cos();
+    let smallest = q + 2.0 * p * (phi + 2.0 * std::f64::consts::PI / 3.0).cos();
+    [largest, 3.0 * q - largest - smallest, smallest]
+}
+
+/// Writer keeping the positions of the uncertain bodies at every recorded time.
+struct Tracker<'a> {
+    uncertain: &'a [Uncertain],
+    frames: Vec<(f64, Vec<[f64; 3]>)>,
+}
+
+impl SequentialWriter for Tracker<'_> {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        let positions = self
+            .uncertain
+            .iter()
+            .map(|u| {
+                let p = &bodies[u.body].position;
+                [p.x, p.y, p.z]
+            })
+            .collect();
+        self.frames.push((time, positions));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Tags;
+    use crate::dynamics::Recording;
+
+    fn free_body() -> Body {
+        Body {
+            name: "Rock".to_string(),
+            mass: 1.0,
+            position: Vector::null(),
+            velocity: Vector { x: 1.0, y: 0.0, z: 0.0 },
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }
+    }
+
+    #[test]
+    fn test_free_motion_grows_linearly() {
+        let settings = Settings {
+            total_time: 10.0,
+            dt: 0.5,
+            recording: Recording::Interval(5.0),
+            progress: false,
+            ..Settings::default()
+        };
+        let uncertain = [Uncertain::diagonal(0, 2.0, 0.1)];
+
+        let ellipsoids = propagate(&[free_body()], &settings, &uncertain).unwrap();
+
+        assert_eq!(ellipsoids.len(), 3);
+        let last = &ellipsoids[2];
+        assert_eq!(last.time, 10.0);
+        assert!((last.mean.x - 10.0).abs() < 1e-9);
+        // σ² = 2² + (0.1·10)² on every axis, uncorrelated.
+        for i in 0..3 {
+            assert!((last.covariance[i][i] - 5.0).abs() < 1e-9, "{:?}", last.covariance);
+            assert!((last.semi_axes[i] - 5f64.sqrt()).abs() < 1e-9);
+        }
+        assert!(last.covariance[0][1].abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_eigenvalues() {
+        let m = [[2.0, 1.0, 0.0], [1.0, 2.0, 0.0], [0.0, 0.0, 5.0]];
+        let values = eigenvalues(&m);
+        for (value, expected) in values.iter().zip([5.0, 3.0, 1.0]) {
+            assert!((value - expected).abs() < 1e-12, "{:?}", values);
+        }
+    }
+
+    #[test]
+    fn test_rejects_indefinite_covariance() {
+        let mut uncertain = Uncertain::diagonal(0, 1.0, 1.0);
+        uncertain.covariance[0][0] = -1.0;
+        assert!(propagate(&[free_body()], &Settings::default(), &[uncertain]).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+/// A 3D vector of `f64` components, in SI units wherever bodies are concerned.
+///
+/// Supports the usual arithmetic operators, and converts to and from `[f64; 3]`,
+/// nalgebra's `Vector3<f64>` (feature `nalgebra`) and glam's `DVec3` (feature `glam`).
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Vector {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Vector {
+    pub const fn new(x: f64, y: f64, z: f64) -> Self {
+        Vector { x, y, z }
+    }
+
+    pub fn null() -> Self {
+        Vector {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    pub fn dot(&self, other: &Vector) -> f64 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(&self, other: &Vector) -> Vector {
+        Vector {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn norm_squared(&self) -> f64 {
+        self.dot(self)
+    }
+
+    pub fn norm(&self) -> f64 {
+        self.norm_squared().sqrt()
+    }
+
+    pub fn to_array(self) -> [f64; 3] {
+        [self.x, self.y, self.z]
+    }
+}
+
+impl Add for Vector {
+    type Output = Vector;
+
+    fn add(self, other: Vector) -> Vector {
+        Vector::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl Sub for Vector {
+    type Output = Vector;
+
+    fn sub(self, other: Vector) -> Vector {
+        Vector::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl Neg for Vector {
+    type Output = Vector;
+
+    fn neg(self) -> Vector {
+        Vector::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl Mul<f64> for Vector {
+    type Output = Vector;
+
+    fn mul(self, k: f64) -> Vector {
+        Vector::new(self.x * k, self.y * k, self.z * k)
+    }
+}
+
+impl Mul<Vector> for f64 {
+    type Output = Vector;
+
+    fn mul(self, v: Vector) -> Vector {
+        v * self
+    }
+}
+
+impl Div<f64> for Vector {
+    type Output = Vector;
+
+    fn div(self, k: f64) -> Vector {
+        Vector::new(self.x / k, self.y / k, self.z / k)
+    }
+}
+
+impl AddAssign for Vector {
+    fn add_assign(&mut self, other: Vector) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Vector {
+    fn sub_assign(&mut self, other: Vector) {
+        *self = *self - other;
+    }
+}
+
+impl MulAssign<f64> for Vector {
+    fn mul_assign(&mut self, k: f64) {
+        *self = *self * k;
+    }
+}
+
+impl DivAssign<f64> for Vector {
+    fn div_assign(&mut self, k: f64) {
+        *self = *self / k;
+    }
+}
+
+impl From<[f64; 3]> for Vector {
+    fn from([x, y, z]: [f64; 3]) -> Self {
+        Vector { x, y, z }
+    }
+}
+
+impl From<Vector> for [f64; 3] {
+    fn from(v: Vector) -> Self {
+        v.to_array()
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<nalgebra::Vector3<f64>> for Vector {
+    fn from(v: nalgebra::Vector3<f64>) -> Self {
+        Vector::new(v.x, v.y, v.z)
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<Vector> for nalgebra::Vector3<f64> {
+    fn from(v: Vector) -> Self {
+        nalgebra::Vector3::new(v.x, v.y, v.z)
+    }
+}
+
+#[cfg(feature = "glam")]
+impl From<glam::DVec3> for Vector {
+    fn from(v: glam::DVec3) -> Self {
+        Vector::new(v.x, v.y, v.z)
+    }
+}
+
+#[cfg(feature = "glam")]
+impl From<Vector> for glam::DVec3 {
+    fn from(v: Vector) -> Self {
+        glam::DVec3::new(v.x, v.y, v.z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operators() {
+        let a = Vector::new(1.0, 2.0, 3.0);
+        let b = Vector::new(-2.0, 0.5, 4.0);
+
+        assert_eq!(a + b, Vector::new(-1.0, 2.5, 7.0));
+        assert_eq!(a - b, Vector::new(3.0, 1.5, -1.0));
+        assert_eq!(2.0 * a, a * 2.0);
+        assert_eq!(a / 2.0, Vector::new(0.5, 1.0, 1.5));
+        assert_eq!(-a + a, Vector::null());
+        assert_eq!(a.dot(&b), 11.0);
+        assert_eq!(a.cross(&b).dot(&a), 0.0);
+        assert_eq!(Vector::new(3.0, 4.0, 12.0).norm(), 13.0);
+
+        let mut c = a;
+        c += b;
+        c -= b;
+        c *= 3.0;
+        c /= 3.0;
+        assert_eq!(c, a);
+        assert_eq!(<[f64; 3]>::from(a), [1.0, 2.0, 3.0]);
+    }
+
+    #[cfg(feature = "nalgebra")]
+    #[test]
+    fn test_nalgebra_round_trip() {
+        let v = Vector::new(1.0, 2.0, 3.0);
+        let n: nalgebra::Vector3<f64> = v.into();
+        assert_eq!(n.norm(), v.norm());
+        assert_eq!(Vector::from(n), v);
+    }
+
+    #[cfg(feature = "glam")]
+    #[test]
+    fn test_glam_round_trip() {
+        let v = Vector::new(1.0, 2.0, 3.0);
+        let g: glam::DVec3 = v.into();
+        assert_eq!(g.length(), v.norm());
+        assert_eq!(Vector::from(g), v);
+    }
+}
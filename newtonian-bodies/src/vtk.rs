@@ -0,0 +1,144 @@
+use super::body::Vector;
+use super::dynamics::SequentialWriter;
+use super::Body;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes frames as a ParaView time series: one `.vtp` poly data file per
+/// frame, in a directory named after the collection, and a `.pvd` collection
+/// listing them with their times.
+///
+/// Bodies are vertices carrying `mass`, `speed`, `velocity` and `id` (their
+/// position in the frame) point arrays. ParaView's "Temporal Particles To
+/// Pathlines" filter turns the series into trajectories.
+pub struct Writer {
+    collection: PathBuf,
+    directory: PathBuf,
+    frames: Vec<(f64, String)>,
+}
+
+impl Writer {
+    /// Prepares a series whose collection file is `collection` (e.g. `orbits.pvd`).
+    pub fn new(collection: &Path) -> Result<Self, Box<dyn Error>> {
+        let stem = collection
+            .file_stem()
+            .ok_or_else(|| format!("invalid collection path {}", collection.display()))?;
+        let directory = collection.with_file_name(stem);
+        fs::create_dir_all(&directory)?;
+        Ok(Writer {
+            collection: collection.to_path_buf(),
+            directory,
+            frames: Vec::new(),
+        })
+    }
+
+    /// Writes the `.pvd` collection referencing every frame added so far.
+    pub fn close(self) -> Result<(), Box<dyn Error>> {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\"?>\n<VTKFile type=\"Collection\" version=\"0.1\" byte_order=\"LittleEndian\">\n  <Collection>\n",
+        );
+        for (time, file) in &self.frames {
+            writeln!(xml, "    <DataSet timestep=\"{:e}\" group=\"\" part=\"0\" file=\"{}\"/>", time, file)?;
+        }
+        xml.push_str("  </Collection>\n</VTKFile>\n");
+        fs::write(&self.collection, xml)?;
+        Ok(())
+    }
+}
+
+impl SequentialWriter for Writer {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        let name = format!("frame_{:06}.vtp", self.frames.len());
+        let mut writer = BufWriter::new(File::create(self.directory.join(&name))?);
+        writer.write_all(poly_data(bodies)?.as_bytes())?;
+        writer.flush()?;
+
+        // Paths in the collection are relative to it, with forward slashes.
+        let directory = self.directory.file_name().unwrap_or_default().to_string_lossy();
+        self.frames.push((time, format!("{}/{}", directory, name)));
+        Ok(())
+    }
+}
+
+/// ASCII VTK poly data with one vertex per body.
+fn poly_data(bodies: &[Body]) -> Result<String, Box<dyn Error>> {
+    let n = bodies.len();
+    let vector = |v: &Vector| format!("{:e} {:e} {:e}", v.x, v.y, v.z);
+    let arrays = [
+        ("mass", "Float64", 1, join(bodies.iter().map(|b| format!("{:e}", b.mass)))),
+        ("speed", "Float64", 1, join(bodies.iter().map(|b| format!("{:e}", b.velocity.norm())))),
+        ("velocity", "Float64", 3, join(bodies.iter().map(|b| vector(&b.velocity)))),
+        ("id", "Int32", 1, join((0..n).map(|i| i.to_string()))),
+    ];
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<VTKFile type=\"PolyData\" version=\"0.1\" byte_order=\"LittleEndian\">\n  <PolyData>\n",
+    );
+    writeln!(
+        xml,
+        "    <Piece NumberOfPoints=\"{n}\" NumberOfVerts=\"{n}\" NumberOfLines=\"0\" NumberOfStrips=\"0\" NumberOfPolys=\"0\">"
+    )?;
+    xml.push_str("      <PointData Scalars=\"mass\" Vectors=\"velocity\">\n");
+    for (name, kind, components, values) in arrays {
+        writeln!(
+            xml,
+            "        <DataArray type=\"{kind}\" Name=\"{name}\" NumberOfComponents=\"{components}\" format=\"ascii\">{values}</DataArray>"
+        )?;
+    }
+    xml.push_str("      </PointData>\n");
+    writeln!(
+        xml,
+        "      <Points>\n        <DataArray type=\"Float64\" NumberOfComponents=\"3\" format=\"ascii\">{}</DataArray>\n      </Points>",
+        join(bodies.iter().map(|b| vector(&b.position)))
+    )?;
+    // Each vertex cell holds a single point.
+    writeln!(
+        xml,
+        "      <Verts>\n        <DataArray type=\"Int32\" Name=\"connectivity\" format=\"ascii\">{}</DataArray>\n        <DataArray type=\"Int32\" Name=\"offsets\" format=\"ascii\">{}</DataArray>\n      </Verts>",
+        join((0..n).map(|i| i.to_string())),
+        join((1..=n).map(|i| i.to_string()))
+    )?;
+    xml.push_str("    </Piece>\n  </PolyData>\n</VTKFile>\n");
+    Ok(xml)
+}
+
+fn join(values: impl Iterator<Item = String>) -> String {
+    values.collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Tags;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_series_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let collection = temp_dir.path().join("orbits.pvd");
+        let bodies = vec![Body {
+            name: "Earth".to_string(),
+            mass: 5.972e24,
+            position: Vector { x: 1.0, y: 2.0, z: 3.0 },
+            velocity: Vector { x: 3.0, y: 4.0, z: 0.0 },
+            acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
+        }];
+
+        let mut writer = Writer::new(&collection).unwrap();
+        writer.add(0.0, &bodies).unwrap();
+        writer.add(10.0, &bodies).unwrap();
+        writer.close().unwrap();
+
+        let pvd = fs::read_to_string(&collection).unwrap();
+        assert!(pvd.contains("timestep=\"1e1\" group=\"\" part=\"0\" file=\"orbits/frame_000001.vtp\""));
+        let vtp = fs::read_to_string(temp_dir.path().join("orbits/frame_000000.vtp")).unwrap();
+        assert!(vtp.contains("NumberOfPoints=\"1\""));
+        assert!(vtp.contains("Name=\"speed\" NumberOfComponents=\"1\" format=\"ascii\">5e0<"));
+        assert!(vtp.contains(">1e0 2e0 3e0<"));
+    }
+}
@@ -1,98 +1,259 @@
+use super::body::Tags;
 use super::dynamics::SequentialWriter;
+use super::schema::{self, Layout, Precision};
 use super::Body;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use arrow::array::{Float64Array, StringArray, UInt64Array};
-use arrow::datatypes::{DataType, Field, Schema};
+use arrow::array::{ArrayRef, BooleanArray, Float32Array, Float64Array, StringArray};
+use arrow::datatypes::Schema;
 use arrow::record_batch::RecordBatch;
 use parquet::arrow::arrow_writer::ArrowWriter;
+use parquet::file::properties::WriterProperties;
 
 
 
 pub struct Writer {
     writer: ArrowWriter<File>,
     schema: Schema,
+    max_buffer: Option<usize>,
+    peak_buffer: usize,
+    tags: BTreeMap<String, Tags>,
+    layout: Layout,
+    /// Masses of the recorded bodies, stored as metadata when the column is left out.
+    masses: BTreeMap<String, f64>,
+    frames: usize,
+    /// Position of each body as a reader of delta-encoded output reconstructs it.
+    previous: HashMap<String, [f64; 3]>,
 }
 
 impl Writer {
     pub fn new(file: PathBuf) -> Result<Self, Box<dyn Error>> {
-        let schema = Schema::new(vec![
-            Field::new("time", DataType::UInt64, false),
-            Field::new("name", DataType::Utf8, false),
-            Field::new("mass", DataType::Float64, false),
-            Field::new("pos_x", DataType::Float64, false),
-            Field::new("pos_y", DataType::Float64, false),
-            Field::new("pos_z", DataType::Float64, false),
-            // Add velocity and acceleration fields if needed
-        ]);
+        Self::with_layout(file, Layout::default())
+    }
+
+    /// Writer leaving out columns or storing them with less precision, for
+    /// smaller outputs (e.g., of runs only meant to be visualized).
+    pub fn with_layout(file: PathBuf, layout: Layout) -> Result<Self, Box<dyn Error>> {
+        let schema = schema::layout_schema(&layout);
+        let properties = WriterProperties::builder()
+            .set_key_value_metadata(Some(vec![schema::version_metadata()]))
+            .build();
 
         let file = File::create(file)?;
-        let writer = ArrowWriter::try_new(file, Arc::new(schema.clone()), None)?;
+        let writer = ArrowWriter::try_new(file, Arc::new(schema.clone()), Some(properties))?;
 
-        Ok(Self { writer, schema: schema.clone() })
+        Ok(Self {
+            writer,
+            schema: schema.clone(),
+            max_buffer: None,
+            peak_buffer: 0,
+            tags: BTreeMap::new(),
+            layout,
+            masses: BTreeMap::new(),
+            frames: 0,
+            previous: HashMap::new(),
+        })
+    }
+
+    /// Flushes a row group to disk whenever the buffered rows exceed `bytes`,
+    /// instead of waiting for the default row group size.
+    pub fn with_max_buffer(mut self, bytes: usize) -> Self {
+        self.max_buffer = Some(bytes);
+        self
+    }
+
+    /// Largest amount of memory, in bytes, held by rows not yet flushed to disk.
+    pub fn peak_buffer(&self) -> usize {
+        self.peak_buffer
+    }
+
+    /// Writes the states of one body at successive `times`, each a frame of
+    /// its own, as a single batch.
+    pub fn add_series(&mut self, times: &[f64], states: &[Body]) -> Result<(), Box<dyn Error>> {
+        debug_assert_eq!(times.len(), states.len());
+        self.write(times, states)
     }
 
     // `close` is now handled when the writer is dropped, but an explicit
     // close is good practice to handle potential I/O errors.
-    pub fn close(self) -> Result<(), Box<dyn Error>> {
+    pub fn close(mut self) -> Result<(), Box<dyn Error>> {
+        if !self.tags.is_empty() {
+            self.writer.append_key_value_metadata(schema::tags_metadata(&self.tags)?);
+        }
+        if !self.layout.mass {
+            self.writer.append_key_value_metadata(schema::masses_metadata(&self.masses)?);
+        }
         self.writer.close()?;
         Ok(())
     }
 }
 
 impl SequentialWriter for Writer {
-    /// Converts the slice of bodies into Arrow arrays and writes them as a RecordBatch.
-    fn add(&mut self, time: u64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        self.write(&[time], bodies)
+    }
+}
+
+impl Writer {
+    /// Converts the bodies into Arrow arrays and writes them as a RecordBatch:
+    /// one frame of them all when there is a single time, otherwise a frame
+    /// per row at its own time.
+    fn write(&mut self, times: &[f64], bodies: &[Body]) -> Result<(), Box<dyn Error>> {
         let num_rows = bodies.len();
+        let series = times.len() > 1;
+        for body in bodies.iter().filter(|b| !b.tags.is_empty()) {
+            if !self.tags.contains_key(&body.name) {
+                self.tags.insert(body.name.clone(), body.tags.clone());
+            }
+        }
+
+        if !self.layout.mass {
+            for body in bodies {
+                if !self.masses.contains_key(&body.name) {
+                    self.masses.insert(body.name.clone(), body.mass);
+                }
+            }
+        }
 
-        let time_array = Arc::new(UInt64Array::from(vec![time as u64; num_rows]));
-        let name_array = Arc::new(StringArray::from_iter_values(
-            bodies.iter().map(|b| &b.name),
-        ));
-        let mass_array = Arc::new(Float64Array::from_iter_values(
-            bodies.iter().map(|b| b.mass),
-        ));
-        let pos_x_array = Arc::new(Float64Array::from_iter_values(
-            bodies.iter().map(|b| b.position.x),
-        ));
-        let pos_y_array = Arc::new(Float64Array::from_iter_values(
-            bodies.iter().map(|b| b.position.y),
-        ));
-        let pos_z_array = Arc::new(Float64Array::from_iter_values(
-            bodies.iter().map(|b| b.position.z),
-        ));
+        let precision = self.layout.precision;
+        let column = |values: Vec<f64>| -> ArrayRef {
+            match precision {
+                Precision::Single => Arc::new(Float32Array::from_iter_values(values.into_iter().map(|v| v as f32))),
+                _ => Arc::new(Float64Array::from(values)),
+            }
+        };
+        let first = self.frames;
+        self.frames += times.len();
+        let mut positions = [Vec::with_capacity(num_rows), Vec::with_capacity(num_rows), Vec::with_capacity(num_rows)];
+        let mut deltas = Vec::with_capacity(num_rows);
+        for (row, body) in bodies.iter().enumerate() {
+            let frame = if series { first + row } else { first };
+            let delta_frame = self.layout.keyframe_interval.is_some_and(|n| !frame.is_multiple_of(n.max(1)));
+            let position = [body.position.x, body.position.y, body.position.z];
+            let previous = self.previous.get(&body.name).filter(|_| delta_frame).copied();
+            let stored = match previous {
+                Some(previous) => [0, 1, 2].map(|i| precision.round(position[i] - previous[i])),
+                None => position.map(|value| precision.round(value)),
+            };
+            if self.layout.keyframe_interval.is_some() {
+                let reconstructed = previous.map_or(stored, |previous| [0, 1, 2].map(|i| previous[i] + stored[i]));
+                self.previous.insert(body.name.clone(), reconstructed);
+            }
+            deltas.push(previous.is_some());
+            for (column, value) in positions.iter_mut().zip(stored) {
+                column.push(value);
+            }
+        }
+
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(Float64Array::from_iter_values((0..num_rows).map(|row| times[if series { row } else { 0 }]))),
+            Arc::new(StringArray::from_iter_values(bodies.iter().map(|b| &b.name))),
+        ];
+        if self.layout.mass {
+            columns.push(Arc::new(Float64Array::from_iter_values(bodies.iter().map(|b| b.mass))));
+        }
+        columns.extend(positions.map(column));
+        if self.layout.velocities {
+            let velocity = |value: fn(&Body) -> f64| column(bodies.iter().map(|b| precision.round(value(b))).collect());
+            columns.extend([velocity(|b| b.velocity.x), velocity(|b| b.velocity.y), velocity(|b| b.velocity.z)]);
+        }
+        if self.layout.keyframe_interval.is_some() {
+            columns.push(Arc::new(BooleanArray::from(deltas)));
+        }
+        if self.layout.temperature {
+            columns.push(Arc::new(Float64Array::from_iter(bodies.iter().map(|b| b.temperature))));
+        }
 
         // 2. Create a RecordBatch from the arrays.
-        let batch = RecordBatch::try_new(
-            Arc::new(self.schema.clone()),
-            vec![
-                time_array,
-                name_array,
-                mass_array,
-                pos_x_array,
-                pos_y_array,
-                pos_z_array,
-            ],
-        )?;
+        let batch = RecordBatch::try_new(Arc::new(self.schema.clone()), columns)?;
 
         // 3. Write the batch to the Parquet file.
         self.writer.write(&batch)?;
 
+        // 4. Keep the buffered row group within the memory budget.
+        let buffered = self.writer.memory_size();
+        self.peak_buffer = self.peak_buffer.max(buffered);
+        if self.max_buffer.is_some_and(|max| buffered > max) {
+            self.writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Writes frames as CSV with the column names of the Parquet output, which the
+/// `reader` module reads back. Meant for plotting and spreadsheets; masses are
+/// always written and delta encoding isn't supported.
+pub struct CsvWriter {
+    writer: BufWriter<File>,
+    layout: Layout,
+}
+
+impl CsvWriter {
+    pub fn new(file: PathBuf, layout: Layout) -> Result<Self, Box<dyn Error>> {
+        if !layout.mass || layout.keyframe_interval.is_some() {
+            return Err("CSV outputs keep the mass column and absolute positions".into());
+        }
+        let mut writer = BufWriter::new(File::create(file)?);
+        write!(writer, "time,name,mass,pos_x,pos_y,pos_z")?;
+        if layout.velocities {
+            write!(writer, ",vel_x,vel_y,vel_z")?;
+        }
+        if layout.temperature {
+            write!(writer, ",temperature")?;
+        }
+        writeln!(writer)?;
+        Ok(CsvWriter { writer, layout })
+    }
+
+    pub fn close(mut self) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
         Ok(())
     }
 }
 
+impl SequentialWriter for CsvWriter {
+    fn add(&mut self, time: f64, bodies: &[Body]) -> Result<(), Box<dyn Error>> {
+        let precision = self.layout.precision;
+        for body in bodies {
+            write!(self.writer, "{},\"{}\",{}", time, body.name.replace('"', "\"\""), body.mass)?;
+            let mut values = vec![body.position.x, body.position.y, body.position.z];
+            if self.layout.velocities {
+                values.extend([body.velocity.x, body.velocity.y, body.velocity.z]);
+            }
+            for value in values {
+                match precision {
+                    // Shortest text that reads back as the same f32.
+                    Precision::Single => write!(self.writer, ",{}", value as f32)?,
+                    _ => write!(self.writer, ",{}", precision.round(value))?,
+                }
+            }
+            if self.layout.temperature {
+                write!(self.writer, ",")?;
+                if let Some(temperature) = body.temperature {
+                    write!(self.writer, "{}", temperature)?;
+                }
+            }
+            writeln!(self.writer)?;
+        }
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 mod tests {  
     use super::*;
-    use crate::body::Vector;
-    use parquet::arrow::arrow_reader::ParquetRecordBatchReader;
+    use crate::body::{Tags, Vector};
+    use arrow::datatypes::DataType;
+    use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
     use arrow::record_batch::RecordBatchReader;
-    use arrow::array::{Float64Array, StringArray, UInt64Array};
+    use arrow::array::{Float64Array, StringArray};
 
     fn create_test_body(name: &str, mass: f64, x: f64, y: f64, z: f64) -> Body {
         Body {
@@ -101,6 +262,8 @@ mod tests {
             position: Vector { x, y, z },
             velocity: Vector::null(),
             acceleration: Vector::null(),
+            tags: Tags::new(),
+            temperature: None,
         }
     }
 
@@ -110,7 +273,7 @@ mod tests {
         
         // Create writer and write test data
         let mut writer = Writer::new(test_file.clone()).unwrap();
-        writer.add(0, &[create_test_body("Earth", 5.972e24, 1.496e11, 0.0, 0.0)]).unwrap();
+        writer.add(0.0, &[create_test_body("Earth", 5.972e24, 1.496e11, 0.0, 0.0)]).unwrap();
         writer.close().unwrap();
 
         // Read the file and verify schema
@@ -119,11 +282,11 @@ mod tests {
         let schema = reader.schema();
         
         // Check field count
-        assert_eq!(schema.fields().len(), 6);
+        assert_eq!(schema.fields().len(), 9);
         
         // Check field names and data types
         assert_eq!(schema.field(0).name(), "time");
-        assert_eq!(schema.field(0).data_type(), &DataType::UInt64);
+        assert_eq!(schema.field(0).data_type(), &DataType::Float64);
         assert!(!schema.field(0).is_nullable());
         
         assert_eq!(schema.field(1).name(), "name");
@@ -145,6 +308,12 @@ mod tests {
         assert_eq!(schema.field(5).name(), "pos_z");
         assert_eq!(schema.field(5).data_type(), &DataType::Float64);
         assert!(!schema.field(5).is_nullable());
+
+        for (i, name) in ["vel_x", "vel_y", "vel_z"].iter().enumerate() {
+            assert_eq!(schema.field(6 + i).name(), name);
+            assert_eq!(schema.field(6 + i).data_type(), &DataType::Float64);
+            assert!(!schema.field(6 + i).is_nullable());
+        }
         
         // Clean up test file
         std::fs::remove_file(&test_file).unwrap();
@@ -154,7 +323,7 @@ mod tests {
     fn test_generated_file_has_the_correct_data() {
         let test_file = PathBuf::from("test_data.parquet");
         let mut writer = Writer::new(test_file.clone()).unwrap();
-        writer.add(0, &[create_test_body("Earth", 5.972e24, 1.496e11, 0.0, 0.0)]).unwrap();
+        writer.add(0.0, &[create_test_body("Earth", 5.972e24, 1.496e11, 0.0, 0.0)]).unwrap();
         writer.close().unwrap();
 
         let file = File::open(&test_file).unwrap();
@@ -170,9 +339,9 @@ mod tests {
         
         // Extract arrays and verify values
         let time_array = batch.column(0).as_any()
-            .downcast_ref::<UInt64Array>()
-            .expect("Column 0 should be UInt64Array");
-        assert_eq!(time_array.value(0), 0, "Time should be 0");
+            .downcast_ref::<Float64Array>()
+            .expect("Column 0 should be Float64Array");
+        assert_eq!(time_array.value(0), 0.0, "Time should be 0");
         
         let name_array = batch.column(1).as_any()
             .downcast_ref::<StringArray>()
@@ -206,4 +375,58 @@ mod tests {
         std::fs::remove_file(&test_file).unwrap();
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_generated_file_records_the_schema_version() {
+        let test_file = PathBuf::from("test_version.parquet");
+        let mut writer = Writer::new(test_file.clone()).unwrap();
+        writer.add(0.0, &[create_test_body("Earth", 5.972e24, 1.496e11, 0.0, 0.0)]).unwrap();
+        writer.close().unwrap();
+
+        let file = File::open(&test_file).unwrap();
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        let metadata = builder.metadata().file_metadata().key_value_metadata();
+        assert_eq!(schema::version_from_metadata(metadata).unwrap(), schema::CURRENT_VERSION);
+
+        std::fs::remove_file(&test_file).unwrap();
+    }
+
+    #[test]
+    fn test_max_buffer_flushes_row_groups() {
+        let test_file = PathBuf::from("test_max_buffer.parquet");
+        let mut writer = Writer::new(test_file.clone()).unwrap().with_max_buffer(1);
+        for time in [0.0, 1.0, 2.0] {
+            writer.add(time, &[create_test_body("Earth", 5.972e24, 1.496e11, 0.0, 0.0)]).unwrap();
+        }
+        assert!(writer.peak_buffer() > 0);
+        writer.close().unwrap();
+
+        let file = File::open(&test_file).unwrap();
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        assert_eq!(builder.metadata().num_row_groups(), 3);
+
+        std::fs::remove_file(&test_file).unwrap();
+    }
+
+    #[test]
+    fn test_csv_output_reads_back() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("plot.csv");
+        let layout = Layout {
+            precision: Precision::Decimals(0),
+            velocities: false,
+            ..Layout::default()
+        };
+
+        let mut writer = CsvWriter::new(path.clone(), layout).unwrap();
+        writer.add(0.0, &[create_test_body("Earth, \"blue\"", 5.972e24, 1.4, 2.0, 3.0)]).unwrap();
+        writer.add(1.0, &[create_test_body("Earth, \"blue\"", 5.972e24, 1.6, 2.0, 3.0)]).unwrap();
+        writer.close().unwrap();
+
+        let records = crate::reader::read_records(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].body.name, "Earth, \"blue\"");
+        assert_eq!(records[1].body.mass, 5.972e24);
+        assert_eq!(records[0].body.position.x, 1.0);
+        assert_eq!(records[1].body.position.x, 2.0);
+    }
+}
@@ -49,7 +49,7 @@ fn test_basic_functionality() {
     
     // Run the CLI with basic arguments
     let output = Command::new("cargo")
-        .args(&[
+        .args([
             "run", "--",
             &input_file,
             "-o", output_file.to_str().unwrap(),
@@ -83,7 +83,7 @@ fn test_default_output_filename() {
     
     // Run the CLI without specifying output file (should use default)
     let output = Command::new("cargo")
-        .args(&[
+        .args([
             "run", "--",
             &input_file,
             "-g", "6.67430e-11",
@@ -117,7 +117,7 @@ fn test_expression_parsing() {
     
     // Test with mathematical expressions in arguments
     let output = Command::new("cargo")
-        .args(&[
+        .args([
             "run", "--",
             &input_file,
             "-o", output_file.to_str().unwrap(),
@@ -149,7 +149,7 @@ fn test_long_arguments() {
     
     // Test with long argument forms
     let output = Command::new("cargo")
-        .args(&[
+        .args([
             "run", "--",
             &input_file,
             "--output", output_file.to_str().unwrap(),
@@ -181,7 +181,7 @@ fn test_invalid_input_file() {
     
     // Test with non-existent input file
     let output = Command::new("cargo")
-        .args(&[
+        .args([
             "run", "--",
             invalid_input.to_str().unwrap()
         ])
@@ -206,7 +206,7 @@ fn test_invalid_gravity_expression() {
     
     // Test with invalid gravity expression
     let output = Command::new("cargo")
-        .args(&[
+        .args([
             "run", "--",
             &input_file,
             "-g", "invalid_expression"
@@ -231,7 +231,7 @@ fn test_output_file_permissions() {
     
     // Run the CLI to generate output file
     let output = Command::new("cargo")
-        .args(&[
+        .args([
             "run", "--",
             &input_file,
             "-o", "test_output.parquet",
@@ -266,3 +266,2020 @@ fn test_output_file_permissions() {
     // Clean up the output file
     fs::remove_file(output_file_path).expect("Failed to remove test output file");
 }
+
+#[test]
+fn test_record_count() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let output_file = temp_dir.path().join("test_output.parquet");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            &input_file,
+            "-o", output_file.to_str().unwrap(),
+            "-t", "10.0",
+            "-d", "0.3",
+            "-i", "rk4",
+            "--record-count", "5"
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Initial and final states plus three evenly spaced frames in between
+    let frames: Vec<_> = newtonian_solar_system::reader::SimulationReader::open(&output_file)
+        .expect("Failed to open output file")
+        .collect::<Result<_, _>>()
+        .expect("Failed to read frames");
+    let times: Vec<f64> = frames.iter().map(|frame| frame.time).collect();
+    assert_eq!(times, vec![0.0, 2.5, 5.0, 7.5, 10.0]);
+}
+
+#[test]
+fn test_verlet_integrator() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("orbit.json");
+    fs::write(&input_file, r#"[
+        {"name": "Star", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Planet", "mass": 1e-12, "position": {"x": 1.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 1.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+    let output_file = temp_dir.path().join("orbit.parquet");
+
+    // Fifty orbits of period 2π.
+    let output = Command::new("cargo")
+        .args(["run", "--", input_file.to_str().unwrap(), "-o", output_file.to_str().unwrap()])
+        .args(["-g", "1", "-t", "314.159", "-d", "0.01", "-i", "verlet", "--record-count", "2"])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let frames: Vec<_> = newtonian_solar_system::reader::SimulationReader::open(&output_file)
+        .expect("Failed to open output file")
+        .collect::<Result<_, _>>()
+        .expect("Failed to read frames");
+    let planet = &frames.last().expect("No frames").bodies[1];
+    assert!((planet.position.norm() - 1.0).abs() < 1e-4, "{:?}", planet.position);
+    assert!((planet.velocity.norm() - 1.0).abs() < 1e-4, "{:?}", planet.velocity);
+}
+
+#[test]
+fn test_output_precision_and_dropped_columns() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let output_file = temp_dir.path().join("test_output.parquet");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            &input_file,
+            "-o", output_file.to_str().unwrap(),
+            "-t", "10.0",
+            "-d", "0.5",
+            "--output-precision", "0",
+            "--drop-columns", "mass,velocity",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let reader = newtonian_solar_system::reader::SimulationReader::open(&output_file)
+        .expect("Failed to open output file");
+    assert!(!reader.has_velocities());
+    let frames: Vec<_> = reader.collect::<Result<_, _>>().expect("Failed to read frames");
+    let last = &frames.last().unwrap().bodies[1];
+    // Masses come back from the metadata, positions are whole meters.
+    assert_eq!(last.mass, 5.0e23);
+    assert_eq!(last.position.y, last.position.y.round());
+    assert!(last.position.y > 0.0);
+}
+
+#[test]
+fn test_several_outputs() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let full = temp_dir.path().join("full.parquet");
+    let plot = temp_dir.path().join("plot.csv");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            &input_file,
+            "-o", full.to_str().unwrap(),
+            "-o", &format!("{},every=2,precision=f32,drop=velocity", plot.to_str().unwrap()),
+            "-t", "10.0",
+            "-d", "0.5",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let times = |path: &Path| {
+        let frames: Vec<_> = newtonian_solar_system::reader::SimulationReader::open(path)
+            .expect("Failed to open output file")
+            .collect::<Result<_, _>>()
+            .expect("Failed to read frames");
+        frames.iter().map(|frame| frame.time).collect::<Vec<f64>>()
+    };
+    let full_times = times(&full);
+    assert!(full_times.len() > 2);
+    // The CSV keeps every other frame.
+    let every_other: Vec<f64> = full_times.iter().step_by(2).copied().collect();
+    assert_eq!(times(&plot), every_other);
+    assert!(!newtonian_solar_system::reader::SimulationReader::open(&plot).unwrap().has_velocities());
+}
+
+#[test]
+fn test_pair_quantities() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let output_file = temp_dir.path().join("test_output.parquet");
+    let pairs_file = temp_dir.path().join("pairs.csv");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            &input_file,
+            "-o", output_file.to_str().unwrap(),
+            "-t", "10.0",
+            "-d", "0.5",
+            "-r", "5",
+            "--pair", "TestBody1,TestBody2",
+            "--pairs-output", pairs_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let table = fs::read_to_string(&pairs_file).expect("Failed to read pairs table");
+    let rows: Vec<Vec<&str>> = table.lines().map(|line| line.split(',').collect()).collect();
+    assert_eq!(rows[0], ["time", "body_a", "body_b", "distance", "relative_speed", "specific_energy"]);
+    assert!(rows.len() >= 3);
+    assert_eq!(rows[1][1..3], ["\"TestBody1\"", "\"TestBody2\""]);
+    assert_eq!(rows[1][3].parse::<f64>().unwrap(), 1.0e6);
+    assert_eq!(rows[1][4].parse::<f64>().unwrap(), 1000.0);
+}
+
+#[test]
+fn test_periodic_box_keeps_bodies_inside() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let output_file = temp_dir.path().join("test_output.parquet");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            &input_file,
+            "-o", output_file.to_str().unwrap(),
+            "-t", "10.0",
+            "-d", "0.5",
+            "--periodic-box", "2e6",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let frames: Vec<_> = newtonian_solar_system::reader::SimulationReader::open(&output_file)
+        .expect("Failed to open output file")
+        .collect::<Result<_, _>>()
+        .expect("Failed to read frames");
+    // TestBody1 is pulled towards -x, through the face at the origin.
+    let last = &frames.last().unwrap().bodies[0];
+    assert!(last.position.x > 1.9e6 && last.position.x < 2e6, "{:?}", last.position);
+    for body in frames.iter().flat_map(|frame| &frame.bodies) {
+        for x in [body.position.x, body.position.y, body.position.z] {
+            assert!((0.0..2e6).contains(&x), "{} left the box", body.name);
+        }
+    }
+}
+
+#[test]
+fn test_sph_pressure_pushes_gas_apart() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let output_file = temp_dir.path().join("test_output.parquet");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            &input_file,
+            "-o", output_file.to_str().unwrap(),
+            "-g", "0",
+            "-t", "10.0",
+            "-d", "0.5",
+            "--sph-smoothing", "1e6",
+            "--sound-speed", "1e9",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let frames: Vec<_> = newtonian_solar_system::reader::SimulationReader::open(&output_file)
+        .expect("Failed to open output file")
+        .collect::<Result<_, _>>()
+        .expect("Failed to read frames");
+    let last = &frames.last().unwrap().bodies;
+    // Without gravity, only the pressure moves the body at rest, away from the other.
+    assert!(last[0].position.x < 0.0, "{:?}", last[0].position);
+}
+
+#[test]
+fn test_irradiated_temperature_is_recorded() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("irradiated.json");
+    let output_file = temp_dir.path().join("irradiated.csv");
+    fs::write(&input_file, r#"[
+        {"name": "Sun", "mass": 1.989e30, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Earth", "mass": 5.972e24, "position": {"x": 1.496e11, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 29780.0, "z": 0.0}, "temperature": 1000.0}
+    ]"#).expect("Failed to write scenario");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            input_file.to_str().unwrap(),
+            "-o", output_file.to_str().unwrap(),
+            "-t", "100",
+            "-d", "1",
+            "--record-count", "2",
+            "--thermal-relaxation", "5",
+            "--luminosity", "Sun=3.828e26",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let csv = fs::read_to_string(&output_file).expect("Failed to read output");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert!(lines[0].ends_with(",temperature"), "{}", lines[0]);
+    // The Sun has no temperature; the Earth cooled to about 278 K.
+    assert!(lines[3].ends_with(','), "{}", lines[3]);
+    let earth: f64 = lines[4].rsplit(',').next().unwrap().parse().unwrap();
+    assert!((earth - 278.3).abs() < 0.5, "{}", earth);
+}
+
+#[test]
+fn test_tree_code_options() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let output_file = temp_dir.path().join("test_output.parquet");
+    let run = |order: &str| {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                &input_file,
+                "-o", output_file.to_str().unwrap(),
+                "-t", "1.0",
+                "-d", "0.1",
+                "--opening-angle", "0.5",
+                "--multipole-order", order,
+            ])
+            .output()
+            .expect("Failed to execute CLI")
+    };
+
+    let output = run("monopole");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = run("1");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown multipole order"));
+}
+
+#[test]
+fn test_benchmark_reports_tree_phases() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "benchmark", &input_file, "--repeat", "2", "--direct"])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("2 bodies"), "{}", stdout);
+    for phase in ["sort", "build", "traverse", "total", "direct"] {
+        assert!(stdout.lines().any(|line| line.starts_with(phase)), "{}", stdout);
+    }
+}
+
+#[test]
+fn test_run_batch() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let scenarios = temp_dir.path().join("scenarios");
+    fs::create_dir(&scenarios).expect("Failed to create scenarios directory");
+    let input_file = create_test_input_file(&temp_dir);
+    fs::copy(&input_file, scenarios.join("first.json")).expect("Failed to copy scenario");
+    fs::copy(&input_file, scenarios.join("second.json")).expect("Failed to copy scenario");
+    let output_dir = temp_dir.path().join("results");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "run-batch",
+            scenarios.to_str().unwrap(),
+            "--output-dir", output_dir.to_str().unwrap(),
+            "--jobs", "2",
+            "-t", "1.0",
+            "-d", "0.1"
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output_dir.join("first.parquet").exists(), "First output was not created");
+    assert!(output_dir.join("second.parquet").exists(), "Second output was not created");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("first.json") && stdout.contains("second.json"),
+        "Summary should list every scenario: {}", stdout);
+}
+
+#[test]
+fn test_max_memory_fails_fast() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let output_file = temp_dir.path().join("test_output.parquet");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            &input_file,
+            "-o", output_file.to_str().unwrap(),
+            "-t", "1.0",
+            "-d", "0.1",
+            "--max-memory", "100B"
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(!output.status.success(), "CLI should fail when the state exceeds --max-memory");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--max-memory"),
+        "Error message should mention the memory cap: {}", stderr);
+}
+
+#[test]
+fn test_convert_to_rebound_and_back() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let recording = temp_dir.path().join("recording.parquet");
+    let snapshot = temp_dir.path().join("snapshot.bin");
+    let scenario = temp_dir.path().join("scenario.json");
+
+    let run = |args: &[&str]| {
+        let output = Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .current_dir(".")
+            .output()
+            .expect("Failed to execute CLI");
+        assert!(output.status.success(),
+            "CLI failed with stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+    run(&[&input_file, "-o", recording.to_str().unwrap(), "-t", "1.0", "-d", "0.1"]);
+    run(&["convert", recording.to_str().unwrap(), snapshot.to_str().unwrap()]);
+    run(&["convert", snapshot.to_str().unwrap(), scenario.to_str().unwrap()]);
+
+    // The REBOUND snapshot can seed a new run directly.
+    run(&[snapshot.to_str().unwrap(), "-o", recording.to_str().unwrap(), "-t", "1.0", "-d", "0.1"]);
+
+    let bodies: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&scenario).expect("Failed to read scenario")).unwrap();
+    assert_eq!(bodies.as_array().unwrap().len(), 2);
+    assert_eq!(bodies[1]["mass"], 5.0e23);
+}
+
+#[test]
+fn test_snapshot_checkpoints() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let checkpoint = temp_dir.path().join("checkpoint.msgpack");
+    let recording = temp_dir.path().join("recording.parquet");
+    let scenario = temp_dir.path().join("scenario.json");
+
+    let run = |args: &[&str]| {
+        let output = Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .output()
+            .expect("Failed to execute CLI");
+        assert!(output.status.success(),
+            "CLI failed with stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    // The checkpoint keeps the last recorded frame.
+    run(&[&input_file, "-o", recording.to_str().unwrap(), "-o", checkpoint.to_str().unwrap(), "-t", "1.0", "-d", "0.1"]);
+    let snapshot = newtonian_solar_system::snapshot::read(&checkpoint).expect("Failed to read snapshot");
+    let last = newtonian_solar_system::reader::SimulationReader::open(&recording)
+        .expect("Failed to open output file")
+        .last()
+        .expect("No frames")
+        .expect("Failed to read frame");
+    assert_eq!(snapshot.time, last.time);
+    assert_eq!(snapshot.gravity, Some(6.67430e-11));
+    assert_eq!(snapshot.bodies[1].position.to_array(), last.bodies[1].position.to_array());
+
+    // And seeds new runs, which say they start over at t=0, and conversions.
+    let stderr = run(&[checkpoint.to_str().unwrap(), "-o", recording.to_str().unwrap(), "-t", "1.0", "-d", "0.1"]);
+    assert!(stderr.contains("warning: ") && stderr.contains("counts its time from 0"), "{}", stderr);
+    run(&["convert", checkpoint.to_str().unwrap(), scenario.to_str().unwrap()]);
+    let bodies: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&scenario).expect("Failed to read scenario")).unwrap();
+    assert_eq!(bodies[1]["position"]["x"], last.bodies[1].position.x);
+}
+
+#[test]
+fn test_impact_probability_shards() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("approach.json");
+    fs::write(&input_file, r#"[
+        {"name": "Target", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Rock", "mass": 1.0, "position": {"x": 10.0, "y": 0.5, "z": 0.0}, "velocity": {"x": -2.0, "y": 0.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+    let template = temp_dir.path().join("impacts-{shard}.csv");
+
+    let run = |output: &str, shard: &str| {
+        Command::new("cargo")
+            .args([
+                "run", "--", "analyze", "impact-probability",
+                input_file.to_str().unwrap(),
+                "--body", "Rock",
+                "--target", "Target",
+                "--radius", "1",
+                "--sigma-position", "3",
+                "--sigma-velocity", "0",
+                "--realizations", "20",
+                "--shard-count", "2",
+                "--shard-index", shard,
+                "-g", "0",
+                "-t", "10",
+                "-d", "0.05",
+                "-o", output,
+            ])
+            .output()
+            .expect("Failed to execute CLI")
+    };
+    for shard in ["0", "1"] {
+        let output = run(template.to_str().unwrap(), shard);
+        assert!(output.status.success(),
+            "CLI failed with stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains(&format!("of 10 realizations (shard {} of 2) hit Target", shard)), "{}", stdout);
+
+        // Each shard keeps its own file, numbered by realization.
+        let outcomes = fs::read_to_string(temp_dir.path().join(format!("impacts-{}.csv", shard)))
+            .expect("Failed to read the outcomes");
+        let numbers: Vec<usize> = outcomes
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().parse().unwrap())
+            .collect();
+        let first: usize = shard.parse().unwrap();
+        assert_eq!(numbers, (first..20).step_by(2).collect::<Vec<_>>());
+    }
+
+    // Shards writing to the same file would overwrite each other.
+    let output = run(temp_dir.path().join("impacts.csv").to_str().unwrap(), "0");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("{shard}"));
+}
+
+#[test]
+fn test_analyze_porkchop() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("planets.json");
+    let output_file = temp_dir.path().join("porkchop.csv");
+    // Circular orbits of radius 1 and 1.5 around a unit gravitational parameter.
+    fs::write(&input_file, r#"[
+        {"name": "Sun", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Earth", "mass": 0.0, "position": {"x": 1.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 1.0, "z": 0.0}},
+        {"name": "Mars", "mass": 0.0, "position": {"x": 0.0, "y": 1.5, "z": 0.0}, "velocity": {"x": -0.816496580927726, "y": 0.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "analyze", "porkchop",
+            input_file.to_str().unwrap(),
+            "--from", "Earth",
+            "--to", "Mars",
+            "--departure-end", "2",
+            "--arrival-start", "3",
+            "--arrival-end", "6",
+            "--steps", "4",
+            "-g", "1",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let csv = fs::read_to_string(&output_file).expect("Failed to read porkchop data");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "departure,arrival,time_of_flight,c3,v_inf_departure,v_inf_arrival,delta_v");
+    assert_eq!(lines.len(), 1 + 4 * 4);
+}
+
+#[test]
+fn test_analyze_frequencies() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("planet.json");
+    let recording = temp_dir.path().join("planet.parquet");
+    let output_file = temp_dir.path().join("frequencies.csv");
+    // Circular orbit of period 2π.
+    fs::write(&input_file, r#"[
+        {"name": "Sun", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Planet", "mass": 1e-12, "position": {"x": 1.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 1.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            input_file.to_str().unwrap(),
+            "-o", recording.to_str().unwrap(),
+            "-g", "1",
+            "-t", "63",
+            "-d", "0.01",
+            "-i", "rk4",
+            "--record-interval", "0.1",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "analyze", "frequencies",
+            recording.to_str().unwrap(),
+            "--lines", "1",
+            "-g", "1",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let csv = fs::read_to_string(&output_file).expect("Failed to read frequencies");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "body,signal,frequency,period,arcsec_per_year,amplitude");
+    let mean_motion = lines
+        .iter()
+        .find(|line| line.starts_with("\"Planet\",lambda,"))
+        .expect("Missing mean longitude line");
+    let period: f64 = mean_motion.split(',').nth(3).unwrap().parse().unwrap();
+    assert!((period - 2.0 * std::f64::consts::PI).abs() < 0.01, "{}", mean_motion);
+}
+
+#[test]
+fn test_analyze_moid() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("planets.json");
+    let recording = temp_dir.path().join("planets.parquet");
+    let output_file = temp_dir.path().join("moid.csv");
+    // Circular orbits of radius 1 and 1.5: their MOID stays 0.5.
+    fs::write(&input_file, r#"[
+        {"name": "Sun", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Earth", "mass": 1e-12, "position": {"x": 1.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 1.0, "z": 0.0}},
+        {"name": "Mars", "mass": 1e-12, "position": {"x": 0.0, "y": 1.5, "z": 0.0}, "velocity": {"x": -0.816496580927726, "y": 0.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            input_file.to_str().unwrap(),
+            "-o", recording.to_str().unwrap(),
+            "-g", "1",
+            "-t", "2",
+            "-d", "0.01",
+            "-i", "rk4",
+            "--record-interval", "1",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "analyze", "moid",
+            recording.to_str().unwrap(),
+            "--against", "Earth",
+            "-g", "1",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let csv = fs::read_to_string(&output_file).expect("Failed to read MOID table");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "time,body_a,body_b,moid");
+    assert_eq!(lines.len(), 1 + 3);
+    for line in &lines[1..] {
+        let moid: f64 = line.split(',').nth(3).unwrap().parse().unwrap();
+        assert!((moid - 0.5).abs() < 1e-4, "{}", line);
+    }
+}
+
+#[test]
+fn test_generate_tidal_disruption() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let output_file = temp_dir.path().join("disruption.json");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "generate", "tidal-disruption",
+            "--particles", "20",
+            "--ring-particles", "5",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let scenario = fs::read_to_string(&output_file).expect("Failed to read scenario");
+    let bodies: serde_json::Value = serde_json::from_str(&scenario).expect("Invalid scenario");
+    let bodies = bodies.as_array().unwrap();
+    assert_eq!(bodies.len(), 1 + 20 + 5);
+    assert_eq!(bodies[0]["tags"]["group"], "planet");
+    assert_eq!(bodies[1]["tags"]["group"], "rubble");
+    assert_eq!(bodies[25]["tags"]["group"], "ring");
+}
+
+#[test]
+fn test_tidal_stream() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let scenario = temp_dir.path().join("stream.json");
+    let recording = temp_dir.path().join("stream.parquet");
+    let output_file = temp_dir.path().join("stream.csv");
+
+    // A loose cluster in units where G = 1, falling towards the center of the halo.
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "generate", "stream",
+            "-g", "1",
+            "--cluster-mass", "1e-3",
+            "--scale-radius", "0.1",
+            "--stars", "40",
+            "--distance", "10",
+            "--halo-speed", "1",
+            "--halo-core", "0",
+            "-o", scenario.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            scenario.to_str().unwrap(),
+            "-o", recording.to_str().unwrap(),
+            "-g", "1",
+            "-t", "40",
+            "-d", "0.01",
+            "-i", "rk4",
+            "--record-count", "5",
+            "--halo-speed", "1",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "analyze", "stream",
+            recording.to_str().unwrap(),
+            "--tag", "group=cluster",
+            "--halo-speed", "1",
+            "-g", "1",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let csv = fs::read_to_string(&output_file).expect("Failed to read stream analysis");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "time,bound,stripped,bound_mass,tidal_radius,stream_length");
+    let rows: Vec<Vec<f64>> = lines[1..]
+        .iter()
+        .map(|line| line.split(',').map(|value| value.parse().unwrap()).collect())
+        .collect();
+    assert_eq!(rows.len(), 5);
+    assert!(rows.iter().all(|row| row[1] + row[2] == 40.0));
+    // Most stars start bound, and the tides strip them into a growing stream.
+    let (first, last) = (&rows[0], &rows[4]);
+    assert!(first[1] >= 30.0, "{:?}", first);
+    assert!(last[2] > first[2] + 10.0, "{:?}", rows);
+    assert!(last[5] > 5.0 * first[5], "{:?}", rows);
+}
+
+#[test]
+fn test_cluster_statistics() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let scenario = temp_dir.path().join("cluster.json");
+    let recording = temp_dir.path().join("cluster.parquet");
+    let output_file = temp_dir.path().join("cluster.csv");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "generate", "stream",
+            "-g", "1",
+            "--cluster-mass", "1e-3",
+            "--scale-radius", "0.1",
+            "--stars", "100",
+            "--distance", "10",
+            "--halo-speed", "1",
+            "-o", scenario.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // The cluster alone, without the halo.
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            scenario.to_str().unwrap(),
+            "-o", recording.to_str().unwrap(),
+            "-g", "1",
+            "-t", "2",
+            "-d", "0.01",
+            "-i", "rk4",
+            "--record-count", "3",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "analyze", "cluster",
+            recording.to_str().unwrap(),
+            "--tag", "group=cluster",
+            "-g", "1",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let csv = fs::read_to_string(&output_file).expect("Failed to read cluster statistics");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "time,bound,bound_fraction,lagrangian_10,lagrangian_50,lagrangian_90,core_radius,velocity_dispersion"
+    );
+    let rows: Vec<Vec<f64>> = lines[1..]
+        .iter()
+        .map(|line| line.split(',').map(|value| value.parse().unwrap()).collect())
+        .collect();
+    assert_eq!(rows.len(), 3);
+    for row in &rows {
+        assert!(row[1] >= 90.0 && row[2] > 0.9, "{:?}", row);
+        assert!(row[3] < row[4] && row[4] < row[5], "{:?}", row);
+        // Half the mass of a Plummer sphere is within 1.3 scale radii.
+        assert!(row[4] > 0.08 && row[4] < 0.2, "{:?}", row);
+        assert!(row[6] > 0.0 && row[7] > 0.0, "{:?}", row);
+    }
+}
+
+#[test]
+fn test_generate_kuiper_belt() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let scenario = temp_dir.path().join("sun.json");
+    fs::write(&scenario, r#"[{"name": "Sun", "mass": 1.989e30,
+        "position": {"x": 0, "y": 0, "z": 0}, "velocity": {"x": 0, "y": 0, "z": 0}}]"#)
+        .expect("Failed to write scenario");
+    let output_file = temp_dir.path().join("belt.json");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "generate", "belt",
+            scenario.to_str().unwrap(),
+            "--population", "kuiper",
+            "--count", "30",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bodies: serde_json::Value = serde_json::from_str(&fs::read_to_string(&output_file).unwrap())
+        .expect("Invalid scenario");
+    let bodies = bodies.as_array().unwrap();
+    assert_eq!(bodies.len(), 1 + 30);
+    assert_eq!(bodies[0]["name"], "Sun");
+    assert_eq!(bodies[30]["name"], "kbo 30");
+    assert_eq!(bodies[30]["tags"]["group"], "kbo");
+}
+
+#[test]
+fn test_generate_circular_orbit() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let output_file = temp_dir.path().join("circular.json");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "generate", "circular",
+            &input_file,
+            "--orbit", "TestBody2=TestBody1",
+            "-g", "1e-10",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bodies: serde_json::Value = serde_json::from_str(&fs::read_to_string(&output_file).unwrap())
+        .expect("Invalid scenario");
+    // v = sqrt(G (M + m) / r) = sqrt(1e-10 * 1.5e24 / 1e6)
+    let speed = bodies[1]["velocity"]["y"].as_f64().unwrap();
+    assert!((speed - 12247.449).abs() < 1e-3, "{}", speed);
+}
+
+#[test]
+fn test_analyze_hohmann_schedule() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let schedule = temp_dir.path().join("burns.csv");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "analyze", "hohmann",
+            &input_file,
+            "--from", "TestBody2",
+            "--to-radius", "2e6",
+            "--schedule", schedule.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("Hohmann transfer about TestBody1"), "{}", stdout);
+    let burns = fs::read_to_string(&schedule).expect("Failed to read schedule");
+    let lines: Vec<&str> = burns.lines().collect();
+    assert_eq!(lines[0], "time,body,delta_v,delta_vx,delta_vy,delta_vz");
+    assert_eq!(lines.len(), 3);
+    // The first burn speeds the body up along its motion (+y).
+    let first: Vec<&str> = lines[1].split(',').collect();
+    assert_eq!(first[0], "0");
+    assert!(first[4].parse::<f64>().unwrap() > 0.0, "{}", burns);
+}
+
+#[test]
+fn test_analyze_flyby_writes_the_encounter() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let scenario = temp_dir.path().join("flyby.json");
+    fs::write(&scenario, r#"[
+        {"name": "Planet", "mass": 6e24, "position": {"x": 0, "y": 0, "z": 0}, "velocity": {"x": 0, "y": 0, "z": 0}},
+        {"name": "Probe", "mass": 1000, "position": {"x": -1e9, "y": 0, "z": 0}, "velocity": {"x": 5000, "y": 0, "z": 0}}
+    ]"#).expect("Failed to write scenario");
+    let encounter = temp_dir.path().join("encounter.json");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "analyze", "flyby",
+            scenario.to_str().unwrap(),
+            "--body", "Probe",
+            "--planet", "Planet",
+            "--periapsis", "7e6",
+            "--start-distance", "1e9",
+            "-o", encounter.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("v-infinity 5.000000e3 m/s turned by"));
+    let bodies: serde_json::Value = serde_json::from_str(&fs::read_to_string(&encounter).unwrap())
+        .expect("Invalid scenario");
+    let position = &bodies[1]["position"];
+    let distance = [&position["x"], &position["y"], &position["z"]]
+        .iter()
+        .map(|c| c.as_f64().unwrap().powi(2))
+        .sum::<f64>()
+        .sqrt();
+    assert!((distance - 1e9).abs() < 1.0, "{}", distance);
+}
+
+#[test]
+fn test_target_final_position() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("probe.json");
+    let output_file = temp_dir.path().join("targeted.json");
+    fs::write(&input_file, r#"[
+        {"name": "Sun", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Probe", "mass": 1e-12, "position": {"x": 1.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 1.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "target",
+            input_file.to_str().unwrap(),
+            "--body", "Probe",
+            "--vary", "vx,vy",
+            "--final-position", "0,1.5,0",
+            "--relative-to", "Sun",
+            "--tolerance", "1e-6",
+            "-g", "1",
+            "-t", "2",
+            "-d", "0.01",
+            "-i", "rk4",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bodies: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&output_file).expect("Failed to read scenario")).unwrap();
+    assert_ne!(bodies[1]["velocity"]["x"], 0.0);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Probe: position moved"));
+}
+
+#[test]
+fn test_od_fits_a_circular_orbit() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("guess.json");
+    let observations = temp_dir.path().join("observations.csv");
+    let output_file = temp_dir.path().join("fitted.json");
+    let residuals = temp_dir.path().join("residuals.csv");
+    fs::write(&input_file, r#"[
+        {"name": "Sun", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Probe", "mass": 1e-12, "position": {"x": 1.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 1.02, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+    // The unit circle, seen from the Sun.
+    let mut table = String::from("time,observer,target,kind,value,sigma\n");
+    for k in 1..=6 {
+        let time = 0.5 * k as f64;
+        table += &format!("{},Sun,Probe,range,1,1e-6\n", time);
+        table += &format!("{},Sun,Probe,ra,{},1e-4\n", time, time.to_degrees());
+        table += &format!("{},Sun,Probe,dec,0,1e-4\n", time);
+    }
+    fs::write(&observations, table).expect("Failed to write observations");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "od",
+            input_file.to_str().unwrap(),
+            "--body", "Probe",
+            "--observations", observations.to_str().unwrap(),
+            "--residuals", residuals.to_str().unwrap(),
+            "-g", "1",
+            "-d", "0.01",
+            "-i", "rk4",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bodies: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&output_file).expect("Failed to read scenario")).unwrap();
+    assert!((bodies[1]["velocity"]["y"].as_f64().unwrap() - 1.0).abs() < 1e-5);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Probe: position moved"));
+    let csv = fs::read_to_string(&residuals).expect("Failed to read residuals");
+    assert_eq!(csv.lines().count(), 1 + 18);
+}
+
+#[test]
+fn test_observe_then_fit_the_orbit() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("truth.json");
+    let recording = temp_dir.path().join("truth.parquet");
+    let observations = temp_dir.path().join("observations.csv");
+    let guess_file = temp_dir.path().join("guess.json");
+    let output_file = temp_dir.path().join("fitted.json");
+    let scenario = |vy: f64| format!(r#"[
+        {{"name": "Sun", "mass": 1.0, "position": {{"x": 0.0, "y": 0.0, "z": 0.0}}, "velocity": {{"x": 0.0, "y": 0.0, "z": 0.0}}}},
+        {{"name": "Probe", "mass": 1e-12, "position": {{"x": 1.0, "y": 0.0, "z": 0.0}}, "velocity": {{"x": 0.0, "y": {}, "z": 0.1}}}}
+    ]"#, vy);
+    fs::write(&input_file, scenario(1.0)).expect("Failed to write scenario");
+    fs::write(&guess_file, scenario(0.97)).expect("Failed to write scenario");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            input_file.to_str().unwrap(),
+            "-o", recording.to_str().unwrap(),
+            "-g", "1",
+            "-t", "3",
+            "-d", "0.01",
+            "-i", "rk4",
+            "--record-interval", "0.5",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "analyze", "observe",
+            recording.to_str().unwrap(),
+            "--observer", "Sun",
+            "--target", "Probe",
+            "--sigma", "range=1e-6",
+            "-o", observations.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let csv = fs::read_to_string(&observations).expect("Failed to read observations");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "time,observer,target,kind,value,sigma");
+    assert_eq!(lines.len(), 1 + 7 * 4);
+    assert_eq!(lines[1], "0,\"Sun\",\"Probe\",range,1,0.000001");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "od",
+            guess_file.to_str().unwrap(),
+            "--body", "Probe",
+            "--observations", observations.to_str().unwrap(),
+            "-g", "1",
+            "-d", "0.01",
+            "-i", "rk4",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bodies: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&output_file).expect("Failed to read scenario")).unwrap();
+    assert!((bodies[1]["velocity"]["y"].as_f64().unwrap() - 1.0).abs() < 1e-5);
+}
+
+#[test]
+fn test_apparent_positions() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("bodies.json");
+    let recording = temp_dir.path().join("orbits.parquet");
+    let output_file = temp_dir.path().join("apparent.csv");
+    fs::write(&input_file, r#"[
+        {"name": "Sun", "mass": 1.989e30, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Earth", "mass": 5.972e24, "position": {"x": 1.496e11, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 29780.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            input_file.to_str().unwrap(),
+            "-o", recording.to_str().unwrap(),
+            "-t", "60",
+            "-d", "60",
+            "--record-interval", "60",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "analyze", "apparent",
+            recording.to_str().unwrap(),
+            "--observer", "Earth",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let csv = fs::read_to_string(&output_file).expect("Failed to read apparent positions");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "time,name,x,y,z,distance,light_time,shift");
+    assert_eq!(lines.len(), 3);
+    let values: Vec<&str> = lines[1].split(',').collect();
+    assert_eq!(values[1], "\"Sun\"");
+    // The Sun is seen 499 s late and about 20.5 arcseconds ahead of the Earth's motion.
+    let light_time: f64 = values[6].parse().unwrap();
+    let shift: f64 = values[7].parse().unwrap();
+    assert!((light_time - 499.0).abs() < 0.5, "{}", light_time);
+    assert!((shift - 20.5).abs() < 0.2, "{}", shift);
+    assert!(values[3].parse::<f64>().unwrap() > 0.0);
+}
+
+#[test]
+fn test_analyze_eclipses() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("planets.json");
+    let recording = temp_dir.path().join("planets.parquet");
+    let output_file = temp_dir.path().join("eclipses.csv");
+    // Circular orbits of radius 1 and 0.5, lined up at the start: the synodic
+    // period is 2π/(2^1.5 - 1).
+    fs::write(&input_file, r#"[
+        {"name": "Sun", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Earth", "mass": 1e-12, "position": {"x": 1.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 1.0, "z": 0.0}},
+        {"name": "Venus", "mass": 1e-12, "position": {"x": 0.5, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 1.4142135623730951, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            input_file.to_str().unwrap(),
+            "-o", recording.to_str().unwrap(),
+            "-g", "1",
+            "-t", "4",
+            "-d", "0.01",
+            "-i", "rk4",
+            "--record-interval", "0.05",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "analyze", "eclipses",
+            recording.to_str().unwrap(),
+            "--observer", "Earth",
+            "--bodies", "Venus,Sun",
+            "--tolerance", "5",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let csv = fs::read_to_string(&output_file).expect("Failed to read alignments");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "start,end,closest,separation,front,behind");
+    assert_eq!(lines.len(), 1 + 3, "{}", csv);
+    let synodic = 2.0 * std::f64::consts::PI / (2f64.powf(1.5) - 1.0);
+    for (line, (closest, front)) in lines[1..].iter().zip([(0.0, "Venus"), (synodic / 2.0, "Sun"), (synodic, "Venus")]) {
+        let values: Vec<&str> = line.split(',').collect();
+        assert!((values[2].parse::<f64>().unwrap() - closest).abs() < 1e-2, "{}", line);
+        assert!(values[3].parse::<f64>().unwrap() < 1.0, "{}", line);
+        assert_eq!(values[4], format!("\"{}\"", front));
+    }
+}
+
+#[test]
+fn test_analyze_ground_track() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("planet.json");
+    let recording = temp_dir.path().join("planet.parquet");
+    let output_file = temp_dir.path().join("ground_track.csv");
+    // A synchronous orbit stays over the same point of the equator.
+    fs::write(&input_file, r#"[
+        {"name": "Planet", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Satellite", "mass": 1e-12, "position": {"x": 1.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 1.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            input_file.to_str().unwrap(),
+            "-o", recording.to_str().unwrap(),
+            "-g", "1",
+            "-t", "6",
+            "-d", "0.01",
+            "-i", "rk4",
+            "--record-interval", "1",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "analyze", "ground-track",
+            recording.to_str().unwrap(),
+            "--rotation-period", "2*pi",
+            "--prime-meridian", "-40",
+            "--radius", "0.25",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let csv = fs::read_to_string(&output_file).expect("Failed to read ground track");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "time,name,latitude,longitude,altitude");
+    assert_eq!(lines.len(), 1 + 7);
+    for line in &lines[1..] {
+        let values: Vec<f64> = line.split(',').filter_map(|value| value.parse().ok()).collect();
+        assert!(values[1].abs() < 1e-6, "{}", line);
+        assert!((values[2] - 40.0).abs() < 1e-4, "{}", line);
+        assert!((values[3] - 0.75).abs() < 1e-6, "{}", line);
+    }
+}
+
+#[test]
+fn test_mass_transfer() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("binary.json");
+    let output_file = temp_dir.path().join("binary.csv");
+    let events_file = temp_dir.path().join("mass_transfer.csv");
+    // An eccentric binary whose donor only overflows its lobe around periapsis.
+    fs::write(&input_file, r#"[
+        {"name": "Donor", "mass": 2.0, "position": {"x": 0.46666666666666667, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.4317, "z": 0.0}},
+        {"name": "Accretor", "mass": 1.0, "position": {"x": -0.9333333333333333, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": -0.8633, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            input_file.to_str().unwrap(),
+            "-o", output_file.to_str().unwrap(),
+            "-g", "1",
+            "-t", "9",
+            "-d", "0.001",
+            "-i", "rk4",
+            "--record-count", "91",
+            "--mass-transfer", "Donor=Accretor",
+            "--donor-radius", "0.5",
+            "--transfer-rate", "1",
+            "--transfer-events", events_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let csv = fs::read_to_string(&events_file).expect("Failed to read mass transfer events");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "time,event,donor_mass,accretor_mass,separation,roche_lobe,rate");
+    let events: Vec<&str> = lines[1..].iter().map(|line| line.split(',').nth(1).unwrap()).collect();
+    assert_eq!(events, ["start", "stop", "start", "stop"]);
+
+    // The donor's recorded mass only goes down, and the pair's total stays.
+    let csv = fs::read_to_string(&output_file).expect("Failed to read output");
+    let rows: Vec<Vec<&str>> = csv.lines().skip(1).map(|line| line.split(',').collect()).collect();
+    let masses: Vec<f64> = rows.iter().filter(|row| row[1] == "\"Donor\"").map(|row| row[2].parse().unwrap()).collect();
+    assert_eq!(masses.len(), 91);
+    assert!(masses.windows(2).all(|pair| pair[1] <= pair[0]));
+    assert!(masses[90] < 1.99, "{}", masses[90]);
+    for frame in rows.chunks(2) {
+        let total: f64 = frame.iter().map(|row| row[2].parse::<f64>().unwrap()).sum();
+        assert!((total - 3.0).abs() < 1e-12, "{:?}", frame);
+    }
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            input_file.to_str().unwrap(),
+            "-o", &format!("{},drop=mass", temp_dir.path().join("binary.parquet").display()),
+            "-t", "1",
+            "--mass-transfer", "Donor=Accretor",
+            "--donor-radius", "0.5",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("mass column"));
+}
+
+#[test]
+fn test_group_integrator() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("probes.json");
+    // Two massless probes on opposite sides of the same circular orbit.
+    fs::write(&input_file, r#"[
+        {"name": "Star", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Spacecraft", "mass": 0.0, "position": {"x": 1.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 1.0, "z": 0.0}, "tags": {"category": "spacecraft"}},
+        {"name": "Asteroid", "mass": 0.0, "position": {"x": -1.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": -1.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+
+    let run = |name: &str, extra: &[&str]| {
+        let output_file = temp_dir.path().join(name);
+        let output = Command::new("cargo")
+            .args(["run", "--", input_file.to_str().unwrap(), "-o", output_file.to_str().unwrap()])
+            .args(["-g", "1", "-t", "10", "-d", "0.01", "--record-count", "11"])
+            .args(extra)
+            .output()
+            .expect("Failed to execute CLI");
+        assert!(output.status.success(),
+            "CLI failed with stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let csv = fs::read_to_string(&output_file).expect("Failed to read output");
+        let rows: Vec<Vec<f64>> = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').skip(3).map(|value| value.parse().unwrap()).collect())
+            .collect();
+        rows
+    };
+    let mixed = run("mixed.csv", &["-i", "euler", "--group-integrator", "category=spacecraft=rk4"]);
+    let rk4 = run("rk4.csv", &["-i", "rk4"]);
+    let euler = run("euler.csv", &["-i", "euler"]);
+
+    // Rows go Star, Spacecraft, Asteroid at each recorded time.
+    assert_eq!(mixed.len(), 33);
+    let close = |a: &[f64], b: &[f64]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-9);
+    for (i, row) in mixed.iter().enumerate() {
+        let expected = if i % 3 == 1 { &rk4[i] } else { &euler[i] };
+        assert!(close(row, expected), "row {}: {:?} != {:?}", i, row, expected);
+    }
+    assert!(!close(&rk4[31], &euler[31]));
+
+    let output = Command::new("cargo")
+        .args(["run", "--", input_file.to_str().unwrap(), "-t", "1", "--group-integrator", "category=rk4"])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_regularization() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("triple.json");
+    let output_file = temp_dir.path().join("triple.csv");
+    // A binary 0.01 apart, with a period of about 0.0044, and a distant third star.
+    fs::write(&input_file, r#"[
+        {"name": "A", "mass": 1.0, "position": {"x": 0.005, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 7.0710678118654755, "z": 0.0}},
+        {"name": "B", "mass": 1.0, "position": {"x": -0.005, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": -7.0710678118654755, "z": 0.0}},
+        {"name": "C", "mass": 1e-3, "position": {"x": 10.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.4472, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            input_file.to_str().unwrap(),
+            "-o", output_file.to_str().unwrap(),
+            "-g", "1",
+            "-t", "1",
+            "-d", "0.01",
+            "-i", "rk4",
+            "--record-count", "11",
+            "--regularize", "0.1",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // The binary keeps its separation across steps spanning two of its orbits.
+    let csv = fs::read_to_string(&output_file).expect("Failed to read output");
+    let rows: Vec<Vec<f64>> = csv
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').skip(3).take(3).map(|value| value.parse().unwrap()).collect())
+        .collect();
+    assert_eq!(rows.len(), 33);
+    for frame in rows.chunks(3) {
+        let separation = (0..3).map(|k| (frame[0][k] - frame[1][k]).powi(2)).sum::<f64>().sqrt();
+        assert!((separation - 0.01).abs() < 1e-6, "{:?}", frame);
+    }
+
+    let output = Command::new("cargo")
+        .args(["run", "--", input_file.to_str().unwrap(), "-t", "1", "-i", "patched-conics", "--regularize", "0.1"])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_sphere_of_influence_events() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("flyby.json");
+    let output_file = temp_dir.path().join("flyby.parquet");
+    let events_file = temp_dir.path().join("soi.csv");
+    // The planet's sphere of influence has a radius of 10 (1e-3)^0.4 ≈ 0.63; the
+    // probe crosses it 0.3 from the planet.
+    fs::write(&input_file, r#"[
+        {"name": "Sun", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Planet", "mass": 1e-3, "position": {"x": 10.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.31622776601683794, "z": 0.0}},
+        {"name": "Probe", "mass": 1e-20, "position": {"x": 10.3, "y": -3.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 5.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            input_file.to_str().unwrap(),
+            "-o", output_file.to_str().unwrap(),
+            "-g", "1",
+            "-t", "1.5",
+            "-d", "0.001",
+            "-i", "rk4",
+            "--record-interval", "0.01",
+            "--soi-events", events_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let csv = fs::read_to_string(&events_file).expect("Failed to read events");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "time,name,from,to,x,y,z,vx,vy,vz");
+    assert_eq!(lines.len(), 3, "{}", csv);
+    for (line, (time, from, to)) in lines[1..].iter().zip([(0.522, "Sun", "Planet"), (0.759, "Planet", "Sun")]) {
+        let values: Vec<&str> = line.split(',').collect();
+        assert!((values[0].parse::<f64>().unwrap() - time).abs() < 0.02, "{}", line);
+        assert_eq!((values[1], values[2], values[3]), ("\"Probe\"", format!("\"{}\"", from).as_str(), format!("\"{}\"", to).as_str()));
+    }
+}
+
+#[test]
+fn test_stability_map() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("planet.json");
+    let output_file = temp_dir.path().join("map.csv");
+    fs::write(&input_file, r#"[
+        {"name": "Star", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Planet", "mass": 1e-3, "position": {"x": 1.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 1.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--", "map",
+            input_file.to_str().unwrap(),
+            "--central", "Star",
+            "--x", "a=0.5:1.1:3",
+            "--y", "e=0:0.2:2",
+            "--fix", "mean_anomaly=180",
+            "-g", "1",
+            "-t", "100",
+            "-d", "0.01",
+            "-i", "rk4",
+            "-o", output_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let csv = fs::read_to_string(&output_file).expect("Failed to read map");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "a,e,megno,fli");
+    assert_eq!(lines.len(), 1 + 3 * 2);
+    let cell = |line: &str| -> Vec<f64> { line.split(',').map(|value| value.parse().unwrap()).collect() };
+    let inner = cell(lines[1]);
+    assert_eq!((inner[0], inner[1]), (0.5, 0.0));
+    assert!((inner[2] - 2.0).abs() < 0.3, "{}", lines[1]);
+    // Eccentric orbits with a = 1.1 cross the planet's.
+    let crossing = cell(lines[6]);
+    assert_eq!((crossing[0], crossing[1]), (1.1, 0.2));
+    assert!(crossing[2] > 4.0, "{}", lines[6]);
+}
+
+#[test]
+fn test_uncertainty_ellipsoids() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let output_file = temp_dir.path().join("run.parquet");
+    let ellipsoids = temp_dir.path().join("ellipsoids.csv");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            &input_file,
+            "-o", output_file.to_str().unwrap(),
+            "-t", "1.0",
+            "-d", "0.1",
+            "--record-count", "3",
+            "--uncertain", "TestBody2=100,1",
+            "--uncertainty-output", ellipsoids.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let csv = fs::read_to_string(&ellipsoids).expect("Failed to read ellipsoids");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert!(lines[0].starts_with("time,name,x,y,z,cov_xx"));
+    assert_eq!(lines.len(), 1 + 3);
+    assert!(lines[3].starts_with("1,\"TestBody2\","));
+}
+
+#[test]
+fn test_state_transition_matrices() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let output_file = temp_dir.path().join("run.parquet");
+    let matrices = temp_dir.path().join("stm.csv");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            &input_file,
+            "-o", output_file.to_str().unwrap(),
+            "-t", "1.0",
+            "-d", "0.1",
+            "--record-count", "3",
+            "--stm", "TestBody2",
+            "--stm-output", matrices.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let csv = fs::read_to_string(&matrices).expect("Failed to read matrices");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert!(lines[0].starts_with("time,name,phi_11,phi_12"));
+    assert!(lines[0].ends_with("phi_66"));
+    assert_eq!(lines.len(), 1 + 3);
+    // Nothing has moved yet at the start.
+    let first: Vec<f64> = lines[1].split(',').skip(2).map(|v| v.parse().unwrap()).collect();
+    assert_eq!(first.len(), 36);
+    assert!((first[0] - 1.0).abs() < 1e-9 && first[1].abs() < 1e-9);
+}
+
+#[test]
+fn test_console_steers_the_run_and_not_its_shifted_copies() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use std::process::Stdio;
+
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("probe.json");
+    fs::write(&input_file, r#"[
+        {"name": "Probe", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 1.0, "y": 0.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+    let output_file = temp_dir.path().join("run.csv");
+    let matrices = temp_dir.path().join("stm.csv");
+
+    let mut run = Command::new(env!("CARGO_BIN_EXE_newtonian-solar-system"))
+        .args([input_file.to_str().unwrap(), "-o", output_file.to_str().unwrap(), "-g", "0", "-t", "10", "-d", "1"])
+        .args(["--stm", "Probe", "--stm-output", matrices.to_str().unwrap()])
+        .args(["--console", "127.0.0.1:0", "--console-paused"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start the run");
+    let mut stderr = BufReader::new(run.stderr.take().unwrap());
+    let mut line = String::new();
+    let address = loop {
+        line.clear();
+        assert!(stderr.read_line(&mut line).expect("Failed to read stderr") > 0, "the run ended without a console");
+        if let Some(address) = line.trim().strip_prefix("console listening on ") {
+            break address.to_string();
+        }
+    };
+    let draining = std::thread::spawn(move || std::io::copy(&mut stderr, &mut std::io::sink()));
+
+    let mut console = TcpStream::connect(address).expect("Failed to connect to the console");
+    console.write_all(b"impulse Probe 0 1 0\nresume\nquit\n").expect("Failed to send commands");
+    let replies: Vec<String> = BufReader::new(console).lines().map(Result::unwrap).collect();
+    assert!(run.wait().expect("Failed to wait for the run").success());
+    draining.join().unwrap().unwrap();
+
+    assert_eq!(replies.len(), 2, "{:?}", replies);
+    // The impulse went to the run itself, whatever the shifted runs did meanwhile.
+    let csv = fs::read_to_string(&output_file).expect("Failed to read output");
+    let last = csv.lines().last().expect("No rows");
+    assert!(last.starts_with("10,"), "{}", last);
+    assert!(last.ends_with(",10,10,0,1,1,0"), "{}", last);
+    // While the matrices come from runs the console left alone.
+    assert_eq!(fs::read_to_string(&matrices).expect("Failed to read matrices").lines().count(), 1 + 11);
+}
+
+#[test]
+fn test_worker_sums_a_share_of_the_bodies() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let output_file = temp_dir.path().join("test_output.csv");
+
+    // The binary itself rather than `cargo run`, so killing it stops the worker.
+    let mut worker = Command::new(env!("CARGO_BIN_EXE_newtonian-solar-system"))
+        .args(["worker", "--listen", "127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start worker");
+    let mut line = String::new();
+    BufReader::new(worker.stdout.take().unwrap()).read_line(&mut line).expect("Failed to read worker address");
+    let address = line.trim().strip_prefix("listening on ").expect("worker didn't report its address").to_string();
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            &input_file,
+            "-o", output_file.to_str().unwrap(),
+            "-t", "1.0",
+            "-d", "0.1",
+            "--worker", &address,
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+    worker.kill().expect("Failed to stop worker");
+    worker.wait().expect("Failed to wait for worker");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("failed"));
+    assert!(fs::read_to_string(&output_file).unwrap().contains("TestBody2"));
+}
+
+#[test]
+fn test_threads_and_parallel_efficiency() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let output_file = temp_dir.path().join("test_output.parquet");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            &input_file,
+            "-o", output_file.to_str().unwrap(),
+            "-t", "1.0",
+            "-d", "0.1",
+            "--threads", "2",
+            "--pin-threads",
+        ])
+        .output()
+        .expect("Failed to execute CLI");
+
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("on 2 threads)"));
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "benchmark", &input_file, "--repeat", "1", "--threads", "3"])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("2 bodies, 3 threads"));
+}
+
+#[test]
+fn test_registry_lists_and_shows_runs() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let output_file = temp_dir.path().join("test_output.parquet");
+    let registry = temp_dir.path().join("runs");
+
+    for dt in ["0.1", "0"] {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                &input_file,
+                "-o", output_file.to_str().unwrap(),
+                "-t", "1.0",
+                "-d", dt,
+                "--registry", registry.to_str().unwrap()
+            ])
+            .current_dir(".")
+            .output()
+            .expect("Failed to execute CLI");
+    }
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "runs", "list", "--registry", registry.to_str().unwrap()])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", stdout);
+    assert!(lines[1].trim_start().starts_with('1') && lines[1].contains("ok -> "), "{}", stdout);
+    assert!(lines[2].trim_start().starts_with('2') && lines[2].contains("failed: "), "{}", stdout);
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "runs", "show", "1", "--registry", registry.to_str().unwrap()])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let run: serde_json::Value = serde_json::from_slice(&output.stdout).expect("run should be JSON");
+    assert_eq!(run["manifest"]["dt"], 0.1);
+    assert!(run["summary"]["frames"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn test_initial_from_a_recorded_frame() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("probes.json");
+    fs::write(&input_file, r#"[
+        {"name": "Star", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Planet", "mass": 1e-3, "position": {"x": 1.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 1.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+    let full_parquet = temp_dir.path().join("full.parquet");
+
+    let run = |from: &[&str], name: &str, duration: &str, count: &str| {
+        let output_file = temp_dir.path().join(name);
+        let output = Command::new("cargo")
+            .args(["run", "--"])
+            .args(from)
+            .args(["-o", output_file.to_str().unwrap(), "-o", full_parquet.to_str().unwrap()])
+            .args(["-g", "1", "-i", "rk4", "-d", "0.01", "-t", duration, "--record-count", count])
+            .output()
+            .expect("Failed to execute CLI");
+        assert!(output.status.success(),
+            "CLI failed with stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let csv = fs::read_to_string(&output_file).expect("Failed to read output");
+        let rows: Vec<Vec<f64>> = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').skip(3).map(|value| value.parse().unwrap()).collect())
+            .collect();
+        (rows, String::from_utf8_lossy(&output.stderr).to_string())
+    };
+    let (full, _) = run(&[input_file.to_str().unwrap()], "full.csv", "10", "11");
+    // Branch off the full run at its frame closest to t = 4.9, i.e. t = 5,
+    // overwriting the file it came from.
+    let from = format!("{}:t=4.9", full_parquet.display());
+    let (branch, stderr) = run(&["--initial-from", &from], "branch.csv", "5", "6");
+
+    assert!(stderr.contains("starting from the frame at t = 5 s"), "{}", stderr);
+    // Rows go Star, Planet at each recorded time; the branch starts at the sixth.
+    assert_eq!(branch.len(), 12);
+    for (row, expected) in branch.iter().zip(&full[10..]) {
+        assert!(row.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-9), "{:?} != {:?}", row, expected);
+    }
+
+    let output = Command::new("cargo")
+        .args(["run", "--", input_file.to_str().unwrap(), "--initial-from", &from])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(!output.status.success());
+    let output = Command::new("cargo")
+        .args(["run", "--", "--initial-from", full_parquet.to_str().unwrap()])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_partition_by_name() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("pair.json");
+    fs::write(&input_file, r#"[
+        {"name": "Star", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Planet b", "mass": 1e-3, "position": {"x": 1.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 1.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+    let partitions = temp_dir.path().join("orbits");
+    let plain = temp_dir.path().join("plain.csv");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", input_file.to_str().unwrap(), "--partition-by", "name"])
+        .args(["-o", partitions.to_str().unwrap(), "-o", plain.to_str().unwrap()])
+        .args(["-g", "1", "-t", "3", "-d", "0.01", "--record-count", "4"])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert!(partitions.join("name=Star/part-0.parquet").is_file());
+    let planet = partitions.join("name=Planet b/part-0.parquet");
+    let exported = temp_dir.path().join("planet.csv");
+    let output = Command::new("cargo")
+        .args(["run", "--", "convert", planet.to_str().unwrap(), exported.to_str().unwrap(), "--format", "blender"])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // Four frames of the planet alone.
+    let frames = fs::read_to_string(&exported).expect("Failed to read export");
+    assert_eq!(frames.lines().skip(1).count(), 4, "{}", frames);
+    assert!(frames.lines().skip(1).all(|line| line.contains("Planet b")), "{}", frames);
+    // The CSV output isn't partitioned.
+    let plain = fs::read_to_string(&plain).expect("Failed to read output");
+    assert_eq!(plain.lines().skip(1).count(), 8);
+
+    let output = Command::new("cargo")
+        .args(["run", "--", input_file.to_str().unwrap(), "-t", "1"])
+        .args(["-o", temp_dir.path().join("plot.csv,partition=name").to_str().unwrap()])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_jsonl_to_standard_output() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = temp_dir.path().join("pair.json");
+    fs::write(&input_file, r#"[
+        {"name": "Star", "mass": 1.0, "position": {"x": 0.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 0.0, "z": 0.0}},
+        {"name": "Planet b", "mass": 1e-3, "position": {"x": 1.0, "y": 0.0, "z": 0.0}, "velocity": {"x": 0.0, "y": 1.0, "z": 0.0}}
+    ]"#).expect("Failed to write scenario");
+    let frames = temp_dir.path().join("frames.jsonl");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", input_file.to_str().unwrap(), "-o", "-"])
+        .args(["-o", &format!("{},group=frame,drop=velocity", frames.to_str().unwrap())])
+        .args(["-g", "1", "-t", "3", "-d", "0.01", "--record-count", "4"])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success(),
+        "CLI failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // A line per body and frame on the standard output.
+    let lines: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("Failed to parse line"))
+        .collect();
+    assert_eq!(lines.len(), 8);
+    assert_eq!((lines[0]["time"].as_f64(), &lines[1]["name"]), (Some(0.0), &serde_json::json!("Planet b")));
+    assert_eq!(lines[1]["pos_x"], 1.0);
+    assert_eq!(lines[1]["vel_y"], 1.0);
+    // And a line per frame in the file.
+    let frames = fs::read_to_string(&frames).expect("Failed to read output");
+    let frames: Vec<serde_json::Value> = frames.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(frames.len(), 4);
+    assert_eq!(frames[3]["time"], lines[7]["time"]);
+    assert_eq!(frames[3]["bodies"][1]["pos_x"], lines[7]["pos_x"]);
+    assert!(frames[3]["bodies"][1].get("vel_x").is_none());
+
+    let output = Command::new("cargo")
+        .args(["run", "--", input_file.to_str().unwrap(), "-t", "1"])
+        .args(["-o", temp_dir.path().join("plot.csv,group=frame").to_str().unwrap()])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_query() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let input_file = create_test_input_file(&temp_dir);
+    let output_file = temp_dir.path().join("run.parquet");
+    let output = Command::new("cargo")
+        .args(["run", "--", &input_file, "-o", output_file.to_str().unwrap(), "-t", "10", "-d", "1"])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(output.status.success());
+
+    // The query runs in a build with the features of the tests.
+    let features: &[&str] = if cfg!(feature = "duckdb") { &["--features", "duckdb"] } else { &[] };
+    let output = Command::new("cargo")
+        .arg("run")
+        .args(features)
+        .args(["--", "query", "SELECT name, max(pos_x) AS x FROM run GROUP BY name ORDER BY name"])
+        .arg(&output_file)
+        .output()
+        .expect("Failed to execute CLI");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if cfg!(feature = "duckdb") {
+        assert!(output.status.success(), "CLI failed with stderr: {}", stderr);
+        assert!(stdout.starts_with("name") && stdout.contains("TestBody1"), "{}", stdout);
+    } else {
+        assert!(!output.status.success());
+        assert!(stderr.contains("duckdb feature"), "{}", stderr);
+    }
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "query", "SELECT 1", temp_dir.path().join("missing.parquet").to_str().unwrap()])
+        .output()
+        .expect("Failed to execute CLI");
+    assert!(!output.status.success());
+}